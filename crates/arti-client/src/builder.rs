@@ -3,6 +3,8 @@
 #![allow(missing_docs, clippy::missing_docs_in_private_items)]
 
 use crate::{err::ErrorDetail, BootstrapBehavior, Result, TorClient, TorClientConfig};
+#[cfg(feature = "onion-service-service")]
+use std::num::NonZeroUsize;
 use std::sync::Arc;
 use tor_dirmgr::{DirMgrConfig, DirMgrStore};
 use tor_rtcompat::Runtime;
@@ -64,6 +66,9 @@ pub struct TorClientBuilder<R: Runtime> {
     /// Only available when `arti-client` is built with the `dirfilter` and `experimental-api` features.
     #[cfg(feature = "dirfilter")]
     dirfilter: tor_dirmgr::filter::FilterConfig,
+    /// Limit on concurrent descriptor uploads, shared by all the client's onion services.
+    #[cfg(feature = "onion-service-service")]
+    onion_service_upload_limit: Option<NonZeroUsize>,
 }
 
 impl<R: Runtime> TorClientBuilder<R> {
@@ -76,6 +81,8 @@ impl<R: Runtime> TorClientBuilder<R> {
             dirmgr_builder: Arc::new(DirMgrBuilder {}),
             #[cfg(feature = "dirfilter")]
             dirfilter: None,
+            #[cfg(feature = "onion-service-service")]
+            onion_service_upload_limit: None,
         }
     }
 
@@ -122,6 +129,19 @@ impl<R: Runtime> TorClientBuilder<R> {
         self
     }
 
+    /// Limit the number of descriptor uploads that may be in flight at once,
+    /// across all the onion services launched by the `TorClient` under construction.
+    ///
+    /// Each onion service already limits its own uploads; this bounds their combined total,
+    /// which matters when running many services in one process.
+    ///
+    /// If not called, only the per-service limit applies.
+    #[cfg(feature = "onion-service-service")]
+    pub fn onion_service_upload_limit(mut self, limit: NonZeroUsize) -> Self {
+        self.onion_service_upload_limit = Some(limit);
+        self
+    }
+
     /// Create a `TorClient` from this builder, without automatically launching
     /// the bootstrap process.
     ///
@@ -151,6 +171,9 @@ impl<R: Runtime> TorClientBuilder<R> {
             self.bootstrap_behavior,
             self.dirmgr_builder.as_ref(),
             dirmgr_extensions,
+            #[cfg(feature = "onion-service-service")]
+            self.onion_service_upload_limit
+                .map(tor_hsservice::DescriptorUploadLimit::new),
         )
         .map_err(ErrorDetail::into)
     }
@@ -117,6 +117,9 @@ pub struct TorClient<R: Runtime> {
     /// Circuit pool for providing onion services with circuits.
     #[cfg(feature = "onion-service-service")]
     hs_circ_pool: Arc<tor_circmgr::hspool::HsCircPool<R>>,
+    /// Limit on concurrent descriptor uploads, shared by all our onion services, if any.
+    #[cfg(feature = "onion-service-service")]
+    hs_upload_limit: Option<tor_hsservice::DescriptorUploadLimit>,
     /// The key manager.
     ///
     /// This is used for retrieving private keys, certificates, and other sensitive data (for
@@ -533,6 +536,9 @@ impl<R: Runtime> TorClient<R> {
         autobootstrap: BootstrapBehavior,
         dirmgr_builder: &dyn crate::builder::DirProviderBuilder<R>,
         dirmgr_extensions: tor_dirmgr::config::DirMgrExtensions,
+        #[cfg(feature = "onion-service-service")] hs_upload_limit: Option<
+            tor_hsservice::DescriptorUploadLimit,
+        >,
     ) -> StdResult<Self, ErrorDetail> {
         if crate::util::running_as_setuid() {
             return Err(tor_error::bad_api_usage!(
@@ -715,6 +721,8 @@ impl<R: Runtime> TorClient<R> {
             hsclient,
             #[cfg(feature = "onion-service-service")]
             hs_circ_pool,
+            #[cfg(feature = "onion-service-service")]
+            hs_upload_limit,
             keymgr,
             guardmgr,
             statemgr,
@@ -1380,20 +1388,25 @@ impl<R: Runtime> TorClient<R> {
             .ok_or_else(|| internal!("Tried to launch onion service with no key storage enabled"))
             .map_err(ErrorDetail::from)?
             .clone();
-        let service = tor_hsservice::OnionService::new(
-            self.runtime.clone(),
-            config,
-            self.dirmgr.clone().upcast_arc(),
-            self.hs_circ_pool.clone(),
-            // TODO HSS: Allow override of KeyMgr for "ephemeral" operation?
-            keymgr,
-            // TODO HSS: Allow override of StateMgr for "ephemeral" operation?
-            self.statemgr.clone(),
-            // TODO HSS: Allow override of state_dir for "ephemeral" operation?
-            &self.state_dir,
-            &self.storage_mistrust,
-        )
-        .map_err(ErrorDetail::LaunchOnionService)?;
+        let mut builder = tor_hsservice::OnionService::builder();
+        if let Some(limit) = &self.hs_upload_limit {
+            builder = builder.upload_limit(limit.clone());
+        }
+        let service = builder
+            .create(
+                self.runtime.clone(),
+                config,
+                self.dirmgr.clone().upcast_arc(),
+                self.hs_circ_pool.clone(),
+                // TODO HSS: Allow override of KeyMgr for "ephemeral" operation?
+                keymgr,
+                // TODO HSS: Allow override of StateMgr for "ephemeral" operation?
+                self.statemgr.clone(),
+                // TODO HSS: Allow override of state_dir for "ephemeral" operation?
+                &self.state_dir,
+                &self.storage_mistrust,
+            )
+            .map_err(ErrorDetail::LaunchOnionService)?;
         let stream = service.launch().map_err(ErrorDetail::LaunchOnionService)?;
 
         Ok((service, stream))
@@ -3,28 +3,37 @@
 pub(crate) mod auth;
 
 use std::{
-    collections::HashMap,
+    collections::{hash_map::Entry, HashMap, VecDeque},
     pin::Pin,
     sync::{Arc, Mutex, RwLock, Weak},
+    time::Duration,
 };
 
 use asynchronous_codec::JsonCodecError;
+use ciborium::de::Error as CborError;
 use futures::{
     channel::mpsc,
+    future::{BoxFuture, Fuse},
     stream::{FusedStream, FuturesUnordered},
-    FutureExt, Sink, SinkExt as _, StreamExt,
+    Future, FutureExt, Sink, SinkExt as _, StreamExt,
 };
 use pin_project::pin_project;
 use rpc::dispatch::BoxedUpdateSink;
 use serde_json::error::Category as JsonErrorCategory;
 use tor_async_utils::SinkExt as _;
+use tor_rtcompat::{SleepProvider, SleepProviderExt as _};
 
 use crate::{
     cancel::{Cancel, CancelHandle},
     err::RequestParseError,
     globalid::{GlobalId, MacKey},
-    msgs::{BoxedResponse, FlexibleRequest, Request, RequestId, ResponseBody},
+    msgs::{BoxedResponse, FlexibleRequest, Request, RequestId, ResponseBody, SingleRequest},
     objmap::{GenIdx, ObjMap},
+    streams::{
+        RequestDecoder, RequestStream, RequestStreamError, ResponseEncoder, ResponseSinkError,
+        WireFormat, DEFAULT_MAX_REQUEST_LEN,
+    },
+    telemetry::{RequestOutcome, TelemetrySink, TimedRequest},
     RpcMgr,
 };
 
@@ -68,31 +77,85 @@ rpc::decl_object! {Connection}
 /// The inner, lock-protected part of an RPC connection.
 struct Inner {
     /// Map from request ID to handles; used when we need to cancel a request.
-    //
-    // TODO: We have two options here for handling colliding IDs.  We can either turn
-    // this into a multimap, or we can declare that cancelling a request only
-    // cancels the most recent request sent with that ID.
+    ///
+    /// A request's ID stays in this map until the request is finished
+    /// (or cancelled); until then, we reject any other request with the same ID.
     inflight: HashMap<RequestId, CancelHandle>,
 
     /// An object map used to look up most objects by ID, and keep track of
     /// which objects are owned by this connection.
     objects: ObjMap,
+
+    /// Whether the client has tried to authenticate, and failed.
+    ///
+    /// Once this is set, we close the connection.
+    authentication_failed: bool,
 }
 
 /// How many updates can be pending, per connection, before they start to block?
 const UPDATE_CHAN_SIZE: usize = 128;
 
+/// How many responses can be waiting to be written, per connection, by default?
+const DEFAULT_MAX_BUFFERED_RESPONSES: usize = 1024;
+
+/// How long do requests get to finish, by default, once we're shutting down?
+const DEFAULT_SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Timeouts and limits to apply to an RPC [`Connection`].
+///
+/// By default, there are no timeouts, other than the grace period for
+/// shutting down.
+#[derive(Clone, Debug)]
+#[allow(clippy::exhaustive_structs)]
+pub struct ConnectionConfig {
+    /// If present, close the connection once it has gone this long with no
+    /// request in progress.
+    pub idle_timeout: Option<Duration>,
+    /// If present, give up on any request that takes longer than this to
+    /// finish, and reply to it with an error.
+    pub request_timeout: Option<Duration>,
+    /// Close the connection if more than this many responses are waiting
+    /// for the client to read them.
+    ///
+    /// Before closing, we give the client up to `shutdown_grace_period` to
+    /// read the responses we already have, followed by an error.
+    pub max_buffered_responses: usize,
+    /// The encoding to use for requests and responses on this connection.
+    pub wire_format: WireFormat,
+    /// Once [`RpcMgr::shutdown`] is called, give any requests in progress
+    /// this long to finish before cancelling them.
+    pub shutdown_grace_period: Duration,
+    /// If present, report how long each request takes to this sink.
+    pub telemetry: Option<TelemetrySink>,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: None,
+            request_timeout: None,
+            max_buffered_responses: DEFAULT_MAX_BUFFERED_RESPONSES,
+            wire_format: WireFormat::default(),
+            shutdown_grace_period: DEFAULT_SHUTDOWN_GRACE_PERIOD,
+            telemetry: None,
+        }
+    }
+}
+
 /// A type-erased [`FusedStream`] yielding [`Request`]s.
 //
 // (We name this type and [`BoxedResponseSink`] below so as to keep the signature for run_loop
 // nice and simple.)
-pub(crate) type BoxedRequestStream = Pin<
-    Box<dyn FusedStream<Item = Result<FlexibleRequest, asynchronous_codec::JsonCodecError>> + Send>,
->;
+pub(crate) type BoxedRequestStream =
+    Pin<Box<dyn FusedStream<Item = Result<FlexibleRequest, RequestStreamError>> + Send>>;
 
 /// A type-erased [`Sink`] accepting [`BoxedResponse`]s.
 pub(crate) type BoxedResponseSink =
-    Pin<Box<dyn Sink<BoxedResponse, Error = asynchronous_codec::JsonCodecError> + Send>>;
+    Pin<Box<dyn Sink<BoxedResponse, Error = ResponseSinkError> + Send>>;
+
+/// A future that writes a single response onto a [`BoxedResponseSink`], and
+/// then gives the sink back.
+type PendingWrite = Fuse<BoxFuture<'static, (BoxedResponseSink, Result<(), ResponseSinkError>)>>;
 
 /// A random value used to identify an connection.
 #[derive(
@@ -128,6 +191,7 @@ impl Connection {
             inner: Mutex::new(Inner {
                 inflight: HashMap::new(),
                 objects: ObjMap::new(),
+                authentication_failed: false,
             }),
             dispatch_table,
             connection_id,
@@ -201,45 +265,68 @@ impl Connection {
         inner.inflight.remove(id);
     }
 
+    /// Record that the client has failed to authenticate, so that we close the connection.
+    pub(crate) fn note_authentication_failed(&self) {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        inner.authentication_failed = true;
+    }
+
+    /// Return true if the client has failed to authenticate.
+    fn authentication_failed(&self) -> bool {
+        let inner = self.inner.lock().expect("lock poisoned");
+        inner.authentication_failed
+    }
+
     /// Register the request `id` as a cancellable request.
-    fn register_request(&self, id: RequestId, handle: CancelHandle) {
+    ///
+    /// Return an error if some other request with the same ID is still in
+    /// progress.
+    fn register_request(&self, id: RequestId, handle: CancelHandle) -> Result<(), RequestIdInUse> {
         let mut inner = self.inner.lock().expect("lock poisoned");
-        inner.inflight.insert(id, handle);
+        match inner.inflight.entry(id) {
+            Entry::Occupied(_) => Err(RequestIdInUse),
+            Entry::Vacant(e) => {
+                e.insert(handle);
+                Ok(())
+            }
+        }
     }
 
     /// Run in a loop, decoding JSON requests from `input` and
     /// writing JSON responses onto `output`.
-    pub async fn run<IN, OUT>(
+    ///
+    /// Use `sleep_provider` to enforce the timeouts in `config`.
+    pub async fn run<IN, OUT, SP>(
         self: Arc<Self>,
         input: IN,
         output: OUT,
+        sleep_provider: SP,
+        config: ConnectionConfig,
     ) -> Result<(), ConnectionError>
     where
         IN: futures::AsyncRead + Send + Sync + Unpin + 'static,
         OUT: futures::AsyncWrite + Send + Sync + Unpin + 'static,
+        SP: SleepProvider,
     {
         let write = Box::pin(asynchronous_codec::FramedWrite::new(
             output,
-            crate::streams::JsonLinesEncoder::<BoxedResponse>::default(),
+            ResponseEncoder::<BoxedResponse>::new(config.wire_format),
         ));
 
-        let read = Box::pin(
-            asynchronous_codec::FramedRead::new(
-                input,
-                asynchronous_codec::JsonCodec::<(), FlexibleRequest>::new(),
-            )
-            .fuse(),
-        );
+        let decoder = RequestDecoder::for_format(config.wire_format, DEFAULT_MAX_REQUEST_LEN);
+        let read = Box::pin(RequestStream::new(input, decoder).fuse());
 
-        self.run_loop(read, write).await
+        self.run_loop(read, write, sleep_provider, config).await
     }
 
     /// Run in a loop, handling requests from `request_stream` and writing
     /// responses onto `response_stream`.
-    pub(crate) async fn run_loop(
+    pub(crate) async fn run_loop<SP: SleepProvider>(
         self: Arc<Self>,
         mut request_stream: BoxedRequestStream,
-        mut response_sink: BoxedResponseSink,
+        response_sink: BoxedResponseSink,
+        sleep_provider: SP,
+        config: ConnectionConfig,
     ) -> Result<(), ConnectionError> {
         // This function will multiplex on three streams:
         // * `request_stream` -- a stream of incoming requests from the client.
@@ -249,33 +336,124 @@ impl Connection {
         //   same channel to ensure that they stay in-order for each method
         //   invocation.
         //
-        // Note that the blocking behavior here is deliberate: We want _all_ of
-        // these reads to start blocking when response_sink.send is blocked.
+        // Responses are queued in `outbound`, and written to `response_sink`
+        // one at a time by `pending_write`.  We never wait for a write to
+        // finish before handling anything else: instead, if the client is
+        // not reading their responses (or not reading them fast enough), the
+        // queue grows until it exceeds `config.max_buffered_responses`, and we
+        // close the connection.  (We still try to deliver the responses in the
+        // queue, followed by an error, but we don't wait for the client forever.)
+        //
+        // Once our RpcMgr is shut down, we stop reading requests, and close
+        // the connection as soon as every request in progress is done.  Any
+        // request still in progress after `config.shutdown_grace_period` gets
+        // cancelled.  We do the same once the client has failed to
+        // authenticate, so that it can't keep guessing.
 
         let (tx_response, mut rx_response) = mpsc::channel::<BoxedResponse>(UPDATE_CHAN_SIZE);
         let mut finished_requests = FuturesUnordered::new();
         finished_requests.push(futures::future::pending().boxed());
+        let mut idle_timer = sleep_or_pending(&sleep_provider, config.idle_timeout);
+        let mut outbound = VecDeque::new();
+        // The sink, if no write is currently in progress.
+        let mut idle_sink = Some(response_sink);
+        let mut pending_write: PendingWrite = Fuse::terminated();
+        let mut shutdown_requested = match self.mgr() {
+            Ok(mgr) => mgr.shutdown_requested().boxed().fuse(),
+            Err(_) => futures::future::pending::<()>().boxed().fuse(),
+        };
+        let mut shutting_down = false;
+        let mut authentication_failed = false;
+        let mut too_many_buffered = false;
+        let mut grace_timer: Fuse<BoxFuture<'static, ()>> = Fuse::terminated();
 
         'outer: loop {
+            // (`finished_requests` always holds one extra, never-ending, future.)
+            if shutting_down && finished_requests.len() == 1 {
+                // Every request is done, and so has already put its final
+                // response onto `rx_response`.
+                while let Ok(Some(response)) = rx_response.try_next() {
+                    outbound.push_back(response);
+                }
+                break 'outer;
+            }
+
+            if let Some(sink) = idle_sink.take() {
+                match outbound.pop_front() {
+                    Some(response) => pending_write = write_response(sink, response),
+                    None => idle_sink = Some(sink),
+                }
+            }
+            if outbound.len() > config.max_buffered_responses {
+                // Stop handling requests, and tell the client why, after
+                // whatever responses are already waiting to be sent.
+                too_many_buffered = true;
+                while let Ok(Some(response)) = rx_response.try_next() {
+                    outbound.push_back(response);
+                }
+                outbound.push_back(BoxedResponse::from_error(None, TooManyBufferedResponses));
+                break 'outer;
+            }
+
             futures::select! {
+                (sink, r) = pending_write => {
+                    r.map_err(|_| ConnectionError::WriteFailed)?;
+                    idle_sink = Some(sink);
+                },
+
+                () = shutdown_requested => {
+                    // Stop reading new requests, and give the ones in
+                    // progress a while to finish.
+                    request_stream = Box::pin(futures::stream::pending());
+                    shutting_down = true;
+                    grace_timer = sleep_or_pending(&sleep_provider, Some(config.shutdown_grace_period));
+                },
+
+                () = grace_timer => {
+                    // Time's up: cancel whatever is still in progress.
+                    for handle in self.inner.lock().expect("lock poisoned").inflight.values() {
+                        handle.cancel();
+                    }
+                },
+
+                () = idle_timer => {
+                    let idle = self.inner.lock().expect("lock poisoned").inflight.is_empty();
+                    if idle {
+                        return Err(ConnectionError::IdleTimeout);
+                    }
+                    // Some request is still in progress, so we aren't idle.
+                    // We start timing again once it's done.
+                    idle_timer = Fuse::terminated();
+                }
+
                 r = finished_requests.next() => {
                     // A task is done, so we can forget about it.
                     let () = r.expect("Somehow, future::pending() terminated.");
+
+                    // If that was the last request in progress, we're idle from now on.
+                    if self.inner.lock().expect("lock poisoned").inflight.is_empty() {
+                        idle_timer = sleep_or_pending(&sleep_provider, config.idle_timeout);
+                    }
+
+                    if !authentication_failed && self.authentication_failed() {
+                        // Stop reading new requests, and close the connection
+                        // once the rejection (and any other responses) have been sent.
+                        authentication_failed = true;
+                        request_stream = Box::pin(futures::stream::pending());
+                        shutting_down = true;
+                        grace_timer = sleep_or_pending(&sleep_provider, Some(config.shutdown_grace_period));
+                    }
                 }
 
                 r = rx_response.next() => {
                     // The future for some request has sent a response (success,
                     // failure, or update), so we can inform the client.
-                    let update = r.expect("Somehow, tx_update got closed.");
-                    debug_assert!(! update.body.is_final());
-                    // Calling `await` here (and below) is deliberate: we _want_
-                    // to stop reading the client's requests if the client is
-                    // not reading their responses (or not) reading them fast
-                    // enough.
-                    response_sink.send(update).await.map_err(|_| ConnectionError::WriteFailed)?;
+                    let response = r.expect("Somehow, tx_response got closed.");
+                    outbound.push_back(response);
                 }
 
                 req = request_stream.next() => {
+                    idle_timer = sleep_or_pending(&sleep_provider, config.idle_timeout);
                     match req {
                         None => {
                             // We've reached the end of the stream of requests;
@@ -283,55 +461,113 @@ impl Connection {
                             break 'outer;
                         }
                         Some(Err(e)) => {
-                            // We got a non-recoverable error from the JSON codec.
+                            // We got a non-recoverable error from the JSON or CBOR codec.
                            let error = match e {
-                                JsonCodecError::Io(_) => return Err(ConnectionError::ReadFailed),
-                                JsonCodecError::Json(e) => match e.classify() {
+                                RequestStreamError::Codec(JsonCodecError::Io(_)) => return Err(ConnectionError::ReadFailed),
+                                RequestStreamError::Codec(JsonCodecError::Json(e)) => match e.classify() {
                                     JsonErrorCategory::Eof => break 'outer,
                                     JsonErrorCategory::Io => return Err(ConnectionError::ReadFailed),
                                     JsonErrorCategory::Syntax => RequestParseError::InvalidJson,
                                     JsonErrorCategory::Data => RequestParseError::NotAnObject,
                                 }
+                                RequestStreamError::Cbor(e) => match e {
+                                    CborError::Io(_) => return Err(ConnectionError::ReadFailed),
+                                    CborError::Syntax(_) | CborError::RecursionLimitExceeded => RequestParseError::InvalidCbor,
+                                    CborError::Semantic(..) => RequestParseError::NotAnObject,
+                                }
+                                RequestStreamError::TooLong(_) => RequestParseError::TooLong,
                             };
 
-                            response_sink
-                                .send(
-                                    BoxedResponse::from_error(None, error)
-                                ).await.map_err(|_| ConnectionError::WriteFailed)?;
+                            outbound.push_back(BoxedResponse::from_error(None, error));
 
                             // TODO RPC: Perhaps we should keep going on the NotAnObject case?
                             //      (InvalidJson is not recoverable!)
                             break 'outer;
                         }
-                        Some(Ok(FlexibleRequest::Invalid(bad_req))) => {
-                            response_sink
-                                .send(
-                                    BoxedResponse::from_error(bad_req.id().cloned(), bad_req.error())
-                                ).await.map_err(|_| ConnectionError::WriteFailed)?;
-                            if bad_req.id().is_none() {
-                                // The spec says we must close the connection in this case.
-                                break 'outer;
+                        Some(Ok(request)) => {
+                            // We have a request, or a batch of them. The
+                            // members of a batch are launched one by one, and
+                            // then run concurrently like any other requests.
+                            for request in request.into_requests() {
+                                match request {
+                                    SingleRequest::Invalid(bad_req) => {
+                                        outbound.push_back(
+                                            BoxedResponse::from_error(bad_req.id().cloned(), bad_req.error())
+                                        );
+                                        if bad_req.id().is_none() {
+                                            // The spec says we must close the connection in this case.
+                                            break 'outer;
+                                        }
+                                    }
+                                    SingleRequest::Valid(req) => {
+                                        // Time to launch it!
+                                        let id = req.id.clone();
+                                        match self.launch_request(tx_response.clone(), req, &sleep_provider, config.request_timeout, config.telemetry.as_ref()) {
+                                            Ok(fut) => finished_requests.push(fut.boxed()),
+                                            Err(e) => outbound.push_back(BoxedResponse::from_error(Some(id), e)),
+                                        }
+                                    }
+                                }
                             }
                         }
-                        Some(Ok(FlexibleRequest::Valid(req))) => {
-                            // We have a request. Time to launch it!
-                            let fut = self.run_method_and_deliver_response(tx_response.clone(), req);
-                            finished_requests.push(fut.boxed());
-                        }
                     }
                 }
             }
         }
 
+        // Before we close, make sure that the client gets every response we
+        // have already queued.
+        let flush = async move {
+            let mut response_sink = match idle_sink {
+                Some(sink) => sink,
+                None => {
+                    let (sink, r) = pending_write.await;
+                    r.map_err(|_| ConnectionError::WriteFailed)?;
+                    sink
+                }
+            };
+            for response in outbound {
+                response_sink
+                    .send(response)
+                    .await
+                    .map_err(|_| ConnectionError::WriteFailed)?;
+            }
+            Ok(())
+        };
+        if too_many_buffered {
+            // The client is slow to read its responses, so we only give it a
+            // while to catch up.
+            let _: Result<Result<(), ConnectionError>, _> = sleep_provider
+                .timeout(config.shutdown_grace_period, flush)
+                .await;
+            return Err(ConnectionError::TooManyBufferedResponses);
+        }
+        flush.await?;
+
+        if authentication_failed {
+            return Err(ConnectionError::AuthenticationFailed);
+        }
         Ok(())
     }
 
-    /// Invoke `request` and send all of its responses to `tx_response`.
-    async fn run_method_and_deliver_response(
-        self: &Arc<Self>,
-        mut tx_response: mpsc::Sender<BoxedResponse>,
+    /// Register `request`, and return a future that will invoke it and send all
+    /// of its responses to `tx_response`.
+    ///
+    /// If `timeout` is present, use `sleep_provider` to give up on the request
+    /// if it takes longer than `timeout`.
+    ///
+    /// If `telemetry` is present, use `sleep_provider` to measure how long the
+    /// request takes, and report it to `telemetry`.
+    ///
+    /// Return an error if another request with the same ID is in progress.
+    fn launch_request<'a, SP: SleepProvider>(
+        self: &'a Arc<Self>,
+        tx_response: mpsc::Sender<BoxedResponse>,
         request: Request,
-    ) {
+        sleep_provider: &SP,
+        timeout: Option<Duration>,
+        telemetry: Option<&TelemetrySink>,
+    ) -> Result<impl Future<Output = ()> + Send + 'a, RequestIdInUse> {
         let Request {
             id,
             obj,
@@ -339,6 +575,9 @@ impl Connection {
             method,
         } = request;
 
+        let timer = telemetry
+            .map(|sink| TimedRequest::start(sink.clone(), sleep_provider, method.method_name()));
+
         let update_sender: BoxedUpdateSink = if meta.updates {
             let id_clone = id.clone();
             let sink =
@@ -356,14 +595,44 @@ impl Connection {
             Box::pin(sink)
         };
 
-        // Create `run_method_lowlevel` future, and make it cancellable.
+        // Create `run_method_lowlevel` future, give it a timeout, and make it
+        // cancellable.
         let fut = self.run_method_lowlevel(update_sender, obj, method);
+        let sleep_provider = sleep_provider.clone();
+        let fut = async move {
+            match timeout {
+                Some(timeout) => sleep_provider
+                    .timeout(timeout, fut)
+                    .await
+                    .unwrap_or_else(|_| Err(rpc::RpcError::from(RequestTimedOut))),
+                None => fut.await,
+            }
+        };
         let (handle, fut) = Cancel::new(fut);
-        self.register_request(id.clone(), handle);
+        self.register_request(id.clone(), handle)?;
+
+        Ok(self.deliver_response(tx_response, id, fut, timer))
+    }
 
+    /// Run `fut` (the cancellable future for request `id`) to completion, and
+    /// send its final response to `tx_response`.
+    ///
+    /// If `timer` is present, report the request's timing to it.
+    async fn deliver_response<F, SP>(
+        self: &Arc<Self>,
+        mut tx_response: mpsc::Sender<BoxedResponse>,
+        id: RequestId,
+        fut: Cancel<F>,
+        timer: Option<TimedRequest<SP>>,
+    ) where
+        F: Future<
+            Output = Result<Box<dyn erased_serde::Serialize + Send + 'static>, rpc::RpcError>,
+        >,
+        SP: SleepProvider,
+    {
         // Run the cancellable future to completion, and figure out how to respond.
-        let body = match fut.await {
-            Ok(Ok(value)) => ResponseBody::Success(value),
+        let (body, outcome) = match fut.await {
+            Ok(Ok(value)) => (ResponseBody::Success(value), RequestOutcome::Success),
             // TODO: If we're going to box this, let's do so earlier.
             Ok(Err(err)) => {
                 if err.is_internal() {
@@ -372,24 +641,28 @@ impl Connection {
                         err
                     );
                 }
-                ResponseBody::Error(Box::new(err))
+                (ResponseBody::Error(Box::new(err)), RequestOutcome::Error)
             }
-            Err(_cancelled) => ResponseBody::Error(Box::new(rpc::RpcError::from(RequestCancelled))),
+            Err(_cancelled) => (
+                ResponseBody::Error(Box::new(rpc::RpcError::from(RequestCancelled))),
+                RequestOutcome::Cancelled,
+            ),
         };
+        if let Some(timer) = timer {
+            timer.finish(outcome);
+        }
+
+        // Unregister the request.
+        //
+        // We do this before sending the response, so that the ID is free
+        // for reuse by the time the client learns that this request is done.
+        self.remove_request(&id);
 
         // Send the response.
         //
         // (It's okay to ignore the error here, since it can only mean that the
         // RPC connection has closed.)
-        let _ignore_err = tx_response
-            .send(BoxedResponse {
-                id: Some(id.clone()),
-                body,
-            })
-            .await;
-
-        // Unregister the request.
-        self.remove_request(&id);
+        let _ignore_err = tx_response.send(BoxedResponse { id: Some(id), body }).await;
     }
 
     /// Run a single method, and return its final response.
@@ -440,6 +713,39 @@ pub enum ConnectionError {
     /// Read error from connection.
     #[error("Problem reading from connection")]
     ReadFailed,
+    /// The connection was idle for too long.
+    #[error("Connection was idle for too long")]
+    IdleTimeout,
+    /// The client was not reading its responses fast enough.
+    #[error("Too many responses were waiting for the client to read them")]
+    TooManyBufferedResponses,
+    /// The client tried to authenticate, and failed.
+    #[error("Client failed to authenticate")]
+    AuthenticationFailed,
+}
+
+/// Return a future that writes `response` onto `sink`, and then returns `sink`
+/// along with the outcome of the write.
+fn write_response(mut sink: BoxedResponseSink, response: BoxedResponse) -> PendingWrite {
+    async move {
+        let r = sink.send(response).await;
+        (sink, r)
+    }
+    .boxed()
+    .fuse()
+}
+
+/// Return a future that resolves once `timeout` has elapsed, or never if
+/// `timeout` is `None`.
+fn sleep_or_pending<SP: SleepProvider>(
+    sleep_provider: &SP,
+    timeout: Option<Duration>,
+) -> futures::future::Fuse<futures::future::BoxFuture<'static, ()>> {
+    match timeout {
+        Some(timeout) => sleep_provider.sleep(timeout).boxed(),
+        None => futures::future::pending().boxed(),
+    }
+    .fuse()
 }
 
 /// A failure from trying to upgrade a `Weak<RpcMgr>`.
@@ -530,6 +836,40 @@ impl rpc::Context for RequestContext {
     }
 }
 
+/// An error given when a client sends a request whose ID is already in use by
+/// another request that hasn't finished.
+#[derive(thiserror::Error, Clone, Debug, serde::Serialize)]
+#[error("Request ID is already in use by an unfinished request")]
+pub(crate) struct RequestIdInUse;
+impl tor_error::HasKind for RequestIdInUse {
+    fn kind(&self) -> tor_error::ErrorKind {
+        tor_error::ErrorKind::RpcInvalidRequest
+    }
+}
+
+/// An error given when an RPC request takes too long.
+#[derive(thiserror::Error, Clone, Debug, serde::Serialize)]
+#[error("RPC request timed out")]
+pub(crate) struct RequestTimedOut;
+impl tor_error::HasKind for RequestTimedOut {
+    fn kind(&self) -> tor_error::ErrorKind {
+        // TODO RPC: Can we do better here?
+        tor_error::ErrorKind::Other
+    }
+}
+
+/// An error given when we close a connection because the client is not reading
+/// its responses fast enough.
+#[derive(thiserror::Error, Clone, Debug, serde::Serialize)]
+#[error("Too many responses were waiting for the client to read them")]
+pub(crate) struct TooManyBufferedResponses;
+impl tor_error::HasKind for TooManyBufferedResponses {
+    fn kind(&self) -> tor_error::ErrorKind {
+        // TODO RPC: Can we do better here?
+        tor_error::ErrorKind::Other
+    }
+}
+
 /// An error given when an RPC request is cancelled.
 ///
 /// This is a separate type from [`crate::cancel::Cancelled`] since eventually
@@ -544,3 +884,568 @@ impl tor_error::HasKind for RequestCancelled {
         tor_error::ErrorKind::Other
     }
 }
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+    use futures_await_test::async_test;
+    use std::task::{Context, Poll};
+    use tor_rtmock::MockRuntime;
+
+    /// Make a new connection with no manager.
+    fn new_connection() -> Arc<Connection> {
+        Arc::new(Connection::new(
+            ConnectionId::from([7; ConnectionId::LEN]),
+            Arc::new(RwLock::new(rpc::DispatchTable::from_inventory())),
+            MacKey::new(&mut rand::thread_rng()),
+            Weak::new(),
+        ))
+    }
+
+    /// Return the JSON for a new request with a given ID.
+    fn request_json(id: u64) -> String {
+        format!(
+            r#"{{"id": {id}, "obj": "connection", "method": "arti:x-echo", "params": {{"msg": "hi"}}}}"#
+        )
+    }
+
+    /// Make a new request with a given ID.
+    fn request(id: u64) -> Request {
+        serde_json::from_str(&request_json(id)).unwrap()
+    }
+
+    #[async_test]
+    async fn duplicate_request_id() {
+        let conn = new_connection();
+        let rt = MockRuntime::new();
+        let (tx, mut rx) = mpsc::channel(UPDATE_CHAN_SIZE);
+
+        // Launch a request, but don't run it yet, so that it stays in flight.
+        let first = conn
+            .launch_request(tx.clone(), request(7), &rt, None, None)
+            .unwrap();
+        // Another request with the same ID is rejected...
+        assert!(conn
+            .launch_request(tx.clone(), request(7), &rt, None, None)
+            .is_err());
+        // ...but one with a different ID is fine.
+        assert!(conn
+            .launch_request(tx.clone(), request(8), &rt, None, None)
+            .is_ok());
+
+        // Once the first request is cancelled and finished, its ID is free again.
+        let handle = conn.inner.lock().unwrap().inflight[&RequestId::Int(7)].clone();
+        handle.cancel();
+        first.await;
+        let response = rx.next().await.unwrap();
+        assert_eq!(response.id, Some(RequestId::Int(7)));
+        assert!(matches!(response.body, ResponseBody::Error(_)));
+
+        assert!(conn
+            .launch_request(tx.clone(), request(7), &rt, None, None)
+            .is_ok());
+    }
+
+    /// A reader that never yields any data.
+    struct Silent;
+
+    impl futures::AsyncRead for Silent {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn idle_timeout() {
+        MockRuntime::test_with_various(|rt| async move {
+            let config = ConnectionConfig {
+                idle_timeout: Some(Duration::from_secs(30)),
+                ..Default::default()
+            };
+            let conn = new_connection();
+            let output = futures::io::sink();
+            let result = rt.spawn_join("connection", conn.run(Silent, output, rt.clone(), config));
+
+            let mut result = Box::pin(result);
+            rt.advance_by(Duration::from_secs(29)).await;
+            assert!(futures::poll!(&mut result).is_pending());
+            rt.advance_by(Duration::from_secs(2)).await;
+            assert!(matches!(result.await, Err(ConnectionError::IdleTimeout)));
+        });
+    }
+
+    #[test]
+    fn idle_timeout_after_long_request() {
+        use futures::AsyncReadExt as _;
+
+        MockRuntime::test_with_various(|rt| async move {
+            // A request that takes 25 seconds, since it hangs until it times out.
+            let config = ConnectionConfig {
+                idle_timeout: Some(Duration::from_secs(30)),
+                request_timeout: Some(Duration::from_secs(25)),
+                ..Default::default()
+            };
+            let request =
+                r#"{"id": 1, "obj": "connection", "method": "x-test:hang", "params": {}}"#;
+            let input = futures::io::Cursor::new(request.as_bytes()).chain(Silent);
+            let conn = new_connection();
+            let result = rt.spawn_join(
+                "connection",
+                conn.run(input, futures::io::sink(), rt.clone(), config),
+            );
+            let mut result = Box::pin(result);
+
+            // We're only idle once the request is done.
+            rt.advance_by(Duration::from_secs(25 + 29)).await;
+            assert!(futures::poll!(&mut result).is_pending());
+            rt.advance_by(Duration::from_secs(2)).await;
+            assert!(matches!(result.await, Err(ConnectionError::IdleTimeout)));
+        });
+    }
+
+    #[test]
+    fn no_idle_timeout_by_default() {
+        MockRuntime::test_with_various(|rt| async move {
+            let conn = new_connection();
+            let output = futures::io::sink();
+            let result = rt.spawn_join(
+                "connection",
+                conn.run(Silent, output, rt.clone(), ConnectionConfig::default()),
+            );
+
+            rt.advance_by(Duration::from_secs(86400)).await;
+            assert!(futures::poll!(Box::pin(result)).is_pending());
+        });
+    }
+
+    /// A writer that never accepts any data.
+    struct Stuck;
+
+    impl futures::AsyncWrite for Stuck {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Poll::Pending
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Pending
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn client_never_reads() {
+        use futures::AsyncReadExt as _;
+
+        MockRuntime::test_with_various(|rt| async move {
+            let config = ConnectionConfig {
+                max_buffered_responses: 4,
+                ..Default::default()
+            };
+            let requests: String = (0..16).map(|id| request_json(id) + "\n").collect();
+            let input = futures::io::Cursor::new(requests.into_bytes()).chain(Silent);
+            let conn = new_connection();
+            let result = rt.spawn_join("connection", conn.run(input, Stuck, rt.clone(), config));
+
+            // We give up on the client once the grace period is over.
+            rt.advance_until_stalled().await;
+            assert!(matches!(
+                result.await,
+                Err(ConnectionError::TooManyBufferedResponses)
+            ));
+        });
+    }
+
+    /// A writer that doesn't accept any data until it is opened,
+    /// and then appends everything it receives to a shared buffer.
+    #[derive(Clone, Default)]
+    struct Gated {
+        /// The buffer we append to.
+        buf: SharedBuf,
+        /// Whether we are open, and the waker to wake when we become open.
+        state: Arc<Mutex<(bool, Option<std::task::Waker>)>>,
+    }
+
+    impl Gated {
+        /// Start accepting data.
+        fn open(&self) {
+            let mut state = self.state.lock().unwrap();
+            state.0 = true;
+            if let Some(waker) = state.1.take() {
+                waker.wake();
+            }
+        }
+
+        /// Return `Pending` (and arrange to be woken) if we aren't open yet.
+        fn poll_open(&self, cx: &mut Context<'_>) -> Poll<()> {
+            let mut state = self.state.lock().unwrap();
+            if state.0 {
+                Poll::Ready(())
+            } else {
+                state.1 = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    impl futures::AsyncWrite for Gated {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            futures::ready!(self.poll_open(cx));
+            Pin::new(&mut self.buf).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            futures::ready!(self.poll_open(cx));
+            Pin::new(&mut self.buf).poll_flush(cx)
+        }
+
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            futures::ready!(self.poll_open(cx));
+            Pin::new(&mut self.buf).poll_close(cx)
+        }
+    }
+
+    #[test]
+    fn client_reads_slowly() {
+        use futures::AsyncReadExt as _;
+
+        MockRuntime::test_with_various(|rt| async move {
+            let config = ConnectionConfig {
+                max_buffered_responses: 4,
+                ..Default::default()
+            };
+            let requests: String = (0..16).map(|id| request_json(id) + "\n").collect();
+            let input = futures::io::Cursor::new(requests.into_bytes()).chain(Silent);
+            let output = Gated::default();
+            let conn = new_connection();
+            let result = rt.spawn_join(
+                "connection",
+                conn.run(input, output.clone(), rt.clone(), config),
+            );
+
+            // The client starts reading during the grace period...
+            rt.progress_until_stalled().await;
+            output.open();
+            rt.progress_until_stalled().await;
+            assert!(matches!(
+                result.await,
+                Err(ConnectionError::TooManyBufferedResponses)
+            ));
+
+            // ...so it gets the responses we had queued, and then an error.
+            let output = output.buf.0.lock().unwrap().clone();
+            let responses = std::str::from_utf8(&output)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                .collect::<Vec<_>>();
+            let (last, queued) = responses.split_last().unwrap();
+            assert!(queued.len() > 4);
+            for (id, response) in queued.iter().enumerate() {
+                assert_eq!(response["id"], id);
+            }
+            assert!(last["id"].is_null());
+            assert!(last.get("error").is_some());
+        });
+    }
+
+    /// A writer that appends everything it receives to a shared buffer.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl futures::AsyncWrite for SharedBuf {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn batch() {
+        use futures::AsyncReadExt as _;
+
+        MockRuntime::test_with_various(|rt| async move {
+            let query = |id: &str| {
+                format!(
+                    r#"{{"id": {id}, "obj": "connection", "method": "auth:query", "params": {{}}}}"#
+                )
+            };
+            let batch = format!(
+                "[{}, {}, {}]\n",
+                query("1"),
+                query("2"),
+                query(r#""three""#)
+            );
+            let input = futures::io::Cursor::new(batch.into_bytes()).chain(Silent);
+            let output = SharedBuf::default();
+            // (`auth:query` needs the connection to have an RpcMgr.)
+            let mgr = RpcMgr::new(|_| unreachable!());
+            let conn = mgr.new_connection();
+            let result = rt.spawn_join(
+                "connection",
+                conn.run(
+                    input,
+                    output.clone(),
+                    rt.clone(),
+                    ConnectionConfig::default(),
+                ),
+            );
+            rt.advance_until_stalled().await;
+            mgr.shutdown();
+            assert!(result.await.is_ok());
+
+            // Every request in the batch gets its own response, with its own ID.
+            let output = output.0.lock().unwrap().clone();
+            let responses: Vec<serde_json::Value> = std::str::from_utf8(&output)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect();
+            let mut ids: Vec<String> = responses
+                .iter()
+                .map(|r| {
+                    assert_eq!(r["result"]["schemes"][0], "inherent:unix_path");
+                    r["id"].to_string()
+                })
+                .collect();
+            ids.sort();
+            assert_eq!(ids, [r#""three""#, "1", "2"]);
+        });
+    }
+
+    #[test]
+    fn cookie_auth() {
+        use futures::AsyncReadExt as _;
+
+        MockRuntime::test_with_various(|rt| async move {
+            let tmp = tempfile::tempdir().unwrap();
+            let path = tmp.path().join("cookie");
+            let mistrust = fs_mistrust::Mistrust::new_dangerously_trust_everyone();
+            let cookie = crate::RpcCookie::load_or_create(&path, &mistrust).unwrap();
+            let cookie_hex = std::fs::read_to_string(&path).unwrap();
+            let mgr = RpcMgr::new_with_cookie(cookie, |_| {
+                let client: Arc<dyn rpc::Object> = new_connection();
+                crate::RpcSession::new_for_test(client)
+            });
+
+            // Launch a new connection that sends an `auth:authenticate` request with `params`.
+            let authenticate = |params: &str| {
+                let request = format!(
+                    r#"{{"id": 1, "obj": "connection", "method": "auth:authenticate", "params": {params}}}"#
+                ) + "\n";
+                let input = futures::io::Cursor::new(request.into_bytes()).chain(Silent);
+                let output = SharedBuf::default();
+                let result = rt.spawn_join(
+                    "connection",
+                    mgr.new_connection().run(
+                        input,
+                        output.clone(),
+                        rt.clone(),
+                        ConnectionConfig::default(),
+                    ),
+                );
+                (result, output)
+            };
+            // Return the single response written to `output`.
+            let response = |output: SharedBuf| {
+                let output = output.0.lock().unwrap().clone();
+                let lines = std::str::from_utf8(&output)
+                    .unwrap()
+                    .lines()
+                    .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+                    .collect::<Vec<_>>();
+                assert_eq!(lines.len(), 1);
+                lines.into_iter().next().unwrap()
+            };
+
+            let wrong_cookie = format!(
+                r#"{{"scheme": "fs:cookie", "cookie": "{}"}}"#,
+                "00".repeat(32)
+            );
+            for params in [r#"{"scheme": "inherent:unix_path"}"#, &wrong_cookie] {
+                // Without the right cookie, we're rejected, and the connection is closed.
+                let (result, output) = authenticate(params);
+                rt.advance_until_stalled().await;
+                assert!(matches!(
+                    result.await,
+                    Err(ConnectionError::AuthenticationFailed)
+                ));
+                assert!(response(output).get("error").is_some());
+            }
+
+            // With the right cookie, we get a session, and the connection stays open.
+            let (result, output) = authenticate(&format!(
+                r#"{{"scheme": "fs:cookie", "cookie": "{cookie_hex}"}}"#
+            ));
+            rt.advance_until_stalled().await;
+            let mut result = Box::pin(result);
+            assert!(futures::poll!(&mut result).is_pending());
+            // (Dropping `mgr` would shut down the connection, so we do that
+            // explicitly, and wait for it to close.)
+            mgr.shutdown();
+            assert!(result.await.is_ok());
+            assert!(response(output)["result"]["session"].is_string());
+        });
+    }
+
+    /// A method that never finishes.
+    #[derive(Debug, serde::Deserialize)]
+    struct Hang {}
+    rpc::decl_method! {"x-test:hang" => Hang}
+    impl rpc::Method for Hang {
+        type Output = rpc::Nil;
+        type Update = rpc::NoUpdates;
+    }
+    /// Implement `x-test:hang` on a connection.
+    async fn conn_hang(
+        _conn: Arc<Connection>,
+        _method: Box<Hang>,
+        _ctx: Box<dyn rpc::Context>,
+    ) -> Result<rpc::Nil, rpc::RpcError> {
+        futures::future::pending().await
+    }
+    rpc::rpc_invoke_fn! {
+        conn_hang(Connection, Hang);
+    }
+
+    #[test]
+    fn shutdown() {
+        use futures::AsyncReadExt as _;
+
+        MockRuntime::test_with_various(|rt| async move {
+            let grace = Duration::from_secs(10);
+            let config = ConnectionConfig {
+                shutdown_grace_period: grace,
+                ..Default::default()
+            };
+            let mgr = RpcMgr::new(|_| unreachable!());
+
+            // One connection with nothing to do...
+            let idle =
+                mgr.new_connection()
+                    .run(Silent, futures::io::sink(), rt.clone(), config.clone());
+            let mut idle = Box::pin(rt.spawn_join("idle", idle));
+            // ...and one with a request that never finishes.
+            let request =
+                r#"{"id": 5, "obj": "connection", "method": "x-test:hang", "params": {}}"#;
+            let input = futures::io::Cursor::new(request.as_bytes()).chain(Silent);
+            let output = SharedBuf::default();
+            let busy = mgr
+                .new_connection()
+                .run(input, output.clone(), rt.clone(), config.clone());
+            let mut busy = Box::pin(rt.spawn_join("busy", busy));
+            rt.advance_until_stalled().await;
+            assert!(futures::poll!(&mut idle).is_pending());
+            assert!(futures::poll!(&mut busy).is_pending());
+
+            mgr.shutdown();
+            // (We mustn't advance the clock here, or the grace period would
+            // already be over.)
+            rt.progress_until_stalled().await;
+            // The idle connection closes right away...
+            assert!(matches!(futures::poll!(&mut idle), Poll::Ready(Ok(()))));
+            // ...but the busy one waits for its request, until the grace
+            // period is over.
+            rt.advance_by(grace - Duration::from_secs(1)).await;
+            assert!(futures::poll!(&mut busy).is_pending());
+            rt.advance_by(Duration::from_secs(2)).await;
+            assert!(matches!(futures::poll!(&mut busy), Poll::Ready(Ok(()))));
+
+            // The request was cancelled, and the client was told so.
+            let output = output.0.lock().unwrap().clone();
+            let response: serde_json::Value = serde_json::from_slice(&output).unwrap();
+            assert_eq!(response["id"], 5);
+            assert!(response.get("error").is_some());
+
+            // Any new connection closes immediately.
+            let late = mgr
+                .new_connection()
+                .run(Silent, futures::io::sink(), rt.clone(), config);
+            assert!(late.await.is_ok());
+        });
+    }
+
+    #[test]
+    fn telemetry() {
+        use futures::AsyncReadExt as _;
+
+        MockRuntime::test_with_various(|rt| async move {
+            let timings = Arc::new(Mutex::new(Vec::new()));
+            let timings_clone = timings.clone();
+            let config = ConnectionConfig {
+                request_timeout: Some(Duration::from_secs(3)),
+                telemetry: Some(crate::TelemetrySink::new(move |t| {
+                    timings_clone.lock().unwrap().push(t.clone());
+                })),
+                ..Default::default()
+            };
+
+            // One request that finishes right away, and one that takes as long
+            // as the timeout allows.
+            let requests = concat!(
+                r#"{"id": 1, "obj": "connection", "method": "auth:query", "params": {}}"#,
+                r#"{"id": 2, "obj": "connection", "method": "x-test:hang", "params": {}}"#,
+            );
+            let input = futures::io::Cursor::new(requests.as_bytes()).chain(Silent);
+            // (`auth:query` needs the connection to have an RpcMgr.)
+            let mgr = RpcMgr::new(|_| unreachable!());
+            let conn = mgr.new_connection();
+            let result = rt.spawn_join(
+                "connection",
+                conn.run(input, futures::io::sink(), rt.clone(), config),
+            );
+            rt.advance_until_stalled().await;
+            rt.advance_by(Duration::from_secs(5)).await;
+            mgr.shutdown();
+            assert!(result.await.is_ok());
+
+            let timings = timings.lock().unwrap();
+            assert_eq!(timings.len(), 2);
+            assert_eq!(timings[0].method, "auth:query");
+            assert_eq!(timings[0].outcome, crate::RequestOutcome::Success);
+            assert_eq!(timings[0].duration(), Duration::ZERO);
+            assert_eq!(timings[1].method, "x-test:hang");
+            assert_eq!(timings[1].outcome, crate::RequestOutcome::Error);
+            assert_eq!(timings[1].duration(), Duration::from_secs(3));
+        });
+    }
+}
@@ -80,12 +80,18 @@ pub struct RpcAuthentication {}
 /// Conceptually, an authentication scheme answers the question "How can the
 /// Arti process know you have permissions to use or administer it?"
 ///
-/// TODO RPC: The only supported one for now is "inherent:unix_path"
+/// Which scheme is supported depends on whether the [`RpcMgr`](crate::RpcMgr)
+/// has an [`RpcCookie`](crate::RpcCookie): if it does, only "fs:cookie" is
+/// supported; otherwise, only "inherent:unix_path" is.
 #[derive(Debug, Copy, Clone, serde::Serialize, serde::Deserialize)]
 enum AuthenticationScheme {
     /// Inherent authority based on the ability to access an AF_UNIX address.
     #[serde(rename = "inherent:unix_path")]
     InherentUnixPath,
+    /// Authority based on knowing a pre-shared cookie, which is stored in a
+    /// file that only trusted users can read.
+    #[serde(rename = "fs:cookie")]
+    Cookie,
 }
 
 /// Method to ask which authentication methods are supported.
@@ -113,27 +119,32 @@ impl rpc::Method for AuthQuery {
 }
 /// Implement `auth:AuthQuery` on a connection.
 async fn conn_authquery(
-    _conn: Arc<Connection>,
+    conn: Arc<Connection>,
     _query: Box<AuthQuery>,
     _ctx: Box<dyn rpc::Context>,
 ) -> Result<SupportedAuth, rpc::RpcError> {
-    // Right now, every connection supports the same scheme.
+    let scheme = if conn.mgr()?.auth_cookie().is_some() {
+        AuthenticationScheme::Cookie
+    } else {
+        AuthenticationScheme::InherentUnixPath
+    };
     Ok(SupportedAuth {
-        schemes: vec![AuthenticationScheme::InherentUnixPath],
+        schemes: vec![scheme],
     })
 }
 rpc::rpc_invoke_fn! {
     conn_authquery(Connection, AuthQuery);
 }
 
-/// Method to implement basic authentication.  Right now only "I connected to
-/// you so I must have permission!" is supported.
+/// Method to implement basic authentication: either "I connected to you so I
+/// must have permission!", or "I know the cookie, so I must have permission!"
 #[derive(Debug, serde::Deserialize)]
 struct Authenticate {
     /// The authentication scheme as enumerated in the spec.
-    ///
-    /// TODO RPC: The only supported one for now is "inherent:unix_path"
     scheme: AuthenticationScheme,
+    /// The cookie, in hexadecimal, if `scheme` is "fs:cookie".
+    #[serde(default)]
+    cookie: Option<String>,
 }
 
 /// A reply from the `Authenticate` method.
@@ -151,7 +162,14 @@ impl rpc::Method for Authenticate {
 
 /// An error during authentication.
 #[derive(Debug, Clone, thiserror::Error, serde::Serialize)]
-enum AuthenticationFailure {}
+enum AuthenticationFailure {
+    /// The client asked for a scheme that this connection doesn't support.
+    #[error("Authentication scheme not supported")]
+    SchemeNotSupported,
+    /// The client didn't present a cookie, or presented the wrong one.
+    #[error("Incorrect or missing RPC cookie")]
+    IncorrectCookie,
+}
 
 impl tor_error::HasKind for AuthenticationFailure {
     fn kind(&self) -> tor_error::ErrorKind {
@@ -162,6 +180,9 @@ impl tor_error::HasKind for AuthenticationFailure {
 
 /// Invoke the "authenticate" method on a connection.
 ///
+/// If authentication fails, the connection is closed once the client has been
+/// told so.
+///
 /// TODO RPC: This behavior is wrong; we'll need to fix it to be all
 /// capabilities-like.
 async fn authenticate_connection(
@@ -169,18 +190,29 @@ async fn authenticate_connection(
     method: Box<Authenticate>,
     ctx: Box<dyn rpc::Context>,
 ) -> Result<AuthenticateReply, rpc::RpcError> {
-    match method.scheme {
-        // For now, we only support AF_UNIX connections, and we assume that if
-        // you have permission to open such a connection to us, you have
-        // permission to use Arti. We will refine this later on!
-        AuthenticationScheme::InherentUnixPath => {}
+    let mgr = unauth.mgr()?;
+    match (method.scheme, mgr.auth_cookie()) {
+        // If we have no cookie, we only support AF_UNIX connections, and we
+        // assume that if you have permission to open such a connection to us,
+        // you have permission to use Arti.
+        (AuthenticationScheme::InherentUnixPath, None) => {}
+        // If we have a cookie, you need to prove that you can read it.
+        (AuthenticationScheme::Cookie, Some(cookie)) => {
+            let presented = method.cookie.as_deref().unwrap_or_default();
+            if !cookie.matches(presented) {
+                unauth.note_authentication_failed();
+                return Err(AuthenticationFailure::IncorrectCookie.into());
+            }
+        }
+        (AuthenticationScheme::InherentUnixPath, Some(_))
+        | (AuthenticationScheme::Cookie, None) => {
+            unauth.note_authentication_failed();
+            return Err(AuthenticationFailure::SchemeNotSupported.into());
+        }
     }
 
     let auth = RpcAuthentication {};
-    let session = {
-        let mgr = unauth.mgr()?;
-        mgr.create_session(&auth)
-    };
+    let session = mgr.create_session(&auth);
     let session = ctx.register_owned(session);
     Ok(AuthenticateReply { session })
 }
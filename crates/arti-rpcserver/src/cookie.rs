@@ -0,0 +1,157 @@
+//! Pre-shared cookies, used to authenticate RPC connections.
+
+use std::path::{Path, PathBuf};
+
+use fs_mistrust::Mistrust;
+use rand::{CryptoRng, Rng};
+use tor_llcrypto::util::ct::CtByteArray;
+use zeroize::Zeroizing;
+
+/// The length of an RPC cookie, in bytes.
+const COOKIE_LEN: usize = 32;
+
+/// A secret shared between Arti and its RPC clients.
+///
+/// If an [`RpcMgr`](crate::RpcMgr) has a cookie, then a client must present
+/// that cookie (using the `"fs:cookie"` authentication scheme) before it can get
+/// an RPC session: anybody else who can reach the RPC socket can't do anything
+/// with it.
+///
+/// Both on disk and on the wire, the cookie is written as a hexadecimal string.
+#[derive(Clone)]
+pub struct RpcCookie {
+    /// The secret value of this cookie.
+    value: Zeroizing<CtByteArray<COOKIE_LEN>>,
+}
+
+impl std::fmt::Debug for RpcCookie {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RpcCookie").finish_non_exhaustive()
+    }
+}
+
+/// An error that occurred while loading or creating an [`RpcCookie`].
+#[derive(Clone, Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum CookieError {
+    /// We couldn't read or write the cookie file, or its permissions were
+    /// wrong.
+    #[error("Problem accessing RPC cookie file")]
+    Access(#[from] fs_mistrust::Error),
+
+    /// The cookie file didn't contain a cookie.
+    #[error("RPC cookie file {0:?} was malformed")]
+    Malformed(PathBuf),
+
+    /// The cookie file's location didn't name a file in a directory.
+    #[error("Invalid location {0:?} for RPC cookie file")]
+    BadPath(PathBuf),
+}
+
+impl RpcCookie {
+    /// Generate a new random cookie.
+    pub fn new<R: Rng + CryptoRng>(rng: &mut R) -> Self {
+        let value: [u8; COOKIE_LEN] = rng.gen();
+        Self {
+            value: Zeroizing::new(value.into()),
+        }
+    }
+
+    /// Load a cookie from the file at `path`; or, if there is no such file,
+    /// generate a new cookie and store it there.
+    ///
+    /// Use `mistrust` to make sure that nobody untrusted can read or replace
+    /// the cookie.
+    pub fn load_or_create<P: AsRef<Path>>(
+        path: P,
+        mistrust: &Mistrust,
+    ) -> Result<Self, CookieError> {
+        let path = path.as_ref();
+        let (dir, fname) = match (path.parent(), path.file_name()) {
+            (Some(dir), Some(fname)) => (dir, fname),
+            _ => return Err(CookieError::BadPath(path.into())),
+        };
+        let dir = mistrust.verifier().make_secure_dir(dir)?;
+
+        match dir.read_to_string(fname) {
+            Ok(text) => Self::parse(&text).ok_or_else(|| CookieError::Malformed(path.into())),
+            Err(fs_mistrust::Error::NotFound(_)) => {
+                let cookie = Self::new(&mut rand::thread_rng());
+                let hex = Zeroizing::new(cookie.to_hex());
+                dir.write_and_replace(fname, hex.as_bytes())?;
+                Ok(cookie)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Try to parse a cookie from its hexadecimal representation,
+    /// ignoring any surrounding whitespace.
+    fn parse(s: &str) -> Option<Self> {
+        let mut value = Zeroizing::new([0_u8; COOKIE_LEN]);
+        hex::decode_to_slice(s.trim(), &mut value[..]).ok()?;
+        Some(Self {
+            value: Zeroizing::new((*value).into()),
+        })
+    }
+
+    /// Return the hexadecimal representation of this cookie.
+    fn to_hex(&self) -> String {
+        let value: &[u8; COOKIE_LEN] = (*self.value).as_ref();
+        hex::encode(value)
+    }
+
+    /// Return true if `presented` is the hexadecimal representation of this
+    /// cookie.
+    ///
+    /// The comparison is done in constant time.
+    pub(crate) fn matches(&self, presented: &str) -> bool {
+        match Self::parse(presented) {
+            Some(other) => self.value == other.value,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+
+    #[test]
+    fn load_or_create() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("cookie");
+        let mistrust = Mistrust::new_dangerously_trust_everyone();
+
+        // The first time, we create a cookie...
+        let cookie = RpcCookie::load_or_create(&path, &mistrust).unwrap();
+        let hex = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(hex.len(), COOKIE_LEN * 2);
+        assert!(cookie.matches(&hex));
+
+        // ...and after that, we load the same one.
+        let again = RpcCookie::load_or_create(&path, &mistrust).unwrap();
+        assert!(again.matches(&cookie.to_hex()));
+        assert!(!RpcCookie::new(&mut rand::thread_rng()).matches(&hex));
+        assert!(!cookie.matches("not hex"));
+
+        std::fs::write(&path, "12345").unwrap();
+        assert!(matches!(
+            RpcCookie::load_or_create(&path, &mistrust),
+            Err(CookieError::Malformed(_))
+        ));
+    }
+}
@@ -7,6 +7,10 @@ pub(crate) enum RequestParseError {
     #[error("Error in json syntax.")]
     InvalidJson,
 
+    /// The provided item was not well-formed CBOR.
+    #[error("Error in CBOR syntax.")]
+    InvalidCbor,
+
     /// Received something that was json, but not a json object.
     #[error("Received something other than a json object.")]
     NotAnObject,
@@ -52,6 +56,10 @@ pub(crate) enum RequestParseError {
     /// The `params` field was missing.
     #[error("Request's `params` field was missing.")]
     MissingParams,
+
+    /// The request was longer than we are willing to accept.
+    #[error("Request was too long.")]
+    TooLong,
 }
 
 impl tor_error::HasKind for RequestParseError {
@@ -60,6 +68,7 @@ impl tor_error::HasKind for RequestParseError {
 
         match self {
             Self::InvalidJson
+            | Self::InvalidCbor
             | Self::NotAnObject
             | Self::IdMissing
             | Self::IdType
@@ -68,7 +77,8 @@ impl tor_error::HasKind for RequestParseError {
             | Self::MethodMissing
             | Self::MethodType
             | Self::MetaType
-            | Self::MissingParams => EK::RpcInvalidRequest,
+            | Self::MissingParams
+            | Self::TooLong => EK::RpcInvalidRequest,
             Self::MethodUnrecognized => EK::RpcMethodNotFound,
             Self::ParamType => EK::RpcInvalidMethodParameters,
         }
@@ -41,6 +41,7 @@
 
 mod cancel;
 mod connection;
+mod cookie;
 mod err;
 mod globalid;
 mod mgr;
@@ -48,7 +49,11 @@ mod msgs;
 mod objmap;
 mod session;
 mod streams;
+mod telemetry;
 
-pub use connection::{auth::RpcAuthentication, Connection, ConnectionError};
+pub use connection::{auth::RpcAuthentication, Connection, ConnectionConfig, ConnectionError};
+pub use cookie::{CookieError, RpcCookie};
 pub use mgr::RpcMgr;
 pub use session::RpcSession;
+pub use streams::WireFormat;
+pub use telemetry::{RequestOutcome, RequestTiming, TelemetrySink};
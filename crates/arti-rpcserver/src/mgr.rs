@@ -2,14 +2,16 @@
 
 use std::sync::{Arc, Mutex, RwLock, Weak};
 
+use futures::future::{Future, FutureExt as _, Shared};
 use rand::Rng;
+use tor_async_utils::oneshot;
 use tor_rpcbase as rpc;
 use weak_table::WeakValueHashMap;
 
 use crate::{
     connection::{Connection, ConnectionId},
     globalid::{GlobalId, MacKey},
-    RpcAuthentication, RpcSession,
+    RpcAuthentication, RpcCookie, RpcSession,
 };
 
 /// A function we use to construct Session objects in response to authentication.
@@ -43,6 +45,17 @@ pub struct RpcMgr {
     /// is successful.
     session_factory: SessionFactory,
 
+    /// If present, a cookie that clients must present in order to authenticate.
+    ///
+    /// If this is `None`, we trust anybody who can connect to us.
+    auth_cookie: Option<RpcCookie>,
+
+    /// A future that becomes ready once [`RpcMgr::shutdown`] is called.
+    ///
+    /// (It becomes ready when the corresponding sender, in
+    /// [`Inner::shutdown_tx`], is dropped.)
+    shutdown_rx: Shared<oneshot::Receiver<()>>,
+
     /// Lock-protected view of the manager's state.
     ///
     /// **NOTE: observe the [Lock hierarchy](crate::mgr::Inner#lock-hierarchy)**
@@ -89,6 +102,11 @@ pub(crate) struct Inner {
     /// MACing anything derived from them, which in turn makes the overhead of a
     /// HashMap negligible.
     connections: WeakValueHashMap<ConnectionId, Weak<Connection>>,
+
+    /// A sender that we drop in order to tell everybody to shut down.
+    ///
+    /// This is `None` once we have started shutting down.
+    shutdown_tx: Option<oneshot::Sender<()>>,
 }
 
 impl RpcMgr {
@@ -98,12 +116,33 @@ impl RpcMgr {
     where
         F: Fn(&RpcAuthentication) -> Arc<RpcSession> + Send + Sync + 'static,
     {
+        Self::new_inner(make_session, None)
+    }
+
+    /// Create a new RpcMgr that only gives sessions to clients that present
+    /// `cookie`.
+    pub fn new_with_cookie<F>(cookie: RpcCookie, make_session: F) -> Arc<Self>
+    where
+        F: Fn(&RpcAuthentication) -> Arc<RpcSession> + Send + Sync + 'static,
+    {
+        Self::new_inner(make_session, Some(cookie))
+    }
+
+    /// Helper: Create a new RpcMgr, with an optional cookie.
+    fn new_inner<F>(make_session: F, auth_cookie: Option<RpcCookie>) -> Arc<Self>
+    where
+        F: Fn(&RpcAuthentication) -> Arc<RpcSession> + Send + Sync + 'static,
+    {
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
         Arc::new(RpcMgr {
             global_id_mac_key: MacKey::new(&mut rand::thread_rng()),
             dispatch_table: Arc::new(RwLock::new(rpc::DispatchTable::from_inventory())),
             session_factory: Box::new(make_session),
+            auth_cookie,
+            shutdown_rx: shutdown_rx.shared(),
             inner: Mutex::new(Inner {
                 connections: WeakValueHashMap::new(),
+                shutdown_tx: Some(shutdown_tx),
             }),
         })
     }
@@ -158,6 +197,33 @@ impl RpcMgr {
         connection.lookup_by_idx(id.local_id)
     }
 
+    /// Shut down every [`Connection`] belonging to this `RpcMgr`.
+    ///
+    /// Each connection stops reading new requests, and closes once all of its
+    /// requests in progress are done.  Requests that don't finish within the
+    /// connection's [`shutdown_grace_period`](crate::ConnectionConfig::shutdown_grace_period)
+    /// are cancelled.
+    ///
+    /// Any connection created after this is called will shut down as soon as
+    /// it is run.  Listeners should stop accepting new connections once
+    /// [`shutdown_requested`](RpcMgr::shutdown_requested) is ready.
+    pub fn shutdown(&self) {
+        let mut inner = self.inner.lock().expect("lock poisoned");
+        // Dropping the sender wakes everybody waiting on `shutdown_rx`.
+        drop(inner.shutdown_tx.take());
+    }
+
+    /// Return a future that becomes ready once [`RpcMgr::shutdown`] has been
+    /// called.
+    pub fn shutdown_requested(&self) -> impl Future<Output = ()> + Send + 'static {
+        self.shutdown_rx.clone().map(|_| ())
+    }
+
+    /// Return the cookie that clients must present to authenticate, if any.
+    pub(crate) fn auth_cookie(&self) -> Option<&RpcCookie> {
+        self.auth_cookie.as_ref()
+    }
+
     /// Construct a new object to serve as the `session` for a connection.
     pub(crate) fn create_session(&self, auth: &RpcAuthentication) -> Arc<RpcSession> {
         (self.session_factory)(auth)
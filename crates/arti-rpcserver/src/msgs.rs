@@ -70,10 +70,42 @@ pub(crate) struct Request {
 pub(crate) enum FlexibleRequest {
     /// A valid request.
     Valid(Request),
+    /// A batch of requests, sent as an array.
+    ///
+    /// Each request in a batch is handled just as if it had been sent on its
+    /// own: the requests run concurrently, and their responses are sent
+    /// (correlated by `id`) as soon as each one is ready.
+    ///
+    /// (This variant has to come before `Invalid`, or else serde would try to
+    /// parse the array as an `InvalidRequest`.)
+    Batch(Vec<SingleRequest>),
     /// An invalid request.
     Invalid(invalid::InvalidRequest),
 }
 
+/// A single request, not part of a batch, that may or may not be valid.
+#[derive(Debug, serde::Deserialize)]
+#[serde(untagged)]
+pub(crate) enum SingleRequest {
+    /// A valid request.
+    Valid(Request),
+    /// An invalid request.
+    Invalid(invalid::InvalidRequest),
+}
+
+impl FlexibleRequest {
+    /// Return every individual request in this request, in order.
+    ///
+    /// (A batch yields its members; anything else yields itself.)
+    pub(crate) fn into_requests(self) -> Vec<SingleRequest> {
+        match self {
+            FlexibleRequest::Valid(req) => vec![SingleRequest::Valid(req)],
+            FlexibleRequest::Batch(reqs) => reqs,
+            FlexibleRequest::Invalid(bad) => vec![SingleRequest::Invalid(bad)],
+        }
+    }
+}
+
 /// A Response to send to an RPC client.
 #[derive(Debug, Serialize)]
 pub(crate) struct BoxedResponse {
@@ -122,17 +154,6 @@ pub(crate) enum ResponseBody {
     Update(Box<dyn erased_serde::Serialize + Send>),
 }
 
-impl ResponseBody {
-    /// Return true if this body type indicates that no future responses will be
-    /// sent for this request.
-    pub(crate) fn is_final(&self) -> bool {
-        match self {
-            ResponseBody::Error(_) | ResponseBody::Success(_) => true,
-            ResponseBody::Update(_) => false,
-        }
-    }
-}
-
 impl From<rpc::RpcError> for ResponseBody {
     fn from(inp: rpc::RpcError) -> ResponseBody {
         ResponseBody::Error(Box::new(inp))
@@ -223,6 +244,24 @@ mod test {
         );
     }
 
+    #[test]
+    fn batch_requests() {
+        let reqs = match serde_json::from_str::<FlexibleRequest>(
+            r#"[{"id": 1, "obj": "hello", "method": "x-test:dummy", "params": {} },
+                {"id": 2, "obj": "hello", "method": "x-test:dummy", "params": {"stuff": 3} },
+                {"id": 3, "obj": "hello", "method": "x-test:dummy" }]"#,
+        ) {
+            Ok(req @ FlexibleRequest::Batch(_)) => req.into_requests(),
+            x => panic!("Didn't expect {:?}", x),
+        };
+        assert_eq!(reqs.len(), 3);
+        assert!(matches!(&reqs[0], SingleRequest::Valid(r) if r.id == RequestId::Int(1)));
+        assert!(matches!(&reqs[1], SingleRequest::Valid(r) if r.id == RequestId::Int(2)));
+        assert!(
+            matches!(&reqs[2], SingleRequest::Invalid(r) if r.id() == Some(&RequestId::Int(3)))
+        );
+    }
+
     #[test]
     fn invalid_requests() {
         use crate::err::RequestParseError as RPE;
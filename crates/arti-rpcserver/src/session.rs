@@ -36,6 +36,12 @@ impl RpcSession {
     ) -> Arc<Self> {
         Arc::new(Self { client })
     }
+
+    /// Create a new session object wrapping an arbitrary object, for testing.
+    #[cfg(test)]
+    pub(crate) fn new_for_test(client: Arc<dyn rpc::Object>) -> Arc<Self> {
+        Arc::new(Self { client })
+    }
 }
 
 /// RPC method to release a single strong reference.
@@ -78,6 +84,64 @@ rpc::rpc_invoke_fn! {
     rpc_release(RpcSession,RpcRelease);
 }
 
+/// RPC method to describe what this RPC server supports.
+///
+/// Clients can use this to adapt to whichever version of Arti they're
+/// talking to.
+#[derive(Debug, serde::Deserialize)]
+struct RpcDescribe {}
+
+/// Reply to the [`RpcDescribe`] method.
+#[derive(Debug, serde::Serialize)]
+struct RpcDescription {
+    /// The version of the RPC protocol that this server speaks.
+    protocol_version: RpcProtocolVersion,
+    /// The names of every method that this server recognizes, in sorted order.
+    ///
+    /// (A method may still be unsupported on any particular object.)
+    methods: Vec<&'static str>,
+}
+
+/// Identifier for a version of the RPC protocol.
+#[derive(Debug, Copy, Clone, serde::Serialize)]
+enum RpcProtocolVersion {
+    /// Alpha version of the protocol.  Things might break between here and the
+    /// stable protocol.
+    #[serde(rename = "alpha")]
+    Alpha,
+}
+
+impl RpcDescription {
+    /// Return a description of this RPC server.
+    fn current() -> Self {
+        let mut methods: Vec<_> = rpc::iter_method_names().collect();
+        methods.sort_unstable();
+        methods.dedup();
+        RpcDescription {
+            protocol_version: RpcProtocolVersion::Alpha,
+            methods,
+        }
+    }
+}
+
+rpc::decl_method! { "rpc:describe" => RpcDescribe}
+impl rpc::Method for RpcDescribe {
+    type Output = RpcDescription;
+    type Update = rpc::NoUpdates;
+}
+
+/// Implementation for calling "describe" on a Session.
+async fn rpc_describe(
+    _obj: Arc<RpcSession>,
+    _method: Box<RpcDescribe>,
+    _ctx: Box<dyn rpc::Context>,
+) -> Result<RpcDescription, rpc::RpcError> {
+    Ok(RpcDescription::current())
+}
+rpc::rpc_invoke_fn! {
+    rpc_describe(RpcSession,RpcDescribe);
+}
+
 /// A simple temporary method to echo a reply.
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct Echo {
@@ -104,3 +168,37 @@ async fn echo_on_session(
 rpc::rpc_invoke_fn! {
     echo_on_session(RpcSession,Echo);
 }
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+
+    #[test]
+    fn describe() {
+        let desc = RpcDescription::current();
+        assert!(desc.methods.contains(&"rpc:release"));
+        assert!(desc.methods.contains(&"rpc:describe"));
+        assert!(desc.methods.windows(2).all(|w| w[0] < w[1]));
+
+        let json: serde_json::Value = serde_json::to_value(&desc).unwrap();
+        assert_eq!(json["protocol_version"], "alpha");
+        assert!(json["methods"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|m| m == "auth:authenticate"));
+    }
+}
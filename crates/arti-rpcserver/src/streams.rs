@@ -1,19 +1,279 @@
-//! Helper types for framing Json objects into async read/writes
+//! Helper types for framing Json (or CBOR) objects into async read/writes
 
 use std::marker::PhantomData;
 
-use asynchronous_codec::JsonCodec;
-use bytes::BytesMut;
+use asynchronous_codec::{Decoder, Encoder, JsonCodec, JsonCodecError};
+use bytes::{Buf as _, BufMut as _, BytesMut};
 use serde::Serialize;
 
 use crate::msgs::BoxedResponse;
 use crate::msgs::FlexibleRequest;
 
+/// The largest request that we will buffer by default, in bytes.
+pub(crate) const DEFAULT_MAX_REQUEST_LEN: usize = 1024 * 1024;
+
+/// The encoding used for requests and responses on an RPC connection.
+///
+/// Whichever format is used, the messages themselves have the same structure:
+/// a CBOR request is a map with the same keys and values as the corresponding
+/// JSON object.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum WireFormat {
+    /// Each request is a JSON object; each response is a JSON object followed
+    /// by a newline.
+    #[default]
+    Json,
+    /// Each request and each response is a single CBOR data item
+    /// (see [RFC 8949](https://www.rfc-editor.org/rfc/rfc8949)),
+    /// with no separator between them.
+    Cbor,
+}
+
 /// A stream of [`Request`](crate::msgs::Request)
-/// taken from `T` (an `AsyncRead`) and deserialized from Json.
-#[allow(dead_code)] // TODO RPC
-pub(crate) type RequestStream<T> =
-    asynchronous_codec::FramedRead<T, JsonCodec<(), FlexibleRequest>>;
+/// taken from `T` (an `AsyncRead`) and deserialized from Json or CBOR.
+pub(crate) type RequestStream<T> = asynchronous_codec::FramedRead<T, RequestDecoder>;
+
+/// An error that occurred while reading a request from a [`RequestStream`].
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum RequestStreamError {
+    /// We couldn't read from the stream, or couldn't decode the request.
+    #[error("{0}")]
+    Codec(#[from] JsonCodecError),
+
+    /// We couldn't decode a CBOR request.
+    #[error("{0}")]
+    Cbor(#[from] ciborium::de::Error<std::io::Error>),
+
+    /// The client sent a request that was larger than we are willing to
+    /// buffer.
+    #[error("Request was longer than {0} bytes")]
+    TooLong(usize),
+}
+
+impl From<std::io::Error> for RequestStreamError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Codec(e.into())
+    }
+}
+
+/// As JsonCodec, but only supports decoding [`FlexibleRequest`]s, and refuses
+/// to buffer more than a given number of bytes for a single request.
+///
+/// Requests may be split across any number of reads.  JSON objects and CBOR
+/// data items are both self-delimiting, so (as the spec requires) we don't
+/// insist on a newline after each one.
+pub(crate) struct RequestDecoder {
+    /// The decoder that actually parses the requests.
+    inner: RequestCodec,
+    /// The largest number of bytes we will buffer while waiting for a request
+    /// to be complete.
+    max_len: usize,
+}
+
+/// The format-specific part of a [`RequestDecoder`].
+enum RequestCodec {
+    /// Requests are JSON objects.
+    Json(JsonCodec<(), FlexibleRequest>),
+    /// Requests are CBOR data items.
+    Cbor(CborScanner),
+}
+
+impl RequestDecoder {
+    /// Create a new `RequestDecoder` that will reject any request longer than
+    /// `max_len` bytes.
+    pub(crate) fn new(max_len: usize) -> Self {
+        Self::for_format(WireFormat::Json, max_len)
+    }
+
+    /// Create a new `RequestDecoder` for requests in `format`,
+    /// that will reject any request longer than `max_len` bytes.
+    pub(crate) fn for_format(format: WireFormat, max_len: usize) -> Self {
+        let inner = match format {
+            WireFormat::Json => RequestCodec::Json(JsonCodec::new()),
+            WireFormat::Cbor => RequestCodec::Cbor(CborScanner::default()),
+        };
+        Self { inner, max_len }
+    }
+}
+
+/// How deeply we let the containers in a CBOR request nest.
+///
+/// This is the same as ciborium's recursion limit:
+/// anything deeper would be rejected when we deserialized it anyway.
+const MAX_CBOR_NESTING: usize = 256;
+
+/// Finds the end of the CBOR data item at the start of a buffer.
+///
+/// The item may arrive across any number of reads.  We remember how far we
+/// got, so that each byte is only scanned once however the item is split up,
+/// and we only deserialize the item once it is complete.
+#[derive(Default)]
+struct CborScanner {
+    /// How many bytes of the item we have scanned so far.
+    ///
+    /// This is always at the end of a data item's head, or of a string's contents.
+    offset: usize,
+    /// The containers that we are inside at `offset`, innermost last.
+    open: Vec<CborContainer>,
+}
+
+/// A CBOR container that the [`CborScanner`] hasn't reached the end of.
+///
+/// Arrays, maps, tags, and indefinite-length strings are all containers.
+enum CborContainer {
+    /// A definite-length container, which still needs this many more items.
+    Items(u64),
+    /// An indefinite-length container, which ends with a "break" byte.
+    UntilBreak,
+}
+
+impl CborScanner {
+    /// Scan as much as we can of the item at the start of `buf`.
+    ///
+    /// Return the item's length if `buf` holds all of it.  Give an error if
+    /// the item is malformed, nests too deeply, or can't fit in `max_len`
+    /// bytes (which we can often tell before it has arrived).
+    ///
+    /// Until this returns the item's length, `buf` must keep starting with the
+    /// same bytes on each call.  Afterwards, we are ready to scan a new item.
+    fn scan(&mut self, buf: &[u8], max_len: usize) -> Result<Option<usize>, RequestStreamError> {
+        loop {
+            let start = self.offset;
+            let Some(&initial) = buf.get(start) else {
+                return Ok(None);
+            };
+            let (major, info) = (initial >> 5, initial & 0x1f);
+            let syntax_error = || ciborium::de::Error::Syntax(start).into();
+            let too_long = || RequestStreamError::TooLong(max_len);
+
+            // The head's argument (None for indefinite length), and where the head ends.
+            let (arg, mut end) = match info {
+                0..=23 => (Some(u64::from(info)), start + 1),
+                24..=27 => {
+                    let n = 1 << (info - 24);
+                    let Some(bytes) = buf.get(start + 1..start + 1 + n) else {
+                        return Ok(None);
+                    };
+                    let arg = bytes.iter().fold(0, |arg, b| (arg << 8) | u64::from(*b));
+                    (Some(arg), start + 1 + n)
+                }
+                31 => (None, start + 1),
+                _ => return Err(syntax_error()),
+            };
+
+            let mut opened = None;
+            match (major, arg) {
+                // Integers, simple values, and floats: there's nothing after the head.
+                (0 | 1 | 7, Some(_)) => {}
+                // Byte and text strings: the contents follow the head.
+                (2 | 3, Some(len)) => {
+                    end = usize::try_from(len)
+                        .ok()
+                        .and_then(|len| end.checked_add(len))
+                        .ok_or_else(too_long)?;
+                }
+                // Arrays, maps, and tags: the contents are more data items.
+                (4..=6, Some(n)) => {
+                    let n = match major {
+                        4 => Some(n),
+                        5 => n.checked_mul(2),
+                        _ => Some(1),
+                    };
+                    // Every item takes at least one byte.
+                    let n = n.filter(|n| *n <= max_len as u64).ok_or_else(too_long)?;
+                    if n > 0 {
+                        opened = Some(CborContainer::Items(n));
+                    }
+                }
+                (2..=5, None) => opened = Some(CborContainer::UntilBreak),
+                // A "break", closing the innermost indefinite-length container.
+                (7, None) => match self.open.pop() {
+                    Some(CborContainer::UntilBreak) => {}
+                    _ => return Err(syntax_error()),
+                },
+                _ => return Err(syntax_error()),
+            }
+
+            if end > max_len {
+                return Err(too_long());
+            }
+            if end > buf.len() {
+                return Ok(None);
+            }
+            self.offset = end;
+
+            if let Some(container) = opened {
+                if self.open.len() >= MAX_CBOR_NESTING {
+                    return Err(ciborium::de::Error::RecursionLimitExceeded.into());
+                }
+                self.open.push(container);
+                continue;
+            }
+
+            // We have reached the end of an item: see which containers that completes.
+            loop {
+                match self.open.last_mut() {
+                    None => {
+                        *self = Self::default();
+                        return Ok(Some(end));
+                    }
+                    Some(CborContainer::Items(n)) => {
+                        *n -= 1;
+                        if *n > 0 {
+                            break;
+                        }
+                        self.open.pop();
+                    }
+                    Some(CborContainer::UntilBreak) => break,
+                }
+            }
+        }
+    }
+}
+
+/// Try to decode a single CBOR request from the start of `src`,
+/// using `scanner` to tell whether it has all arrived.
+///
+/// On success, remove the request from `src`.  If `src` holds only part of a
+/// request, leave it alone and return `None`.
+fn decode_cbor(
+    scanner: &mut CborScanner,
+    src: &mut BytesMut,
+    max_len: usize,
+) -> Result<Option<FlexibleRequest>, RequestStreamError> {
+    let Some(len) = scanner.scan(src, max_len)? else {
+        return Ok(None);
+    };
+    let request = ciborium::de::from_reader(&src[..len])?;
+    src.advance(len);
+    Ok(Some(request))
+}
+
+impl Default for RequestDecoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_REQUEST_LEN)
+    }
+}
+
+impl Decoder for RequestDecoder {
+    type Item = FlexibleRequest;
+
+    type Error = RequestStreamError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let request = match &mut self.inner {
+            RequestCodec::Json(codec) => codec.decode(src)?,
+            RequestCodec::Cbor(scanner) => decode_cbor(scanner, src, self.max_len)?,
+        };
+        match request {
+            Some(request) => Ok(Some(request)),
+            // Whatever is left is (the start of) a single incomplete request.
+            None if src.len() > self.max_len => Err(RequestStreamError::TooLong(self.max_len)),
+            None => Ok(None),
+        }
+    }
+}
 
 /// As JsonCodec, but only supports encoding, and places a newline after every
 /// object.
@@ -31,7 +291,7 @@ impl<T> Default for JsonLinesEncoder<T> {
     }
 }
 
-impl<T> asynchronous_codec::Encoder for JsonLinesEncoder<T>
+impl<T> Encoder for JsonLinesEncoder<T>
 where
     T: Serialize + 'static,
 {
@@ -55,6 +315,71 @@ where
 pub(crate) type ResponseSink<T> =
     asynchronous_codec::FramedWrite<T, JsonLinesEncoder<BoxedResponse>>;
 
+/// An error that occurred while writing a response onto a
+/// [`ResponseEncoder`]'s output.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ResponseSinkError {
+    /// We couldn't write to the output.
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    /// We couldn't encode the response as JSON.
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+
+    /// We couldn't encode the response as CBOR.
+    #[error("{0}")]
+    Cbor(#[from] ciborium::ser::Error<std::io::Error>),
+}
+
+impl From<JsonCodecError> for ResponseSinkError {
+    fn from(e: JsonCodecError) -> Self {
+        match e {
+            JsonCodecError::Io(e) => Self::Io(e),
+            JsonCodecError::Json(e) => Self::Json(e),
+        }
+    }
+}
+
+/// An encoder that writes objects in a given [`WireFormat`].
+///
+/// JSON objects are each followed by a newline, as with [`JsonLinesEncoder`];
+/// CBOR data items are written back-to-back.
+#[derive(Clone)]
+pub(crate) struct ResponseEncoder<T> {
+    /// The format to write.
+    format: WireFormat,
+    /// We consume objects of type T.
+    _phantom: PhantomData<fn(T) -> ()>,
+}
+
+impl<T> ResponseEncoder<T> {
+    /// Create a new `ResponseEncoder` that writes objects in `format`.
+    pub(crate) fn new(format: WireFormat) -> Self {
+        Self {
+            format,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> Encoder for ResponseEncoder<T>
+where
+    T: Serialize + 'static,
+{
+    type Item<'a> = T;
+
+    type Error = ResponseSinkError;
+
+    fn encode(&mut self, item: Self::Item<'_>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        match self.format {
+            WireFormat::Json => JsonLinesEncoder::default().encode(item, dst)?,
+            WireFormat::Cbor => ciborium::ser::into_writer(&item, dst.writer())?,
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     // @@ begin test lint list maintained by maint/add_warning @@
@@ -118,4 +443,224 @@ mod test {
         // Make sure that the output is what we expected.
         assert_eq!(std::str::from_utf8(&buf).unwrap(), &expect);
     }
+
+    /// A request for a method that doesn't exist: it decodes as an invalid
+    /// request with an ID.
+    const REQUEST: &str = r#"{"id": 7, "obj": "hello", "method": "x-test:nonesuch", "params": {}}"#;
+
+    /// Assert that `r` is the request from [`REQUEST`].
+    fn assert_is_request(r: Option<FlexibleRequest>) {
+        match r {
+            Some(FlexibleRequest::Invalid(bad)) => assert_eq!(bad.id(), Some(&RequestId::Int(7))),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn request_split_across_reads() {
+        let mut dec = RequestDecoder::new(REQUEST.len());
+        let mut buf = BytesMut::new();
+        let (first, second) = REQUEST.split_at(20);
+
+        buf.extend_from_slice(first.as_bytes());
+        assert!(dec.decode(&mut buf).unwrap().is_none());
+
+        // The rest of the request, followed by the start of another.
+        buf.extend_from_slice(second.as_bytes());
+        buf.extend_from_slice(b"\n");
+        buf.extend_from_slice(first.as_bytes());
+        assert_is_request(dec.decode(&mut buf).unwrap());
+        assert!(dec.decode(&mut buf).unwrap().is_none());
+
+        buf.extend_from_slice(second.as_bytes());
+        assert_is_request(dec.decode(&mut buf).unwrap());
+        assert!(dec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn request_too_long() {
+        let mut dec = RequestDecoder::new(REQUEST.len() - 1);
+        let mut buf = BytesMut::new();
+
+        // We don't object to a partial request that still fits...
+        buf.extend_from_slice(&REQUEST.as_bytes()[..REQUEST.len() - 1]);
+        assert!(dec.decode(&mut buf).unwrap().is_none());
+
+        // ...but we do once it's too long to be complete.
+        buf.extend_from_slice(b"  ");
+        assert!(matches!(
+            dec.decode(&mut buf),
+            Err(RequestStreamError::TooLong(n)) if n == REQUEST.len() - 1
+        ));
+    }
+
+    /// Return [`REQUEST`], encoded as CBOR.
+    fn request_cbor() -> Vec<u8> {
+        let value: serde_json::Value = serde_json::from_str(REQUEST).unwrap();
+        let mut cbor = Vec::new();
+        ciborium::ser::into_writer(&value, &mut cbor).unwrap();
+        cbor
+    }
+
+    #[test]
+    fn cbor_request_split_across_reads() {
+        let request = request_cbor();
+        let mut dec = RequestDecoder::for_format(WireFormat::Cbor, request.len());
+        let mut buf = BytesMut::new();
+        let (first, second) = request.split_at(20);
+
+        buf.extend_from_slice(first);
+        assert!(dec.decode(&mut buf).unwrap().is_none());
+        assert_eq!(buf.len(), first.len());
+
+        // The rest of the request, followed by the start of another.
+        buf.extend_from_slice(second);
+        buf.extend_from_slice(first);
+        assert_is_request(dec.decode(&mut buf).unwrap());
+        assert!(dec.decode(&mut buf).unwrap().is_none());
+
+        buf.extend_from_slice(second);
+        assert_is_request(dec.decode(&mut buf).unwrap());
+        assert!(dec.decode(&mut buf).unwrap().is_none());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn cbor_request_one_byte_at_a_time() {
+        let request = request_cbor();
+        let mut dec = RequestDecoder::for_format(WireFormat::Cbor, request.len());
+        let mut buf = BytesMut::new();
+
+        let (last, rest) = request.split_last().unwrap();
+        for b in rest {
+            buf.extend_from_slice(&[*b]);
+            assert!(dec.decode(&mut buf).unwrap().is_none());
+        }
+        buf.extend_from_slice(&[*last]);
+        assert_is_request(dec.decode(&mut buf).unwrap());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn cbor_indefinite_length() {
+        // {"id": 7, "obj": "hello", "method": "x-test:nonesuch", "params": {}},
+        // with the outer map and the "hello" string in indefinite-length form.
+        let mut request = vec![0xbf, 0x62];
+        request.extend(b"id");
+        request.extend([0x07, 0x63]);
+        request.extend(b"obj");
+        request.extend([0x7f, 0x63]);
+        request.extend(b"hel");
+        request.push(0x62);
+        request.extend(b"lo");
+        request.extend([0xff, 0x66]);
+        request.extend(b"method");
+        request.push(0x6f);
+        request.extend(b"x-test:nonesuch");
+        request.push(0x66);
+        request.extend(b"params");
+        request.extend([0xa0, 0xff]);
+
+        let mut dec = RequestDecoder::for_format(WireFormat::Cbor, request.len());
+        let mut buf = BytesMut::new();
+        let (first, second) = request.split_at(request.len() - 2);
+        buf.extend_from_slice(first);
+        assert!(dec.decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(second);
+        assert_is_request(dec.decode(&mut buf).unwrap());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn cbor_request_too_long() {
+        let request = request_cbor();
+        let mut dec = RequestDecoder::for_format(WireFormat::Cbor, request.len() - 1);
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&request);
+        assert!(matches!(
+            dec.decode(&mut buf),
+            Err(RequestStreamError::TooLong(n)) if n == request.len() - 1
+        ));
+
+        // A string that says it's too long is rejected before it arrives.
+        let mut dec = RequestDecoder::for_format(WireFormat::Cbor, 1000);
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&[0x7a, 0x00, 0x10, 0x00, 0x00]);
+        assert!(matches!(
+            dec.decode(&mut buf),
+            Err(RequestStreamError::TooLong(1000))
+        ));
+
+        // So is an array with more items than could fit.
+        let mut dec = RequestDecoder::for_format(WireFormat::Cbor, 1000);
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&[0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        assert!(matches!(
+            dec.decode(&mut buf),
+            Err(RequestStreamError::TooLong(1000))
+        ));
+    }
+
+    #[test]
+    fn cbor_nested_too_deep() {
+        let mut dec = RequestDecoder::for_format(WireFormat::Cbor, DEFAULT_MAX_REQUEST_LEN);
+        let mut buf = BytesMut::new();
+        // An unterminated tower of one-item arrays.
+        buf.extend_from_slice(&[0x81; MAX_CBOR_NESTING + 1]);
+        assert!(matches!(
+            dec.decode(&mut buf),
+            Err(RequestStreamError::Cbor(
+                ciborium::de::Error::RecursionLimitExceeded
+            ))
+        ));
+    }
+
+    #[test]
+    fn cbor_malformed() {
+        let mut dec = RequestDecoder::for_format(WireFormat::Cbor, DEFAULT_MAX_REQUEST_LEN);
+        let mut buf = BytesMut::new();
+        // A "break" outside any indefinite-length item.
+        buf.extend_from_slice(&[0xff]);
+        assert!(matches!(
+            dec.decode(&mut buf),
+            Err(RequestStreamError::Cbor(ciborium::de::Error::Syntax(0)))
+        ));
+    }
+
+    #[async_test]
+    async fn cbor_round_trip() {
+        use futures::stream::StreamExt as _;
+
+        // Two requests, back to back, read through a RequestStream.
+        let mut input = request_cbor();
+        input.extend(request_cbor());
+        let decoder = RequestDecoder::for_format(WireFormat::Cbor, DEFAULT_MAX_REQUEST_LEN);
+        let mut requests = RequestStream::new(&input[..], decoder);
+        assert_is_request(Some(requests.next().await.unwrap().unwrap()));
+        assert_is_request(Some(requests.next().await.unwrap().unwrap()));
+        assert!(requests.next().await.is_none());
+
+        // Responses written through a ResponseEncoder.
+        let response = |id| BoxedResponse {
+            id: Some(RequestId::Int(id)),
+            body: ResponseBody::Success(Box::new(Empty {})),
+        };
+        let mut buf = Vec::new();
+        {
+            let encoder = ResponseEncoder::new(WireFormat::Cbor);
+            let mut sink = asynchronous_codec::FramedWrite::new(&mut buf, encoder);
+            sink.send(response(7)).await.unwrap();
+            sink.send(response(8)).await.unwrap();
+        }
+        let mut decoded: Vec<serde_json::Value> = Vec::new();
+        let mut remaining = &buf[..];
+        while !remaining.is_empty() {
+            decoded.push(ciborium::de::from_reader(&mut remaining).unwrap());
+        }
+        let expected: Vec<serde_json::Value> = [7, 8]
+            .into_iter()
+            .map(|id| serde_json::to_value(response(id)).unwrap())
+            .collect();
+        assert_eq!(decoded, expected);
+    }
 }
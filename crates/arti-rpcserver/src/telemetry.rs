@@ -0,0 +1,102 @@
+//! Optional timing information about RPC requests.
+//!
+//! If a [`ConnectionConfig`](crate::ConnectionConfig) has a [`TelemetrySink`],
+//! we report a [`RequestTiming`] to it whenever a request on that connection
+//! finishes.  Otherwise, we don't measure anything.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tor_rtcompat::SleepProvider;
+
+/// How an RPC request ended.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum RequestOutcome {
+    /// The request succeeded.
+    Success,
+    /// The request failed, or timed out.
+    Error,
+    /// The request was cancelled.
+    Cancelled,
+}
+
+/// Timing information about a single finished RPC request.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct RequestTiming {
+    /// The name of the method that the request invoked.
+    pub method: &'static str,
+    /// When we started handling the request.
+    pub dispatched: Instant,
+    /// When the request finished.
+    pub completed: Instant,
+    /// How the request ended.
+    pub outcome: RequestOutcome,
+}
+
+impl RequestTiming {
+    /// Return how long the request took.
+    pub fn duration(&self) -> Duration {
+        self.completed.saturating_duration_since(self.dispatched)
+    }
+}
+
+/// A callback that receives a [`RequestTiming`] for every finished RPC request.
+///
+/// The callback is invoked from the connection's main loop, so it should be
+/// quick.
+#[derive(Clone)]
+pub struct TelemetrySink(Arc<dyn Fn(&RequestTiming) + Send + Sync>);
+
+impl TelemetrySink {
+    /// Create a new `TelemetrySink` that invokes `f` on every finished request.
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&RequestTiming) + Send + Sync + 'static,
+    {
+        Self(Arc::new(f))
+    }
+}
+
+impl std::fmt::Debug for TelemetrySink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TelemetrySink").finish_non_exhaustive()
+    }
+}
+
+/// A request whose timing we are measuring.
+pub(crate) struct TimedRequest<SP> {
+    /// Where to report the timing.
+    sink: TelemetrySink,
+    /// Used to tell the time.
+    sleep_provider: SP,
+    /// The name of the request's method.
+    method: &'static str,
+    /// When we started handling the request.
+    dispatched: Instant,
+}
+
+impl<SP: SleepProvider> TimedRequest<SP> {
+    /// Start measuring a request for `method`, as of now.
+    pub(crate) fn start(sink: TelemetrySink, sleep_provider: &SP, method: &'static str) -> Self {
+        Self {
+            sink,
+            dispatched: sleep_provider.now(),
+            sleep_provider: sleep_provider.clone(),
+            method,
+        }
+    }
+
+    /// Note that the request has just finished with `outcome`, and report its
+    /// timing.
+    pub(crate) fn finish(self, outcome: RequestOutcome) {
+        let timing = RequestTiming {
+            method: self.method,
+            dispatched: self.dispatched,
+            completed: self.sleep_provider.now(),
+            outcome,
+        };
+        (self.sink.0)(&timing);
+    }
+}
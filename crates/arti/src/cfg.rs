@@ -128,6 +128,19 @@ pub struct ProxyConfig {
     #[builder_setter_attr(deprecated)]
     pub(crate) socks_port: (),
 
+    /// Whether incoming SOCKS connections start with a PROXY protocol header.
+    ///
+    /// Enable this if the SOCKS port is only reachable through a load balancer
+    /// or other proxy that sends a [PROXY protocol] header (version 1 or 2),
+    /// so that we can isolate streams by the real client address.
+    ///
+    /// When this is enabled, connections without a valid header are rejected.
+    /// When it is disabled, connections that start with a header are rejected.
+    ///
+    /// [PROXY protocol]: https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+    #[builder(default)]
+    pub(crate) socks_proxy_protocol: bool,
+
     /// Addresses to listen on for incoming DNS connections.
     #[builder(field(build = r#"resolve_listen_port!(self, dns, 0)"#))]
     pub(crate) dns_listen: Listen,
@@ -178,6 +191,28 @@ pub struct RpcConfig {
     #[cfg(feature = "rpc")]
     #[builder(default = "default_rpc_path()")]
     pub(crate) rpc_listen: Option<CfgPath>,
+
+    /// Location of a cookie file that RPC clients must read in order to
+    /// authenticate.
+    ///
+    /// If this file doesn't exist, we create it with a new random cookie.
+    /// If this is not set, anybody who can connect to `rpc_listen` can use Arti.
+    /// If `rpc_listen` is not set, this is ignored, and no cookie file is created.
+    #[cfg(feature = "rpc")]
+    #[builder(default = "default_rpc_cookie_path()")]
+    pub(crate) rpc_cookie: Option<CfgPath>,
+
+    /// How long an RPC connection may go without sending us a request
+    /// (while none of its requests are in progress) before we close it.
+    ///
+    /// If this is not set, we never close idle connections.
+    #[cfg(feature = "rpc")]
+    #[builder(
+        setter(strip_option),
+        field(type = "Option<std::time::Duration>", build = "self.rpc_idle_timeout")
+    )]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    pub(crate) rpc_idle_timeout: Option<std::time::Duration>,
 }
 
 /// Return the default value for our configuration path.
@@ -192,6 +227,13 @@ fn default_rpc_path() -> Option<CfgPath> {
     Some(CfgPath::new(s.to_string()))
 }
 
+/// Return the default value for our RPC cookie path.
+#[cfg(feature = "rpc")]
+#[allow(clippy::unnecessary_wraps)]
+fn default_rpc_cookie_path() -> Option<CfgPath> {
+    Some(CfgPath::new("~/.local/run/arti/COOKIE".to_string()))
+}
+
 /// Structure to hold Arti's configuration options, whether from a
 /// configuration file or the command line.
 //
@@ -467,6 +509,8 @@ mod test {
                 "logging.time_granularity",
                 "proxy.socks_listen",
                 "proxy.dns_listen",
+                "proxy.socks_proxy_protocol",
+                "storage.permissions.new_dir_mode",
             ],
         );
 
@@ -545,6 +589,8 @@ mod test {
                 // RPC-only settings
                 "rpc",
                 "rpc.rpc_listen",
+                "rpc.rpc_cookie",
+                "rpc.rpc_idle_timeout",
             ],
         );
 
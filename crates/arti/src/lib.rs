@@ -192,6 +192,16 @@ async fn run<R: Runtime>(
         }
     };
 
+    // We only need a cookie if we are going to listen for RPC connections.
+    #[cfg(feature = "rpc")]
+    let rpc_cookie = match (&rpc_path, &arti_config.rpc().rpc_cookie) {
+        (Some(_), Some(path)) => Some(arti_rpcserver::RpcCookie::load_or_create(
+            path.path()?,
+            client_config.fs_mistrust(),
+        )?),
+        _ => None,
+    };
+
     let client_builder = TorClient::with_runtime(runtime.clone())
         .config(client_config)
         .bootstrap_behavior(OnDemand);
@@ -226,6 +236,11 @@ async fn run<R: Runtime>(
                 &runtime,
                 listen_path,
                 client.clone(),
+                rpc_cookie,
+                arti_rpcserver::ConnectionConfig {
+                    idle_timeout: arti_config.rpc().rpc_idle_timeout,
+                    ..Default::default()
+                },
             )?)
         } else {
             None
@@ -236,11 +251,13 @@ async fn run<R: Runtime>(
     if !socks_listen.is_empty() {
         let runtime = runtime.clone();
         let client = client.isolated_client();
+        let socks_proxy_protocol = arti_config.proxy().socks_proxy_protocol;
         proxy.push(Box::pin(async move {
             let res = socks::run_socks_proxy(
                 runtime,
                 client,
                 socks_listen,
+                socks_proxy_protocol,
                 #[cfg(all(feature = "rpc", feature = "tokio"))]
                 rpc_mgr,
             )
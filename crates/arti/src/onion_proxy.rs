@@ -92,10 +92,8 @@ define_list_builder_helper! {
 fn build_list(
     services: Vec<OnionServiceProxyConfig>,
 ) -> Result<OnionServiceProxyConfigMap, ConfigBuildError> {
-    // TODO: Add a test for this function.
-    //
-    // It *is* reachable from OnionServiceProxyConfigMapBuilder::build(), since
-    // that builder's API uses push() to add OnionServiceProxyConfigBuilders to
+    // Duplicate nicknames *are* reachable from OnionServiceProxyConfigMapBuilder::build(),
+    // since that builder's API uses push() to add OnionServiceProxyConfigBuilders to
     // an internal _list_.  Alternatively, we might want to have a distinct
     // MapBuilder type.
 
@@ -328,3 +326,111 @@ impl<R: Runtime> crate::reload_cfg::ReconfigurableModule for ProxySet<R> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use tor_hsrproxy::config::{Encapsulation, ProxyAction, ProxyPattern, ProxyRule, TargetAddr};
+
+    /// Two onion services, the second of which overrides one of the defaults.
+    const TWO_SERVICES: &str = r#"
+[allium-cepa]
+proxy_ports = [["80", "127.0.0.1:10080"]]
+
+[allium-ursinum]
+num_intro_points = 5
+proxy_ports = [["443", "127.0.0.1:10443"]]
+"#;
+
+    /// Build the configuration of a service called `nickname`,
+    /// which forwards `port` to `target`,
+    /// with its service configuration customized by `customize`.
+    fn expected_config(
+        nickname: &str,
+        port: u16,
+        target: &str,
+        customize: impl FnOnce(&mut OnionServiceConfigBuilder),
+    ) -> OnionServiceProxyConfig {
+        let mut b = OnionServiceProxyConfigBuilder::default();
+        b.service().nickname(nickname.parse().unwrap());
+        customize(b.service());
+        b.proxy().proxy_ports().push(ProxyRule::new(
+            ProxyPattern::one_port(port).unwrap(),
+            ProxyAction::Forward(
+                Encapsulation::Simple,
+                TargetAddr::Inet(target.parse().unwrap()),
+            ),
+        ));
+        b.build().unwrap()
+    }
+
+    #[test]
+    fn multiple_services() {
+        let builder: OnionServiceProxyConfigMapBuilder = toml::from_str(TWO_SERVICES).unwrap();
+        let services = builder.build().unwrap();
+
+        // Each service is named after its key.
+        let nicknames = services.keys().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(nicknames, ["allium-cepa", "allium-ursinum"]);
+        for (nickname, svc) in &services {
+            assert_eq!(svc.svc_cfg.nickname(), nickname);
+        }
+
+        // Each service gets the defaults for whatever it doesn't set itself.
+        let cepa: HsNickname = "allium-cepa".parse().unwrap();
+        let ursinum: HsNickname = "allium-ursinum".parse().unwrap();
+        assert_eq!(
+            services[&cepa],
+            expected_config("allium-cepa", 80, "127.0.0.1:10080", |_| {})
+        );
+        assert_eq!(
+            services[&ursinum],
+            expected_config("allium-ursinum", 443, "127.0.0.1:10443", |b| {
+                b.num_intro_points(5);
+            })
+        );
+
+        // The builder survives a round trip through serde.
+        let serialized = toml::to_string(&builder).unwrap();
+        let reparsed: OnionServiceProxyConfigMapBuilder = toml::from_str(&serialized).unwrap();
+        assert_eq!(reparsed.build().unwrap(), services);
+    }
+
+    #[test]
+    fn mismatched_nickname() {
+        let config = r#"
+[allium-cepa]
+nickname = "allium-ursinum"
+proxy_ports = [["80", "127.0.0.1:10080"]]
+"#;
+        assert!(toml::from_str::<OnionServiceProxyConfigMapBuilder>(config).is_err());
+    }
+
+    #[test]
+    fn duplicate_nicknames() {
+        let mut builder = OnionServiceProxyConfigMapBuilder::default();
+        for _ in 0..2 {
+            let mut svc = OnionServiceProxyConfigBuilder::default();
+            svc.service().nickname("allium-cepa".parse().unwrap());
+            builder.access().push(svc);
+        }
+
+        let err = builder.build().unwrap_err();
+        assert!(
+            matches!(err, ConfigBuildError::Inconsistent { .. }),
+            "unexpected error {err:?}"
+        );
+    }
+}
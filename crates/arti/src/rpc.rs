@@ -1,8 +1,8 @@
 //! Experimental RPC support.
 
 use anyhow::Result;
-use arti_rpcserver::{RpcMgr, RpcSession};
-use futures::task::SpawnExt;
+use arti_rpcserver::{ConnectionConfig, RpcAuthentication, RpcCookie, RpcMgr, RpcSession};
+use futures::{task::SpawnExt, FutureExt as _};
 use std::{path::Path, sync::Arc};
 
 use arti_client::TorClient;
@@ -25,10 +25,15 @@ cfg_if::cfg_if! {
 
 /// Run an RPC listener task to accept incoming connections at the Unix
 /// socket address of `path`.
+///
+/// If `cookie` is present, clients must present it to get an RPC session.
+/// Each connection is run with `config`.
 pub(crate) fn launch_rpc_listener<R: Runtime>(
     runtime: &R,
     path: impl AsRef<Path>,
     client: TorClient<R>,
+    cookie: Option<RpcCookie>,
+    config: ConnectionConfig,
 ) -> Result<Arc<RpcMgr>> {
     // TODO RPC: there should be an error return instead.
 
@@ -36,8 +41,13 @@ pub(crate) fn launch_rpc_listener<R: Runtime>(
     // But I certainly don't want to make breaking changes there if we can help
     // it.
     let listener = UnixListener::bind(path)?;
-    let rpc_mgr =
-        RpcMgr::new(move |_auth| RpcSession::new_with_client(Arc::new(client.isolated_client())));
+    let make_session = move |_auth: &RpcAuthentication| {
+        RpcSession::new_with_client(Arc::new(client.isolated_client()))
+    };
+    let rpc_mgr = match cookie {
+        Some(cookie) => RpcMgr::new_with_cookie(cookie, make_session),
+        None => RpcMgr::new(make_session),
+    };
     let rt_clone = runtime.clone();
     let rpc_mgr_clone = rpc_mgr.clone();
 
@@ -45,7 +55,7 @@ pub(crate) fn launch_rpc_listener<R: Runtime>(
     // succeeded or not. This is something we should fix when we refactor
     // our service-launching code.
     runtime.spawn(async {
-        let result = run_rpc_listener(rt_clone, listener, rpc_mgr_clone).await;
+        let result = run_rpc_listener(rt_clone, listener, rpc_mgr_clone, config).await;
         if let Err(e) = result {
             tracing::warn!("RPC manager quit with an error: {}", e);
         }
@@ -53,14 +63,23 @@ pub(crate) fn launch_rpc_listener<R: Runtime>(
     Ok(rpc_mgr)
 }
 
-/// Backend function to implement an RPC listener: runs in a loop.
+/// Backend function to implement an RPC listener: runs in a loop, until
+/// `rpc_mgr` is shut down.
 async fn run_rpc_listener<R: Runtime>(
     runtime: R,
     listener: UnixListener,
     rpc_mgr: Arc<RpcMgr>,
+    config: ConnectionConfig,
 ) -> Result<()> {
+    let mut shutdown_requested = rpc_mgr.shutdown_requested().boxed().fuse();
     loop {
-        let (stream, _addr) = listener.accept().await?;
+        let (stream, _addr) = futures::select! {
+            r = listener.accept().fuse() => r?,
+            () = shutdown_requested => {
+                // The connections we've already accepted will shut themselves down.
+                return Ok(());
+            }
+        };
         // TODO RPC: Perhaps we should have rpcmgr hold the client reference?
         let connection = rpc_mgr.new_connection();
         let (input, output) = stream.into_split();
@@ -68,8 +87,10 @@ async fn run_rpc_listener<R: Runtime>(
         #[cfg(feature = "tokio")]
         let (input, output) = (input.compat(), output.compat_write());
 
-        runtime.spawn(async {
-            let result = connection.run(input, output).await;
+        let config = config.clone();
+        let sleep_provider = runtime.clone();
+        runtime.spawn(async move {
+            let result = connection.run(input, output, sleep_provider, config).await;
             if let Err(e) = result {
                 tracing::warn!("RPC session ended with an error: {}", e);
             }
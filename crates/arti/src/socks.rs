@@ -20,7 +20,7 @@ use tor_error::warn_report;
 #[cfg(feature = "rpc")]
 use tor_rpcbase as rpc;
 use tor_rtcompat::{Runtime, TcpListener};
-use tor_socksproto::{SocksAddr, SocksAuth, SocksCmd, SocksRequest};
+use tor_socksproto::{ProxyProtocolHeader, SocksAddr, SocksAuth, SocksCmd, SocksRequest};
 
 use anyhow::{anyhow, Context, Result};
 
@@ -136,7 +136,7 @@ fn interpret_socks_auth(auth: &SocksAuth) -> Result<AuthInterpretation> {
     use AuthInterpretation::*;
     #[allow(unused_variables)] // TODO RPC remove
     match auth {
-        SocksAuth::Username(user, pass) if user == RPC_SESSION_CONST => {
+        SocksAuth::Username(user, pass) if user.as_slice() == RPC_SESSION_CONST => {
             cfg_if::cfg_if! {
                 if #[cfg(feature="rpc")] {
                     let pass =
@@ -231,16 +231,56 @@ impl<R: Runtime> SocksConnContext<R> {
 /// Uses `isolation_info` to decide which circuits this connection
 /// may use.  Requires that `isolation_info` is a pair listing the listener
 /// id and the source address for the socks request.
+///
+/// If `expect_proxy_protocol` is true, the connection must start with a
+/// PROXY protocol header, and the client address it reports replaces the
+/// source address in `isolation_info`.
 async fn handle_socks_conn<R, S>(
     runtime: R,
     context: SocksConnContext<R>,
     socks_stream: S,
-    isolation_info: ConnIsolation,
+    mut isolation_info: ConnIsolation,
+    expect_proxy_protocol: bool,
 ) -> Result<()>
 where
     R: Runtime,
     S: AsyncRead + AsyncWrite + Send + Sync + Unpin + 'static,
 {
+    let (mut socks_r, mut socks_w) = socks_stream.split();
+    let mut inbuf = [0_u8; 1024];
+    let mut n_read = 0;
+
+    // Part 0: If we're behind a proxy that uses the PROXY protocol,
+    // find out where the connection really came from.
+    if expect_proxy_protocol {
+        let header = loop {
+            if n_read == inbuf.len() {
+                return Err(anyhow!("PROXY protocol header did not fit in 1KiB buffer"));
+            }
+            let n = socks_r
+                .read(&mut inbuf[n_read..])
+                .await
+                .context("Error while reading PROXY protocol header")?;
+            if n == 0 {
+                return Err(anyhow!("Connection closed during PROXY protocol header"));
+            }
+            n_read += n;
+
+            match ProxyProtocolHeader::parse(&inbuf[..n_read]) {
+                Err(_) => continue, // Header truncated.
+                Ok(Err(e)) => return Err(e).context("Expected a PROXY protocol header"),
+                Ok(Ok(header)) => break header,
+            }
+        };
+
+        inbuf.copy_within(header.drain()..n_read, 0);
+        n_read -= header.drain();
+        if let Some(source) = header.source() {
+            debug!("PROXY protocol: connection is from {}", sensitive(&source));
+            isolation_info.1 = source.ip();
+        }
+    }
+
     // Part 1: Perform the SOCKS handshake, to learn where we are
     // being asked to connect, and what we're being asked to do once
     // we connect there.
@@ -250,30 +290,53 @@ where
     // loop.
     let mut handshake = tor_socksproto::SocksProxyHandshake::new();
 
-    let (mut socks_r, mut socks_w) = socks_stream.split();
-    let mut inbuf = [0_u8; 1024];
-    let mut n_read = 0;
+    // We may already have some of the handshake, left over from the PROXY
+    // protocol header, so we only read when the handshake tells us it needs more.
+    let mut need_read = false;
     let request = loop {
-        if n_read == inbuf.len() {
-            // We would like to read more of this SOCKS request, but there is no
-            // more space in the buffer.  If we try to keep reading into an
-            // empty buffer, we'll just read nothing, try to parse it, and learn
-            // that we still wish we had more to read.
-            //
-            // In theory we might want to resize the buffer.  Right now, though,
-            // we just reject handshakes that don't fit into 1k.
-            return Err(anyhow!("Socks handshake did not fit in 1KiB buffer"));
+        let mut at_eof = false;
+        if need_read {
+            if n_read == inbuf.len() {
+                // We would like to read more of this SOCKS request, but there is no
+                // more space in the buffer.  If we try to keep reading into an
+                // empty buffer, we'll just read nothing, try to parse it, and learn
+                // that we still wish we had more to read.
+                //
+                // In theory we might want to resize the buffer.  Right now, though,
+                // we just reject handshakes that don't fit into 1k.
+                return Err(anyhow!("Socks handshake did not fit in 1KiB buffer"));
+            }
+            // Read some more stuff.
+            let n = socks_r
+                .read(&mut inbuf[n_read..])
+                .await
+                .context("Error while reading SOCKS handshake")?;
+            n_read += n;
+            at_eof = n == 0;
+            need_read = false;
         }
-        // Read some more stuff.
-        n_read += socks_r
-            .read(&mut inbuf[n_read..])
-            .await
-            .context("Error while reading SOCKS handshake")?;
 
         // try to advance the handshake to the next state.
-        let action = match handshake.handshake(&inbuf[..n_read]) {
-            Err(_) => continue, // Message truncated.
+        let action = if at_eof {
+            // The client hung up: there's no point waiting for the rest of the handshake.
+            Ok(handshake.handshake_at_eof(&inbuf[..n_read]))
+        } else {
+            handshake.handshake(&inbuf[..n_read])
+        };
+        let action = match action {
+            Err(_) => {
+                // Message truncated.
+                need_read = true;
+                continue;
+            }
             Ok(Err(e)) => {
+                if tor_socksproto::looks_like_proxy_protocol(&inbuf[..n_read]) {
+                    // Don't trust client addresses from anyone who hasn't been configured
+                    // to provide them.
+                    return Err(anyhow!(
+                        "Unexpected PROXY protocol header on SOCKS port (see proxy.socks_proxy_protocol)"
+                    ));
+                }
                 if let tor_socksproto::Error::BadProtocol(version) = e {
                     // check for HTTP methods: CONNECT, DELETE, GET, HEAD, OPTION, PUT, POST, PATCH and
                     // TRACE.
@@ -292,13 +355,16 @@ where
 
         // reply if needed.
         if action.drain > 0 {
-            inbuf.copy_within(action.drain..action.drain + n_read, 0);
+            inbuf.copy_within(action.drain..n_read, 0);
             n_read -= action.drain;
         }
         if !action.reply.is_empty() {
             write_all_and_flush(&mut socks_w, &action.reply).await?;
         }
         if action.finished {
+            if let Some(e) = handshake.failure() {
+                return Err(e.clone().into());
+            }
             break handshake.into_request();
         }
     };
@@ -343,7 +409,13 @@ where
                 .context("Encoding socks reply")?;
             write_all_and_flush(&mut socks_w, &reply[..]).await?;
 
-            let (tor_r, tor_w) = tor_stream.split();
+            let (tor_r, mut tor_w) = tor_stream.split();
+
+            // The client may have sent some data along with the end of its
+            // handshake: that data belongs to the stream.
+            if n_read > 0 {
+                write_all_and_flush(&mut tor_w, &inbuf[..n_read]).await?;
+            }
 
             // Finally, spawn two background tasks to relay traffic between
             // the socks stream and the tor stream.
@@ -596,11 +668,15 @@ fn accept_err_is_fatal(err: &IoError) -> bool {
 /// Requires a `runtime` to use for launching tasks and handling
 /// timeouts, and a `tor_client` to use in connecting over the Tor
 /// network.
+///
+/// If `expect_proxy_protocol` is true, every connection must start with a
+/// PROXY protocol header, which tells us the real address of the client.
 #[cfg_attr(feature = "experimental-api", visibility::make(pub))]
 pub(crate) async fn run_socks_proxy<R: Runtime>(
     runtime: R,
     tor_client: TorClient<R>,
     listen: Listen,
+    expect_proxy_protocol: bool,
     // TODO RPC: This is not a good way to make an API conditional. We MUST
     // refactor this before the RPC feature becomes non-experimental.
     #[cfg(feature = "rpc")] rpc_mgr: Option<Arc<arti_rpcserver::RpcMgr>>,
@@ -670,8 +746,14 @@ pub(crate) async fn run_socks_proxy<R: Runtime>(
         };
         let runtime_copy = runtime.clone();
         runtime.spawn(async move {
-            let res =
-                handle_socks_conn(runtime_copy, socks_context, stream, (sock_id, addr.ip())).await;
+            let res = handle_socks_conn(
+                runtime_copy,
+                socks_context,
+                stream,
+                (sock_id, addr.ip()),
+                expect_proxy_protocol,
+            )
+            .await;
             if let Err(e) = res {
                 // TODO: warn_report doesn't work on anyhow::Error.
                 warn!("connection exited with error: {}", tor_error::Report(e));
@@ -131,6 +131,11 @@ pub enum Error {
     #[error("Configured with nonexistent user: {0}")]
     NoSuchUser(String),
 
+    /// A configured mode for new directories would have given access to
+    /// untrusted users, or would not have given full access to the owner.
+    #[error("Configured with unacceptable mode for new directories: {0:o}")]
+    BadNewDirMode(u32),
+
     /// Error accessing passwd/group databases or obtaining our uids/gids
     #[error("Error accessing passwd/group databases or obtaining our uids/gids")]
     PasswdGroupIoError(#[source] Arc<IoError>),
@@ -179,6 +184,7 @@ impl Error {
                 Error::MissingField(_) => return None,
                 Error::NoSuchGroup(_) => return None,
                 Error::NoSuchUser(_) => return None,
+                Error::BadNewDirMode(_) => return None,
                 Error::PasswdGroupIoError(_) => return None,
             }
             .as_path(),
@@ -205,6 +211,7 @@ impl Error {
             | Error::MissingField(_)
             | Error::NoSuchGroup(_)
             | Error::NoSuchUser(_)
+            | Error::BadNewDirMode(_)
             | Error::PasswdGroupIoError(_) => false,
 
             Error::Multiple(errs) => errs.iter().any(|e| e.is_bad_permission()),
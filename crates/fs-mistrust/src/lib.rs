@@ -75,6 +75,7 @@ pub mod walk;
 use serde::{Deserialize, Serialize};
 use std::{
     fs::DirBuilder,
+    io,
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -168,6 +169,32 @@ pub struct Mistrust {
         field(type = "TrustedGroup", build = "self.trust_group.get_gid()?")
     )]
     trust_group: Option<u32>,
+
+    /// What mode should we give to directories that we create? (Unix only.)
+    ///
+    /// Defaults to `0o700`.
+    #[builder(
+        setter(custom),
+        field(type = "Option<u32>", build = "check_new_dir_mode(self.new_dir_mode)?")
+    )]
+    new_dir_mode: u32,
+}
+
+/// The mode we give to new directories by default.
+const DEFAULT_NEW_DIR_MODE: u32 = 0o700;
+
+/// Check that a configured mode for new directories is acceptable, and return it,
+/// or return the default mode if none was configured.
+///
+/// The mode must give full access to the owner, and no access to other users.
+fn check_new_dir_mode(mode: Option<u32>) -> Result<u32> {
+    match mode {
+        None => Ok(DEFAULT_NEW_DIR_MODE),
+        Some(mode) if mode & !0o777 != 0 || mode & 0o700 != 0o700 || mode & 0o007 != 0 => {
+            Err(Error::BadNewDirMode(mode))
+        }
+        Some(mode) => Ok(mode),
+    }
 }
 
 /// Compute the canonical prefix for a given path prefix.
@@ -222,6 +249,23 @@ impl MistrustBuilder {
         self
     }
 
+    /// Configure the mode with which this `Mistrust` creates new directories.
+    ///
+    /// By default, new directories are created with mode `0o700`.  Setting a
+    /// mode such as `0o750` lets members of a trusted group read the
+    /// directories we create.  (Note that such a group must also be trusted
+    /// using [`MistrustBuilder::trust_group`] for the new directory to pass
+    /// our checks.)
+    ///
+    /// The mode must give the owner full access, and must not give other
+    /// users any access: otherwise, [`MistrustBuilder::build`] will fail.
+    ///
+    /// This has no effect on platforms other than Unix.
+    pub fn new_dir_mode(&mut self, mode: u32) -> &mut Self {
+        self.new_dir_mode = Some(mode);
+        self
+    }
+
     /// Configure this `Mistrust` to trust every user and every group.
     ///
     /// With this option set, every file and directory is treated as having
@@ -541,6 +585,10 @@ impl<'a> Verifier<'a> {
     ///  * there was a problem when creating the directory
     ///  * after creating the directory, we found that it had a permissions or
     ///    ownership problem.
+    ///
+    /// Every directory that this creates (including any missing ancestors of
+    /// `path`) is given the mode configured with
+    /// [`MistrustBuilder::new_dir_mode`].
     pub fn make_directory<P: AsRef<Path>>(mut self, path: P) -> Result<()> {
         self.enforce_type = Type::Dir;
 
@@ -552,15 +600,41 @@ impl<'a> Verifier<'a> {
         }
 
         // Looks like we got a "not found", so we're creating the path.
+        //
+        // We create each missing directory ourselves, rather than using
+        // `DirBuilder::recursive`, since that would give the missing ancestors
+        // a mode based on the umask instead of the one we were configured with.
         let mut bld = DirBuilder::new();
         #[cfg(target_family = "unix")]
         {
             use std::os::unix::fs::DirBuilderExt;
-            bld.mode(0o700);
+            bld.mode(self.mistrust.new_dir_mode);
+        }
+        let missing: Vec<&Path> = path
+            .ancestors()
+            .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+            .collect();
+        for dir in missing.into_iter().rev() {
+            match bld.create(dir) {
+                Ok(()) => {}
+                // Somebody else created it first; the check below will tell us
+                // whether we can use it.
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(Error::CreatingDir(Arc::new(e))),
+            }
+
+            // The mode we gave to DirBuilder was masked by the umask, which
+            // might have removed bits that we were configured to set.
+            #[cfg(target_family = "unix")]
+            if self.mistrust.new_dir_mode != DEFAULT_NEW_DIR_MODE {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(
+                    dir,
+                    std::fs::Permissions::from_mode(self.mistrust.new_dir_mode),
+                )
+                .map_err(|e| Error::CreatingDir(Arc::new(e)))?;
+            }
         }
-        bld.recursive(true)
-            .create(path)
-            .map_err(|e| Error::CreatingDir(Arc::new(e)))?;
 
         // We built the path!  But for paranoia's sake, check it again.
         self.check(path)
@@ -862,6 +936,70 @@ mod test {
         m.make_directory(d.path("a/b/c/d")).unwrap();
     }
 
+    #[cfg(all(
+        target_family = "unix",
+        not(target_os = "ios"),
+        not(target_os = "android")
+    ))]
+    #[test]
+    fn make_directory_with_mode() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let d = Dir::new();
+        d.dir("a");
+        d.chmod("a", 0o700);
+        let gid = std::fs::metadata(d.path("a")).unwrap().gid();
+
+        // A group-readable directory is fine, if we trust the group.
+        let m = Mistrust::builder()
+            .ignore_prefix(d.canonical_root())
+            .trust_group(gid)
+            .new_dir_mode(0o750)
+            .build()
+            .unwrap();
+        m.make_directory(d.path("a/b")).unwrap();
+        let mode = std::fs::metadata(d.path("a/b"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o750);
+        m.check_directory(d.path("a/b")).unwrap();
+
+        // The mode applies to missing ancestors too, not just the last
+        // directory.  (We use a group-writable mode here, since a typical umask
+        // would remove that bit.)
+        let m = Mistrust::builder()
+            .ignore_prefix(d.canonical_root())
+            .trust_group(gid)
+            .new_dir_mode(0o770)
+            .build()
+            .unwrap();
+        m.make_directory(d.path("a/d/e/f")).unwrap();
+        for dir in ["a/d", "a/d/e", "a/d/e/f"] {
+            let mode = std::fs::metadata(d.path(dir)).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o770, "{}", dir);
+        }
+
+        // The default is still 0o700.
+        let m = mistrust_build(&[MistrustOp::IgnorePrefix(d.canonical_root())]);
+        m.make_directory(d.path("a/c")).unwrap();
+        let mode = std::fs::metadata(d.path("a/c"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        // Modes that give access to other users (or that lock out the owner)
+        // are rejected.
+        for bad_mode in [0o755, 0o701, 0o500, 0o1700] {
+            let e = Mistrust::builder()
+                .new_dir_mode(bad_mode)
+                .build()
+                .unwrap_err();
+            assert!(matches!(e, Error::BadNewDirMode(m) if m == bad_mode));
+        }
+    }
+
     #[cfg(target_family = "unix")]
     #[test]
     fn check_contents() {
@@ -83,6 +83,16 @@ impl DosParams {
             burst_per_sec: normalize(burst_per_sec)?,
         })
     }
+
+    /// Return the rate per second of INTRODUCE2 cells to relay to the service, if set.
+    pub fn rate_per_sec(&self) -> Option<i32> {
+        self.rate_per_sec.map(|v| v.get())
+    }
+
+    /// Return the burst per second of INTRODUCE2 cells to relay to the service, if set.
+    pub fn burst_per_sec(&self) -> Option<i32> {
+        self.burst_per_sec.map(|v| v.get())
+    }
 }
 
 impl Ext for DosParams {
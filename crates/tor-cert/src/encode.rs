@@ -23,6 +23,19 @@ use derive_more::{AsRef, Deref, Into};
 #[cfg_attr(docsrs, doc(cfg(feature = "encode")))]
 pub struct EncodedEd25519Cert(Vec<u8>);
 
+impl EncodedEd25519Cert {
+    /// Create an `EncodedEd25519Cert` from a byte slice.
+    ///
+    /// **Important**: generally you should not use this function.
+    /// Instead, prefer using [`Ed25519CertConstructor`] to create certificates.
+    ///
+    /// This function should only be used if `cert` is known to be the byte representation of a
+    /// valid `EncodedEd25519Cert` (for example, one that was previously encoded and saved).
+    pub fn dangerously_from_bytes(cert: &[u8]) -> Self {
+        Self(cert.into())
+    }
+}
+
 impl Ed25519Cert {
     /// Return a new `Ed25519CertConstructor` to create and return a new signed
     /// `Ed25519Cert`.
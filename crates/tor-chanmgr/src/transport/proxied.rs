@@ -422,14 +422,20 @@ pub fn settings_to_protocol(vers: SocksVersion, s: String) -> Result<Protocol, P
                 ),
             ));
         } else {
-            Protocol::Socks(SocksVersion::V4, SocksAuth::Socks4(bytes))
+            Protocol::Socks(SocksVersion::V4, SocksAuth::Socks4(bytes.into()))
         }
     } else if bytes.len() <= 255 {
         // The [0] here is mandatory according to the pt-spec.
-        Protocol::Socks(SocksVersion::V5, SocksAuth::Username(bytes, vec![0]))
+        Protocol::Socks(
+            SocksVersion::V5,
+            SocksAuth::Username(bytes.into(), vec![0].into()),
+        )
     } else if bytes.len() <= (255 * 2) {
         let password = bytes.split_off(255);
-        Protocol::Socks(SocksVersion::V5, SocksAuth::Username(bytes, password))
+        Protocol::Socks(
+            SocksVersion::V5,
+            SocksAuth::Username(bytes.into(), password.into()),
+        )
     } else {
         return Err(ProxyError::InvalidSocksRequest(
             tor_socksproto::Error::NotImplemented("PT settings list too long for SOCKS 5".into()),
@@ -500,39 +506,51 @@ mod test {
         assert_eq!(s(0, 0), Protocol::Socks(V5, SocksAuth::NoAuth));
         assert_eq!(
             s(0, 50),
-            Protocol::Socks(V5, SocksAuth::Username(v(0, 50), vec![0]))
+            Protocol::Socks(V5, SocksAuth::Username(v(0, 50).into(), vec![0].into()))
         );
         assert_eq!(
             s(0, 255),
-            Protocol::Socks(V5, SocksAuth::Username(v(0, 255), vec![0]))
+            Protocol::Socks(V5, SocksAuth::Username(v(0, 255).into(), vec![0].into()))
         );
         assert_eq!(
             s(0, 256),
-            Protocol::Socks(V5, SocksAuth::Username(v(0, 255), v(255, 256)))
+            Protocol::Socks(
+                V5,
+                SocksAuth::Username(v(0, 255).into(), v(255, 256).into())
+            )
         );
         assert_eq!(
             s(0, 300),
-            Protocol::Socks(V5, SocksAuth::Username(v(0, 255), v(255, 300)))
+            Protocol::Socks(
+                V5,
+                SocksAuth::Username(v(0, 255).into(), v(255, 300).into())
+            )
         );
         assert_eq!(
             s(0, 510),
-            Protocol::Socks(V5, SocksAuth::Username(v(0, 255), v(255, 510)))
+            Protocol::Socks(
+                V5,
+                SocksAuth::Username(v(0, 255).into(), v(255, 510).into())
+            )
         );
 
         // This one needs to use socks4, or it won't fit. :P
         assert_eq!(
             sv(V4, 0, 511),
-            Protocol::Socks(V4, SocksAuth::Socks4(v(0, 511)))
+            Protocol::Socks(V4, SocksAuth::Socks4(v(0, 511).into()))
         );
 
         // Small requests with "0" bytes work fine...
         assert_eq!(
             settings_to_protocol(V5, "\0".to_owned()).unwrap(),
-            Protocol::Socks(V5, SocksAuth::Username(vec![0], vec![0]))
+            Protocol::Socks(V5, SocksAuth::Username(vec![0].into(), vec![0].into()))
         );
         assert_eq!(
             settings_to_protocol(V5, "\0".to_owned().repeat(510)).unwrap(),
-            Protocol::Socks(V5, SocksAuth::Username(vec![0; 255], vec![0; 255]))
+            Protocol::Socks(
+                V5,
+                SocksAuth::Username(vec![0; 255].into(), vec![0; 255].into())
+            )
         );
 
         // Huge requests with "0" simply can't be encoded.
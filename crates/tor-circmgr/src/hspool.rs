@@ -21,6 +21,8 @@ use tor_rtcompat::{
     Runtime, SleepProviderExt,
 };
 use tracing::warn;
+#[cfg(feature = "hs-service")]
+use {crate::path::TorPath, tor_chanmgr::ChannelUsage};
 
 /// The (onion-service-related) purpose for which a given circuit is going to be
 /// used.
@@ -220,6 +222,42 @@ impl<R: Runtime> HsCircPool<R> {
         Ok(circ)
     }
 
+    /// Launch a one-hop circuit, suitable for use for `kind`, directly to `target`.
+    ///
+    /// Unlike [`get_or_launch_specific`](Self::get_or_launch_specific),
+    /// the resulting circuit does nothing to hide our location from `target`:
+    /// it is only appropriate for non-anonymous ("single onion") services.
+    ///
+    /// Only makes a single attempt; the caller needs to loop if they want to retry.
+    #[cfg(feature = "hs-service")]
+    pub async fn launch_specific_one_hop<T>(
+        &self,
+        netdir: &NetDir,
+        kind: HsCircKind,
+        target: T,
+    ) -> Result<Arc<ClientCirc>>
+    where
+        T: CircTarget,
+    {
+        match kind {
+            HsCircKind::SvcHsDir | HsCircKind::SvcIntro | HsCircKind::SvcRend => {}
+            HsCircKind::ClientHsDir | HsCircKind::ClientIntro | HsCircKind::ClientRend => {
+                return Err(bad_api_usage!(
+                    "launch_specific_one_hop with client circuit kind {:?}!?",
+                    kind
+                )
+                .into())
+            }
+        }
+
+        let path = TorPath::new_one_hop_circ_target_owned(&target);
+        let params = crate::DirInfo::from(netdir).circ_params();
+        self.circmgr
+            .builder()
+            .build(&path, &params, ChannelUsage::UserTraffic)
+            .await
+    }
+
     /// Take and return a circuit from our pool suitable for being extended to `avoid_target`.
     ///
     /// If there is no such circuit, build and return a new one.
@@ -125,6 +125,13 @@ impl<'a> TorPath<'a> {
         }
     }
 
+    /// Construct a new one-hop path to an arbitrarily chosen circuit target,
+    /// using a normal (non-CREATE_FAST) Tor handshake.
+    #[cfg(feature = "hs-service")]
+    pub(crate) fn new_one_hop_circ_target_owned<T: tor_linkspec::CircTarget>(target: &T) -> Self {
+        Self::new_multihop_from_maybe_owned(vec![OwnedCircTarget::from_circ_target(target).into()])
+    }
+
     /// Create a new multi-hop path with a given number of ordered relays.
     pub fn new_multihop(relays: impl IntoIterator<Item = Relay<'a>>) -> Self {
         Self {
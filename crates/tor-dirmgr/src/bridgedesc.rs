@@ -33,11 +33,14 @@ use tor_netdoc::doc::routerdesc::RouterDesc;
 use tor_rtcompat::Runtime;
 
 use crate::event::FlagPublisher;
-use crate::storage::CachedBridgeDescriptor;
-use crate::{DirMgrStore, DynStore};
+use crate::DirMgrStore;
 
 #[cfg(test)]
 mod bdtest;
+mod store;
+
+pub use crate::storage::CachedBridgeDescriptor;
+pub use store::{BridgeDescStore, FileBridgeDescStore, MemoryBridgeDescStore};
 
 /// The key we use in all our data structures
 ///
@@ -103,8 +106,9 @@ where
 
 /// Configuration for the `BridgeDescMgr`
 ///
-/// Currently, the only way to make this is via its `Default` impl.
-// TODO: there should be some way to override the defaults.  See #629 for considerations.
+/// Currently, the only way to make this is via its `Default` impl,
+/// and only [`prune_interval`](BridgeDescDownloadConfig::set_prune_interval) can be changed.
+// TODO: there should be some way to override the other defaults.  See #629 for considerations.
 #[derive(Debug, Clone)]
 pub struct BridgeDescDownloadConfig {
     /// How many bridge descriptor downloads to attempt in parallel?
@@ -136,6 +140,20 @@ pub struct BridgeDescDownloadConfig {
     // TODO: When this is configurable, we need to make sure we reject
     // configurations with max_refresh < min_refresh, or we may panic.
     max_refetch: Duration,
+
+    /// How often to remove expired descriptors, for bridges we no longer use, from the store
+    ///
+    /// See [`BridgeDescMgr::prune_store`].
+    prune_interval: Duration,
+}
+
+impl BridgeDescDownloadConfig {
+    /// Set how often to remove expired descriptors, for bridges we no longer use, from the store
+    ///
+    /// The default is once a day.
+    pub fn set_prune_interval(&mut self, prune_interval: Duration) {
+        self.prune_interval = prune_interval;
+    }
 }
 
 impl Default for BridgeDescDownloadConfig {
@@ -147,6 +165,7 @@ impl Default for BridgeDescDownloadConfig {
             prefetch: secs(1000),
             min_refetch: secs(3600),
             max_refetch: secs(3600 * 3), // matches C Tor behaviour
+            prune_interval: secs(86400),
         }
     }
 }
@@ -239,7 +258,7 @@ struct Manager<R: Runtime, M: Mockable<R>> {
     circmgr: M::CircMgr,
 
     /// Persistent state store
-    store: Arc<Mutex<DynStore>>,
+    store: Mutex<Box<dyn BridgeDescStore>>,
 
     /// Mock for testing, usually `()`
     mockable: M,
@@ -507,7 +526,22 @@ impl<R: Runtime> BridgeDescMgr<R> {
         circmgr: Arc<tor_circmgr::CircMgr<R>>,
         dormancy: Dormancy,
     ) -> Result<Self, StartupError> {
-        Self::new_internal(runtime, circmgr, store.store, config, dormancy, ())
+        Self::new_with_store(config, runtime, store.store, circmgr, dormancy)
+    }
+
+    /// Create a new `BridgeDescMgr` which caches descriptors in `store`
+    ///
+    /// Use this instead of [`new`](BridgeDescMgr::new)
+    /// to keep the cache somewhere other than the directory manager's database,
+    /// for example in a [`MemoryBridgeDescStore`] or a [`FileBridgeDescStore`].
+    pub fn new_with_store(
+        config: &BridgeDescDownloadConfig,
+        runtime: R,
+        store: impl BridgeDescStore,
+        circmgr: Arc<tor_circmgr::CircMgr<R>>,
+        dormancy: Dormancy,
+    ) -> Result<Self, StartupError> {
+        Self::new_internal(runtime, circmgr, Box::new(store), config, dormancy, ())
     }
 }
 
@@ -534,7 +568,7 @@ impl<R: Runtime, M: Mockable<R>> BridgeDescMgr<R, M> {
     fn new_internal(
         runtime: R,
         circmgr: M::CircMgr,
-        store: Arc<Mutex<DynStore>>,
+        store: Box<dyn BridgeDescStore>,
         config: &BridgeDescDownloadConfig,
         dormancy: Dormancy,
         mockable: M,
@@ -544,6 +578,7 @@ impl<R: Runtime, M: Mockable<R>> BridgeDescMgr<R, M> {
             Default::default()
         }
 
+        let prune_interval = config.prune_interval;
         let config = config.clone().into();
         let (earliest_timeout, timeout_update) = postage::watch::channel();
 
@@ -562,7 +597,7 @@ impl<R: Runtime, M: Mockable<R>> BridgeDescMgr<R, M> {
             state,
             runtime: runtime.clone(),
             circmgr,
-            store,
+            store: Mutex::new(store),
             mockable,
         });
 
@@ -571,6 +606,7 @@ impl<R: Runtime, M: Mockable<R>> BridgeDescMgr<R, M> {
                 runtime.clone(),
                 Arc::downgrade(&mgr),
                 timeout_update,
+                prune_interval,
             ))
             .map_err(|cause| StartupError::Spawn {
                 spawning: "timeout task",
@@ -596,6 +632,20 @@ impl<R: Runtime, M: Mockable<R>> BridgeDescMgr<R, M> {
     pub fn set_dormancy(&self, dormancy: Dormancy) {
         self.mgr.lock_then_process().dormancy = dormancy;
     }
+
+    /// Remove expired descriptors from the store
+    ///
+    /// Deletes every cached descriptor whose expiry time has passed,
+    /// unless its bridge is one of those passed to the last call to
+    /// [`set_bridges`](BridgeDescProvider::set_bridges).
+    /// (We will be refetching those anyway.)
+    ///
+    /// Returns the number of entries removed.
+    ///
+    /// This is also done periodically by the `BridgeDescMgr` itself.
+    pub fn prune_store(&self) -> crate::Result<usize> {
+        self.mgr.prune_store()
+    }
 }
 
 impl<R: Runtime, M: Mockable<R>> BridgeDescProvider for BridgeDescMgr<R, M> {
@@ -914,6 +964,19 @@ impl State {
         self.subscribers.publish(BridgeDescEvent::SomethingChanged);
     }
 
+    /// Return every Tracked bridge
+    ///
+    /// By the *Input* invariant, these are the bridges passed to the last `set_bridges`.
+    fn tracked_bridges(&self) -> HashSet<BridgeKey> {
+        self.running
+            .keys()
+            .chain(self.queued.iter().map(|qe| &qe.bridge))
+            .chain(self.refetch_schedule.iter().map(|re| &re.bridge))
+            .chain(self.retry_schedule.iter().map(|re| &re.bridge))
+            .cloned()
+            .collect()
+    }
+
     /// Obtain the currently-desired level of parallelism
     ///
     /// Helper function.  The return value depends the mutable state and also the `config`.
@@ -984,11 +1047,40 @@ impl<R: Runtime, M: Mockable<R>> StateGuard<'_, R, M> {
             }
         };
 
+        let transition = match (self.current.get(&bridge), &insert) {
+            (Some(Err(_)), Ok(_)) => Some(BridgeDescEvent::BridgeRecovered),
+            (Some(Ok(_)), Err(_)) => Some(BridgeDescEvent::BridgeFailed),
+            _ => None,
+        };
+
         self.modify_current(|current| current.insert(bridge, insert));
+
+        if let Some(transition) = transition {
+            self.subscribers.publish(transition);
+        }
     }
 }
 
 impl<R: Runtime, M: Mockable<R>> Manager<R, M> {
+    /// Remove expired descriptors for unwanted bridges from the store
+    ///
+    /// Implementation of [`BridgeDescMgr::prune_store`].
+    fn prune_store(&self) -> crate::Result<usize> {
+        // Don't hold the state lock while we use the store.
+        let keep = self.lock_only().tracked_bridges();
+        let now = self.runtime.wallclock();
+        let n_deleted = self
+            .store
+            .lock()
+            .map_err(|_| internal!("bridge descriptor store poisoned"))?
+            .prune_bridgedescs(now, &keep)?;
+        debug!(
+            "pruned {} expired bridge descriptor(s) from the store",
+            n_deleted
+        );
+        Ok(n_deleted)
+    }
+
     /// Downloads a descriptor.
     ///
     /// The core of the descriptor download task
@@ -1190,10 +1282,13 @@ fn process_document<R: Runtime>(
 /// `updates` is the receiving end of [`State`]'s `earliest_timeout`,
 /// which is maintained to be the earliest time any of the schedules says we should wake up
 /// (liveness property *Timeout*).
+///
+/// It also prunes the store every `prune_interval`.
 async fn timeout_task<R: Runtime, M: Mockable<R>>(
     runtime: R,
     inner: Weak<Manager<R, M>>,
     update: postage::watch::Receiver<Option<Instant>>,
+    prune_interval: Duration,
 ) {
     /// Requeue things in `*_schedule` whose time for action has arrived
     ///
@@ -1224,8 +1319,18 @@ async fn timeout_task<R: Runtime, M: Mockable<R>>(
 
     let mut next_wakeup = Some(runtime.now());
     let mut update = update.fuse();
+    let mut prune_timer = Box::pin(runtime.sleep(prune_interval)).fuse();
     loop {
         select! {
+            // Time to tidy up the store
+            () = prune_timer => {
+                let inner = if let Some(i) = inner.upgrade() { i } else { break; };
+                if let Err(err) = inner.prune_store() {
+                    error_report!(err, "failed to prune bridge descriptor store");
+                }
+                prune_timer = Box::pin(runtime.sleep(prune_interval)).fuse();
+            },
+
             // Someone modified the schedules, and sent us a new earliest timeout
             changed = update.next() => {
                 // changed is Option<Option< >>.
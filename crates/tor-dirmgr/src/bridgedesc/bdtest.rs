@@ -26,12 +26,13 @@ use tempfile::TempDir;
 use time::OffsetDateTime;
 use tracing_test::traced_test;
 
-use tor_linkspec::HasAddrs;
+use tor_linkspec::{HasAddrs, HasChanMethod, TransportId};
 use tor_rtcompat::SleepProvider;
 use tor_rtmock::simple_time::SimpleMockTimeProvider;
 use tor_rtmock::MockRuntime;
 
 use super::*;
+use crate::storage::DynStore;
 
 const EXAMPLE_DESCRIPTOR: &str = include_str!("../../testdata/routerdesc1.txt");
 const EXAMPLE_PORT: u16 = 9001;
@@ -80,6 +81,9 @@ struct MockState {
     docs: HashMap<u16, Result<String, Error>>,
 
     download_calls: usize,
+
+    /// The transport used for each download, in order
+    download_transports: Vec<TransportId>,
 }
 
 impl Mockable<R> for Mock {}
@@ -98,10 +102,13 @@ impl mockable::MockableAPI<R> for Mock {
         eprint!("download ...");
         let mut mstate = self.mstate.lock().await;
         mstate.download_calls += 1;
+        mstate.download_transports.push(bridge.transport_id());
         eprintln!("#{} {:?}", mstate.download_calls, bridge);
-        let addr = bridge
-            .addrs()
-            .get(0)
+        // For a PT bridge, this is the address we would ask the PT to connect to
+        let chan_method = bridge.chan_method();
+        let addr = chan_method
+            .socket_addrs()
+            .and_then(|addrs| addrs.first())
             .ok_or(TE("bridge has no error", RT::Never))?;
         let doc = mstate
             .docs
@@ -128,6 +135,21 @@ impl Mock {
 }
 
 fn setup(runtime: MockRuntime) -> (TempDir, Bdm, R, M, BridgeKey, rusqlite::Connection) {
+    let (db_tmp_dir, store) = crate::storage::sqlite::test::new_empty().unwrap();
+    let store: Arc<Mutex<DynStore>> = Arc::new(Mutex::new(Box::new(store)));
+
+    let sql_path = db_tmp_dir.path().join("db.sql");
+    let conn = rusqlite::Connection::open(sql_path).unwrap();
+
+    let (bdm, runtime, mock, bridge) = setup_with_store(runtime, Box::new(store));
+
+    (db_tmp_dir, bdm, runtime, mock, bridge, conn)
+}
+
+fn setup_with_store(
+    runtime: MockRuntime,
+    store: Box<dyn BridgeDescStore>,
+) -> (Bdm, R, M, BridgeKey) {
     let sleep = runtime.mock_sleep().clone();
     sleep.jump_wallclock(example_wallclock());
 
@@ -137,16 +159,11 @@ fn setup(runtime: MockRuntime) -> (TempDir, Bdm, R, M, BridgeKey, rusqlite::Conn
     let mstate = Arc::new(futures::lock::Mutex::new(MockState {
         docs,
         download_calls: 0,
+        download_transports: vec![],
     }));
 
     let mock = Mock { sleep, mstate };
 
-    let (db_tmp_dir, store) = crate::storage::sqlite::test::new_empty().unwrap();
-    let store = Arc::new(Mutex::new(Box::new(store) as _));
-
-    let sql_path = db_tmp_dir.path().join("db.sql");
-    let conn = rusqlite::Connection::open(sql_path).unwrap();
-
     let bdm = BridgeDescMgr::<R, M>::new_internal(
         runtime.clone(),
         (),
@@ -161,7 +178,7 @@ fn setup(runtime: MockRuntime) -> (TempDir, Bdm, R, M, BridgeKey, rusqlite::Conn
         .parse()
         .unwrap();
 
-    (db_tmp_dir, bdm, runtime, mock, bridge, conn)
+    (bdm, runtime, mock, bridge)
 }
 
 async fn stream_drain_ready<S: Stream + Unpin + FusedStream>(s: &mut S) -> usize {
@@ -383,26 +400,132 @@ fn success() -> Result<(), anyhow::Error> {
             mstate.download_calls = 0;
         }
 
+        eprintln!("----- make a failing bridge start working ----------");
+
+        let recovering = &bad[0];
+        assert!(bridges.contains(recovering));
+        in_results(&bdm, recovering, Some(Err(()))).unwrap();
+
+        let port = recovering.addrs()[0].port();
+        mock.mstate
+            .lock()
+            .await
+            .docs
+            .insert(port, Ok(EXAMPLE_DESCRIPTOR.into()));
+
+        // Retries happen no later than max_refetch
+        mock.sleep.advance(Duration::from_secs(3600 * 3 + 1));
+
+        let mut seen = vec![];
+        for _ in 0..20 {
+            seen.push(events.next().await.unwrap());
+            if in_results(&bdm, recovering, Some(Ok(()))).is_some() {
+                break;
+            }
+        }
+        in_results(&bdm, recovering, Some(Ok(()))).unwrap();
+        // Pick up anything published at the same time as the change we saw
+        while let Some(Some(event)) = events.next().now_or_never() {
+            seen.push(event);
+        }
+        eprintln!("events: {:?}", seen);
+        assert!(seen.contains(&BridgeDescEvent::BridgeRecovered));
+        assert!(!seen.contains(&BridgeDescEvent::BridgeFailed));
+
         Ok(())
     })
 }
 
+/// Check that descriptors are cached in `bdm`'s store, and reloaded from it
+///
+/// This is the same for every [`BridgeDescStore`]: see the `cache_*` tests.
+async fn cache_scenario((bdm, runtime, mock, bridge): (Bdm, R, M, BridgeKey)) {
+    let mut events = bdm.events().fuse();
+
+    let in_results = |wanted| in_results(&bdm, &bridge, wanted);
+    let lookup = || {
+        bdm.mgr
+            .store
+            .lock()
+            .unwrap()
+            .lookup_bridgedesc(&bridge)
+            .unwrap()
+    };
+
+    eprintln!("----- test that a downloaded descriptor goes into the cache -----");
+
+    bdm.set_bridges(&[bridge.clone()]);
+    stream_drain_until(3, &mut events, || async { in_results(Some(Ok(()))) }).await;
+
+    mock.expect_download_calls(1).await;
+
+    let cached = lookup().unwrap();
+    assert!(cached.fetched() <= runtime.wallclock());
+    assert_eq!(cached.document(), EXAMPLE_DESCRIPTOR);
+
+    eprintln!("----- forget the descriptor and try to reload it from the cache -----");
+
+    clear_and_re_request(&bdm, &mut events, &bridge).await;
+    stream_drain_until(3, &mut events, || async { in_results(Some(Ok(()))) }).await;
+
+    // Should not have been re-downloaded, since the fetch time is great.
+    mock.expect_download_calls(0).await;
+
+    eprintln!("----- corrupt the cache and check we re-download -----");
+
+    bdm.mgr
+        .store
+        .lock()
+        .unwrap()
+        .store_bridgedesc(
+            &bridge,
+            CachedBridgeDescriptor::new("garbage".into(), cached.fetched()),
+            runtime.wallclock() + Duration::from_secs(86400),
+        )
+        .unwrap();
+
+    clear_and_re_request(&bdm, &mut events, &bridge).await;
+    stream_drain_until(3, &mut events, || async { in_results(Some(Ok(()))) }).await;
+
+    mock.expect_download_calls(1).await;
+    assert_eq!(lookup().unwrap().document(), EXAMPLE_DESCRIPTOR);
+
+    eprintln!("----- advance the lock and check that we do an if-modified-since -----");
+
+    let published = bdm
+        .bridges()
+        .get(&bridge)
+        .unwrap()
+        .as_ref()
+        .unwrap()
+        .as_ref()
+        .published();
+
+    mock.mstate.lock().await.docs.insert(
+        EXAMPLE_PORT,
+        Ok(format!("{}{:?}", MOCK_NOT_MODIFIED, published)),
+    );
+
+    // Exceeds default max_refetch
+    mock.sleep.advance(Duration::from_secs(20000));
+
+    stream_drain_until(3, &mut events, || async {
+        (mock.mstate.lock().await.download_calls > 0).then_some(())
+    })
+    .await;
+
+    mock.expect_download_calls(1).await;
+}
+
 #[traced_test]
 #[test]
 fn cache() -> Result<(), anyhow::Error> {
     MockRuntime::try_test_with_various(|runtime| async {
         let (_db_tmp_path, bdm, runtime, mock, bridge, sql_conn, ..) = setup(runtime);
-        let mut events = bdm.events().fuse();
-
-        let in_results = |wanted| in_results(&bdm, &bridge, wanted);
-
-        eprintln!("----- test that a downloaded descriptor goes into the cache -----");
-
-        bdm.set_bridges(&[bridge.clone()]);
-        stream_drain_until(3, &mut events, || async { in_results(Some(Ok(()))) }).await;
 
-        mock.expect_download_calls(1).await;
+        cache_scenario((bdm, runtime.clone(), mock, bridge.clone())).await;
 
+        // Check that the store really is our SQLite database
         sql_conn
             .query_row("SELECT * FROM BridgeDescs", [], |row| {
                 let get_time =
@@ -420,50 +543,152 @@ fn cache() -> Result<(), anyhow::Error> {
             })
             .unwrap();
 
-        eprintln!("----- forget the descriptor and try to reload it from the cache -----");
+        Ok(())
+    })
+}
 
-        clear_and_re_request(&bdm, &mut events, &bridge).await;
-        stream_drain_until(3, &mut events, || async { in_results(Some(Ok(()))) }).await;
+#[traced_test]
+#[test]
+fn cache_memory() -> Result<(), anyhow::Error> {
+    MockRuntime::try_test_with_various(|runtime| async {
+        cache_scenario(setup_with_store(
+            runtime,
+            Box::new(MemoryBridgeDescStore::new()),
+        ))
+        .await;
 
-        // Should not have been re-downloaded, since the fetch time is great.
-        mock.expect_download_calls(0).await;
+        Ok(())
+    })
+}
 
-        eprintln!("----- corrupt the cache and check we re-download -----");
+#[traced_test]
+#[test]
+fn cache_files() -> Result<(), anyhow::Error> {
+    MockRuntime::try_test_with_various(|runtime| async {
+        let tmp = tempfile::tempdir().unwrap();
+        let mistrust = fs_mistrust::Mistrust::new_dangerously_trust_everyone();
+        let store =
+            FileBridgeDescStore::from_path_and_mistrust(tmp.path().join("bridgedescs"), &mistrust)
+                .unwrap();
+        cache_scenario(setup_with_store(runtime, Box::new(store))).await;
 
-        sql_conn
-            .execute_batch("UPDATE BridgeDescs SET contents = 'garbage'")
-            .unwrap();
+        Ok(())
+    })
+}
 
-        clear_and_re_request(&bdm, &mut events, &bridge).await;
-        stream_drain_until(3, &mut events, || async { in_results(Some(Ok(()))) }).await;
+#[cfg(feature = "pt-client")]
+#[traced_test]
+#[test]
+fn pt_bridge() -> Result<(), anyhow::Error> {
+    MockRuntime::try_test_with_various(|runtime| async {
+        let (_db_tmp_path, bdm, _runtime, mock, direct, sql_conn, ..) = setup(runtime);
+        let mut events = bdm.events().fuse();
 
-        mock.expect_download_calls(1).await;
+        // Same fingerprint and endpoint as the direct bridge, but via obfs4.
+        let pt: BridgeKey =
+            "obfs4 51.68.172.83:9001 EB6EFB27F29AC9511A4246D7ABE1AFABFB416FF1 iat-mode=0"
+                .parse()
+                .unwrap();
+        assert_ne!(pt, direct);
+        assert!(direct.transport_id().is_builtin());
+        assert_eq!(pt.transport_id(), "obfs4".parse().unwrap());
 
-        eprintln!("----- advance the lock and check that we do an if-modified-since -----");
+        let bridges = [direct.clone(), pt.clone()];
+        bdm.set_bridges(&bridges);
+        bdm.check_consistency(Some(&bridges));
 
-        let published = bdm
-            .bridges()
-            .get(&bridge)
-            .unwrap()
-            .as_ref()
-            .unwrap()
-            .as_ref()
-            .published();
+        stream_drain_until(6, &mut events, || async {
+            in_results(&bdm, &direct, Some(Ok(())))
+                .and_then(|()| in_results(&bdm, &pt, Some(Ok(()))))
+        })
+        .await;
 
-        mock.mstate.lock().await.docs.insert(
-            EXAMPLE_PORT,
-            Ok(format!("{}{:?}", MOCK_NOT_MODIFIED, published)),
-        );
+        assert_eq!(bdm.bridges().len(), 2);
+        bdm.check_consistency(Some(&bridges));
 
-        // Exceeds default max_refetch
-        mock.sleep.advance(Duration::from_secs(20000));
+        {
+            let mut mstate = mock.mstate.lock().await;
+            let transports = mstate.download_transports.drain(..).collect_vec();
+            assert_eq!(transports.len(), 2);
+            assert!(transports.contains(&direct.transport_id()));
+            assert!(transports.contains(&pt.transport_id()));
+        }
+        mock.expect_download_calls(2).await;
 
+        // Each has its own cache entry.
+        let mut lines: Vec<String> = sql_conn
+            .prepare("SELECT bridge_line FROM BridgeDescs")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        lines.sort();
+        let mut expected = vec![direct.to_string(), pt.to_string()];
+        expected.sort();
+        assert_eq!(lines, expected);
+
+        // Dropping the PT bridge leaves the direct one alone.
+        bdm.set_bridges(&[direct.clone()]);
         stream_drain_until(3, &mut events, || async {
-            (mock.mstate.lock().await.download_calls > 0).then_some(())
+            in_results(&bdm, &pt, None).and_then(|()| in_results(&bdm, &direct, Some(Ok(()))))
         })
         .await;
+        mock.expect_download_calls(0).await;
 
-        mock.expect_download_calls(1).await;
+        Ok(())
+    })
+}
+
+#[traced_test]
+#[test]
+fn prune_store() -> Result<(), anyhow::Error> {
+    MockRuntime::try_test_with_various(|runtime| async {
+        #[allow(unused_variables)] // avoids churn and makes all of these identical
+        let (db_tmp_path, bdm, runtime, mock, bridge, sql_conn, ..) = setup(runtime);
+
+        // Don't download anything: we just want `bridge` to be configured.
+        bdm.set_dormancy(Dormancy::Dormant);
+        bdm.set_bridges(&[bridge.clone()]);
+
+        let now = runtime.wallclock();
+        let day = Duration::from_secs(86400);
+        let expired = bad_bridge(1);
+        let valid = bad_bridge(2);
+
+        let store = |b: &BridgeKey, until| {
+            let entry = CachedBridgeDescriptor::new(EXAMPLE_DESCRIPTOR.into(), now - day * 2);
+            bdm.mgr
+                .store
+                .lock()
+                .unwrap()
+                .store_bridgedesc(b, entry, until)
+                .unwrap();
+        };
+        // Expired, and for a bridge which is still configured
+        store(&bridge, now - day);
+        // Expired, and not configured
+        store(&expired, now - day);
+        // Not expired
+        store(&valid, now + day);
+
+        let count_rows = || -> usize {
+            sql_conn
+                .query_row("SELECT COUNT(*) FROM BridgeDescs", [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(count_rows(), 3);
+
+        assert_eq!(bdm.prune_store().unwrap(), 1);
+
+        let lookup = |b: &BridgeKey| bdm.mgr.store.lock().unwrap().lookup_bridgedesc(b).unwrap();
+        assert!(lookup(&expired).is_none());
+        assert!(lookup(&valid).is_some());
+        assert!(lookup(&bridge).is_some());
+        assert_eq!(count_rows(), 2);
+
+        // Nothing more to do
+        assert_eq!(bdm.prune_store().unwrap(), 0);
 
         Ok(())
     })
@@ -0,0 +1,389 @@
+//! Storage backends for the bridge descriptor cache
+//!
+//! By default, [`BridgeDescMgr`](super::BridgeDescMgr) keeps its cache in the
+//! directory manager's sqlite database.
+//! The types here let an embedder use something else instead,
+//! via [`BridgeDescMgr::new_with_store`](super::BridgeDescMgr::new_with_store).
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use digest::Digest;
+use fs_mistrust::CheckedDir;
+use tor_error::internal;
+use tor_guardmgr::bridge::BridgeConfig;
+use tor_llcrypto as ll;
+
+use crate::storage::{CachedBridgeDescriptor, DynStore};
+use crate::{Error, Result};
+
+/// A place to cache bridges' descriptors
+///
+/// Entries are keyed by the whole [`BridgeConfig`],
+/// so the same relay reached via different transports or addresses
+/// has separate entries.
+pub trait BridgeDescStore: Send + 'static {
+    /// Look up a cached bridge descriptor.
+    fn lookup_bridgedesc(&self, bridge: &BridgeConfig) -> Result<Option<CachedBridgeDescriptor>>;
+
+    /// Store a cached bridge descriptor.
+    ///
+    /// This entry will be deleted some time after `until`
+    /// (but the caller is not allowed to rely on either timely deletion,
+    /// or retention until that time).
+    fn store_bridgedesc(
+        &mut self,
+        bridge: &BridgeConfig,
+        entry: CachedBridgeDescriptor,
+        until: SystemTime,
+    ) -> Result<()>;
+
+    /// Delete a cached bridge descriptor for this bridge.
+    ///
+    /// It's not an error if it's not present.
+    fn delete_bridgedesc(&mut self, bridge: &BridgeConfig) -> Result<()>;
+
+    /// Delete every cached bridge descriptor whose `until` is before `now`,
+    /// except those for bridges in `keep`.
+    ///
+    /// Returns the number of entries deleted.
+    fn prune_bridgedescs(&mut self, now: SystemTime, keep: &HashSet<BridgeConfig>)
+        -> Result<usize>;
+}
+
+/// The directory manager's own store (usually sqlite), shared with the `DirMgr`
+impl BridgeDescStore for Arc<Mutex<DynStore>> {
+    fn lookup_bridgedesc(&self, bridge: &BridgeConfig) -> Result<Option<CachedBridgeDescriptor>> {
+        lock_dirstore(self)?.lookup_bridgedesc(bridge)
+    }
+
+    fn store_bridgedesc(
+        &mut self,
+        bridge: &BridgeConfig,
+        entry: CachedBridgeDescriptor,
+        until: SystemTime,
+    ) -> Result<()> {
+        lock_dirstore(self)?.store_bridgedesc(bridge, entry, until)
+    }
+
+    fn delete_bridgedesc(&mut self, bridge: &BridgeConfig) -> Result<()> {
+        lock_dirstore(self)?.delete_bridgedesc(bridge)
+    }
+
+    fn prune_bridgedescs(
+        &mut self,
+        now: SystemTime,
+        keep: &HashSet<BridgeConfig>,
+    ) -> Result<usize> {
+        lock_dirstore(self)?.prune_bridgedescs(now, keep)
+    }
+}
+
+/// Lock the directory manager's store
+fn lock_dirstore(store: &Mutex<DynStore>) -> Result<std::sync::MutexGuard<'_, DynStore>> {
+    Ok(store
+        .lock()
+        .map_err(|_| internal!("bridge descriptor store poisoned"))?)
+}
+
+/// In-memory bridge descriptor cache
+///
+/// Nothing is saved to disk, so every descriptor will be downloaded afresh
+/// each time the program starts.
+/// Suitable for ephemeral setups, or where no persistent storage is available.
+#[derive(Default, Debug)]
+pub struct MemoryBridgeDescStore {
+    /// The entries, and when each may be discarded
+    entries: HashMap<BridgeConfig, (CachedBridgeDescriptor, SystemTime)>,
+}
+
+impl MemoryBridgeDescStore {
+    /// Create a new, empty, in-memory store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl BridgeDescStore for MemoryBridgeDescStore {
+    fn lookup_bridgedesc(&self, bridge: &BridgeConfig) -> Result<Option<CachedBridgeDescriptor>> {
+        Ok(self.entries.get(bridge).map(|(entry, _)| entry.clone()))
+    }
+
+    fn store_bridgedesc(
+        &mut self,
+        bridge: &BridgeConfig,
+        entry: CachedBridgeDescriptor,
+        until: SystemTime,
+    ) -> Result<()> {
+        self.entries.insert(bridge.clone(), (entry, until));
+        Ok(())
+    }
+
+    fn delete_bridgedesc(&mut self, bridge: &BridgeConfig) -> Result<()> {
+        self.entries.remove(bridge);
+        Ok(())
+    }
+
+    fn prune_bridgedescs(
+        &mut self,
+        now: SystemTime,
+        keep: &HashSet<BridgeConfig>,
+    ) -> Result<usize> {
+        let before = self.entries.len();
+        self.entries
+            .retain(|bridge, (_, until)| *until >= now || keep.contains(bridge));
+        Ok(before - self.entries.len())
+    }
+}
+
+/// Bridge descriptor cache with one file per bridge
+///
+/// Each file is named after a digest of the bridge line,
+/// and contains the fetch and expiry times followed by the descriptor text.
+/// Intended for platforms where sqlite is not available.
+///
+/// There is no locking: only one process should use a particular directory.
+#[derive(Debug)]
+pub struct FileBridgeDescStore {
+    /// The directory containing the files
+    dir: CheckedDir,
+}
+
+/// Prefix of the names of our files
+const FILE_PREFIX: &str = "bridgedesc-";
+
+impl FileBridgeDescStore {
+    /// Construct a new `FileBridgeDescStore` storing its files in `path`.
+    ///
+    /// This function will try to create `path` if it does not already exist.
+    ///
+    /// All files must be "private" according to the rules specified in `mistrust`.
+    pub fn from_path_and_mistrust<P: AsRef<Path>>(
+        path: P,
+        mistrust: &fs_mistrust::Mistrust,
+    ) -> Result<Self> {
+        let dir = mistrust.verifier().check_content().make_secure_dir(path)?;
+        Ok(FileBridgeDescStore { dir })
+    }
+
+    /// Return the filename, relative to our directory, for `bridge`
+    fn rel_filename(bridge: &BridgeConfig) -> String {
+        let digest = ll::d::Sha3_256::digest(bridge.to_string().as_bytes());
+        format!("{}{}", FILE_PREFIX, hex::encode(digest))
+    }
+
+    /// Read and parse the file `fname`, if it exists
+    fn read_entry(&self, fname: &str) -> Result<Option<(CachedBridgeDescriptor, SystemTime)>> {
+        let text = match self.dir.read_to_string(fname) {
+            Ok(text) => text,
+            Err(fs_mistrust::Error::NotFound(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let corrupt = || Error::CacheCorruption("malformed bridge descriptor cache file");
+
+        let mut parts = text.splitn(3, '\n');
+        let mut time_line = |keyword: &str| -> Result<SystemTime> {
+            let secs = parts
+                .next()
+                .and_then(|l| l.strip_prefix(keyword))
+                .and_then(|l| l.strip_prefix(' '))
+                .and_then(|l| l.parse().ok())
+                .ok_or_else(corrupt)?;
+            SystemTime::UNIX_EPOCH
+                .checked_add(Duration::from_secs(secs))
+                .ok_or_else(corrupt)
+        };
+        let fetched = time_line("fetched")?;
+        let until = time_line("until")?;
+        let document = parts.next().ok_or_else(corrupt)?.to_string();
+
+        Ok(Some((CachedBridgeDescriptor { fetched, document }, until)))
+    }
+
+    /// Delete every entry whose `until` is before `now`, other than the files in `keep`
+    ///
+    /// Unreadable files are deleted too.
+    /// Returns the number of files deleted.
+    fn expire(&self, now: SystemTime, keep: &HashSet<String>) -> Result<usize> {
+        let mut n_deleted = 0;
+        let entries = self.dir.read_directory("")?;
+        for entry in entries {
+            let entry = entry.map_err(|error| Error::CacheFile {
+                action: "listing",
+                fname: self.dir.as_path().to_owned(),
+                error: error.into(),
+            })?;
+            let fname = entry.file_name();
+            let Some(fname) = fname.to_str() else {
+                continue;
+            };
+            if !fname.starts_with(FILE_PREFIX) || fname.ends_with(".tmp") || keep.contains(fname) {
+                continue;
+            }
+            let current = matches!(self.read_entry(fname), Ok(Some((_, until))) if until >= now);
+            if !current {
+                self.dir.remove_file(fname)?;
+                n_deleted += 1;
+            }
+        }
+        Ok(n_deleted)
+    }
+}
+
+/// Convert `t` to whole seconds since the epoch, for our file format
+fn to_unix_secs(t: SystemTime) -> u64 {
+    t.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl BridgeDescStore for FileBridgeDescStore {
+    fn lookup_bridgedesc(&self, bridge: &BridgeConfig) -> Result<Option<CachedBridgeDescriptor>> {
+        Ok(self
+            .read_entry(&Self::rel_filename(bridge))?
+            .map(|(entry, _)| entry))
+    }
+
+    fn store_bridgedesc(
+        &mut self,
+        bridge: &BridgeConfig,
+        entry: CachedBridgeDescriptor,
+        until: SystemTime,
+    ) -> Result<()> {
+        let contents = format!(
+            "fetched {}\nuntil {}\n{}",
+            to_unix_secs(entry.fetched),
+            to_unix_secs(until),
+            entry.document,
+        );
+        self.dir
+            .write_and_replace(Self::rel_filename(bridge), contents)?;
+        Ok(())
+    }
+
+    fn delete_bridgedesc(&mut self, bridge: &BridgeConfig) -> Result<()> {
+        match self.dir.remove_file(Self::rel_filename(bridge)) {
+            Ok(()) | Err(fs_mistrust::Error::NotFound(_)) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn prune_bridgedescs(
+        &mut self,
+        now: SystemTime,
+        keep: &HashSet<BridgeConfig>,
+    ) -> Result<usize> {
+        let keep = keep.iter().map(Self::rel_filename).collect();
+        self.expire(now, &keep)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    use super::*;
+
+    /// Exercise `store`, which should be empty
+    fn roundtrip(store: &mut dyn BridgeDescStore) {
+        let bridge: BridgeConfig = "51.68.172.83:9001 EB6EFB27F29AC9511A4246D7ABE1AFABFB416FF1"
+            .parse()
+            .unwrap();
+        let other: BridgeConfig = "192.0.2.1:9001 EB6EFB27F29AC9511A4246D7ABE1AFABFB416FF1"
+            .parse()
+            .unwrap();
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let hour = Duration::from_secs(3600);
+        let entry = |document: &str, fetched| CachedBridgeDescriptor {
+            fetched,
+            document: document.into(),
+        };
+
+        assert!(store.lookup_bridgedesc(&bridge).unwrap().is_none());
+
+        store
+            .store_bridgedesc(&bridge, entry("doc\nwith lines\n", t0), t0 + hour)
+            .unwrap();
+        let got = store.lookup_bridgedesc(&bridge).unwrap().unwrap();
+        assert_eq!(got.document, "doc\nwith lines\n");
+        assert_eq!(got.fetched, t0);
+        assert!(store.lookup_bridgedesc(&other).unwrap().is_none());
+
+        // Storing something else much later leaves the first entry alone:
+        // only pruning removes it, once it has expired
+        store
+            .store_bridgedesc(&other, entry("other", t0 + hour * 2), t0 + hour * 3)
+            .unwrap();
+        assert!(store.lookup_bridgedesc(&bridge).unwrap().is_some());
+        assert_eq!(
+            store
+                .prune_bridgedescs(t0 + hour * 2, &HashSet::new())
+                .unwrap(),
+            1
+        );
+        assert!(store.lookup_bridgedesc(&bridge).unwrap().is_none());
+        assert_eq!(
+            store.lookup_bridgedesc(&other).unwrap().unwrap().document,
+            "other"
+        );
+
+        store.delete_bridgedesc(&other).unwrap();
+        store.delete_bridgedesc(&other).unwrap();
+        assert!(store.lookup_bridgedesc(&other).unwrap().is_none());
+
+        // Pruning spares unexpired entries, and those we are told to keep
+        let t1 = t0 + hour * 10;
+        store
+            .store_bridgedesc(&bridge, entry("bridge", t1), t1 + hour)
+            .unwrap();
+        store
+            .store_bridgedesc(&other, entry("other", t1), t1 + hour)
+            .unwrap();
+        let keep = [bridge.clone()].into_iter().collect();
+        assert_eq!(store.prune_bridgedescs(t1, &keep).unwrap(), 0);
+        assert_eq!(store.prune_bridgedescs(t1 + hour * 2, &keep).unwrap(), 1);
+        assert!(store.lookup_bridgedesc(&bridge).unwrap().is_some());
+        assert!(store.lookup_bridgedesc(&other).unwrap().is_none());
+    }
+
+    #[test]
+    fn memory() {
+        roundtrip(&mut MemoryBridgeDescStore::new());
+    }
+
+    #[test]
+    fn files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mistrust = fs_mistrust::Mistrust::new_dangerously_trust_everyone();
+        let path = tmp.path().join("bridgedescs");
+        let mut store = FileBridgeDescStore::from_path_and_mistrust(&path, &mistrust).unwrap();
+        roundtrip(&mut store);
+
+        // Garbage files are treated as corrupt
+        let bridge: BridgeConfig = "51.68.172.83:9001 EB6EFB27F29AC9511A4246D7ABE1AFABFB416FF1"
+            .parse()
+            .unwrap();
+        std::fs::write(
+            path.join(FileBridgeDescStore::rel_filename(&bridge)),
+            "garbage",
+        )
+        .unwrap();
+        assert!(matches!(
+            store.lookup_bridgedesc(&bridge),
+            Err(Error::CacheCorruption(_))
+        ));
+    }
+}
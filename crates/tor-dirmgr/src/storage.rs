@@ -329,12 +329,23 @@ pub(crate) trait Store: Send + 'static {
     /// It's not an error if it's not present.
     #[cfg(feature = "bridge-client")]
     fn delete_bridgedesc(&mut self, bridge: &BridgeConfig) -> Result<()>;
+
+    /// Delete every cached bridge descriptor whose `until` is before `now`,
+    /// except those for bridges in `keep`.
+    ///
+    /// Returns the number of entries deleted.
+    #[cfg(feature = "bridge-client")]
+    fn prune_bridgedescs(
+        &mut self,
+        now: SystemTime,
+        keep: &std::collections::HashSet<BridgeConfig>,
+    ) -> Result<usize>;
 }
 
 /// Value in the bridge descriptor cache
 #[derive(Clone, Debug)]
 #[cfg_attr(not(feature = "bridge_client"), allow(dead_code))]
-pub(crate) struct CachedBridgeDescriptor {
+pub struct CachedBridgeDescriptor {
     /// When we fetched this
     pub(crate) fetched: SystemTime,
 
@@ -342,6 +353,23 @@ pub(crate) struct CachedBridgeDescriptor {
     pub(crate) document: String,
 }
 
+impl CachedBridgeDescriptor {
+    /// Make a cache entry for `document`, which was fetched at `fetched`
+    pub fn new(document: String, fetched: SystemTime) -> Self {
+        CachedBridgeDescriptor { fetched, document }
+    }
+
+    /// When we fetched this
+    pub fn fetched(&self) -> SystemTime {
+        self.fetched
+    }
+
+    /// The document text, as we fetched it
+    pub fn document(&self) -> &str {
+        &self.document
+    }
+}
+
 #[cfg(test)]
 mod test {
     // @@ begin test lint list maintained by maint/add_warning @@
@@ -644,6 +644,31 @@ impl Store for SqliteStore {
         self.conn.execute(DELETE_BRIDGEDESC, params![bridge_line])?;
         Ok(())
     }
+
+    #[cfg(feature = "bridge-client")]
+    fn prune_bridgedescs(
+        &mut self,
+        now: SystemTime,
+        keep: &std::collections::HashSet<BridgeConfig>,
+    ) -> Result<usize> {
+        if self.is_readonly() {
+            return Ok(0);
+        }
+        let keep: std::collections::HashSet<String> = keep.iter().map(|b| b.to_string()).collect();
+        let tx = self.conn.transaction()?;
+        let expired: Vec<String> = tx
+            .prepare(FIND_EXPIRED_BRIDGEDESCS)?
+            .query_map(params![OffsetDateTime::from(now)], |row| row.get(0))?
+            .collect::<std::result::Result<_, _>>()?;
+        let mut n_deleted = 0;
+        for bridge_line in expired {
+            if !keep.contains(&bridge_line) {
+                n_deleted += tx.execute(DELETE_BRIDGEDESC, params![bridge_line])?;
+            }
+        }
+        tx.commit()?;
+        Ok(n_deleted)
+    }
 }
 
 /// Handle to a blob that we have saved to disk but not yet committed to
@@ -944,6 +969,9 @@ const INSERT_BRIDGEDESC: &str = "
 /// Query: Remove a cached bridge descriptor
 #[cfg(feature = "bridge-client")]
 const DELETE_BRIDGEDESC: &str = "DELETE FROM BridgeDescs WHERE bridge_line = ?;";
+/// Query: Find the bridge lines of bridge descriptors which have expired
+#[cfg(feature = "bridge-client")]
+const FIND_EXPIRED_BRIDGEDESCS: &str = "SELECT bridge_line FROM BridgeDescs WHERE ? > until;";
 
 /// Query: Discard every expired extdoc.
 ///
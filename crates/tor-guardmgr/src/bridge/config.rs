@@ -119,6 +119,16 @@ impl HasAddrs for BridgeConfig {
 
 impl ChanTarget for BridgeConfig {}
 
+impl BridgeConfig {
+    /// Return the transport via which this bridge is reached
+    ///
+    /// For a direct bridge, this is the built-in transport
+    /// (for which [`TransportId::is_builtin`] is true).
+    pub fn transport_id(&self) -> TransportId {
+        self.0.addrs.transport_id()
+    }
+}
+
 derive_serde_raw! {
 /// Builder for a `BridgeConfig`.
 ///
@@ -479,12 +489,13 @@ impl FromStr for Inner {
                     })?;
                     let addr = s
                         .next()
-                        .map(|s| s.parse())
-                        .transpose()
-                        .map_err(|source| BPE::InvalidIPtHostAddr {
-                            word: word.to_string(),
-                            source,
-                        })?
+                        .map(|addr_word| {
+                            addr_word.parse().map_err(|source| BPE::InvalidIPtHostAddr {
+                                word: addr_word.to_string(),
+                                source,
+                            })
+                        })
+                        .transpose()?
                         .unwrap_or(PtTargetAddr::None);
                     ChannelMethod::Pluggable(PtTarget::new(pt_name, addr))
                 }
@@ -546,12 +557,20 @@ impl FromStr for Inner {
 
             match &mut method {
                 ChannelMethod::Direct(_) => return Err(BPE::DirectParametersNotAllowed),
-                ChannelMethod::Pluggable(t) => t.push_setting(k, v).map_err(|source| {
-                    BPE::InvalidPluggableTransportSetting {
-                        word: word.to_string(),
-                        source,
+                ChannelMethod::Pluggable(t) => {
+                    if t.settings().any(|(k0, _)| k0 == k) {
+                        return Err(BPE::DuplicatePtParameter {
+                            key: k.to_string(),
+                            word: word.to_string(),
+                        });
                     }
-                })?,
+                    t.push_setting(k, v).map_err(|source| {
+                        BPE::InvalidPluggableTransportSetting {
+                            word: word.to_string(),
+                            source,
+                        }
+                    })?;
+                }
                 other => panic!("made ourselves an unsupported ChannelMethod {:?}", other),
             }
         }
@@ -822,6 +841,63 @@ mod test {
         );
     }
 
+    #[test]
+    fn bridge_line_errors() {
+        use BridgeParseError as BPE;
+
+        let parse = |s: &str| s.parse::<BridgeConfig>().expect_err(s);
+
+        // Missing fingerprint
+        assert!(matches!(parse("38.229.33.83:80"), BPE::NoRsaIdentity));
+
+        // Malformed direct address
+        match parse("38.229.33.83:port 0BAC39417268B96B9F514E7F63FA6FBA1A788955") {
+            BPE::InvalidIpAddrOrPt { word, .. } => assert_eq!(word, "38.229.33.83:port"),
+            other => panic!("{other:?}"),
+        }
+
+        // Malformed PT address: the error names the address, not the transport
+        #[cfg(feature = "pt-client")]
+        match parse("obfs4 some-host:99999 0BAC39417268B96B9F514E7F63FA6FBA1A788955") {
+            BPE::InvalidIPtHostAddr { word, .. } => assert_eq!(word, "some-host:99999"),
+            other => panic!("{other:?}"),
+        }
+
+        // Unknown (unparseable) transport
+        #[cfg(feature = "pt-client")]
+        match parse("obfs4! some-host:80 0BAC39417268B96B9F514E7F63FA6FBA1A788955") {
+            BPE::InvalidPtOrAddr { word, .. } => assert_eq!(word, "obfs4!"),
+            other => panic!("{other:?}"),
+        }
+        #[cfg(not(feature = "pt-client"))]
+        assert!(matches!(
+            parse("obfs4 some-host:80 0BAC39417268B96B9F514E7F63FA6FBA1A788955"),
+            BPE::PluggableTransportsNotSupported { .. }
+        ));
+
+        // Duplicate PT parameter
+        #[cfg(feature = "pt-client")]
+        match parse(
+            "obfs4 some-host:80 0BAC39417268B96B9F514E7F63FA6FBA1A788955 iat-mode=1 iat-mode=0",
+        ) {
+            BPE::DuplicatePtParameter { key, word } => {
+                assert_eq!(key, "iat-mode");
+                assert_eq!(word, "iat-mode=0");
+            }
+            other => panic!("{other:?}"),
+        }
+
+        // Duplicate identity
+        match parse(
+            "38.229.33.83:80 0BAC39417268B96B9F514E7F63FA6FBA1A788955 23AC39417268B96B9F514E7F63FA6FBA1A788955",
+        ) {
+            BPE::MultipleIdentitiesOfSameType { word } => {
+                assert_eq!(word, "23AC39417268B96B9F514E7F63FA6FBA1A788955");
+            }
+            other => panic!("{other:?}"),
+        }
+    }
+
     #[test]
     fn config_api() {
         let chk_bridgeline = |line: &str, jsons: &[&str], f: &dyn Fn(&mut BridgeConfigBuilder)| {
@@ -80,6 +80,16 @@ pub enum BridgeParseError {
         source: tor_linkspec::PtTargetInvalidSetting,
     },
 
+    /// The same PT key=value parameter was specified more than once
+    #[cfg(feature = "pt-client")]
+    #[error("PT parameter {key:?} specified more than once, at {word:?}")]
+    DuplicatePtParameter {
+        /// The repeated key
+        key: String,
+        /// The offending word
+        word: String,
+    },
+
     /// More than one identity of the same type specified
     #[cfg(feature = "bridge-client")]
     #[error("More than one identity of the same type specified, at {word:?}")]
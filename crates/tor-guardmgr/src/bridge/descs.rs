@@ -96,11 +96,15 @@ dyn_clone::clone_trait_object!(BridgeDescProvider);
 
 /// An event describing a change in a `BridgeDescList`.
 ///
-/// Currently changes are always reported as `BridgeDescEvent::SomethingChanged`.
+/// Every change is reported as `BridgeDescEvent::SomethingChanged`.
+/// Some changes are additionally reported with a more specific event,
+/// such as [`BridgeRecovered`](BridgeDescEvent::BridgeRecovered).
+/// Callers which only want to know when to look at
+/// [`bridges()`](BridgeDescProvider::bridges) again
+/// can act on `SomethingChanged` and ignore the rest.
 ///
 /// In the future, as an optimization, more fine-grained information may be provided.
 /// Unrecognized variants should be handled the same way as `SomethingChanged`.
-/// (So right now, it is not necessary to match on the variant at all.)
 #[derive(
     Debug, Clone, Copy, PartialEq, Eq, EnumIter, EnumCount, IntoPrimitive, TryFromPrimitive,
 )]
@@ -118,6 +122,26 @@ pub enum BridgeDescEvent {
     /// This event may also be generated spuriously, if nothing has changed,
     /// but this will usually be avoided for performance reasons.
     SomethingChanged,
+
+    /// At least one bridge whose descriptor we had failed to get now has a descriptor
+    ///
+    /// That is, some entry in [`bridges()`](BridgeDescProvider::bridges)
+    /// went from `Err` to `Ok`.
+    /// (A newly-added bridge getting its first descriptor is not a recovery.)
+    ///
+    /// Always accompanied by `SomethingChanged`.
+    /// Events may be coalesced, so this does not say how many bridges recovered.
+    BridgeRecovered,
+
+    /// At least one bridge for which we had a descriptor now has an error instead
+    ///
+    /// That is, some entry in [`bridges()`](BridgeDescProvider::bridges)
+    /// went from `Ok` to `Err`.
+    /// (A newly-added bridge failing on its first attempt is not reported this way.)
+    ///
+    /// Always accompanied by `SomethingChanged`.
+    /// Events may be coalesced, so this does not say how many bridges failed.
+    BridgeFailed,
 }
 
 /// An error caused while fetching bridge descriptors
@@ -141,6 +141,8 @@ pub(crate) async fn keep_bridge_descs_updated<RT: tor_rtcompat::Runtime>(
                     return;
                 }
             }
+            // These are always accompanied by SomethingChanged, which is all we need.
+            E::BridgeRecovered | E::BridgeFailed => {}
         }
     }
 }
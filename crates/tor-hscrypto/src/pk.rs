@@ -516,6 +516,8 @@ impl PartialEq for HsClientDescEncKey {
     }
 }
 
+impl Eq for HsClientDescEncKey {}
+
 define_pk_keypair! {
 /// Server key, used for diffie hellman during onion descriptor decryption.
 /// (`KP_hss_desc_enc`)
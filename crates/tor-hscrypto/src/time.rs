@@ -79,6 +79,44 @@ impl TimePeriod {
         })
     }
 
+    /// Return every time period of a given `length` that overlaps `range`, in order.
+    ///
+    /// The `length` and `epoch_offset` are interpreted as in [`TimePeriod::new`]:
+    /// typically, they come from the consensus parameters.
+    ///
+    /// A time period overlaps `range` if it contains any time in `range`.
+    /// (Since `range` is half-open, a time period that begins at `range.end` does not
+    /// overlap it.) If `range` is empty, no time periods are returned.
+    ///
+    /// Return an error if `length` or `epoch_offset` are invalid, or if the start or end
+    /// of `range` cannot be represented as a time period.
+    pub fn overlapping(
+        length: Duration,
+        epoch_offset: Duration,
+        range: std::ops::Range<SystemTime>,
+    ) -> Result<impl Iterator<Item = Self>, TimePeriodError> {
+        let first = Self::new(length, range.start, epoch_offset)?;
+        let end = Self::new(length, range.end, epoch_offset)?;
+
+        let interval_nums = if range.start >= range.end {
+            // An empty range overlaps nothing.
+            first.interval_num..first.interval_num
+        } else if end.range()?.start == range.end {
+            // `range.end` isn't part of `range`, so the time period that begins there
+            // doesn't overlap it.
+            first.interval_num..end.interval_num
+        } else {
+            // This can't overflow: an `interval_num` counts minutes or longer,
+            // so it is far smaller than `u64::MAX`.
+            first.interval_num..(end.interval_num + 1)
+        };
+
+        Ok(interval_nums.map(move |interval_num| TimePeriod {
+            interval_num,
+            ..first
+        }))
+    }
+
     /// Compute the `TimePeriod`, given its length (in **minutes**), index (the number of time
     /// periods that have passed since the unix epoch), and offset from the epoch (in seconds).
     ///
@@ -296,4 +334,57 @@ mod test {
         );
         assert_eq_from_parts(period2);
     }
+
+    #[test]
+    fn overlapping() {
+        let offset = Duration::new(12 * 60 * 60, 0);
+        let one_day = parse_duration("1day").unwrap();
+        let t = |s| parse_rfc3339(s).unwrap();
+        let overlapping = |range| {
+            TimePeriod::overlapping(one_day, offset, range)
+                .unwrap()
+                .collect::<Vec<_>>()
+        };
+        let period = TimePeriod::new(one_day, t("2016-04-13T11:00:00Z"), offset).unwrap();
+
+        // A range within a single time period.
+        assert_eq!(
+            overlapping(t("2016-04-13T11:00:00Z")..t("2016-04-13T11:59:59Z")),
+            vec![period]
+        );
+        // A range that ends exactly where the next time period begins.
+        assert_eq!(
+            overlapping(t("2016-04-12T12:00:00Z")..t("2016-04-13T12:00:00Z")),
+            vec![period]
+        );
+        // A range that reaches into the next time period.
+        assert_eq!(
+            overlapping(t("2016-04-13T11:00:00Z")..t("2016-04-13T12:00:01Z")),
+            vec![period, period.next().unwrap()]
+        );
+        // Empty ranges.
+        let when = t("2016-04-13T11:00:00Z");
+        assert_eq!(overlapping(when..when), vec![]);
+        assert_eq!(overlapping(when..t("2016-04-13T10:00:00Z")), vec![]);
+
+        // A week, starting in the middle of a time period, spans eight time periods.
+        let periods = overlapping(t("2016-04-13T11:00:00Z")..t("2016-04-20T11:00:00Z"));
+        assert_eq!(periods.len(), 8);
+        assert_eq!(periods[0], period);
+        for (prev, next) in periods.iter().zip(periods.iter().skip(1)) {
+            assert_eq!(prev.next().unwrap(), *next);
+            assert_eq!(prev.range().unwrap().end, next.range().unwrap().start);
+        }
+        assert!(periods[7].contains(t("2016-04-20T10:59:59Z")));
+
+        // Invalid parameters are rejected.
+        assert!(matches!(
+            TimePeriod::overlapping(Duration::from_secs(90), offset, when..when),
+            Err(TimePeriodError::IntervalInvalid)
+        ));
+        assert!(matches!(
+            TimePeriod::overlapping(one_day, offset, SystemTime::UNIX_EPOCH..when),
+            Err(TimePeriodError::OutOfRange)
+        ));
+    }
 }
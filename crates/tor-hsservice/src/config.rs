@@ -5,12 +5,14 @@ use derive_adhoc::Adhoc;
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 use std::ops::RangeInclusive;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use tor_cell::relaycell::hs::est_intro;
 use tor_config::ConfigBuildError;
 use tor_error::into_internal;
 use tor_hscrypto::pk::HsClientDescEncKey;
+use tor_keymgr::{KeystoreId, KeystoreSelector};
+use tor_linkspec::RelayId;
 use tor_llcrypto::pk::curve25519;
 
 use crate::HsNickname;
@@ -40,20 +42,233 @@ pub struct OnionServiceConfig {
     ///
     /// We send this to the send to the introduction point to configure how many
     /// introduction requests it sends us.  
-    /// If this is not set, the introduction point chooses a default based on
+    /// By default, the introduction point chooses a limit based on
     /// the current consensus.
+    /// See [`RateLimitAtIntro`] for the other options.
     ///
     /// We do not enforce this limit ourselves.
     ///
     /// This configuration is sent as a `DOS_PARAMS` extension, as documented in
     /// <https://spec.torproject.org/rend-spec/introduction-protocol.html#EST_INTRO_DOS_EXT>.
     #[builder(default)]
-    rate_limit_at_intro: Option<TokenBucketConfig>,
+    rate_limit_at_intro: RateLimitAtIntro,
+
+    /// A rate-limit on how fast we process introduction requests.
+    ///
+    /// Unlike [`rate_limit_at_intro`](OnionServiceConfigBuilder::rate_limit_at_intro),
+    /// we enforce this limit ourselves, across all of our introduction points:
+    /// introduction requests that arrive faster than this are dropped,
+    /// rather than being passed on as [`RendRequest`](crate::RendRequest)s.
+    ///
+    /// If this is not set, we do not limit the rate of introduction requests.
+    /// If it is set, the rate and burst must both be nonzero,
+    /// and the burst must be at least the rate.
+    ///
+    /// This cannot be changed while the service is running.
+    #[builder(default)]
+    pub(crate) rate_limit_rend_requests: Option<TokenBucketConfig>,
 
     /// How many streams will we allow to be open at once for a single circuit on
     /// this service?
     #[builder(default = "65535")]
     max_concurrent_streams_per_circuit: u32,
+
+    /// Whether to only select introduction point relays that advertise an IPv6 ORPort.
+    ///
+    /// Set this if we can only reach relays over IPv6 (for example, on an IPv6-only host).
+    /// Otherwise, we may select introduction points we are unable to connect to.
+    #[builder(default)]
+    pub(crate) ipt_require_ipv6: bool,
+
+    /// Whether to avoid selecting introduction point relays that are in the same family,
+    /// or the same subnet, as any of our other introduction point relays.
+    ///
+    /// Spreading our introduction points over unrelated relays makes it harder for
+    /// any single relay operator to observe (or disrupt) all of them.
+    #[builder(default = "true")]
+    pub(crate) ipt_relay_diversity: bool,
+
+    /// The largest number of new introduction points we start establishing at once.
+    ///
+    /// When we need several new introduction points (for example, at startup),
+    /// we select relays and start establishing introduction points at up to
+    /// this many of them in a batch, rather than one at a time.
+    #[builder(default = "1")]
+    pub(crate) ipt_establish_concurrency: u8,
+
+    /// The number of extra introduction points to keep established, but unpublished,
+    /// in addition to `num_intro_points`.
+    ///
+    /// If one of the introduction points we are publishing fails,
+    /// we can publish one of these straight away,
+    /// rather than waiting for a new one to be established.
+    #[builder(default)]
+    pub(crate) ipt_warm_standby: u8,
+
+    /// The minimum number of good introduction points we need before we publish a descriptor.
+    ///
+    /// If we have fewer than `num_intro_points` good introduction points,
+    /// we normally give the others a short while to become established,
+    /// and then publish the ones we have.
+    /// With this set, we never publish fewer than this many introduction points,
+    /// so that clients are less likely to be unable to connect.
+    ///
+    /// Defaults to 1.  Must be at least 1, and at most `num_intro_points`.
+    #[builder(default = "1")]
+    pub(crate) min_ipts_before_publish: u8,
+
+    /// How to choose which introduction points to publish,
+    /// when we have more good ones than we want to publish.
+    ///
+    /// See [`IptPublicationStrategy`] for the options and their tradeoffs.
+    #[builder(default)]
+    pub(crate) ipt_publication_strategy: IptPublicationStrategy,
+
+    /// The number of consecutive storage failures (for example, keystore errors)
+    /// when preparing a new introduction point, after which we report the
+    /// service as broken.
+    ///
+    /// We keep retrying (with increasing delays) after reaching this threshold.
+    #[builder(default = "5")]
+    pub(crate) ipt_storage_failure_threshold: u32,
+
+    /// How long all of our introduction points can be faulty for,
+    /// before we report the service as broken.
+    ///
+    /// When this happens, we also start considering more relays as introduction points,
+    /// and call the callback set with
+    /// [`OnionService::set_all_ipts_faulty_callback`](crate::OnionService::set_all_ipts_faulty_callback),
+    /// if any.
+    #[builder(default = "Duration::from_secs(10 * 60)")]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    pub(crate) ipt_all_faulty_timeout: Duration,
+
+    /// How long after a descriptor we published has expired,
+    /// we should continue to maintain the introduction points it mentions.
+    ///
+    /// This is an allowance for clock skew, and for the delays
+    /// between a client obtaining our descriptor and its introduction request reaching us.
+    /// (We can't really measure this ourselves, since what matters is the client's latency.)
+    ///
+    /// Defaults to 5 minutes.  May be at most one day.
+    #[builder(default = "Duration::from_secs(5 * 60)")]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    pub(crate) ipt_publish_expiry_slop: Duration,
+
+    /// How long to carry on publishing an introduction point after it stops working.
+    ///
+    /// If one of the introduction points in our descriptor becomes faulty,
+    /// we keep it in our descriptor for up to this long, in case it recovers,
+    /// rather than immediately publishing a descriptor without it.
+    /// This avoids republishing our descriptor whenever an introduction point
+    /// has a brief outage.
+    ///
+    /// Defaults to zero: faulty introduction points are withdrawn straight away.
+    #[builder(default)]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    pub(crate) ipt_fault_grace_period: Duration,
+
+    /// How long the descriptor publisher may wait for introduction points,
+    /// before we report the service as broken.
+    ///
+    /// The publisher can't publish a descriptor until the introduction point manager
+    /// has established some introduction points.
+    /// If this takes longer than `ipt_wait_timeout`, the publisher reports itself
+    /// as [`Broken`](crate::status::State::Broken), and
+    /// [`OnionServiceStatus::awaiting_ipts_timed_out`](crate::status::OnionServiceStatus::awaiting_ipts_timed_out)
+    /// returns `true`, until some introduction points become available.
+    #[builder(default = "Duration::from_secs(10 * 60)")]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    pub(crate) ipt_wait_timeout: Duration,
+
+    /// In what order to upload our descriptor to the HsDirs of each time period.
+    ///
+    /// See [`DescriptorUploadOrder`].
+    #[builder(default)]
+    pub(crate) descriptor_upload_order: DescriptorUploadOrder,
+
+    /// How often to republish our descriptor, even if it hasn't changed.
+    ///
+    /// Each republication happens after a random delay of between
+    /// two thirds and four thirds of this interval,
+    /// so that our republications don't happen at perfectly regular intervals.
+    ///
+    /// Defaults to 90 minutes.  Setting this to zero disables periodic republication.
+    #[builder(default = "Duration::from_secs(90 * 60)")]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    pub(crate) descriptor_republish_interval: Duration,
+
+    /// The minimum time between selections of new introduction point relays.
+    ///
+    /// This limits how quickly we churn through relays, for example if the network directory
+    /// keeps changing, or if our introduction points keep failing.
+    /// It applies to each batch of up to `ipt_establish_concurrency` relays.
+    ///
+    /// By default, there is no minimum.
+    #[builder(default)]
+    #[builder_field_attr(serde(default, with = "humantime_serde::option"))]
+    pub(crate) min_ipt_reselect_interval: Duration,
+
+    /// The keystore in which to store this service's keys.
+    ///
+    /// This must be the ID of one of the keystores configured in the key manager.
+    /// If this is not set, the key manager's default keystore is used.
+    #[builder(default)]
+    pub(crate) keystore: Option<KeystoreId>,
+
+    /// The directory in which to keep this service's introduction request replay logs.
+    ///
+    /// The replay logs see a lot of churn, so it can be useful to put them on fast storage.
+    /// If this is not set, they are kept in a per-service subdirectory of the state directory.
+    ///
+    /// The directory must not be shared with any other service.
+    /// Its permissions are checked, and it is locked while we are using it,
+    /// just as for the default location.
+    #[builder(default)]
+    pub(crate) replay_log_dir: Option<PathBuf>,
+
+    /// Whether to generate (and store) the keys we need, such as the blinded identity keys,
+    /// if they are not already in the keystore.
+    ///
+    /// Set this to `false` if the keys are provisioned ahead of time (for example, because
+    /// the identity key is kept offline): a missing key will then be reported as an error,
+    /// rather than being silently created.
+    #[builder(default = "true")]
+    pub(crate) allow_key_generation: bool,
+
+    /// Whether to refuse to start if the keys of one of our existing introduction points
+    /// are missing from the keystore.
+    ///
+    /// If this is `false`, we generate fresh keys for that introduction point instead,
+    /// logging an error.  But clients using a descriptor we published earlier
+    /// won't be able to connect to us via that introduction point.
+    #[builder(default)]
+    pub(crate) strict_ipt_keys: bool,
+
+    /// Whether to publish our descriptor even if we only have a stale network directory.
+    ///
+    /// Normally, we wait for a timely consensus before selecting the HsDirs to upload to,
+    /// so while we don't have one (for example, during a consensus gap) we publish nothing.
+    /// If this is `true`, we use whatever network directory we have instead, logging a warning:
+    /// the HsDirs we select may then differ from the ones clients look for us at.
+    #[builder(default)]
+    pub(crate) publish_with_stale_netdir: bool,
+
+    /// If present, only upload our descriptor to the HsDirs with one of these identities.
+    ///
+    /// This is meant for testing against small private networks: on the real
+    /// network, restricting our HsDirs makes the service harder to reach.
+    /// If this leaves fewer HsDirs than we are supposed to upload to, we warn about it.
+    #[builder(default)]
+    pub(crate) hsdir_allowlist: Option<Vec<RelayId>>,
+
+    /// HsDirs to upload our descriptor to, in addition to the ones from the HsDir ring.
+    ///
+    /// Uploading to a fixed set of trusted HsDirs can make the service more resilient,
+    /// although clients only look for our descriptor on the HsDir ring.
+    /// Any of these HsDirs that aren't in the consensus are skipped.
+    #[builder(default)]
+    pub(crate) extra_hsdirs: Vec<RelayId>,
     // TODO POW: The POW items are disabled for now, since they aren't implemented.
     // /// If true, we will require proof-of-work when we're under heavy load.
     // // enable_pow: bool,
@@ -69,19 +284,17 @@ pub struct OnionServiceConfig {
     // /// our proof-of-work defense is enabled.
     // pow_queue_rate: TokenBucketConfig,
     // ...
-
-    // /// Configure descriptor-based client authorization.
-    // ///
-    // /// When this is enabled, we encrypt our list of introduction point and keys
-    // /// so that only clients holding one of the listed keys can decrypt it.
-    //
-    // TODO HSS: we'd like this to be an Option, but that doesn't work well with
-    // sub_builder.  We need to figure out what to do there.
-    //
-    // TODO HSS: Temporarily disabled while we figure out how we want it to work;
-    // see #1028
+    /// Configure descriptor-based client authorization.
+    ///
+    /// When this is enabled, we encrypt our list of introduction point and keys
+    /// so that only clients holding one of the listed keys can decrypt it.
+    ///
+    /// This is independent of [`anonymity`](OnionServiceConfigBuilder::anonymity).
     //
-    // pub(crate) encrypt_descriptor: Option<DescEncryptionConfig>,
+    // TODO HSS: we'd like to use sub_builder here, but that doesn't work well
+    // with an Option.  For now, the caller has to build the DescEncryptionConfig.
+    #[builder(default)]
+    pub(crate) encrypt_descriptor: Option<DescEncryptionConfig>,
     //
     // TODO HSS: Do we want a "descriptor_lifetime" setting? C tor doesn't have
     // one. See TODOS on IPT_PUBLISH_{,UN}CERTAIN.
@@ -122,20 +335,57 @@ impl OnionServiceConfig {
             how.cannot_change("anonymity")?;
             other.anonymity = self.anonymity;
         }
+        if self.keystore != other.keystore {
+            // Our existing keys live in the old keystore: we don't support
+            // migrating them.
+            how.cannot_change("keystore")?;
+            other.keystore = self.keystore.clone();
+        }
+        if self.rate_limit_rend_requests != other.rate_limit_rend_requests {
+            // The limit is shared by all of our IPTs, and was set up when we started.
+            how.cannot_change("rate_limit_rend_requests")?;
+            other.rate_limit_rend_requests = self.rate_limit_rend_requests.clone();
+        }
+        if self.replay_log_dir != other.replay_log_dir {
+            // We have the old directory open (and locked), and our IPTs are using
+            // the replay logs in it.
+            how.cannot_change("replay_log_dir")?;
+            other.replay_log_dir = self.replay_log_dir.clone();
+        }
 
         Ok(other)
     }
 
+    /// Return the directory in which this service keeps its replay logs.
+    ///
+    /// This is [`replay_log_dir`](OnionServiceConfigBuilder::replay_log_dir) if it is set,
+    /// or else a directory named after the service in the Arti state directory `state_dir`.
+    pub(crate) fn replay_log_dir(&self, state_dir: &Path) -> PathBuf {
+        match &self.replay_log_dir {
+            Some(dir) => dir.clone(),
+            None => state_dir.join(format!("hss_iptreplay/{}", self.nickname)),
+        }
+    }
+
+    /// Return the [`KeystoreSelector`] to use for this service's keys.
+    pub(crate) fn keystore_selector(&self) -> KeystoreSelector<'_> {
+        keystore_selector(&self.keystore)
+    }
+
     /// Return the DosParams extension we should send for this configuration, if any.
     pub(crate) fn dos_extension(&self) -> Result<Option<est_intro::DosParams>, crate::FatalError> {
-        Ok(self
-            .rate_limit_at_intro
-            .as_ref()
-            .map(dos_params_from_token_bucket_config)
-            .transpose()
-            .map_err(into_internal!(
-                "somehow built an un-validated rate-limit-at-intro"
-            ))?)
+        Ok(match &self.rate_limit_at_intro {
+            RateLimitAtIntro::Default => None,
+            RateLimitAtIntro::Disabled => Some(
+                // Setting the rate and burst to zero disables the defense
+                // at the introduction point.
+                est_intro::DosParams::new(Some(0), Some(0))
+                    .map_err(into_internal!("zero rate-limit-at-intro out of range?!"))?,
+            ),
+            RateLimitAtIntro::Custom(c) => Some(dos_params_from_token_bucket_config(c).map_err(
+                into_internal!("somehow built an un-validated rate-limit-at-intro"),
+            )?),
+        })
     }
 
     /// Time for which we'll use an IPT relay before selecting a new relay to be our IPT
@@ -165,8 +415,43 @@ impl OnionServiceConfigBuilder {
             }
         }
 
+        if let Some(min_ipts) = self.min_ipts_before_publish {
+            let num_ipts = self.num_intro_points.unwrap_or(3);
+            if !(1..=num_ipts).contains(&min_ipts) {
+                return Err(ConfigBuildError::Invalid {
+                    field: "min_ipts_before_publish".into(),
+                    problem: "Must be at least 1, and at most num_intro_points".into(),
+                });
+            }
+        }
+
+        if let Some(Some(limit)) = &self.rate_limit_rend_requests {
+            limit.check("rate_limit_rend_requests.")?;
+        }
+
+        if self.ipt_establish_concurrency == Some(0) {
+            return Err(ConfigBuildError::Invalid {
+                field: "ipt_establish_concurrency".into(),
+                problem: "Must be at least 1".into(),
+            });
+        }
+
+        /// Largest supported `ipt_publish_expiry_slop`
+        ///
+        /// Much more than this would mean maintaining old IPTs for an absurdly long time.
+        const MAX_IPT_PUBLISH_EXPIRY_SLOP: Duration = Duration::from_secs(24 * 3600);
+
+        if let Some(slop) = self.ipt_publish_expiry_slop {
+            if slop > MAX_IPT_PUBLISH_EXPIRY_SLOP {
+                return Err(ConfigBuildError::Invalid {
+                    field: "ipt_publish_expiry_slop".into(),
+                    problem: "Must be at most one day".into(),
+                });
+            }
+        }
+
         // Make sure that our rate_limit_at_intro is valid.
-        if let Some(Some(ref rate_limit)) = self.rate_limit_at_intro {
+        if let Some(RateLimitAtIntro::Custom(ref rate_limit)) = self.rate_limit_at_intro {
             let _ignore_extension: est_intro::DosParams =
                 dos_params_from_token_bucket_config(rate_limit)?;
         }
@@ -180,6 +465,13 @@ impl OnionServiceConfigBuilder {
     }
 }
 
+/// Return the [`KeystoreSelector`] that selects `keystore`, or the default keystore if `None`.
+pub(crate) fn keystore_selector(keystore: &Option<KeystoreId>) -> KeystoreSelector<'_> {
+    keystore
+        .as_ref()
+        .map_or(KeystoreSelector::Default, KeystoreSelector::Id)
+}
+
 /// Configure a token-bucket style limit on some process.
 //
 // TODO: Someday we may wish to lower this; it will be used in far more places.
@@ -203,16 +495,88 @@ impl TokenBucketConfig {
     pub fn new(rate: u32, burst: u32) -> Self {
         Self { rate, burst }
     }
+
+    /// Create a new token-bucket configuration, like [`new`](TokenBucketConfig::new),
+    /// but check that it makes sense.
+    ///
+    /// Returns an error if `rate` or `burst` is zero,
+    /// or if `burst` is less than `rate`.
+    pub fn new_checked(rate: u32, burst: u32) -> Result<Self, ConfigBuildError> {
+        let config = Self::new(rate, burst);
+        config.check("")?;
+        Ok(config)
+    }
+
+    /// Check that `rate` and `burst` are nonzero, and that `burst` is at least `rate`.
+    ///
+    /// The fields named in the error are prefixed with `prefix`.
+    fn check(&self, prefix: &str) -> Result<(), ConfigBuildError> {
+        let invalid = |field: &str, problem: &str| ConfigBuildError::Invalid {
+            field: format!("{prefix}{field}"),
+            problem: problem.into(),
+        };
+
+        if self.rate == 0 {
+            return Err(invalid("rate", "must be at least 1"));
+        }
+        if self.burst == 0 {
+            return Err(invalid("burst", "must be at least 1"));
+        }
+        if self.burst < self.rate {
+            return Err(invalid("burst", "must be at least rate"));
+        }
+
+        Ok(())
+    }
+
+    /// Return the maximum number of items to process per second.
+    pub(crate) fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Return the maximum number of items to process in a single burst.
+    pub(crate) fn burst(&self) -> u32 {
+        self.burst
+    }
+}
+
+/// What rate-limit to ask our introduction points to apply to introduction requests
+///
+/// See [`rate_limit_at_intro`](OnionServiceConfigBuilder::rate_limit_at_intro).
+///
+/// In the configuration file, this is `"default"`, `"disabled"`,
+/// or a table like `{ custom = { rate = 25, burst = 200 } }`.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum RateLimitAtIntro {
+    /// Let the introduction point choose a limit, based on the current consensus
+    ///
+    /// We don't send a `DOS_PARAMS` extension.
+    #[default]
+    Default,
+    /// Ask the introduction point not to rate-limit introduction requests at all
+    ///
+    /// We send a `DOS_PARAMS` extension with a rate and burst of zero.
+    Disabled,
+    /// Ask the introduction point to apply this limit
+    ///
+    /// The rate and burst must both be nonzero, and the burst must be at least the rate.
+    Custom(TokenBucketConfig),
 }
 
 /// Helper: Try to create a DosParams from a given token bucket configuration.
-/// Give an error if the value is out of range.
+/// Give an error if the value is out of range, or if the rate and burst don't make sense
+/// together.
 ///
 /// This is a separate function so we can use the same logic when validating
 /// and when making the extension object.
 fn dos_params_from_token_bucket_config(
     c: &TokenBucketConfig,
 ) -> Result<est_intro::DosParams, ConfigBuildError> {
+    // (To ask for no limit at all, use RateLimitAtIntro::Disabled.)
+    c.check("rate_limit_at_intro.")?;
+
     let err = || ConfigBuildError::Invalid {
         field: "rate_limit_at_intro".into(),
         problem: "out of range".into(),
@@ -221,8 +585,76 @@ fn dos_params_from_token_bucket_config(
     est_intro::DosParams::new(Some(cast(c.rate)?), Some(cast(c.burst)?)).map_err(|_| err())
 }
 
+/// How to choose which introduction points to publish
+///
+/// This matters when we have more good introduction points than
+/// [`num_intro_points`](OnionServiceConfigBuilder::num_intro_points),
+/// for example when maintaining warm standby introduction points.
+///
+/// Whatever the strategy, introduction points we are already publishing
+/// are preferred to warm standbys, so that a standby is only published
+/// when one of the published introduction points stops being good.
+///
+/// Preferring introduction points according to how well they perform
+/// makes our choices depend on the network environment,
+/// which an adversary may be able to observe or influence.
+/// So, the default is [`MostRecent`](IptPublicationStrategy::MostRecent),
+/// which doesn't depend on any measurements.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum IptPublicationStrategy {
+    /// Publish the introduction points at the relays we selected most recently
+    ///
+    /// Since we only select new relays when old ones are to retire, or are faulty,
+    /// this prefers relays we don't know to be faulty
+    /// to ones we have considered faulty at least once.
+    /// It may also slightly bring forward the time at which all descriptors
+    /// mentioning a relay have expired, so that we can forget about it.
+    #[default]
+    MostRecent,
+
+    /// Publish the introduction points that have become faulty the fewest times
+    ///
+    /// Ties are broken as for `MostRecent`.
+    ///
+    /// This favours reliable introduction points,
+    /// but an adversary who can make our introduction points fail
+    /// can influence which ones we publish.
+    FewestFaults,
+
+    /// Publish the introduction points that were quickest to establish
+    ///
+    /// Introduction points whose establishment time we don't know are least preferred.
+    /// Ties are broken as for `MostRecent`.
+    ///
+    /// This favours responsive (often, nearby) introduction relays,
+    /// which may reveal something about our location on the network.
+    FastestEstablish,
+}
+
+/// In what order to upload our descriptor to the HsDirs of a time period
+///
+/// We upload to several HsDirs at once, but not to all of them,
+/// so the first HsDirs in the order get our descriptor first.
+/// If an upload pass is cut short, the HsDirs at the end of the order may miss out.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum DescriptorUploadOrder {
+    /// Upload in the order the HsDirs appear in the hash ring
+    #[default]
+    RingOrder,
+
+    /// Upload in a random order, chosen afresh for each upload pass
+    ///
+    /// This spreads our uploads over the HsDirs,
+    /// rather than always starting with the same ones.
+    Random,
+}
+
 /// Configuration for descriptor encryption.
-#[derive(Debug, Clone, Builder, PartialEq)]
+#[derive(Debug, Clone, Builder, Eq, PartialEq, Serialize, Deserialize)]
 #[builder(derive(Serialize, Deserialize))]
 #[non_exhaustive]
 pub struct DescEncryptionConfig {
@@ -238,7 +670,9 @@ pub struct DescEncryptionConfig {
 }
 
 /// A single client (or a collection of clients) authorized using the descriptor encryption mechanism.
-#[derive(Debug, Clone, PartialEq, serde_with::DeserializeFromStr, serde_with::SerializeDisplay)]
+#[derive(
+    Debug, Clone, Eq, PartialEq, serde_with::DeserializeFromStr, serde_with::SerializeDisplay,
+)]
 #[non_exhaustive]
 pub enum AuthorizedClientConfig {
     /// A directory full of authorized public keys.
@@ -297,3 +731,125 @@ impl std::str::FromStr for AuthorizedClientConfig {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    /// Build a config with `rate_limit_at_intro` set to `limit`, and return its extension
+    fn dos_extension(limit: RateLimitAtIntro) -> Option<est_intro::DosParams> {
+        let nick: HsNickname = "nick".to_string().try_into().unwrap();
+        OnionServiceConfigBuilder::default()
+            .nickname(nick)
+            .rate_limit_at_intro(limit)
+            .build()
+            .unwrap()
+            .dos_extension()
+            .unwrap()
+    }
+
+    #[test]
+    fn rate_limit_at_intro_default() {
+        assert!(dos_extension(RateLimitAtIntro::Default).is_none());
+    }
+
+    #[test]
+    fn rate_limit_at_intro_disabled() {
+        let params = dos_extension(RateLimitAtIntro::Disabled).unwrap();
+        assert_eq!(params.rate_per_sec(), Some(0));
+        assert_eq!(params.burst_per_sec(), Some(0));
+    }
+
+    #[test]
+    fn rate_limit_at_intro_custom() {
+        let limit = TokenBucketConfig::new(25, 200);
+        let params = dos_extension(RateLimitAtIntro::Custom(limit)).unwrap();
+        assert_eq!(params.rate_per_sec(), Some(25));
+        assert_eq!(params.burst_per_sec(), Some(200));
+
+        // Out-of-range values are rejected when we build the configuration.
+        let nick: HsNickname = "nick".to_string().try_into().unwrap();
+        let limit = TokenBucketConfig::new(u32::MAX, u32::MAX);
+        assert!(OnionServiceConfigBuilder::default()
+            .nickname(nick)
+            .rate_limit_at_intro(RateLimitAtIntro::Custom(limit))
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn rate_limit_at_intro_nonsensical() {
+        let build = |rate, burst| {
+            let nick: HsNickname = "nick".to_string().try_into().unwrap();
+            let limit = TokenBucketConfig::new(rate, burst);
+            OnionServiceConfigBuilder::default()
+                .nickname(nick)
+                .rate_limit_at_intro(RateLimitAtIntro::Custom(limit))
+                .build()
+        };
+
+        assert!(build(25, 25).is_ok());
+        for (rate, burst, bad_field) in [
+            (200, 25, "burst"),
+            (0, 25, "rate"),
+            (25, 0, "burst"),
+            (0, 0, "rate"),
+        ] {
+            match build(rate, burst).unwrap_err() {
+                ConfigBuildError::Invalid { field, .. } => {
+                    assert_eq!(field, format!("rate_limit_at_intro.{bad_field}"));
+                }
+                other => panic!("unexpected error {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn rate_limit_rend_requests_nonsensical() {
+        let build = |rate, burst| {
+            let nick: HsNickname = "nick".to_string().try_into().unwrap();
+            OnionServiceConfigBuilder::default()
+                .nickname(nick)
+                .rate_limit_rend_requests(Some(TokenBucketConfig::new(rate, burst)))
+                .build()
+        };
+
+        assert!(build(25, 25).is_ok());
+        for (rate, burst, bad_field) in [
+            (200, 25, "burst"),
+            (0, 25, "rate"),
+            (25, 0, "burst"),
+            (0, 0, "rate"),
+        ] {
+            match build(rate, burst).unwrap_err() {
+                ConfigBuildError::Invalid { field, .. } => {
+                    assert_eq!(field, format!("rate_limit_rend_requests.{bad_field}"));
+                }
+                other => panic!("unexpected error {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn token_bucket_new_checked() {
+        assert_eq!(
+            TokenBucketConfig::new_checked(25, 200).unwrap(),
+            TokenBucketConfig::new(25, 200)
+        );
+        assert!(TokenBucketConfig::new_checked(200, 25).is_err());
+        assert!(TokenBucketConfig::new_checked(0, 25).is_err());
+        assert!(TokenBucketConfig::new_checked(25, 0).is_err());
+    }
+}
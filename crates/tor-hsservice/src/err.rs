@@ -1,5 +1,7 @@
 //! Declare an error type for the `tor-hsservice` crate.
 
+use std::io;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -9,6 +11,7 @@ use thiserror::Error;
 
 use tor_error::error_report;
 use tor_error::{Bug, ErrorKind, HasKind};
+use tor_hscrypto::time::TimePeriod;
 use tor_persist::FsMistrustErrorExt as _;
 
 pub use crate::svc::rend_handshake::{EstablishSessionError, IntroRequestError};
@@ -108,6 +111,51 @@ impl From<Bug> for StartupError {
     }
 }
 
+/// An error which occurs trying to purge the persistent state of an onion service
+///
+/// Returned by [`purge_service_state`](crate::purge_service_state).
+#[derive(Clone, Debug, Error)]
+#[non_exhaustive]
+pub enum PurgeError {
+    /// The service is running, in this process or another one.
+    #[error("Onion service {0} is running")]
+    ServiceRunning(HsNickname),
+
+    /// A filesystem operation failed.
+    #[error("Unable to {action} {path:?}")]
+    Io {
+        /// The action we were trying to perform.
+        action: &'static str,
+        /// The file or directory we were trying to act on.
+        path: PathBuf,
+        /// The underlying error
+        #[source]
+        cause: Arc<io::Error>,
+    },
+
+    /// A keystore operation failed.
+    #[error("Keystore error while attempting to {action}")]
+    Keystore {
+        /// The action we were trying to perform.
+        action: &'static str,
+        /// The underlying error
+        #[source]
+        cause: tor_keymgr::Error,
+    },
+}
+
+impl HasKind for PurgeError {
+    fn kind(&self) -> ErrorKind {
+        use ErrorKind as EK;
+        use PurgeError as E;
+        match self {
+            E::ServiceRunning(_) => EK::LocalResourceAlreadyInUse,
+            E::Io { .. } => EK::PersistentStateAccessFailed,
+            E::Keystore { cause, .. } => cause.kind(),
+        }
+    }
+}
+
 /// An error which occurs trying to communicate with a particular client.
 ///
 /// This is returned by `RendRequest::accept` and `StreamRequest::accept`.
@@ -131,6 +179,30 @@ pub enum ClientError {
     RejectStream(#[source] tor_proto::Error),
 }
 
+impl ClientError {
+    /// Return true if this error was caused by a bad request from the client,
+    /// rather than by a problem on our side or in the network.
+    ///
+    /// Clients can cause these errors at will, so they are not worth alarming
+    /// the operator about.
+    pub fn is_bad_request(&self) -> bool {
+        use EstablishSessionError as ESE;
+        match self {
+            ClientError::BadIntroduce(_) => true,
+            ClientError::EstablishSession(e) => match e {
+                ESE::UnsupportedOnionKey | ESE::ImpossibleIds(_) => true,
+                ESE::NetdirUnavailable(_)
+                | ESE::RendCirc(_)
+                | ESE::VirtualHop(_)
+                | ESE::AcceptBegins(_)
+                | ESE::SendRendezvous(_)
+                | ESE::Bug(_) => false,
+            },
+            ClientError::AcceptStream(_) | ClientError::RejectStream(_) => false,
+        }
+    }
+}
+
 impl HasKind for ClientError {
     fn kind(&self) -> ErrorKind {
         match self {
@@ -142,6 +214,54 @@ impl HasKind for ClientError {
     }
 }
 
+/// An error which occurs when self-testing an onion service.
+///
+/// This is returned by `OnionService::self_test`.
+#[cfg(feature = "self-test")]
+#[derive(Clone, Debug, Error)]
+#[non_exhaustive]
+pub enum SelfTestError {
+    /// We haven't successfully published a descriptor yet, so clients can't find us.
+    #[error("No descriptor has been published yet")]
+    NotPublished,
+
+    /// Unable to read our identity key.
+    #[error("Unable to read our identity key from the keystore")]
+    Keystore(#[source] tor_keymgr::Error),
+
+    /// We don't have a usable network directory.
+    #[error("No usable network directory")]
+    NetDir(#[source] tor_netdir::Error),
+
+    /// Unable to start the client-side connector.
+    #[error("Unable to start an onion service client")]
+    ClientStartup(#[source] tor_hsclient::StartupError),
+
+    /// Connecting to our own service, as a client, failed.
+    #[error("Unable to connect to our own onion service")]
+    Connect(#[source] tor_hsclient::ConnError),
+
+    /// An internal error.
+    #[error("Internal error")]
+    Bug(#[from] Bug),
+}
+
+#[cfg(feature = "self-test")]
+impl HasKind for SelfTestError {
+    fn kind(&self) -> ErrorKind {
+        use ErrorKind as EK;
+        use SelfTestError as STE;
+        match self {
+            STE::NotPublished => EK::OnionServiceNotRunning,
+            STE::Keystore(e) => e.kind(),
+            STE::NetDir(e) => e.kind(),
+            STE::ClientStartup(e) => e.kind(),
+            STE::Connect(e) => e.kind(),
+            STE::Bug(e) => e.kind(),
+        }
+    }
+}
+
 /// Latest time to retry a failed IPT store (eg, disk full)
 // TODO HSS configure?
 const IPT_STORE_RETRY_MAX: Duration = Duration::from_secs(60);
@@ -217,6 +337,39 @@ pub enum FatalError {
     #[error("Hidden service identity key not found: {0}")]
     MissingHsIdKeypair(HsNickname),
 
+    /// The blinded identity keypair of the service for a time period could not be found
+    /// in the keystore, and we are not allowed to generate it.
+    #[error("Blinded identity key of {nickname} for time period {period:?} not found")]
+    MissingBlindIdKeypair {
+        /// The nickname of the service.
+        nickname: HsNickname,
+        /// The time period of the missing key.
+        period: TimePeriod,
+    },
+
+    /// The service is running in offline mode, and has run out of the
+    /// descriptor signing keys that were provisioned for it.
+    ///
+    /// In offline mode, the identity keypair of the service is not in the keystore,
+    /// so we cannot derive the keys we need for a time period ourselves:
+    /// they must be provisioned in advance.
+    #[error("Offline service {nickname} has no descriptor signing key for time period {period:?}")]
+    OfflineKeysExhausted {
+        /// The nickname of the service.
+        nickname: HsNickname,
+        /// The time period for which we have no key.
+        period: TimePeriod,
+    },
+
+    /// Unable to access the stored certificates of the descriptor signing keys
+    /// provisioned for an offline service.
+    #[error("Unable to access the stored offline descriptor signing key certificates")]
+    OfflineCertStore(#[source] tor_persist::Error),
+
+    /// Unable to read the keys of the authorized clients from the configuration.
+    #[error("Unable to read the authorized client keys")]
+    AuthorizedClients(#[source] AuthorizedClientConfigError),
+
     /// IPT keys found for being-created IPT
     ///
     /// This could only happen if someone is messing with our RNG
@@ -224,6 +377,13 @@ pub enum FatalError {
     #[error("IPT keys found for being-created IPT {0} (serious key management problems!)")]
     IptKeysFoundUnexpectedly(tor_keymgr::ArtiPath),
 
+    /// The keys of a previously-created IPT are missing from the keystore,
+    /// and we are not allowed to regenerate them.
+    ///
+    /// See [`strict_ipt_keys`](crate::config::OnionServiceConfigBuilder::strict_ipt_keys).
+    #[error("Key {0} of existing IPT is missing from the keystore, and strict_ipt_keys is set")]
+    IptKeysMissing(tor_keymgr::ArtiPath),
+
     /// The network directory provider is shutting down without giving us the
     /// netdir we asked for.
     #[error("{0}")]
@@ -255,9 +415,60 @@ impl HasKind for FatalError {
             FE::Spawn { cause, .. } => cause.kind(),
             FE::Keystore(e) => e.kind(),
             FE::MissingHsIdKeypair(_) => EK::Internal, // TODO HSS this is wrong
+            FE::MissingBlindIdKeypair { .. } => EK::InvalidConfig,
+            FE::OfflineKeysExhausted { .. } => EK::InvalidConfig,
+            FE::OfflineCertStore(e) => e.kind(),
+            FE::AuthorizedClients(e) => e.kind(),
             FE::IptKeysFoundUnexpectedly(_) => EK::Internal, // This is indeed quite bad.
+            FE::IptKeysMissing(_) => EK::KeystoreCorrupted,
             FE::NetdirProviderShutdown(e) => e.kind(),
             FE::Bug(e) => e.kind(),
         }
     }
 }
+
+/// Authorized client configuration error.
+#[derive(Debug, Clone, Error)]
+#[non_exhaustive]
+pub enum AuthorizedClientConfigError {
+    /// A key is malformed if it doesn't start with the "curve25519" prefix,
+    /// or if its decoded content is not exactly 32 bytes long.
+    #[error("Malformed authorized client key")]
+    MalformedKey,
+
+    /// Error while decoding an authorized client's key.
+    #[error("Failed base64-decode an authorized client's key")]
+    Base64Decode(#[from] base64ct::Error),
+
+    /// Error while accessing the authorized_client key dir.
+    #[error("Failed to {action} file {path}")]
+    KeyDir {
+        /// What we were doing when we encountered the error.
+        action: &'static str,
+        /// The file that we were trying to access.
+        path: std::path::PathBuf,
+        /// The underlying I/O error.
+        #[source]
+        error: Arc<std::io::Error>,
+    },
+
+    /// Error while accessing the authorized_client key dir.
+    #[error("expected regular file, found directory: {path}")]
+    MalformedFile {
+        /// The file that we were trying to access.
+        path: std::path::PathBuf,
+    },
+}
+
+impl HasKind for AuthorizedClientConfigError {
+    fn kind(&self) -> ErrorKind {
+        use AuthorizedClientConfigError as ACCE;
+        use ErrorKind as EK;
+        match self {
+            ACCE::MalformedKey | ACCE::Base64Decode(_) | ACCE::MalformedFile { .. } => {
+                EK::InvalidConfig
+            }
+            ACCE::KeyDir { .. } => EK::Other,
+        }
+    }
+}
@@ -1,28 +1,177 @@
 //! Functions to help working with onion services.
 
+use futures::channel::mpsc;
 use futures::{future::Either, stream, FutureExt, Stream, StreamExt};
-use tor_error::warn_report;
+use tor_error::{debug_report, warn_report};
 
-use crate::{RendRequest, StreamRequest};
+use crate::{ClientError, RendRequest, StreamRequest};
 
 /// Consume a stream of [`RendRequest`], accepting them all, and produce a
 /// stream of [`StreamRequest`].
 ///
 /// If you want to reject certain [`RendRequest`]s, you can use [`StreamExt::filter`] or
 /// similar in order to remove them from the incoming stream.
+///
+/// Failures to accept individual requests are logged, and otherwise ignored.
+/// To observe them, use [`handle_rend_requests_reporting_errors`].
 pub fn handle_rend_requests<S>(rend_requests: S) -> impl Stream<Item = StreamRequest>
 where
     S: Stream<Item = RendRequest>,
 {
-    rend_requests.flat_map_unordered(None, |rend_request| {
+    accept_rend_requests(rend_requests, None)
+}
+
+/// Like [`handle_rend_requests`], but also send every error that occurs
+/// while accepting a [`RendRequest`] to `errors`.
+///
+/// Callers can use [`HasKind::kind`](tor_error::HasKind::kind) to classify the errors,
+/// and [`ClientError::is_bad_request`] to tell requests that the client got wrong
+/// apart from failures on our side (or in the network).
+///
+/// If `errors` is closed, the errors are still logged, but are not otherwise reported.
+pub fn handle_rend_requests_reporting_errors<S>(
+    rend_requests: S,
+    errors: mpsc::UnboundedSender<ClientError>,
+) -> impl Stream<Item = StreamRequest>
+where
+    S: Stream<Item = RendRequest>,
+{
+    accept_rend_requests(rend_requests, Some(errors))
+}
+
+/// Implementation of [`handle_rend_requests`] and [`handle_rend_requests_reporting_errors`]
+fn accept_rend_requests<S>(
+    rend_requests: S,
+    errors: Option<mpsc::UnboundedSender<ClientError>>,
+) -> impl Stream<Item = StreamRequest>
+where
+    S: Stream<Item = RendRequest>,
+{
+    rend_requests.flat_map_unordered(None, move |rend_request| {
+        let errors = errors.clone();
         Box::pin(rend_request.accept())
-            .map(|outcome| match outcome {
+            .map(move |outcome| match outcome {
                 Ok(stream_requests) => Either::Left(stream_requests),
                 Err(e) => {
-                    warn_report!(e, "Problem while accepting rendezvous request");
+                    report_rend_error(e, errors);
                     Either::Right(stream::empty())
                 }
             })
             .flatten_stream()
     })
 }
+
+/// Log `e`, an error from accepting a rendezvous request, and send it to `errors`, if any.
+fn report_rend_error(e: ClientError, errors: Option<mpsc::UnboundedSender<ClientError>>) {
+    if e.is_bad_request() {
+        // Clients can trigger these at will, so don't shout about them.
+        debug_report!(&e, "Client sent a bad rendezvous request");
+    } else {
+        warn_report!(&e, "Problem while accepting rendezvous request");
+    }
+    if let Some(errors) = errors {
+        // If the receiver has gone away, nobody wants to hear about this.
+        let _: Result<(), mpsc::TrySendError<_>> = errors.unbounded_send(e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use tor_basic_utils::test_rng::testing_rng;
+    use tor_cell::relaycell::msg::{Body as _, Introduce2};
+    use tor_circmgr::hspool::HsCircKind;
+    use tor_error::{ErrorKind, HasKind as _};
+    use tor_linkspec::{verbatim::VerbatimLinkSpecCircTarget, OwnedCircTarget};
+    use tor_llcrypto::pk::{curve25519, ed25519};
+    use tor_netdir::testprovider::TestNetDirProvider;
+    use tor_proto::circuit::ClientCirc;
+
+    use crate::req::RendRequestContext;
+    use crate::svc::rend_handshake::RendCircConnector;
+    use crate::IptLocalId;
+
+    /// A [`RendCircConnector`] that must never be used.
+    struct NoCircuits;
+
+    #[async_trait]
+    impl RendCircConnector for NoCircuits {
+        async fn get_or_launch_specific(
+            &self,
+            _netdir: &tor_netdir::NetDir,
+            _kind: HsCircKind,
+            _target: VerbatimLinkSpecCircTarget<OwnedCircTarget>,
+        ) -> tor_circmgr::Result<Arc<ClientCirc>> {
+            panic!("tried to build a rendezvous circuit for a malformed request");
+        }
+    }
+
+    /// Make a `RendRequest` whose INTRODUCE2 message cannot be decrypted.
+    fn malformed_rend_request() -> RendRequest {
+        let mut rng = testing_rng();
+        let ntor_secret = curve25519::StaticSecret::random_from_rng(&mut rng);
+        let kp_hss_ntor = curve25519::StaticKeypair {
+            public: (&ntor_secret).into(),
+            secret: ntor_secret,
+        };
+        let kp_hs_ipt_sid = ed25519::Keypair::generate(&mut rng).verifying_key();
+
+        let context = RendRequestContext {
+            kp_hss_ntor: Arc::new(kp_hss_ntor.into()),
+            kp_hs_ipt_sid: kp_hs_ipt_sid.into(),
+            subcredentials: vec![[0x42; 32].into()],
+            netdir_provider: Arc::new(TestNetDirProvider::new()),
+            circ_pool: Arc::new(NoCircuits),
+        };
+
+        // A header with a zero legacy key id, an ed25519 auth key, and no extensions,
+        // followed by a body that is long enough to parse but has a bogus MAC.
+        let mut body = vec![0; 20];
+        body.push(2); // auth key type: ed25519
+        body.extend_from_slice(&32_u16.to_be_bytes());
+        body.extend_from_slice(kp_hs_ipt_sid.as_bytes());
+        body.push(0); // no extensions
+        body.extend_from_slice(&[0x77; 32 + 64 + 32]);
+        let msg =
+            Introduce2::decode_from_reader(&mut tor_bytes::Reader::from_slice(&body)).unwrap();
+
+        RendRequest::new(IptLocalId::dummy(1), msg, Arc::new(context))
+    }
+
+    #[test]
+    fn malformed_request_reported() {
+        tor_rtcompat::test_with_one_runtime!(|_runtime| async move {
+            let (errors_tx, errors_rx) = mpsc::unbounded();
+            let stream_requests = handle_rend_requests_reporting_errors(
+                stream::iter([malformed_rend_request()]),
+                errors_tx,
+            );
+
+            let stream_requests: Vec<_> = stream_requests.collect().await;
+            assert!(stream_requests.is_empty());
+
+            let errors: Vec<_> = errors_rx.collect().await;
+            assert_eq!(errors.len(), 1);
+            let error = &errors[0];
+            assert!(matches!(error, ClientError::BadIntroduce(_)), "{error:?}");
+            assert!(error.is_bad_request());
+            assert_eq!(error.kind(), ErrorKind::TorProtocolViolation);
+        });
+    }
+}
@@ -6,6 +6,7 @@
 //! See [`IptManager::run_once`] for discussion of the implementation approach.
 
 use std::any::Any;
+use std::cmp::Reverse;
 use std::collections::{HashMap, VecDeque};
 use std::fmt::{self, Debug};
 use std::hash::Hash;
@@ -13,18 +14,20 @@ use std::io;
 use std::marker::PhantomData;
 use std::panic::AssertUnwindSafe;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use futures::channel::mpsc;
+use futures::select_biased;
+use futures::stream::BoxStream;
 use futures::task::SpawnExt as _;
-use futures::{future, select_biased};
 use futures::{FutureExt as _, SinkExt as _, StreamExt as _};
 
 use educe::Educe;
 use fslock::LockFile;
 use itertools::Itertools as _;
 use postage::{broadcast, watch};
+use rand::seq::SliceRandom as _;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -36,17 +39,25 @@ use tor_basic_utils::RngExt as _;
 use tor_circmgr::hspool::HsCircPool;
 use tor_error::{error_report, info_report};
 use tor_error::{internal, into_internal, Bug, ErrorKind, HasKind};
-use tor_hscrypto::pk::{HsIntroPtSessionIdKeypair, HsSvcNtorKeypair};
-use tor_linkspec::{HasRelayIds as _, RelayIds};
+use tor_hscrypto::pk::{
+    HsIntroPtSessionIdKey, HsIntroPtSessionIdKeypair, HsSvcNtorKey, HsSvcNtorKeypair,
+};
+use tor_linkspec::{HasAddrs as _, HasRelayIds, RelayId, RelayIds};
 use tor_llcrypto::pk::ed25519;
-use tor_netdir::NetDirProvider;
-use tor_rtcompat::Runtime;
+use tor_netdir::{DirEvent, NetDir, NetDirProvider, Relay, RelayWeight, SubnetConfig};
+use tor_rtcompat::timeout_track::{TrackingInstantOffsetNow, TrackingNow, Update as _};
+use tor_rtcompat::{Runtime, SleepProvider};
 
+use crate::config::IptPublicationStrategy;
 use crate::ipt_set::{self, IptsManagerView, PublishIptSet};
 use crate::keys::{IptKeyRole, IptKeySpecifier};
 use crate::replay::ReplayLog;
+use crate::status::{
+    send_event, IptFailureEvent, PublishedIptSetEvent, State as SvcState, StatusSender,
+};
+use crate::svc::netdir::NetDirProviderRx;
 use crate::svc::{ipt_establish, ShutdownStatus};
-use crate::timeout_track::{TrackingInstantOffsetNow, TrackingNow, Update as _};
+use crate::token_bucket::SharedTokenBucket;
 use crate::{FatalError, IptStoreError, StartupError};
 use crate::{HsNickname, IptLocalId, OnionServiceConfig, RendRequest};
 use ipt_establish::{IptEstablisher, IptParameters, IptStatus, IptStatusStatus, IptWantsToRetire};
@@ -54,7 +65,10 @@ use ipt_establish::{IptEstablisher, IptParameters, IptStatus, IptStatusStatus, I
 use IptStatusStatus as ISS;
 use TrackedStatus as TS;
 
+mod diagnostics;
 mod persist;
+use diagnostics::IptMgrDiagnostics;
+pub(crate) use diagnostics::IptMgrDiagnosticsHandle;
 use persist::IptStorageHandle;
 
 /// Expiry time to put on an interim descriptor (IPT publication set Uncertain)
@@ -64,6 +78,27 @@ const IPT_PUBLISH_UNCERTAIN: Duration = Duration::from_secs(30 * 60); // 30 mins
 // TODO HSS IPT_PUBLISH_CERTAIN configure? get from netdir?
 const IPT_PUBLISH_CERTAIN: Duration = Duration::from_secs(12 * 3600); // 12 hours
 
+/// How long to wait after receiving a configuration update before applying it
+///
+/// Any further updates received in the meantime are coalesced into the same application,
+/// so that a flurry of updates causes only one re-evaluation.
+const CONFIG_UPDATE_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// How many IPT failure events to buffer for each subscriber
+///
+/// A subscriber that falls further behind than this misses events.
+const IPT_FAILURE_EVENT_BUFFER: usize = 32;
+
+/// How many published IPT set events to buffer for each subscriber
+///
+/// A subscriber that falls further behind than this misses events.
+const PUBLISHED_IPT_SET_EVENT_BUFFER: usize = 16;
+
+/// Shared copy of the configuration the IPT manager is currently using
+///
+/// Replaced by the IPT manager each time it applies a configuration update.
+pub(crate) type AppliedConfigHandle = Arc<Mutex<Arc<OnionServiceConfig>>>;
+
 /// IPT Manager (for one hidden service)
 #[derive(Educe)]
 #[educe(Debug(bound))]
@@ -87,8 +122,11 @@ pub(crate) struct Immutable<R> {
     runtime: R,
 
     /// Netdir provider
+    ///
+    /// This can be replaced while we are running: use [`Immutable::dirprovider`]
+    /// to get the current one.
     #[educe(Debug(ignore))]
-    dirprovider: Arc<dyn NetDirProvider>,
+    dirprovider: NetDirProviderRx,
 
     /// Nickname
     nick: HsNickname,
@@ -98,6 +136,12 @@ pub(crate) struct Immutable<R> {
     /// Passed to IPT Establishers we create
     output_rend_reqs: mpsc::Sender<RendRequest>,
 
+    /// Rate limit on rendezvous requests, shared by all our IPT Establishers
+    ///
+    /// Made from the configuration when we start;
+    /// `rate_limit_rend_requests` cannot be changed while we are running.
+    rend_request_limit: Option<SharedTokenBucket>,
+
     /// Internal channel for updates from IPT Establishers (sender)
     ///
     /// When we make a new `IptEstablisher` we use this arrange for
@@ -112,6 +156,10 @@ pub(crate) struct Immutable<R> {
     #[educe(Debug(ignore))]
     keymgr: Arc<KeyMgr>,
 
+    /// Where we report our high-level state
+    #[educe(Debug(ignore))]
+    status_tx: StatusSender,
+
     /// Replay log directory
     ///
     /// Files are named after the (bare) IptLocalId
@@ -125,6 +173,78 @@ pub(crate) struct Immutable<R> {
     /// **Must have been locked** and this cannot be assured by the type system.
     #[educe(Debug(ignore))]
     replay_log_lock: Arc<LockFile>,
+
+    /// Our view of the monotonic clock, which never goes backwards
+    clock: MonotonicClock,
+}
+
+impl<R: Runtime> Immutable<R> {
+    /// Return the netdir provider we should currently use
+    fn dirprovider(&self) -> Arc<dyn NetDirProvider> {
+        Arc::clone(&self.dirprovider.borrow())
+    }
+
+    /// Return the current monotonic time
+    ///
+    /// Always use this, rather than `runtime.now()`: see [`MonotonicClock`].
+    fn now(&self) -> Instant {
+        self.clock.now(&self.runtime, &self.status_tx)
+    }
+}
+
+/// Monotonic clock readings, with detection of (and recovery from) regressions
+///
+/// A monotonic clock ought never to go backwards, but on some broken systems it does.
+/// When we see that happen, we log it, count it in our status
+/// (so that operators can diagnose the broken clock),
+/// and pretend that time stood still.
+#[derive(Debug, Default)]
+struct MonotonicClock {
+    /// The latest time we have returned
+    latest: Mutex<Option<Instant>>,
+}
+
+impl MonotonicClock {
+    /// Return the current time according to `runtime`, clamped so that it never goes backwards
+    fn now(&self, runtime: &impl SleepProvider, status_tx: &StatusSender) -> Instant {
+        self.clamp(runtime.now(), status_tx)
+    }
+
+    /// Return `now`, or the latest time we have previously returned, if that is later
+    ///
+    /// If we have to clamp `now`, reports the regression via `status_tx`.
+    fn clamp(&self, now: Instant, status_tx: &StatusSender) -> Instant {
+        let mut latest = self.latest.lock().expect("poisoned lock");
+        match *latest {
+            Some(prev) if now < prev => {
+                warn!(
+                    "monotonic clock went backwards by {}ms! (HS IPT)",
+                    (prev - now).as_millis()
+                );
+                status_tx.note_monotonic_clock_regression();
+                prev
+            }
+            _ => {
+                *latest = Some(now);
+                now
+            }
+        }
+    }
+}
+
+/// Configuration update(s) waiting to be applied
+#[derive(Debug)]
+struct PendingConfig {
+    /// The most recent configuration we received
+    config: Arc<OnionServiceConfig>,
+
+    /// When we should apply `config`
+    ///
+    /// This is [`CONFIG_UPDATE_DEBOUNCE`] after the first of the coalesced updates arrived.
+    apply_at: Instant,
+
+    /// How many updates have been coalesced into this one
+    n_coalesced: usize,
 }
 
 /// State of an IPT Manager
@@ -144,6 +264,54 @@ pub(crate) struct State<R, M> {
     /// with a mixture of old and new config.)
     current_config: Arc<OnionServiceConfig>,
 
+    /// Copy of `current_config`, shared via [`IptManager::applied_config`]
+    applied_config: AppliedConfigHandle,
+
+    /// Configuration update(s) we have received but not yet applied
+    ///
+    /// See [`CONFIG_UPDATE_DEBOUNCE`].
+    pending_config: Option<PendingConfig>,
+
+    /// Source of updates to the IPT relay blocklist
+    new_blocklists: watch::Receiver<Arc<[RelayId]>>,
+
+    /// Source of notifications that the netdir provider has been replaced
+    ///
+    /// The new provider is read via [`Immutable::dirprovider`].
+    new_dirproviders: NetDirProviderRx,
+
+    /// Relays which we must not use for IPTs
+    ///
+    /// Snapshot of the last update we received on `new_blocklists`.
+    /// We will not select any of these relays,
+    /// and we retire any IPTs we have at them.
+    blocklist: Arc<[RelayId]>,
+
+    /// Source of updates to the IPT relay scorer
+    #[educe(Debug(ignore))]
+    new_relay_scorers: watch::Receiver<Option<IptRelayScorer>>,
+
+    /// Scoring function used to bias IPT relay selection, if any
+    ///
+    /// Snapshot of the last update we received on `new_relay_scorers`.
+    #[educe(Debug(ignore))]
+    relay_scorer: Option<IptRelayScorer>,
+
+    /// Source of updates to the all-IPTs-faulty callback
+    #[educe(Debug(ignore))]
+    new_all_faulty_callbacks: watch::Receiver<Option<AllIptsFaultyCallback>>,
+
+    /// Function to call when all our IPTs have been faulty for too long, if any
+    ///
+    /// Snapshot of the last update we received on `new_all_faulty_callbacks`.
+    #[educe(Debug(ignore))]
+    all_faulty_callback: Option<AllIptsFaultyCallback>,
+
+    /// Whether (and since when) all our current IPTs have been faulty
+    ///
+    /// See [`OnionServiceConfig::ipt_all_faulty_timeout`].
+    all_faulty: Option<AllFaulty>,
+
     /// Channel for updates from IPT Establishers (receiver)
     ///
     /// We arrange for all the updates to be multiplexed,
@@ -161,9 +329,35 @@ pub(crate) struct State<R, M> {
     /// This can only be caused (or triggered) by a busted netdir or config.
     last_irelay_selection_outcome: Result<(), ()>,
 
+    /// When we last selected (or tried to select) new relays
+    ///
+    /// See [`OnionServiceConfig::min_ipt_reselect_interval`].
+    last_irelay_selection: Option<Instant>,
+
+    /// Consecutive failures to create an IPT because of a storage problem
+    storage_failures: StorageFailures,
+
     /// Signal for us to shut down
     shutdown: broadcast::Receiver<Void>,
 
+    /// Channel on which we report IPTs which have become faulty
+    ///
+    /// Subscribed to via [`IptManager::ipt_failure_events`].
+    #[educe(Debug(ignore))]
+    ipt_failure_tx: broadcast::Sender<IptFailureEvent>,
+
+    /// Channel on which we report changes to the set of IPTs we publish
+    ///
+    /// Subscribed to via [`IptManager::published_ipt_set_events`].
+    #[educe(Debug(ignore))]
+    published_ipts_tx: broadcast::Sender<PublishedIptSetEvent>,
+
+    /// Snapshot of our state, for diagnostics
+    ///
+    /// Replaced each time we have finished our work.
+    /// Shared via [`IptManager::diagnostics`].
+    diagnostics: IptMgrDiagnosticsHandle,
+
     /// Mockable state, normally [`Real`]
     ///
     /// This is in `State` so it can be passed mutably to tests,
@@ -175,6 +369,74 @@ pub(crate) struct State<R, M> {
     runtime: PhantomData<R>,
 }
 
+/// Record of a period during which all of our current IPTs have been faulty
+#[derive(Debug, Clone, Copy)]
+struct AllFaulty {
+    /// When we noticed that all our current IPTs were faulty
+    since: Instant,
+
+    /// Whether we have given up waiting, and escalated
+    ///
+    /// If so, we have reported the service as broken,
+    /// and we are selecting from more relays (see [`IptManager::max_n_intro_relays`]).
+    /// We stay escalated until one of our IPTs becomes good.
+    escalated: bool,
+}
+
+/// Record of consecutive storage failures when creating IPTs
+///
+/// See [`CreateIptError::Keystore`] and [`CreateIptError::OpenReplayLog`].
+#[derive(Debug, Default)]
+struct StorageFailures {
+    /// How many times in a row we have failed
+    ///
+    /// Reset to zero when we successfully create an IPT.
+    n_failures: u32,
+
+    /// When we may next try to create an IPT
+    ///
+    /// `None` if the last attempt succeeded.
+    retry_at: Option<Instant>,
+}
+
+/// Delay before retrying after the `n_failures`th consecutive storage failure
+///
+/// Starts off short, since many storage problems are transient,
+/// and doubles each time, up to a limit.
+fn storage_retry_delay(n_failures: u32) -> Duration {
+    /// Delay after the first failure
+    const STORAGE_RETRY_INITIAL: Duration = Duration::from_secs(1);
+    /// Maximum delay
+    const STORAGE_RETRY_MAX: Duration = Duration::from_secs(60);
+
+    let shift = n_failures.saturating_sub(1).min(16);
+    STORAGE_RETRY_INITIAL
+        .saturating_mul(1 << shift)
+        .min(STORAGE_RETRY_MAX)
+}
+
+/// When to next retry after `n_failures` consecutive storage failures, given that it is `now`
+///
+/// Returns `None` if that time can't be represented.
+fn storage_retry_at(now: Instant, n_failures: u32) -> Option<Instant> {
+    now.checked_add(storage_retry_delay(n_failures))
+}
+
+/// Has `min_ipt_reselect_interval` elapsed since we `last` selected new IPT relays?
+///
+/// If the time arithmetic overflows, the interval can never elapse.
+fn ipt_reselect_interval_elapsed(
+    now: &TrackingNow,
+    last: Option<Instant>,
+    min_ipt_reselect_interval: Duration,
+) -> bool {
+    let Some(last) = last else {
+        return true;
+    };
+    last.checked_add(min_ipt_reselect_interval)
+        .map_or(false, |next| *now >= next)
+}
+
 /// Mockable state in an IPT Manager - real version
 #[derive(Educe)]
 #[educe(Debug)]
@@ -203,6 +465,120 @@ struct IptRelay {
     ipts: Vec<Ipt>,
 }
 
+/// Is `relay` suitable for selection as a new IPT relay?
+///
+/// `existing` are the IPT relays we have already selected;
+/// we must not select any of them again.
+/// If `ipt_relay_diversity` is configured, we also reject relays which are in
+/// the same family or subnet as any of the `existing` ones that we can find in `netdir`.
+///
+/// Relays in `blocklist` are never usable.
+fn ipt_relay_usable(
+    config: &OnionServiceConfig,
+    netdir: &NetDir,
+    existing: &[IptRelay],
+    blocklist: &[RelayId],
+    relay: &Relay<'_>,
+) -> bool {
+    if !relay.is_hs_intro_point() {
+        return false;
+    }
+
+    if is_blocklisted(blocklist, relay) {
+        return false;
+    }
+
+    if config.ipt_require_ipv6 && !relay.addrs().iter().any(|addr| addr.is_ipv6()) {
+        return false;
+    }
+
+    // TODO HSS: perhaps we should use the subnet configuration from the circuit manager
+    let subnet_config = SubnetConfig::default();
+
+    !existing.iter().any(|existing| {
+        if relay.has_any_relay_id_from(&existing.relay) {
+            return true;
+        }
+
+        if !config.ipt_relay_diversity {
+            return false;
+        }
+
+        // If an existing relay has vanished from the netdir, we can't tell
+        // whether it's related to `relay`; we don't let that stop us.
+        netdir.by_ids(&existing.relay).map_or(false, |existing| {
+            relay.in_same_family(&existing) || relay.in_same_subnet(&existing, &subnet_config)
+        })
+    })
+}
+
+/// Is `relay` in `blocklist` ?
+///
+/// A relay is blocklisted if any one of its identities is listed.
+fn is_blocklisted(blocklist: &[RelayId], relay: &impl HasRelayIds) -> bool {
+    blocklist.iter().any(|id| relay.has_identity(id.as_ref()))
+}
+
+/// The local identifiers of the IPTs in `publish_set`
+///
+/// Empty if we aren't publishing any.
+fn published_lids(publish_set: &PublishIptSet) -> Vec<IptLocalId> {
+    publish_set
+        .ipts
+        .iter()
+        .flat_map(|ipts| ipts.ipts.iter().map(|ipt| ipt.lid))
+        .collect()
+}
+
+/// A caller-supplied scoring function for candidate introduction point relays
+///
+/// When choosing a relay for a new introduction point,
+/// each usable relay's standard `HsIntro` selection weight is multiplied by its score.
+/// So a relay with score `2.0` is twice as likely to be chosen as it would otherwise be,
+/// and a relay with score `0.0` is never chosen.
+///
+/// Scores should be finite.  Negative (and NaN) scores are treated as zero.
+///
+/// See [`OnionService::set_ipt_relay_scorer`](crate::OnionService::set_ipt_relay_scorer).
+pub type IptRelayScorer = Arc<dyn Fn(&Relay<'_>) -> f64 + Send + Sync>;
+
+/// A caller-supplied function to call when all of a service's introduction points are faulty
+///
+/// This is called (once) when all of our introduction points have been faulty for
+/// [`ipt_all_faulty_timeout`](crate::config::OnionServiceConfigBuilder::ipt_all_faulty_timeout).
+/// It is called again only after one of them has recovered, and they have all failed again.
+///
+/// It is called from the introduction point manager's task, so it must not block.
+///
+/// See [`OnionService::set_all_ipts_faulty_callback`](crate::OnionService::set_all_ipts_faulty_callback).
+pub type AllIptsFaultyCallback = Arc<dyn Fn() + Send + Sync>;
+
+/// Choose a relay from `netdir`, weighting the `HsIntro` weights by `scorer`
+///
+/// Only relays for which `usable` returns true are considered.
+/// Returns `None` if no usable relay has a nonzero combined weight.
+fn pick_scored_relay<'a>(
+    netdir: &'a NetDir,
+    rng: &mut impl Rng,
+    usable: impl FnMut(&Relay<'a>) -> bool,
+    scorer: &IptRelayScorer,
+) -> Option<Relay<'a>> {
+    let role = tor_netdir::WeightRole::HsIntro;
+    let relays = netdir.relays().filter(usable).collect_vec();
+    let total: RelayWeight = relays.iter().map(|r| netdir.relay_weight(r, role)).sum();
+    relays
+        .choose_weighted(rng, |r| {
+            let base = netdir
+                .relay_weight(r, role)
+                .checked_div(total)
+                .unwrap_or(0.);
+            // f64::max returns the non-NaN argument, so this also turns NaN into zero
+            base * scorer(r).max(0.)
+        })
+        .ok()
+        .cloned()
+}
+
 /// Type-erased version of `Box<IptEstablisher>`
 ///
 /// The real type is `M::IptEstablisher`.
@@ -232,6 +608,16 @@ struct Ipt {
     /// Last information about how it's doing including timing info
     status_last: TrackedStatus,
 
+    /// When this IPT stopped being Good, if it has since been Good
+    ///
+    /// If we have published this IPT, then while this is within the
+    /// configured `ipt_fault_grace_period`, we carry on publishing it,
+    /// with the details it had when it was Good:
+    /// see [`Ipt::in_fault_grace_period`].
+    ///
+    /// Cleared when the IPT becomes Good again.
+    lapsed: Option<LapsedIpt>,
+
     /// Until when ought we to try to maintain it
     ///
     /// For introduction points we are publishing,
@@ -259,6 +645,16 @@ struct Ipt {
     is_current: Option<IsCurrent>,
 }
 
+/// Record of an IPT which has stopped being Good
+#[derive(Debug)]
+struct LapsedIpt {
+    /// When it stopped being Good
+    since: Instant,
+
+    /// Its details, from when it was Good
+    details: ipt_establish::GoodIptDetails,
+}
+
 /// Last information from establisher about an IPT, with timing info added by us
 #[derive(Debug)]
 enum TrackedStatus {
@@ -285,6 +681,9 @@ enum TrackedStatus {
         /// Can only be `Err` in strange situations.
         time_to_establish: Result<Duration, ()>,
 
+        /// How many times the establisher says this IPT has become faulty
+        n_faults: u32,
+
         /// Details, from the Establisher
         details: ipt_establish::GoodIptDetails,
     },
@@ -321,9 +720,10 @@ impl IptRelay {
 
     /// Should this IPT Relay be retired ?
     ///
-    /// This is determined by our IPT relay rotation time.
+    /// This is determined by our IPT relay rotation time,
+    /// which we may also bring forward to retire surplus IPTs.
     fn should_retire(&self, now: &TrackingNow) -> bool {
-        now > &self.planned_retirement
+        now >= &self.planned_retirement
     }
 
     /// Make a new introduction point at this relay
@@ -344,7 +744,7 @@ impl IptRelay {
             &self.relay,
             lid,
             Some(IsCurrent),
-            None::<IptExpectExistingKeys>,
+            None::<&IptExpectExistingKeys>,
             // None is precisely right: the descriptor hasn't been published.
             PromiseLastDescriptorExpiryNoneIsGood {},
         )?;
@@ -362,8 +762,24 @@ impl IptRelay {
 struct PromiseLastDescriptorExpiryNoneIsGood {}
 
 /// Token telling [`Ipt::start_establisher`] to expect existing keys in the keystore
-#[derive(Debug, Clone, Copy)]
-struct IptExpectExistingKeys;
+#[derive(Debug, Clone)]
+struct IptExpectExistingKeys {
+    /// The public keys we recorded for this IPT, if we know them
+    ///
+    /// If present, the keys we load must match these:
+    /// otherwise, the keystore has been corrupted (or swapped),
+    /// and we might be about to serve the wrong keys.
+    public_keys: Option<IptPublicKeys>,
+}
+
+/// The public halves of an IPT's keys
+#[derive(Debug, Clone)]
+struct IptPublicKeys {
+    /// `KP_hss_ntor`
+    k_hss_ntor: HsSvcNtorKey,
+    /// `KP_hs_ipt_sid`
+    k_sid: HsIntroPtSessionIdKey,
+}
 
 impl Ipt {
     /// Start a new IPT establisher, and create and return an `Ipt`
@@ -375,17 +791,20 @@ impl Ipt {
         relay: &RelayIds,
         lid: IptLocalId,
         is_current: Option<IsCurrent>,
-        expect_existing_keys: Option<IptExpectExistingKeys>,
+        expect_existing_keys: Option<&IptExpectExistingKeys>,
         _: PromiseLastDescriptorExpiryNoneIsGood,
     ) -> Result<Ipt, CreateIptError> {
         let mut rng = mockable.thread_rng();
+        // The keystore can't be changed by reconfiguration, so it doesn't matter
+        // which version of the config we look at.
+        let config = Arc::clone(&new_configs.borrow());
 
         /// Load (from disk) or generate an IPT key with role IptKeyRole::$role
         ///
         /// Ideally this would be a closure, but it has to be generic over the
         /// returned key type.  So it's a macro.  (A proper function would have
         /// many type parameters and arguments and be quite annoying.)
-        macro_rules! get_or_gen_key { { $Keypair:ty, $role:ident } => { (||{
+        macro_rules! get_or_gen_key { { $Keypair:ty, $role:ident, $public:ident } => { (||{
             let spec = IptKeySpecifier {
                 nick: imm.nick.clone(),
                 role: IptKeyRole::$role,
@@ -403,10 +822,18 @@ impl Ipt {
             //     this IPT exists.  But this could happen due to file deletion or something.
             //     And we could recover by creating fresh keys, although maybe some clients
             //     would find the previous keys in old descriptors.
-            //     So if the keys are missing, make and store new ones, logging an error msg.
+            //     So if the keys are missing, make and store new ones, logging an error msg;
+            //     unless the config says we mustn't (strict_ipt_keys), in which case, crash.
+            //     If we recorded the public key, the key we load must match it.
             // TODO HSS See #1074: The current keymgr API doesn't make this easy
             // Tidy this code up when the API is better.
-            let k: Option<$Keypair> = imm.keymgr.get(&spec)?;
+            let expected_public = expect_existing_keys
+                .and_then(|expect| expect.public_keys.as_ref())
+                .map(|public_keys| &public_keys.$public);
+            let k: Option<$Keypair> = match expected_public {
+                Some(expected_public) => imm.keymgr.get_checked(&spec, expected_public)?,
+                None => imm.keymgr.get(&spec)?,
+            };
             let arti_path = || {
                 spec
                     .arti_path()
@@ -422,6 +849,9 @@ impl Ipt {
                     return Err(FatalError::IptKeysFoundUnexpectedly(arti_path()?).into())
                 },
                 (Some(_), None) => {
+                    if config.strict_ipt_keys {
+                        return Err(FatalError::IptKeysMissing(arti_path()?).into());
+                    }
                     error!("HS service {} missing previous key {:?}, regenerating",
                            &imm.nick, arti_path()?);
                 }
@@ -430,21 +860,19 @@ impl Ipt {
                 // TODO HSS get_or_generate is strictly speaking a bit wrong here, see above
                 imm.keymgr.get_or_generate(
                     &spec,
-                    tor_keymgr::KeystoreSelector::Default,
+                    config.keystore_selector(),
                     &mut rng,
                 )
             })?;
             Ok::<_, CreateIptError>(Arc::new(k))
         })() } }
 
-        let k_hss_ntor = get_or_gen_key!(HsSvcNtorKeypair, KHssNtor)?;
-        let k_sid = get_or_gen_key!(HsIntroPtSessionIdKeypair, KSid)?;
+        let k_hss_ntor = get_or_gen_key!(HsSvcNtorKeypair, KHssNtor, k_hss_ntor)?;
+        let k_sid = get_or_gen_key!(HsIntroPtSessionIdKeypair, KSid, k_sid)?;
         drop(rng);
 
         // we'll treat it as Establishing until we find otherwise
-        let status_last = TS::Establishing {
-            started: imm.runtime.now(),
-        };
+        let status_last = TS::Establishing { started: imm.now() };
 
         // TODO HSS: Support ephemeral services (without persistent replay log)
         let replay_log = {
@@ -461,25 +889,39 @@ impl Ipt {
         let params = IptParameters {
             replay_log,
             config_rx: new_configs.clone(),
-            netdir_provider: imm.dirprovider.clone(),
+            // TODO HSS: if the netdir provider is replaced, existing establishers
+            // keep using the one they were started with.
+            netdir_provider: imm.dirprovider(),
             introduce_tx: imm.output_rend_reqs.clone(),
             lid,
             target: relay.clone(),
             k_sid: k_sid.clone(),
             k_ntor: Arc::clone(&k_hss_ntor),
             accepting_requests: ipt_establish::RequestDisposition::NotAdvertised,
+            rend_request_limit: imm.rend_request_limit.clone(),
         };
         let (establisher, mut watch_rx) = mockable.make_new_ipt(imm, params)?;
 
+        // We forward the establisher's status updates to the manager.
+        //
+        // `watch_rx` only ever gives us the latest status, so updates that arrive
+        // while we are waiting for the manager to accept the previous one are coalesced.
+        // We also drop updates that don't change anything, so that an establisher
+        // which keeps reporting the same status doesn't make the manager do needless work.
         imm.runtime
             .spawn({
                 let mut status_send = imm.status_send.clone();
                 async move {
+                    let mut last_sent = None;
                     loop {
                         let Some(status) = watch_rx.next().await else {
                             trace!("HS service IPT status task: establisher went away");
                             break;
                         };
+                        if last_sent.as_ref() == Some(&status) {
+                            continue;
+                        }
+                        last_sent = Some(status.clone());
                         match status_send.send((lid, status)).await {
                             Ok(()) => {}
                             Err::<_, mpsc::SendError>(e) => {
@@ -502,6 +944,7 @@ impl Ipt {
             k_hss_ntor,
             k_sid,
             status_last,
+            lapsed: None,
             is_current,
             last_descriptor_expiry_including_slop: None,
         };
@@ -527,6 +970,65 @@ impl Ipt {
         }
     }
 
+    /// Returns `true` if this IPT is Good or Establishing
+    ///
+    /// We optimistically count an Establishing IPT as good-ish,
+    /// when deciding whether we have enough (or too many) IPTs.
+    fn is_good_ish(&self) -> bool {
+        match self.status_last {
+            TS::Good { .. } | TS::Establishing { .. } => true,
+            TS::Faulty { .. } => false,
+        }
+    }
+
+    /// Returns `true` if this IPT recently stopped being Good, but we should still publish it
+    ///
+    /// That is, if we have published it, and it stopped being Good less than `grace` ago.
+    fn in_fault_grace_period(&self, now: &TrackingNow, grace: Duration) -> bool {
+        let Some(lapsed) = &self.lapsed else {
+            return false;
+        };
+        if self.last_descriptor_expiry_including_slop.is_none() {
+            // Never published, so there's nothing to keep stable.
+            return false;
+        }
+        match lapsed.since.checked_add(grace) {
+            Some(until) => now < &until,
+            // On time overflow, the grace period never ends.
+            None => true,
+        }
+    }
+
+    /// Returns `true` if this IPT is Good, or in its fault grace period
+    ///
+    /// See [`in_fault_grace_period`](Self::in_fault_grace_period).
+    fn is_publishable(&self, now: &TrackingNow, grace: Duration) -> bool {
+        self.is_good() || self.in_fault_grace_period(now, grace)
+    }
+
+    /// Returns how many times this IPT has become faulty, if it is Good
+    ///
+    /// For use as a sort key: IPTs that aren't Good are treated as the worst.
+    fn n_faults_if_good(&self) -> u32 {
+        match self.status_last {
+            TS::Good { n_faults, .. } => n_faults,
+            TS::Establishing { .. } | TS::Faulty { .. } => u32::MAX,
+        }
+    }
+
+    /// Returns how long this IPT took to establish, if it is Good and we know
+    ///
+    /// For use as a sort key: if we don't know, this is treated as the worst.
+    fn time_to_establish_if_good(&self) -> Duration {
+        match self.status_last {
+            TS::Good {
+                time_to_establish: Ok(time_to_establish),
+                ..
+            } => time_to_establish,
+            TS::Good { .. } | TS::Establishing { .. } | TS::Faulty { .. } => Duration::MAX,
+        }
+    }
+
     /// Construct the information needed by the publisher for this intro point
     fn for_publish(&self, details: &ipt_establish::GoodIptDetails) -> Result<ipt_set::Ipt, Bug> {
         let k_sid: &ed25519::Keypair = (*self.k_sid).as_ref();
@@ -546,14 +1048,18 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
     #[allow(clippy::too_many_arguments)] // TODO HSS
     pub(crate) fn new(
         runtime: R,
-        dirprovider: Arc<dyn NetDirProvider>,
+        dirprovider: NetDirProviderRx,
         nick: HsNickname,
         config: watch::Receiver<Arc<OnionServiceConfig>>,
+        blocklist: watch::Receiver<Arc<[RelayId]>>,
+        relay_scorer: watch::Receiver<Option<IptRelayScorer>>,
+        all_faulty_callback: watch::Receiver<Option<AllIptsFaultyCallback>>,
         output_rend_reqs: mpsc::Sender<RendRequest>,
         shutdown: broadcast::Receiver<Void>,
         storage: impl tor_persist::StateMgr + Send + Sync + 'static,
         mockable: M,
         keymgr: Arc<KeyMgr>,
+        status_tx: StatusSender,
         state_dir: &Path,
         state_mistrust: &fs_mistrust::Mistrust,
     ) -> Result<Self, StartupError> {
@@ -567,7 +1073,7 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
 
         let (replay_log_dir, replay_log_lock) = {
             // TODO HSS something should expire these! (and our keys too, obviously)
-            let dir = state_dir.join(format!("hss_iptreplay/{nick}"));
+            let dir = config.borrow().replay_log_dir(state_dir);
             let dir = state_mistrust
                 .verifier()
                 .make_secure_dir(dir)
@@ -597,27 +1103,58 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
             (dir, lock)
         };
 
+        let rend_request_limit = config
+            .borrow()
+            .rate_limit_rend_requests
+            .as_ref()
+            .map(|limit| SharedTokenBucket::new(&runtime, limit));
+
+        let new_dirproviders = dirprovider.clone();
         let imm = Immutable {
             runtime,
             dirprovider,
             nick,
             status_send,
             output_rend_reqs,
+            rend_request_limit,
             keymgr,
+            status_tx,
             storage,
             replay_log_dir,
             replay_log_lock,
+            clock: MonotonicClock::default(),
         };
         let current_config = config.borrow().clone();
+        let applied_config = Arc::new(Mutex::new(Arc::clone(&current_config)));
+        let current_blocklist = blocklist.borrow().clone();
+        let current_relay_scorer = relay_scorer.borrow().clone();
+        let current_all_faulty_callback = all_faulty_callback.borrow().clone();
+        let (ipt_failure_tx, _) = broadcast::channel(IPT_FAILURE_EVENT_BUFFER);
+        let (published_ipts_tx, _) = broadcast::channel(PUBLISHED_IPT_SET_EVENT_BUFFER);
 
         let state = State {
             current_config,
+            applied_config,
+            pending_config: None,
             new_configs: config,
+            blocklist: current_blocklist,
+            new_blocklists: blocklist,
+            relay_scorer: current_relay_scorer,
+            new_relay_scorers: relay_scorer,
+            all_faulty_callback: current_all_faulty_callback,
+            new_all_faulty_callbacks: all_faulty_callback,
+            all_faulty: None,
+            new_dirproviders,
             status_recv,
             mockable,
             shutdown,
+            ipt_failure_tx,
+            published_ipts_tx,
+            diagnostics: Default::default(),
             irelays,
             last_irelay_selection_outcome: Ok(()),
+            last_irelay_selection: None,
+            storage_failures: StorageFailures::default(),
             runtime: PhantomData,
         };
         let mgr = IptManager { imm, state };
@@ -625,6 +1162,35 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
         Ok(mgr)
     }
 
+    /// Return the channel on which we report IPTs that have become faulty
+    ///
+    /// Callers subscribe to it to obtain an [`IptFailureEventStream`](crate::status::IptFailureEventStream);
+    /// they can do so even after we are launched.
+    pub(crate) fn ipt_failure_sender(&self) -> broadcast::Sender<IptFailureEvent> {
+        self.state.ipt_failure_tx.clone()
+    }
+
+    /// Return the channel on which we report changes to the set of IPTs we publish
+    ///
+    /// Callers subscribe to it to obtain a [`PublishedIptSetEventStream`](crate::status::PublishedIptSetEventStream);
+    /// they can do so even after we are launched.
+    pub(crate) fn published_ipt_set_sender(&self) -> broadcast::Sender<PublishedIptSetEvent> {
+        self.state.published_ipts_tx.clone()
+    }
+
+    /// Return a handle to a snapshot of our state, for diagnostics
+    pub(crate) fn diagnostics(&self) -> IptMgrDiagnosticsHandle {
+        Arc::clone(&self.state.diagnostics)
+    }
+
+    /// Return a handle to the configuration we are currently using
+    ///
+    /// This is only updated when we apply a configuration update,
+    /// which is some time after it is sent to us: see [`CONFIG_UPDATE_DEBOUNCE`].
+    pub(crate) fn applied_config(&self) -> AppliedConfigHandle {
+        Arc::clone(&self.state.applied_config)
+    }
+
     /// Send the IPT manager off to run and establish intro points
     pub(crate) fn launch_background_tasks(
         mut self,
@@ -639,6 +1205,13 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
             &mut self.state.mockable,
             &publisher.borrow_for_read(),
         )?;
+        // A scorer may have been set since we were created;
+        // we want to use it for our very first relay selections.
+        self.state.relay_scorer = self.state.new_relay_scorers.borrow().clone();
+
+        self.imm
+            .status_tx
+            .maybe_update_ipt_mgr(SvcState::Bootstrapping);
 
         let runtime = self.imm.runtime.clone();
         runtime
@@ -737,24 +1310,25 @@ impl<R: Runtime, M: Mockable<R>> State<R, M> {
         imm: &Immutable<R>,
         now: Instant,
     ) -> Result<(), ChooseIptError> {
-        let netdir = imm.dirprovider.timely_netdir()?;
+        let netdir = imm.dirprovider().timely_netdir()?;
 
         let mut rng = self.mockable.thread_rng();
 
-        let relay = netdir
-            .pick_relay(
-                &mut rng,
-                tor_netdir::WeightRole::HsIntro,
-                // TODO HSS should we apply any other conditions to the selected IPT?
-                |new| {
-                    new.is_hs_intro_point()
-                        && !self
-                            .irelays
-                            .iter()
-                            .any(|existing| new.has_any_relay_id_from(&existing.relay))
-                },
+        // TODO HSS should we apply any other conditions to the selected IPT?
+        let usable = |new: &Relay<'_>| {
+            ipt_relay_usable(
+                &self.current_config,
+                &netdir,
+                &self.irelays,
+                &self.blocklist,
+                new,
             )
-            .ok_or(ChooseIptError::TooFewUsableRelays)?;
+        };
+        let relay = match &self.relay_scorer {
+            None => netdir.pick_relay(&mut rng, tor_netdir::WeightRole::HsIntro, usable),
+            Some(scorer) => pick_scored_relay(&netdir, &mut rng, usable, scorer),
+        }
+        .ok_or(ChooseIptError::TooFewUsableRelays)?;
 
         let retirement = rng
             .gen_range_checked(self.current_config.ipt_relay_rotation_time())
@@ -791,16 +1365,33 @@ impl<R: Runtime, M: Mockable<R>> State<R, M> {
         let IptStatus {
             status: update,
             wants_to_retire,
-            n_faults: _,
+            n_faults,
         } = update;
 
+        // We only report the transition into Faulty, not repeated Faulty updates.
+        let became_faulty =
+            matches!(update, ISS::Faulty) && !matches!(ipt.status_last, TS::Faulty { .. });
+
         #[allow(clippy::single_match)] // want to be explicit about the Ok type
         match wants_to_retire {
             Err(IptWantsToRetire) => ipt.is_current = None,
             Ok(()) => {}
         }
 
-        let now = || imm.runtime.now();
+        let now = || imm.now();
+
+        // Remember when this IPT stopped being Good, so that if we were publishing it,
+        // we can carry on publishing it for a while: see `ipt_fault_grace_period`.
+        match (&ipt.status_last, &update) {
+            (TS::Good { details, .. }, ISS::Establishing | ISS::Faulty) => {
+                ipt.lapsed = Some(LapsedIpt {
+                    since: now(),
+                    details: details.clone(),
+                });
+            }
+            (_, ISS::Good(_)) => ipt.lapsed = None,
+            _ => {}
+        }
 
         let started = match &ipt.status_last {
             TS::Establishing { started, .. } => Ok(*started),
@@ -814,20 +1405,41 @@ impl<R: Runtime, M: Mockable<R>> State<R, M> {
             },
             ISS::Good(details) => {
                 let time_to_establish = started.and_then(|started| {
-                    // return () at end of ok_or_else closure, for clarity
-                    #[allow(clippy::unused_unit, clippy::semicolon_if_nothing_returned)]
-                    now().checked_duration_since(started).ok_or_else(|| {
-                        warn!("monotonic clock went backwards! (HS IPT)");
-                        ()
-                    })
+                    // `imm.now()` never goes backwards, so this can only fail if
+                    // `started` somehow came from elsewhere.
+                    now().checked_duration_since(started).ok_or(())
                 });
                 TS::Good {
                     time_to_establish,
+                    n_faults,
                     details,
                 }
             }
             ISS::Faulty => TS::Faulty { started },
         };
+
+        if became_faulty {
+            let Some(ir) = self
+                .irelays
+                .iter()
+                .find(|ir| ir.ipts.iter().any(|ipt| ipt.lid == lid))
+            else {
+                // We found this IPT above, so this can't happen.
+                return;
+            };
+
+            info!(
+                "HS service {}: IPT {lid} at relay {} became faulty (n_faults={n_faults})",
+                &imm.nick,
+                ir.relay.display_relay_ids()
+            );
+            send_event(
+                &mut self.ipt_failure_tx,
+                IptFailureEvent::new(lid, ir.relay.clone(), n_faults),
+                &imm.nick,
+                "IPT failure",
+            );
+        }
     }
 }
 
@@ -906,7 +1518,13 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
         // This tracks everything we compare it to, using interior mutability,
         // so that if there is no work to do and no timeouts have expired,
         // we know when we will want to wake up.
-        let now = TrackingNow::now(&self.imm.runtime);
+        let now = TrackingNow::new(self.imm.now(), self.imm.runtime.wallclock());
+
+        // ---------- apply configuration updates ----------
+
+        if self.apply_pending_config(&now) {
+            return CONTINUE;
+        }
 
         // ---------- collect garbage ----------
 
@@ -922,6 +1540,32 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
             }
         }
 
+        // Retire a surplus IPT if we have more good-ish IPTs than we want
+        // (for example, because num_intro_points has been reduced).
+        // A retired IPT that we have published is kept until the last descriptor
+        // mentioning it has expired: see "Forget old IPTs", below.
+        if self.retire_surplus_ipt(&now) {
+            return CONTINUE;
+        }
+
+        // Retire the IPTs at any relays that have been blocklisted
+        for ir in &mut self.state.irelays {
+            if !is_blocklisted(&self.state.blocklist, &ir.relay) {
+                continue;
+            }
+            if let Some(lid) = ir.current_ipt().map(|ipt| ipt.lid) {
+                info!(
+                    "HS service {}: retiring IPT {lid} at blocklisted relay {}",
+                    &self.imm.nick,
+                    ir.relay.display_relay_ids()
+                );
+                if let Some(ipt) = ir.current_ipt_mut() {
+                    ipt.is_current = None;
+                }
+                return CONTINUE;
+            }
+        }
+
         // Forget old IPTs (after the last descriptor mentioning them has expired)
         for ir in &mut self.state.irelays {
             // When we drop the Ipt we drop the IptEstablisher, withdrawing the intro point
@@ -936,40 +1580,75 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
             // by discarding a non-current IPT.
         }
 
-        // Forget retired IPT relays (all their IPTs are gone)
-        self.state
-            .irelays
-            .retain(|ir| !(ir.should_retire(&now) && ir.ipts.is_empty()));
+        // Forget retired or blocklisted IPT relays (all their IPTs are gone)
+        let blocklist = &self.state.blocklist;
+        self.state.irelays.retain(|ir| {
+            !((ir.should_retire(&now) || is_blocklisted(blocklist, &ir.relay))
+                && ir.ipts.is_empty())
+        });
         // If we deleted relays, we might want to select new ones.  That happens below.
 
+        // ---------- check health ----------
+
+        // If all our IPTs have been faulty for too long, we report the service as broken,
+        // and widen our relay search.
+        if self.check_all_faulty(&now) {
+            return CONTINUE;
+        }
+
         // ---------- make progress ----------
         //
         // Consider selecting new relays and setting up new IPTs.
 
+        // We start up to this many new IPTs (or select up to this many new relays)
+        // before returning CONTINUE.
+        let establish_concurrency =
+            usize::from(self.state.current_config.ipt_establish_concurrency.max(1));
+
         // Create new IPTs at already-chosen relays
+        let storage_retry_ok = match self.state.storage_failures.retry_at {
+            None => true,
+            Some(retry_at) => now >= retry_at,
+        };
+        let mut n_new_ipts = 0;
+        let mut storage_error = None;
         for ir in &mut self.state.irelays {
-            if !ir.should_retire(&now) && ir.current_ipt_mut().is_none() {
+            if storage_retry_ok
+                && !ir.should_retire(&now)
+                && !is_blocklisted(&self.state.blocklist, &ir.relay)
+                && ir.current_ipt_mut().is_none()
+            {
                 // We don't have a current IPT at this relay, but we should.
                 match ir.make_new_ipt(&self.imm, &self.state.new_configs, &mut self.state.mockable)
                 {
-                    Ok(()) => return CONTINUE,
+                    Ok(()) => {
+                        n_new_ipts += 1;
+                        if n_new_ipts >= establish_concurrency {
+                            break;
+                        }
+                    }
                     Err(CreateIptError::Fatal(fatal)) => return Err(fatal),
                     Err(
                         e @ (CreateIptError::Keystore(_) | CreateIptError::OpenReplayLog { .. }),
                     ) => {
-                        error_report!(e, "HS {}: failed to prepare new IPT", &self.imm.nick);
-                        // Let's not try any more of this.
-                        // We'll run the rest of our "make progress" algorithms,
-                        // presenting them with possibly-suboptimal state.  That's fine.
-                        // At some point we'll be poked to run again and then we'll retry.
-                        /// Retry no later than this:
-                        const STORAGE_RETRY: Duration = Duration::from_secs(60);
-                        now.update(STORAGE_RETRY);
+                        storage_error = Some(e);
                         break;
                     }
                 }
             }
         }
+        if n_new_ipts > 0 {
+            self.note_storage_success();
+        }
+        if let Some(e) = storage_error {
+            // Let's not try any more of this until the retry delay has elapsed.
+            // We'll run the rest of our "make progress" algorithms,
+            // presenting them with possibly-suboptimal state.  That's fine.
+            self.note_storage_failure(&e, &now);
+        }
+        if n_new_ipts > 0 {
+            return CONTINUE;
+        }
 
         // Consider choosing a new IPT relay
         {
@@ -980,17 +1659,27 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
             // relay because we don't have enough good-looking ones.
             let n_good_ish_relays = self
                 .current_ipts()
-                .filter(|(_ir, ipt)| match ipt.status_last {
-                    TS::Good { .. } | TS::Establishing { .. } => true,
-                    TS::Faulty { .. } => false,
-                })
+                .filter(|(_ir, ipt)| ipt.is_good_ish())
                 .count();
 
+            // The relays we select here don't have IPTs yet, so we count them separately.
+            let mut n_new_relays = 0;
+
             #[allow(clippy::unused_unit, clippy::semicolon_if_nothing_returned)] // in map_err
-            if n_good_ish_relays < self.target_n_intro_points()
+            while n_good_ish_relays + n_new_relays
+                < self.target_n_intro_points() + self.n_standby_intro_points()
                 && self.state.irelays.len() < self.max_n_intro_relays()
                 && self.state.last_irelay_selection_outcome.is_ok()
+                && n_new_relays < establish_concurrency
+                // Don't select relays more often than the config permits.
+                // (Checked last, so that we only arrange to wake up if we want a relay.)
+                && ipt_reselect_interval_elapsed(
+                    &now,
+                    self.state.last_irelay_selection,
+                    self.state.current_config.min_ipt_reselect_interval,
+                )
             {
+                n_new_relays += 1;
                 self.state.last_irelay_selection_outcome = self
                     .state
                     .choose_new_ipt_relay(&self.imm, now.instant().get_now_untracked())
@@ -1011,6 +1700,9 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
                         };
                         ()
                     });
+            }
+            if n_new_relays > 0 {
+                self.state.last_irelay_selection = Some(now.instant().get_now_untracked());
                 return CONTINUE;
             }
         }
@@ -1212,7 +1904,13 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
             Some((lid, wait_more))
         };
 
-        let n_good_ipts = self.good_ipts().count();
+        // IPTs in their fault grace period count as good here,
+        // so that a brief outage doesn't change what we publish.
+        let grace = self.state.current_config.ipt_fault_grace_period;
+        let n_good_ipts = self
+            .current_ipts()
+            .filter(|(_ir, ipt)| ipt.is_publishable(now, grace))
+            .count();
         let publish_lifetime = if n_good_ipts >= self.target_n_intro_points() {
             // "Certain" - we are sure of which IPTs we want to publish
             debug!(
@@ -1222,12 +1920,19 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
                 self.target_n_intro_points()
             );
             Some(IPT_PUBLISH_CERTAIN)
-        } else if self.good_ipts().next().is_none()
-        /* !... .is_empty() */
-        {
+        } else if n_good_ipts == 0 {
             // "Unknown" - we have no idea which IPTs to publish.
             debug!("HS service {}: no good IPTs", &self.imm.nick);
             None
+        } else if n_good_ipts < self.min_ipts_before_publish() {
+            // "Unknown" - we have some IPTs we could publish, but too few to be worth it.
+            debug!(
+                "HS service {}: {} good IPTs, < minimum {}, not publishing",
+                &self.imm.nick,
+                n_good_ipts,
+                self.min_ipts_before_publish()
+            );
+            None
         } else if let Some((wait_for, wait_more)) = started_establishing_very_recently() {
             // "Unknown" - we say have no idea which IPTs to publish:
             // although we have *some* idea, we hold off a bit to see if things improve.
@@ -1253,15 +1958,18 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
             Some(IPT_PUBLISH_UNCERTAIN)
         };
 
+        let old_lids = published_lids(publish_set);
         publish_set.ipts = if let Some(lifetime) = publish_lifetime {
-            let selected = self.publish_set_select();
+            let selected = self.publish_set_select(now);
             for ipt in &selected {
                 self.state.mockable.start_accepting(&*ipt.establisher);
             }
-            Some(Self::make_publish_set(selected, lifetime)?)
+            let expiry_slop = self.state.current_config.ipt_publish_expiry_slop;
+            Some(Self::make_publish_set(selected, lifetime, expiry_slop)?)
         } else {
             None
         };
+        self.note_published_ipts(&old_lids, publish_set);
 
         //---------- store persistent state ----------
 
@@ -1270,25 +1978,55 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
         Ok(())
     }
 
+    /// Report a change to the set of IPTs we publish, if there was one
+    ///
+    /// `old_lids` are the IPTs that were in `publish_set` before we updated it.
+    fn note_published_ipts(&mut self, old_lids: &[IptLocalId], publish_set: &PublishIptSet) {
+        let new_lids = published_lids(publish_set);
+        if new_lids == old_lids {
+            return;
+        }
+
+        debug!(
+            "HS service {}: now publishing {} IPTs",
+            &self.imm.nick,
+            new_lids.len()
+        );
+        send_event(
+            &mut self.state.published_ipts_tx,
+            PublishedIptSetEvent::new(new_lids),
+            &self.imm.nick,
+            "published IPT set",
+        );
+    }
+
     /// Select IPTs to publish, given that we have decided to publish *something*
     ///
     /// Calculates set of ipts to publish, selecting up to the target `N`
     /// from the available good current IPTs.
     /// (Old, non-current IPTs, that we are trying to retire, are never published.)
+    /// IPTs in their fault grace period are also available,
+    /// but are the least preferred.
     ///
-    /// The returned list is in the same order as our data structure:
+    /// The candidates are ordered according to the configured
+    /// [`IptPublicationStrategy`], and the last `N` are selected.
+    /// The starting point is the order of our data structure:
     /// firstly, by the ordering in `State.irelays`, and then within each relay,
-    /// by the ordering in `IptRelay.ipts`.  Both of these are stable.
+    /// by the ordering in `IptRelay.ipts`.  Both of these are stable,
+    /// and so are the sorts we do, so ties are broken by this order.
+    /// (If we are maintaining warm standby IPTs,
+    /// the ones we have already published come last, regardless of the strategy.)
     ///
     /// ### Performance
     ///
     /// This function is at worst O(N) where N is the number of IPTs.
     /// See the performance note on [`run_once()`](Self::run_once).
-    fn publish_set_select(&self) -> VecDeque<&Ipt> {
+    fn publish_set_select(&self, now: &TrackingNow) -> VecDeque<&Ipt> {
         /// Good candidate introduction point for publication
         type Candidate<'i> = &'i Ipt;
 
         let target_n = self.target_n_intro_points();
+        let grace = self.state.current_config.ipt_fault_grace_period;
 
         let mut candidates: VecDeque<_> = self
             .state
@@ -1296,37 +2034,52 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
             .iter()
             .filter_map(|ir: &_| -> Option<Candidate<'_>> {
                 let current_ipt = ir.current_ipt()?;
-                if !current_ipt.is_good() {
+                if !current_ipt.is_publishable(now, grace) {
                     return None;
                 }
                 Some(current_ipt)
             })
             .collect();
 
-        // Take the last N good IPT relays
+        // Take the last N good IPT relays, in order of preference
         //
-        // The way we manage irelays means that this is always
+        // The way we manage irelays means that, before sorting, the last N are
         // the ones we selected most recently.
+        // See IptPublicationStrategy for the tradeoffs between the strategies.
         //
-        // TODO SPEC  Publication strategy when we have more than >N IPTs
-        //
-        // We could have a number of strategies here.  We could take some timing
-        // measurements, or use the establishment time, or something; but we don't
-        // want to add distinguishability.
-        //
-        // Another concern is manipulability, but
-        // We can't be forced to churn because we don't remove relays
-        // from our list of relays to try to use, other than on our own schedule.
-        // But we probably won't want to be too reactive to the network environment.
+        // We can't be forced to churn by any of the strategies,
+        // because we don't remove relays from our list of relays to try to use,
+        // other than on our own schedule.
         //
-        // Since we only choose new relays when old ones are to retire, or are faulty,
-        // choosing the most recently selected, rather than the least recently,
-        // has the effect of preferring relays we don't know to be faulty,
-        // to ones we have considered faulty least once.
+        // TODO SPEC  Publication strategy when we have more than >N IPTs
         //
-        // That's better than the opposite.  Also, choosing more recently selected relays
-        // for publication may slightly bring forward the time at which all descriptors
-        // mentioning that relay have expired, and then we can forget about it.
+        // Sort so that the most preferred come last.
+        match self.state.current_config.ipt_publication_strategy {
+            IptPublicationStrategy::MostRecent => {}
+            IptPublicationStrategy::FewestFaults => candidates
+                .make_contiguous()
+                .sort_by_key(|ipt| Reverse(ipt.n_faults_if_good())),
+            IptPublicationStrategy::FastestEstablish => candidates
+                .make_contiguous()
+                .sort_by_key(|ipt| Reverse(ipt.time_to_establish_if_good())),
+        }
+
+        // If we are maintaining warm standby IPTs, we keep publishing the IPTs we have
+        // already published, in preference to the standbys: a standby should only be
+        // promoted when one of the published IPTs stops being good.
+        // (The sort is stable, so otherwise the order above is preserved.)
+        if self.n_standby_intro_points() > 0 {
+            candidates
+                .make_contiguous()
+                .sort_by_key(|ipt| ipt.last_descriptor_expiry_including_slop.is_some());
+        }
+
+        // IPTs which aren't actually good (just in their fault grace period) are
+        // the least preferred: once we have enough good IPTs, we stop publishing them.
+        candidates
+            .make_contiguous()
+            .sort_by_key(|ipt| ipt.is_good());
+
         while candidates.len() > target_n {
             // WTB: VecDeque::truncate_front
             let _: Candidate = candidates.pop_front().expect("empty?!");
@@ -1348,12 +2101,16 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
     fn make_publish_set<'i>(
         selected: impl IntoIterator<Item = &'i Ipt>,
         lifetime: Duration,
+        expiry_slop: Duration,
     ) -> Result<ipt_set::IptSet, FatalError> {
         let ipts = selected
             .into_iter()
             .map(|current_ipt| {
-                let TS::Good { details, .. } = &current_ipt.status_last else {
-                    return Err(internal!("was good but now isn't?!").into());
+                let details = match (&current_ipt.status_last, &current_ipt.lapsed) {
+                    (TS::Good { details, .. }, _) => details,
+                    // In its fault grace period: publish what we published before.
+                    (_, Some(lapsed)) => &lapsed.details,
+                    (_, None) => return Err(internal!("was good but now isn't?!").into()),
                 };
 
                 let publish = current_ipt.for_publish(details)?;
@@ -1369,7 +2126,11 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
             })
             .collect::<Result<_, _>>()?;
 
-        Ok(ipt_set::IptSet { ipts, lifetime })
+        Ok(ipt_set::IptSet {
+            ipts,
+            lifetime,
+            expiry_slop,
+        })
     }
 
     /// Run one iteration of the loop
@@ -1413,6 +2174,13 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
         &mut self,
         // This is a separate argument for borrowck reasons
         publisher: &mut IptsManagerView,
+        // Notifications about changes to the netdir.
+        //
+        // Subscribed from the current netdir provider, and resubscribed if it is replaced.
+        // After a failed relay selection, an event here is what prompts us to retry.
+        //
+        // This lives in `main_loop_task`, not in our `State`, because it isn't `Sync`.
+        dir_events: &mut BoxStream<'static, DirEvent>,
     ) -> Result<ShutdownStatus, FatalError> {
         let now = {
             // Block to persuade borrow checker that publish_set isn't
@@ -1438,21 +2206,33 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
                 }
             };
 
-            // TODO HSS: Maybe something at level Error or Info, for example
-            // Log an error if everything is terrilbe
-            //   - we have >=N Faulty IPTs ?
-            //    we have only Faulty IPTs and can't select another due to 2N limit ?
-            // Log at info if and when we publish?  Maybe the publisher should do that?
+            // (If we have only Faulty IPTs for too long,
+            // idempotently_progress_things_now reports an error; see `AllFaulty`.)
+            //
+            // TODO HSS: Maybe log at info if and when we publish?  Maybe the publisher should do that?
 
             if let Err(operr) = self.compute_iptsetstatus_publish(&now, &mut publish_set) {
                 // This is not good, is it.
+                let old_lids = published_lids(&publish_set);
                 publish_set.ipts = None;
+                self.note_published_ipts(&old_lids, &publish_set);
                 let wait = operr.log_retry_max(&self.imm.nick)?;
                 now.update(wait);
             };
 
             self.expire_old_expiry_times(&mut publish_set, &now);
 
+            *self.state.diagnostics.lock().expect("poisoned lock") =
+                IptMgrDiagnostics::new(&self.imm, &self.state, &publish_set);
+            self.imm.status_tx.note_ipt_progress(
+                self.current_ipts().count(),
+                self.good_ipts().count(),
+                self.target_n_intro_points(),
+            );
+            self.imm
+                .status_tx
+                .note_ipts_good_enough(self.good_ipts().count() >= self.target_n_intro_points());
+
             drop(publish_set); // release lock, and notify publisher of any changes
 
             now
@@ -1465,6 +2245,10 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
         );
 
         let mut new_configs = self.state.new_configs.next().fuse();
+        let mut new_blocklists = self.state.new_blocklists.next().fuse();
+        let mut new_relay_scorers = self.state.new_relay_scorers.next().fuse();
+        let mut new_all_faulty_callbacks = self.state.new_all_faulty_callbacks.next().fuse();
+        let mut new_dirproviders = self.state.new_dirproviders.next().fuse();
 
         select_biased! {
             () = now.wait_for_earliest(&self.imm.runtime).fuse() => {},
@@ -1480,13 +2264,15 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
                 self.state.handle_ipt_status_update(&self.imm, lid, update);
             }
 
-            _dir_event = async {
-                match self.state.last_irelay_selection_outcome {
-                    Ok(()) => future::pending().await,
-                    // This boxes needlessly but it shouldn't really happen
-                    Err(()) => self.imm.dirprovider.events().next().await,
+            dir_event = dir_events.next().fuse() => {
+                // We always read the events, even if our last relay selection succeeded,
+                // so that we don't act on a stale event the next time selection fails.
+                if dir_event.is_none() {
+                    trace!("HS service {}: EOF on netdir events stream", &self.imm.nick);
+                    // Don't spin on the ended stream; we'll resubscribe if the
+                    // netdir provider is replaced.
+                    *dir_events = Box::pin(futures::stream::pending());
                 }
-            }.fuse() => {
                 self.state.last_irelay_selection_outcome = Ok(());
             }
 
@@ -1496,21 +2282,275 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
                            &self.imm.nick);
                     return Ok(ShutdownStatus::Terminate);
                 };
-                self.state.current_config = new_config;
-                self.state.last_irelay_selection_outcome = Ok(());
+                if Arc::ptr_eq(&new_config, &self.state.current_config) {
+                    // Eg, the initial value from the watch; or, a pending update was undone.
+                    self.state.pending_config = None;
+                } else if let Some(pending) = &mut self.state.pending_config {
+                    pending.config = new_config;
+                    pending.n_coalesced += 1;
+                } else {
+                    self.state.pending_config = Some(PendingConfig {
+                        config: new_config,
+                        // On time overflow, don't debounce; just apply it straight away.
+                        apply_at: {
+                            let now = self.imm.now();
+                            now.checked_add(CONFIG_UPDATE_DEBOUNCE).unwrap_or(now)
+                        },
+                        n_coalesced: 1,
+                    });
+                }
+            }
+
+            new_blocklist = new_blocklists => {
+                let Some(new_blocklist) = new_blocklist else {
+                    trace!("HS service {}: terminating due to EOF on blocklist updates stream",
+                           &self.imm.nick);
+                    return Ok(ShutdownStatus::Terminate);
+                };
+                self.state.blocklist = new_blocklist;
+                // A relay we previously couldn't use might now be available
+                self.state.last_irelay_selection_outcome = Ok(());
+            }
+
+            new_relay_scorer = new_relay_scorers => {
+                let Some(new_relay_scorer) = new_relay_scorer else {
+                    trace!("HS service {}: terminating due to EOF on relay scorer updates stream",
+                           &self.imm.nick);
+                    return Ok(ShutdownStatus::Terminate);
+                };
+                self.state.relay_scorer = new_relay_scorer;
+                // A relay we previously scored at zero might now be eligible
+                self.state.last_irelay_selection_outcome = Ok(());
+            }
+
+            new_all_faulty_callback = new_all_faulty_callbacks => {
+                let Some(new_all_faulty_callback) = new_all_faulty_callback else {
+                    trace!("HS service {}: terminating due to EOF on all-IPTs-faulty callback updates stream",
+                           &self.imm.nick);
+                    return Ok(ShutdownStatus::Terminate);
+                };
+                self.state.all_faulty_callback = new_all_faulty_callback;
+            }
+
+            new_dirprovider = new_dirproviders => {
+                let Some(new_dirprovider) = new_dirprovider else {
+                    trace!("HS service {}: terminating due to EOF on netdir provider updates stream",
+                           &self.imm.nick);
+                    return Ok(ShutdownStatus::Terminate);
+                };
+                trace!("HS service {}: netdir provider (re)set, reevaluating IPT relays",
+                       &self.imm.nick);
+                *dir_events = new_dirprovider.events();
+                // The new provider's netdir might let us select relays we couldn't before
+                self.state.last_irelay_selection_outcome = Ok(());
             }
         }
 
         Ok(ShutdownStatus::Continue)
     }
 
+    /// Retire one IPT, if we have more good-ish IPTs than we want
+    ///
+    /// We prefer to retire IPTs that aren't good yet, then ones we haven't published,
+    /// and otherwise the most recently selected.
+    /// We retire the whole relay, so that we don't make a new IPT there.
+    ///
+    /// Returns `true` if we retired an IPT, in which case
+    /// [`idempotently_progress_things_now`](Self::idempotently_progress_things_now)
+    /// should be rerun.
+    fn retire_surplus_ipt(&mut self, now: &TrackingNow) -> bool {
+        let n_good_ish = self
+            .current_ipts()
+            .filter(|(_ir, ipt)| ipt.is_good_ish())
+            .count();
+        if n_good_ish <= self.target_n_intro_points() + self.n_standby_intro_points() {
+            return false;
+        }
+        let surplus = self
+            .state
+            .irelays
+            .iter_mut()
+            .rev()
+            .filter_map(|ir| {
+                let ipt = ir.current_ipt().filter(|ipt| ipt.is_good_ish())?;
+                let key = (
+                    ipt.is_good(),
+                    ipt.last_descriptor_expiry_including_slop.is_some(),
+                );
+                Some((key, ir))
+            })
+            .min_by_key(|(key, _ir)| *key);
+        let Some((_key, ir)) = surplus else {
+            return false;
+        };
+        info!(
+            "HS service {}: retiring surplus IPT at relay {} ({} good-ish IPTs)",
+            &self.imm.nick,
+            ir.relay.display_relay_ids(),
+            n_good_ish,
+        );
+        ir.planned_retirement = now.instant().get_now_untracked();
+        if let Some(ipt) = ir.current_ipt_mut() {
+            ipt.is_current = None;
+        }
+        true
+    }
+
+    /// Apply the pending configuration update, if it is due
+    ///
+    /// Returns `true` if we applied it, in which case
+    /// [`idempotently_progress_things_now`](Self::idempotently_progress_things_now)
+    /// should be rerun.
+    fn apply_pending_config(&mut self, now: &TrackingNow) -> bool {
+        match &self.state.pending_config {
+            Some(pending) if *now >= pending.apply_at => {}
+            _ => return false,
+        }
+        let PendingConfig {
+            config,
+            n_coalesced,
+            ..
+        } = self
+            .state
+            .pending_config
+            .take()
+            .expect("pending config vanished");
+        debug!(
+            "HS service {}: applying configuration update (coalesced {} updates)",
+            &self.imm.nick, n_coalesced,
+        );
+        *self.state.applied_config.lock().expect("poisoned lock") = Arc::clone(&config);
+        self.state.current_config = config;
+        self.state.last_irelay_selection_outcome = Ok(());
+        true
+    }
+
+    /// Check whether all our IPTs have been faulty for too long, or have recovered
+    ///
+    /// Updates `all_faulty`, reporting the service as broken (or recovering) as appropriate.
+    ///
+    /// Returns `true` if we changed something, in which case
+    /// [`idempotently_progress_things_now`](Self::idempotently_progress_things_now)
+    /// should be rerun.
+    fn check_all_faulty(&mut self, now: &TrackingNow) -> bool {
+        let any_good = self.good_ipts().next().is_some();
+        let all_faulty = self.current_ipts().next().is_some()
+            && self
+                .current_ipts()
+                .all(|(_ir, ipt)| matches!(ipt.status_last, TS::Faulty { .. }));
+        match self.state.all_faulty {
+            None => {
+                if all_faulty {
+                    self.state.all_faulty = Some(AllFaulty {
+                        since: now.instant().get_now_untracked(),
+                        escalated: false,
+                    });
+                    // Run again, so that we compare `now` with the deadline,
+                    // and therefore arrange to wake up when it arrives.
+                    return true;
+                }
+            }
+            Some(AllFaulty {
+                since,
+                escalated: false,
+            }) => {
+                let timeout = self.state.current_config.ipt_all_faulty_timeout;
+                if !all_faulty {
+                    self.state.all_faulty = None;
+                } else if since
+                    .checked_add(timeout)
+                    .map_or(false, |deadline| *now >= deadline)
+                {
+                    error!(
+                        "HS service {}: all our introduction points have been faulty for {}; service is broken",
+                        &self.imm.nick,
+                        humantime::format_duration(timeout),
+                    );
+                    self.imm.status_tx.maybe_update_ipt_mgr(SvcState::Broken);
+                    if let Some(callback) = &self.state.all_faulty_callback {
+                        callback();
+                    }
+                    self.state.all_faulty = Some(AllFaulty {
+                        since,
+                        escalated: true,
+                    });
+                    // We are now allowed more relays; try selecting some even if we failed before.
+                    self.state.last_irelay_selection_outcome = Ok(());
+                    return true;
+                }
+            }
+            Some(AllFaulty {
+                escalated: true, ..
+            }) => {
+                if any_good {
+                    info!(
+                        "HS service {}: recovered: we have a good introduction point again",
+                        &self.imm.nick,
+                    );
+                    self.imm
+                        .status_tx
+                        .maybe_update_ipt_mgr(SvcState::Recovering);
+                    self.state.all_faulty = None;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Note that we have managed to create an IPT, so our storage is working
+    ///
+    /// If we had reported persistent storage failures as [`Broken`](SvcState::Broken),
+    /// we are now recovering.
+    fn note_storage_success(&mut self) {
+        let failures = &mut self.state.storage_failures;
+        if failures.n_failures >= self.state.current_config.ipt_storage_failure_threshold {
+            self.imm
+                .status_tx
+                .maybe_update_ipt_mgr(SvcState::Recovering);
+        }
+        *failures = StorageFailures::default();
+    }
+
+    /// Note that we failed to create an IPT because of the storage problem `e`
+    ///
+    /// Storage problems are often transient, so we arrange to retry soon at first,
+    /// backing off if the problem persists.
+    /// After `ipt_storage_failure_threshold` consecutive failures,
+    /// we report ourselves as [`Broken`](SvcState::Broken).
+    fn note_storage_failure(&mut self, e: &CreateIptError, now: &TrackingNow) {
+        let failures = &mut self.state.storage_failures;
+        failures.n_failures = failures.n_failures.saturating_add(1);
+        error_report!(
+            e,
+            "HS {}: failed to prepare new IPT ({} consecutive failures)",
+            &self.imm.nick,
+            failures.n_failures,
+        );
+        if failures.n_failures >= self.state.current_config.ipt_storage_failure_threshold {
+            self.imm.status_tx.maybe_update_ipt_mgr(SvcState::Broken);
+        }
+        failures.retry_at =
+            storage_retry_at(now.instant().get_now_untracked(), failures.n_failures);
+        if failures.retry_at.is_some() {
+            now.update(storage_retry_delay(failures.n_failures));
+        } else {
+            // Without a retry time, we'll retry on the next wakeup.
+            warn!(
+                "HS {}: time overflow calculating storage retry time",
+                &self.imm.nick,
+            );
+        }
+    }
+
     /// IPT Manager main loop, runs as a task
     ///
     /// Contains the error handling, including catching panics.
     async fn main_loop_task(mut self, mut publisher: IptsManagerView) {
+        let mut dir_events = self.imm.dirprovider().events();
         loop {
             match async {
-                AssertUnwindSafe(self.run_once(&mut publisher))
+                AssertUnwindSafe(self.run_once(&mut publisher, &mut dir_events))
                     .catch_unwind()
                     .await
                     .map_err(|_: Box<dyn Any + Send>| internal!("IPT manager crashed"))?
@@ -1533,11 +2573,25 @@ impl<R: Runtime, M: Mockable<R>> IptManager<R, M> {
         self.state.current_config.num_intro_points.into()
     }
 
+    /// Minimum number of good intro points we need before we publish any
+    pub(crate) fn min_ipts_before_publish(&self) -> usize {
+        self.state.current_config.min_ipts_before_publish.into()
+    }
+
+    /// Number of extra ("warm standby") intro points we maintain but don't publish
+    pub(crate) fn n_standby_intro_points(&self) -> usize {
+        self.state.current_config.ipt_warm_standby.into()
+    }
+
     /// Maximum number of concurrent intro point relays
     pub(crate) fn max_n_intro_relays(&self) -> usize {
         // TODO HSS max_n_intro_relays should be configurable
         // TODO HSS consider default, in context of intro point forcing attacks
-        self.target_n_intro_points() * 2
+        //
+        // If all our IPTs have been faulty for a long time, we cast our net wider.
+        let escalated = self.state.all_faulty.map_or(false, |af| af.escalated);
+        let factor = if escalated { 3 } else { 2 };
+        (self.target_n_intro_points() + self.n_standby_intro_points()) * factor
     }
 }
 
@@ -1677,15 +2731,25 @@ mod test {
     use super::*;
 
     use crate::config::OnionServiceConfigBuilder;
+    use crate::status::{IptFailureEventStream, OnionServiceStatus, PublishedIptSetEventStream};
+    use crate::svc::apply_reconfiguration;
     use crate::svc::ipt_establish::GoodIptDetails;
+    use crate::svc::netdir::test::NotifyingNetDirProvider;
     use crate::svc::test::{create_keymgr, create_storage_handles_from_state_mgr};
     use crate::test_temp_dir::TestTempDir;
     use rand::SeedableRng as _;
     use slotmap::DenseSlotMap;
-    use std::collections::BTreeMap;
-    use std::sync::Mutex;
+    use std::collections::{BTreeMap, HashSet};
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use tor_basic_utils::test_rng::TestingRng;
+    use tor_config::{Reconfigure, ReconfigureError};
+    use tor_keymgr::test_utils::HookedKeystore;
+    use tor_keymgr::{
+        ArtiNativeKeystore, KeyMgrBuilder, KeystoreCorruptionError, KeystoreError, KeystoreSelector,
+    };
+    use tor_llcrypto::pk::rsa::RsaIdentity;
     use tor_netdir::testprovider::TestNetDirProvider;
+    use tor_rtcompat::SleepProviderExt as _;
     use tor_rtmock::MockRuntime;
     use tracing_test::traced_test;
 
@@ -1752,31 +2816,91 @@ mod test {
         }
     }
 
+    /// Build the configuration of our test service, with the settings made by `f`
+    ///
+    /// The service is called `nick`, unless `f` says otherwise.
+    fn cfg_with(
+        f: impl FnOnce(&mut OnionServiceConfigBuilder) -> &mut OnionServiceConfigBuilder,
+    ) -> OnionServiceConfig {
+        let nick: HsNickname = "nick".to_string().try_into().unwrap();
+        f(OnionServiceConfigBuilder::default().nickname(nick))
+            .build()
+            .unwrap()
+    }
+
     struct MockedIptManager<'d> {
         estabs: MockEstabs,
         pub_view: ipt_set::IptsPublisherView,
+        ipt_failures: IptFailureEventStream,
         shut_tx: broadcast::Sender<Void>,
-        #[allow(dead_code)]
         cfg_tx: watch::Sender<Arc<OnionServiceConfig>>,
+        blocklist_tx: watch::Sender<Arc<[RelayId]>>,
+        relay_scorer_tx: watch::Sender<Option<IptRelayScorer>>,
+        all_faulty_callback_tx: watch::Sender<Option<AllIptsFaultyCallback>>,
+        dirprovider_tx: watch::Sender<Arc<dyn NetDirProvider>>,
+        status_tx: StatusSender,
         #[allow(dead_code)] // ensures temp dir lifetime; paths stored in self
         temp_dir: &'d TestTempDir,
     }
 
     impl<'d> MockedIptManager<'d> {
         fn startup(runtime: MockRuntime, temp_dir: &'d TestTempDir) -> Self {
+            let keymgr = create_keymgr(temp_dir);
+            let keymgr = keymgr.into_untracked(); // OK because our return value captures 'd
+            Self::startup_with_keymgr(runtime, temp_dir, keymgr)
+        }
+
+        fn startup_with_keymgr(
+            runtime: MockRuntime,
+            temp_dir: &'d TestTempDir,
+            keymgr: Arc<KeyMgr>,
+        ) -> Self {
+            let (m, mgr, mgr_view) =
+                Self::new_unlaunched(runtime, temp_dir, keymgr, cfg_with(|b| b));
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            m
+        }
+
+        /// Create an `IptManager` with the specified config and a fresh keystore,
+        /// without launching it.
+        fn unlaunched(
+            runtime: MockRuntime,
+            temp_dir: &'d TestTempDir,
+            cfg: OnionServiceConfig,
+        ) -> (
+            Self,
+            IptManager<MockRuntime, Mocks>,
+            ipt_set::IptsManagerView,
+        ) {
+            let keymgr = create_keymgr(temp_dir);
+            let keymgr = keymgr.into_untracked(); // OK because our return value captures 'd
+            Self::new_unlaunched(runtime, temp_dir, keymgr, cfg)
+        }
+
+        /// Create an `IptManager` with the specified config, without launching it.
+        fn new_unlaunched(
+            runtime: MockRuntime,
+            temp_dir: &'d TestTempDir,
+            keymgr: Arc<KeyMgr>,
+            cfg: OnionServiceConfig,
+        ) -> (
+            Self,
+            IptManager<MockRuntime, Mocks>,
+            ipt_set::IptsManagerView,
+        ) {
             let dir: TestNetDirProvider = tor_netdir::testnet::construct_netdir()
                 .unwrap_if_sufficient()
                 .unwrap()
                 .into();
 
-            let nick: HsNickname = "nick".to_string().try_into().unwrap();
-
-            let cfg = OnionServiceConfigBuilder::default()
-                .nickname(nick.clone())
-                .build()
-                .unwrap();
+            let nick = cfg.nickname.clone();
 
             let (cfg_tx, cfg_rx) = watch::channel_with(Arc::new(cfg));
+            let (blocklist_tx, blocklist_rx) = watch::channel_with(Arc::from(vec![]));
+            let (relay_scorer_tx, relay_scorer_rx) = watch::channel();
+            let (all_faulty_callback_tx, all_faulty_callback_rx) = watch::channel();
+            let (dirprovider_tx, dirprovider_rx) =
+                watch::channel_with(Arc::new(dir) as Arc<dyn NetDirProvider>);
 
             let (rend_tx, _rend_rx) = mpsc::channel(10);
             let (shut_tx, shut_rx) = broadcast::channel::<Void>(0);
@@ -1804,38 +2928,47 @@ mod test {
             let (mgr_view, pub_view) =
                 ipt_set::ipts_channel(&runtime, iptpub_state_handle).unwrap();
 
-            let keymgr = create_keymgr(temp_dir);
-            let keymgr = keymgr.into_untracked(); // OK because our return value captures 'd
+            let status_tx = StatusSender::new(OnionServiceStatus::new_shutdown());
             let mgr = IptManager::new(
                 runtime.clone(),
-                Arc::new(dir),
+                dirprovider_rx,
                 nick,
                 cfg_rx,
+                blocklist_rx,
+                relay_scorer_rx,
+                all_faulty_callback_rx,
                 rend_tx,
                 shut_rx,
                 state_mgr,
                 mocks,
                 keymgr,
+                status_tx.clone(),
                 &state_dir,
                 &mistrust,
             )
             .unwrap();
 
-            mgr.launch_background_tasks(mgr_view).unwrap();
+            let ipt_failures = IptFailureEventStream::new(mgr.ipt_failure_sender().subscribe());
 
-            MockedIptManager {
+            let m = MockedIptManager {
                 estabs,
                 pub_view,
+                ipt_failures,
                 shut_tx,
                 cfg_tx,
+                blocklist_tx,
+                relay_scorer_tx,
+                all_faulty_callback_tx,
+                dirprovider_tx,
+                status_tx,
                 temp_dir,
-            }
+            };
+            (m, mgr, mgr_view)
         }
 
         async fn shutdown_check_no_tasks(self, runtime: &MockRuntime) {
             drop(self.shut_tx);
-            runtime.progress_until_stalled().await;
-            assert_eq!(runtime.mock_task().n_tasks(), 1); // just us
+            runtime.assert_no_extra_tasks().await;
         }
 
         fn estabs_inventory(&self) -> impl Eq + Debug + 'static {
@@ -1949,6 +3082,1651 @@ mod test {
         });
     }
 
+    #[test]
+    #[traced_test]
+    fn test_min_ipts_before_publish() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+            let cfg = cfg_with(|b| b.min_ipts_before_publish(2));
+
+            let (m, mgr, mgr_view) = MockedIptManager::unlaunched(runtime.clone(), &temp_dir, cfg);
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            runtime.progress_until_stalled().await;
+            assert!(m.pub_view.borrow_for_publish().ipts.is_none());
+
+            let good = GoodIptDetails {
+                link_specifiers: vec![],
+                ipt_kp_ntor: [0x55; 32].into(),
+            };
+            let set_good = |n: usize| {
+                for e in m.estabs.lock().unwrap().values_mut().take(n) {
+                    e.st_tx.borrow_mut().status = IptStatusStatus::Good(good.clone());
+                }
+            };
+
+            // One of our IPTs becomes good.
+            runtime.advance_by(ms(500)).await;
+            set_good(1);
+            runtime.progress_until_stalled().await;
+
+            // Without the minimum, we would publish it after a further 500ms.
+            // But we don't, no matter how long we wait.
+            runtime.advance_by(ms(60 * 1000)).await;
+            runtime.progress_until_stalled().await;
+            assert!(m.pub_view.borrow_for_publish().ipts.is_none());
+
+            // Once a second IPT becomes good, we publish both of them.
+            set_good(2);
+            runtime.progress_until_stalled().await;
+            let n_published = m
+                .pub_view
+                .borrow_for_publish()
+                .ipts
+                .as_ref()
+                .unwrap()
+                .ipts
+                .len();
+            assert_eq!(n_published, 2);
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_ipt_key_mismatch() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+
+            let m = MockedIptManager::startup(runtime.clone(), &temp_dir);
+            runtime.progress_until_stalled().await;
+            let lid = m.estabs.lock().unwrap().values().next().unwrap().params.lid;
+            m.shutdown_check_no_tasks(&runtime).await;
+
+            // Swap one of the IPT's keys for a different one
+            let keymgr = create_keymgr(&temp_dir);
+            let keymgr = keymgr.into_untracked(); // OK because `m` doesn't outlive `temp_dir`
+            let nick: HsNickname = "nick".to_string().try_into().unwrap();
+            let spec = IptKeySpecifier {
+                nick: nick.clone(),
+                role: IptKeyRole::KSid,
+                lid,
+            };
+            keymgr
+                .generate::<HsIntroPtSessionIdKeypair>(
+                    &spec,
+                    KeystoreSelector::Default,
+                    &mut TestingRng::seed_from_u64(1),
+                    true,
+                )
+                .unwrap();
+
+            // ---------- restart! ----------
+            // We notice that the key doesn't match the one we used before.
+            let cfg = cfg_with(|b| b);
+            let (_m, mgr, mgr_view) =
+                MockedIptManager::new_unlaunched(runtime.clone(), &temp_dir, keymgr, cfg);
+            let err = mgr.launch_background_tasks(mgr_view).unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    StartupError::Keystore {
+                        cause: tor_keymgr::Error::Corruption(
+                            KeystoreCorruptionError::PublicKeyMismatch
+                        ),
+                        ..
+                    }
+                ),
+                "{err:?}"
+            );
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_ipt_key_missing() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+
+            let m = MockedIptManager::startup(runtime.clone(), &temp_dir);
+            runtime.progress_until_stalled().await;
+            let lid = m.estabs.lock().unwrap().values().next().unwrap().params.lid;
+            m.shutdown_check_no_tasks(&runtime).await;
+
+            // Delete one of the IPT's keys
+            let keymgr = create_keymgr(&temp_dir);
+            let keymgr = keymgr.into_untracked(); // OK because `m` doesn't outlive `temp_dir`
+            let nick: HsNickname = "nick".to_string().try_into().unwrap();
+            let spec = IptKeySpecifier {
+                nick: nick.clone(),
+                role: IptKeyRole::KSid,
+                lid,
+            };
+            keymgr
+                .remove::<HsIntroPtSessionIdKeypair>(&spec, KeystoreSelector::Default)
+                .unwrap()
+                .unwrap();
+
+            let mk_cfg = |strict_ipt_keys| cfg_with(|b| b.strict_ipt_keys(strict_ipt_keys));
+
+            // ---------- restart, strictly ----------
+            // We refuse to regenerate the missing key.
+            let (m, mgr, mgr_view) = MockedIptManager::new_unlaunched(
+                runtime.clone(),
+                &temp_dir,
+                Arc::clone(&keymgr),
+                mk_cfg(true),
+            );
+            let err = mgr.launch_background_tasks(mgr_view).unwrap_err();
+            assert!(
+                matches!(err, StartupError::Fatal(FatalError::IptKeysMissing(_))),
+                "{err:?}"
+            );
+            drop(m);
+
+            // ---------- restart, leniently ----------
+            // We make a new key, and carry on.
+            let (m, mgr, mgr_view) =
+                MockedIptManager::new_unlaunched(runtime.clone(), &temp_dir, keymgr, mk_cfg(false));
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            runtime.progress_until_stalled().await;
+            assert!(logs_contain("regenerating"));
+            assert_eq!(m.estabs.lock().unwrap().len(), 3);
+            assert!(m
+                .estabs
+                .lock()
+                .unwrap()
+                .values()
+                .any(|e| e.params.lid == lid));
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_ipt_failure_events() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+
+            let mut m = MockedIptManager::startup(runtime.clone(), &temp_dir);
+            runtime.progress_until_stalled().await;
+
+            // Nothing has failed yet
+            assert!(m.ipt_failures.next().now_or_never().is_none());
+
+            let good = GoodIptDetails {
+                link_specifiers: vec![],
+                ipt_kp_ntor: [0x55; 32].into(),
+            };
+
+            let lids = m
+                .estabs
+                .lock()
+                .unwrap()
+                .values()
+                .map(|e| e.params.lid)
+                .collect_vec();
+
+            // Updates the status of the establisher for `lid`, returning its relay
+            let set_status = |lid: IptLocalId, status: IptStatusStatus, n_faults: u32| {
+                let mut estabs = m.estabs.lock().unwrap();
+                let estab = estabs.values_mut().find(|e| e.params.lid == lid).unwrap();
+                let mut st = estab.st_tx.borrow_mut();
+                st.status = status;
+                st.n_faults = n_faults;
+                estab.params.target.clone()
+            };
+
+            // Good -> Faulty
+            let _ = set_status(lids[0], IptStatusStatus::Good(good.clone()), 0);
+            runtime.progress_until_stalled().await;
+            assert!(m.ipt_failures.next().now_or_never().is_none());
+
+            let relay = set_status(lids[0], IptStatusStatus::Faulty, 1);
+            runtime.progress_until_stalled().await;
+            let event = m.ipt_failures.next().now_or_never().unwrap().unwrap();
+            assert_eq!(event, IptFailureEvent::new(lids[0], relay, 1));
+
+            // Establishing -> Faulty
+            let lid = lids[1];
+            let relay = set_status(lid, IptStatusStatus::Faulty, 2);
+            runtime.progress_until_stalled().await;
+            let event = m.ipt_failures.next().now_or_never().unwrap().unwrap();
+            assert_eq!(event.lid(), lid);
+            assert_eq!(event.relay(), &relay);
+            assert_eq!(event.n_faults(), 2);
+
+            // Several IPTs failing at once are each reported
+            let relays = [
+                set_status(lids[0], IptStatusStatus::Good(good.clone()), 1),
+                set_status(lids[1], IptStatusStatus::Good(good.clone()), 2),
+            ];
+            runtime.progress_until_stalled().await;
+            let _ = set_status(lids[0], IptStatusStatus::Faulty, 3);
+            let _ = set_status(lids[1], IptStatusStatus::Faulty, 4);
+            runtime.progress_until_stalled().await;
+            let events = [
+                m.ipt_failures.next().now_or_never().unwrap().unwrap(),
+                m.ipt_failures.next().now_or_never().unwrap().unwrap(),
+            ];
+            let events = events
+                .into_iter()
+                .sorted_by_key(|e| e.n_faults())
+                .collect_vec();
+            assert_eq!(
+                events,
+                [
+                    IptFailureEvent::new(lids[0], relays[0].clone(), 3),
+                    IptFailureEvent::new(lids[1], relays[1].clone(), 4),
+                ]
+            );
+            assert!(m.ipt_failures.next().now_or_never().is_none());
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_published_ipt_set_events() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+            let cfg = cfg_with(|b| b);
+
+            let (m, mgr, mgr_view) = MockedIptManager::unlaunched(runtime.clone(), &temp_dir, cfg);
+            let mut events =
+                PublishedIptSetEventStream::new(mgr.published_ipt_set_sender().subscribe());
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            runtime.progress_until_stalled().await;
+
+            // We aren't publishing anything yet
+            const EXPECT_N_IPTS: usize = 3;
+            assert_eq!(m.estabs.lock().unwrap().len(), EXPECT_N_IPTS);
+            assert!(events.next().now_or_never().is_none());
+
+            let good = GoodIptDetails {
+                link_specifiers: vec![],
+                ipt_kp_ntor: [0x55; 32].into(),
+            };
+
+            // All our IPTs become good
+            let lids = {
+                let mut estabs = m.estabs.lock().unwrap();
+                for e in estabs.values_mut() {
+                    e.st_tx.borrow_mut().status = IptStatusStatus::Good(good.clone());
+                }
+                estabs.values().map(|e| e.params.lid).sorted().collect_vec()
+            };
+            runtime.progress_until_stalled().await;
+
+            // We may have started publishing before all of them were good;
+            // each change is reported, and the last one has them all
+            let event = std::iter::from_fn(|| events.next().now_or_never().flatten())
+                .last()
+                .unwrap();
+            assert_eq!(event.n_ipts(), EXPECT_N_IPTS);
+            assert_eq!(event.lids().iter().copied().sorted().collect_vec(), lids);
+
+            // Nothing changes, so there are no more events
+            runtime.advance_by(ms(1000)).await;
+            runtime.progress_until_stalled().await;
+            assert!(events.next().now_or_never().is_none());
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_ipt_status_dedup() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+
+            let m = MockedIptManager::startup(runtime.clone(), &temp_dir);
+            runtime.progress_until_stalled().await;
+
+            let lid = m.estabs.lock().unwrap().values().next().unwrap().params.lid;
+
+            // Counts the status updates for `lid` that the manager has processed
+            let n_processed = || {
+                let n = std::cell::Cell::new(0);
+                logs_assert(|lines| {
+                    let needle = format!("{lid:?} status update");
+                    n.set(lines.iter().filter(|l| l.contains(&needle)).count());
+                    Ok(())
+                });
+                n.get()
+            };
+            let set_status = |status: IptStatusStatus| {
+                let mut estabs = m.estabs.lock().unwrap();
+                let estab = estabs.values_mut().find(|e| e.params.lid == lid).unwrap();
+                estab.st_tx.borrow_mut().status = status;
+            };
+
+            // The establisher keeps telling us it's still establishing
+            let before = n_processed();
+            for _ in 0..100 {
+                set_status(IptStatusStatus::Establishing);
+                runtime.progress_until_stalled().await;
+            }
+            assert_eq!(n_processed(), before);
+
+            // An actual change gets through
+            set_status(IptStatusStatus::Good(GoodIptDetails {
+                link_specifiers: vec![],
+                ipt_kp_ntor: [0x55; 32].into(),
+            }));
+            runtime.progress_until_stalled().await;
+            assert_eq!(n_processed(), before + 1);
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_ipt_relay_blocklist() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+
+            let mut m = MockedIptManager::startup(runtime.clone(), &temp_dir);
+            runtime.progress_until_stalled().await;
+
+            let targets = |m: &MockedIptManager| {
+                m.estabs
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .map(|e| (e.params.lid, e.params.target.clone()))
+                    .collect_vec()
+            };
+
+            const EXPECT_N_IPTS: usize = 3;
+            let before = targets(&m);
+            assert_eq!(before.len(), EXPECT_N_IPTS);
+
+            // Blocklist the relay hosting one of our current IPTs
+            let (blocked_lid, blocked_relay) = before[0].clone();
+            let blocked_id = RelayId::from(*blocked_relay.rsa_identity().unwrap());
+            *m.blocklist_tx.borrow_mut() = Arc::from([blocked_id]);
+            runtime.progress_until_stalled().await;
+
+            // The IPT at the blocklisted relay has been retired, and replaced by one
+            // at a different relay; the other IPTs are unaffected.
+            let after = targets(&m);
+            assert_eq!(after.len(), EXPECT_N_IPTS);
+            assert!(after.iter().all(|(lid, _)| *lid != blocked_lid));
+            assert!(after
+                .iter()
+                .all(|(_, relay)| !relay.has_any_relay_id_from(&blocked_relay)));
+            for kept in &before[1..] {
+                assert!(after.contains(kept));
+            }
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_ipt_relay_scorer() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+            let cfg = cfg_with(|b| b);
+
+            // Pick a relay that is usable, and that has a nonzero HsIntro weight
+            let netdir = tor_netdir::testnet::construct_netdir()
+                .unwrap_if_sufficient()
+                .unwrap();
+            let preferred = netdir
+                .relays()
+                .filter(|relay| {
+                    netdir.relay_weight(relay, tor_netdir::WeightRole::HsIntro)
+                        > RelayWeight::from(0)
+                        && ipt_relay_usable(&cfg, &netdir, &[], &[], relay)
+                })
+                .last()
+                .unwrap();
+            let preferred = *preferred.rsa_id();
+
+            let (mut m, mgr, mgr_view) =
+                MockedIptManager::unlaunched(runtime.clone(), &temp_dir, cfg);
+
+            // A scorer that only likes `preferred`
+            let scorer: IptRelayScorer = Arc::new(move |relay: &Relay<'_>| {
+                if relay.rsa_id() == &preferred {
+                    1.0
+                } else {
+                    0.0
+                }
+            });
+            *m.relay_scorer_tx.borrow_mut() = Some(scorer);
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            runtime.progress_until_stalled().await;
+
+            // We chose the preferred relay, and no other
+            let targets = m
+                .estabs
+                .lock()
+                .unwrap()
+                .values()
+                .map(|e| *e.params.target.rsa_identity().unwrap())
+                .collect_vec();
+            assert_eq!(targets, [preferred]);
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_all_ipts_faulty() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+            const TIMEOUT: Duration = Duration::from_secs(60);
+            let cfg = cfg_with(|b| {
+                b.ipt_all_faulty_timeout(TIMEOUT)
+                    // The test network has too few unrelated relays for 9 diverse IPT relays
+                    .ipt_relay_diversity(false)
+            });
+
+            let (mut m, mgr, mgr_view) =
+                MockedIptManager::unlaunched(runtime.clone(), &temp_dir, cfg);
+
+            let n_callbacks = Arc::new(AtomicUsize::new(0));
+            let callback: AllIptsFaultyCallback = {
+                let n_callbacks = Arc::clone(&n_callbacks);
+                Arc::new(move || {
+                    n_callbacks.fetch_add(1, Ordering::SeqCst);
+                })
+            };
+            *m.all_faulty_callback_tx.borrow_mut() = Some(callback);
+
+            // Pretend the publisher is happy, so that the overall state reflects ours
+            m.status_tx.maybe_update_publisher(SvcState::Running);
+
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            assert_eq!(m.status_tx.get().state(), SvcState::Bootstrapping);
+            runtime.progress_until_stalled().await;
+
+            let set_all_faulty = || {
+                for estab in m.estabs.lock().unwrap().values_mut() {
+                    estab.st_tx.borrow_mut().status = IptStatusStatus::Faulty;
+                }
+            };
+
+            // Our 3 IPTs fail, so we select 3 more relays, and those IPTs fail too.
+            // That takes us to the limit of 6 relays.
+            assert_eq!(m.estabs.lock().unwrap().len(), 3);
+            set_all_faulty();
+            runtime.progress_until_stalled().await;
+            assert_eq!(m.estabs.lock().unwrap().len(), 6);
+            set_all_faulty();
+            runtime.progress_until_stalled().await;
+            assert_eq!(m.estabs.lock().unwrap().len(), 6);
+            let faulty_lids = m
+                .estabs
+                .lock()
+                .unwrap()
+                .values()
+                .map(|e| e.params.lid)
+                .collect_vec();
+
+            // Not for long enough, yet
+            runtime.advance_by(TIMEOUT - ms(1)).await;
+            assert_ne!(m.status_tx.get().state(), SvcState::Broken);
+            assert_eq!(n_callbacks.load(Ordering::SeqCst), 0);
+
+            // Now we escalate
+            runtime.advance_by(ms(1)).await;
+            runtime.progress_until_stalled().await;
+            assert_eq!(m.status_tx.get().state(), SvcState::Broken);
+            assert_eq!(n_callbacks.load(Ordering::SeqCst), 1);
+            assert!(logs_contain("all our introduction points have been faulty"));
+
+            // We are now trying more relays
+            assert_eq!(m.estabs.lock().unwrap().len(), 9);
+
+            // One of the new IPTs becomes good, and we recover
+            m.estabs
+                .lock()
+                .unwrap()
+                .values_mut()
+                .find(|e| !faulty_lids.contains(&e.params.lid))
+                .unwrap()
+                .st_tx
+                .borrow_mut()
+                .status = IptStatusStatus::Good(GoodIptDetails {
+                link_specifiers: vec![],
+                ipt_kp_ntor: [0x55; 32].into(),
+            });
+            runtime.progress_until_stalled().await;
+            assert_eq!(m.status_tx.get().state(), SvcState::Recovering);
+            assert_eq!(n_callbacks.load(Ordering::SeqCst), 1);
+
+            // The rest of the new IPTs become good; now we have all we want, so we're running
+            for estab in m
+                .estabs
+                .lock()
+                .unwrap()
+                .values_mut()
+                .filter(|e| !faulty_lids.contains(&e.params.lid))
+            {
+                estab.st_tx.borrow_mut().status = IptStatusStatus::Good(GoodIptDetails {
+                    link_specifiers: vec![],
+                    ipt_kp_ntor: [0x55; 32].into(),
+                });
+            }
+            runtime.progress_until_stalled().await;
+            assert_eq!(m.status_tx.get().state(), SvcState::Running);
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    fn test_dump_diagnostics() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+            let cfg = cfg_with(|b| b);
+
+            let (m, mgr, mgr_view) = MockedIptManager::unlaunched(runtime.clone(), &temp_dir, cfg);
+            let diagnostics = mgr.diagnostics();
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            runtime.progress_until_stalled().await;
+
+            // One of our IPTs becomes good, and (after a while) we decide to publish it
+            let good_lid = {
+                let mut estabs = m.estabs.lock().unwrap();
+                let estab = estabs.values_mut().next().unwrap();
+                estab.st_tx.borrow_mut().status = IptStatusStatus::Good(GoodIptDetails {
+                    link_specifiers: vec![],
+                    ipt_kp_ntor: [0x55; 32].into(),
+                });
+                estab.params.lid
+            };
+            runtime.advance_by(Duration::from_secs(1)).await;
+            runtime.progress_until_stalled().await;
+
+            let json = serde_json::to_value(&*diagnostics.lock().unwrap()).unwrap();
+
+            let keys = |v: &serde_json::Value| {
+                v.as_object()
+                    .unwrap()
+                    .keys()
+                    .map(|k| k.as_str())
+                    .sorted()
+                    .join(",")
+            };
+            assert_eq!(keys(&json), "ipt_relays,publish,taken");
+
+            let relays = json["ipt_relays"].as_array().unwrap();
+            assert_eq!(relays.len(), 3);
+            let mut states = vec![];
+            for relay in relays {
+                assert_eq!(keys(relay), "ipts,planned_retirement,relay");
+                assert!(relay["relay"]["rsa"].is_string());
+                assert!(relay["planned_retirement"].is_string());
+                let ipts = relay["ipts"].as_array().unwrap();
+                assert_eq!(ipts.len(), 1);
+                let ipt = &ipts[0];
+                assert_eq!(
+                    keys(ipt),
+                    "is_current,last_descriptor_expiry_including_slop,lid,status",
+                );
+                assert_eq!(ipt["is_current"], true);
+                let state = ipt["status"]["state"].as_str().unwrap();
+                if ipt["lid"] == good_lid.to_string() {
+                    assert_eq!(state, "good");
+                    assert_eq!(keys(&ipt["status"]), "state,time_to_establish");
+                } else {
+                    assert_eq!(state, "establishing");
+                    assert_eq!(keys(&ipt["status"]), "started,state");
+                }
+                states.push(state.to_owned());
+            }
+            assert_eq!(states.iter().filter(|s| *s == "good").count(), 1);
+
+            assert_eq!(keys(&json["publish"]), "ipts,lifetime");
+            assert_eq!(
+                json["publish"]["ipts"],
+                serde_json::json!([good_lid.to_string()])
+            );
+
+            // No key material: in particular, not the IPT's public ntor key (all 0x55 bytes)
+            let json = json.to_string();
+            for secret in ["85,85,85", "5555555555555555", "k_sid", "ntor"] {
+                assert!(!json.contains(secret), "{secret:?} in {json}");
+            }
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    fn test_ipt_establish_concurrency() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+            const N_IPTS: usize = 5;
+            const CONCURRENCY: u8 = 3;
+            let cfg = cfg_with(|b| {
+                b.num_intro_points(N_IPTS.try_into().unwrap())
+                    .ipt_establish_concurrency(CONCURRENCY)
+            });
+            let (m, mut mgr, _mgr_view) =
+                MockedIptManager::unlaunched(runtime.clone(), &temp_dir, cfg);
+            let n_estabs = || m.estabs.lock().unwrap().len();
+
+            // Progress until we start establishing our first IPTs:
+            // we should have started CONCURRENCY of them at once.
+            while n_estabs() == 0 {
+                assert!(mgr.idempotently_progress_things_now().unwrap().is_none());
+            }
+            assert_eq!(n_estabs(), usize::from(CONCURRENCY));
+
+            // The next batch is limited by the number of IPTs we still need.
+            while mgr.idempotently_progress_things_now().unwrap().is_none() {
+                assert!(n_estabs() <= N_IPTS);
+            }
+            assert_eq!(n_estabs(), N_IPTS);
+        });
+    }
+
+    #[test]
+    fn test_min_ipt_reselect_interval() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+            const INTERVAL: Duration = Duration::from_secs(60);
+            let cfg = cfg_with(|b| b.min_ipt_reselect_interval(INTERVAL));
+            let (m, mgr, mgr_view) = MockedIptManager::unlaunched(runtime.clone(), &temp_dir, cfg);
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            runtime.progress_until_stalled().await;
+            let n_estabs = || m.estabs.lock().unwrap().len();
+
+            // We want 3 IPTs straight away, but we only select one relay at a time,
+            // at least INTERVAL apart.
+            assert_eq!(n_estabs(), 1);
+            for n in 2..=3 {
+                runtime.advance_by(INTERVAL - Duration::from_secs(1)).await;
+                assert_eq!(n_estabs(), n - 1);
+                runtime.advance_by(Duration::from_secs(1)).await;
+                assert_eq!(n_estabs(), n);
+            }
+
+            // Once we have all the IPTs we want, we don't select any more.
+            runtime.advance_by(INTERVAL * 2).await;
+            assert_eq!(n_estabs(), 3);
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_config_update_debounce() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+
+            let mut m = MockedIptManager::startup(runtime.clone(), &temp_dir);
+            runtime.progress_until_stalled().await;
+            let n_estabs = |m: &MockedIptManager| m.estabs.lock().unwrap().len();
+            assert_eq!(n_estabs(&m), 3);
+
+            // Send several config updates in quick succession;
+            // none of them is applied straight away.
+            for n_ipts in 4..=6 {
+                let cfg = cfg_with(|b| {
+                    b.num_intro_points(n_ipts)
+                        // The test network has too few unrelated relays for 6 diverse IPTs
+                        .ipt_relay_diversity(false)
+                });
+                *m.cfg_tx.borrow_mut() = Arc::new(cfg);
+                runtime.advance_by(CONFIG_UPDATE_DEBOUNCE / 5).await;
+                assert_eq!(n_estabs(&m), 3);
+            }
+
+            // Once the debounce period is over, they are all applied at once
+            runtime.advance_by(CONFIG_UPDATE_DEBOUNCE).await;
+            assert_eq!(n_estabs(&m), 6);
+            assert!(logs_contain("coalesced 3 updates"));
+            assert!(!logs_contain("coalesced 1 updates"));
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_reconfigure_num_intro_points() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+
+            let mut m = MockedIptManager::startup(runtime.clone(), &temp_dir);
+            runtime.progress_until_stalled().await;
+            let lids = |m: &MockedIptManager| {
+                m.estabs
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .map(|e| e.params.lid)
+                    .collect::<HashSet<_>>()
+            };
+            let orig_lids = lids(&m);
+            assert_eq!(orig_lids.len(), 3);
+
+            let mk_cfg = |nick: &str, n_ipts| {
+                cfg_with(|b| {
+                    b.nickname(nick.to_string().try_into().unwrap())
+                        .num_intro_points(n_ipts)
+                })
+            };
+
+            // Changing the nickname is not allowed, and nothing changes.
+            let err =
+                apply_reconfiguration(&mut m.cfg_tx, mk_cfg("other", 5), Reconfigure::AllOrNothing)
+                    .unwrap_err();
+            assert!(matches!(err, ReconfigureError::CannotChange { .. }));
+            // Merely checking a permitted change doesn't apply it.
+            apply_reconfiguration(
+                &mut m.cfg_tx,
+                mk_cfg("nick", 5),
+                Reconfigure::CheckAllOrNothing,
+            )
+            .unwrap();
+            runtime.advance_by(CONFIG_UPDATE_DEBOUNCE * 2).await;
+            assert_eq!(lids(&m), orig_lids);
+
+            // Increasing num_intro_points is applied live.
+            apply_reconfiguration(&mut m.cfg_tx, mk_cfg("nick", 5), Reconfigure::AllOrNothing)
+                .unwrap();
+            runtime.advance_by(CONFIG_UPDATE_DEBOUNCE * 2).await;
+            assert_eq!(m.cfg_tx.borrow().num_intro_points, 5);
+            let new_lids = lids(&m);
+            assert_eq!(new_lids.len(), 5);
+            // Our existing IPTs were kept, not restarted.
+            assert!(new_lids.is_superset(&orig_lids));
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_reduce_num_intro_points() {
+        MockRuntime::test_with_various(|runtime| async move {
+            const SLOP: Duration = Duration::from_secs(5 * 60);
+
+            let temp_dir = test_temp_dir!();
+            let mk_cfg =
+                |n_ipts| cfg_with(|b| b.num_intro_points(n_ipts).ipt_publish_expiry_slop(SLOP));
+            let (mut m, mgr, mgr_view) =
+                MockedIptManager::unlaunched(runtime.clone(), &temp_dir, mk_cfg(5));
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            runtime.progress_until_stalled().await;
+
+            let lids = |m: &MockedIptManager| {
+                m.estabs
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .map(|e| e.params.lid)
+                    .collect::<HashSet<_>>()
+            };
+            assert_eq!(lids(&m).len(), 5);
+
+            for e in m.estabs.lock().unwrap().values_mut() {
+                e.st_tx.borrow_mut().status = IptStatusStatus::Good(GoodIptDetails {
+                    link_specifiers: vec![],
+                    ipt_kp_ntor: [0x55; 32].into(),
+                });
+            }
+            runtime.progress_until_stalled().await;
+
+            // Pretend to be the publisher, and publish a descriptor mentioning all 5 IPTs
+            {
+                let mut pg = m.pub_view.borrow_for_publish();
+                assert_eq!(pg.ipts.as_ref().unwrap().ipts.len(), 5);
+                pg.note_publication_attempt(&runtime, runtime.now())
+                    .unwrap();
+            }
+
+            apply_reconfiguration(&mut m.cfg_tx, mk_cfg(3), Reconfigure::AllOrNothing).unwrap();
+            runtime.advance_by(CONFIG_UPDATE_DEBOUNCE * 2).await;
+
+            // We now publish only 3 IPTs, and don't establish any replacements
+            let published = m
+                .pub_view
+                .borrow_for_publish()
+                .ipts
+                .as_ref()
+                .unwrap()
+                .ipts
+                .iter()
+                .map(|ipt| ipt.lid)
+                .collect::<HashSet<_>>();
+            assert_eq!(published.len(), 3);
+            assert!(logs_contain("retiring surplus IPT"));
+
+            // But the retired IPTs are kept until the descriptor mentioning them has expired
+            assert_eq!(lids(&m).len(), 5);
+            let second = Duration::from_secs(1);
+            runtime
+                .advance_by(IPT_PUBLISH_CERTAIN + SLOP - CONFIG_UPDATE_DEBOUNCE * 2 - second)
+                .await;
+            assert_eq!(lids(&m).len(), 5);
+
+            runtime.advance_by(second * 2).await;
+            assert_eq!(lids(&m), published);
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_applied_config() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+            let mk_cfg = |n_ipts| cfg_with(|b| b.num_intro_points(n_ipts));
+            let (mut m, mgr, mgr_view) =
+                MockedIptManager::unlaunched(runtime.clone(), &temp_dir, mk_cfg(3));
+            let applied_config = mgr.applied_config();
+            let applied_n_ipts = || applied_config.lock().unwrap().num_intro_points;
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            runtime.progress_until_stalled().await;
+            assert_eq!(applied_n_ipts(), 3);
+
+            apply_reconfiguration(&mut m.cfg_tx, mk_cfg(4), Reconfigure::AllOrNothing).unwrap();
+            runtime.progress_until_stalled().await;
+
+            // The new config has been sent, but not yet applied
+            assert_eq!(m.cfg_tx.borrow().num_intro_points, 4);
+            assert_eq!(applied_n_ipts(), 3);
+
+            runtime.advance_by(CONFIG_UPDATE_DEBOUNCE).await;
+            assert_eq!(applied_n_ipts(), 4);
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_startup_progress() {
+        use crate::status::StartupProgress as SP;
+
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+            let cfg = cfg_with(|b| b);
+            let (m, mgr, mgr_view) = MockedIptManager::unlaunched(runtime.clone(), &temp_dir, cfg);
+            let mut progress = m.status_tx.subscribe_progress();
+            let mut next_progress = || progress.next().now_or_never().map(Option::unwrap);
+            assert_eq!(next_progress(), Some(SP::SelectingIpts));
+
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            runtime.progress_until_stalled().await;
+            let establishing = |good| SP::EstablishingIpts { good, target: 3 };
+            assert_eq!(next_progress(), Some(establishing(0)));
+
+            // Our IPTs become good, one at a time
+            let lids = m
+                .estabs
+                .lock()
+                .unwrap()
+                .values()
+                .map(|e| e.params.lid)
+                .collect_vec();
+            let set_status = |lid: IptLocalId, status: IptStatusStatus| {
+                let mut estabs = m.estabs.lock().unwrap();
+                let estab = estabs.values_mut().find(|e| e.params.lid == lid).unwrap();
+                estab.st_tx.borrow_mut().status = status;
+            };
+            let good = || {
+                IptStatusStatus::Good(GoodIptDetails {
+                    link_specifiers: vec![],
+                    ipt_kp_ntor: [0x55; 32].into(),
+                })
+            };
+            for (i, lid) in lids.iter().enumerate() {
+                set_status(*lid, good());
+                runtime.progress_until_stalled().await;
+                assert_eq!(next_progress(), Some(establishing(i + 1)));
+            }
+
+            // The publisher starts uploading our descriptor
+            m.status_tx.note_publishing();
+            assert_eq!(next_progress(), Some(SP::Publishing));
+
+            // Changes to our IPTs no longer count as progress
+            set_status(lids[0], IptStatusStatus::Faulty);
+            runtime.progress_until_stalled().await;
+            assert_eq!(next_progress(), None);
+
+            // The upload succeeds
+            m.status_tx.note_reachable();
+            assert_eq!(next_progress(), Some(SP::Reachable));
+            m.status_tx.note_publishing();
+            assert_eq!(next_progress(), None);
+            assert_eq!(m.status_tx.get_progress(), SP::Reachable);
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_wait_until_reachable() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+            let cfg = cfg_with(|b| b);
+            let (m, mgr, mgr_view) = MockedIptManager::unlaunched(runtime.clone(), &temp_dir, cfg);
+            let mut reachable = Box::pin(m.status_tx.subscribe_progress().wait_until_reachable());
+            let timeout = Duration::from_secs(60);
+            let mut reachable_soon = Box::pin(runtime.timeout(
+                timeout,
+                m.status_tx.subscribe_progress().wait_until_reachable(),
+            ));
+
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            runtime.progress_until_stalled().await;
+            assert!((&mut reachable).now_or_never().is_none());
+
+            // All our IPTs become good, and the publisher starts uploading
+            for e in m.estabs.lock().unwrap().values_mut() {
+                e.st_tx.borrow_mut().status = IptStatusStatus::Good(GoodIptDetails {
+                    link_specifiers: vec![],
+                    ipt_kp_ntor: [0x55; 32].into(),
+                });
+            }
+            runtime.progress_until_stalled().await;
+            m.status_tx.note_publishing();
+            assert!((&mut reachable).now_or_never().is_none());
+
+            // We don't become reachable in time
+            runtime.advance_by(timeout).await;
+            assert_eq!(
+                (&mut reachable_soon).now_or_never(),
+                Some(Err(tor_rtcompat::TimeoutError))
+            );
+
+            // The upload succeeds
+            m.status_tx.note_reachable();
+            assert_eq!((&mut reachable).now_or_never(), Some(()));
+
+            // Once we are reachable, waiting returns immediately
+            let reachable = m.status_tx.subscribe_progress().wait_until_reachable();
+            assert_eq!(reachable.now_or_never(), Some(()));
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_custom_replay_log_dir() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+            let replay_log_dir = temp_dir.subdir_untracked("fast_storage");
+            let cfg = cfg_with(|b| b.replay_log_dir(Some(replay_log_dir.clone())));
+            let (m, mgr, mgr_view) = MockedIptManager::unlaunched(runtime.clone(), &temp_dir, cfg);
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            runtime.progress_until_stalled().await;
+
+            // We have a replay log for each of our IPTs in the custom directory
+            let lids = m
+                .estabs
+                .lock()
+                .unwrap()
+                .values()
+                .map(|e| e.params.lid)
+                .collect_vec();
+            assert_eq!(lids.len(), 3);
+            for lid in lids {
+                assert!(replay_log_dir.join(format!("{lid}.bin")).is_file());
+            }
+
+            // and nothing in the default location
+            let default_dir = temp_dir.subdir_untracked("state_dir").join("hss_iptreplay");
+            assert!(!default_dir.exists());
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_replace_netdir_provider() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+            let cfg = cfg_with(|b| b);
+            let (mut m, mgr, mgr_view) =
+                MockedIptManager::unlaunched(runtime.clone(), &temp_dir, cfg);
+
+            // Start off with a provider that has no netdir, and never will
+            let netdir = tor_netdir::testnet::construct_netdir()
+                .unwrap_if_sufficient()
+                .unwrap();
+            *m.dirprovider_tx.borrow_mut() = Arc::new(TestNetDirProvider::new());
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            runtime.progress_until_stalled().await;
+            assert_eq!(m.estabs.lock().unwrap().len(), 0);
+
+            // Once we switch to a provider that has a netdir, we can select relays
+            *m.dirprovider_tx.borrow_mut() = Arc::new(TestNetDirProvider::from(netdir));
+            runtime.progress_until_stalled().await;
+            assert_eq!(m.estabs.lock().unwrap().len(), 3);
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_retry_selection_on_dir_event() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+            let cfg = cfg_with(|b| b);
+            let (mut m, mgr, mgr_view) =
+                MockedIptManager::unlaunched(runtime.clone(), &temp_dir, cfg);
+
+            // Relay selection fails, since there is no netdir yet
+            let provider = Arc::new(NotifyingNetDirProvider::empty());
+            *m.dirprovider_tx.borrow_mut() = provider.clone();
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            runtime.progress_until_stalled().await;
+            assert_eq!(m.estabs.lock().unwrap().len(), 0);
+
+            // A netdir arriving makes us retry straight away, without any timeout
+            let netdir = tor_netdir::testnet::construct_netdir()
+                .unwrap_if_sufficient()
+                .unwrap();
+            provider.set_netdir_and_notify(netdir);
+            runtime.progress_until_stalled().await;
+            assert_eq!(m.estabs.lock().unwrap().len(), 3);
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_ipt_warm_standby() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+            const N_IPTS: usize = 3;
+            let cfg = cfg_with(|b| {
+                b.num_intro_points(N_IPTS.try_into().unwrap())
+                    .ipt_warm_standby(1)
+            });
+            let (m, mgr, mgr_view) = MockedIptManager::unlaunched(runtime.clone(), &temp_dir, cfg);
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            runtime.progress_until_stalled().await;
+
+            // We establish one more IPT than we publish
+            assert_eq!(m.estabs.lock().unwrap().len(), N_IPTS + 1);
+
+            let good = GoodIptDetails {
+                link_specifiers: vec![],
+                ipt_kp_ntor: [0x55; 32].into(),
+            };
+            for e in m.estabs.lock().unwrap().values_mut() {
+                e.st_tx.borrow_mut().status = IptStatusStatus::Good(good.clone());
+            }
+            runtime.progress_until_stalled().await;
+
+            let published = |m: &MockedIptManager<'_>| {
+                m.pub_view
+                    .borrow_for_publish()
+                    .ipts
+                    .as_ref()
+                    .unwrap()
+                    .ipts
+                    .iter()
+                    .map(|ipt| ipt.lid)
+                    .collect_vec()
+            };
+            let before = published(&m);
+            assert_eq!(before.len(), N_IPTS);
+            let standby = m
+                .estabs
+                .lock()
+                .unwrap()
+                .values()
+                .map(|e| e.params.lid)
+                .find(|lid| !before.contains(lid))
+                .unwrap();
+
+            // One of the published IPTs fails
+            let failed = before[0];
+            m.estabs
+                .lock()
+                .unwrap()
+                .values_mut()
+                .find(|e| e.params.lid == failed)
+                .unwrap()
+                .st_tx
+                .borrow_mut()
+                .status = IptStatusStatus::Faulty;
+            runtime.progress_until_stalled().await;
+
+            // The standby is published straight away, even though the IPT
+            // we are establishing to replace it isn't good yet.
+            let after = published(&m);
+            assert_eq!(after.len(), N_IPTS);
+            assert!(after.contains(&standby));
+            assert!(!after.contains(&failed));
+            assert_eq!(m.estabs.lock().unwrap().len(), N_IPTS + 2);
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_ipt_publication_strategy() {
+        use IptPublicationStrategy as IPS;
+
+        for (strategy, exp_published) in [
+            (IPS::MostRecent, 2),
+            (IPS::FewestFaults, 0),
+            (IPS::FastestEstablish, 1),
+        ] {
+            MockRuntime::test_with_various(|runtime| async move {
+                let temp_dir = test_temp_dir!();
+                // Publish one IPT, so that there are two others to choose from.
+                let cfg = cfg_with(|b| {
+                    b.num_intro_points(1)
+                        .ipt_warm_standby(2)
+                        .ipt_publication_strategy(strategy)
+                });
+                let (m, mgr, mgr_view) =
+                    MockedIptManager::unlaunched(runtime.clone(), &temp_dir, cfg);
+                mgr.launch_background_tasks(mgr_view).unwrap();
+                runtime.progress_until_stalled().await;
+
+                // In order of selection
+                let lids = m
+                    .estabs
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .map(|e| e.params.lid)
+                    .collect_vec();
+                assert_eq!(lids.len(), 3);
+
+                let set_good = |lid: IptLocalId, n_faults: u32| {
+                    let mut estabs = m.estabs.lock().unwrap();
+                    let estab = estabs.values_mut().find(|e| e.params.lid == lid).unwrap();
+                    let mut st = estab.st_tx.borrow_mut();
+                    st.status = IptStatusStatus::Good(GoodIptDetails {
+                        link_specifiers: vec![],
+                        ipt_kp_ntor: [0x55; 32].into(),
+                    });
+                    st.n_faults = n_faults;
+                };
+
+                // lids[1] is the fastest to establish, lids[0] has the fewest faults,
+                // and lids[2] was selected most recently.
+                for (i, n_faults) in [(1, 2), (0, 0), (2, 1)] {
+                    runtime.advance_by(Duration::from_secs(10)).await;
+                    set_good(lids[i], n_faults);
+                    runtime.progress_until_stalled().await;
+                }
+
+                let published = m
+                    .pub_view
+                    .borrow_for_publish()
+                    .ipts
+                    .as_ref()
+                    .unwrap()
+                    .ipts
+                    .iter()
+                    .map(|ipt| ipt.lid)
+                    .collect_vec();
+                assert_eq!(published, [lids[exp_published]], "{strategy:?}");
+
+                m.shutdown_check_no_tasks(&runtime).await;
+            });
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_ipt_fault_grace_period() {
+        MockRuntime::test_with_various(|runtime| async move {
+            const GRACE: Duration = Duration::from_secs(60);
+
+            let temp_dir = test_temp_dir!();
+            let cfg = cfg_with(|b| b.ipt_fault_grace_period(GRACE));
+            let (m, mgr, mgr_view) = MockedIptManager::unlaunched(runtime.clone(), &temp_dir, cfg);
+            let mut published_events =
+                PublishedIptSetEventStream::new(mgr.published_ipt_set_sender().subscribe());
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            runtime.progress_until_stalled().await;
+
+            let set_status = |lid: IptLocalId, status: IptStatusStatus| {
+                let mut estabs = m.estabs.lock().unwrap();
+                let estab = estabs.values_mut().find(|e| e.params.lid == lid).unwrap();
+                estab.st_tx.borrow_mut().status = status;
+            };
+            let good = || {
+                IptStatusStatus::Good(GoodIptDetails {
+                    link_specifiers: vec![],
+                    ipt_kp_ntor: [0x55; 32].into(),
+                })
+            };
+            let lids = m
+                .estabs
+                .lock()
+                .unwrap()
+                .values()
+                .map(|e| e.params.lid)
+                .collect_vec();
+            assert_eq!(lids.len(), 3);
+            for lid in &lids {
+                set_status(*lid, good());
+            }
+            runtime.progress_until_stalled().await;
+
+            // Pretend to be the publisher, and publish a descriptor mentioning our IPTs
+            let published = |m: &MockedIptManager| {
+                let pg = m.pub_view.borrow_for_publish();
+                let ipts = pg.ipts.as_ref().unwrap();
+                let lids = ipts.ipts.iter().map(|ipt| ipt.lid).collect::<HashSet<_>>();
+                (lids, ipts.lifetime)
+            };
+            let before = published(&m);
+            assert_eq!(
+                before,
+                (lids.iter().copied().collect(), IPT_PUBLISH_CERTAIN)
+            );
+            m.pub_view
+                .borrow_for_publish()
+                .note_publication_attempt(&runtime, runtime.now())
+                .unwrap();
+            runtime.progress_until_stalled().await;
+            let n_events = std::iter::from_fn(|| published_events.next().now_or_never().flatten());
+            assert_ne!(n_events.count(), 0);
+
+            // One of our IPTs flaps, recovering within the grace period:
+            // we carry on publishing exactly the same thing.
+            set_status(lids[0], IptStatusStatus::Faulty);
+            runtime.progress_until_stalled().await;
+            assert_eq!(published(&m), before);
+            runtime.advance_by(GRACE / 2).await;
+            assert_eq!(published(&m), before);
+            set_status(lids[0], good());
+            runtime.progress_until_stalled().await;
+            runtime.advance_by(GRACE).await;
+            assert_eq!(published(&m), before);
+            assert!(published_events.next().now_or_never().is_none());
+
+            // If it is faulty for longer than the grace period, we stop publishing it
+            set_status(lids[0], IptStatusStatus::Faulty);
+            runtime.progress_until_stalled().await;
+            assert_eq!(published(&m), before);
+            runtime.advance_by(GRACE).await;
+            let (after, _lifetime) = published(&m);
+            assert!(!after.contains(&lids[0]));
+            assert!(published_events.next().now_or_never().is_some());
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_ipt_publish_expiry_slop() {
+        MockRuntime::test_with_various(|runtime| async move {
+            const SLOP: Duration = Duration::from_secs(2 * 3600);
+
+            let temp_dir = test_temp_dir!();
+            let cfg = cfg_with(|b| b.num_intro_points(1).ipt_publish_expiry_slop(SLOP));
+            let (m, mgr, mgr_view) = MockedIptManager::unlaunched(runtime.clone(), &temp_dir, cfg);
+            mgr.launch_background_tasks(mgr_view).unwrap();
+            runtime.progress_until_stalled().await;
+
+            let lid = {
+                let mut estabs = m.estabs.lock().unwrap();
+                let estab = estabs.values_mut().next().unwrap();
+                estab.st_tx.borrow_mut().status = IptStatusStatus::Good(GoodIptDetails {
+                    link_specifiers: vec![],
+                    ipt_kp_ntor: [0x55; 32].into(),
+                });
+                estab.params.lid
+            };
+            runtime.progress_until_stalled().await;
+
+            // Pretend to be the publisher, and publish a descriptor mentioning our IPT
+            let published = runtime.now();
+            {
+                let mut pg = m.pub_view.borrow_for_publish();
+                let ipts = pg.ipts.as_ref().unwrap();
+                assert_eq!(ipts.expiry_slop, SLOP);
+                assert_eq!(ipts.lifetime, IPT_PUBLISH_CERTAIN);
+                pg.note_publication_attempt(&runtime, published).unwrap();
+            }
+
+            // Now the IPT wants to retire, but it must be kept until the descriptor expires
+            m.estabs
+                .lock()
+                .unwrap()
+                .values_mut()
+                .find(|e| e.params.lid == lid)
+                .unwrap()
+                .st_tx
+                .borrow_mut()
+                .wants_to_retire = Err(IptWantsToRetire);
+            runtime.progress_until_stalled().await;
+
+            let still_maintained = || {
+                m.estabs
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .any(|e| e.params.lid == lid)
+            };
+
+            let second = Duration::from_secs(1);
+            runtime
+                .advance_by(IPT_PUBLISH_CERTAIN + SLOP - second)
+                .await;
+            assert!(still_maintained());
+
+            runtime.advance_by(second * 2).await;
+            assert!(!still_maintained());
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    fn test_ipt_relay_usable_ipv6() {
+        // Even-numbered relays also advertise an IPv6 ORPort; odd-numbered ones are IPv4-only.
+        let netdir = tor_netdir::testnet::construct_custom_netdir(|idx, nb| {
+            if idx % 2 == 0 {
+                let idx = u16::try_from(idx).unwrap();
+                nb.rs.add_or_port(std::net::SocketAddr::from((
+                    [0x2001, 0xdb8, 0, 0, 0, 0, 0, idx],
+                    9001,
+                )));
+            }
+        })
+        .unwrap()
+        .unwrap_if_sufficient()
+        .unwrap();
+
+        let mk_cfg = |require_ipv6| cfg_with(|b| b.ipt_require_ipv6(require_ipv6));
+        let has_ipv6 = |relay: &Relay<'_>| relay.addrs().iter().any(|addr| addr.is_ipv6());
+
+        let mut rng = TestingRng::seed_from_u64(0);
+        let pick = |rng: &mut TestingRng, cfg: &OnionServiceConfig| {
+            netdir
+                .pick_relay(rng, tor_netdir::WeightRole::HsIntro, |relay| {
+                    ipt_relay_usable(cfg, &netdir, &[], &[], relay)
+                })
+                .unwrap()
+        };
+
+        // With the option set, we only ever pick IPv6-capable relays.
+        let cfg = mk_cfg(true);
+        for _ in 0..100 {
+            assert!(has_ipv6(&pick(&mut rng, &cfg)));
+        }
+        assert!(netdir
+            .relays()
+            .filter(|relay| !has_ipv6(relay))
+            .all(|relay| !ipt_relay_usable(&cfg, &netdir, &[], &[], &relay)));
+
+        // Without it, IPv4-only relays are fair game.
+        let cfg = mk_cfg(false);
+        assert!((0..100).any(|_| !has_ipv6(&pick(&mut rng, &cfg))));
+    }
+
+    /// The error returned by the flaky keystore in [`test_storage_failure_backoff`]
+    #[derive(Clone, Debug, Error)]
+    #[error("transient keystore failure")]
+    struct FlakyKeystoreError;
+
+    impl HasKind for FlakyKeystoreError {
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::KeystoreAccessFailed
+        }
+    }
+
+    impl KeystoreError for FlakyKeystoreError {}
+
+    #[test]
+    #[traced_test]
+    fn test_storage_failure_backoff() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+
+            let attempts: Arc<Mutex<Vec<Instant>>> = Default::default();
+            let keymgr = temp_dir.used_by("keystore", |keystore_dir| {
+                let inner = ArtiNativeKeystore::from_path_and_mistrust(
+                    keystore_dir,
+                    &fs_mistrust::Mistrust::new_dangerously_trust_everyone(),
+                )
+                .unwrap();
+                // `get` fails the first two times it is called.
+                let n_failures = Mutex::new(2);
+                let keystore = HookedKeystore::new(inner).on_get({
+                    let runtime = runtime.clone();
+                    let attempts = attempts.clone();
+                    move |_, _| {
+                        attempts.lock().unwrap().push(runtime.now());
+                        let mut n_failures = n_failures.lock().unwrap();
+                        if *n_failures > 0 {
+                            *n_failures -= 1;
+                            let e: Arc<dyn KeystoreError> = Arc::new(FlakyKeystoreError);
+                            return Err(e.into());
+                        }
+                        Ok(())
+                    }
+                });
+
+                Arc::new(
+                    KeyMgrBuilder::default()
+                        .default_store(Box::new(keystore))
+                        .build()
+                        .unwrap(),
+                )
+            });
+            // OK because `m` doesn't outlive `temp_dir`
+            let keymgr = keymgr.into_untracked();
+
+            let m = MockedIptManager::startup_with_keymgr(runtime.clone(), &temp_dir, keymgr);
+            runtime.progress_until_stalled().await;
+
+            // The first attempt failed, and we haven't retried yet
+            assert_eq!(attempts.lock().unwrap().len(), 1);
+            assert_eq!(m.estabs.lock().unwrap().len(), 0);
+            assert!(logs_contain("1 consecutive failures"));
+
+            // The second attempt fails too, and the third succeeds
+            runtime.advance_by(Duration::from_secs(60)).await;
+            runtime.progress_until_stalled().await;
+            assert!(logs_contain("2 consecutive failures"));
+            assert_eq!(m.estabs.lock().unwrap().len(), 3);
+
+            // Each retry waited longer than the previous one
+            let gaps = attempts
+                .lock()
+                .unwrap()
+                .iter()
+                .take(3)
+                .tuple_windows()
+                .map(|(a, b)| *b - *a)
+                .collect_vec();
+            assert_eq!(gaps, [storage_retry_delay(1), storage_retry_delay(2)]);
+            assert!(gaps[0] < gaps[1]);
+
+            m.shutdown_check_no_tasks(&runtime).await;
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn test_monotonic_clock_regression() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let status_tx = StatusSender::new(OnionServiceStatus::new_shutdown());
+            let clock = MonotonicClock::default();
+            let regressions = || status_tx.get().monotonic_clock_regressions();
+
+            let t0 = clock.now(&runtime, &status_tx);
+            assert_eq!(t0, runtime.now());
+            runtime.advance_by(Duration::from_secs(10)).await;
+            let t1 = clock.now(&runtime, &status_tx);
+            assert_eq!(t1, t0 + Duration::from_secs(10));
+            assert_eq!(regressions(), 0);
+
+            // The clock goes backwards: we pretend it stood still, and count it
+            let t2 = clock.clamp(t1 - Duration::from_secs(5), &status_tx);
+            assert_eq!(t2, t1);
+            assert_eq!(regressions(), 1);
+            assert!(logs_contain("monotonic clock went backwards by 5000ms"));
+
+            // Once the clock is past the latest time we saw, we use it again
+            assert_eq!(clock.now(&runtime, &status_tx), t1);
+            runtime.advance_by(Duration::from_secs(1)).await;
+            assert_eq!(clock.now(&runtime, &status_tx), runtime.now());
+            assert_eq!(regressions(), 1);
+        });
+    }
+
+    #[test]
+    fn test_storage_retry_delay() {
+        let delays = (1..=8).map(storage_retry_delay).collect_vec();
+        assert_eq!(delays[0], Duration::from_secs(1));
+        assert!(delays.iter().tuple_windows().all(|(a, b)| a <= b));
+        assert_eq!(delays[7], Duration::from_secs(60));
+        assert_eq!(storage_retry_delay(u32::MAX), Duration::from_secs(60));
+    }
+
+    /// Return an `Instant` so late that adding even a nanosecond to it overflows
+    fn near_max_instant() -> Instant {
+        let mut t = Instant::now();
+        let mut step = Duration::MAX;
+        while step > Duration::ZERO {
+            match t.checked_add(step) {
+                Some(later) => t = later,
+                None => step /= 2,
+            }
+        }
+        t
+    }
+
+    #[test]
+    fn test_time_overflow() {
+        let max = near_max_instant();
+        let earlier = max - Duration::from_secs(30);
+        let now = TrackingNow::new(max, std::time::SystemTime::UNIX_EPOCH);
+
+        assert_eq!(storage_retry_at(max, 1), None);
+        assert_eq!(storage_retry_at(earlier, u32::MAX), None);
+        assert_eq!(
+            storage_retry_at(earlier, 1),
+            Some(earlier + Duration::from_secs(1))
+        );
+
+        assert!(ipt_reselect_interval_elapsed(&now, None, Duration::MAX));
+        assert!(ipt_reselect_interval_elapsed(
+            &now,
+            Some(earlier),
+            Duration::from_secs(30)
+        ));
+        assert!(!ipt_reselect_interval_elapsed(
+            &now,
+            Some(earlier),
+            Duration::from_secs(31)
+        ));
+        assert!(!ipt_reselect_interval_elapsed(
+            &now,
+            Some(max),
+            Duration::from_secs(1)
+        ));
+        assert!(!ipt_reselect_interval_elapsed(
+            &now,
+            Some(earlier),
+            Duration::MAX
+        ));
+    }
+
+    #[test]
+    fn test_ipt_relay_usable_diversity() {
+        // Relays 0..10 are all in one big family.
+        // (Every relay is also in the same /16 as every 5th relay; see construct_custom_network.)
+        let family = (0..10_u8)
+            .map(|i| hex::encode([i; 20]))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let netdir = tor_netdir::testnet::construct_custom_netdir(|idx, nb| {
+            if idx < 10 {
+                nb.md.family(family.parse().unwrap());
+            }
+        })
+        .unwrap()
+        .unwrap_if_sufficient()
+        .unwrap();
+
+        let mk_cfg = |diversity| cfg_with(|b| b.ipt_relay_diversity(diversity));
+        let mk_irelay = |relay: &Relay<'_>| IptRelay {
+            relay: RelayIds::from_relay_ids(relay),
+            planned_retirement: Instant::now(),
+            ipts: vec![],
+        };
+        let by_idx = |idx: u8| netdir.by_id(&RsaIdentity::from([idx; 20])).unwrap();
+        let subnet_config = SubnetConfig::default();
+        let relay_0 = by_idx(0);
+        let existing = [mk_irelay(&relay_0)];
+
+        // Diversity enforced: no relay sharing a family or subnet with relay 0 is usable.
+        let cfg = mk_cfg(true);
+        for relay in netdir.relays() {
+            let related =
+                relay.in_same_family(&relay_0) || relay.in_same_subnet(&relay_0, &subnet_config);
+            assert_eq!(
+                ipt_relay_usable(&cfg, &netdir, &existing, &[], &relay),
+                !related,
+                "{}",
+                relay.display_relay_ids()
+            );
+        }
+
+        // Picking a whole set of IPT relays yields pairwise unrelated relays.
+        let mut rng = TestingRng::seed_from_u64(0);
+        let mut irelays = vec![];
+        for _ in 0..3 {
+            let relay = netdir
+                .pick_relay(&mut rng, tor_netdir::WeightRole::HsIntro, |relay| {
+                    ipt_relay_usable(&cfg, &netdir, &irelays, &[], relay)
+                })
+                .unwrap();
+            irelays.push(mk_irelay(&relay));
+        }
+        let chosen = irelays
+            .iter()
+            .map(|ir| netdir.by_ids(&ir.relay).unwrap())
+            .collect_vec();
+        for (a, b) in chosen.iter().tuple_combinations() {
+            assert!(!a.in_same_family(b));
+            assert!(!a.in_same_subnet(b, &subnet_config));
+        }
+
+        // Diversity not enforced: family members and subnet neighbours are usable,
+        // but relay 0 itself still isn't.
+        let cfg = mk_cfg(false);
+        let relay_1 = by_idx(1);
+        let relay_5 = by_idx(5);
+        assert!(relay_1.in_same_family(&relay_0));
+        assert!(relay_5.in_same_subnet(&relay_0, &subnet_config));
+        assert!(ipt_relay_usable(&cfg, &netdir, &existing, &[], &relay_1));
+        assert!(ipt_relay_usable(&cfg, &netdir, &existing, &[], &relay_5));
+        assert!(!ipt_relay_usable(&cfg, &netdir, &existing, &[], &relay_0));
+    }
+
     #[test]
     fn test_merge_join_subset_by() {
         fn chk(bigger: &str, smaller: &str, output: &str) {
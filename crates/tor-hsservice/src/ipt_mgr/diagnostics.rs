@@ -0,0 +1,171 @@
+//! Diagnostic snapshots of the IPT manager's state
+//!
+//! For support requests: see
+//! [`OnionService::dump_diagnostics`](crate::OnionService::dump_diagnostics).
+//! Does *not* include any key material, not even public keys.
+
+use super::*;
+use std::time::SystemTime;
+
+/// Shared snapshot of the IPT manager's state
+///
+/// Replaced by the IPT manager each time it has finished its work.
+pub(crate) type IptMgrDiagnosticsHandle = Arc<Mutex<IptMgrDiagnostics>>;
+
+//---------- Data structures, serialized with serde ----------
+
+/// Snapshot of the IPT manager's state, for diagnostics
+///
+/// Times are converted to (approximate) wallclock times, to make them meaningful to a reader.
+#[derive(Serialize, Debug, Clone, Default)]
+pub(crate) struct IptMgrDiagnostics {
+    /// When this snapshot was taken
+    ///
+    /// `None` if the IPT manager hasn't run yet.
+    #[serde(with = "humantime_serde")]
+    taken: Option<SystemTime>,
+    /// Relays
+    ipt_relays: Vec<RelayDiagnostics>,
+    /// What we have told the publisher to publish
+    ///
+    /// `None` if we don't (yet) want to publish a descriptor.
+    publish: Option<PublishDiagnostics>,
+}
+
+/// Snapshot of a selected intro point relay
+#[derive(Serialize, Debug, Clone)]
+struct RelayDiagnostics {
+    /// Which relay?
+    relay: RelayIds,
+    /// When do we plan to retire it?
+    #[serde(with = "humantime_serde")]
+    planned_retirement: SystemTime,
+    /// The IPTs, including the current one and any still-wanted old ones
+    ipts: Vec<IptDiagnostics>,
+}
+
+/// Snapshot of a single intro point
+#[derive(Serialize, Debug, Clone)]
+struct IptDiagnostics {
+    /// Local identifier
+    lid: IptLocalId,
+    /// Is this IPT current, or are we just keeping it because of old descriptors
+    is_current: bool,
+    /// Last information about how it's doing
+    status: StatusDiagnostics,
+    /// Until when ought we to try to maintain it, if it has been published
+    #[serde(with = "humantime_serde")]
+    last_descriptor_expiry_including_slop: Option<SystemTime>,
+}
+
+/// Snapshot of a [`TrackedStatus`]
+///
+/// We leave out the details of `Good` IPTs, since they contain keys.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "state", rename_all = "snake_case")]
+enum StatusDiagnostics {
+    /// [`TrackedStatus::Faulty`]
+    Faulty,
+    /// [`TrackedStatus::Establishing`]
+    Establishing {
+        /// When we were told we started to establish
+        #[serde(with = "humantime_serde")]
+        started: SystemTime,
+    },
+    /// [`TrackedStatus::Good`]
+    Good {
+        /// How long it took to establish, if we know
+        #[serde(with = "humantime_serde")]
+        time_to_establish: Option<Duration>,
+    },
+}
+
+/// Snapshot of the IPTs we have told the publisher to publish
+#[derive(Serialize, Debug, Clone)]
+struct PublishDiagnostics {
+    /// The IPTs to list in the descriptor
+    ipts: Vec<IptLocalId>,
+    /// When to make the descriptor expire
+    #[serde(with = "humantime_serde")]
+    lifetime: Duration,
+}
+
+//---------- Taking a snapshot ----------
+
+/// Converter from `Instant`s to approximate `SystemTime`s
+struct ToWallclock {
+    /// Monotonic time corresponding to `wallclock`
+    now: Instant,
+    /// Wallclock time corresponding to `now`
+    wallclock: SystemTime,
+}
+
+impl ToWallclock {
+    /// Convert `t` to the corresponding wallclock time
+    fn convert(&self, t: Instant) -> SystemTime {
+        // If the result is unrepresentable, something is very wrong;
+        // but this is only for diagnostics, so we don't fail.
+        if t >= self.now {
+            self.wallclock.checked_add(t - self.now)
+        } else {
+            self.wallclock.checked_sub(self.now - t)
+        }
+        .unwrap_or(self.wallclock)
+    }
+}
+
+impl IptMgrDiagnostics {
+    /// Take a snapshot of the IPT manager's state, and of what we've told the publisher
+    pub(super) fn new<R: Runtime, M: Mockable<R>>(
+        imm: &Immutable<R>,
+        state: &State<R, M>,
+        publish_set: &PublishIptSet,
+    ) -> Self {
+        let conv = ToWallclock {
+            now: imm.now(),
+            wallclock: imm.runtime.wallclock(),
+        };
+
+        let ipt_relays = state
+            .irelays
+            .iter()
+            .map(|irelay| RelayDiagnostics {
+                relay: irelay.relay.clone(),
+                planned_retirement: conv.convert(irelay.planned_retirement),
+                ipts: irelay
+                    .ipts
+                    .iter()
+                    .map(|ipt| IptDiagnostics {
+                        lid: ipt.lid,
+                        is_current: ipt.is_current.is_some(),
+                        status: match &ipt.status_last {
+                            TS::Faulty { .. } => StatusDiagnostics::Faulty,
+                            TS::Establishing { started } => StatusDiagnostics::Establishing {
+                                started: conv.convert(*started),
+                            },
+                            TS::Good {
+                                time_to_establish, ..
+                            } => StatusDiagnostics::Good {
+                                time_to_establish: time_to_establish.ok(),
+                            },
+                        },
+                        last_descriptor_expiry_including_slop: ipt
+                            .last_descriptor_expiry_including_slop
+                            .map(|t| conv.convert(t)),
+                    })
+                    .collect_vec(),
+            })
+            .collect_vec();
+
+        let publish = publish_set.ipts.as_ref().map(|ipts| PublishDiagnostics {
+            ipts: ipts.ipts.iter().map(|ipt| ipt.lid).collect_vec(),
+            lifetime: ipts.lifetime,
+        });
+
+        IptMgrDiagnostics {
+            taken: Some(conv.wallclock),
+            ipt_relays,
+            publish,
+        }
+    }
+}
@@ -5,6 +5,8 @@
 
 use super::*;
 use crate::time_store;
+use tor_llcrypto::pk::curve25519;
+use tor_llcrypto::pk::ed25519::Ed25519Identity;
 
 /// Handle for a suitable persistent storage manager
 pub(crate) type IptStorageHandle = dyn tor_persist::StorageHandle<StateRecord> + Sync + Send;
@@ -39,6 +41,23 @@ struct IptRecord {
     /// Is this IPT current, or are we just keeping it because of old descriptors
     #[serde(default, skip_serializing_if = "<&bool as std::ops::Not>::not")]
     is_current: bool,
+    /// The public halves of this IPT's keys
+    ///
+    /// Used to check that the keys in the keystore are the ones we had before.
+    /// (Absent from records written by older versions.)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    public_keys: Option<IptPublicKeysRecord>,
+}
+
+/// Record of the public halves of an intro point's keys, as stored on disk
+#[derive(Serialize, Deserialize, Debug)]
+struct IptPublicKeysRecord {
+    /// `KP_hss_ntor`
+    ///
+    /// As raw bytes, since curve25519 public keys don't implement serde.
+    k_hss_ntor: [u8; 32],
+    /// `KP_hs_ipt_sid`
+    k_sid: Ed25519Identity,
 }
 
 //---------- Storing ----------
@@ -63,9 +82,14 @@ pub(super) fn store<R: Runtime, M: Mockable<R>>(
                 .iter()
                 .map(|ipt| {
                     // Convert one IPT - at least, the parts we store here
+                    let k_sid: &ed25519::Keypair = (*ipt.k_sid).as_ref();
                     IptRecord {
                         lid: ipt.lid,
                         is_current: ipt.is_current.is_some(),
+                        public_keys: Some(IptPublicKeysRecord {
+                            k_hss_ntor: *ipt.k_hss_ntor.public().as_bytes(),
+                            k_sid: k_sid.verifying_key().into(),
+                        }),
                     }
                 })
                 .collect_vec();
@@ -146,7 +170,12 @@ impl IptRecord {
         mockable: &mut M,
         relay: &RelayIds,
     ) -> Result<Ipt, StartupError> {
-        let IptRecord { lid, is_current } = self;
+        let IptRecord {
+            lid,
+            is_current,
+            public_keys,
+        } = self;
+        let public_keys = public_keys.and_then(|public_keys| public_keys.load(&imm.nick, lid));
 
         let ipt = Ipt::start_establisher(
             imm,
@@ -155,7 +184,7 @@ impl IptRecord {
             relay,
             lid,
             is_current.then_some(IsCurrent),
-            Some(IptExpectExistingKeys),
+            Some(&IptExpectExistingKeys { public_keys }),
             // last_descriptor_expiry_including_slop
             // is restored by the `import_new_expiry_times` call in `load`
             PromiseLastDescriptorExpiryNoneIsGood {},
@@ -183,3 +212,24 @@ impl IptRecord {
         Ok(ipt)
     }
 }
+
+impl IptPublicKeysRecord {
+    /// Convert the recorded public keys to the in-memory format
+    ///
+    /// Returns `None` (having logged a warning) if the recorded `KP_hs_ipt_sid` is invalid:
+    /// in that case, we can't check the keys we load.
+    fn load(self, nick: &HsNickname, lid: IptLocalId) -> Option<IptPublicKeys> {
+        let IptPublicKeysRecord { k_hss_ntor, k_sid } = self;
+        let k_sid = match ed25519::PublicKey::try_from(k_sid) {
+            Ok(k_sid) => k_sid,
+            Err(e) => {
+                warn!("HS service {nick}: recorded public key for IPT {lid} is invalid, not checking keys: {e}");
+                return None;
+            }
+        };
+        Some(IptPublicKeys {
+            k_hss_ntor: curve25519::PublicKey::from(k_hss_ntor).into(),
+            k_sid: k_sid.into(),
+        })
+    }
+}
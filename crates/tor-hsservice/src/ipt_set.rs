@@ -60,7 +60,7 @@ pub(crate) struct PublishIptSet {
     ///
     ///   * Plus the length of time between a client obtaining the descriptor
     ///     and its introduction request reaching us through the intro point
-    ///     ([`IPT_PUBLISH_EXPIRY_SLOP`])
+    ///     (the `expiry_slop` that was used for publication)
     ///
     /// This field is updated by the publisher, using
     /// [`note_publication_attempt`](PublishIptSet::note_publication_attempt),
@@ -114,6 +114,22 @@ pub(crate) struct IptSet {
     ///
     /// Set by the manager and read by the publisher.
     pub(crate) lifetime: Duration,
+
+    /// Descriptor expiry time slop
+    ///
+    /// How long after our descriptor expired should we continue to maintain an old IPT?
+    /// This is an allowance for:
+    ///
+    ///   - Various RTTs and delays in clients setting up circuits
+    ///     (we can't really measure this ourselves properly,
+    ///     since what matters is the client's latency)
+    ///
+    ///   - Clock skew
+    ///
+    /// Set by the manager (from `ipt_publish_expiry_slop` in the configuration)
+    /// and used by the publisher, in
+    /// [`note_publication_attempt`](PublishIptSet::note_publication_attempt).
+    pub(crate) expiry_slop: Duration,
 }
 
 /// Introduction point as specified to publisher by manager
@@ -138,20 +154,6 @@ pub(crate) struct IptInSet {
 /// Convenience type alias.
 pub(crate) type Ipt = tor_netdoc::doc::hsdesc::IntroPointDesc;
 
-/// Descriptor expiry time slop
-///
-/// How long after our descriptor expired should we continue to maintain an old IPT?
-/// This is an allowance for:
-///
-///   - Various RTTs and delays in clients setting up circuits
-///     (we can't really measure this ourselves properly,
-///     since what matters is the client's latency)
-///
-///   - Clock skew
-//
-// TODO HSS IPT_PUBLISH_EXPIRY_SLOP configure?
-pub(crate) const IPT_PUBLISH_EXPIRY_SLOP: Duration = Duration::from_secs(300); // 5 minutes
-
 /// Shared view of introduction points - IPT manager's view
 ///
 /// This is the manager's end of a bidirectional "channel",
@@ -397,7 +399,7 @@ impl PublishIptSet {
         let new_value = (|| {
             worst_case_end
                 .checked_add(ipts.lifetime)?
-                .checked_add(IPT_PUBLISH_EXPIRY_SLOP)
+                .checked_add(ipts.expiry_slop)
         })()
         .ok_or_else(
             // Clock overflow on the monotonic clock.  Everything is terrible.
@@ -590,6 +592,8 @@ mod test {
         // since this test case doesn't spawn tasks
         let runtime = tor_rtmock::MockRuntime::new();
         runtime.clone().block_on(async move {
+            const EXPIRY_SLOP: Duration = Duration::from_secs(300);
+
             // make a channel; it should have no updates yet
 
             let (_state_mgr, iptpub_state_handle) = create_storage_handles();
@@ -613,6 +617,7 @@ mod test {
             mg.ipts = Some(IptSet {
                 ipts: vec![],
                 lifetime: Duration::ZERO,
+                expiry_slop: EXPIRY_SLOP,
             });
             drop(mg);
 
@@ -644,8 +649,7 @@ mod test {
 
             pv_note_publication_attempt(&runtime, &pv, runtime.now() + PUBLISH_END_TIMEOUT);
 
-            let expected_expiry =
-                runtime.now() + PUBLISH_END_TIMEOUT + LIFETIME + IPT_PUBLISH_EXPIRY_SLOP;
+            let expected_expiry = runtime.now() + PUBLISH_END_TIMEOUT + LIFETIME + EXPIRY_SLOP;
             assert_eq!(mv_get_0_expiry(&mut mv), expected_expiry);
 
             // setting an *earlier* lifetime is ignored
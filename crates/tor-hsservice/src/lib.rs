@@ -66,43 +66,49 @@ mod ipt_mgr;
 mod ipt_set;
 mod keys;
 mod nickname;
+mod offline;
+mod periodic;
 mod replay;
 mod req;
 mod state;
 pub mod status;
 mod svc;
-mod timeout_track;
+mod token_bucket;
 
 // rustdoc doctests can't use crate-public APIs, so are broken if provided for private items.
 // So we export the whole module again under this name.
-// Supports the Example in timeout_track.rs's module-level docs.
+// Supports the Example in time_store.rs's module-level docs.
 //
 // Any out-of-crate user needs to write this ludicrous name in their code,
 // so we don't need to put any warnings in the docs for the individual items.)
 //
-// (`#[doc(hidden)] pub mod timeout_track;` would work for the test but it would
+// (`#[doc(hidden)] pub mod time_store;` would work for the test but it would
 // completely suppress the actual documentation, which is not what we want.)
 #[doc(hidden)]
-pub mod timeout_track_for_doctests_unstable_no_semver_guarantees {
-    pub use crate::timeout_track::*;
-}
-#[doc(hidden)]
 pub mod time_store_for_doctests_unstable_no_semver_guarantees {
     pub use crate::time_store::*;
 }
 
 pub use anon_level::Anonymity;
 pub use config::OnionServiceConfig;
-pub use err::{ClientError, EstablishSessionError, FatalError, IntroRequestError, StartupError};
+#[cfg(feature = "self-test")]
+pub use err::SelfTestError;
+pub use err::{
+    AuthorizedClientConfigError, ClientError, EstablishSessionError, FatalError, IntroRequestError,
+    PurgeError, StartupError,
+};
+pub use ipt_mgr::{AllIptsFaultyCallback, IptRelayScorer};
 pub use keys::{
     BlindIdKeypairSpecifier, BlindIdPublicKeySpecifier, DescSigningKeypairSpecifier,
     HsIdKeypairSpecifier, HsIdPublicKeySpecifier,
 };
 pub use nickname::{HsNickname, InvalidNickname};
+pub use offline::{provision_offline_keys, OfflineKeys};
 pub use req::{RendRequest, StreamRequest};
-pub use state::StateMgr;
+pub use state::{list_services_with_state, purge_service_state, PurgeSummary, StateMgr};
 pub use svc::netdir::NetdirProviderShutdown;
-pub use svc::OnionService;
+pub use svc::publish::{DescriptorUploadLimit, DescriptorUploadObserver};
+pub use svc::{OnionService, OnionServiceBuilder};
 
 use err::IptStoreError;
 
@@ -114,7 +120,7 @@ use err::IptStoreError;
 /// Is a randomly-generated byte string, currently 32 long.
 #[derive(Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd, Adhoc)]
 #[derive_adhoc(SerdeStringOrTransparent)]
-pub(crate) struct IptLocalId([u8; 32]);
+pub struct IptLocalId([u8; 32]);
 
 impl_debug_hex!(IptLocalId.0);
 
@@ -131,7 +137,7 @@ impl Display for IptLocalId {
 #[derive(Debug, Error, Clone, Eq, PartialEq)]
 #[error("invalid IptLocalId")]
 #[non_exhaustive]
-pub(crate) struct InvalidIptLocalId {}
+pub struct InvalidIptLocalId {}
 
 impl FromStr for IptLocalId {
     type Err = InvalidIptLocalId;
@@ -154,7 +160,7 @@ impl IptLocalId {
     }
 }
 
-pub use helpers::handle_rend_requests;
+pub use helpers::{handle_rend_requests, handle_rend_requests_reporting_errors};
 
 #[cfg(test)]
 pub(crate) mod test {
@@ -0,0 +1,266 @@
+//! Support for running an onion service whose identity keypair is kept offline.
+//!
+//! A service whose identity keypair ("`KS_hs_id`") is not in its keystore cannot derive the keys
+//! it needs for each time period. Instead, the operator uses [`provision_offline_keys`] on the
+//! host that has the identity keypair, to generate the keys for some upcoming time periods,
+//! and then imports them into the keystore (and state directory) of the online host
+//! with [`OfflineKeys::insert`].
+//!
+//! When the service runs out of provisioned keys, its publisher stops with
+//! [`FatalError::OfflineKeysExhausted`](crate::FatalError::OfflineKeysExhausted).
+
+use std::sync::Arc;
+
+use rand::{CryptoRng, Rng};
+use serde::{Deserialize, Serialize};
+
+use tor_cert::EncodedEd25519Cert;
+use tor_error::{internal, into_internal, Bug};
+use tor_hscrypto::pk::{HsBlindIdKey, HsDescSigningKeypair, HsIdKeypair};
+use tor_hscrypto::time::TimePeriod;
+use tor_keymgr::{KeyMgr, KeystoreSelector};
+use tor_llcrypto::pk::ed25519;
+use tor_netdoc::doc::hsdesc::create_desc_sign_key_cert;
+use tor_persist::StateMgr;
+
+use crate::svc::publish::HS_DESC_CERT_LIFETIME_SEC;
+use crate::{BlindIdPublicKeySpecifier, DescSigningKeypairSpecifier, FatalError, HsNickname};
+
+/// Handle for the storage of the descriptor signing key certificates of an offline service.
+///
+/// The keystore can't hold certificates, so we keep them in the state directory instead.
+pub(crate) type OfflineCertStorageHandle =
+    dyn tor_persist::StorageHandle<OfflineCertsRecord> + Sync + Send;
+
+/// Return the handle for the certificate storage of the service called `nickname`.
+pub(crate) fn offline_cert_storage_handle<S>(
+    statemgr: S,
+    nickname: &HsNickname,
+) -> Arc<OfflineCertStorageHandle>
+where
+    S: StateMgr + Send + Sync + 'static,
+{
+    statemgr.create_handle(format!("hs_offline_certs_{nickname}"))
+}
+
+/// On-disk record of the descriptor signing key certificates of an offline service.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(crate) struct OfflineCertsRecord {
+    /// The certificates, one per time period.
+    certs: Vec<OfflineCertRecord>,
+}
+
+/// On-disk record of the descriptor signing key certificate for one time period.
+#[derive(Serialize, Deserialize, Debug)]
+struct OfflineCertRecord {
+    /// The length of the time period, in minutes.
+    period_length: u32,
+    /// The index of the time period.
+    period_interval_num: u64,
+    /// The offset of the epoch of the time period, in seconds.
+    period_epoch_offset_in_sec: u32,
+    /// The encoded certificate.
+    cert: Vec<u8>,
+}
+
+impl OfflineCertRecord {
+    /// Return the time period this certificate is for.
+    fn period(&self) -> TimePeriod {
+        TimePeriod::from_parts(
+            self.period_length,
+            self.period_interval_num,
+            self.period_epoch_offset_in_sec,
+        )
+    }
+}
+
+/// The keys an onion service needs for one time period,
+/// if its identity keypair is offline.
+#[derive(Debug)]
+pub struct OfflineKeys {
+    /// The time period these keys are for.
+    period: TimePeriod,
+    /// The blinded public key of the service in `period`.
+    blind_id_key: HsBlindIdKey,
+    /// The descriptor signing keypair of the service in `period`.
+    desc_signing_keypair: HsDescSigningKeypair,
+    /// The certificate of the descriptor signing key, signed by the blinded keypair
+    /// of the service in `period`.
+    desc_signing_key_cert: EncodedEd25519Cert,
+}
+
+impl OfflineKeys {
+    /// Return the time period these keys are for.
+    pub fn period(&self) -> TimePeriod {
+        self.period
+    }
+
+    /// Return the blinded public key of the service in this time period.
+    pub fn blind_id_key(&self) -> &HsBlindIdKey {
+        &self.blind_id_key
+    }
+
+    /// Return the descriptor signing keypair of the service in this time period.
+    pub fn desc_signing_keypair(&self) -> &HsDescSigningKeypair {
+        &self.desc_signing_keypair
+    }
+
+    /// Return the certificate of the descriptor signing key, signed by the blinded keypair
+    /// of the service in this time period.
+    ///
+    /// The certificate expires 54 hours after the end of this time period,
+    /// so it remains valid for as long as any descriptor built in this time period.
+    pub fn desc_signing_key_cert(&self) -> &EncodedEd25519Cert {
+        &self.desc_signing_key_cert
+    }
+
+    /// Return the blinded public key, the descriptor signing keypair, and its certificate.
+    pub(crate) fn into_parts(self) -> (HsBlindIdKey, HsDescSigningKeypair, EncodedEd25519Cert) {
+        (
+            self.blind_id_key,
+            self.desc_signing_keypair,
+            self.desc_signing_key_cert,
+        )
+    }
+
+    /// Import these keys into the keystore and state of the service called `nickname`.
+    ///
+    /// The descriptor signing keypair is stored under a [`DescSigningKeypairSpecifier`],
+    /// and the blinded public key under a [`BlindIdPublicKeySpecifier`].
+    /// The certificate of the descriptor signing key is stored in `statemgr`,
+    /// which must be the state manager of the service, and must be locked.
+    pub fn insert<S>(
+        self,
+        keymgr: &KeyMgr,
+        statemgr: &S,
+        nickname: &HsNickname,
+        selector: KeystoreSelector<'_>,
+    ) -> Result<(), FatalError>
+    where
+        S: StateMgr + Send + Sync + 'static,
+    {
+        let Self {
+            period,
+            blind_id_key,
+            desc_signing_keypair,
+            desc_signing_key_cert,
+        } = self;
+
+        keymgr.insert(
+            blind_id_key,
+            &BlindIdPublicKeySpecifier::new(nickname.clone(), period),
+            selector,
+        )?;
+        keymgr.insert(
+            desc_signing_keypair,
+            &DescSigningKeypairSpecifier::new(nickname.clone(), period),
+            selector,
+        )?;
+
+        // TODO HSS: remove the certificates of time periods that have long expired.
+        let storage = offline_cert_storage_handle(statemgr.clone(), nickname);
+        let mut record = storage
+            .load()
+            .map_err(FatalError::OfflineCertStore)?
+            .unwrap_or_default();
+        record.certs.retain(|cert| cert.period() != period);
+        record.certs.push(OfflineCertRecord {
+            period_length: period.length().as_minutes(),
+            period_interval_num: period.interval_num(),
+            period_epoch_offset_in_sec: period.epoch_offset_in_sec(),
+            cert: desc_signing_key_cert.into(),
+        });
+        storage.store(&record).map_err(FatalError::OfflineCertStore)
+    }
+
+    /// Read the keys that were imported for `period` into the keystore and state of
+    /// the service called `nickname` (see [`OfflineKeys::insert`]).
+    ///
+    /// Returns [`FatalError::OfflineKeysExhausted`] if any of them is missing.
+    pub(crate) fn read(
+        keymgr: &KeyMgr,
+        storage: &Arc<OfflineCertStorageHandle>,
+        nickname: &HsNickname,
+        period: TimePeriod,
+    ) -> Result<Self, FatalError> {
+        let exhausted = || FatalError::OfflineKeysExhausted {
+            nickname: nickname.clone(),
+            period,
+        };
+
+        let blind_id_key = keymgr
+            .get::<HsBlindIdKey>(&BlindIdPublicKeySpecifier::new(nickname.clone(), period))?
+            .ok_or_else(exhausted)?;
+        let desc_signing_keypair = keymgr
+            .get::<HsDescSigningKeypair>(&DescSigningKeypairSpecifier::new(
+                nickname.clone(),
+                period,
+            ))?
+            .ok_or_else(exhausted)?;
+        let desc_signing_key_cert = storage
+            .load()
+            .map_err(FatalError::OfflineCertStore)?
+            .and_then(|record| {
+                record
+                    .certs
+                    .into_iter()
+                    .find(|cert| cert.period() == period)
+            })
+            .map(|cert| EncodedEd25519Cert::dangerously_from_bytes(&cert.cert))
+            .ok_or_else(exhausted)?;
+
+        Ok(OfflineKeys {
+            period,
+            blind_id_key,
+            desc_signing_keypair,
+            desc_signing_key_cert,
+        })
+    }
+}
+
+/// Generate the keys needed by a service with the identity keypair `hsid_kp`,
+/// for each of the specified time `periods`.
+///
+/// This is meant to be run on the (offline) host that has the identity keypair.
+/// The blinded keys are derived from the identity keypair exactly as an online service
+/// would derive them, and a fresh descriptor signing keypair is generated for each period,
+/// along with its certificate (signed by the blinded keypair for that period).
+///
+/// The keys are returned in the same order as `periods`.
+pub fn provision_offline_keys<R: Rng + CryptoRng>(
+    hsid_kp: &HsIdKeypair,
+    periods: impl IntoIterator<Item = TimePeriod>,
+    rng: &mut R,
+) -> Result<Vec<OfflineKeys>, Bug> {
+    periods
+        .into_iter()
+        .map(|period| {
+            let (blind_id_key, blind_id_kp, _subcredential) =
+                hsid_kp
+                    .compute_blinded_key(period)
+                    .map_err(|_| internal!("failed to compute blinded key"))?;
+            let desc_signing_keypair: HsDescSigningKeypair =
+                ed25519::Keypair::generate(&mut *rng).into();
+
+            // The descriptors for `period` are built (at the latest) at the end of `period`,
+            // and must remain usable for as long as a descriptor built then would be.
+            let period_end = period
+                .range()
+                .map_err(into_internal!("time period out of range"))?
+                .end;
+            let desc_signing_key_cert = create_desc_sign_key_cert(
+                &desc_signing_keypair.as_ref().verifying_key(),
+                &blind_id_kp,
+                period_end + HS_DESC_CERT_LIFETIME_SEC,
+            )
+            .map_err(into_internal!("failed to sign the descriptor signing key"))?;
+
+            Ok(OfflineKeys {
+                period,
+                blind_id_key,
+                desc_signing_keypair,
+                desc_signing_key_cert,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,153 @@
+//! A periodic timer with randomly jittered ticks.
+//!
+//! See [`JitteredInterval`].
+
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::{Future, Stream};
+use rand::Rng;
+use tor_basic_utils::RngExt as _;
+use tor_rtcompat::SleepProvider;
+
+/// A stream that yields `()` about every `period`, give or take `jitter`.
+///
+/// The delay before each tick is chosen uniformly at random from
+/// `period - jitter ..= period + jitter`, independently for each tick,
+/// so that whatever we do on each tick doesn't happen at perfectly regular,
+/// fingerprintable, intervals.
+///
+/// The first tick happens after one such delay, not immediately.
+/// Each delay starts when the previous tick is returned,
+/// so if the stream isn't polled for a while, the ticks don't "catch up".
+///
+/// This stream never ends.
+pub(crate) struct JitteredInterval<R: SleepProvider, G: Rng> {
+    /// The runtime, for sleeping.
+    runtime: R,
+    /// The source of randomness for the jitter.
+    rng: G,
+    /// The mean delay between ticks.
+    period: Duration,
+    /// The maximum deviation of each delay from `period`.
+    ///
+    /// Never more than `period`.
+    jitter: Duration,
+    /// The sleep until the next tick.
+    sleep: Pin<Box<R::SleepFuture>>,
+}
+
+// We never project a pin onto any of our fields (`sleep` is boxed),
+// so we don't need them to be `Unpin`.
+impl<R: SleepProvider, G: Rng> Unpin for JitteredInterval<R, G> {}
+
+impl<R: SleepProvider, G: Rng> JitteredInterval<R, G> {
+    /// Create a new `JitteredInterval`, ticking every `period` ± `jitter`.
+    ///
+    /// If `jitter` is greater than `period`, it is reduced to `period`.
+    pub(crate) fn new(runtime: R, mut rng: G, period: Duration, jitter: Duration) -> Self {
+        let jitter = std::cmp::min(jitter, period);
+        let sleep = Box::pin(runtime.sleep(Self::choose_delay(&mut rng, period, jitter)));
+
+        JitteredInterval {
+            runtime,
+            rng,
+            period,
+            jitter,
+            sleep,
+        }
+    }
+
+    /// Choose the delay until the next tick.
+    fn choose_delay(rng: &mut G, period: Duration, jitter: Duration) -> Duration {
+        // `jitter <= period`, so this doesn't underflow, and the range isn't empty.
+        let earliest = period - jitter;
+        let latest = period.saturating_add(jitter);
+        rng.gen_range_checked(earliest..=latest).unwrap_or(period)
+    }
+}
+
+impl<R: SleepProvider, G: Rng> Stream for JitteredInterval<R, G> {
+    type Item = ();
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<()>> {
+        let this = self.get_mut();
+
+        match this.sleep.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                let delay = Self::choose_delay(&mut this.rng, this.period, this.jitter);
+                this.sleep = Box::pin(this.runtime.sleep(delay));
+                Poll::Ready(Some(()))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use std::collections::HashSet;
+
+    use futures::{FutureExt as _, StreamExt as _};
+    use tor_basic_utils::test_rng::testing_rng;
+    use tor_rtmock::MockRuntime;
+
+    #[test]
+    fn ticks_within_jitter_window() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let period = Duration::from_secs(60);
+            let jitter = Duration::from_secs(10);
+            let step = Duration::from_secs(1);
+            let mut interval =
+                JitteredInterval::new(runtime.clone(), testing_rng(), period, jitter);
+
+            let mut delays = HashSet::new();
+            for _ in 0..20 {
+                let start = runtime.now();
+                // Step through time until the next tick.
+                loop {
+                    if interval.next().now_or_never().is_some() {
+                        break;
+                    }
+                    runtime.advance_by(step).await;
+                }
+                let delay = runtime.now() - start;
+                assert!(delay >= period - jitter, "{delay:?} too early");
+                assert!(delay <= period + jitter, "{delay:?} too late");
+                delays.insert(delay);
+            }
+
+            // The delays aren't all the same.
+            assert!(delays.len() > 1);
+        });
+    }
+
+    #[test]
+    fn jitter_capped_at_period() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let period = Duration::from_secs(10);
+            let mut interval =
+                JitteredInterval::new(runtime.clone(), testing_rng(), period, period * 5);
+
+            for _ in 0..20 {
+                runtime.advance_by(period * 2).await;
+                // Each tick comes within 2 * period.
+                assert!(interval.next().now_or_never().is_some());
+            }
+        });
+    }
+}
@@ -1,13 +1,17 @@
 //! State management utilities for hidden services.
 
-use std::path::Path;
+use std::collections::BTreeSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use fs_mistrust::Mistrust;
+use fslock::LockFile;
 use tor_error::into_internal;
 use tor_hscrypto::pk::{HsId, HsIdKey};
-use tor_keymgr::{ArtiNativeKeystore, KeyMgr, KeyMgrBuilder};
+use tor_keymgr::{ArtiNativeKeystore, KeyMgr, KeyMgrBuilder, KeyPath, KeyPathPattern};
 
-use crate::{HsIdPublicKeySpecifier, HsNickname};
+use crate::{HsIdPublicKeySpecifier, HsNickname, OnionServiceConfig, PurgeError};
 
 /// A helper for managing the persistent state of hidden services.
 //
@@ -46,3 +50,318 @@ impl StateMgr {
             .map(|hsid| hsid.id())
     }
 }
+
+/// Return the nicknames of all the onion services that have state in `state_dir`.
+///
+/// `state_dir` is the Arti state directory (the one passed to
+/// [`OnionService::new`](crate::OnionService::new)).
+/// We look for the services' IPT state files (in the `state` subdirectory),
+/// and for their introduction request replay log directories.
+///
+/// This includes services that are no longer configured, so it can be used
+/// to find state that has been orphaned.
+/// Replay logs kept in a custom
+/// [`replay_log_dir`](crate::config::OnionServiceConfigBuilder::replay_log_dir)
+/// are not found.
+///
+/// Entries whose names aren't valid nicknames are ignored.
+pub fn list_services_with_state(state_dir: impl AsRef<Path>) -> io::Result<BTreeSet<HsNickname>> {
+    let state_dir = state_dir.as_ref();
+    let mut nicknames = BTreeSet::new();
+
+    // The state files written via `tor_persist::FsStateMgr`,
+    // which are named after their storage keys.
+    for name in dir_entry_names(&state_dir.join("state"))? {
+        let nick = ["hs_ipts_", "hs_iptpub_"]
+            .iter()
+            .find_map(|prefix| name.strip_prefix(prefix)?.strip_suffix(".json"));
+        if let Some(nick) = nick {
+            nicknames.extend(HsNickname::try_from(nick.to_owned()).ok());
+        }
+    }
+
+    // The replay log directories, which are named after the services.
+    for name in dir_entry_names(&state_dir.join("hss_iptreplay"))? {
+        nicknames.extend(HsNickname::try_from(name).ok());
+    }
+
+    Ok(nicknames)
+}
+
+/// What [`purge_service_state`] removed
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct PurgeSummary {
+    /// The replay log directory, if there was one
+    pub replay_log_dir: Option<PathBuf>,
+    /// The IPT state files
+    pub state_files: Vec<PathBuf>,
+    /// The keys
+    pub keys: Vec<KeyPath>,
+}
+
+/// Remove all the persistent state of the onion service configured by `config`.
+///
+/// This removes the service's introduction request replay logs,
+/// its IPT state files, and all of its keys (including its identity key!)
+/// from the keystore it is configured to use in `keymgr`.
+/// `state_dir` is the Arti state directory,
+/// as for [`list_services_with_state`].
+///
+/// The replay logs are found the same way the running service finds them,
+/// so a custom
+/// [`replay_log_dir`](crate::config::OnionServiceConfigBuilder::replay_log_dir)
+/// is honoured.
+///
+/// We refuse (returning [`PurgeError::ServiceRunning`]) if the service is running,
+/// in this process or another one.
+/// We detect this via the lock on the replay log directory.
+///
+/// Keys in other keystores are not removed.
+pub fn purge_service_state(
+    state_dir: impl AsRef<Path>,
+    keymgr: &KeyMgr,
+    config: &OnionServiceConfig,
+) -> Result<PurgeSummary, PurgeError> {
+    let nickname = &config.nickname;
+    let state_dir = state_dir.as_ref();
+    let mut summary = PurgeSummary::default();
+
+    let io_error = |action, path: &Path| {
+        let path = path.to_owned();
+        move |cause| PurgeError::Io {
+            action,
+            path,
+            cause: Arc::new(cause),
+        }
+    };
+
+    // The IPT manager holds this lock while the service is running.
+    let replay_log_dir = config.replay_log_dir(state_dir);
+    let lock = if replay_log_dir.exists() {
+        let lock_path = replay_log_dir.join("lock");
+        let mut lock = LockFile::open(&lock_path).map_err(io_error("open", &lock_path))?;
+        if !lock.try_lock().map_err(io_error("lock", &lock_path))? {
+            return Err(PurgeError::ServiceRunning(nickname.clone()));
+        }
+        Some(lock)
+    } else {
+        None
+    };
+
+    for key in [
+        format!("hs_ipts_{nickname}"),
+        format!("hs_iptpub_{nickname}"),
+    ] {
+        // As named by `tor_persist::FsStateMgr`
+        let path = state_dir.join("state").join(format!("{key}.json"));
+        match std::fs::remove_file(&path) {
+            Ok(()) => summary.state_files.push(path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(io_error("remove", &path)(e)),
+        }
+    }
+
+    let keystore_error = |action| move |cause| PurgeError::Keystore { action, cause };
+    let pattern = KeyPathPattern::Arti(format!("hs/{nickname}/**"));
+    let keys = keymgr
+        .list_matching(&pattern)
+        .map_err(keystore_error("list keys"))?;
+    for (key_path, key_type) in keys {
+        let removed = keymgr
+            .remove_with_type(&key_path, &key_type, config.keystore_selector())
+            .map_err(keystore_error("remove key"))?;
+        if removed.is_some() {
+            summary.keys.push(key_path);
+        }
+    }
+
+    // We can't remove a locked file on all platforms, so we must unlock it first.
+    // (If the service is started in the meantime, we may remove its new replay logs
+    // from under it.  That's tolerable: it doesn't need them for correctness.)
+    drop(lock);
+    if replay_log_dir.exists() {
+        std::fs::remove_dir_all(&replay_log_dir).map_err(io_error("remove", &replay_log_dir))?;
+        summary.replay_log_dir = Some(replay_log_dir);
+    }
+
+    Ok(summary)
+}
+
+/// Return the names of the entries in the directory `dir`.
+///
+/// Returns an empty list if `dir` doesn't exist.
+/// Names that aren't valid UTF-8 are skipped.
+fn dir_entry_names(dir: &Path) -> io::Result<Vec<String>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut names = vec![];
+    for entry in entries {
+        if let Ok(name) = entry?.file_name().into_string() {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    use std::fs;
+
+    use tor_basic_utils::test_rng::testing_rng;
+    use tor_hscrypto::pk::{HsIdKeypair, HsIntroPtSessionIdKeypair};
+    use tor_llcrypto::pk::ed25519;
+
+    use tor_keymgr::KeystoreSelector;
+
+    use crate::config::OnionServiceConfigBuilder;
+    use crate::keys::{IptKeyRole, IptKeySpecifier};
+    use crate::svc::test::create_keymgr;
+    use crate::{test_temp_dir, HsIdKeypairSpecifier, IptLocalId};
+
+    #[test]
+    fn list_services() {
+        let state_dir = tempfile::tempdir().unwrap();
+        let state_dir = state_dir.path();
+
+        // Nothing there yet
+        assert!(list_services_with_state(state_dir).unwrap().is_empty());
+
+        // "alpha" has only IPT state; "beta" has only a replay log directory.
+        fs::create_dir_all(state_dir.join("state")).unwrap();
+        fs::write(state_dir.join("state/hs_ipts_alpha.json"), "{}").unwrap();
+        fs::create_dir_all(state_dir.join("hss_iptreplay/beta")).unwrap();
+        // Unrelated state, and an entry that isn't a valid nickname
+        fs::write(state_dir.join("state/guards.json"), "{}").unwrap();
+        fs::create_dir_all(state_dir.join("hss_iptreplay/not a nickname!")).unwrap();
+
+        let nicks = list_services_with_state(state_dir).unwrap();
+        let nicks = nicks.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(nicks, ["alpha", "beta"]);
+    }
+
+    #[test]
+    fn purge_service() {
+        let temp_dir = test_temp_dir!();
+        let keymgr = create_keymgr(&temp_dir);
+        let keymgr = keymgr.into_untracked(); // OK because `keymgr` doesn't outlive `temp_dir`
+        let state_dir = temp_dir.subdir_untracked("state_dir");
+        let mut rng = testing_rng();
+
+        // Provision some state for "victim" and for "bystander"
+        let nicks = ["victim", "bystander"].map(|n| HsNickname::try_from(n.to_owned()).unwrap());
+        fs::create_dir_all(state_dir.join("state")).unwrap();
+        for nick in &nicks {
+            fs::write(state_dir.join(format!("state/hs_ipts_{nick}.json")), "{}").unwrap();
+            fs::create_dir_all(state_dir.join(format!("hss_iptreplay/{nick}"))).unwrap();
+            fs::write(state_dir.join(format!("hss_iptreplay/{nick}/0.bin")), "").unwrap();
+
+            let id_keypair = HsIdKeypair::from(ed25519::ExpandedKeypair::from(
+                &ed25519::Keypair::generate(&mut rng),
+            ));
+            keymgr
+                .insert(
+                    id_keypair,
+                    &HsIdKeypairSpecifier::new(nick.clone()),
+                    KeystoreSelector::Default,
+                )
+                .unwrap();
+            let ipt_key_spec = IptKeySpecifier {
+                nick: nick.clone(),
+                role: IptKeyRole::KSid,
+                lid: IptLocalId::dummy(1),
+            };
+            keymgr
+                .generate::<HsIntroPtSessionIdKeypair>(
+                    &ipt_key_spec,
+                    KeystoreSelector::Default,
+                    &mut rng,
+                    false,
+                )
+                .unwrap();
+        }
+        let [victim, bystander] = &nicks;
+        let victim_config = OnionServiceConfigBuilder::default()
+            .nickname(victim.clone())
+            .build()
+            .unwrap();
+
+        // We refuse while the service is running
+        {
+            let mut lock = LockFile::open(&state_dir.join("hss_iptreplay/victim/lock")).unwrap();
+            assert!(lock.try_lock().unwrap());
+            let err = purge_service_state(&state_dir, &keymgr, &victim_config).unwrap_err();
+            assert!(matches!(err, PurgeError::ServiceRunning(_)), "{err:?}");
+        }
+
+        let summary = purge_service_state(&state_dir, &keymgr, &victim_config).unwrap();
+        assert_eq!(
+            summary.replay_log_dir,
+            Some(state_dir.join("hss_iptreplay/victim"))
+        );
+        assert_eq!(
+            summary.state_files,
+            [state_dir.join("state/hs_ipts_victim.json")]
+        );
+        assert_eq!(summary.keys.len(), 2);
+
+        // Nothing of the victim remains, but the bystander is untouched
+        let listed = list_services_with_state(&state_dir).unwrap();
+        assert_eq!(listed.into_iter().collect::<Vec<_>>(), [bystander.clone()]);
+        let pattern = |nick| KeyPathPattern::Arti(format!("hs/{nick}/**"));
+        assert!(keymgr.list_matching(&pattern(victim)).unwrap().is_empty());
+        assert_eq!(keymgr.list_matching(&pattern(bystander)).unwrap().len(), 2);
+
+        // Purging again finds nothing to do
+        let summary = purge_service_state(&state_dir, &keymgr, &victim_config).unwrap();
+        assert!(summary.replay_log_dir.is_none());
+        assert!(summary.state_files.is_empty());
+        assert!(summary.keys.is_empty());
+    }
+
+    #[test]
+    fn purge_service_custom_replay_log_dir() {
+        let temp_dir = test_temp_dir!();
+        let keymgr = create_keymgr(&temp_dir);
+        let keymgr = keymgr.into_untracked(); // OK because `keymgr` doesn't outlive `temp_dir`
+        let state_dir = temp_dir.subdir_untracked("state_dir");
+        let replay_log_dir = temp_dir.subdir_untracked("fast_storage");
+        fs::create_dir_all(&replay_log_dir).unwrap();
+        fs::write(replay_log_dir.join("0.bin"), "").unwrap();
+
+        let config = OnionServiceConfigBuilder::default()
+            .nickname("victim".to_string().try_into().unwrap())
+            .replay_log_dir(Some(replay_log_dir.clone()))
+            .build()
+            .unwrap();
+
+        // The lock in the custom directory is the one that tells us the service is running
+        {
+            let mut lock = LockFile::open(&replay_log_dir.join("lock")).unwrap();
+            assert!(lock.try_lock().unwrap());
+            let err = purge_service_state(&state_dir, &keymgr, &config).unwrap_err();
+            assert!(matches!(err, PurgeError::ServiceRunning(_)), "{err:?}");
+        }
+
+        let summary = purge_service_state(&state_dir, &keymgr, &config).unwrap();
+        assert_eq!(summary.replay_log_dir, Some(replay_log_dir.clone()));
+        assert!(!replay_log_dir.exists());
+    }
+}
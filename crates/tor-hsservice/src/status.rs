@@ -7,6 +7,12 @@ use std::{
 
 use futures::StreamExt as _;
 use tor_async_utils::PostageWatchSenderExt;
+use tor_hscrypto::time::TimePeriod;
+use tor_hscrypto::RevisionCounter;
+use tor_linkspec::RelayIds;
+use tracing::debug;
+
+use crate::{HsNickname, IptLocalId};
 
 /// The current reported status of an onion service.
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -19,6 +25,13 @@ pub struct OnionServiceStatus {
 
     /// The current high-level state for the descriptor publisher.
     publisher_state: State,
+
+    /// Whether the descriptor publisher has been waiting for introduction points
+    /// for longer than the configured `ipt_wait_timeout`.
+    awaiting_ipts_timed_out: bool,
+
+    /// How many times we have seen the monotonic clock go backwards.
+    monotonic_clock_regressions: u64,
     // TODO HSS: Add key expiration
     // TODO HSS: Add latest-error.
     //
@@ -73,6 +86,8 @@ impl OnionServiceStatus {
             state: State::Shutdown,
             ipt_mgr_state: State::Shutdown,
             publisher_state: State::Shutdown,
+            awaiting_ipts_timed_out: false,
+            monotonic_clock_regressions: 0,
         }
     }
 
@@ -85,6 +100,7 @@ impl OnionServiceStatus {
 
         match (self.ipt_mgr_state, self.publisher_state) {
             (Shutdown, _) | (_, Shutdown) => Shutdown,
+            _ if self.awaiting_ipts_timed_out => Broken,
             (Bootstrapping, _) | (_, Bootstrapping) => Bootstrapping,
             (Running, Running) => Running,
             (Recovering, _) | (_, Recovering) => Recovering,
@@ -106,6 +122,32 @@ impl OnionServiceStatus {
         None
     }
 
+    /// Return the current state of the descriptor publisher.
+    #[cfg(test)]
+    pub(crate) fn publisher_state(&self) -> State {
+        self.publisher_state
+    }
+
+    /// Return true if publication is stuck waiting for introduction points.
+    ///
+    /// This is the case if the descriptor publisher has been unable to publish
+    /// for longer than the configured `ipt_wait_timeout`,
+    /// because we don't have any introduction points to put in the descriptor.
+    /// While this is the case, the service is reported as [`Broken`](State::Broken),
+    /// regardless of what the introduction point manager is doing.
+    pub fn awaiting_ipts_timed_out(&self) -> bool {
+        self.awaiting_ipts_timed_out
+    }
+
+    /// Return the number of times we have seen the monotonic clock go backwards.
+    ///
+    /// This should always be zero.  If it isn't, the system clock is broken:
+    /// we tolerate this by pretending that time stood still, but the service
+    /// may not behave as well as it should.
+    pub fn monotonic_clock_regressions(&self) -> u64 {
+        self.monotonic_clock_regressions
+    }
+
     /// Return a time before which the user must re-provision this onion service
     /// with new keys.
     ///
@@ -139,20 +181,101 @@ impl futures::Stream for OnionServiceStatusStream {
     }
 }
 
+/// How far an onion service has got with starting up.
+///
+/// The phases happen in this order.
+/// Once the service is [`Reachable`](StartupProgress::Reachable),
+/// its startup progress doesn't change any more:
+/// any later problems are reported in its [`OnionServiceStatus`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum StartupProgress {
+    /// We are selecting relays to use as introduction points.
+    SelectingIpts,
+    /// We are establishing introduction points.
+    EstablishingIpts {
+        /// The number of introduction points we have established.
+        good: usize,
+        /// The number of introduction points we want.
+        target: usize,
+    },
+    /// We are uploading our descriptor to the HsDirs.
+    Publishing,
+    /// Our descriptor has been uploaded to enough HsDirs for clients to reach us.
+    Reachable,
+}
+
+impl StartupProgress {
+    /// Return the position of this phase in the startup sequence.
+    fn phase(&self) -> u8 {
+        match self {
+            StartupProgress::SelectingIpts => 0,
+            StartupProgress::EstablishingIpts { .. } => 1,
+            StartupProgress::Publishing => 2,
+            StartupProgress::Reachable => 3,
+        }
+    }
+}
+
+/// A stream of [`StartupProgress`] events, returned by an onion service.
+///
+/// Like [`OnionServiceStatusStream`], this only yields the most recent progress:
+/// if the receiver does not read it as fast as it is reported,
+/// some intermediate events will be lost.
+//
+// We define this so that we aren't exposing postage in our public API.
+#[derive(Clone)]
+pub struct StartupProgressStream(postage::watch::Receiver<StartupProgress>);
+
+impl StartupProgressStream {
+    /// Wait until the service is [`Reachable`](StartupProgress::Reachable).
+    pub(crate) async fn wait_until_reachable(mut self) {
+        while let Some(progress) = self.next().await {
+            if progress == StartupProgress::Reachable {
+                return;
+            }
+        }
+        // The service has gone away, so it will never be reachable.
+        futures::future::pending::<()>().await;
+    }
+}
+
+impl futures::Stream for StartupProgressStream {
+    type Item = StartupProgress;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.0.poll_next_unpin(cx)
+    }
+}
+
 /// A shared handle to a postage::watch::Sender that we can use to update an OnionServiceStatus.
+///
+/// Also used to report our [`StartupProgress`].
 //
 // TODO HSS: Possibly, we don't need this to be Clone: as we implement the code
 // that adjusts the status, we might find that only a single location needs to
 // hold the Sender.  If that turns out to be the case, we should remove the
 // `Arc<Mutex<.>>` here.  If not, we should remove this comment.
 #[derive(Clone)]
-pub(crate) struct StatusSender(Arc<Mutex<postage::watch::Sender<OnionServiceStatus>>>);
+pub(crate) struct StatusSender {
+    /// The sender for our status.
+    status: Arc<Mutex<postage::watch::Sender<OnionServiceStatus>>>,
+    /// The sender for our startup progress.
+    progress: Arc<Mutex<postage::watch::Sender<StartupProgress>>>,
+}
 
 impl StatusSender {
     /// Create a new StatusSender with a given initial status.
     pub(crate) fn new(initial_status: OnionServiceStatus) -> Self {
         let (tx, _) = postage::watch::channel_with(initial_status);
-        StatusSender(Arc::new(Mutex::new(tx)))
+        let (progress_tx, _) = postage::watch::channel_with(StartupProgress::SelectingIpts);
+        StatusSender {
+            status: Arc::new(Mutex::new(tx)),
+            progress: Arc::new(Mutex::new(progress_tx)),
+        }
     }
 
     /// Update the current IPT manager state.
@@ -160,9 +283,8 @@ impl StatusSender {
     /// If the new state is different, update the current status and notify all listeners.
     //
     // TODO: should we have separate state enums for the IPT mgr and publisher states?
-    #[allow(dead_code)]
     pub(crate) fn maybe_update_ipt_mgr(&self, state: State) {
-        let mut tx = self.0.lock().expect("Poisoned lock");
+        let mut tx = self.status.lock().expect("Poisoned lock");
         let mut svc_status = tx.borrow().clone();
         svc_status.ipt_mgr_state = state;
         tx.maybe_send(|_| svc_status);
@@ -171,21 +293,376 @@ impl StatusSender {
     /// Update the current publisher state.
     ///
     /// If the new state is different, update the current status and notify all listeners.
-    #[allow(dead_code)]
     pub(crate) fn maybe_update_publisher(&self, state: State) {
-        let mut tx = self.0.lock().expect("Poisoned lock");
+        let mut tx = self.status.lock().expect("Poisoned lock");
         let mut svc_status = tx.borrow().clone();
         svc_status.publisher_state = state;
         tx.maybe_send(|_| svc_status);
     }
 
+    /// Record whether the publisher has been waiting for introduction points for too long.
+    ///
+    /// While it has, the publisher state is `Broken`.
+    /// When it stops waiting, the publisher state becomes `Recovering`,
+    /// until the outcome of its next uploads is known.
+    pub(crate) fn note_awaiting_ipts_timed_out(&self, timed_out: bool) {
+        let mut tx = self.status.lock().expect("Poisoned lock");
+        let mut svc_status = tx.borrow().clone();
+        if timed_out {
+            svc_status.publisher_state = State::Broken;
+        } else if svc_status.awaiting_ipts_timed_out {
+            svc_status.publisher_state = State::Recovering;
+        }
+        svc_status.awaiting_ipts_timed_out = timed_out;
+        tx.maybe_send(|_| svc_status);
+    }
+
+    /// Record whether the IPT manager has as many good introduction points as it wants.
+    ///
+    /// While it does, the IPT manager state is `Running`.
+    /// If it has been running, and then loses some of them, the state becomes `Recovering`.
+    ///
+    /// This has no effect while the IPT manager state is `Broken`:
+    /// the IPT manager reports when it starts to recover from whatever broke it.
+    pub(crate) fn note_ipts_good_enough(&self, good_enough: bool) {
+        let mut tx = self.status.lock().expect("Poisoned lock");
+        let mut svc_status = tx.borrow().clone();
+        svc_status.ipt_mgr_state = match (svc_status.ipt_mgr_state, good_enough) {
+            (State::Broken, _) => return,
+            (_, true) => State::Running,
+            (State::Running | State::Recovering, false) => State::Recovering,
+            (other, false) => other,
+        };
+        tx.maybe_send(|_| svc_status);
+    }
+
+    /// Record that we have seen the monotonic clock go backwards, and notify all listeners.
+    pub(crate) fn note_monotonic_clock_regression(&self) {
+        let mut tx = self.status.lock().expect("Poisoned lock");
+        let mut svc_status = tx.borrow().clone();
+        svc_status.monotonic_clock_regressions =
+            svc_status.monotonic_clock_regressions.saturating_add(1);
+        tx.maybe_send(|_| svc_status);
+    }
+
     /// Return a copy of the current status.
     pub(crate) fn get(&self) -> OnionServiceStatus {
-        self.0.lock().expect("Poisoned lock").borrow().clone()
+        self.status.lock().expect("Poisoned lock").borrow().clone()
     }
 
     /// Return a new OnionServiceStatusStream to return events from this StatusSender.
     pub(crate) fn subscribe(&self) -> OnionServiceStatusStream {
-        OnionServiceStatusStream(self.0.lock().expect("Poisoned lock").subscribe())
+        OnionServiceStatusStream(self.status.lock().expect("Poisoned lock").subscribe())
+    }
+
+    /// Record our progress with establishing introduction points.
+    ///
+    /// `n_ipts` is the number of introduction points we currently have,
+    /// of which `good` are established; we want `target`.
+    pub(crate) fn note_ipt_progress(&self, n_ipts: usize, good: usize, target: usize) {
+        let progress = if n_ipts == 0 {
+            StartupProgress::SelectingIpts
+        } else {
+            StartupProgress::EstablishingIpts { good, target }
+        };
+        self.advance_progress(progress);
+    }
+
+    /// Record that we have started uploading our descriptor.
+    pub(crate) fn note_publishing(&self) {
+        self.advance_progress(StartupProgress::Publishing);
+    }
+
+    /// Record that our descriptor has been uploaded to enough HsDirs.
+    pub(crate) fn note_reachable(&self) {
+        self.advance_progress(StartupProgress::Reachable);
+    }
+
+    /// Update our startup progress, and notify all listeners of any change.
+    ///
+    /// We never go back to an earlier phase.
+    fn advance_progress(&self, progress: StartupProgress) {
+        let mut tx = self.progress.lock().expect("Poisoned lock");
+        if progress.phase() >= tx.borrow().phase() {
+            tx.maybe_send(|_| progress);
+        }
+    }
+
+    /// Return our current startup progress.
+    pub(crate) fn get_progress(&self) -> StartupProgress {
+        *self.progress.lock().expect("Poisoned lock").borrow()
+    }
+
+    /// Return a new StartupProgressStream to return events from this StatusSender.
+    pub(crate) fn subscribe_progress(&self) -> StartupProgressStream {
+        StartupProgressStream(self.progress.lock().expect("Poisoned lock").subscribe())
+    }
+}
+
+/// Notification that one of our introduction points has become faulty.
+///
+/// This is reported whenever an introduction point that was being established,
+/// or that was working, fails.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct IptFailureEvent {
+    /// The local identifier of the introduction point.
+    lid: IptLocalId,
+    /// The relay hosting the introduction point.
+    relay: RelayIds,
+    /// The number of times this introduction point has become faulty.
+    n_faults: u32,
+}
+
+impl IptFailureEvent {
+    /// Create a new `IptFailureEvent`.
+    pub(crate) fn new(lid: IptLocalId, relay: RelayIds, n_faults: u32) -> Self {
+        Self {
+            lid,
+            relay,
+            n_faults,
+        }
+    }
+
+    /// Return the local identifier of the introduction point that failed.
+    pub fn lid(&self) -> IptLocalId {
+        self.lid
+    }
+
+    /// Return the identities of the relay hosting the introduction point that failed.
+    pub fn relay(&self) -> &RelayIds {
+        &self.relay
+    }
+
+    /// Return the number of times this introduction point has become faulty.
+    pub fn n_faults(&self) -> u32 {
+        self.n_faults
     }
 }
+
+/// A stream of [`IptFailureEvent`]s, returned by an onion service.
+pub type IptFailureEventStream = EventStream<IptFailureEvent>;
+
+/// Notification that the set of introduction points we publish has changed.
+///
+/// This is reported whenever we start publishing introduction points,
+/// stop publishing them, or change which ones we publish.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PublishedIptSetEvent {
+    /// The local identifiers of the introduction points we now publish.
+    ///
+    /// Empty if we are not publishing any introduction points.
+    lids: Vec<IptLocalId>,
+}
+
+impl PublishedIptSetEvent {
+    /// Create a new `PublishedIptSetEvent`.
+    pub(crate) fn new(lids: Vec<IptLocalId>) -> Self {
+        Self { lids }
+    }
+
+    /// Return the number of introduction points we now publish.
+    pub fn n_ipts(&self) -> usize {
+        self.lids.len()
+    }
+
+    /// Return the local identifiers of the introduction points we now publish.
+    pub fn lids(&self) -> &[IptLocalId] {
+        &self.lids
+    }
+}
+
+/// A stream of [`PublishedIptSetEvent`]s, returned by an onion service.
+pub type PublishedIptSetEventStream = EventStream<PublishedIptSetEvent>;
+
+/// Notification that the set of time periods for which we publish descriptors has changed.
+///
+/// This is reported whenever a new consensus causes time periods
+/// to become relevant, or to stop being relevant.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TimePeriodChangeEvent {
+    /// The time periods that have become relevant.
+    added: Vec<TimePeriod>,
+    /// The time periods that are no longer relevant.
+    removed: Vec<TimePeriod>,
+}
+
+impl TimePeriodChangeEvent {
+    /// Create a new `TimePeriodChangeEvent`.
+    pub(crate) fn new(added: Vec<TimePeriod>, removed: Vec<TimePeriod>) -> Self {
+        Self { added, removed }
+    }
+
+    /// Return the time periods that have become relevant.
+    pub fn added(&self) -> &[TimePeriod] {
+        &self.added
+    }
+
+    /// Return the time periods that are no longer relevant.
+    pub fn removed(&self) -> &[TimePeriod] {
+        &self.removed
+    }
+}
+
+/// A stream of [`TimePeriodChangeEvent`]s, returned by an onion service.
+pub type TimePeriodChangeEventStream = EventStream<TimePeriodChangeEvent>;
+
+/// A stream of events of type `T`, returned by an onion service.
+///
+/// Unlike [`OnionServiceStatusStream`], this yields every event
+/// that happens after it was created, since each one only describes a change.
+/// The service keeps a small buffer of events for each stream:
+/// if a stream falls so far behind that the buffer is full,
+/// new events are discarded until it catches up.
+//
+// We define this so that we aren't exposing postage in our public API.
+#[derive(Clone)]
+pub struct EventStream<T>(postage::broadcast::Receiver<T>);
+
+impl<T> EventStream<T> {
+    /// Create a new `EventStream` from a `postage::broadcast::Receiver`.
+    pub(crate) fn new(rx: postage::broadcast::Receiver<T>) -> Self {
+        Self(rx)
+    }
+}
+
+impl<T: Clone> futures::Stream for EventStream<T> {
+    type Item = T;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.0.poll_next_unpin(cx)
+    }
+}
+
+/// Send `event` to each of the subscribers of `tx`, without waiting for any of them.
+///
+/// A subscriber that isn't keeping up misses the event:
+/// there's nothing sensible we can do about that (we mustn't wait for it),
+/// so we just log it, describing the event as `what`.
+pub(crate) fn send_event<T: Clone>(
+    tx: &mut postage::broadcast::Sender<T>,
+    event: T,
+    nickname: &HsNickname,
+    what: &str,
+) {
+    let sent = postage::sink::Sink::try_send(tx, event);
+    if let Err(postage::sink::TrySendError::Pending(_)) = sent {
+        debug!(%nickname, "{what} subscriber is lagging; dropping event");
+    }
+}
+
+/// The time at which our descriptor was last successfully uploaded,
+/// for a given time period.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct DescriptorUploadTime {
+    /// The time period of the descriptor.
+    time_period: TimePeriod,
+    /// When the upload completed.
+    uploaded_at: SystemTime,
+}
+
+impl DescriptorUploadTime {
+    /// Create a new `DescriptorUploadTime`.
+    pub(crate) fn new(time_period: TimePeriod, uploaded_at: SystemTime) -> Self {
+        Self {
+            time_period,
+            uploaded_at,
+        }
+    }
+
+    /// Return the time period of the descriptor.
+    pub fn time_period(&self) -> TimePeriod {
+        self.time_period
+    }
+
+    /// Return the time at which the descriptor last reached an HsDir.
+    pub fn uploaded_at(&self) -> SystemTime {
+        self.uploaded_at
+    }
+}
+
+/// A shared record of the last successful upload of our descriptor,
+/// for each relevant time period.
+///
+/// Written by the descriptor publisher, and read by the [`OnionService`](crate::OnionService).
+pub(crate) type DescriptorUploadTimes = Arc<Mutex<Vec<DescriptorUploadTime>>>;
+
+/// The outcome of uploading a descriptor.
+//
+// TODO: consider making this a type alias for Result<(), ()>
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum UploadStatus {
+    /// The descriptor upload succeeded.
+    Success,
+    /// The descriptor upload failed.
+    Failure,
+}
+
+/// The outcome of uploading a descriptor to a particular HsDir.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HsDirUploadStatus {
+    /// The identity of the HsDir we attempted to upload the descriptor to.
+    pub(crate) relay_ids: RelayIds,
+    /// The outcome of this attempt.
+    pub(crate) upload_res: UploadStatus,
+    /// The revision counter of the descriptor we tried to upload.
+    pub(crate) revision_counter: RevisionCounter,
+}
+
+impl HsDirUploadStatus {
+    /// Return the identities of the HsDir we attempted to upload the descriptor to.
+    pub fn relay_ids(&self) -> &RelayIds {
+        &self.relay_ids
+    }
+
+    /// Return the outcome of the upload.
+    pub fn upload_status(&self) -> UploadStatus {
+        self.upload_res
+    }
+
+    /// Return the revision counter of the descriptor we tried to upload.
+    pub fn revision_counter(&self) -> RevisionCounter {
+        self.revision_counter
+    }
+}
+
+/// The outcome of our most recent upload to each HsDir,
+/// for a given time period.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TimePeriodUploadStatus {
+    /// The time period of the descriptor.
+    time_period: TimePeriod,
+    /// The outcome of the most recent upload to each HsDir.
+    hsdirs: Vec<HsDirUploadStatus>,
+}
+
+impl TimePeriodUploadStatus {
+    /// Create a new `TimePeriodUploadStatus`.
+    pub(crate) fn new(time_period: TimePeriod, hsdirs: Vec<HsDirUploadStatus>) -> Self {
+        Self {
+            time_period,
+            hsdirs,
+        }
+    }
+
+    /// Return the time period of the descriptor.
+    pub fn time_period(&self) -> TimePeriod {
+        self.time_period
+    }
+
+    /// Return the outcome of the most recent upload to each HsDir.
+    ///
+    /// HsDirs we haven't tried to upload to yet are omitted.
+    pub fn hsdirs(&self) -> &[HsDirUploadStatus] {
+        &self.hsdirs
+    }
+}
+
+/// A shared record of the outcome of our most recent upload to each HsDir,
+/// for each relevant time period.
+///
+/// Written by the descriptor publisher, and read by the [`OnionService`](crate::OnionService).
+pub(crate) type HsDirUploadStatuses = Arc<Mutex<Vec<TimePeriodUploadStatus>>>;
@@ -4,6 +4,7 @@ pub(crate) mod netdir;
 
 use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use futures::channel::mpsc;
 use futures::channel::oneshot;
@@ -19,17 +20,26 @@ use tor_hscrypto::pk::HsIdKey;
 use tor_hscrypto::pk::HsIdKeypair;
 use tor_keymgr::KeyMgr;
 use tor_keymgr::KeystoreSelector;
+use tor_linkspec::RelayId;
 use tor_llcrypto::pk::curve25519;
 use tor_llcrypto::pk::ed25519;
 use tor_netdir::NetDirProvider;
-use tor_rtcompat::Runtime;
+use tor_rtcompat::{Runtime, SleepProvider, SleepProviderExt as _, TimeoutError};
 use tracing::{info, warn};
 
-use crate::ipt_mgr::IptManager;
+use crate::config::keystore_selector;
+use crate::ipt_mgr::{
+    AllIptsFaultyCallback, AppliedConfigHandle, IptManager, IptMgrDiagnosticsHandle, IptRelayScorer,
+};
 use crate::ipt_set::IptsManagerView;
-use crate::status::{OnionServiceStatus, OnionServiceStatusStream, StatusSender};
+use crate::status::{
+    DescriptorUploadTime, DescriptorUploadTimes, HsDirUploadStatuses, IptFailureEvent,
+    IptFailureEventStream, OnionServiceStatus, OnionServiceStatusStream, PublishedIptSetEvent,
+    PublishedIptSetEventStream, StartupProgress, StartupProgressStream, StatusSender,
+    TimePeriodChangeEvent, TimePeriodChangeEventStream, TimePeriodUploadStatus,
+};
 use crate::svc::keystore_sweeper::KeystoreSweeper;
-use crate::svc::publish::Publisher;
+use crate::svc::publish::{DescriptorUploadLimit, DescriptorUploadObserver, Publisher};
 use crate::HsIdKeypairSpecifier;
 use crate::HsIdPublicKeySpecifier;
 use crate::HsNickname;
@@ -41,6 +51,8 @@ pub(crate) mod ipt_establish;
 pub(crate) mod keystore_sweeper;
 pub(crate) mod publish;
 pub(crate) mod rend_handshake;
+#[cfg(feature = "self-test")]
+pub(crate) mod self_test;
 
 /// Convenience alias for link specifiers of an intro point
 pub(crate) type LinkSpecs = Vec<tor_linkspec::EncodedLinkSpec>;
@@ -77,6 +89,66 @@ struct SvcInner {
     /// this onion service.
     status_tx: StatusSender,
 
+    /// Sender for updates to the list of relays we must not use as introduction points.
+    ipt_blocklist_tx: postage::watch::Sender<Arc<[RelayId]>>,
+
+    /// Sender for updates to the scoring function used to bias introduction point selection.
+    ipt_relay_scorer_tx: postage::watch::Sender<Option<IptRelayScorer>>,
+
+    /// Sender for updates to the function to call when all our introduction points are faulty.
+    all_ipts_faulty_callback_tx: postage::watch::Sender<Option<AllIptsFaultyCallback>>,
+
+    /// Sender for pausing (`true`) or resuming (`false`) descriptor publication.
+    pause_tx: postage::watch::Sender<bool>,
+
+    /// Sender for updates to the function to call with each descriptor before it is uploaded.
+    upload_observer_tx: postage::watch::Sender<Option<DescriptorUploadObserver>>,
+
+    /// Sender for replacing the netdir provider used by the service's tasks.
+    netdir_provider_tx: postage::watch::Sender<Arc<dyn NetDirProvider>>,
+
+    /// The time of the last successful descriptor upload for each time period.
+    ///
+    /// Updated by the publisher.
+    upload_times: DescriptorUploadTimes,
+
+    /// The outcome of the most recent descriptor upload to each HsDir, for each time period.
+    ///
+    /// Updated by the publisher.
+    upload_statuses: HsDirUploadStatuses,
+
+    /// The channel on which the IPT manager reports introduction points
+    /// that have become faulty.
+    ///
+    /// We subscribe to this on behalf of our callers.
+    ipt_failure_tx: broadcast::Sender<IptFailureEvent>,
+
+    /// The channel on which the IPT manager reports changes to the set of
+    /// introduction points we publish.
+    ///
+    /// We subscribe to this on behalf of our callers.
+    published_ipt_set_tx: broadcast::Sender<PublishedIptSetEvent>,
+
+    /// Snapshot of the IPT manager's state, for diagnostics.
+    ///
+    /// Updated by the IPT manager.
+    ipt_mgr_diagnostics: IptMgrDiagnosticsHandle,
+
+    /// The configuration the IPT manager is currently using.
+    ///
+    /// Updated by the IPT manager.
+    applied_config: AppliedConfigHandle,
+
+    /// Used to check that this service is reachable by clients.
+    #[cfg(feature = "self-test")]
+    self_tester: Arc<dyn self_test::SelfTest>,
+
+    /// The channel on which the publisher reports changes in the set of time periods
+    /// we are publishing descriptors for.
+    ///
+    /// We subscribe to this on behalf of our callers.
+    time_period_change_tx: broadcast::Sender<TimePeriodChangeEvent>,
+
     /// Handles that we'll take ownership of when launching the service.
     ///
     /// (TODO HSS: Having to consume this may indicate a design problem.)
@@ -146,15 +218,104 @@ impl From<oneshot::Canceled> for ShutdownStatus {
     }
 }
 
+/// Optional settings for creating an [`OnionService`].
+///
+/// Obtained from [`OnionService::builder`].
+/// Unlike the [`OnionServiceConfig`], these settings are runtime objects,
+/// which can't be written in a configuration file.
+#[derive(Debug, Clone, Default)]
+#[must_use]
+pub struct OnionServiceBuilder {
+    /// A limit on concurrent descriptor uploads, shared with other services.
+    upload_limit: Option<DescriptorUploadLimit>,
+}
+
+impl OnionServiceBuilder {
+    /// Make this service's descriptor uploads count against `limit`,
+    /// along with those of every other service sharing the same limit.
+    pub fn upload_limit(mut self, limit: DescriptorUploadLimit) -> Self {
+        self.upload_limit = Some(limit);
+        self
+    }
+
+    /// Create (but do not launch) a new onion service, with these settings.
+    ///
+    /// The arguments are the same as for [`OnionService::new`].
+    #[allow(clippy::too_many_arguments)] // TODO HSS should there be a builder?
+    pub fn create<R, S>(
+        self,
+        runtime: R,
+        config: OnionServiceConfig,
+        netdir_provider: Arc<dyn NetDirProvider>,
+        circ_pool: Arc<HsCircPool<R>>,
+        keymgr: Arc<KeyMgr>,
+        statemgr: S,
+        state_dir: &Path,
+        state_mistrust: &fs_mistrust::Mistrust,
+    ) -> Result<Arc<OnionService>, StartupError>
+    where
+        R: Runtime,
+        S: tor_persist::StateMgr + Send + Sync + 'static,
+    {
+        OnionService::create(
+            self,
+            runtime,
+            config,
+            netdir_provider,
+            circ_pool,
+            keymgr,
+            statemgr,
+            state_dir,
+            state_mistrust,
+        )
+    }
+}
+
 impl OnionService {
     /// Create (but do not launch) a new onion service.
+    ///
+    /// To supply optional settings, use [`OnionService::builder`] instead.
+    #[allow(clippy::too_many_arguments)] // TODO HSS should there be a builder?
+    pub fn new<R, S>(
+        runtime: R,
+        config: OnionServiceConfig,
+        netdir_provider: Arc<dyn NetDirProvider>,
+        circ_pool: Arc<HsCircPool<R>>,
+        keymgr: Arc<KeyMgr>,
+        statemgr: S,
+        state_dir: &Path,
+        state_mistrust: &fs_mistrust::Mistrust,
+    ) -> Result<Arc<Self>, StartupError>
+    where
+        R: Runtime,
+        S: tor_persist::StateMgr + Send + Sync + 'static,
+    {
+        Self::builder().create(
+            runtime,
+            config,
+            netdir_provider,
+            circ_pool,
+            keymgr,
+            statemgr,
+            state_dir,
+            state_mistrust,
+        )
+    }
+
+    /// Return a builder for an onion service with optional settings.
+    pub fn builder() -> OnionServiceBuilder {
+        OnionServiceBuilder::default()
+    }
+
+    /// Create (but do not launch) a new onion service, with the settings in `builder`.
     //
     // TODO HSS: How do we handle the case where somebody tries to launch two
     // onion services with the same nickname?  They will conflict by trying to
     // use the same state and the same keys.  Do we stop it here, or in
     // arti_client?
-    #[allow(clippy::too_many_arguments)] // TODO HSS should there be a builder?
-    pub fn new<R, S>(
+    #[allow(clippy::too_many_arguments)]
+    fn create<R, S>(
+        builder: OnionServiceBuilder,
         runtime: R,
         config: OnionServiceConfig,
         netdir_provider: Arc<dyn NetDirProvider>,
@@ -168,6 +329,7 @@ impl OnionService {
         R: Runtime,
         S: tor_persist::StateMgr + Send + Sync + 'static,
     {
+        let OnionServiceBuilder { upload_limit } = builder;
         let nickname = config.nickname.clone();
 
         {
@@ -183,19 +345,33 @@ impl OnionService {
         let iptpub_storage_handle = statemgr
             .clone()
             .create_handle(format!("hs_iptpub_{nickname}"));
+        let offline_cert_storage_handle =
+            crate::offline::offline_cert_storage_handle(statemgr.clone(), &nickname);
 
         let (rend_req_tx, rend_req_rx) = mpsc::channel(32);
         let (shutdown_tx, shutdown_rx) = broadcast::channel(0);
         let (config_tx, config_rx) = postage::watch::channel_with(Arc::new(config));
+        let (ipt_blocklist_tx, ipt_blocklist_rx) = postage::watch::channel_with(Arc::from(vec![]));
+        let (ipt_relay_scorer_tx, ipt_relay_scorer_rx) = postage::watch::channel();
+        let (all_ipts_faulty_callback_tx, all_ipts_faulty_callback_rx) = postage::watch::channel();
+        let (pause_tx, pause_rx) = postage::watch::channel();
+        let (upload_observer_tx, upload_observer_rx) = postage::watch::channel();
+        let (netdir_provider_tx, netdir_provider_rx) =
+            postage::watch::channel_with(netdir_provider);
 
         let (ipt_mgr_view, publisher_view) =
             crate::ipt_set::ipts_channel(&runtime, iptpub_storage_handle)?;
 
+        let status_tx = StatusSender::new(OnionServiceStatus::new_shutdown());
+
         let ipt_mgr = IptManager::new(
             runtime.clone(),
-            netdir_provider.clone(),
+            netdir_provider_rx.clone(),
             nickname.clone(),
             config_rx.clone(),
+            ipt_blocklist_rx,
+            ipt_relay_scorer_rx,
+            all_ipts_faulty_callback_rx,
             rend_req_tx,
             shutdown_rx.clone(),
             statemgr,
@@ -203,33 +379,69 @@ impl OnionService {
                 circ_pool: circ_pool.clone(),
             },
             keymgr.clone(),
+            status_tx.clone(),
             state_dir,
             state_mistrust,
         )?;
+        let ipt_failure_tx = ipt_mgr.ipt_failure_sender();
+        let published_ipt_set_tx = ipt_mgr.published_ipt_set_sender();
+        let ipt_mgr_diagnostics = ipt_mgr.diagnostics();
+        let applied_config = ipt_mgr.applied_config();
 
         // TODO HSS: add a config option for specifying whether to expect the KS_hsid to be stored
         // offline
         //let offline_hsid = config.offline_hsid;
         let offline_hsid = false;
 
-        maybe_generate_hsid(&keymgr, &nickname, offline_hsid)?;
+        // The keystore can't be changed by reconfiguring the service.
+        let keystore = config_rx.borrow().keystore.clone();
+
+        maybe_generate_hsid(
+            &keymgr,
+            &nickname,
+            offline_hsid,
+            keystore_selector(&keystore),
+        )?;
+
+        #[cfg(feature = "self-test")]
+        let self_test_connector = self_test::RealConnector::new(
+            runtime.clone(),
+            Arc::clone(&circ_pool),
+            netdir_provider_rx.clone(),
+        );
 
         let publisher: Publisher<R, publish::Real<R>> = Publisher::new(
             runtime.clone(),
             nickname.clone(),
-            Arc::clone(&netdir_provider),
+            netdir_provider_rx.clone(),
             circ_pool,
             publisher_view,
             config_rx,
             shutdown_rx.clone(),
+            pause_rx,
             Arc::clone(&keymgr),
+            offline_cert_storage_handle,
+            status_tx.clone(),
+            upload_observer_rx,
+            upload_limit,
         );
+        let upload_times = publisher.upload_times();
+        let upload_statuses = publisher.upload_statuses();
+        let time_period_change_tx = publisher.time_period_change_sender();
+
+        #[cfg(feature = "self-test")]
+        let self_tester = Arc::new(self_test::SelfTester::new(
+            runtime.clone(),
+            Arc::clone(&upload_times),
+            self_test_connector,
+        ));
 
         let keystore_sweeper = KeystoreSweeper::new(
             runtime,
             nickname,
             Arc::clone(&keymgr),
-            netdir_provider,
+            keystore,
+            netdir_provider_rx,
             shutdown_rx,
         );
 
@@ -237,15 +449,26 @@ impl OnionService {
         // rend_req_rx.  The latter may need to be refactored to actually work
         // with svc::rend_handshake, if it doesn't already.
 
-        // TODO HSS: We should pass a copy of this to the publisher and/or the
-        // IptMgr, and they should adjust it as needed.
-        let status_tx = StatusSender::new(OnionServiceStatus::new_shutdown());
-
         Ok(Arc::new(OnionService {
             inner: Mutex::new(SvcInner {
                 config_tx,
                 shutdown_tx,
                 status_tx,
+                ipt_blocklist_tx,
+                ipt_relay_scorer_tx,
+                all_ipts_faulty_callback_tx,
+                pause_tx,
+                upload_observer_tx,
+                netdir_provider_tx,
+                upload_times,
+                upload_statuses,
+                ipt_failure_tx,
+                published_ipt_set_tx,
+                ipt_mgr_diagnostics,
+                applied_config,
+                time_period_change_tx,
+                #[cfg(feature = "self-test")]
+                self_tester,
                 keymgr,
                 unlaunched: Some((
                     rend_req_rx,
@@ -262,30 +485,161 @@ impl OnionService {
 
     /// Change the configuration of this onion service.
     ///
-    /// (Not everything can be changed here. At the very least we'll need to say
-    /// that the identity of a service is fixed. We might want to make the
-    /// storage  backing this, and the anonymity status, unchangeable.)
+    /// The new configuration is applied to the running service:
+    /// it is passed on to the introduction point manager and the descriptor publisher,
+    /// which act on it without restarting any introduction points
+    /// that are still wanted.
+    /// (For example, if `num_intro_points` is increased,
+    /// we establish more introduction points, and keep our existing ones.)
+    ///
+    /// Not everything can be changed here:
+    /// the nickname, anonymity, keystore, replay log directory,
+    /// and `rate_limit_rend_requests` of a running service are fixed.
+    /// Attempting to change them is an error,
+    /// unless `how` is [`Reconfigure::WarnOnFailures`],
+    /// in which case we warn, keep their old values, and apply the rest of the new configuration.
     pub fn reconfigure(
         &self,
         new_config: OnionServiceConfig,
         how: Reconfigure,
     ) -> Result<(), ReconfigureError> {
         let mut inner = self.inner.lock().expect("lock poisoned");
-        inner.config_tx.try_maybe_send(|cur_config| {
-            let new_config = cur_config.for_transition_to(new_config, how)?;
-            Ok(match how {
-                // We're only checking, so return the current configuration.
-                tor_config::Reconfigure::CheckAllOrNothing => Arc::clone(cur_config),
-                // We're replacing the configuration, and we didn't get an error.
-                _ => Arc::new(new_config),
-            })
-        })
+        apply_reconfiguration(&mut inner.config_tx, new_config, how)
 
         // TODO HSS: We need to make sure that the various tasks listening on
         // config_rx actually enforce the configuration, not only on new
         // connections, but existing ones.
     }
 
+    /// Return the configuration this onion service is currently using.
+    ///
+    /// After a successful [`reconfigure`](Self::reconfigure),
+    /// this continues to return the old configuration
+    /// until the service has actually applied the new one, which happens shortly afterwards.
+    /// So this can be used to confirm that a reconfiguration has taken effect.
+    pub fn current_config(&self) -> Arc<OnionServiceConfig> {
+        let inner = self.inner.lock().expect("poisoned lock");
+        let config = inner.applied_config.lock().expect("poisoned lock");
+        Arc::clone(&config)
+    }
+
+    /// Replace the set of relays that this onion service must not use as introduction points.
+    ///
+    /// A relay is blocklisted if any of its identities is listed in `relays`.
+    /// We will not select any blocklisted relay as an introduction point,
+    /// and we will promptly retire (and replace) any of our current introduction points
+    /// that are on a blocklisted relay.
+    pub fn set_ipt_relay_blocklist(&self, relays: impl IntoIterator<Item = RelayId>) {
+        let relays = relays.into_iter().collect::<Arc<[RelayId]>>();
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        *inner.ipt_blocklist_tx.borrow_mut() = relays;
+    }
+
+    /// Set (or, with `None`, clear) a function for biasing our choice of introduction points.
+    ///
+    /// When we choose a relay for a new introduction point, each candidate's
+    /// standard selection weight is multiplied by the score `scorer` gives it.
+    /// See [`IptRelayScorer`] for details.
+    ///
+    /// Our existing introduction points are not affected.
+    pub fn set_ipt_relay_scorer(&self, scorer: Option<IptRelayScorer>) {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        *inner.ipt_relay_scorer_tx.borrow_mut() = scorer;
+    }
+
+    /// Set (or, with `None`, clear) a function to call when all our introduction points are faulty.
+    ///
+    /// The function is called when all of our introduction points have been faulty for
+    /// [`ipt_all_faulty_timeout`](crate::config::OnionServiceConfigBuilder::ipt_all_faulty_timeout).
+    /// At that point, we also report the service as [`Broken`](crate::status::State::Broken).
+    /// See [`AllIptsFaultyCallback`] for details.
+    pub fn set_all_ipts_faulty_callback(&self, callback: Option<AllIptsFaultyCallback>) {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        *inner.all_ipts_faulty_callback_tx.borrow_mut() = callback;
+    }
+
+    /// Set (or, with `None`, clear) a function to call with each descriptor before it is uploaded.
+    ///
+    /// The function is given the encoded descriptor, and the identities of the HsDir
+    /// we are about to upload it to.
+    /// See [`DescriptorUploadObserver`] for details.
+    pub fn set_descriptor_upload_observer(&self, observer: Option<DescriptorUploadObserver>) {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        *inner.upload_observer_tx.borrow_mut() = observer;
+    }
+
+    /// Stop publishing descriptors for this onion service.
+    ///
+    /// Our introduction points are kept established,
+    /// so that publication can be resumed quickly with
+    /// [`resume_publication`](Self::resume_publication).
+    ///
+    /// The descriptors we have already published are not withdrawn:
+    /// they remain available from the HsDirs until they expire.
+    pub fn pause_publication(&self) {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        *inner.pause_tx.borrow_mut() = true;
+    }
+
+    /// Resume publishing descriptors for this onion service,
+    /// after a call to [`pause_publication`](Self::pause_publication).
+    ///
+    /// The descriptor is reuploaded to all the HsDirs.
+    pub fn resume_publication(&self) {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        *inner.pause_tx.borrow_mut() = false;
+    }
+
+    /// Replace the [`NetDirProvider`] used by this onion service.
+    ///
+    /// The IPT manager and the descriptor publisher switch to the new provider
+    /// without restarting; the publisher recomputes its HsDirs immediately
+    /// using the new provider's network directory.
+    pub fn replace_netdir_provider(&self, provider: Arc<dyn NetDirProvider>) {
+        let mut inner = self.inner.lock().expect("poisoned lock");
+        *inner.netdir_provider_tx.borrow_mut() = provider;
+    }
+
+    /// Return the time at which our descriptor last reached the HsDirs,
+    /// for each time period we are publishing descriptors for.
+    ///
+    /// Time periods for which no upload has succeeded yet are omitted.
+    pub fn last_successful_uploads(&self) -> Vec<DescriptorUploadTime> {
+        let inner = self.inner.lock().expect("poisoned lock");
+        let upload_times = inner.upload_times.lock().expect("poisoned lock");
+        upload_times.clone()
+    }
+
+    /// Return the outcome of the most recent upload of our descriptor to each HsDir,
+    /// for each time period we are publishing descriptors for.
+    ///
+    /// This is a snapshot: it won't change as the service uploads more descriptors.
+    pub fn hsdir_upload_statuses(&self) -> Vec<TimePeriodUploadStatus> {
+        let inner = self.inner.lock().expect("poisoned lock");
+        let upload_statuses = inner.upload_statuses.lock().expect("poisoned lock");
+        upload_statuses.clone()
+    }
+
+    /// Write a snapshot of the state of our introduction points to `writer`, as JSON.
+    ///
+    /// This is meant for diagnosing problems, for example when asking for support.
+    /// It lists the relays we have selected as introduction points,
+    /// the status of each introduction point and when we plan to retire it,
+    /// when the last descriptor mentioning it expires,
+    /// and which introduction points we are currently publishing.
+    /// It does not include any keys.
+    ///
+    /// The format is not stable.
+    pub fn dump_diagnostics(&self, writer: impl std::io::Write) -> std::io::Result<()> {
+        let diagnostics = {
+            let inner = self.inner.lock().expect("poisoned lock");
+            let diagnostics = inner.ipt_mgr_diagnostics.lock().expect("poisoned lock");
+            diagnostics.clone()
+        };
+        serde_json::to_writer_pretty(writer, &diagnostics)?;
+        Ok(())
+    }
+
     /// Tell this onion service about some new short-term keys it can use.
     pub fn add_keys(&self, keys: ()) -> Result<(), Bug> {
         todo!() // TODO hss
@@ -306,6 +660,91 @@ impl OnionService {
             .subscribe()
     }
 
+    /// Return how far this onion service has got with starting up.
+    ///
+    /// See [`StartupProgress`] for the phases of startup.
+    pub fn startup_progress(&self) -> StartupProgress {
+        self.inner
+            .lock()
+            .expect("poisoned lock")
+            .status_tx
+            .get_progress()
+    }
+
+    /// Return a stream of events that will receive notifications of changes in
+    /// this onion service's startup progress.
+    ///
+    /// The stream first yields the current progress.
+    /// Once the service is [`Reachable`](StartupProgress::Reachable),
+    /// there are no more events.
+    pub fn startup_progress_events(&self) -> StartupProgressStream {
+        self.inner
+            .lock()
+            .expect("poisoned lock")
+            .status_tx
+            .subscribe_progress()
+    }
+
+    /// Wait until this onion service is reachable by clients.
+    ///
+    /// That is, until we have enough introduction points,
+    /// and have published a descriptor listing them to enough HsDirs:
+    /// see [`StartupProgress::Reachable`].
+    /// Returns immediately if the service is already reachable.
+    ///
+    /// This waits indefinitely, and reports no errors:
+    /// if the service never manages to publish its descriptor to enough HsDirs
+    /// (for example, because they keep rejecting our uploads),
+    /// or if it shuts down or fails before becoming reachable,
+    /// this never returns.
+    /// See [`wait_until_reachable_timeout`](Self::wait_until_reachable_timeout)
+    /// for a version with a timeout,
+    /// and [`status_events`](Self::status_events) for finding out about failures.
+    pub async fn wait_until_reachable(&self) {
+        self.startup_progress_events().wait_until_reachable().await;
+    }
+
+    /// Wait until this onion service is reachable by clients, for at most `timeout`.
+    ///
+    /// Like [`wait_until_reachable`](Self::wait_until_reachable),
+    /// but returns an error if the service is still not reachable after `timeout`.
+    pub async fn wait_until_reachable_timeout<R: SleepProvider>(
+        &self,
+        runtime: &R,
+        timeout: Duration,
+    ) -> Result<(), TimeoutError> {
+        runtime.timeout(timeout, self.wait_until_reachable()).await
+    }
+
+    /// Return a stream of notifications about introduction points that have become faulty.
+    ///
+    /// Each event identifies the introduction point and the relay hosting it,
+    /// so that it can be used to spot unreliable relays.
+    pub fn ipt_failure_events(&self) -> IptFailureEventStream {
+        let inner = self.inner.lock().expect("poisoned lock");
+        IptFailureEventStream::new(inner.ipt_failure_tx.subscribe())
+    }
+
+    /// Return a stream of notifications about changes to the set of introduction points
+    /// we publish in our descriptors.
+    ///
+    /// Each event lists the introduction points we publish after the change
+    /// (which is empty if we have stopped publishing any).
+    pub fn published_ipt_set_events(&self) -> PublishedIptSetEventStream {
+        let inner = self.inner.lock().expect("poisoned lock");
+        PublishedIptSetEventStream::new(inner.published_ipt_set_tx.subscribe())
+    }
+
+    /// Return a stream of notifications about changes in the set of time periods
+    /// we are publishing descriptors for.
+    ///
+    /// Each event lists the time periods that a new consensus has made relevant,
+    /// and those that are no longer relevant.
+    pub fn time_period_change_events(&self) -> TimePeriodChangeEventStream {
+        let inner = self.inner.lock().expect("poisoned lock");
+        TimePeriodChangeEventStream::new(inner.time_period_change_tx.subscribe())
+    }
+
     /// Tell this onion service to begin running, and return a
     /// stream of rendezvous requests on the service.
     ///
@@ -349,6 +788,39 @@ impl OnionService {
         todo!() // TODO hss
     }
 
+    /// Check that this onion service is reachable, by connecting to it as a client.
+    ///
+    /// We fetch our own descriptor from the HsDirs,
+    /// and complete a rendezvous with ourselves via one of our introduction points,
+    /// just as any other client would.
+    /// On success, returns how long that took.
+    ///
+    /// The rendezvous can only complete if the [`RendRequest`]s from this service
+    /// are being accepted (for example, by [`handle_rend_requests`](crate::handle_rend_requests)).
+    /// Services that require client authorization can't (yet) be tested this way.
+    #[cfg(feature = "self-test")]
+    pub async fn self_test(&self) -> Result<std::time::Duration, crate::SelfTestError> {
+        let (self_tester, hsid) = {
+            let mut inner = self.inner.lock().expect("poisoned lock");
+
+            let nickname = {
+                let config: postage::watch::Ref<'_, Arc<OnionServiceConfig>> =
+                    postage::watch::Sender::borrow(&mut inner.config_tx);
+                config.nickname().clone()
+            };
+            let pub_hsid_spec = HsIdPublicKeySpecifier::new(nickname);
+            let key = inner
+                .keymgr
+                .get::<HsIdKey>(&pub_hsid_spec)
+                .map_err(crate::SelfTestError::Keystore)?
+                .ok_or_else(|| tor_error::internal!("our identity key is missing"))?;
+
+            (Arc::clone(&inner.self_tester), key.id())
+        };
+
+        self_tester.self_test(hsid).await
+    }
+
     /// Get the .onion associated with this onion service.
     pub fn hostname(&self) -> Result<String, tor_keymgr::Error> {
         let mut inner = self.inner.lock().expect("poisoned lock");
@@ -366,10 +838,13 @@ impl OnionService {
 }
 
 /// Generate the identity key of the service, unless it already exists or `offline_hsid` is `true`.
+///
+/// The key is generated in the keystore selected by `keystore_sel`.
 fn maybe_generate_hsid(
     keymgr: &Arc<KeyMgr>,
     nickname: &HsNickname,
     offline_hsid: bool,
+    keystore_sel: KeystoreSelector<'_>,
 ) -> Result<(), StartupError> {
     let hsid_spec = HsIdKeypairSpecifier::new(nickname.clone());
     let pub_hsid_spec = HsIdPublicKeySpecifier::new(nickname.clone());
@@ -404,8 +879,6 @@ fn maybe_generate_hsid(
             return Err(StartupError::KeystoreCorrupted);
         }
 
-        // TODO HSS: make the selector configurable
-        let keystore_sel = KeystoreSelector::Default;
         let mut rng = rand::thread_rng();
 
         // NOTE: KeyMgr::generate will generate a new hsid keypair and corresponding public
@@ -452,6 +925,26 @@ fn maybe_generate_hsid(
     Ok(())
 }
 
+/// Validate `new_config` as a replacement for the configuration in `config_tx`,
+/// according to the rules of `how`, and send it if appropriate.
+///
+/// This is the implementation of [`OnionService::reconfigure`].
+pub(crate) fn apply_reconfiguration(
+    config_tx: &mut postage::watch::Sender<Arc<OnionServiceConfig>>,
+    new_config: OnionServiceConfig,
+    how: Reconfigure,
+) -> Result<(), ReconfigureError> {
+    config_tx.try_maybe_send(|cur_config| {
+        let new_config = cur_config.for_transition_to(new_config, how)?;
+        Ok(match how {
+            // We're only checking, so return the current configuration.
+            tor_config::Reconfigure::CheckAllOrNothing => Arc::clone(cur_config),
+            // We're replacing the configuration, and we didn't get an error.
+            _ => Arc::new(new_config),
+        })
+    })
+}
+
 #[cfg(test)]
 pub(crate) mod test {
     // @@ begin test lint list maintained by maint/add_warning @@
@@ -469,11 +962,13 @@ pub(crate) mod test {
     use super::*;
 
     use std::fmt::Display;
+    use std::str::FromStr as _;
 
     use fs_mistrust::Mistrust;
 
     use tor_basic_utils::test_rng::testing_rng;
-    use tor_keymgr::{ArtiNativeKeystore, KeyMgrBuilder};
+    use tor_keymgr::test_utils::HookedKeystore;
+    use tor_keymgr::{ArtiNativeKeystore, KeyMgrBuilder, KeystoreId};
 
     use crate::ipt_set::IptSetStorageHandle;
     use crate::test_temp_dir::{TestTempDir, TestTempDirGuard};
@@ -520,6 +1015,20 @@ pub(crate) mod test {
         (state_mgr, iptpub_state_handle)
     }
 
+    /// Make a fresh `KeyMgr` (containing no keys) for a single `ArtiNativeKeystore` in `dir`.
+    fn single_store_keymgr(dir: &Path) -> KeyMgr {
+        let keystore = ArtiNativeKeystore::from_path_and_mistrust(
+            dir,
+            &Mistrust::new_dangerously_trust_everyone(),
+        )
+        .unwrap();
+
+        KeyMgrBuilder::default()
+            .default_store(Box::new(keystore))
+            .build()
+            .unwrap()
+    }
+
     macro_rules! maybe_generate_hsid {
         ($keymgr:expr, $offline_hsid:expr) => {{
             let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
@@ -529,7 +1038,13 @@ pub(crate) mod test {
             assert!($keymgr.get::<HsIdKey>(&pub_hsid_spec).unwrap().is_none());
             assert!($keymgr.get::<HsIdKeypair>(&hsid_spec).unwrap().is_none());
 
-            maybe_generate_hsid(&$keymgr, &nickname, $offline_hsid).unwrap();
+            maybe_generate_hsid(
+                &$keymgr,
+                &nickname,
+                $offline_hsid,
+                KeystoreSelector::Default,
+            )
+            .unwrap();
         }};
     }
 
@@ -544,6 +1059,63 @@ pub(crate) mod test {
         (id_keypair, id_pub)
     }
 
+    #[test]
+    fn generate_hsid_in_selected_keystore() {
+        let temp_dir = test_temp_dir!();
+        let default_dir = temp_dir.subdir_untracked("default_keystore");
+        let secondary_dir = temp_dir.subdir_untracked("secondary_keystore");
+        let secondary_id = KeystoreId::from_str("secondary").unwrap();
+
+        let mistrust = Mistrust::new_dangerously_trust_everyone();
+        let default_store =
+            ArtiNativeKeystore::from_path_and_mistrust(&default_dir, &mistrust).unwrap();
+        // All `ArtiNativeKeystore`s have the same ID, so we must rename this one.
+        let secondary_store = HookedKeystore::new(
+            ArtiNativeKeystore::from_path_and_mistrust(&secondary_dir, &mistrust).unwrap(),
+        )
+        .with_id(secondary_id.clone());
+        let mut builder = KeyMgrBuilder::default().default_store(Box::new(default_store));
+        builder.secondary_stores().push(Box::new(secondary_store));
+        let keymgr = Arc::new(builder.build().unwrap());
+
+        let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+        let hsid_spec = HsIdKeypairSpecifier::new(nickname.clone());
+        let pub_hsid_spec = HsIdPublicKeySpecifier::new(nickname.clone());
+
+        maybe_generate_hsid(
+            &keymgr,
+            &nickname,
+            false, /* offline_hsid */
+            KeystoreSelector::Id(&secondary_id),
+        )
+        .unwrap();
+
+        // The keys are visible through the KeyMgr...
+        assert!(keymgr.get::<HsIdKey>(&pub_hsid_spec).unwrap().is_some());
+        assert!(keymgr.get::<HsIdKeypair>(&hsid_spec).unwrap().is_some());
+
+        // ...but they were only written to the selected keystore.
+        let default_keymgr = single_store_keymgr(&default_dir);
+        assert!(default_keymgr
+            .get::<HsIdKey>(&pub_hsid_spec)
+            .unwrap()
+            .is_none());
+        assert!(default_keymgr
+            .get::<HsIdKeypair>(&hsid_spec)
+            .unwrap()
+            .is_none());
+
+        let secondary_keymgr = single_store_keymgr(&secondary_dir);
+        assert!(secondary_keymgr
+            .get::<HsIdKey>(&pub_hsid_spec)
+            .unwrap()
+            .is_some());
+        assert!(secondary_keymgr
+            .get::<HsIdKeypair>(&hsid_spec)
+            .unwrap()
+            .is_some());
+    }
+
     #[test]
     fn generate_hsid() {
         let temp_dir = test_temp_dir!();
@@ -596,7 +1168,13 @@ pub(crate) mod test {
                     )
                     .unwrap();
             }
-            maybe_generate_hsid(&keymgr, &nickname, false /* offline_hsid */).unwrap();
+            maybe_generate_hsid(
+                &keymgr,
+                &nickname,
+                false, /* offline_hsid */
+                KeystoreSelector::Default,
+            )
+            .unwrap();
 
             let hsid_public = keymgr.get::<HsIdKey>(&pub_hsid_spec).unwrap().unwrap();
             let hsid_keypair = keymgr.get::<HsIdKeypair>(&hsid_spec).unwrap().unwrap();
@@ -641,7 +1219,13 @@ pub(crate) mod test {
 
         // We're running with an online hsid, but the keypair is missing! The public part
         // of the key exists in the keystore, so we can't generate a new keypair.
-        assert!(maybe_generate_hsid(&keymgr, &nickname, false /* offline_hsid */).is_err());
+        assert!(maybe_generate_hsid(
+            &keymgr,
+            &nickname,
+            false, /* offline_hsid */
+            KeystoreSelector::Default,
+        )
+        .is_err());
     }
 
     #[test]
@@ -665,6 +1249,12 @@ pub(crate) mod test {
             .insert(hsid_public, &pub_hsid_spec, KeystoreSelector::Default)
             .unwrap();
 
-        assert!(maybe_generate_hsid(&keymgr, &nickname, false /* offline_hsid */).is_err());
+        assert!(maybe_generate_hsid(
+            &keymgr,
+            &nickname,
+            false, /* offline_hsid */
+            KeystoreSelector::Default,
+        )
+        .is_err());
     }
 }
@@ -43,6 +43,7 @@ use void::{ResultVoidErrExt as _, Void};
 
 use crate::replay::ReplayError;
 use crate::replay::ReplayLog;
+use crate::token_bucket::SharedTokenBucket;
 use crate::BlindIdKeypairSpecifier;
 use crate::HsIdPublicKeySpecifier;
 use crate::OnionServiceConfig;
@@ -51,7 +52,7 @@ use crate::{
     svc::{LinkSpecs, NtorPublicKey},
     HsNickname,
 };
-use crate::{FatalError, IptLocalId, RendRequest};
+use crate::{Anonymity, FatalError, IptLocalId, RendRequest};
 
 use super::netdir::{wait_for_netdir, wait_for_netdir_to_list, NetdirProviderShutdown};
 
@@ -208,6 +209,8 @@ pub(crate) struct IptParameters {
     pub(crate) k_sid: Arc<HsIntroPtSessionIdKeypair>,
     pub(crate) accepting_requests: RequestDisposition,
     pub(crate) k_ntor: Arc<HsSvcNtorKeypair>,
+    /// Rate limit on introduction requests, shared by all of the service's IPTs
+    pub(crate) rend_request_limit: Option<SharedTokenBucket>,
 }
 
 impl IptEstablisher {
@@ -242,6 +245,7 @@ impl IptEstablisher {
             k_ntor,
             accepting_requests,
             replay_log,
+            rend_request_limit,
         } = params;
         let config = Arc::clone(&config_rx.borrow());
         let nickname = config.nickname().clone();
@@ -277,11 +281,13 @@ impl IptEstablisher {
             runtime: runtime.clone(),
             nickname,
             pool,
+            anonymity: config.anonymity,
             netdir_provider,
             lid,
             target,
             k_sid, // TODO HSS this is now redundant.
             introduce_tx,
+            rend_request_limit,
             extensions: EstIntroExtensionSet {
                 dos_params: config.dos_extension()?,
             },
@@ -609,6 +615,11 @@ struct Reactor<R: Runtime> {
     nickname: HsNickname,
     /// A pool used to create circuits to the introduction point.
     pool: Arc<HsCircPool<R>>,
+    /// The anonymity level of the service.
+    ///
+    /// If this is [`Anonymity::DangerouslyNonAnonymous`],
+    /// we use one-hop circuits to the introduction point.
+    anonymity: Anonymity,
     /// A provider used to select the other relays in the circuit.
     netdir_provider: Arc<dyn NetDirProvider>,
     /// Identifier for the intro point.
@@ -632,6 +643,9 @@ struct Reactor<R: Runtime> {
     /// The stream that will receive INTRODUCE2 messages.
     introduce_tx: mpsc::Sender<RendRequest>,
 
+    /// Rate limit on the INTRODUCE2 messages we pass on to `introduce_tx`.
+    rend_request_limit: Option<SharedTokenBucket>,
+
     /// Mutable state shared with the Establisher, Reactor, and MsgHandler.
     state: Arc<Mutex<EstablisherState>>,
 
@@ -748,11 +762,19 @@ impl<R: Runtime> Reactor<R> {
 
             let kind = tor_circmgr::hspool::HsCircKind::SvcIntro;
             let protovers = circ_target.protovers().clone();
-            let circuit = self
-                .pool
-                .get_or_launch_specific(netdir.as_ref(), kind, circ_target)
-                .await
-                .map_err(IptError::BuildCircuit)?;
+            let circuit = match self.anonymity {
+                Anonymity::Anonymous => {
+                    self.pool
+                        .get_or_launch_specific(netdir.as_ref(), kind, circ_target)
+                        .await
+                }
+                Anonymity::DangerouslyNonAnonymous => {
+                    self.pool
+                        .launch_specific_one_hop(netdir.as_ref(), kind, circ_target)
+                        .await
+                }
+            }
+            .map_err(IptError::BuildCircuit)?;
             // note that netdir is dropped here, to avoid holding on to it any
             // longer than necessary.
             (protovers, circuit)
@@ -809,6 +831,7 @@ impl<R: Runtime> Reactor<R> {
         let handler = IptMsgHandler {
             established_tx: Some(established_tx),
             introduce_tx: self.introduce_tx.clone(),
+            rend_request_limit: self.rend_request_limit.clone(),
             state: self.state.clone(),
             lid: self.lid,
             request_context: self.request_context.clone(),
@@ -876,6 +899,11 @@ struct IptMsgHandler {
     /// A channel used to report Introduce2 messages.
     introduce_tx: mpsc::Sender<RendRequest>,
 
+    /// Rate limit on the Introduce2 messages we report.
+    ///
+    /// Shared with the handlers for all of the service's other IPTs.
+    rend_request_limit: Option<SharedTokenBucket>,
+
     /// Keys that we'll need to answer the introduction requests.
     request_context: Arc<RendRequestContext>,
 
@@ -896,8 +924,6 @@ impl tor_proto::circuit::MsgHandler for IptMsgHandler {
         conversation: ConversationInHandler<'_, '_, '_>,
         any_msg: AnyRelayMsg,
     ) -> tor_proto::Result<MetaCellDisposition> {
-        // TODO HSS: Implement rate-limiting.
-        //
         // TODO HSS: Is CircProto right or should this be a new error type?
         let msg: IptMsg = any_msg.try_into().map_err(|m: AnyRelayMsg| {
             tor_proto::Error::CircProto(format!("Invalid message type {}", m.cmd()))
@@ -950,6 +976,17 @@ impl tor_proto::circuit::MsgHandler for IptMsgHandler {
                     }
                 }
 
+                if let Some(limit) = &self.rend_request_limit {
+                    if !limit.try_take() {
+                        // We are receiving requests faster than we are willing to
+                        // process them.  Drop this one, as we would if the
+                        // receiver were full.
+                        //
+                        // TODO HSS: record when this happens.
+                        return Ok(MetaCellDisposition::Consumed);
+                    }
+                }
+
                 let request = RendRequest::new(self.lid, introduce2, self.request_context.clone());
                 match self.introduce_tx.try_send(request) {
                     Ok(()) => Ok(()),
@@ -2,6 +2,8 @@
 
 use std::sync::Arc;
 
+use crate::config::keystore_selector;
+use crate::svc::netdir::{same_netdir_provider, NetDirProviderRx};
 use crate::{
     BlindIdKeypairSpecifier, BlindIdPublicKeySpecifier, DescSigningKeypairSpecifier, HsNickname,
     StartupError,
@@ -10,8 +12,8 @@ use futures::{select_biased, task::SpawnExt};
 use futures::{FutureExt, StreamExt};
 use postage::broadcast;
 use tor_error::error_report;
-use tor_keymgr::KeyMgr;
-use tor_netdir::{DirEvent, NetDirProvider};
+use tor_keymgr::{KeyMgr, KeystoreId};
+use tor_netdir::{DirEvent, NetDir};
 use tor_rtcompat::Runtime;
 use tracing::{debug, warn};
 use void::Void;
@@ -27,8 +29,12 @@ pub(crate) struct KeystoreSweeper<R: Runtime> {
     nickname: HsNickname,
     /// A keymgr used to look up our keys and store new medium-term keys.
     keymgr: Arc<KeyMgr>,
-    /// A netdir provider for watching for consensus changes.
-    netdir_provider: Arc<dyn NetDirProvider>,
+    /// The keystore from which to remove keys, if not the default one.
+    keystore: Option<KeystoreId>,
+    /// A receiver for the netdir provider to watch for consensus changes.
+    ///
+    /// We switch to each new provider sent on this channel.
+    netdir_provider_rx: NetDirProviderRx,
     /// A channel for receiving the signal to shut down.
     shutdown: broadcast::Receiver<Void>,
 }
@@ -39,14 +45,16 @@ impl<R: Runtime> KeystoreSweeper<R> {
         runtime: R,
         nickname: HsNickname,
         keymgr: Arc<KeyMgr>,
-        netdir_provider: Arc<dyn NetDirProvider>,
+        keystore: Option<KeystoreId>,
+        netdir_provider_rx: NetDirProviderRx,
         shutdown: broadcast::Receiver<Void>,
     ) -> Self {
         Self {
             runtime,
             nickname,
             keymgr,
-            netdir_provider,
+            keystore,
+            netdir_provider_rx,
             shutdown,
         }
     }
@@ -57,11 +65,12 @@ impl<R: Runtime> KeystoreSweeper<R> {
             runtime,
             nickname,
             keymgr,
-            netdir_provider,
+            keystore,
+            mut netdir_provider_rx,
             mut shutdown,
         } = self;
 
-        let match_all_arti_pat = tor_keymgr::KeyPathPattern::Arti("*".into());
+        let mut netdir_provider = Arc::clone(&netdir_provider_rx.borrow());
         let mut netdir_events = netdir_provider.events();
 
         let () = runtime
@@ -74,6 +83,24 @@ impl<R: Runtime> KeystoreSweeper<R> {
                             assert!(shutdown.is_none());
                             return;
                         },
+                        provider = netdir_provider_rx.next().fuse() => {
+                            let Some(provider) = provider else {
+                                debug!(nickname=%nickname, "terminating keystore sweeper task: service dropped");
+                                return;
+                            };
+                            if same_netdir_provider(&netdir_provider, &provider) {
+                                continue;
+                            }
+
+                            debug!(nickname=%nickname, "keystore sweeper switching to a new netdir provider");
+                            netdir_provider = provider;
+                            netdir_events = netdir_provider.events();
+
+                            // The new provider may already have a consensus we haven't seen.
+                            if let Ok(netdir) = netdir_provider.timely_netdir() {
+                                remove_expired_keys(&keymgr, &nickname, &keystore, &netdir);
+                            }
+                        },
                         event = netdir_events.next().fuse() => {
                             let Some(event) = event else {
                                 warn!(nickname=%nickname, "netdir provider sender dropped");
@@ -88,50 +115,7 @@ impl<R: Runtime> KeystoreSweeper<R> {
                                     }
                                 };
 
-                                let relevant_periods = netdir.hs_all_time_periods();
-                                // The consensus changed, so we need to remove any expired keys.
-                                let expire_keys = || -> tor_keymgr::Result<()> {
-                                    let all_arti_keys = keymgr.list_matching(&match_all_arti_pat)?;
-
-                                    for (key_path, key_type) in all_arti_keys {
-                                        /// Remove the specified key, if it's no longer relevant.
-                                        macro_rules! remove_if_expired {
-                                            ($K:ty) => {{
-                                                if let Ok(spec) = <$K>::try_from(&key_path) {
-                                                    // Only remove the keys of the hidden service
-                                                    // that concerns us
-                                                    if &spec.nickname == &nickname {
-                                                        let is_expired = !relevant_periods
-                                                            .contains(&spec.period);
-                                                        // TODO: make the keystore selector
-                                                        // configurable
-                                                        let selector = Default::default();
-
-                                                        if is_expired {
-                                                            keymgr.remove_with_type(
-                                                                &key_path,
-                                                                &key_type,
-                                                                selector
-                                                            )?;
-                                                        }
-                                                    }
-                                                }
-                                            }};
-                                        }
-
-                                        // TODO: any invalid/malformed keys are ignored (rather than
-                                        // removed).
-                                        remove_if_expired!(BlindIdPublicKeySpecifier);
-                                        remove_if_expired!(BlindIdKeypairSpecifier);
-                                        remove_if_expired!(DescSigningKeypairSpecifier);
-                                    }
-
-                                    Ok(())
-                                };
-
-                                if let Err(e) = expire_keys() {
-                                    error_report!(e, "failed to remove expired keys");
-                                }
+                                remove_expired_keys(&keymgr, &nickname, &keystore, &netdir);
                             }
                         }
                     }
@@ -145,3 +129,149 @@ impl<R: Runtime> KeystoreSweeper<R> {
         Ok(())
     }
 }
+
+/// Remove the keys of the service `nickname` that aren't relevant to any of the time periods
+/// of `netdir`.
+fn remove_expired_keys(
+    keymgr: &KeyMgr,
+    nickname: &HsNickname,
+    keystore: &Option<KeystoreId>,
+    netdir: &NetDir,
+) {
+    // (`*` doesn't match across `/`, so it wouldn't match any of our keys.)
+    let match_all_arti_pat = tor_keymgr::KeyPathPattern::Arti("**".into());
+    let relevant_periods = netdir.hs_all_time_periods();
+    // The consensus changed, so we need to remove any expired keys.
+    let expire_keys = || -> tor_keymgr::Result<()> {
+        let all_arti_keys = keymgr.list_matching(&match_all_arti_pat)?;
+
+        for (key_path, key_type) in all_arti_keys {
+            /// Remove the specified key, if it's no longer relevant.
+            macro_rules! remove_if_expired {
+                ($K:ty) => {{
+                    if let Ok(spec) = <$K>::try_from(&key_path) {
+                        // Only remove the keys of the hidden service
+                        // that concerns us
+                        if &spec.nickname == nickname {
+                            let is_expired = !relevant_periods.contains(&spec.period);
+                            let selector = keystore_selector(keystore);
+
+                            if is_expired {
+                                keymgr.remove_with_type(&key_path, &key_type, selector)?;
+                            }
+                        }
+                    }
+                }};
+            }
+
+            // TODO: any invalid/malformed keys are ignored (rather than
+            // removed).
+            remove_if_expired!(BlindIdPublicKeySpecifier);
+            remove_if_expired!(BlindIdKeypairSpecifier);
+            remove_if_expired!(DescSigningKeypairSpecifier);
+        }
+
+        Ok(())
+    };
+
+    if let Err(e) = expire_keys() {
+        error_report!(e, "failed to remove expired keys");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    use tor_basic_utils::test_rng::testing_rng;
+    use tor_hscrypto::pk::HsDescSigningKeypair;
+    use tor_hscrypto::time::TimePeriod;
+    use tor_keymgr::KeystoreSelector;
+    use tor_llcrypto::pk::ed25519;
+    use tor_netdir::testprovider::TestNetDirProvider;
+    use tor_netdir::NetDirProvider;
+    use tor_rtmock::MockRuntime;
+
+    use crate::svc::netdir::test::NotifyingNetDirProvider;
+    use crate::svc::test::create_keymgr;
+
+    #[test]
+    #[tracing_test::traced_test]
+    fn follows_replaced_netdir_provider() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let temp_dir = test_temp_dir!();
+            let keymgr = create_keymgr(&temp_dir);
+            let keymgr = keymgr.into_untracked(); // OK because the sweeper doesn't outlive `temp_dir`
+            let nickname: HsNickname = "shallot".to_string().try_into().unwrap();
+
+            let netdir = tor_netdir::testnet::construct_netdir()
+                .unwrap_if_sufficient()
+                .unwrap();
+            let current = netdir.hs_time_period();
+            let expired = current.prev().unwrap().prev().unwrap();
+
+            // Store a descriptor signing key for `period`, returning its specifier
+            let insert = |period: TimePeriod| {
+                let spec = DescSigningKeypairSpecifier::new(nickname.clone(), period);
+                let key =
+                    HsDescSigningKeypair::from(ed25519::Keypair::generate(&mut testing_rng()));
+                keymgr
+                    .insert(key, &spec, KeystoreSelector::Default)
+                    .unwrap();
+                spec
+            };
+            let exists = |spec: &DescSigningKeypairSpecifier| {
+                keymgr.get::<HsDescSigningKeypair>(spec).unwrap().is_some()
+            };
+
+            // Start off with a provider that has no netdir
+            let empty: Arc<dyn NetDirProvider> = Arc::new(TestNetDirProvider::new());
+            let (mut provider_tx, provider_rx) = postage::watch::channel_with(empty);
+            let (shutdown_tx, shutdown_rx) = broadcast::channel(0);
+            KeystoreSweeper::new(
+                runtime.clone(),
+                nickname.clone(),
+                Arc::clone(&keymgr),
+                None,
+                provider_rx,
+                shutdown_rx,
+            )
+            .launch()
+            .unwrap();
+
+            let current_key = insert(current);
+            let expired_key = insert(expired);
+            runtime.progress_until_stalled().await;
+            assert!(exists(&expired_key));
+
+            // Switching to a provider that has a netdir removes expired keys straight away
+            let provider = Arc::new(NotifyingNetDirProvider::new(netdir.clone()));
+            *provider_tx.borrow_mut() = provider.clone();
+            runtime.progress_until_stalled().await;
+            assert!(exists(&current_key));
+            assert!(!exists(&expired_key));
+
+            // We also follow the consensus changes of the new provider
+            let expired_key = insert(expired);
+            provider.set_netdir_and_notify(netdir);
+            runtime.progress_until_stalled().await;
+            assert!(exists(&current_key));
+            assert!(!exists(&expired_key));
+
+            drop(shutdown_tx);
+            runtime.progress_until_stalled().await;
+        });
+    }
+}
@@ -6,6 +6,21 @@ use tor_error::ErrorKind;
 use tor_linkspec::RelayIds;
 use tor_netdir::{NetDir, NetDirProvider};
 
+/// A receiver for the [`NetDirProvider`] we should currently be using.
+///
+/// The provider can be replaced while the service is running, with
+/// [`OnionService::replace_netdir_provider`](crate::OnionService::replace_netdir_provider).
+pub(crate) type NetDirProviderRx = postage::watch::Receiver<Arc<dyn NetDirProvider>>;
+
+/// Return true if `a` and `b` are the same `NetDirProvider`.
+pub(crate) fn same_netdir_provider(
+    a: &Arc<dyn NetDirProvider>,
+    b: &Arc<dyn NetDirProvider>,
+) -> bool {
+    // Only compare the data pointers: vtable pointers aren't guaranteed to be unique.
+    std::ptr::eq(Arc::as_ptr(a).cast::<()>(), Arc::as_ptr(b).cast::<()>())
+}
+
 /// Get a NetDir from `provider`, waiting until one exists.
 ///
 /// TODO: perhaps this function would be more generally useful if it were not here?
@@ -87,3 +102,80 @@ impl tor_error::HasKind for NetdirProviderShutdown {
         ErrorKind::ArtiShuttingDown
     }
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use postage::broadcast;
+    use postage::sink::Sink as _;
+    use tor_netdir::testprovider::TestNetDirProvider;
+    use tor_netdir::DirEvent;
+
+    /// A [`NetDirProvider`] that notifies its subscribers whenever its netdir is replaced.
+    pub(crate) struct NotifyingNetDirProvider {
+        /// The provider of the current netdir.
+        inner: TestNetDirProvider,
+        /// The sender for our [`DirEvent`]s.
+        events_tx: Mutex<broadcast::Sender<DirEvent>>,
+    }
+
+    impl NotifyingNetDirProvider {
+        /// Create a new `NotifyingNetDirProvider` that initially provides `netdir`.
+        pub(crate) fn new(netdir: NetDir) -> Self {
+            Self::with_inner(TestNetDirProvider::from(netdir))
+        }
+
+        /// Create a new `NotifyingNetDirProvider` that doesn't have a netdir yet.
+        pub(crate) fn empty() -> Self {
+            Self::with_inner(TestNetDirProvider::new())
+        }
+
+        /// Create a new `NotifyingNetDirProvider` that wraps `inner`.
+        fn with_inner(inner: TestNetDirProvider) -> Self {
+            let (events_tx, _) = broadcast::channel(16);
+            Self {
+                inner,
+                events_tx: Mutex::new(events_tx),
+            }
+        }
+
+        /// Replace the netdir, and tell our subscribers that there is a new consensus.
+        pub(crate) fn set_netdir_and_notify(&self, netdir: NetDir) {
+            self.inner.set_netdir(netdir);
+            self.events_tx
+                .lock()
+                .unwrap()
+                .try_send(DirEvent::NewConsensus)
+                .unwrap();
+        }
+    }
+
+    impl NetDirProvider for NotifyingNetDirProvider {
+        fn netdir(&self, timeliness: tor_netdir::Timeliness) -> tor_netdir::Result<Arc<NetDir>> {
+            self.inner.netdir(timeliness)
+        }
+
+        fn events(&self) -> futures::stream::BoxStream<'static, DirEvent> {
+            Box::pin(self.events_tx.lock().unwrap().subscribe())
+        }
+
+        fn params(&self) -> Arc<dyn AsRef<tor_netdir::params::NetParameters>> {
+            self.inner.params()
+        }
+    }
+}
@@ -2,6 +2,7 @@
 
 mod backoff;
 mod descriptor;
+mod limit;
 mod reactor;
 
 use futures::task::SpawnExt;
@@ -12,14 +13,28 @@ use tracing::warn;
 use void::Void;
 
 use tor_error::warn_report;
-use tor_netdir::NetDirProvider;
 use tor_rtcompat::Runtime;
 
+use crate::offline::OfflineCertStorageHandle;
+use crate::status::{
+    DescriptorUploadTimes, HsDirUploadStatuses, StatusSender, TimePeriodChangeEvent,
+    TimePeriodChangeEventStream,
+};
+use crate::svc::netdir::NetDirProviderRx;
 use crate::{ipt_set::IptsPublisherView, StartupError};
 use crate::{HsNickname, OnionServiceConfig};
 
+pub(crate) use descriptor::HS_DESC_CERT_LIFETIME_SEC;
 use reactor::Reactor;
 
+/// How many [`TimePeriodChangeEvent`]s we buffer for each subscriber.
+///
+/// The set of relevant time periods changes at most a few times per consensus,
+/// so a subscriber would have to ignore us for a long time to fill this up.
+const TIME_PERIOD_CHANGE_BUFFER: usize = 16;
+
+pub use limit::DescriptorUploadLimit;
+pub use reactor::DescriptorUploadObserver;
 pub(crate) use reactor::{Mockable, Real};
 
 /// A handle for the Hsdir Publisher for an onion service.
@@ -33,9 +48,9 @@ pub(crate) struct Publisher<R: Runtime, M: Mockable> {
     runtime: R,
     /// The service for which we're publishing descriptors.
     nickname: HsNickname,
-    /// A source for new network directories that we use to determine
-    /// our HsDirs.
-    dir_provider: Arc<dyn NetDirProvider>,
+    /// A channel for receiving the source of the network directories
+    /// that we use to determine our HsDirs.
+    dir_provider_rx: NetDirProviderRx,
     /// Mockable state.
     ///
     /// This is used for launching circuits and for obtaining random number generators.
@@ -48,8 +63,26 @@ pub(crate) struct Publisher<R: Runtime, M: Mockable> {
     config_rx: watch::Receiver<Arc<OnionServiceConfig>>,
     /// A channel for receiving the signal to shut down.
     shutdown_rx: broadcast::Receiver<Void>,
+    /// A channel for receiving requests to pause (`true`) or resume (`false`) publication.
+    pause_rx: watch::Receiver<bool>,
     /// The key manager.
     keymgr: Arc<KeyMgr>,
+    /// Where the certificates of the descriptor signing keys are stored,
+    /// if the service is running in offline mode.
+    offline_certs: Arc<OfflineCertStorageHandle>,
+    /// A sender for updating the status of the onion service.
+    status_tx: StatusSender,
+    /// Where the reactor records the time of its last successful upload for each time period.
+    upload_times: DescriptorUploadTimes,
+    /// Where the reactor records the outcome of its most recent upload to each HsDir.
+    upload_statuses: HsDirUploadStatuses,
+    /// A channel for receiving the callback to invoke with each descriptor
+    /// before it is uploaded, if any.
+    upload_observer: watch::Receiver<Option<DescriptorUploadObserver>>,
+    /// Where the reactor reports changes in the set of relevant time periods.
+    time_period_change_tx: broadcast::Sender<TimePeriodChangeEvent>,
+    /// A limit on concurrent uploads shared with other services, if any.
+    upload_limit: Option<DescriptorUploadLimit>,
 }
 
 impl<R: Runtime, M: Mockable> Publisher<R, M> {
@@ -59,55 +92,115 @@ impl<R: Runtime, M: Mockable> Publisher<R, M> {
     /// and will therefore not upload any descriptors.
     ///
     /// The publisher won't start publishing until you call [`Publisher::launch`].
+    ///
+    /// If `upload_limit` is provided, each upload must also obtain a permit from it,
+    /// in addition to respecting this publisher's own concurrency limit.
+    ///
+    /// The current value of `upload_observer` (if any) is called with each descriptor
+    /// just before it is uploaded.
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         runtime: R,
         nickname: HsNickname,
-        dir_provider: Arc<dyn NetDirProvider>,
+        dir_provider_rx: NetDirProviderRx,
         mockable: impl Into<M>,
         ipt_watcher: IptsPublisherView,
         config_rx: watch::Receiver<Arc<OnionServiceConfig>>,
         shutdown_rx: broadcast::Receiver<Void>,
+        pause_rx: watch::Receiver<bool>,
         keymgr: Arc<KeyMgr>,
+        offline_certs: Arc<OfflineCertStorageHandle>,
+        status_tx: StatusSender,
+        upload_observer: watch::Receiver<Option<DescriptorUploadObserver>>,
+        upload_limit: Option<DescriptorUploadLimit>,
     ) -> Self {
         let config = config_rx.borrow().clone();
+        let (time_period_change_tx, _) = broadcast::channel(TIME_PERIOD_CHANGE_BUFFER);
         Self {
             runtime,
             nickname,
-            dir_provider,
+            dir_provider_rx,
             mockable: mockable.into(),
             config,
             ipt_watcher,
             config_rx,
             shutdown_rx,
+            pause_rx,
             keymgr,
+            offline_certs,
+            status_tx,
+            upload_times: Default::default(),
+            upload_statuses: Default::default(),
+            upload_observer,
+            time_period_change_tx,
+            upload_limit,
         }
     }
 
+    /// Return a handle for reading the time of the last successful upload
+    /// for each time period.
+    pub(crate) fn upload_times(&self) -> DescriptorUploadTimes {
+        Arc::clone(&self.upload_times)
+    }
+
+    /// Return a handle for reading the outcome of the most recent upload
+    /// to each HsDir, for each time period.
+    pub(crate) fn upload_statuses(&self) -> HsDirUploadStatuses {
+        Arc::clone(&self.upload_statuses)
+    }
+
+    /// Return a stream of notifications about changes in the set of time periods
+    /// we are publishing descriptors for.
+    pub(crate) fn time_period_change_events(&self) -> TimePeriodChangeEventStream {
+        TimePeriodChangeEventStream::new(self.time_period_change_tx.subscribe())
+    }
+
+    /// Return a handle for subscribing to changes in the set of time periods
+    /// we are publishing descriptors for, even after the publisher is launched.
+    pub(crate) fn time_period_change_sender(&self) -> broadcast::Sender<TimePeriodChangeEvent> {
+        self.time_period_change_tx.clone()
+    }
+
     /// Launch the publisher reactor.
     pub(crate) fn launch(self) -> Result<(), StartupError> {
         let Publisher {
             runtime,
             nickname,
-            dir_provider,
+            dir_provider_rx,
             mockable,
             config,
             ipt_watcher,
             config_rx,
             shutdown_rx,
+            pause_rx,
             keymgr,
+            offline_certs,
+            status_tx,
+            upload_times,
+            upload_statuses,
+            upload_observer,
+            time_period_change_tx,
+            upload_limit,
         } = self;
 
         let reactor = Reactor::new(
             runtime.clone(),
             nickname,
-            dir_provider,
+            dir_provider_rx,
             mockable,
             config,
             ipt_watcher,
             config_rx,
             shutdown_rx,
+            pause_rx,
             keymgr,
+            offline_certs,
+            status_tx,
+            upload_times,
+            upload_statuses,
+            upload_observer,
+            time_period_change_tx,
+            upload_limit,
         );
 
         runtime
@@ -184,35 +277,60 @@ mod test {
     //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
     use super::*;
 
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
     use std::io;
+    use std::iter;
+    use std::num::NonZeroUsize;
     use std::pin::Pin;
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Mutex;
     use std::task::{Context, Poll};
-    use std::time::Duration;
+    use std::time::{Duration, SystemTime};
 
     use async_trait::async_trait;
     use fs_mistrust::Mistrust;
-    use futures::{AsyncRead, AsyncWrite};
+    use futures::{AsyncRead, AsyncWrite, FutureExt as _, StreamExt as _};
     use tempfile::{tempdir, TempDir};
 
     use tor_basic_utils::test_rng::{testing_rng, TestingRng};
+    use tor_checkable::{SelfSigned as _, Timebound as _};
     use tor_circmgr::hspool::HsCircKind;
-    use tor_hscrypto::pk::{HsBlindId, HsDescSigningKeypair, HsId, HsIdKey, HsIdKeypair};
-    use tor_keymgr::{ArtiNativeKeystore, KeyMgrBuilder, KeySpecifier, ToEncodableKey};
+    use tor_error::HasKind as _;
+    use tor_hscrypto::pk::{
+        HsBlindId, HsBlindIdKey, HsBlindIdKeypair, HsClientDescEncKeypair, HsDescSigningKeypair,
+        HsId, HsIdKey, HsIdKeypair,
+    };
+    use tor_hscrypto::{RevisionCounter, Subcredential};
+    use tor_keymgr::{
+        ArtiNativeKeystore, KeyMgrBuilder, KeySpecifier, KeystoreSelector, ToEncodableKey,
+    };
+    use tor_linkspec::{HasRelayIds as _, RelayId, RelayIds};
     use tor_llcrypto::pk::{ed25519, rsa};
     use tor_netdir::testprovider::TestNetDirProvider;
-    use tor_netdir::{testnet, NetDir};
-    use tor_netdoc::doc::hsdesc::test_data;
+    use tor_netdir::{testnet, NetDir, NetDirProvider, Timeliness};
+    use tor_netdoc::doc::hsdesc::{test_data, HsDesc};
     use tor_rtcompat::BlockOn;
     use tor_rtmock::MockRuntime;
 
-    use crate::config::OnionServiceConfigBuilder;
-    use crate::ipt_set::{ipts_channel, IptInSet, IptSet};
-    use crate::svc::publish::reactor::MockableClientCirc;
+    use tor_netdoc::doc::netstatus::{Lifetime, RelayFlags};
+    use tracing_test::traced_test;
+
+    use crate::config::{
+        AuthorizedClientConfig, DescEncryptionConfig, OnionServiceConfigBuilder, RateLimitAtIntro,
+    };
+    use crate::ipt_set::{ipts_channel, IptInSet, IptSet, IptsManagerView};
+    use crate::offline::offline_cert_storage_handle;
+    use crate::offline::OfflineCertStorageHandle;
+    use crate::status::{
+        DescriptorUploadTime, OnionServiceStatus, StartupProgress, State, TimePeriodChangeEvent,
+        UploadStatus,
+    };
+    use crate::svc::netdir::test::NotifyingNetDirProvider;
+    use crate::svc::publish::reactor::{
+        read_blind_id_keypair, MockableClientCirc, RETRY_BUDGET_BURST, RETRY_BUDGET_INTERVAL,
+    };
     use crate::svc::test::create_storage_handles;
-    use crate::{Anonymity, HsNickname, IptLocalId};
+    use crate::{Anonymity, FatalError, HsNickname, IptLocalId, OfflineKeys};
     use crate::{
         BlindIdKeypairSpecifier, BlindIdPublicKeySpecifier, DescSigningKeypairSpecifier,
         HsIdKeypairSpecifier, HsIdPublicKeySpecifier,
@@ -261,23 +379,45 @@ mod test {
         ///
         /// Used for testing whether the reactor correctly retries on failure.
         responses_for_hsdir: Arc<Mutex<HashMap<rsa::RsaIdentity, Arc<Mutex<I>>>>>,
+        /// The number of one-hop (non-anonymous) circuits requested by the reactor.
+        one_hop_circ_count: Arc<AtomicUsize>,
+        /// If set, each upload waits at this gate before launching its circuit.
+        upload_gate: Option<UploadGate>,
     }
 
-    #[async_trait]
-    impl<I: PollReadIter> Mockable for MockReactorState<I> {
-        type Rng = TestingRng;
-        type ClientCirc = MockClientCirc<I>;
+    /// A gate that holds uploads open until the test lets them through.
+    ///
+    /// Used for counting how many uploads are in flight at once.
+    #[derive(Clone, Debug)]
+    struct UploadGate {
+        /// The number of uploads currently waiting at the gate.
+        in_flight: Arc<AtomicUsize>,
+        /// The largest number of uploads ever waiting at the gate at the same time.
+        max_in_flight: Arc<AtomicUsize>,
+        /// Whether the gate is open.
+        open: watch::Receiver<bool>,
+    }
 
-        fn thread_rng(&self) -> Self::Rng {
-            testing_rng()
+    impl UploadGate {
+        /// Wait until the gate is open, recording this upload as in flight meanwhile.
+        async fn pass(&self) {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+
+            let mut open = self.open.clone();
+            while let Some(is_open) = open.next().await {
+                if is_open {
+                    break;
+                }
+            }
+
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
         }
+    }
 
-        async fn get_or_launch_specific<T>(
-            &self,
-            netdir: &tor_netdir::NetDir,
-            kind: HsCircKind,
-            target: T,
-        ) -> Result<Arc<Self::ClientCirc>, tor_circmgr::Error>
+    impl<I: PollReadIter> MockReactorState<I> {
+        /// Return a mock circuit of the specified `kind` to `target`.
+        fn mock_circ<T>(&self, kind: HsCircKind, target: T) -> Arc<MockClientCirc<I>>
         where
             T: tor_linkspec::CircTarget + Send + Sync,
         {
@@ -290,11 +430,49 @@ mod test {
                 .entry(*id)
                 .or_insert_with(|| Arc::new(Mutex::new(self.poll_read_responses.clone())));
 
-            Ok(MockClientCirc {
+            MockClientCirc {
                 publish_count: Arc::clone(&self.publish_count),
                 poll_read_responses: Arc::clone(poll_read_responses),
             }
-            .into())
+            .into()
+        }
+    }
+
+    #[async_trait]
+    impl<I: PollReadIter> Mockable for MockReactorState<I> {
+        type Rng = TestingRng;
+        type ClientCirc = MockClientCirc<I>;
+
+        fn thread_rng(&self) -> Self::Rng {
+            testing_rng()
+        }
+
+        async fn get_or_launch_specific<T>(
+            &self,
+            _netdir: &tor_netdir::NetDir,
+            kind: HsCircKind,
+            target: T,
+        ) -> Result<Arc<Self::ClientCirc>, tor_circmgr::Error>
+        where
+            T: tor_linkspec::CircTarget + Send + Sync,
+        {
+            if let Some(gate) = &self.upload_gate {
+                gate.pass().await;
+            }
+            Ok(self.mock_circ(kind, target))
+        }
+
+        async fn launch_specific_one_hop<T>(
+            &self,
+            _netdir: &tor_netdir::NetDir,
+            kind: HsCircKind,
+            target: T,
+        ) -> Result<Arc<Self::ClientCirc>, tor_circmgr::Error>
+        where
+            T: tor_linkspec::CircTarget + Send + Sync,
+        {
+            self.one_hop_circ_count.fetch_add(1, Ordering::SeqCst);
+            Ok(self.mock_circ(kind, target))
         }
     }
 
@@ -453,11 +631,86 @@ mod test {
         (hs_id, hs_blind_id_key.into(), keymgr.into())
     }
 
-    fn build_test_config(nickname: HsNickname) -> OnionServiceConfig {
+    /// Create a new `KeyMgr` for a service whose identity keypair is offline.
+    ///
+    /// The keystore only has the public identity key, and the keys provisioned with
+    /// [`provision_offline_keys`](crate::provision_offline_keys) for each time period of `netdir`
+    /// (whose certificates are stored in `state_mgr`).
+    fn init_offline_keymgr(
+        keystore_dir: &TempDir,
+        nickname: &HsNickname,
+        netdir: &NetDir,
+        state_mgr: &tor_persist::TestingStateMgr,
+    ) -> (HsBlindId, Arc<KeyMgr>) {
+        let mut rng = testing_rng();
+        let keypair = ed25519::Keypair::generate(&mut rng);
+        let id_pub = HsIdKey::from(keypair.verifying_key());
+        let id_keypair = HsIdKeypair::from(ed25519::ExpandedKeypair::from(&keypair));
+
+        let keystore = ArtiNativeKeystore::from_path_and_mistrust(
+            keystore_dir,
+            &Mistrust::new_dangerously_trust_everyone(),
+        )
+        .unwrap();
+        let keymgr = KeyMgrBuilder::default()
+            .default_store(Box::new(keystore))
+            .build()
+            .unwrap();
+
+        insert_svc_key(
+            id_pub.clone(),
+            &keymgr,
+            &HsIdPublicKeySpecifier::new(nickname.clone()),
+        );
+
+        let keys =
+            crate::provision_offline_keys(&id_keypair, netdir.hs_all_time_periods(), &mut rng)
+                .unwrap();
+        for keys in keys {
+            keys.insert(&keymgr, state_mgr, nickname, KeystoreSelector::Default)
+                .unwrap();
+        }
+
+        let (hs_blind_id_key, _subcredential) =
+            id_pub.compute_blinded_key(netdir.hs_time_period()).unwrap();
+        (hs_blind_id_key.into(), keymgr.into())
+    }
+
+    /// Return a storage handle for the certificates of an offline service.
+    ///
+    /// This is only used if the identity keypair of the service is offline,
+    /// so it is empty.
+    fn offline_cert_storage(nickname: &HsNickname) -> Arc<OfflineCertStorageHandle> {
+        offline_cert_storage_handle(create_storage_handles().0, nickname)
+    }
+
+    /// Return a set of introduction points to publish.
+    fn test_ipt_set() -> IptSet {
+        let ipts: Vec<IptInSet> = test_data::test_parsed_hsdesc()
+            .unwrap()
+            .intro_points()
+            .iter()
+            .enumerate()
+            .map(|(i, ipt)| IptInSet {
+                ipt: ipt.clone(),
+                lid: IptLocalId([i.try_into().unwrap(); 32]),
+            })
+            .collect();
+
+        IptSet {
+            ipts,
+            lifetime: Duration::from_secs(20),
+            expiry_slop: Duration::from_secs(300),
+        }
+    }
+
+    fn build_test_config(nickname: HsNickname, anonymity: Anonymity) -> OnionServiceConfig {
         OnionServiceConfigBuilder::default()
             .nickname(nickname)
-            .anonymity(Anonymity::Anonymous)
-            .rate_limit_at_intro(None)
+            .anonymity(anonymity)
+            .rate_limit_at_intro(RateLimitAtIntro::Default)
+            // Periodic republication would stop `advance_until_stalled` from ever returning.
+            .descriptor_republish_interval(Duration::ZERO)
             .build()
             .unwrap()
     }
@@ -475,28 +728,45 @@ mod test {
         reactor_event: impl FnOnce(),
         poll_read_responses: I,
         expected_upload_count: usize,
-    ) {
+        upload_observer: Option<DescriptorUploadObserver>,
+        status_tx: StatusSender,
+    ) -> (usize, Vec<DescriptorUploadTime>) {
         runtime.clone().block_on(async move {
             let netdir_provider: Arc<dyn NetDirProvider> =
                 Arc::new(TestNetDirProvider::from(netdir));
             let publish_count = Default::default();
+            let one_hop_circ_count: Arc<AtomicUsize> = Default::default();
             let circpool = MockReactorState {
                 publish_count: Arc::clone(&publish_count),
                 poll_read_responses,
                 responses_for_hsdir: Arc::new(Mutex::new(Default::default())),
+                one_hop_circ_count: Arc::clone(&one_hop_circ_count),
+                upload_gate: None,
             };
+            let (_pause_tx, pause_rx) = watch::channel();
+            let (_dir_provider_tx, dir_provider_rx) = watch::channel_with(netdir_provider);
 
+            let (_upload_observer_tx, upload_observer_rx) = watch::channel_with(upload_observer);
+
+            let offline_certs = offline_cert_storage(&nickname);
             let publisher: Publisher<MockRuntime, MockReactorState<_>> = Publisher::new(
                 runtime.clone(),
                 nickname,
-                netdir_provider,
+                dir_provider_rx,
                 circpool,
                 pv,
                 config_rx,
                 shutdown_rx,
+                pause_rx,
                 keymgr,
+                offline_certs,
+                status_tx,
+                upload_observer_rx,
+                None,
             );
 
+            let upload_times = publisher.upload_times();
+
             publisher.launch().unwrap();
             runtime.advance_until_stalled().await;
 
@@ -508,7 +778,10 @@ mod test {
             runtime.advance_until_stalled().await;
 
             assert_eq!(publish_count.load(Ordering::SeqCst), expected_upload_count);
-        });
+
+            let upload_times = upload_times.lock().unwrap().clone();
+            (one_hop_circ_count.load(Ordering::SeqCst), upload_times)
+        })
     }
 
     /// Test that the publisher publishes the descriptor when the IPTs change.
@@ -520,32 +793,54 @@ mod test {
     /// obtain the total expected number of uploads (this works because the test "HSDirs" all
     /// behave the same, so the number of uploads is the number of HSDirs multiplied by the number
     /// of retries).
-    fn publish_after_ipt_change<I: PollReadIter>(poll_read_responses: I, multiplier: usize) {
+    ///
+    /// If `upload_observer` is specified, it is registered with the publisher before launching it.
+    ///
+    /// The service is configured with the specified `anonymity`. Every upload is expected to
+    /// use a one-hop circuit if the service is non-anonymous, and none of them otherwise.
+    ///
+    /// Returns the number of HSDirs the descriptor is expected to be uploaded to.
+    fn publish_after_ipt_change<I: PollReadIter>(
+        poll_read_responses: I,
+        multiplier: usize,
+        upload_observer: Option<DescriptorUploadObserver>,
+        anonymity: Anonymity,
+    ) -> usize {
+        let netdir = testnet::construct_netdir().unwrap_if_sufficient().unwrap();
+
+        let (hsdir_count, _state, _upload_times) = publish_after_ipt_change_with_netdir(
+            poll_read_responses,
+            multiplier,
+            upload_observer,
+            anonymity,
+            netdir,
+        );
+
+        hsdir_count
+    }
+
+    /// Like [`publish_after_ipt_change`], but with a custom `netdir`.
+    ///
+    /// Returns the number of HSDirs the descriptor is expected to be uploaded to,
+    /// the state of the publisher after the upload,
+    /// and the last successful upload times recorded by the publisher.
+    fn publish_after_ipt_change_with_netdir<I: PollReadIter>(
+        poll_read_responses: I,
+        multiplier: usize,
+        upload_observer: Option<DescriptorUploadObserver>,
+        anonymity: Anonymity,
+        netdir: NetDir,
+    ) -> (usize, State, Vec<DescriptorUploadTime>) {
         let runtime = MockRuntime::new();
         let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
-        let config = build_test_config(nickname.clone());
+        let config = build_test_config(nickname.clone(), anonymity);
         let (config_tx, config_rx) = watch::channel_with(Arc::new(config));
 
         let (mut mv, pv) = ipts_channel(&runtime, create_storage_handles().1).unwrap();
         let update_ipts = || {
-            let ipts: Vec<IptInSet> = test_data::test_parsed_hsdesc()
-                .unwrap()
-                .intro_points()
-                .iter()
-                .enumerate()
-                .map(|(i, ipt)| IptInSet {
-                    ipt: ipt.clone(),
-                    lid: IptLocalId([i.try_into().unwrap(); 32]),
-                })
-                .collect();
-
-            mv.borrow_for_update(runtime.clone()).ipts = Some(IptSet {
-                ipts,
-                lifetime: Duration::from_secs(20),
-            });
+            mv.borrow_for_update(runtime.clone()).ipts = Some(test_ipt_set());
         };
 
-        let netdir = testnet::construct_netdir().unwrap_if_sufficient().unwrap();
         let keystore_dir = tempdir().unwrap();
 
         let (hsid, blind_id, keymgr) = init_keymgr(&keystore_dir, &nickname, &netdir);
@@ -562,8 +857,9 @@ mod test {
         // affect _each_ hsdir, so the expected number of uploads is a multiple of hsdir_count.
         let expected_upload_count = hsdir_count * multiplier;
         let (_shutdown_tx, shutdown_rx) = broadcast::channel(0);
+        let status_tx = StatusSender::new(OnionServiceStatus::new_shutdown());
 
-        run_test(
+        let (one_hop_circ_count, upload_times) = run_test(
             runtime.clone(),
             hsid,
             nickname,
@@ -575,7 +871,23 @@ mod test {
             update_ipts,
             poll_read_responses,
             expected_upload_count,
+            upload_observer,
+            status_tx.clone(),
         );
+
+        let expected_one_hop_circ_count = match anonymity {
+            Anonymity::Anonymous => 0,
+            Anonymity::DangerouslyNonAnonymous => expected_upload_count,
+        };
+        assert_eq!(one_hop_circ_count, expected_one_hop_circ_count);
+
+        let state = status_tx.get().publisher_state();
+        if state == State::Running {
+            // Our descriptor reached the HsDirs, so clients can reach us.
+            assert_eq!(status_tx.get_progress(), StartupProgress::Reachable);
+        }
+
+        (hsdir_count, state, upload_times)
     }
 
     #[test]
@@ -583,7 +895,14 @@ mod test {
         // The HSDirs always respond with 200 OK, so we expect to publish hsdir_count times.
         let poll_reads = [Ok(OK_RESPONSE.into())].into_iter();
 
-        publish_after_ipt_change(poll_reads, 1);
+        publish_after_ipt_change(poll_reads, 1, None, Anonymity::Anonymous);
+    }
+
+    #[test]
+    fn publish_single_onion_uses_one_hop_circuits() {
+        let poll_reads = [Ok(OK_RESPONSE.into())].into_iter();
+
+        publish_after_ipt_change(poll_reads, 1, None, Anonymity::DangerouslyNonAnonymous);
     }
 
     #[test]
@@ -607,16 +926,1397 @@ mod test {
             ]
             .into_iter();
 
-            publish_after_ipt_change(poll_reads, 2);
+            publish_after_ipt_change(poll_reads, 2, None, Anonymity::Anonymous);
         }
     }
 
-    // TODO HSS: test that the descriptor is republished when the config changes
+    #[test]
+    #[traced_test]
+    fn publish_with_too_few_hsdirs() {
+        // Only keep the HSDir flag of the first two relays.
+        let netdir = testnet::construct_custom_netdir(|idx, nb| {
+            if (2..10).contains(&idx) {
+                nb.rs
+                    .set_flags(RelayFlags::RUNNING | RelayFlags::VALID | RelayFlags::V2DIR);
+            }
+        })
+        .unwrap()
+        .unwrap_if_sufficient()
+        .unwrap();
+        let poll_reads = [Ok(OK_RESPONSE.into())].into_iter();
+
+        let (hsdir_count, state, _upload_times) =
+            publish_after_ipt_change_with_netdir(poll_reads, 1, None, Anonymity::Anonymous, netdir);
+
+        // We still publish the descriptor to the HSDirs we have...
+        assert!((1..=2).contains(&hsdir_count));
+        // ...but we warn about it, and don't consider the descriptor successfully published.
+        assert!(logs_contain("too few HSDirs in the consensus"));
+        assert!(logs_contain("descriptor is only up-to-date on"));
+        assert_eq!(state, State::Recovering);
+        // We don't repeat the warnings each time we recompute the HSDirs, or upload.
+        logs_assert(|lines| {
+            for warning in ["too few HSDirs", "descriptor is only up-to-date on"] {
+                let n = lines.iter().filter(|line| line.contains(warning)).count();
+                if n != 1 {
+                    return Err(format!("{n} warnings containing {warning:?}"));
+                }
+            }
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn publish_records_upload_time() {
+        let netdir = testnet::construct_netdir().unwrap_if_sufficient().unwrap();
+        let period = netdir.hs_time_period();
+        let poll_reads = [Ok(OK_RESPONSE.into())].into_iter();
+
+        let (_hsdir_count, _state, upload_times) =
+            publish_after_ipt_change_with_netdir(poll_reads, 1, None, Anonymity::Anonymous, netdir);
 
-    // TODO HSS: test that the descriptor is reuploaded only to the HSDirs that need it (i.e. the
-    // ones for which it's dirty)
+        // We know when the descriptor for the current time period last reached the HSDirs.
+        assert!(upload_times
+            .iter()
+            .any(|upload_time| upload_time.time_period() == period));
+    }
 
-    // TODO HSS: test that rate-limiting works correctly
+    #[test]
+    fn upload_observer_sees_each_descriptor() {
+        let poll_reads = [Ok(OK_RESPONSE.into())].into_iter();
+        let observed: Arc<Mutex<Vec<(String, RelayIds)>>> = Default::default();
+
+        let observer: DescriptorUploadObserver = {
+            let observed = Arc::clone(&observed);
+            Arc::new(move |desc: &str, hsdir: &RelayIds| {
+                observed
+                    .lock()
+                    .unwrap()
+                    .push((desc.to_string(), hsdir.clone()));
+            })
+        };
+
+        let hsdir_count =
+            publish_after_ipt_change(poll_reads, 1, Some(observer), Anonymity::Anonymous);
+
+        let observed = observed.lock().unwrap();
+        // The observer is called exactly once for each HsDir.
+        assert_eq!(observed.len(), hsdir_count);
+        let distinct_hsdirs = observed
+            .iter()
+            .map(|(_, hsdir)| hsdir.clone())
+            .collect::<HashSet<_>>();
+        assert_eq!(distinct_hsdirs.len(), hsdir_count);
+
+        for (desc, _) in observed.iter() {
+            assert!(desc.starts_with("hs-descriptor 3"));
+        }
+    }
+
+    /// Optional settings for [`TestPublisher::launch_with_options`].
+    #[derive(Default)]
+    struct TestPublisherOptions {
+        /// The upload limit to share with other publishers, if any.
+        upload_limit: Option<DescriptorUploadLimit>,
+        /// The gate each upload waits at, if any.
+        upload_gate: Option<UploadGate>,
+        /// Whether every upload to every HSDir fails.
+        hsdirs_fail: bool,
+        /// Whether the identity keypair of the service is offline
+        /// (see [`init_offline_keymgr`]).
+        offline: bool,
+        /// The observer to notify of each upload, if any.
+        upload_observer: Option<DescriptorUploadObserver>,
+        /// A function for making the netdir provider from the test network's netdir.
+        ///
+        /// If `None`, the netdir provider is a [`TestNetDirProvider`].
+        mk_dir_provider: Option<fn(NetDir) -> Arc<dyn NetDirProvider>>,
+    }
+
+    /// A publisher running on a [`MockRuntime`], with the handles needed to drive it.
+    struct TestPublisher {
+        /// Sender for updating the service config.
+        config_tx: watch::Sender<Arc<OnionServiceConfig>>,
+        /// Sender for pausing and resuming publication.
+        pause_tx: watch::Sender<bool>,
+        /// Sender for replacing the netdir provider.
+        dir_provider_tx: watch::Sender<Arc<dyn NetDirProvider>>,
+        /// Sender for replacing the upload observer.
+        upload_observer_tx: watch::Sender<Option<DescriptorUploadObserver>>,
+        /// The IPT manager's view of the IPT set.
+        ipts: IptsManagerView,
+        /// The number of `POST /tor/hs/3/publish` requests sent by the publisher.
+        publish_count: Arc<AtomicUsize>,
+        /// The number of HSDirs we expect to upload the descriptor to.
+        hsdir_count: usize,
+        /// The sender the publisher reports the status of the service to.
+        status_tx: StatusSender,
+        /// The blinded identity of the service in the current time period.
+        blind_id: HsBlindId,
+        /// The subcredential of the service in the current time period.
+        subcredential: Subcredential,
+        /// The shutdown signal sender (which needs to be kept alive).
+        _shutdown_tx: broadcast::Sender<Void>,
+        /// The keystore directory (which needs to be kept alive).
+        _keystore_dir: TempDir,
+    }
+
+    impl TestPublisher {
+        /// Launch a publisher for a service with the specified `config`.
+        ///
+        /// Each HSDir responds with "200 OK" to each of our uploads.
+        fn launch(
+            runtime: &MockRuntime,
+            config: OnionServiceConfig,
+            upload_observer: Option<DescriptorUploadObserver>,
+        ) -> Self {
+            Self::launch_with_options(
+                runtime,
+                config,
+                TestPublisherOptions {
+                    upload_observer,
+                    ..Default::default()
+                },
+            )
+        }
+
+        /// Like [`TestPublisher::launch`], with the specified `options`.
+        fn launch_with_options(
+            runtime: &MockRuntime,
+            config: OnionServiceConfig,
+            options: TestPublisherOptions,
+        ) -> Self {
+            let TestPublisherOptions {
+                upload_limit,
+                upload_gate,
+                hsdirs_fail,
+                offline,
+                upload_observer,
+                mk_dir_provider,
+            } = options;
+
+            let netdir = testnet::construct_netdir().unwrap_if_sufficient().unwrap();
+            let period = netdir.hs_time_period();
+            let nickname = config.nickname.clone();
+            let (config_tx, config_rx) = watch::channel_with(Arc::new(config));
+            let (shutdown_tx, shutdown_rx) = broadcast::channel(0);
+            let (pause_tx, pause_rx) = watch::channel();
+            let (ipts, pv) = ipts_channel(runtime, create_storage_handles().1).unwrap();
+
+            let keystore_dir = tempdir().unwrap();
+            let (state_mgr, _) = create_storage_handles();
+            let (blind_id, keymgr) = if offline {
+                init_offline_keymgr(&keystore_dir, &nickname, &netdir, &state_mgr)
+            } else {
+                let (_hsid, blind_id, keymgr) = init_keymgr(&keystore_dir, &nickname, &netdir);
+                (blind_id, keymgr)
+            };
+            let offline_certs = offline_cert_storage_handle(state_mgr, &nickname);
+            let hsdir_count = netdir
+                .hs_dirs_upload([(blind_id, period)].into_iter())
+                .unwrap()
+                .count();
+            assert!(hsdir_count > 0);
+
+            let hsid_key: HsIdKey = keymgr
+                .get(&HsIdPublicKeySpecifier::new(nickname.clone()))
+                .unwrap()
+                .unwrap();
+            let blind_id_key: HsBlindIdKey = keymgr
+                .get(&BlindIdPublicKeySpecifier::new(nickname.clone(), period))
+                .unwrap()
+                .unwrap();
+            let subcredential = hsid_key.compute_subcredential(&blind_id_key, period);
+
+            let publish_count: Arc<AtomicUsize> = Default::default();
+            // Each upload reads "200 OK" followed by an EOF,
+            // unless the HSDirs are supposed to fail, in which case every read fails.
+            let responses = if hsdirs_fail {
+                [Err(()), Err(())]
+            } else {
+                [Ok(OK_RESPONSE.to_string()), Ok(String::new())]
+            };
+            let poll_read_responses = responses.into_iter().cycle();
+            let circpool = MockReactorState {
+                publish_count: Arc::clone(&publish_count),
+                poll_read_responses,
+                responses_for_hsdir: Arc::new(Mutex::new(Default::default())),
+                one_hop_circ_count: Default::default(),
+                upload_gate,
+            };
+            let netdir_provider = match mk_dir_provider {
+                Some(mk_dir_provider) => mk_dir_provider(netdir),
+                None => Arc::new(TestNetDirProvider::from(netdir)),
+            };
+            let (dir_provider_tx, dir_provider_rx) = watch::channel_with(netdir_provider);
+            let status_tx = StatusSender::new(OnionServiceStatus::new_shutdown());
+
+            let (upload_observer_tx, upload_observer_rx) = watch::channel_with(upload_observer);
+
+            let publisher: Publisher<MockRuntime, MockReactorState<_>> = Publisher::new(
+                runtime.clone(),
+                nickname,
+                dir_provider_rx,
+                circpool,
+                pv,
+                config_rx,
+                shutdown_rx,
+                pause_rx,
+                keymgr,
+                offline_certs,
+                status_tx.clone(),
+                upload_observer_rx,
+                upload_limit,
+            );
+            publisher.launch().unwrap();
+
+            TestPublisher {
+                config_tx,
+                pause_tx,
+                dir_provider_tx,
+                upload_observer_tx,
+                ipts,
+                publish_count,
+                hsdir_count,
+                status_tx,
+                blind_id,
+                subcredential,
+                _shutdown_tx: shutdown_tx,
+                _keystore_dir: keystore_dir,
+            }
+        }
+
+        /// Tell the publisher the IPTs have changed.
+        fn update_ipts(&mut self, runtime: &MockRuntime) {
+            self.ipts.borrow_for_update(runtime.clone()).ipts = Some(test_ipt_set());
+        }
+
+        /// Return the number of uploads so far.
+        fn publish_count(&self) -> usize {
+            self.publish_count.load(Ordering::SeqCst)
+        }
+
+        /// Return the HSDirs we would normally upload to in the current time period.
+        fn ring_hsdirs(&self) -> Vec<rsa::RsaIdentity> {
+            let netdir = testnet::construct_netdir().unwrap_if_sufficient().unwrap();
+            netdir
+                .hs_dirs_upload([(self.blind_id, netdir.hs_time_period())].into_iter())
+                .unwrap()
+                .map(|(_, hsdir)| *hsdir.rsa_identity().unwrap())
+                .collect()
+        }
+
+        /// Check whether `desc` can be decrypted by a client with the specified `client` key.
+        ///
+        /// If `client` is `None`, this checks whether `desc` is unencrypted.
+        fn can_decrypt(&self, desc: &str, client: Option<&HsClientDescEncKeypair>) -> bool {
+            HsDesc::parse(desc, &self.blind_id)
+                .unwrap()
+                .check_signature()
+                .unwrap()
+                .dangerously_assume_timely()
+                .decrypt(&self.subcredential, client)
+                .is_ok()
+        }
+    }
+
+    #[test]
+    fn publish_pause_resume() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+            let config = build_test_config(nickname, Anonymity::Anonymous);
+            let mut p = TestPublisher::launch(&runtime, config, None);
+            runtime.advance_until_stalled().await;
+
+            // While paused, we don't publish anything, even if the IPTs change.
+            *p.pause_tx.borrow_mut() = true;
+            runtime.advance_until_stalled().await;
+            p.update_ipts(&runtime);
+            runtime.advance_until_stalled().await;
+            assert_eq!(p.publish_count(), 0);
+
+            // Resuming causes the descriptor to be uploaded to all the HSDirs.
+            *p.pause_tx.borrow_mut() = false;
+            runtime.advance_until_stalled().await;
+            assert_eq!(p.publish_count(), p.hsdir_count);
+
+            // Pause again, and change the IPTs.
+            *p.pause_tx.borrow_mut() = true;
+            runtime.advance_until_stalled().await;
+            p.update_ipts(&runtime);
+            runtime.advance_until_stalled().await;
+            assert_eq!(p.publish_count(), p.hsdir_count);
+
+            // Resuming causes the descriptor to be reuploaded.
+            *p.pause_tx.borrow_mut() = false;
+            runtime.advance_until_stalled().await;
+            assert_eq!(p.publish_count(), p.hsdir_count * 2);
+        });
+    }
+
+    #[test]
+    fn upload_limit_shared_between_services() {
+        MockRuntime::test_with_various(|runtime| async move {
+            const LIMIT: usize = 2;
+
+            let upload_limit = DescriptorUploadLimit::new(NonZeroUsize::new(LIMIT).unwrap());
+            let (mut open_tx, open_rx) = watch::channel();
+            let gate = UploadGate {
+                in_flight: Default::default(),
+                max_in_flight: Default::default(),
+                open: open_rx,
+            };
+
+            let mut publishers = ["test-svc-a", "test-svc-b"].map(|nickname| {
+                let nickname = HsNickname::try_from(nickname.to_string()).unwrap();
+                let config = build_test_config(nickname, Anonymity::Anonymous);
+                TestPublisher::launch_with_options(
+                    &runtime,
+                    config,
+                    TestPublisherOptions {
+                        upload_limit: Some(upload_limit.clone()),
+                        upload_gate: Some(gate.clone()),
+                        ..Default::default()
+                    },
+                )
+            });
+            runtime.advance_until_stalled().await;
+
+            // Both services want to upload to all their HSDirs,
+            // but only LIMIT uploads can be in flight between them.
+            for p in &mut publishers {
+                p.update_ipts(&runtime);
+            }
+            runtime.progress_until_stalled().await;
+            assert!(publishers.iter().all(|p| p.hsdir_count > LIMIT));
+            assert_eq!(gate.in_flight.load(Ordering::SeqCst), LIMIT);
+
+            // Once the uploads are let through, every descriptor reaches every HSDir,
+            // without ever exceeding the limit.
+            *open_tx.borrow_mut() = true;
+            runtime.advance_until_stalled().await;
+            assert_eq!(gate.max_in_flight.load(Ordering::SeqCst), LIMIT);
+            for p in &publishers {
+                assert_eq!(p.publish_count(), p.hsdir_count);
+            }
+        });
+    }
+
+    #[test]
+    fn retry_budget_bounds_failing_uploads() {
+        MockRuntime::test_with_various(|runtime| async move {
+            /// How long we let the uploads keep failing for.
+            ///
+            /// This is less than the time after which the publisher gives up on an HsDir.
+            const WINDOW: Duration = Duration::from_secs(20);
+
+            let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+            let config = build_test_config(nickname, Anonymity::Anonymous);
+            let mut p = TestPublisher::launch_with_options(
+                &runtime,
+                config,
+                TestPublisherOptions {
+                    hsdirs_fail: true,
+                    ..Default::default()
+                },
+            );
+            runtime.advance_until_stalled().await;
+
+            // The first attempt to upload to each HSDir doesn't come out of the retry budget.
+            p.update_ipts(&runtime);
+            runtime.progress_until_stalled().await;
+            assert_eq!(p.publish_count(), p.hsdir_count);
+
+            // All the HSDirs keep failing, but between them, they only get retried
+            // as often as the budget allows.
+            runtime.advance_by(WINDOW).await;
+            let retries = p.publish_count() - p.hsdir_count;
+            let budget = RETRY_BUDGET_BURST as usize
+                + (WINDOW.as_secs() / RETRY_BUDGET_INTERVAL.as_secs()) as usize;
+            assert!(retries > 0);
+            assert!(
+                retries <= budget,
+                "{retries} retries exceeds budget of {budget}"
+            );
+        });
+    }
+
+    #[test]
+    #[traced_test]
+    fn publish_ipt_wait_timeout() {
+        MockRuntime::test_with_various(|runtime| async move {
+            const TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+            let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+            let mut config = build_test_config(nickname, Anonymity::Anonymous);
+            config.ipt_wait_timeout = TIMEOUT;
+            let mut p = TestPublisher::launch(&runtime, config, None);
+            // The IPT manager is doing fine, as far as it's concerned.
+            p.status_tx.maybe_update_ipt_mgr(State::Running);
+            runtime.progress_until_stalled().await;
+
+            // We don't have any IPTs, but we haven't been waiting for long.
+            runtime.advance_by(TIMEOUT - Duration::from_secs(1)).await;
+            let status = p.status_tx.get();
+            assert!(!status.awaiting_ipts_timed_out());
+            assert_ne!(status.state(), State::Broken);
+
+            // Now we've been waiting for too long.
+            runtime.advance_by(Duration::from_secs(2)).await;
+            let status = p.status_tx.get();
+            assert!(status.awaiting_ipts_timed_out());
+            assert_eq!(status.state(), State::Broken);
+            assert!(logs_contain("no introduction points available"));
+            assert_eq!(p.publish_count(), 0);
+
+            // Once the IPTs show up, we publish, and recover.
+            p.update_ipts(&runtime);
+            runtime.advance_until_stalled().await;
+            let status = p.status_tx.get();
+            assert!(!status.awaiting_ipts_timed_out());
+            assert_eq!(status.state(), State::Running);
+            assert_eq!(p.publish_count(), p.hsdir_count);
+        });
+    }
+
+    #[test]
+    fn publish_toggle_encryption() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+            let config = build_test_config(nickname, Anonymity::Anonymous);
+            let client = HsClientDescEncKeypair::generate(&mut testing_rng());
+
+            let observed: Arc<Mutex<Vec<String>>> = Default::default();
+            let observer: DescriptorUploadObserver = {
+                let observed = Arc::clone(&observed);
+                Arc::new(move |desc: &str, _hsdir: &RelayIds| {
+                    observed.lock().unwrap().push(desc.to_string());
+                })
+            };
+
+            let mut p = TestPublisher::launch(&runtime, config.clone(), Some(observer));
+            runtime.advance_until_stalled().await;
+            // We parse the uploaded descriptors, whose lifetime must be at least a minute.
+            let mut ipt_set = test_ipt_set();
+            ipt_set.lifetime = Duration::from_secs(3 * 60 * 60);
+            p.ipts.borrow_for_update(runtime.clone()).ipts = Some(ipt_set);
+            runtime.advance_until_stalled().await;
+
+            // Return the descriptors uploaded in the last round of uploads.
+            let last_round = |p: &TestPublisher| {
+                let observed = observed.lock().unwrap();
+                assert_eq!(observed.len(), p.publish_count());
+                observed[observed.len() - p.hsdir_count..].to_vec()
+            };
+
+            // The descriptor is not encrypted by default.
+            assert_eq!(p.publish_count(), p.hsdir_count);
+            for desc in last_round(&p) {
+                assert!(p.can_decrypt(&desc, None));
+            }
+
+            // Enabling encryption causes an encrypted descriptor to be uploaded.
+            let mut encrypted_config = config.clone();
+            encrypted_config.encrypt_descriptor = Some(DescEncryptionConfig {
+                authorized_client: vec![AuthorizedClientConfig::Curve25519Key(
+                    client.public().clone(),
+                )],
+            });
+            *p.config_tx.borrow_mut() = Arc::new(encrypted_config);
+            runtime.advance_until_stalled().await;
+            assert_eq!(p.publish_count(), p.hsdir_count * 2);
+            for desc in last_round(&p) {
+                assert!(!p.can_decrypt(&desc, None));
+                assert!(p.can_decrypt(&desc, Some(&client)));
+            }
+
+            // Disabling encryption causes an unencrypted descriptor to be uploaded.
+            *p.config_tx.borrow_mut() = Arc::new(config);
+            runtime.advance_until_stalled().await;
+            assert_eq!(p.publish_count(), p.hsdir_count * 3);
+            for desc in last_round(&p) {
+                assert!(p.can_decrypt(&desc, None));
+            }
+        });
+    }
+
+    #[test]
+    fn upload_observer_set_while_running() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+            let config = build_test_config(nickname, Anonymity::Anonymous);
+
+            let observed: Arc<Mutex<Vec<String>>> = Default::default();
+            let observer: DescriptorUploadObserver = {
+                let observed = Arc::clone(&observed);
+                Arc::new(move |desc: &str, _hsdir: &RelayIds| {
+                    observed.lock().unwrap().push(desc.to_string());
+                })
+            };
+
+            let mut p = TestPublisher::launch(&runtime, config.clone(), None);
+            runtime.advance_until_stalled().await;
+            p.update_ipts(&runtime);
+            runtime.advance_until_stalled().await;
+            assert_eq!(p.publish_count(), p.hsdir_count);
+            assert!(observed.lock().unwrap().is_empty());
+
+            // Any change to the config the publisher uses causes it to republish.
+            let reconfigure = |p: &mut TestPublisher, publish_with_stale_netdir| {
+                let mut config = config.clone();
+                config.publish_with_stale_netdir = publish_with_stale_netdir;
+                *p.config_tx.borrow_mut() = Arc::new(config);
+            };
+
+            // An observer set after launch sees the next round of uploads...
+            *p.upload_observer_tx.borrow_mut() = Some(observer);
+            reconfigure(&mut p, true);
+            runtime.advance_until_stalled().await;
+            assert_eq!(p.publish_count(), p.hsdir_count * 2);
+            assert_eq!(observed.lock().unwrap().len(), p.hsdir_count);
+
+            // ...until it is cleared.
+            *p.upload_observer_tx.borrow_mut() = None;
+            reconfigure(&mut p, false);
+            runtime.advance_until_stalled().await;
+            assert_eq!(p.publish_count(), p.hsdir_count * 3);
+            assert_eq!(observed.lock().unwrap().len(), p.hsdir_count);
+        });
+    }
+
+    #[test]
+    fn publish_offline() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+            let config = build_test_config(nickname, Anonymity::Anonymous);
+
+            let observed: Arc<Mutex<Vec<String>>> = Default::default();
+            let observer: DescriptorUploadObserver = {
+                let observed = Arc::clone(&observed);
+                Arc::new(move |desc: &str, _hsdir: &RelayIds| {
+                    observed.lock().unwrap().push(desc.to_string());
+                })
+            };
+
+            let mut p = TestPublisher::launch_with_options(
+                &runtime,
+                config,
+                TestPublisherOptions {
+                    offline: true,
+                    upload_observer: Some(observer),
+                    ..Default::default()
+                },
+            );
+            runtime.advance_until_stalled().await;
+            // We parse the uploaded descriptors, whose lifetime must be at least a minute.
+            let mut ipt_set = test_ipt_set();
+            ipt_set.lifetime = Duration::from_secs(3 * 60 * 60);
+            p.ipts.borrow_for_update(runtime.clone()).ipts = Some(ipt_set);
+            runtime.advance_until_stalled().await;
+
+            // The descriptors are built from the provisioned keys, and are valid.
+            assert_eq!(p.publish_count(), p.hsdir_count);
+            assert_eq!(p.status_tx.get().publisher_state(), State::Running);
+            for desc in observed.lock().unwrap().iter() {
+                assert!(p.can_decrypt(desc, None));
+            }
+        });
+    }
+
+    #[test]
+    fn periodic_republication() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+            let mut config = build_test_config(nickname, Anonymity::Anonymous);
+            let interval = Duration::from_secs(30 * 60);
+            config.descriptor_republish_interval = interval;
+            // Each republication happens within this long of `interval`.
+            let jitter = interval / 3;
+
+            let mut p = TestPublisher::launch(&runtime, config, None);
+            runtime.progress_until_stalled().await;
+            p.update_ipts(&runtime);
+            runtime.progress_until_stalled().await;
+            assert_eq!(p.publish_count(), p.hsdir_count);
+
+            // Nothing happens until the earliest time we might republish.
+            runtime
+                .advance_by(interval - jitter - Duration::from_secs(1))
+                .await;
+            assert_eq!(p.publish_count(), p.hsdir_count);
+
+            // By the latest time we might republish, we have republished exactly once.
+            runtime
+                .advance_by(jitter * 2 + Duration::from_secs(1))
+                .await;
+            assert_eq!(p.publish_count(), p.hsdir_count * 2);
+        });
+    }
+
+    #[test]
+    fn revision_counter_increases_despite_clock_going_backwards() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+            let config = build_test_config(nickname, Anonymity::Anonymous);
+
+            // Start in the middle of the current time period, so that going back in time
+            // doesn't take us into the previous one.
+            let netdir = testnet::construct_netdir().unwrap_if_sufficient().unwrap();
+            let range = netdir.hs_time_period().range().unwrap();
+            let midpoint = range.start + (range.end.duration_since(range.start).unwrap() / 2);
+            runtime.jump_wallclock(midpoint);
+
+            let observed: Arc<Mutex<Vec<RevisionCounter>>> = Default::default();
+            let observer: DescriptorUploadObserver = {
+                let observed = Arc::clone(&observed);
+                Arc::new(move |desc: &str, _hsdir: &RelayIds| {
+                    let counter = desc
+                        .lines()
+                        .find_map(|line| line.strip_prefix("revision-counter "))
+                        .unwrap()
+                        .parse::<u64>()
+                        .unwrap();
+                    observed.lock().unwrap().push(counter.into());
+                })
+            };
+
+            let mut p = TestPublisher::launch(&runtime, config, Some(observer));
+            runtime.advance_until_stalled().await;
+            p.update_ipts(&runtime);
+            runtime.advance_until_stalled().await;
+            assert_eq!(p.publish_count(), p.hsdir_count);
+            let first_round_max = *observed.lock().unwrap().iter().max().unwrap();
+
+            // The clock goes back an hour, and the IPTs change.
+            runtime.jump_wallclock(midpoint - Duration::from_secs(60 * 60));
+            p.update_ipts(&runtime);
+            runtime.advance_until_stalled().await;
+            assert_eq!(p.publish_count(), p.hsdir_count * 2);
+
+            // The revision counters of the new descriptors are still higher than those of
+            // the descriptors we published before the clock went backwards.
+            let observed = observed.lock().unwrap();
+            for counter in &observed[p.hsdir_count..] {
+                assert!(*counter > first_round_max);
+            }
+        });
+    }
+
+    #[test]
+    fn time_period_change_event() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+            let config = build_test_config(nickname.clone(), Anonymity::Anonymous);
+            let (_config_tx, config_rx) = watch::channel_with(Arc::new(config));
+            let (_shutdown_tx, shutdown_rx) = broadcast::channel(0);
+            let (_pause_tx, pause_rx) = watch::channel();
+            let (_ipts, pv) = ipts_channel(&runtime, create_storage_handles().1).unwrap();
+
+            let netdir = testnet::construct_netdir().unwrap_if_sufficient().unwrap();
+            let old_period = netdir.hs_time_period();
+            let valid_after = netdir.lifetime().valid_after();
+            let keystore_dir = tempdir().unwrap();
+            let (_hsid, _blind_id, keymgr) = init_keymgr(&keystore_dir, &nickname, &netdir);
+            let netdir_provider = Arc::new(NotifyingNetDirProvider::new(netdir));
+            let (_dir_provider_tx, dir_provider_rx) =
+                watch::channel_with(netdir_provider.clone() as Arc<dyn NetDirProvider>);
+
+            let circpool = MockReactorState {
+                publish_count: Default::default(),
+                poll_read_responses: [Ok(OK_RESPONSE.to_string())].into_iter(),
+                responses_for_hsdir: Default::default(),
+                one_hop_circ_count: Default::default(),
+                upload_gate: None,
+            };
+            let offline_certs = offline_cert_storage(&nickname);
+            let publisher: Publisher<MockRuntime, MockReactorState<_>> = Publisher::new(
+                runtime.clone(),
+                nickname,
+                dir_provider_rx,
+                circpool,
+                pv,
+                config_rx,
+                shutdown_rx,
+                pause_rx,
+                keymgr,
+                offline_certs,
+                StatusSender::new(OnionServiceStatus::new_shutdown()),
+                watch::channel().1,
+                None,
+            );
+            let mut events = publisher.time_period_change_events();
+            publisher.launch().unwrap();
+            runtime.advance_until_stalled().await;
+
+            // Learning about the initial time periods is not a change.
+            assert!(events.next().now_or_never().is_none());
+
+            // A consensus from a day later belongs to the next time period.
+            let one_day = Duration::from_secs(86400);
+            let valid_after = valid_after + one_day;
+            let lifetime = Lifetime::new(
+                valid_after,
+                valid_after + one_day / 2,
+                valid_after + one_day,
+            )
+            .unwrap();
+            let new_netdir = testnet::construct_custom_netdir_with_params(
+                testnet::simple_net_func,
+                iter::empty::<(&str, _)>(),
+                Some(lifetime),
+            )
+            .unwrap()
+            .unwrap_if_sufficient()
+            .unwrap();
+            let new_period = new_netdir.hs_time_period();
+            assert_ne!(new_period, old_period);
+
+            netdir_provider.set_netdir_and_notify(new_netdir);
+            runtime.advance_until_stalled().await;
+
+            let event = events.next().now_or_never().unwrap().unwrap();
+            assert_eq!(
+                event,
+                TimePeriodChangeEvent::new(vec![new_period], vec![old_period])
+            );
+            assert!(events.next().now_or_never().is_none());
+        });
+    }
+
+    #[test]
+    fn replace_netdir_provider() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+            let config = build_test_config(nickname.clone(), Anonymity::Anonymous);
+            let (_config_tx, config_rx) = watch::channel_with(Arc::new(config));
+            let (_shutdown_tx, shutdown_rx) = broadcast::channel(0);
+            let (_pause_tx, pause_rx) = watch::channel();
+            let (_ipts, pv) = ipts_channel(&runtime, create_storage_handles().1).unwrap();
+
+            let netdir = testnet::construct_netdir().unwrap_if_sufficient().unwrap();
+            let old_period = netdir.hs_time_period();
+            let valid_after = netdir.lifetime().valid_after();
+            let keystore_dir = tempdir().unwrap();
+            let (_hsid, _blind_id, keymgr) = init_keymgr(&keystore_dir, &nickname, &netdir);
+            let netdir_provider: Arc<dyn NetDirProvider> =
+                Arc::new(TestNetDirProvider::from(netdir));
+            let (mut dir_provider_tx, dir_provider_rx) = watch::channel_with(netdir_provider);
+
+            let circpool = MockReactorState {
+                publish_count: Default::default(),
+                poll_read_responses: [Ok(OK_RESPONSE.to_string())].into_iter(),
+                responses_for_hsdir: Default::default(),
+                one_hop_circ_count: Default::default(),
+                upload_gate: None,
+            };
+            let offline_certs = offline_cert_storage(&nickname);
+            let publisher: Publisher<MockRuntime, MockReactorState<_>> = Publisher::new(
+                runtime.clone(),
+                nickname,
+                dir_provider_rx,
+                circpool,
+                pv,
+                config_rx,
+                shutdown_rx,
+                pause_rx,
+                keymgr,
+                offline_certs,
+                StatusSender::new(OnionServiceStatus::new_shutdown()),
+                watch::channel().1,
+                None,
+            );
+            let mut events = publisher.time_period_change_events();
+            publisher.launch().unwrap();
+            runtime.advance_until_stalled().await;
+
+            // Learning about the initial time periods is not a change.
+            assert!(events.next().now_or_never().is_none());
+
+            // Replacing the provider with one whose consensus is from a day later
+            // moves us to the next time period, without any DirEvent.
+            let one_day = Duration::from_secs(86400);
+            let valid_after = valid_after + one_day;
+            let lifetime = Lifetime::new(
+                valid_after,
+                valid_after + one_day / 2,
+                valid_after + one_day,
+            )
+            .unwrap();
+            let new_netdir = testnet::construct_custom_netdir_with_params(
+                testnet::simple_net_func,
+                iter::empty::<(&str, _)>(),
+                Some(lifetime),
+            )
+            .unwrap()
+            .unwrap_if_sufficient()
+            .unwrap();
+            let new_period = new_netdir.hs_time_period();
+            assert_ne!(new_period, old_period);
+
+            *dir_provider_tx.borrow_mut() = Arc::new(TestNetDirProvider::from(new_netdir));
+            runtime.advance_until_stalled().await;
+
+            let event = events.next().now_or_never().unwrap().unwrap();
+            assert_eq!(
+                event,
+                TimePeriodChangeEvent::new(vec![new_period], vec![old_period])
+            );
+            assert!(events.next().now_or_never().is_none());
+        });
+    }
+
+    #[test]
+    fn publish_only_to_allowed_hsdirs() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+            let config = build_test_config(nickname, Anonymity::Anonymous);
+
+            let observed: Arc<Mutex<Vec<RelayIds>>> = Default::default();
+            let observer: DescriptorUploadObserver = {
+                let observed = Arc::clone(&observed);
+                Arc::new(move |_desc: &str, hsdir: &RelayIds| {
+                    observed.lock().unwrap().push(hsdir.clone());
+                })
+            };
+
+            let mut p = TestPublisher::launch(&runtime, config.clone(), Some(observer));
+            runtime.advance_until_stalled().await;
+
+            // Find out which HSDirs we would normally upload to, and only allow two of them.
+            let all_hsdirs = p.ring_hsdirs();
+            assert!(all_hsdirs.len() > 2);
+            let allowed = all_hsdirs[..2].iter().copied().collect::<HashSet<_>>();
+
+            let mut allowlist_config = config;
+            allowlist_config.hsdir_allowlist =
+                Some(allowed.iter().copied().map(RelayId::from).collect());
+            *p.config_tx.borrow_mut() = Arc::new(allowlist_config);
+            runtime.advance_until_stalled().await;
+
+            p.update_ipts(&runtime);
+            runtime.advance_until_stalled().await;
+
+            // We only uploaded the descriptor to the allowed HSDirs, once each.
+            assert_eq!(p.publish_count(), allowed.len());
+            let targeted = observed
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|hsdir| *hsdir.rsa_identity().unwrap())
+                .collect::<HashSet<_>>();
+            assert_eq!(targeted, allowed);
+
+            // That's fewer HSDirs than the spec expects, but all the ones we're allowed to use,
+            // so our descriptor counts as published.
+            assert_eq!(p.status_tx.get().publisher_state(), State::Running);
+        });
+    }
+
+    #[test]
+    fn wait_until_reachable_with_allowlist() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+            let config = build_test_config(nickname, Anonymity::Anonymous);
+            let mut p = TestPublisher::launch(&runtime, config.clone(), None);
+            runtime.advance_until_stalled().await;
+
+            // Only allow fewer HSDirs than the spec expects.
+            let ring = p.ring_hsdirs();
+            assert!(ring.len() > 2);
+            let mut allowlist_config = config;
+            allowlist_config.hsdir_allowlist =
+                Some(ring[..2].iter().copied().map(RelayId::from).collect());
+            *p.config_tx.borrow_mut() = Arc::new(allowlist_config);
+            runtime.advance_until_stalled().await;
+
+            let mut reachable = Box::pin(p.status_tx.subscribe_progress().wait_until_reachable());
+            assert!((&mut reachable).now_or_never().is_none());
+
+            // Once our descriptor is on all the allowed HSDirs, clients can reach us.
+            p.update_ipts(&runtime);
+            runtime.advance_until_stalled().await;
+            assert_eq!(p.publish_count(), 2);
+            assert_eq!(reachable.now_or_never(), Some(()));
+        });
+    }
+
+    #[test]
+    fn publish_to_extra_hsdirs() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+            let config = build_test_config(nickname, Anonymity::Anonymous);
+
+            let observed: Arc<Mutex<Vec<RelayIds>>> = Default::default();
+            let observer: DescriptorUploadObserver = {
+                let observed = Arc::clone(&observed);
+                Arc::new(move |_desc: &str, hsdir: &RelayIds| {
+                    observed.lock().unwrap().push(hsdir.clone());
+                })
+            };
+
+            let mut p = TestPublisher::launch(&runtime, config.clone(), Some(observer));
+            runtime.advance_until_stalled().await;
+
+            // Pick a relay that isn't one of the HSDirs we would normally upload to.
+            let netdir = testnet::construct_netdir().unwrap_if_sufficient().unwrap();
+            let ring = p.ring_hsdirs().into_iter().collect::<HashSet<_>>();
+            let extra = netdir
+                .relays()
+                .map(|relay| *relay.rsa_identity().unwrap())
+                .find(|id| !ring.contains(id))
+                .unwrap();
+            // An extra HSDir that's already in the ring is only uploaded to once.
+            let in_ring = *ring.iter().next().unwrap();
+
+            let mut extra_config = config;
+            extra_config.extra_hsdirs = vec![RelayId::from(extra), RelayId::from(in_ring)];
+            *p.config_tx.borrow_mut() = Arc::new(extra_config);
+            runtime.advance_until_stalled().await;
+
+            p.update_ipts(&runtime);
+            runtime.advance_until_stalled().await;
+
+            // We uploaded the descriptor to each member of the ring, and to the extra HSDir.
+            assert_eq!(p.publish_count(), p.hsdir_count + 1);
+            let targeted = observed
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|hsdir| *hsdir.rsa_identity().unwrap())
+                .collect::<HashSet<_>>();
+            let expected = ring.into_iter().chain([extra]).collect::<HashSet<_>>();
+            assert_eq!(targeted, expected);
+        });
+    }
+
+    /// A [`NetDirProvider`] whose netdir is stale, so it only provides it if timeliness is not checked.
+    struct StaleNetDirProvider(TestNetDirProvider);
+
+    impl NetDirProvider for StaleNetDirProvider {
+        fn netdir(&self, timeliness: Timeliness) -> tor_netdir::Result<Arc<NetDir>> {
+            match timeliness {
+                Timeliness::Unchecked => self.0.netdir(timeliness),
+                Timeliness::Strict | Timeliness::Timely => Err(tor_netdir::Error::DirExpired),
+            }
+        }
+
+        fn events(&self) -> futures::stream::BoxStream<'static, tor_netdir::DirEvent> {
+            self.0.events()
+        }
+
+        fn params(&self) -> Arc<dyn AsRef<tor_netdir::params::NetParameters>> {
+            self.0.params()
+        }
+    }
+
+    #[test]
+    #[traced_test]
+    fn publish_with_stale_netdir() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+
+            for publish_with_stale_netdir in [false, true] {
+                let mut config = build_test_config(nickname.clone(), Anonymity::Anonymous);
+                config.publish_with_stale_netdir = publish_with_stale_netdir;
+
+                let mut p = TestPublisher::launch_with_options(
+                    &runtime,
+                    config,
+                    TestPublisherOptions {
+                        mk_dir_provider: Some(|netdir| {
+                            Arc::new(StaleNetDirProvider(TestNetDirProvider::from(netdir)))
+                        }),
+                        ..Default::default()
+                    },
+                );
+                runtime.advance_until_stalled().await;
+
+                p.update_ipts(&runtime);
+                runtime.advance_until_stalled().await;
+
+                if publish_with_stale_netdir {
+                    // We publish anyway, but complain about it.
+                    assert_eq!(p.publish_count(), p.hsdir_count);
+                    assert!(logs_contain("no timely netdir available"));
+                } else {
+                    // We're still waiting for a timely netdir.
+                    assert_eq!(p.publish_count(), 0);
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn hsdir_upload_statuses() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+            let config = build_test_config(nickname.clone(), Anonymity::Anonymous);
+            let (_config_tx, config_rx) = watch::channel_with(Arc::new(config));
+            let (_shutdown_tx, shutdown_rx) = broadcast::channel(0);
+            let (_pause_tx, pause_rx) = watch::channel();
+            let (mut ipts, pv) = ipts_channel(&runtime, create_storage_handles().1).unwrap();
+
+            let netdir = testnet::construct_netdir().unwrap_if_sufficient().unwrap();
+            let period = netdir.hs_time_period();
+            let keystore_dir = tempdir().unwrap();
+            let (_hsid, blind_id, keymgr) = init_keymgr(&keystore_dir, &nickname, &netdir);
+            let hsdirs = netdir
+                .hs_dirs_upload([(blind_id, period)].into_iter())
+                .unwrap()
+                .map(|(_, hsdir)| *hsdir.rsa_identity().unwrap())
+                .collect::<Vec<_>>();
+            assert!(hsdirs.len() > 1);
+
+            // The first HSDir rejects each of our uploads, and the others accept them.
+            let ok_responses = vec![Ok(OK_RESPONSE.to_string()), Ok(String::new())]
+                .into_iter()
+                .cycle();
+            let err_responses = vec![Ok(ERR_RESPONSE.to_string()), Ok(String::new())]
+                .into_iter()
+                .cycle();
+            let failing_hsdir = hsdirs[0];
+            let responses_for_hsdir =
+                HashMap::from([(failing_hsdir, Arc::new(Mutex::new(err_responses)))]);
+            let circpool = MockReactorState {
+                publish_count: Default::default(),
+                poll_read_responses: ok_responses,
+                responses_for_hsdir: Arc::new(Mutex::new(responses_for_hsdir)),
+                one_hop_circ_count: Default::default(),
+                upload_gate: None,
+            };
+
+            let (_dir_provider_tx, dir_provider_rx) =
+                watch::channel_with(Arc::new(TestNetDirProvider::from(netdir)) as Arc<_>);
+            let offline_certs = offline_cert_storage(&nickname);
+            let publisher: Publisher<MockRuntime, MockReactorState<_>> = Publisher::new(
+                runtime.clone(),
+                nickname,
+                dir_provider_rx,
+                circpool,
+                pv,
+                config_rx,
+                shutdown_rx,
+                pause_rx,
+                keymgr,
+                offline_certs,
+                StatusSender::new(OnionServiceStatus::new_shutdown()),
+                watch::channel().1,
+                None,
+            );
+            let upload_statuses = publisher.upload_statuses();
+            publisher.launch().unwrap();
+            runtime.advance_until_stalled().await;
+
+            // Nothing has been uploaded yet.
+            assert!(upload_statuses.lock().unwrap().is_empty());
+
+            ipts.borrow_for_update(runtime.clone()).ipts = Some(test_ipt_set());
+            runtime.advance_until_stalled().await;
+
+            let statuses = upload_statuses.lock().unwrap().clone();
+            assert_eq!(statuses.len(), 1);
+            assert_eq!(statuses[0].time_period(), period);
+            assert_eq!(statuses[0].hsdirs().len(), hsdirs.len());
+            for status in statuses[0].hsdirs() {
+                let rsa_id = status.relay_ids().rsa_identity().unwrap();
+                assert!(hsdirs.contains(rsa_id));
+                let expected = if *rsa_id == failing_hsdir {
+                    UploadStatus::Failure
+                } else {
+                    UploadStatus::Success
+                };
+                assert_eq!(status.upload_status(), expected);
+            }
+        });
+    }
+
+    // TODO HSS: test that the descriptor is republished when the anonymity config changes
+
+    // TODO HSS: test that the descriptor is reuploaded only to the HSDirs that need it (i.e. the
+    // ones for which it's dirty)
+
+    // TODO HSS: test that rate-limiting works correctly
+
+    #[test]
+    fn blind_id_keypair_without_key_generation() {
+        let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+        let netdir = testnet::construct_netdir().unwrap_if_sufficient().unwrap();
+        let keystore_dir = tempdir().unwrap();
+        // This only provisions the blinded key for the current time period.
+        let (_hsid, blind_id, keymgr) = init_keymgr(&keystore_dir, &nickname, &netdir);
+        let period = netdir.hs_time_period();
+        let next_period = period.next().unwrap();
+
+        let read = |period, allow_key_generation| {
+            read_blind_id_keypair(
+                &keymgr,
+                &nickname,
+                period,
+                KeystoreSelector::Default,
+                allow_key_generation,
+            )
+        };
+
+        // A pre-provisioned key is used even if we can't generate keys.
+        let blind_id_kp = read(period, false).unwrap().unwrap();
+        assert_eq!(HsBlindId::from(HsBlindIdKey::from(&blind_id_kp)), blind_id);
+
+        // A missing key is an error if we can't generate keys...
+        let err = read(next_period, false).unwrap_err();
+        assert!(matches!(
+            err,
+            FatalError::MissingBlindIdKeypair { period, .. } if period == next_period
+        ));
+        assert!(keymgr
+            .get::<HsBlindIdKeypair>(&BlindIdKeypairSpecifier::new(nickname.clone(), next_period))
+            .unwrap()
+            .is_none());
+
+        // ...but not otherwise.
+        assert!(read(next_period, true).unwrap().is_some());
+        assert!(read(next_period, false).unwrap().is_some());
+    }
+
+    #[test]
+    fn blind_id_keypair_offline() {
+        let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+        let netdir = testnet::construct_netdir().unwrap_if_sufficient().unwrap();
+        let keystore_dir = tempdir().unwrap();
+        // This only provisions the blinded key and descriptor signing key
+        // for the current time period.
+        let (_hsid, blind_id, keymgr) = init_keymgr(&keystore_dir, &nickname, &netdir);
+        let period = netdir.hs_time_period();
+        let next_period = period.next().unwrap();
+        let after_next_period = next_period.next().unwrap();
+
+        // Take the identity keypair offline, keeping only the public key.
+        keymgr
+            .remove::<HsIdKeypair>(
+                &HsIdKeypairSpecifier::new(nickname.clone()),
+                KeystoreSelector::Default,
+            )
+            .unwrap()
+            .unwrap();
+
+        let read = |period| {
+            read_blind_id_keypair(&keymgr, &nickname, period, KeystoreSelector::Default, true)
+        };
+
+        // A pre-provisioned blinded key is used as usual.
+        let blind_id_kp = read(period).unwrap().unwrap();
+        assert_eq!(HsBlindId::from(HsBlindIdKey::from(&blind_id_kp)), blind_id);
+
+        // With only a descriptor signing key, we can't derive the blinded key.
+        insert_svc_key(
+            HsDescSigningKeypair::from(ed25519::Keypair::generate(&mut testing_rng())),
+            &keymgr,
+            &DescSigningKeypairSpecifier::new(nickname.clone(), next_period),
+        );
+        assert!(read(next_period).unwrap().is_none());
+
+        // Without any provisioned keys, we have run out.
+        let err = read(after_next_period).unwrap_err();
+        assert!(matches!(
+            &err,
+            FatalError::OfflineKeysExhausted { period, .. } if *period == after_next_period
+        ));
+        assert_eq!(err.kind(), tor_error::ErrorKind::InvalidConfig);
+        // We haven't generated anything.
+        assert!(keymgr
+            .get::<HsBlindIdKeypair>(&BlindIdKeypairSpecifier::new(
+                nickname.clone(),
+                after_next_period
+            ))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn provision_offline_keys_for_future_periods() {
+        let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+        let netdir = testnet::construct_netdir().unwrap_if_sufficient().unwrap();
+        let keystore_dir = tempdir().unwrap();
+        let (_hsid, _blind_id, keymgr) = init_keymgr(&keystore_dir, &nickname, &netdir);
+        let hsid_kp: HsIdKeypair = keymgr
+            .get(&HsIdKeypairSpecifier::new(nickname.clone()))
+            .unwrap()
+            .unwrap();
+        let periods = iter::successors(netdir.hs_time_period().next(), |period| period.next())
+            .take(3)
+            .collect::<Vec<_>>();
+
+        let keys =
+            crate::provision_offline_keys(&hsid_kp, periods.iter().copied(), &mut testing_rng())
+                .unwrap();
+        assert_eq!(
+            keys.iter().map(|keys| keys.period()).collect::<Vec<_>>(),
+            periods
+        );
+
+        // The blinded keys are the ones the service would derive if it were online.
+        for keys in &keys {
+            let online_kp = read_blind_id_keypair(
+                &keymgr,
+                &nickname,
+                keys.period(),
+                KeystoreSelector::Default,
+                true,
+            )
+            .unwrap()
+            .unwrap();
+            assert_eq!(
+                HsBlindId::from(HsBlindIdKey::from(&online_kp)),
+                HsBlindId::from(keys.blind_id_key().clone()),
+            );
+        }
+
+        // Each period gets its own descriptor signing key.
+        let desc_signing_keys = keys
+            .iter()
+            .map(|keys| {
+                keys.desc_signing_keypair()
+                    .as_ref()
+                    .verifying_key()
+                    .to_bytes()
+            })
+            .collect::<HashSet<_>>();
+        assert_eq!(desc_signing_keys.len(), periods.len());
+
+        // Each descriptor signing key is certified by the blinded key of its period,
+        // until after the end of the period.
+        for keys in &keys {
+            let blind_id = ed25519::Ed25519Identity::from(keys.blind_id_key().as_ref());
+            let cert = tor_cert::Ed25519Cert::decode(keys.desc_signing_key_cert().as_slice())
+                .unwrap()
+                .should_be_signed_with(&blind_id)
+                .unwrap()
+                .check_signature()
+                .unwrap()
+                .check_valid_at(&keys.period().range().unwrap().end)
+                .unwrap();
+            assert_eq!(
+                cert.cert_type(),
+                tor_cert::CertType::HS_BLINDED_ID_V_SIGNING
+            );
+            assert_eq!(
+                cert.subject_key().as_ed25519(),
+                Some(&keys.desc_signing_keypair().as_ref().verifying_key().into())
+            );
+        }
+
+        // An online host that only has the public identity key can use them,
+        // once they are imported.
+        let online_keystore_dir = tempdir().unwrap();
+        let online_keymgr = Arc::new(
+            KeyMgrBuilder::default()
+                .default_store(Box::new(
+                    ArtiNativeKeystore::from_path_and_mistrust(
+                        &online_keystore_dir,
+                        &Mistrust::new_dangerously_trust_everyone(),
+                    )
+                    .unwrap(),
+                ))
+                .build()
+                .unwrap(),
+        );
+        insert_svc_key(
+            HsIdKey::from(&hsid_kp),
+            &online_keymgr,
+            &HsIdPublicKeySpecifier::new(nickname.clone()),
+        );
+        let (online_state_mgr, _) = create_storage_handles();
+        let online_certs = offline_cert_storage_handle(online_state_mgr.clone(), &nickname);
+        let read = |period| {
+            read_blind_id_keypair(
+                &online_keymgr,
+                &nickname,
+                period,
+                KeystoreSelector::Default,
+                true,
+            )
+        };
+
+        for keys in keys {
+            let period = keys.period();
+            assert!(matches!(
+                read(period),
+                Err(FatalError::OfflineKeysExhausted { .. })
+            ));
+
+            let blind_id = HsBlindId::from(keys.blind_id_key().clone());
+            let cert = keys.desc_signing_key_cert().clone();
+            keys.insert(
+                &online_keymgr,
+                &online_state_mgr,
+                &nickname,
+                KeystoreSelector::Default,
+            )
+            .unwrap();
+
+            assert!(read(period).unwrap().is_none());
+            let blind_id_key: HsBlindIdKey = online_keymgr
+                .get(&BlindIdPublicKeySpecifier::new(nickname.clone(), period))
+                .unwrap()
+                .unwrap();
+            assert_eq!(HsBlindId::from(blind_id_key), blind_id);
+
+            // The certificate is stored along with the keys.
+            let imported =
+                OfflineKeys::read(&online_keymgr, &online_certs, &nickname, period).unwrap();
+            assert_eq!(imported.desc_signing_key_cert(), &cert);
+        }
+    }
+
+    #[test]
+    fn descriptor_round_trip() {
+        let temp_dir = tempdir().unwrap();
+        let nickname = HsNickname::try_from(TEST_SVC_NICKNAME.to_string()).unwrap();
+        let netdir = testnet::construct_netdir().unwrap_if_sufficient().unwrap();
+        let (_hsid, _blind_id, keymgr) = init_keymgr(&temp_dir, &nickname, &netdir);
+        let hsid_key = keymgr
+            .get::<HsIdKey>(&HsIdPublicKeySpecifier::new(nickname.clone()))
+            .unwrap()
+            .unwrap();
+        let config = Arc::new(build_test_config(nickname, Anonymity::Anonymous));
+        let period = netdir.hs_time_period();
+        let mut ipt_set = test_ipt_set();
+        ipt_set.lifetime = Duration::from_secs(3 * 60 * 60);
+        let revision_counter = RevisionCounter::from(1234);
+        let now = SystemTime::now();
+
+        let built = descriptor::build_sign(
+            &keymgr,
+            &offline_cert_storage(&config.nickname),
+            &config,
+            &ipt_set,
+            period,
+            revision_counter,
+            &mut testing_rng(),
+            now,
+        )
+        .unwrap();
+        assert_eq!(built.revision_counter, revision_counter);
+
+        let desc = descriptor::parse_validate(&built.desc, &hsid_key, period, now, None).unwrap();
+
+        assert_eq!(desc.revision_counter(), revision_counter);
+        assert_eq!(desc.intro_points().len(), ipt_set.ipts.len());
+        for (parsed, ipt_in_set) in desc.intro_points().iter().zip(&ipt_set.ipts) {
+            let built = &ipt_in_set.ipt;
+            assert_eq!(parsed.ipt_ntor_key(), built.ipt_ntor_key());
+            assert_eq!(parsed.ipt_sid_key().as_ref(), built.ipt_sid_key().as_ref());
+            assert_eq!(
+                parsed.svc_ntor_key().as_ref(),
+                built.svc_ntor_key().as_ref()
+            );
+        }
+
+        // A descriptor for a different time period doesn't validate.
+        let next_period = period.next().unwrap();
+        assert!(
+            descriptor::parse_validate(&built.desc, &hsid_key, next_period, now, None).is_err()
+        );
+    }
 
     // TODO HSS: test that the uploaded descriptor contains the expected values
 
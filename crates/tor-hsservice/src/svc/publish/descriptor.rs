@@ -6,7 +6,7 @@ use std::time::{Duration, SystemTime};
 use rand_core::{CryptoRng, RngCore};
 
 use tor_cell::chancell::msg::HandshakeType;
-use tor_error::{internal, into_bad_api_usage, into_internal};
+use tor_error::{into_bad_api_usage, into_internal};
 use tor_hscrypto::pk::{HsBlindIdKey, HsDescSigningKeypair, HsIdKey, HsIdKeypair};
 use tor_hscrypto::time::TimePeriod;
 use tor_hscrypto::RevisionCounter;
@@ -17,21 +17,36 @@ use tor_netdoc::NetdocBuilder;
 
 use crate::config::DescEncryptionConfig;
 use crate::ipt_set::IptSet;
-use crate::svc::publish::reactor::{read_blind_id_keypair, AuthorizedClientConfigError};
+use crate::offline::OfflineCertStorageHandle;
+use crate::svc::publish::reactor::read_blind_id_keypair;
 use crate::{
-    BlindIdKeypairSpecifier, DescSigningKeypairSpecifier, FatalError, HsIdKeypairSpecifier,
-    OnionServiceConfig,
+    AuthorizedClientConfigError, DescSigningKeypairSpecifier, FatalError, HsIdKeypairSpecifier,
+    HsIdPublicKeySpecifier, OfflineKeys, OnionServiceConfig,
 };
 
+/// Lifetime of the certificates in the descriptor.
+///
+/// From C-Tor src/feature/hs/hs_descriptor.h:
+///
+/// "This defines the lifetime of the descriptor signing key and the cross certification cert of
+/// that key. It is set to 54 hours because a descriptor can be around for 48 hours and because
+/// consensuses are used after the hour, add an extra 6 hours to give some time for the service
+/// to stop using it."
+pub(crate) const HS_DESC_CERT_LIFETIME_SEC: Duration = Duration::from_secs(54 * 60 * 60);
+
 /// Build the descriptor.
 ///
 /// The `now` argument is used for computing the expiry of the `intro_{auth, enc}_key_cert`
 /// certificates included in the descriptor. The expiry will be set to 54 hours from `now`.
 ///
-/// Note: `blind_id_kp` is the blinded hidden service signing keypair used to sign descriptor
-/// signing keys (KP_hs_blind_id, KS_hs_blind_id).
+/// Note: the blinded hidden service signing keypair (KP_hs_blind_id, KS_hs_blind_id) is used to
+/// sign the descriptor signing key, unless the service is running in offline mode. In that case,
+/// the descriptor signing key and its certificate must have been provisioned instead
+/// (see [`OfflineKeys`]), and the certificate is read from `offline_certs`.
+#[allow(clippy::too_many_arguments)]
 pub(super) fn build_sign<Rng: RngCore + CryptoRng>(
     keymgr: &Arc<KeyMgr>,
+    offline_certs: &Arc<OfflineCertStorageHandle>,
     config: &Arc<OnionServiceConfig>,
     ipt_set: &IptSet,
     period: TimePeriod,
@@ -44,16 +59,6 @@ pub(super) fn build_sign<Rng: RngCore + CryptoRng>(
     /// The CREATE handshake type we support.
     const CREATE2_FORMATS: &[HandshakeType] = &[HandshakeType::NTOR];
 
-    /// Lifetime of the intro_{auth, enc}_key_cert certificates in the descriptor.
-    ///
-    /// From C-Tor src/feature/hs/hs_descriptor.h:
-    ///
-    /// "This defines the lifetime of the descriptor signing key and the cross certification cert of
-    /// that key. It is set to 54 hours because a descriptor can be around for 48 hours and because
-    /// consensuses are used after the hour, add an extra 6 hours to give some time for the service
-    /// to stop using it."
-    const HS_DESC_CERT_LIFETIME_SEC: Duration = Duration::from_secs(54 * 60 * 60);
-
     let intro_points = ipt_set
         .ipts
         .iter()
@@ -62,29 +67,61 @@ pub(super) fn build_sign<Rng: RngCore + CryptoRng>(
 
     let nickname = &config.nickname;
 
-    let svc_key_spec = HsIdKeypairSpecifier::new(nickname.clone());
-    let hsid_kp = keymgr
-        .get::<HsIdKeypair>(&svc_key_spec)?
-        .ok_or_else(|| FatalError::MissingHsIdKeypair(nickname.clone()))?;
-    let hsid = HsIdKey::from(&hsid_kp);
-
-    let blind_id_key_spec = BlindIdKeypairSpecifier::new(nickname.clone(), period);
+    let keystore_selector = config.keystore_selector();
+    let blind_id_kp = read_blind_id_keypair(
+        keymgr,
+        nickname,
+        period,
+        keystore_selector,
+        config.allow_key_generation,
+    )?;
 
-    // TODO: make the keystore selector configurable
-    let keystore_selector = Default::default();
-    let blind_id_kp = read_blind_id_keypair(keymgr, nickname, period)?
-        .ok_or_else(|| internal!("hidden service offline mode not supported"))?;
+    let (hsid, blind_id_key, hs_desc_sign, desc_signing_key_cert) = match blind_id_kp {
+        Some(blind_id_kp) => {
+            let svc_key_spec = HsIdKeypairSpecifier::new(nickname.clone());
+            let hsid_kp = keymgr
+                .get::<HsIdKeypair>(&svc_key_spec)?
+                .ok_or_else(|| FatalError::MissingHsIdKeypair(nickname.clone()))?;
+
+            let hs_desc_sign_key_spec = DescSigningKeypairSpecifier::new(nickname.clone(), period);
+            let hs_desc_sign = keymgr.get_or_generate::<HsDescSigningKeypair>(
+                &hs_desc_sign_key_spec,
+                keystore_selector,
+                rng,
+            )?;
+
+            let desc_signing_key_cert = create_desc_sign_key_cert(
+                &hs_desc_sign.as_ref().verifying_key(),
+                &blind_id_kp,
+                now + HS_DESC_CERT_LIFETIME_SEC,
+            )
+            .map_err(into_bad_api_usage!(
+                "failed to sign the descriptor signing key"
+            ))?;
+
+            (
+                HsIdKey::from(&hsid_kp),
+                HsBlindIdKey::from(&blind_id_kp),
+                hs_desc_sign,
+                desc_signing_key_cert,
+            )
+        }
+        None => {
+            // We are running in offline mode, so we can't sign the descriptor signing key
+            // ourselves: it must have been provisioned, along with its certificate.
+            let hsid_key_spec = HsIdPublicKeySpecifier::new(nickname.clone());
+            let hsid = keymgr
+                .get::<HsIdKey>(&hsid_key_spec)?
+                .ok_or_else(|| FatalError::MissingHsIdKeypair(nickname.clone()))?;
+            let (blind_id_key, hs_desc_sign, desc_signing_key_cert) =
+                OfflineKeys::read(keymgr, offline_certs, nickname, period)?.into_parts();
+
+            (hsid, blind_id_key, hs_desc_sign, desc_signing_key_cert)
+        }
+    };
 
-    let blind_id_key = HsBlindIdKey::from(&blind_id_kp);
     let subcredential = hsid.compute_subcredential(&blind_id_key, period);
 
-    let hs_desc_sign_key_spec = DescSigningKeypairSpecifier::new(nickname.clone(), period);
-    let hs_desc_sign = keymgr.get_or_generate::<HsDescSigningKeypair>(
-        &hs_desc_sign_key_spec,
-        keystore_selector,
-        rng,
-    )?;
-
     // TODO HSS: support introduction-layer authentication.
     let auth_required = None;
 
@@ -95,28 +132,16 @@ pub(super) fn build_sign<Rng: RngCore + CryptoRng>(
     // when building the descriptor. See #1048
     let intro_auth_key_cert_expiry = now + HS_DESC_CERT_LIFETIME_SEC;
     let intro_enc_key_cert_expiry = now + HS_DESC_CERT_LIFETIME_SEC;
-    let hs_desc_sign_cert_expiry = now + HS_DESC_CERT_LIFETIME_SEC;
-
-    // TODO HSS: Temporarily disabled while we figure out how we want the client auth config to
-    // work; see #1028
-    /*
-    let auth_clients: Option<Vec<curve25519::PublicKey>> = config.encrypt_descriptor
-        .map(|auth_clients| build_auth_clients(&auth_clients));
-    */
-
-    let auth_clients: Option<Vec<curve25519::PublicKey>> = None;
-
-    let desc_signing_key_cert = create_desc_sign_key_cert(
-        &hs_desc_sign.as_ref().verifying_key(),
-        &blind_id_kp,
-        hs_desc_sign_cert_expiry,
-    )
-    .map_err(into_bad_api_usage!(
-        "failed to sign the descriptor signing key"
-    ))?;
+
+    let auth_clients: Option<Vec<curve25519::PublicKey>> = config
+        .encrypt_descriptor
+        .as_ref()
+        .map(build_auth_clients)
+        .transpose()
+        .map_err(FatalError::AuthorizedClients)?;
 
     let desc = HsDescBuilder::default()
-        .blinded_id(&(&blind_id_kp).into())
+        .blinded_id(&blind_id_key)
         .hs_desc_sign(hs_desc_sign.as_ref())
         .hs_desc_sign_cert(desc_signing_key_cert)
         .create2_formats(CREATE2_FORMATS)
@@ -138,6 +163,37 @@ pub(super) fn build_sign<Rng: RngCore + CryptoRng>(
     })
 }
 
+/// Parse and validate a descriptor built by [`build_sign`].
+///
+/// This undoes what `build_sign` does: it checks that `desc` is for the blinded
+/// key of `hsid` in `period`, checks the signatures of both layers and that they
+/// are valid at `now`, and decrypts the descriptor (using `client`, if the
+/// descriptor is only for authorized clients).
+///
+/// Its inputs are only the encoded descriptor and public information,
+/// so it can be fed descriptors from `build_sign`, or arbitrary ones.
+/// It does not panic, whatever `desc` contains.
+#[cfg(test)]
+pub(super) fn parse_validate(
+    desc: &str,
+    hsid: &HsIdKey,
+    period: TimePeriod,
+    now: SystemTime,
+    client: Option<&tor_hscrypto::pk::HsClientDescEncKeypair>,
+) -> Result<tor_netdoc::doc::hsdesc::HsDesc, tor_netdoc::doc::hsdesc::HsDescError> {
+    use tor_checkable::Timebound as _;
+    use tor_netdoc::doc::hsdesc::HsDesc;
+
+    let (blind_id_key, subcredential) = hsid
+        .compute_blinded_key(period)
+        .expect("failed to blind a valid HsIdKey");
+    let blind_id = blind_id_key.id();
+
+    let desc = HsDesc::parse_decrypt_validate(desc, &blind_id, now, &subcredential, client)?;
+    // parse_decrypt_validate has just checked that `desc` is valid at `now`.
+    Ok(desc.dangerously_assume_timely())
+}
+
 /// Decode an encoded curve25519 key.
 fn decode_curve25519_str(key: &str) -> Result<curve25519::PublicKey, AuthorizedClientConfigError> {
     use base64ct::{Base64, Encoding};
@@ -0,0 +1,44 @@
+//! A limit on the number of concurrent descriptor uploads, shared between services.
+
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use async_lock::{Semaphore, SemaphoreGuardArc};
+
+/// A limit on the number of descriptor uploads that may be in flight at once.
+///
+/// Each onion service publisher already bounds its own uploads. This handle
+/// bounds the *combined* uploads of every service that was given a clone of it,
+/// so that a process running many services does not open an unbounded
+/// number of HsDir circuits at the same time.
+///
+/// Cloning this handle is cheap; all clones share the same limit.
+#[derive(Clone, Debug)]
+pub struct DescriptorUploadLimit {
+    /// The semaphore from which each upload takes a permit.
+    permits: Arc<Semaphore>,
+    /// The total number of permits, for `Debug` and [`DescriptorUploadLimit::limit`].
+    limit: NonZeroUsize,
+}
+
+impl DescriptorUploadLimit {
+    /// Create a new limit allowing at most `limit` concurrent uploads.
+    pub fn new(limit: NonZeroUsize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(limit.get())),
+            limit,
+        }
+    }
+
+    /// Return the maximum number of concurrent uploads allowed by this limit.
+    pub fn limit(&self) -> NonZeroUsize {
+        self.limit
+    }
+
+    /// Wait until an upload may start.
+    ///
+    /// The returned guard must be held for the duration of the upload.
+    pub(crate) async fn acquire(&self) -> SemaphoreGuardArc {
+        self.permits.acquire_arc().await
+    }
+}
@@ -2,8 +2,10 @@
 //!
 //! TODO HSS: write the docs
 
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::iter;
+use std::mem;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant, SystemTime};
 
@@ -11,13 +13,17 @@ use async_trait::async_trait;
 use derive_more::{From, Into};
 use futures::channel::mpsc::{self, Receiver, Sender};
 use futures::task::SpawnExt;
-use futures::{select_biased, AsyncRead, AsyncWrite, FutureExt, SinkExt, StreamExt, TryStreamExt};
+use futures::{
+    future, select_biased, AsyncRead, AsyncWrite, FutureExt, SinkExt, StreamExt, TryStreamExt,
+};
 use postage::sink::SendError;
 use postage::{broadcast, watch};
+use rand::rngs::StdRng;
+use rand::{Rng as _, SeedableRng as _};
 use tor_basic_utils::retry::RetryDelay;
 use tor_hscrypto::ope::AesOpeKey;
 use tor_hscrypto::RevisionCounter;
-use tor_keymgr::KeyMgr;
+use tor_keymgr::{KeyMgr, KeystoreId, KeystoreSelector};
 use tor_llcrypto::pk::ed25519;
 use tracing::{debug, error, info, trace, warn};
 
@@ -25,26 +31,36 @@ use tor_circmgr::hspool::{HsCircKind, HsCircPool};
 use tor_dirclient::request::HsDescUploadRequest;
 use tor_dirclient::{send_request, Error as DirClientError, RequestFailedError};
 use tor_error::define_asref_dyn_std_error;
-use tor_error::{error_report, internal, into_internal, warn_report};
+use tor_error::{debug_report, error_report, internal, into_internal, warn_report};
 use tor_hscrypto::pk::{
-    HsBlindId, HsBlindIdKey, HsBlindIdKeypair, HsDescSigningKeypair, HsIdKeypair,
+    HsBlindId, HsBlindIdKey, HsBlindIdKeypair, HsDescSigningKeypair, HsIdKey, HsIdKeypair,
 };
 use tor_hscrypto::time::TimePeriod;
-use tor_linkspec::{CircTarget, HasRelayIds, OwnedCircTarget, RelayIds};
+use tor_linkspec::{CircTarget, HasRelayIds, OwnedCircTarget, RelayId, RelayIds};
 use tor_netdir::{NetDir, NetDirProvider, Relay, Timeliness};
 use tor_proto::circuit::ClientCirc;
-use tor_rtcompat::{Runtime, SleepProviderExt};
+use tor_rtcompat::{Deadline, Runtime, SleepProviderExt};
 use void::Void;
 
-use crate::config::OnionServiceConfig;
+use crate::config::{keystore_selector, DescriptorUploadOrder, OnionServiceConfig};
 use crate::ipt_set::{IptsPublisherUploadView, IptsPublisherView};
-use crate::svc::netdir::wait_for_netdir;
+use crate::offline::OfflineCertStorageHandle;
+use crate::periodic::JitteredInterval;
+use crate::status::{
+    send_event, DescriptorUploadTime, DescriptorUploadTimes, HsDirUploadStatus,
+    HsDirUploadStatuses, State, StatusSender, TimePeriodChangeEvent, TimePeriodUploadStatus,
+    UploadStatus,
+};
+use crate::svc::netdir::{
+    same_netdir_provider, wait_for_netdir, NetDirProviderRx, NetdirProviderShutdown,
+};
 use crate::svc::publish::backoff::{BackoffSchedule, RetriableError, Runner};
 use crate::svc::publish::descriptor::{build_sign, DescriptorStatus, VersionedDescriptor};
+use crate::svc::publish::limit::DescriptorUploadLimit;
 use crate::svc::ShutdownStatus;
 use crate::{
-    BlindIdKeypairSpecifier, DescSigningKeypairSpecifier, FatalError, HsIdKeypairSpecifier,
-    HsNickname,
+    Anonymity, BlindIdKeypairSpecifier, DescSigningKeypairSpecifier, FatalError,
+    HsIdKeypairSpecifier, HsIdPublicKeySpecifier, HsNickname, OfflineKeys,
 };
 
 /// The upload rate-limiting threshold.
@@ -68,11 +84,46 @@ const UPLOAD_RATE_LIM_THRESHOLD: Duration = Duration::from_secs(60);
 // We should try to decouple this value from the TP parameters.
 const MAX_CONCURRENT_UPLOADS: usize = 16;
 
+/// The number of upload retries that can be made in a burst, across all the HsDirs
+/// of a service.
+///
+/// See [`RetryBudget`].
+//
+// TODO HSS: this value was arbitrarily chosen and may not be optimal.
+pub(super) const RETRY_BUDGET_BURST: u32 = 8;
+
+/// The time it takes for one retry to be added back to the retry budget.
+///
+/// In the long run, a service makes at most one upload retry per `RETRY_BUDGET_INTERVAL`,
+/// regardless of how many of its HsDirs are failing.
+//
+// TODO HSS: this value was arbitrarily chosen and may not be optimal.
+pub(super) const RETRY_BUDGET_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The fraction of the `descriptor_republish_interval` by which each periodic republication is
+/// jittered.
+///
+/// With the default interval of 90 minutes, we republish every 60 to 120 minutes, like C Tor.
+const REPUBLISH_JITTER_DIVISOR: u32 = 3;
+
 /// The maximum time allowed for uploading a descriptor to an HSDirs.
 //
 // TODO HSS: this value is probably not right.
 const UPLOAD_TIMEOUT: Duration = Duration::from_secs(5 * 60);
 
+/// A read-only callback invoked with each descriptor just before it is uploaded to an HsDir.
+///
+/// The callback receives the encoded, signed descriptor, and the identities of the HsDir we are
+/// about to upload it to. It is called once per HsDir per upload (retries of the same upload do
+/// not cause it to be called again).
+///
+/// This is intended for auditing and debugging. It cannot modify the descriptor.
+///
+/// It is called from the descriptor publisher's tasks, so it must not block.
+///
+/// See [`OnionService::set_descriptor_upload_observer`](crate::OnionService::set_descriptor_upload_observer).
+pub type DescriptorUploadObserver = Arc<dyn Fn(&str, &RelayIds) + Send + Sync>;
+
 /// A reactor for the HsDir [`Publisher`](super::Publisher).
 ///
 /// The entrypoint is [`Reactor::run`].
@@ -82,7 +133,11 @@ pub(super) struct Reactor<R: Runtime, M: Mockable> {
     imm: Arc<Immutable<R, M>>,
     /// A source for new network directories that we use to determine
     /// our HsDirs.
+    ///
+    /// This is the latest provider we received on `dir_provider_rx`.
     dir_provider: Arc<dyn NetDirProvider>,
+    /// A channel for receiving replacement `NetDirProvider`s.
+    dir_provider_rx: NetDirProviderRx,
     /// The mutable inner state,
     inner: Arc<Mutex<Inner>>,
     /// A channel for receiving IPT change notifications.
@@ -91,6 +146,11 @@ pub(super) struct Reactor<R: Runtime, M: Mockable> {
     config_rx: watch::Receiver<Arc<OnionServiceConfig>>,
     /// A channel for receiving the signal to shut down.
     shutdown_rx: broadcast::Receiver<Void>,
+    /// A channel for receiving requests to pause (`true`) or resume (`false`) publication.
+    ///
+    /// While publication is paused, our [`PublishStatus`] is
+    /// [`Paused`](PublishStatus::Paused).
+    pause_rx: watch::Receiver<bool>,
     /// A channel for receiving updates regarding our [`PublishStatus`].
     ///
     /// The main loop of the reactor watches for updates on this channel.
@@ -107,6 +167,29 @@ pub(super) struct Reactor<R: Runtime, M: Mockable> {
     /// When our [`PublishStatus`] changes to [`UploadScheduled`](PublishStatus::UploadScheduled),
     /// we can start publishing descriptors.
     publish_status_tx: watch::Sender<PublishStatus>,
+    /// When we started waiting for introduction points, if we are waiting for them.
+    ///
+    /// This is `Some` while our [`PublishStatus`] is
+    /// [`AwaitingIpts`](PublishStatus::AwaitingIpts).
+    awaiting_ipts_since: Option<Instant>,
+    /// How long we may wait for introduction points before reporting publication as stuck.
+    ///
+    /// This is the `ipt_wait_timeout` from the most recent config we have seen.
+    /// (We keep it here, since `Inner::config` is only updated on changes
+    /// that affect the descriptor.)
+    ipt_wait_timeout: Duration,
+    /// When we will have been waiting for introduction points for longer than
+    /// `ipt_wait_timeout`.
+    ///
+    /// When this is reached, we report publication as stuck, in our [`StatusSender`],
+    /// and clear it.
+    ///
+    /// (The timer that waits for it lives in [`ReactorTimers`], not here.)
+    ipt_wait_deadline: Option<Instant>,
+    /// How often we republish our descriptor, even if it hasn't changed.
+    ///
+    /// This is the `descriptor_republish_interval` from the most recent config we have seen.
+    republish_interval: Duration,
     /// A channel for the telling the upload reminder task (spawned in [`Reactor::run`]) when to
     /// remind us that we need to retry a failed or rate-limited upload.
     ///
@@ -136,6 +219,26 @@ pub(super) struct Reactor<R: Runtime, M: Mockable> {
     upload_task_complete_tx: Sender<TimePeriodUploadResult>,
 }
 
+/// The timers polled by [`Reactor::run_once`].
+///
+/// These live on the stack of [`Reactor::run`], rather than in the [`Reactor`]:
+/// their sleep futures aren't `Sync`, and the reactor must be,
+/// since we hold `&Reactor` across `.await` points.
+/// The reactor records the instants they should fire at,
+/// and `run_once` brings them up to date on each iteration.
+struct ReactorTimers<R: Runtime> {
+    /// Fires at the reactor's `ipt_wait_deadline`.
+    ipt_wait: Deadline<R>,
+    /// The interval with which `republish` was created.
+    ///
+    /// When this differs from the reactor's `republish_interval`, we replace `republish`.
+    republish_interval: Duration,
+    /// Ticks whenever it's time to republish our descriptor.
+    ///
+    /// This is `None` if periodic republication is disabled.
+    republish: Option<JitteredInterval<R, StdRng>>,
+}
+
 /// The immutable, shared state of the descriptor publisher reactor.
 #[derive(Clone)]
 struct Immutable<R: Runtime, M: Mockable> {
@@ -149,6 +252,32 @@ struct Immutable<R: Runtime, M: Mockable> {
     nickname: HsNickname,
     /// The key manager,
     keymgr: Arc<KeyMgr>,
+    /// Where the certificates of the descriptor signing keys are stored,
+    /// if the service is running in offline mode.
+    offline_certs: Arc<OfflineCertStorageHandle>,
+    /// A channel for receiving the callback to notify about each descriptor
+    /// we are about to upload, if any.
+    upload_observer: watch::Receiver<Option<DescriptorUploadObserver>>,
+    /// The anonymity level of the service.
+    ///
+    /// This determines what kind of circuits we build to the HsDirs.
+    /// (It can't be changed by reconfiguring the service.)
+    anonymity: Anonymity,
+    /// The keystore in which to store the service's keys, if not the default one.
+    ///
+    /// (It can't be changed by reconfiguring the service.)
+    keystore: Option<KeystoreId>,
+    /// A sender for updating the status of the onion service.
+    status_tx: StatusSender,
+    /// Where we record the time of our last successful upload for each time period.
+    upload_times: DescriptorUploadTimes,
+    /// Where we record the outcome of our most recent upload to each HsDir,
+    /// for each time period.
+    upload_statuses: HsDirUploadStatuses,
+    /// A limit on concurrent uploads shared with other services, if any.
+    upload_limit: Option<DescriptorUploadLimit>,
+    /// The retry budget shared by all our concurrent uploads.
+    retry_budget: Arc<Mutex<RetryBudget>>,
 }
 
 impl<R: Runtime, M: Mockable> Immutable<R, M> {
@@ -161,11 +290,18 @@ impl<R: Runtime, M: Mockable> Immutable<R, M> {
     ///
     /// Returns an error if the service is running in offline mode and the descriptor signing
     /// keypair of the specified `period` is not available.
-    //
-    // TODO HSS: we don't support "offline" mode (yet), so this always returns an AesOpeKey
-    // built from the blinded id key
-    fn create_ope_key(&self, period: TimePeriod) -> Result<AesOpeKey, FatalError> {
-        let ope_key = match read_blind_id_keypair(&self.keymgr, &self.nickname, period)? {
+    fn create_ope_key(
+        &self,
+        period: TimePeriod,
+        allow_key_generation: bool,
+    ) -> Result<AesOpeKey, FatalError> {
+        let ope_key = match read_blind_id_keypair(
+            &self.keymgr,
+            &self.nickname,
+            period,
+            keystore_selector(&self.keystore),
+            allow_key_generation,
+        )? {
             Some(key) => {
                 let key: ed25519::ExpandedKeypair = key.into();
                 key.to_secret_key_bytes()[0..32]
@@ -173,18 +309,17 @@ impl<R: Runtime, M: Mockable> Immutable<R, M> {
                     .expect("Wrong length on slice")
             }
             None => {
-                // TODO HSS: we don't support externally provisioned keys (yet), so this branch
-                // is unreachable (for now).
+                // We are running in offline mode, so the descriptor signing key
+                // must have been provisioned in advance.
                 let desc_sign_key_spec =
                     DescSigningKeypairSpecifier::new(self.nickname.clone(), period);
                 let key: ed25519::Keypair = self
                     .keymgr
                     .get::<HsDescSigningKeypair>(&desc_sign_key_spec)?
-                    // TODO HSS(#1129): internal! is not the right type for this error (we need an
-                    // error type for the case where a hidden service running in offline mode has
-                    // run out of its pre-previsioned keys). This is somewhat related to #1083
-                    // This will be addressed as part of #1129
-                    .ok_or_else(|| internal!("identity keys are offline, but descriptor signing key is unavailable?!"))?
+                    .ok_or_else(|| FatalError::OfflineKeysExhausted {
+                        nickname: self.nickname.clone(),
+                        period,
+                    })?
                     .into();
                 key.to_bytes()
             }
@@ -198,15 +333,22 @@ impl<R: Runtime, M: Mockable> Immutable<R, M> {
     ///
     /// Returns a revision counter generated according to the [encrypted time in period] scheme.
     ///
+    /// If the generated counter is not greater than `last_successful` (the revision counter of
+    /// the last descriptor we successfully uploaded for this `period`), for example because the
+    /// wallclock went backwards, it is bumped to `last_successful + 1`: HsDirs reject
+    /// descriptors whose revision counter is not higher than that of the one they already have.
+    ///
     /// [encrypted time in period]: https://spec.torproject.org/rend-spec/revision-counter-mgt.html#encrypted-time
     fn generate_revision_counter(
         &self,
         period: TimePeriod,
         now: SystemTime,
+        last_successful: Option<RevisionCounter>,
+        allow_key_generation: bool,
     ) -> Result<RevisionCounter, FatalError> {
         // TODO: in the future, we might want to compute ope_key once per time period (as oppposed
         // to each time we generate a new descriptor), for performance reasons.
-        let ope_key = self.create_ope_key(period)?;
+        let ope_key = self.create_ope_key(period, allow_key_generation)?;
         let offset = period
             .offset_within_period(now)
             .ok_or_else(|| match period.range() {
@@ -219,9 +361,22 @@ impl<R: Runtime, M: Mockable> Immutable<R, M> {
                 }
                 Err(e) => into_internal!("failed to get TimePeriod::range()")(e),
             })?;
-        let rev = ope_key.encrypt(offset);
-
-        Ok(RevisionCounter::from(rev))
+        let rev = RevisionCounter::from(ope_key.encrypt(offset));
+
+        match last_successful {
+            Some(last) if rev <= last => {
+                let bumped = u64::from(last)
+                    .checked_add(1)
+                    .ok_or_else(|| internal!("revision counter overflow (last={:?})", last))?;
+                warn!(
+                    nickname=%self.nickname, time_period=?period,
+                    "generated revision counter {:?} is not greater than that of our last published descriptor ({:?}): did the clock go backwards? Using {} instead",
+                    rev, last, bumped
+                );
+                Ok(RevisionCounter::from(bumped))
+            }
+            _ => Ok(rev),
+        }
     }
 }
 
@@ -248,6 +403,18 @@ pub(crate) trait Mockable: Clone + Send + Sync + Sized + 'static {
     ) -> Result<Arc<Self::ClientCirc>, tor_circmgr::Error>
     where
         T: CircTarget + Send + Sync;
+
+    /// Create a one-hop (non-anonymous) circuit of the specified `kind` to `target`.
+    ///
+    /// Only used by services running in [`Anonymity::DangerouslyNonAnonymous`] mode.
+    async fn launch_specific_one_hop<T>(
+        &self,
+        netdir: &NetDir,
+        kind: HsCircKind,
+        target: T,
+    ) -> Result<Arc<Self::ClientCirc>, tor_circmgr::Error>
+    where
+        T: CircTarget + Send + Sync;
 }
 
 /// Mockable client circuit
@@ -294,6 +461,18 @@ impl<R: Runtime> Mockable for Real<R> {
     {
         self.0.get_or_launch_specific(netdir, kind, target).await
     }
+
+    async fn launch_specific_one_hop<T>(
+        &self,
+        netdir: &NetDir,
+        kind: HsCircKind,
+        target: T,
+    ) -> Result<Arc<ClientCirc>, tor_circmgr::Error>
+    where
+        T: CircTarget + Send + Sync,
+    {
+        self.0.launch_specific_one_hop(netdir, kind, target).await
+    }
 }
 
 /// The mutable state of a [`Reactor`].
@@ -326,8 +505,15 @@ struct Inner {
     /// used for retrying failed uploads (these are handled internally by
     /// [`Reactor::upload_descriptor_with_retries`]).
     last_uploaded: Option<Instant>,
+    /// A channel for notifying our subscribers whenever the set of relevant time periods
+    /// changes because of a new consensus.
+    time_period_change_tx: broadcast::Sender<TimePeriodChangeEvent>,
 }
 
+/// The HsDirs selected for a time period, and the set of those that are only there
+/// because they are in `extra_hsdirs`.
+type SelectedHsDirs = (Vec<(RelayIds, DescriptorStatus)>, HashSet<RelayIds>);
+
 /// The part of the reactor state that changes with every time period.
 struct TimePeriodContext {
     /// The time period.
@@ -341,8 +527,33 @@ struct TimePeriodContext {
     // store `Relay<'_>`s in the reactor, we'd need a way of atomically swapping out both the
     // `NetDir` and the cached relays, and to convince Rust what we're doing is sound)
     hs_dirs: Vec<(RelayIds, DescriptorStatus)>,
+    /// The HsDirs in `hs_dirs` that are only there because they are in `extra_hsdirs`.
+    ///
+    /// Clients don't look for our descriptor on these,
+    /// so they don't count towards our quorum.
+    extra_hs_dirs: HashSet<RelayIds>,
+    /// The number of HsDirs we are supposed to upload the descriptor to in this time period.
+    ///
+    /// If the consensus is too sparse, `hs_dirs` may contain fewer HsDirs than this.
+    expected_hs_dir_count: usize,
+    /// The number of HsDirs from the ring our descriptor must be up-to-date on
+    /// for us to count it as published.
+    ///
+    /// This is `expected_hs_dir_count`, unless `hsdir_allowlist` leaves us fewer HsDirs,
+    /// in which case we need all of the allowlisted ones (and at least one).
+    quorum: usize,
     /// The revision counter of the last successful upload, if any.
     last_successful: Option<RevisionCounter>,
+    /// The time of the last successful upload, if any.
+    last_successful_upload: Option<SystemTime>,
+    /// The outcome of the most recent upload to each of the HsDirs in `hs_dirs`.
+    ///
+    /// HsDirs we haven't tried to upload to yet are omitted.
+    upload_statuses: Vec<HsDirUploadStatus>,
+    /// Whether we have warned that our descriptor is not up-to-date on enough HsDirs.
+    ///
+    /// Cleared once it is.
+    warned_no_quorum: bool,
 }
 
 impl TimePeriodContext {
@@ -350,31 +561,117 @@ impl TimePeriodContext {
     ///
     /// Any of the specified `old_hsdirs` also present in the new list of HsDirs
     /// (returned by `NetDir::hs_dirs_upload`) will have their `DescriptorStatus` preserved.
+    ///
+    /// If `hsdir_allowlist` is present, only the HsDirs with one of the listed identities
+    /// are used. The `extra_hsdirs` are used in addition to the HsDirs from the ring.
     fn new<'r>(
         period: TimePeriod,
         blind_id: HsBlindId,
         netdir: &Arc<NetDir>,
         old_hsdirs: impl Iterator<Item = &'r (RelayIds, DescriptorStatus)>,
+        hsdir_allowlist: Option<&[RelayId]>,
+        extra_hsdirs: &[RelayId],
     ) -> Result<Self, FatalError> {
+        let (hs_dirs, extra_hs_dirs) = Self::compute_hsdirs(
+            period,
+            blind_id,
+            netdir,
+            old_hsdirs,
+            hsdir_allowlist,
+            extra_hsdirs,
+        )?;
+        let expected_hs_dir_count = expected_hs_dir_count(netdir);
+        let quorum = match hsdir_allowlist {
+            Some(_) => {
+                let n_ring_hs_dirs = hs_dirs.len() - extra_hs_dirs.len();
+                std::cmp::max(std::cmp::min(expected_hs_dir_count, n_ring_hs_dirs), 1)
+            }
+            None => expected_hs_dir_count,
+        };
         Ok(Self {
             period,
             blind_id,
-            hs_dirs: Self::compute_hsdirs(period, blind_id, netdir, old_hsdirs)?,
+            hs_dirs,
+            extra_hs_dirs,
+            expected_hs_dir_count,
+            quorum,
             last_successful: None,
+            last_successful_upload: None,
+            upload_statuses: vec![],
+            warned_no_quorum: false,
         })
     }
 
     /// Recompute the HsDirs for this time period.
+    ///
+    /// The statuses of the `old_hsdirs` are indexed by relay identity up front,
+    /// so this takes time linear in the size of the ring and of `old_hsdirs`,
+    /// rather than doing a linear search of `old_hsdirs` for each new HsDir.
+    ///
+    /// Any `extra_hsdirs` not in `netdir` are skipped.
+    ///
+    /// Returns the HsDirs, and the set of those that are only there
+    /// because they are in `extra_hsdirs`: see [`select_hsdirs`](Self::select_hsdirs).
     fn compute_hsdirs<'r>(
         period: TimePeriod,
         blind_id: HsBlindId,
         netdir: &Arc<NetDir>,
-        mut old_hsdirs: impl Iterator<Item = &'r (RelayIds, DescriptorStatus)>,
-    ) -> Result<Vec<(RelayIds, DescriptorStatus)>, FatalError> {
+        old_hsdirs: impl Iterator<Item = &'r (RelayIds, DescriptorStatus)>,
+        hsdir_allowlist: Option<&[RelayId]>,
+        extra_hsdirs: &[RelayId],
+    ) -> Result<SelectedHsDirs, FatalError> {
         let hs_dirs = netdir.hs_dirs_upload([(blind_id, period)].into_iter())?;
+        let extra_hsdirs = extra_hsdirs.iter().filter_map(|id| {
+            let relay = netdir.by_id(id);
+            if relay.is_none() {
+                warn!(hsdir_id=%id, "extra HsDir not found in consensus; skipping it");
+            }
+            relay
+        });
+
+        Ok(Self::select_hsdirs(
+            hs_dirs.map(|(_, hs_dir)| hs_dir),
+            extra_hsdirs,
+            old_hsdirs,
+            hsdir_allowlist,
+        ))
+    }
+
+    /// Pick out the HsDirs we'll upload to from `hs_dirs` and `extra_hsdirs`,
+    /// and work out their statuses.
+    ///
+    /// HsDirs from `hs_dirs` that aren't in `hsdir_allowlist` (if there is one) are skipped.
+    /// So are any HsDirs that have no identities:
+    /// we would not be able to find them in the netdir to upload to them.
+    /// The `extra_hsdirs` that are also in `hs_dirs` are only listed once.
+    ///
+    /// Returns the selected HsDirs, and the set of those that are only there
+    /// because they are in `extra_hsdirs`.
+    fn select_hsdirs<'r, T: HasRelayIds>(
+        hs_dirs: impl Iterator<Item = T>,
+        extra_hsdirs: impl Iterator<Item = T>,
+        old_hsdirs: impl Iterator<Item = &'r (RelayIds, DescriptorStatus)>,
+        hsdir_allowlist: Option<&[RelayId]>,
+    ) -> SelectedHsDirs {
+        let old_hsdirs = old_hsdirs
+            .map(|(id, status)| (id, *status))
+            .collect::<HashMap<&RelayIds, DescriptorStatus>>();
+        let mut seen = HashSet::new();
+        let mut extras = HashSet::new();
+
+        let selected = hs_dirs
+            .filter(|hs_dir| match hsdir_allowlist {
+                Some(allowlist) => allowlist.iter().any(|id| hs_dir.has_identity(id.as_ref())),
+                None => true,
+            })
+            .map(|hs_dir| (hs_dir, false))
+            .chain(extra_hsdirs.map(|hs_dir| (hs_dir, true)))
+            .filter_map(|(hs_dir, is_extra)| {
+                if !hs_dir.has_any_identity() {
+                    warn!("Skipping HsDir with no identities");
+                    return None;
+                }
 
-        Ok(hs_dirs
-            .map(|(_, hs_dir)| {
                 let mut builder = RelayIds::builder();
                 if let Some(ed_id) = hs_dir.ed_identity() {
                     builder.ed_identity(*ed_id);
@@ -384,18 +681,33 @@ impl TimePeriodContext {
                     builder.rsa_identity(*rsa_id);
                 }
 
-                let relay_id = builder.build().unwrap_or_else(|_| RelayIds::empty());
+                let relay_id = match builder.build() {
+                    Ok(relay_id) => relay_id,
+                    Err(e) => {
+                        warn!("Skipping HsDir with no usable identities: {e}");
+                        return None;
+                    }
+                };
+
+                if !seen.insert(relay_id.clone()) {
+                    return None;
+                }
+                if is_extra {
+                    extras.insert(relay_id.clone());
+                }
 
                 // Have we uploaded the descriptor to thiw relay before? If so, we don't need to
                 // reupload it unless it was already dirty and due for a reupload.
-                let status = match old_hsdirs.find(|(id, _)| *id == relay_id) {
-                    Some((_, status)) => *status,
-                    None => DescriptorStatus::Dirty,
-                };
+                let status = old_hsdirs
+                    .get(&relay_id)
+                    .copied()
+                    .unwrap_or(DescriptorStatus::Dirty);
 
-                (relay_id, status)
+                Some((relay_id, status))
             })
-            .collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        (selected, extras)
     }
 
     /// Mark the descriptor dirty for all HSDirs of this time period.
@@ -404,39 +716,40 @@ impl TimePeriodContext {
             .iter_mut()
             .for_each(|(_relay_id, status)| *status = DescriptorStatus::Dirty);
     }
+
+    /// Return the number of HSDirs from the ring that have an up-to-date copy of our descriptor.
+    ///
+    /// The `extra_hs_dirs` are not counted.
+    fn n_clean_hs_dirs(&self) -> usize {
+        self.ring_hs_dirs()
+            .filter(|(_relay_id, status)| *status == DescriptorStatus::Clean)
+            .count()
+    }
+
+    /// Return the HSDirs from the ring, leaving out the `extra_hs_dirs`.
+    fn ring_hs_dirs(&self) -> impl Iterator<Item = &(RelayIds, DescriptorStatus)> {
+        self.hs_dirs
+            .iter()
+            .filter(|(relay_id, _status)| !self.extra_hs_dirs.contains(relay_id))
+    }
+
+    /// Whether our descriptor is up-to-date on as many HSDirs as the spec expects
+    /// (or, if we have an `hsdir_allowlist`, on all the allowlisted ones).
+    fn reached_quorum(&self) -> bool {
+        self.n_clean_hs_dirs() >= self.quorum
+    }
 }
 
-/// Authorized client configuration error.
-#[derive(Debug, Clone, thiserror::Error)]
-#[non_exhaustive]
-pub(crate) enum AuthorizedClientConfigError {
-    /// A key is malformed if it doesn't start with the "curve25519" prefix,
-    /// or if its decoded content is not exactly 32 bytes long.
-    #[error("Malformed authorized client key")]
-    MalformedKey,
-
-    /// Error while decoding an authorized client's key.
-    #[error("Failed base64-decode an authorized client's key")]
-    Base64Decode(#[from] base64ct::Error),
-
-    /// Error while accessing the authorized_client key dir.
-    #[error("Failed to {action} file {path}")]
-    KeyDir {
-        /// What we were doing when we encountered the error.
-        action: &'static str,
-        /// The file that we were trying to access.
-        path: std::path::PathBuf,
-        /// The underlying I/O error.
-        #[source]
-        error: Arc<std::io::Error>,
-    },
-
-    /// Error while accessing the authorized_client key dir.
-    #[error("expected regular file, found directory: {path}")]
-    MalformedFile {
-        /// The file that we were trying to access.
-        path: std::path::PathBuf,
-    },
+/// Return the number of HSDirs to which we're supposed to upload a descriptor,
+/// for each time period.
+///
+/// This is `hsdir_n_replicas * hsdir_spread_store`, as specified in
+/// <https://spec.torproject.org/rend-spec/deriving-keys.html#HASHRING>.
+fn expected_hs_dir_count(netdir: &NetDir) -> usize {
+    let params = netdir.params();
+    let n_replicas = usize::try_from(params.hsdir_n_replicas.get()).unwrap_or(0);
+    let spread_store = usize::try_from(params.hsdir_spread_store.get()).unwrap_or(0);
+    n_replicas * spread_store
 }
 
 /// An error that occurs while trying to upload a descriptor.
@@ -467,13 +780,21 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
     pub(super) fn new(
         runtime: R,
         nickname: HsNickname,
-        dir_provider: Arc<dyn NetDirProvider>,
+        dir_provider_rx: NetDirProviderRx,
         mockable: M,
         config: Arc<OnionServiceConfig>,
         ipt_watcher: IptsPublisherView,
         config_rx: watch::Receiver<Arc<OnionServiceConfig>>,
         shutdown_rx: broadcast::Receiver<Void>,
+        pause_rx: watch::Receiver<bool>,
         keymgr: Arc<KeyMgr>,
+        offline_certs: Arc<OfflineCertStorageHandle>,
+        status_tx: StatusSender,
+        upload_times: DescriptorUploadTimes,
+        upload_statuses: HsDirUploadStatuses,
+        upload_observer: watch::Receiver<Option<DescriptorUploadObserver>>,
+        time_period_change_tx: broadcast::Sender<TimePeriodChangeEvent>,
+        upload_limit: Option<DescriptorUploadLimit>,
     ) -> Self {
         /// The maximum size of the upload completion notifier channel.
         ///
@@ -486,12 +807,29 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
             mpsc::channel(UPLOAD_CHAN_BUF_SIZE);
 
         let (publish_status_tx, publish_status_rx) = watch::channel();
+        let dir_provider = Arc::clone(&dir_provider_rx.borrow());
+        let ipt_wait_timeout = config.ipt_wait_timeout;
+        let republish_interval = config.descriptor_republish_interval;
+        let now = runtime.now();
 
         let imm = Immutable {
             runtime,
             mockable,
             nickname,
             keymgr,
+            offline_certs,
+            upload_observer,
+            anonymity: config.anonymity,
+            keystore: config.keystore.clone(),
+            status_tx,
+            upload_times,
+            upload_statuses,
+            upload_limit,
+            retry_budget: Arc::new(Mutex::new(RetryBudget::new(
+                now,
+                RETRY_BUDGET_BURST,
+                RETRY_BUDGET_INTERVAL,
+            ))),
         };
 
         let inner = Inner {
@@ -499,17 +837,24 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
             config,
             netdir: None,
             last_uploaded: None,
+            time_period_change_tx,
         };
 
         Self {
             imm: Arc::new(imm),
             inner: Arc::new(Mutex::new(inner)),
             dir_provider,
+            dir_provider_rx,
             ipt_watcher,
             config_rx,
             shutdown_rx,
+            pause_rx,
             publish_status_rx,
             publish_status_tx,
+            awaiting_ipts_since: None,
+            ipt_wait_timeout,
+            ipt_wait_deadline: None,
+            republish_interval,
             reattempt_upload_tx: None,
             upload_task_complete_rx,
             upload_task_complete_tx,
@@ -526,15 +871,21 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
         debug!(nickname=%self.imm.nickname, "starting descriptor publisher reactor");
 
         {
-            let netdir = wait_for_netdir(self.dir_provider.as_ref(), Timeliness::Timely).await?;
-            let time_periods = self.compute_time_periods(&netdir, &[])?;
+            let netdir = self.wait_for_netdir_as_configured().await?;
 
             let mut inner = self.inner.lock().expect("poisoned lock");
+            let time_periods = self.compute_time_periods(&netdir, &inner.config, &[])?;
 
             inner.netdir = Some(netdir);
             inner.time_periods = time_periods;
         }
 
+        // We start out without any IPTs.
+        if self.status() == PublishStatus::AwaitingIpts {
+            self.awaiting_ipts_since = Some(self.imm.runtime.now());
+            self.update_ipt_wait_deadline();
+        }
+
         // There will be at most one pending upload.
         let (reattempt_upload_tx, mut reattempt_upload_rx) = watch::channel();
         let (mut schedule_upload_tx, mut schedule_upload_rx) = watch::channel();
@@ -572,8 +923,14 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
             debug!(nickname=%nickname, "reupload task channel closed!");
         });
 
+        let mut timers = ReactorTimers {
+            ipt_wait: Deadline::new(self.imm.runtime.clone()),
+            republish_interval: self.republish_interval,
+            republish: Self::new_republish_timer(&self.imm, self.republish_interval),
+        };
+
         loop {
-            match self.run_once(&mut schedule_upload_rx).await {
+            match self.run_once(&mut schedule_upload_rx, &mut timers).await {
                 Ok(ShutdownStatus::Continue) => continue,
                 Ok(ShutdownStatus::Terminate) => return Ok(()),
                 Err(e) => {
@@ -594,9 +951,20 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
     async fn run_once(
         &mut self,
         schedule_upload_rx: &mut watch::Receiver<()>,
+        timers: &mut ReactorTimers<R>,
     ) -> Result<ShutdownStatus, FatalError> {
         let mut netdir_events = self.dir_provider.events();
 
+        // Catch up with any changes to our deadlines since the last iteration.
+        match self.ipt_wait_deadline {
+            Some(deadline) => timers.ipt_wait.reset(deadline),
+            None => timers.ipt_wait.clear(),
+        }
+        if timers.republish_interval != self.republish_interval {
+            timers.republish_interval = self.republish_interval;
+            timers.republish = Self::new_republish_timer(&self.imm, self.republish_interval);
+        }
+
         select_biased! {
             // TODO HSS: Stop waiting for the shutdown signal
             // (instead, let the sender of the ipt_watcher being dropped
@@ -621,7 +989,7 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
             }
             netidr_event = netdir_events.next().fuse() => {
                 // The consensus changed. Grab a new NetDir.
-                let netdir = match self.dir_provider.netdir(Timeliness::Timely) {
+                let netdir = match self.netdir_as_configured() {
                     Ok(y) => y,
                     Err(e) => {
                         error_report!(e, "HS service {}: netdir unavailable. Retrying...", self.imm.nickname);
@@ -635,8 +1003,7 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                         //
                         // Probably this should be fixed by moving the logging
                         // out of the reactor, where it won't be blocked.
-                        wait_for_netdir(self.dir_provider.as_ref(), Timeliness::Timely)
-                            .await?
+                        self.wait_for_netdir_as_configured().await?
                     }
                 };
                 self.handle_consensus_change(netdir).await?;
@@ -651,13 +1018,27 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
 
                 self.handle_svc_config_change(config).await?;
             },
+            dir_provider = self.dir_provider_rx.next().fuse() => {
+                let Some(dir_provider) = dir_provider else {
+                    return Ok(ShutdownStatus::Terminate);
+                };
+
+                self.handle_dir_provider_change(dir_provider).await?;
+            },
+            paused = self.pause_rx.next().fuse() => {
+                let Some(paused) = paused else {
+                    return Ok(ShutdownStatus::Terminate);
+                };
+
+                self.handle_pause_change(paused).await?;
+            },
             res = schedule_upload_rx.next().fuse() => {
                 let Some(()) = res else {
                     return Ok(ShutdownStatus::Terminate);
                 };
 
-                // Unless we're waiting for IPTs, reattempt the rate-limited upload in the next
-                // iteration.
+                // Unless we're waiting for IPTs (or paused), reattempt the rate-limited upload in
+                // the next iteration.
                 self.update_publish_status_unless_waiting(PublishStatus::UploadScheduled).await?;
             },
             should_upload = self.publish_status_rx.next().fuse() => {
@@ -670,6 +1051,12 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                     self.update_publish_status_unless_waiting(PublishStatus::Idle).await?;
                     self.upload_all().await?;
                 }
+            },
+            () = (&mut timers.ipt_wait).fuse() => {
+                self.handle_ipt_wait_timeout();
+            },
+            () = Self::next_republish_tick(&mut timers.republish).fuse() => {
+                self.handle_republish_tick().await?;
             }
         }
 
@@ -706,10 +1093,24 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
 
             let Some((relay, status)) = relay else {
                 // This HSDir went away, so the result doesn't matter.
-                return;
+                // But the other results in this batch still do,
+                // so we mustn't `return` here.
+                continue;
             };
 
+            // Remember the outcome of the most recent upload to this HSDir.
+            match period
+                .upload_statuses
+                .iter_mut()
+                .find(|prev| prev.relay_ids == upload_res.relay_ids)
+            {
+                Some(prev) => *prev = upload_res.clone(),
+                None => period.upload_statuses.push(upload_res.clone()),
+            }
+
             if upload_res.upload_res == UploadStatus::Success {
+                period.last_successful_upload = Some(self.imm.runtime.wallclock());
+
                 let update_last_successful = match period.last_successful {
                     None => true,
                     Some(counter) => counter <= upload_res.revision_counter,
@@ -734,6 +1135,57 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
 
             // TODO HSS: maybe the failed uploads should be rescheduled at some point.
         }
+
+        if !period.reached_quorum() {
+            // We didn't manage to publish our descriptor to enough HsDirs, so we don't count it
+            // as successfully published.
+            //
+            // We only warn when this first happens, not after every batch of uploads.
+            if period.warned_no_quorum {
+                debug!(
+                    nickname=%self.imm.nickname, time_period=?period.period,
+                    "descriptor is still only up-to-date on {}/{} HSDirs",
+                    period.n_clean_hs_dirs(), period.quorum
+                );
+            } else {
+                warn!(
+                    nickname=%self.imm.nickname, time_period=?period.period,
+                    "descriptor is only up-to-date on {}/{} HSDirs",
+                    period.n_clean_hs_dirs(), period.quorum
+                );
+                period.warned_no_quorum = true;
+            }
+            self.imm.status_tx.maybe_update_publisher(State::Recovering);
+        } else {
+            period.warned_no_quorum = false;
+
+            // Clients can find our descriptor for this time period.
+            self.imm.status_tx.note_reachable();
+
+            if inner
+                .time_periods
+                .iter()
+                .all(TimePeriodContext::reached_quorum)
+            {
+                self.imm.status_tx.maybe_update_publisher(State::Running);
+            }
+        }
+
+        *self.imm.upload_times.lock().expect("poisoned lock") = inner
+            .time_periods
+            .iter()
+            .filter_map(|ctx| {
+                ctx.last_successful_upload
+                    .map(|uploaded_at| DescriptorUploadTime::new(ctx.period, uploaded_at))
+            })
+            .collect();
+
+        // Hand out copies, so that nobody needs to lock `inner` to read them.
+        *self.imm.upload_statuses.lock().expect("poisoned lock") = inner
+            .time_periods
+            .iter()
+            .map(|ctx| TimePeriodUploadStatus::new(ctx.period, ctx.upload_statuses.clone()))
+            .collect();
     }
 
     /// Maybe update our list of HsDirs.
@@ -749,6 +1201,79 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
         Ok(())
     }
 
+    /// Start using `dir_provider` as our source of network directories, if it's a new one.
+    ///
+    /// If the new provider already has a netdir, we recompute our HsDirs from it straight away.
+    /// Otherwise, we keep using our current netdir until the new provider tells us it has one.
+    async fn handle_dir_provider_change(
+        &mut self,
+        dir_provider: Arc<dyn NetDirProvider>,
+    ) -> Result<(), FatalError> {
+        if same_netdir_provider(&self.dir_provider, &dir_provider) {
+            return Ok(());
+        }
+
+        debug!(nickname=%self.imm.nickname, "switching to a new netdir provider");
+        self.dir_provider = dir_provider;
+
+        match self.netdir_as_configured() {
+            Ok(netdir) => self.handle_consensus_change(netdir).await,
+            Err(e) => {
+                debug_report!(
+                    e,
+                    "HS service {}: the new netdir provider has no netdir yet",
+                    self.imm.nickname
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Return how timely the netdirs we compute our HsDirs from must be.
+    ///
+    /// This depends on the `publish_with_stale_netdir` config option.
+    fn netdir_timeliness(&self) -> Timeliness {
+        let inner = self.inner.lock().expect("poisoned lock");
+        if inner.config.publish_with_stale_netdir {
+            Timeliness::Unchecked
+        } else {
+            Timeliness::Timely
+        }
+    }
+
+    /// Get a netdir from our provider, if it has one that is timely enough for our config.
+    ///
+    /// Warns if the netdir isn't actually timely.
+    fn netdir_as_configured(&self) -> Result<Arc<NetDir>, tor_netdir::Error> {
+        let netdir = self.dir_provider.netdir(self.netdir_timeliness())?;
+        self.warn_if_netdir_untimely();
+        Ok(netdir)
+    }
+
+    /// Like [`Reactor::netdir_as_configured`], but waits until the provider has a netdir.
+    async fn wait_for_netdir_as_configured(&self) -> Result<Arc<NetDir>, NetdirProviderShutdown> {
+        let netdir = wait_for_netdir(self.dir_provider.as_ref(), self.netdir_timeliness()).await?;
+        self.warn_if_netdir_untimely();
+        Ok(netdir)
+    }
+
+    /// Warn if we are about to use a netdir that isn't timely.
+    ///
+    /// This can only happen if `publish_with_stale_netdir` is set.
+    fn warn_if_netdir_untimely(&self) {
+        if self.netdir_timeliness() == Timeliness::Timely {
+            return;
+        }
+
+        if let Err(e) = self.dir_provider.netdir(Timeliness::Timely) {
+            warn_report!(
+                e,
+                "HS service {}: no timely netdir available; using a stale one to select our HsDirs",
+                self.imm.nickname
+            );
+        }
+    }
+
     /// Recompute the HsDirs for all relevant time periods.
     fn recompute_hs_dirs(&self) -> Result<(), FatalError> {
         let mut inner = self.inner.lock().expect("poisoned lock");
@@ -762,8 +1287,39 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
         );
 
         // Update our list of relevant time periods.
-        let new_time_periods = self.compute_time_periods(&netdir, &inner.time_periods)?;
-        inner.time_periods = new_time_periods;
+        let new_time_periods =
+            self.compute_time_periods(&netdir, &inner.config, &inner.time_periods)?;
+        let old_time_periods = mem::replace(&mut inner.time_periods, new_time_periods);
+
+        // Tell our subscribers if the set of relevant time periods has changed.
+        let is_in = |period: TimePeriod, ctxs: &[TimePeriodContext]| {
+            ctxs.iter().any(|ctx| ctx.period == period)
+        };
+        let added = inner
+            .time_periods
+            .iter()
+            .map(|ctx| ctx.period)
+            .filter(|period| !is_in(*period, &old_time_periods))
+            .collect::<Vec<_>>();
+        let removed = old_time_periods
+            .iter()
+            .map(|ctx| ctx.period)
+            .filter(|period| !is_in(*period, &inner.time_periods))
+            .collect::<Vec<_>>();
+
+        if !added.is_empty() || !removed.is_empty() {
+            debug!(
+                nickname=%self.imm.nickname, ?added, ?removed,
+                "the set of relevant time periods has changed"
+            );
+            let event = TimePeriodChangeEvent::new(added, removed);
+            send_event(
+                &mut inner.time_period_change_tx,
+                event,
+                &self.imm.nickname,
+                "time period change",
+            );
+        }
 
         Ok(())
     }
@@ -772,41 +1328,46 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
     ///
     /// The specified `time_periods` are used to preserve the `DescriptorStatus` of the
     /// HsDirs where possible.
+    ///
+    /// Only the HsDirs permitted by the `hsdir_allowlist` of `config` (if any) are used,
+    /// along with the `extra_hsdirs` of `config`.
+    ///
+    /// The blinded identity keys for the time periods are generated if needed,
+    /// unless `config` disallows key generation.
     fn compute_time_periods(
         &self,
         netdir: &Arc<NetDir>,
+        config: &OnionServiceConfig,
         time_periods: &[TimePeriodContext],
     ) -> Result<Vec<TimePeriodContext>, FatalError> {
+        let hsdir_allowlist = config.hsdir_allowlist.as_deref();
+        let extra_hsdirs = &config.extra_hsdirs;
+
         netdir
             .hs_all_time_periods()
             .iter()
             .map(|period| {
-                let svc_key_spec = HsIdKeypairSpecifier::new(self.imm.nickname.clone());
-                let hsid_kp = self
-                    .imm
-                    .keymgr
-                    .get::<HsIdKeypair>(&svc_key_spec)?
-                    .ok_or_else(|| FatalError::MissingHsIdKeypair(self.imm.nickname.clone()))?;
-                let svc_key_spec = BlindIdKeypairSpecifier::new(self.imm.nickname.clone(), *period);
-
-                // TODO HSS: make this configurable
-                let keystore_selector = Default::default();
-                let blind_id_kp = self
-                    .imm
-                    .keymgr
-                    .get_or_generate_with_derived::<HsBlindIdKeypair>(
-                        &svc_key_spec,
-                        keystore_selector,
-                        || {
-                            let (_hs_blind_id_key, hs_blind_id_kp, _subcredential) = hsid_kp
-                                .compute_blinded_key(*period)
-                                .map_err(|_| internal!("failed to compute blinded key"))?;
-
-                            Ok(hs_blind_id_kp)
-                        },
-                    )?;
-
-                let blind_id: HsBlindIdKey = (&blind_id_kp).into();
+                let blind_id: HsBlindIdKey = match read_blind_id_keypair(
+                    &self.imm.keymgr,
+                    &self.imm.nickname,
+                    *period,
+                    keystore_selector(&self.imm.keystore),
+                    config.allow_key_generation,
+                )? {
+                    Some(blind_id_kp) => (&blind_id_kp).into(),
+                    None => {
+                        // We are running in offline mode,
+                        // so the blinded public key must have been provisioned.
+                        OfflineKeys::read(
+                            &self.imm.keymgr,
+                            &self.imm.offline_certs,
+                            &self.imm.nickname,
+                            *period,
+                        )?
+                        .blind_id_key()
+                        .clone()
+                    }
+                };
 
                 // If our previous `TimePeriodContext`s also had an entry for `period`, we need to
                 // preserve the `DescriptorStatus` of its HsDirs. This helps prevent unnecessarily
@@ -817,13 +1378,67 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                 //   * are part of a new time period (which we have never published the descriptor
                 //   for), or
                 //   * have just been added to the ring of a time period we already knew about
-                if let Some(ctx) = time_periods.iter().find(|ctx| ctx.period == *period) {
-                    TimePeriodContext::new(*period, blind_id.into(), netdir, ctx.hs_dirs.iter())
+                let old_ctx = time_periods.iter().find(|ctx| ctx.period == *period);
+                let ctx = if let Some(ctx) = old_ctx {
+                    let mut new_ctx = TimePeriodContext::new(
+                        *period,
+                        blind_id.into(),
+                        netdir,
+                        ctx.hs_dirs.iter(),
+                        hsdir_allowlist,
+                        extra_hsdirs,
+                    )?;
+                    new_ctx.last_successful = ctx.last_successful;
+                    new_ctx.last_successful_upload = ctx.last_successful_upload;
+                    new_ctx.warned_no_quorum = ctx.warned_no_quorum;
+                    new_ctx.upload_statuses = ctx
+                        .upload_statuses
+                        .iter()
+                        .filter(|status| {
+                            new_ctx
+                                .hs_dirs
+                                .iter()
+                                .any(|(relay_ids, _)| relay_ids == &status.relay_ids)
+                        })
+                        .cloned()
+                        .collect();
+                    new_ctx
                 } else {
                     // Passing an empty iterator here means all HsDirs in this TimePeriodContext
                     // will be marked as dirty, meaning we will need to upload our descriptor to them.
-                    TimePeriodContext::new(*period, blind_id.into(), netdir, iter::empty())
+                    TimePeriodContext::new(
+                        *period,
+                        blind_id.into(),
+                        netdir,
+                        iter::empty(),
+                        hsdir_allowlist,
+                        extra_hsdirs,
+                    )?
+                };
+
+                // We recompute the HsDirs whenever the netdir or the config changes,
+                // so only warn if the shortfall is new, or different.
+                // The extra HsDirs don't make up for a shortfall, since clients don't use them.
+                let n_hs_dirs = |ctx: &TimePeriodContext| (ctx.ring_hs_dirs().count(), ctx.expected_hs_dir_count);
+                let warned_already = old_ctx.map_or(false, |old| n_hs_dirs(old) == n_hs_dirs(&ctx));
+                let (n_ring_hs_dirs, expected_hs_dir_count) = n_hs_dirs(&ctx);
+                if n_ring_hs_dirs < expected_hs_dir_count && !warned_already {
+                    if hsdir_allowlist.is_some() {
+                        warn!(
+                            nickname=%self.imm.nickname, time_period=?period,
+                            "too few HSDirs in hsdir_allowlist: can only publish descriptor to {}/{} HSDirs",
+                            n_ring_hs_dirs, expected_hs_dir_count
+                        );
+                    } else {
+                        warn!(
+                            nickname=%self.imm.nickname, time_period=?period,
+                            "too few HSDirs in the consensus: can only publish descriptor to {}/{} HSDirs",
+                            n_ring_hs_dirs, expected_hs_dir_count
+                        );
+                    }
                 }
+
+                Ok(ctx)
             })
             .collect::<Result<Vec<TimePeriodContext>, FatalError>>()
     }
@@ -843,8 +1458,8 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
         let mut inner = self.inner.lock().expect("poisoned lock");
         let old_config = &mut inner.config;
 
-        // The fields we're interested in haven't changed, so there's no need to update
-        // `inner.config`.
+        // None of the fields the publisher reads from `inner.config` have changed, so
+        // there's no need to update it.
         //
         // TODO: maybe `Inner` should only contain the fields we're interested in instead of
         // the entire config.
@@ -852,16 +1467,17 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
         // Alternatively, a less error-prone solution would be to introduce a separate
         // `DescriptorConfigView` as described in
         // https://gitlab.torproject.org/tpo/core/arti/-/merge_requests/1603#note_2944902
-
-        // TODO HSS: Temporarily disabled while we figure out how we want the client auth config to
-        // work; see #1028
-        /*
         if old_config.anonymity == new_config.anonymity
             && old_config.encrypt_descriptor == new_config.encrypt_descriptor
+            && old_config.hsdir_allowlist == new_config.hsdir_allowlist
+            && old_config.extra_hsdirs == new_config.extra_hsdirs
+            && old_config.publish_with_stale_netdir == new_config.publish_with_stale_netdir
+            && old_config.descriptor_upload_order == new_config.descriptor_upload_order
+            && old_config.allow_key_generation == new_config.allow_key_generation
+            && old_config.keystore == new_config.keystore
         {
             return false;
         }
-        */
 
         let _old: Arc<OnionServiceConfig> = std::mem::replace(old_config, new_config);
 
@@ -892,25 +1508,69 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                 debug!(nickname=%self.imm.nickname, "the introduction points have changed");
 
                 self.mark_all_dirty();
-                self.update_publish_status(should_upload).await
+                self.update_publish_status_unless_paused(should_upload)
+                    .await
             }
             Some(Err(e)) => Err(e),
             None => {
                 debug!(nickname=%self.imm.nickname, "no IPTs available, ceasing uploads");
-                self.update_publish_status(PublishStatus::AwaitingIpts)
+                self.update_publish_status_unless_paused(PublishStatus::AwaitingIpts)
                     .await
             }
         }
     }
 
+    /// Pause or resume publication.
+    ///
+    /// While paused, we don't upload any descriptors,
+    /// but we keep track of the changes that would require a new descriptor.
+    /// When resuming, we reupload the descriptor to all the HsDirs.
+    async fn handle_pause_change(&mut self, paused: bool) -> Result<(), FatalError> {
+        let is_paused = self.status() == PublishStatus::Paused;
+
+        match (paused, is_paused) {
+            (true, false) => {
+                info!(nickname=%self.imm.nickname, "pausing descriptor publication");
+                // TODO HSS: HsDirs have no way of withdrawing a descriptor, so the descriptors
+                // we have already uploaded remain available until they expire.
+                self.update_publish_status(PublishStatus::Paused).await
+            }
+            (false, true) => {
+                info!(nickname=%self.imm.nickname, "resuming descriptor publication");
+                let should_upload = self.note_ipt_change();
+
+                self.mark_all_dirty();
+                self.update_publish_status(should_upload).await
+            }
+            // Nothing changed.
+            (true, true) | (false, false) => Ok(()),
+        }
+    }
+
     /// Update the `PublishStatus` of the reactor with `new_state`,
-    /// unless the current state is `AwaitingIpts`.
+    /// unless the current state is `AwaitingIpts` or `Paused`.
     async fn update_publish_status_unless_waiting(
         &mut self,
         new_state: PublishStatus,
     ) -> Result<(), FatalError> {
-        // Only update the state if we're not waiting for intro points.
-        if self.status() != PublishStatus::AwaitingIpts {
+        // Only update the state if we're not waiting for intro points, or paused.
+        if !matches!(
+            self.status(),
+            PublishStatus::AwaitingIpts | PublishStatus::Paused
+        ) {
+            self.update_publish_status(new_state).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Update the `PublishStatus` of the reactor with `new_state`,
+    /// unless the current state is `Paused`.
+    async fn update_publish_status_unless_paused(
+        &mut self,
+        new_state: PublishStatus,
+    ) -> Result<(), FatalError> {
+        if self.status() != PublishStatus::Paused {
             self.update_publish_status(new_state).await?;
         }
 
@@ -919,9 +1579,10 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
 
     /// Update the `PublishStatus` of the reactor with `new_state`.
     async fn update_publish_status(&mut self, new_state: PublishStatus) -> Result<(), FatalError> {
+        let old_state = self.status();
         trace!(
             "publisher reactor status change: {:?} -> {:?}",
-            self.status(),
+            old_state,
             new_state
         );
 
@@ -930,9 +1591,102 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
             .await
             .map_err(|_: SendError<_>| internal!("failed to send upload notification?!"))?;
 
+        let was_awaiting = old_state == PublishStatus::AwaitingIpts;
+        let is_awaiting = new_state == PublishStatus::AwaitingIpts;
+        match (was_awaiting, is_awaiting) {
+            (false, true) => {
+                self.awaiting_ipts_since = Some(self.imm.runtime.now());
+                self.update_ipt_wait_deadline();
+            }
+            (true, false) => {
+                self.awaiting_ipts_since = None;
+                self.ipt_wait_deadline = None;
+                self.imm.status_tx.note_awaiting_ipts_timed_out(false);
+            }
+            (false, false) | (true, true) => {}
+        }
+
         Ok(())
     }
 
+    /// Set `ipt_wait_deadline` according to `awaiting_ipts_since` and `ipt_wait_timeout`.
+    ///
+    /// If we aren't waiting for IPTs, the deadline is cleared.
+    fn update_ipt_wait_deadline(&mut self) {
+        let timeout = self.ipt_wait_timeout;
+
+        // If this overflows, we will never consider ourselves stuck.
+        self.ipt_wait_deadline = self
+            .awaiting_ipts_since
+            .and_then(|since| since.checked_add(timeout));
+    }
+
+    /// Report that we have been waiting for IPTs for longer than `ipt_wait_timeout`.
+    fn handle_ipt_wait_timeout(&mut self) {
+        // We only report this once per wait.
+        self.ipt_wait_deadline = None;
+
+        if self.status() != PublishStatus::AwaitingIpts {
+            // We stopped waiting in the meantime.
+            return;
+        }
+
+        warn!(
+            nickname=%self.imm.nickname,
+            "no introduction points available; unable to publish descriptor"
+        );
+        self.imm.status_tx.note_awaiting_ipts_timed_out(true);
+    }
+
+    /// Create the timer for periodically republishing our descriptor every `interval`
+    /// (give or take some jitter).
+    ///
+    /// Returns `None` if `interval` is zero, which disables periodic republication.
+    fn new_republish_timer(
+        imm: &Immutable<R, M>,
+        interval: Duration,
+    ) -> Option<JitteredInterval<R, StdRng>> {
+        if interval.is_zero() {
+            return None;
+        }
+
+        // The reactor must be `Send`, so we can't hold on to a thread-local RNG.
+        let rng = StdRng::from_seed(imm.mockable.thread_rng().gen());
+        let jitter = interval / REPUBLISH_JITTER_DIVISOR;
+
+        Some(JitteredInterval::new(
+            imm.runtime.clone(),
+            rng,
+            interval,
+            jitter,
+        ))
+    }
+
+    /// Wait until `timer` next ticks.
+    ///
+    /// If there is no `timer`, this never returns.
+    async fn next_republish_tick(timer: &mut Option<JitteredInterval<R, StdRng>>) {
+        match timer {
+            Some(timer) => {
+                let _: Option<()> = timer.next().await;
+            }
+            None => future::pending().await,
+        }
+    }
+
+    /// Republish our descriptor to all of our HsDirs, as we do periodically.
+    ///
+    /// This ensures the HsDirs keep a copy of our descriptor, even if it hasn't changed
+    /// for longer than its lifetime.
+    async fn handle_republish_tick(&mut self) -> Result<(), FatalError> {
+        debug!(nickname=%self.imm.nickname, "periodically republishing descriptor");
+
+        self.mark_all_dirty();
+        // Schedule an upload, unless we're waiting for IPTs, or paused.
+        self.update_publish_status_unless_waiting(PublishStatus::UploadScheduled)
+            .await
+    }
+
     /// Use the new keys.
     async fn handle_new_keys(&self) -> Result<(), FatalError> {
         todo!()
@@ -943,7 +1697,18 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
         &mut self,
         config: Arc<OnionServiceConfig>,
     ) -> Result<(), FatalError> {
+        if self.ipt_wait_timeout != config.ipt_wait_timeout {
+            self.ipt_wait_timeout = config.ipt_wait_timeout;
+            self.update_ipt_wait_deadline();
+        }
+
+        // The timer itself is replaced by run_once.
+        self.republish_interval = config.descriptor_republish_interval;
+
         if self.replace_config_if_changed(config) {
+            // The hsdir_allowlist or extra_hsdirs might have changed, so we might need to
+            // upload to different HsDirs.
+            self.recompute_hs_dirs()?;
             self.mark_all_dirty();
 
             // Schedule an upload, unless we're still waiting for IPTs.
@@ -1002,7 +1767,7 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
 
             // Figure out which HsDirs we need to upload the descriptor to (some of them might already
             // have our latest descriptor, so we filter them out).
-            let hs_dirs = period_ctx
+            let mut hs_dirs = period_ctx
                 .hs_dirs
                 .iter()
                 .filter_map(|(relay_id, status)| {
@@ -1013,6 +1778,11 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                     }
                 })
                 .collect::<Vec<_>>();
+            order_hsdirs_for_upload(
+                &mut hs_dirs,
+                inner.config.descriptor_upload_order,
+                &mut self.imm.mockable.thread_rng(),
+            );
 
             if hs_dirs.is_empty() {
                 trace!("the descriptor is clean for all HSDirs. Nothing to do");
@@ -1020,6 +1790,8 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
             }
 
             let time_period = period_ctx.period;
+            let last_successful = period_ctx.last_successful;
+            self.imm.status_tx.note_publishing();
 
             let worst_case_end = self.imm.runtime.now() + UPLOAD_TIMEOUT;
             // This scope exists because rng is not Send, so it needs to fall out of scope before we
@@ -1048,6 +1820,7 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                         &netdir,
                         config,
                         time_period,
+                        last_successful,
                         Arc::clone(&imm),
                         ipt_upload_view.clone(),
                         upload_task_complete_tx,
@@ -1090,11 +1863,13 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
     ///
     /// Any failed uploads are retried (TODO HSS: document the retry logic when we implement it, as
     /// well as in what cases this will return an error).
+    #[allow(clippy::too_many_arguments)]
     async fn upload_for_time_period(
         hs_dirs: Vec<RelayIds>,
         netdir: &Arc<NetDir>,
         config: Arc<OnionServiceConfig>,
         time_period: TimePeriod,
+        last_successful: Option<RevisionCounter>,
         imm: Arc<Immutable<R, M>>,
         ipt_upload_view: IptsPublisherUploadView,
         mut upload_task_complete_tx: Sender<TimePeriodUploadResult>,
@@ -1184,11 +1959,16 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                             // We're about to generate a new version of the descriptor,
                             // so let's generate a new revision counter.
                             let now = imm.runtime.wallclock();
-                            let revision_counter =
-                                imm.generate_revision_counter(time_period, now)?;
+                            let revision_counter = imm.generate_revision_counter(
+                                time_period,
+                                now,
+                                last_successful,
+                                config.allow_key_generation,
+                            )?;
 
                             build_sign(
                                 &imm.keymgr,
+                                &imm.offline_certs,
                                 &config,
                                 ipts,
                                 time_period,
@@ -1223,6 +2003,18 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
                         "generated new descriptor for time period",
                     );
 
+                    let observer = imm.upload_observer.borrow().clone();
+                    if let Some(observer) = observer {
+                        observer(&desc, &relay_ids);
+                    }
+
+                    // Wait for our turn, if we share an upload limit with other services.
+                    // The permit is released when the upload finishes or times out.
+                    let _permit = match &imm.upload_limit {
+                        Some(limit) => Some(limit.acquire().await),
+                        None => None,
+                    };
+
                     let upload_res = match imm
                         .runtime
                         .timeout(UPLOAD_TIMEOUT, run_upload(desc.clone()))
@@ -1300,14 +2092,19 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
             "starting descriptor upload",
         );
 
-        let circuit = imm
-            .mockable
-            .get_or_launch_specific(
-                netdir,
-                HsCircKind::SvcHsDir,
-                OwnedCircTarget::from_circ_target(hsdir),
-            )
-            .await?;
+        let target = OwnedCircTarget::from_circ_target(hsdir);
+        let circuit = match imm.anonymity {
+            Anonymity::Anonymous => {
+                imm.mockable
+                    .get_or_launch_specific(netdir, HsCircKind::SvcHsDir, target)
+                    .await?
+            }
+            Anonymity::DangerouslyNonAnonymous => {
+                imm.mockable
+                    .launch_specific_one_hop(netdir, HsCircKind::SvcHsDir, target)
+                    .await?
+            }
+        };
 
         let mut stream = circuit
             .begin_dir_stream()
@@ -1349,6 +2146,8 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
             let schedule = PublisherBackoffSchedule {
                 retry_delay: RetryDelay::from_msec(BASE_DELAY_MSEC),
                 mockable: imm.mockable.clone(),
+                runtime: imm.runtime.clone(),
+                retry_budget: Arc::clone(&imm.retry_budget),
             };
             Runner::new(
                 "upload a hidden service descriptor".into(),
@@ -1385,26 +2184,73 @@ impl<R: Runtime, M: Mockable> Reactor<R, M> {
     }
 }
 
+/// Put `hs_dirs` into the order in which we should upload our descriptor to them.
+fn order_hsdirs_for_upload<Rng: rand::Rng>(
+    hs_dirs: &mut [RelayIds],
+    order: DescriptorUploadOrder,
+    rng: &mut Rng,
+) {
+    use rand::seq::SliceRandom as _;
+
+    match order {
+        DescriptorUploadOrder::RingOrder => {}
+        DescriptorUploadOrder::Random => hs_dirs.shuffle(rng),
+    }
+}
+
 /// Try to read the blinded identity key for a given `TimePeriod`.
 ///
-/// Returns `None` if the service is running in "offline" mode.
+/// Returns `None` if the service is running in "offline" mode, and the key isn't in the keystore.
+/// The service is in offline mode if its identity keypair is missing from the keystore,
+/// but its public identity key is not. In that case, we can't derive the blinded keypair,
+/// and the descriptor signing key for `period` must have been provisioned instead:
+/// if it wasn't, we return [`FatalError::OfflineKeysExhausted`].
 ///
-// TODO HSS: we don't currently have support for "offline" mode so this can never return
-// `Ok(None)`.
+/// If `allow_key_generation` is `false`, the key must already be in the keystore:
+/// if it isn't, we return [`FatalError::MissingBlindIdKeypair`] instead of deriving it
+/// from the identity key.
 pub(super) fn read_blind_id_keypair(
     keymgr: &Arc<KeyMgr>,
     nickname: &HsNickname,
     period: TimePeriod,
+    keystore_selector: KeystoreSelector<'_>,
+    allow_key_generation: bool,
 ) -> Result<Option<HsBlindIdKeypair>, FatalError> {
+    let blind_id_key_spec = BlindIdKeypairSpecifier::new(nickname.clone(), period);
+
+    if let Some(blind_id_kp) = keymgr.get::<HsBlindIdKeypair>(&blind_id_key_spec)? {
+        return Ok(Some(blind_id_kp));
+    }
+
     let svc_key_spec = HsIdKeypairSpecifier::new(nickname.clone());
-    let hsid_kp = keymgr
-        .get::<HsIdKeypair>(&svc_key_spec)?
-        .ok_or_else(|| FatalError::MissingHsIdKeypair(nickname.clone()))?;
+    let Some(hsid_kp) = keymgr.get::<HsIdKeypair>(&svc_key_spec)? else {
+        let pub_hsid_spec = HsIdPublicKeySpecifier::new(nickname.clone());
+        if keymgr.get::<HsIdKey>(&pub_hsid_spec)?.is_none() {
+            return Err(FatalError::MissingHsIdKeypair(nickname.clone()));
+        }
 
-    let blind_id_key_spec = BlindIdKeypairSpecifier::new(nickname.clone(), period);
+        // The identity keypair is offline.
+        let desc_sign_key_spec = DescSigningKeypairSpecifier::new(nickname.clone(), period);
+        if keymgr
+            .get::<HsDescSigningKeypair>(&desc_sign_key_spec)?
+            .is_none()
+        {
+            return Err(FatalError::OfflineKeysExhausted {
+                nickname: nickname.clone(),
+                period,
+            });
+        }
+
+        return Ok(None);
+    };
+
+    if !allow_key_generation {
+        return Err(FatalError::MissingBlindIdKeypair {
+            nickname: nickname.clone(),
+            period,
+        });
+    }
 
-    // TODO: make the keystore selector configurable
-    let keystore_selector = Default::default();
     let blind_id_kp = keymgr.get_or_generate_with_derived::<HsBlindIdKeypair>(
         &blind_id_key_spec,
         keystore_selector,
@@ -1436,18 +2282,28 @@ enum PublishStatus {
     /// `UploadScheduled`.
     #[default]
     AwaitingIpts,
+    /// Publication has been paused by the user.
+    ///
+    /// No descriptors will be published until publication is resumed.
+    /// Changes that would require a new descriptor (such as IPT changes) are still tracked,
+    /// and a new descriptor is uploaded to all HsDirs when publication is resumed.
+    Paused,
 }
 
 /// The backoff schedule for the task that publishes descriptors.
 #[derive(Clone, Debug)]
-struct PublisherBackoffSchedule<M: Mockable> {
+struct PublisherBackoffSchedule<R: Runtime, M: Mockable> {
     /// The delays
     retry_delay: RetryDelay,
     /// The mockable reactor state, needed for obtaining an rng.
     mockable: M,
+    /// The runtime, needed for knowing when each retry will happen.
+    runtime: R,
+    /// The retry budget shared with the other uploads of this service.
+    retry_budget: Arc<Mutex<RetryBudget>>,
 }
 
-impl<M: Mockable> BackoffSchedule for PublisherBackoffSchedule<M> {
+impl<R: Runtime, M: Mockable> BackoffSchedule for PublisherBackoffSchedule<R, M> {
     fn max_retries(&self) -> Option<usize> {
         None
     }
@@ -1458,7 +2314,68 @@ impl<M: Mockable> BackoffSchedule for PublisherBackoffSchedule<M> {
     }
 
     fn next_delay<E: RetriableError>(&mut self, _error: &E) -> Option<Duration> {
-        Some(self.retry_delay.next_delay(&mut self.mockable.thread_rng()))
+        let delay = self.retry_delay.next_delay(&mut self.mockable.thread_rng());
+        let max_wait = self.timeout().unwrap_or(Duration::MAX);
+
+        // Each retry builds a new circuit, so it must also come out of the retry budget.
+        // If the budget won't allow this retry any time soon, we give up on this HsDir
+        // for now, rather than queueing up retries that are bound to time out.
+        let wait = self
+            .retry_budget
+            .lock()
+            .expect("poisoned lock")
+            .reserve(self.runtime.now() + delay, max_wait)?;
+
+        Some(delay + wait)
+    }
+}
+
+/// A token bucket limiting the rate at which a service retries its descriptor uploads.
+///
+/// The bucket holds up to `burst` retries, and one retry is added back every `interval`.
+///
+/// Internally, this is implemented as a "generic cell rate algorithm": instead of counting
+/// tokens, we keep track of the time at which the bucket would be full again.
+#[derive(Clone, Debug)]
+struct RetryBudget {
+    /// The time at which the bucket will be full, if no more retries are reserved.
+    full_at: Instant,
+    /// The time it takes for one retry to be added back to the bucket.
+    interval: Duration,
+    /// How far `full_at` may be ahead of the time of a retry, for that retry to be allowed.
+    ///
+    /// This is `interval * (burst - 1)`.
+    tolerance: Duration,
+}
+
+impl RetryBudget {
+    /// Create a new `RetryBudget` that starts out full at `now`.
+    fn new(now: Instant, burst: u32, interval: Duration) -> Self {
+        Self {
+            full_at: now,
+            interval,
+            tolerance: interval * burst.saturating_sub(1),
+        }
+    }
+
+    /// Reserve a retry that would like to happen at `at`.
+    ///
+    /// Returns how much longer than that the retry must wait for the budget to allow it.
+    ///
+    /// If it would have to wait longer than `max_wait`, nothing is reserved,
+    /// and `None` is returned.
+    fn reserve(&mut self, at: Instant, max_wait: Duration) -> Option<Duration> {
+        let full_at = std::cmp::max(self.full_at, at);
+        let wait = full_at
+            .saturating_duration_since(at)
+            .saturating_sub(self.tolerance);
+
+        if wait > max_wait {
+            return None;
+        }
+
+        self.full_at = full_at + self.interval;
+        Some(wait)
     }
 }
 
@@ -1480,28 +2397,6 @@ struct TimePeriodUploadResult {
     hsdir_result: Vec<HsDirUploadStatus>,
 }
 
-/// The outcome of uploading a descriptor to a particular HsDir.
-#[derive(Clone, Debug, PartialEq)]
-struct HsDirUploadStatus {
-    /// The identity of the HsDir we attempted to upload the descriptor to.
-    relay_ids: RelayIds,
-    /// The outcome of this attempt.
-    upload_res: UploadStatus,
-    /// The revision counter of the descriptor we tried to upload.
-    revision_counter: RevisionCounter,
-}
-
-/// The outcome of uploading a descriptor.
-//
-// TODO: consider making this a type alias for Result<(), ()>
-#[derive(Copy, Clone, Debug, PartialEq)]
-enum UploadStatus {
-    /// The descriptor upload succeeded.
-    Success,
-    /// The descriptor upload failed.
-    Failure,
-}
-
 impl<T, E> From<Result<T, E>> for UploadStatus {
     fn from(res: Result<T, E>) -> Self {
         if res.is_ok() {
@@ -1511,3 +2406,223 @@ impl<T, E> From<Result<T, E>> for UploadStatus {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    use tor_llcrypto::pk::rsa::RsaIdentity;
+    use tor_netdir::testnet;
+
+    /// The status we pretend to have recorded for the HsDir with the specified `id`.
+    fn old_status(id: &RelayIds) -> DescriptorStatus {
+        if id.rsa_identity().unwrap().as_bytes()[0] % 2 == 0 {
+            DescriptorStatus::Clean
+        } else {
+            DescriptorStatus::Dirty
+        }
+    }
+
+    #[test]
+    fn compute_hsdirs_preserves_many_statuses() {
+        let netdir = Arc::new(testnet::construct_netdir().unwrap_if_sufficient().unwrap());
+        let period = netdir.hs_time_period();
+        let blind_id = HsBlindId::from([7; 32]);
+
+        let (hs_dirs, extras) =
+            TimePeriodContext::compute_hsdirs(period, blind_id, &netdir, iter::empty(), None, &[])
+                .unwrap();
+        assert!(!hs_dirs.is_empty());
+        assert!(extras.is_empty());
+        assert!(hs_dirs
+            .iter()
+            .all(|(_, status)| *status == DescriptorStatus::Dirty));
+
+        // Lots of HsDirs that are no longer in the ring, with the current ones
+        // mixed in (in reverse order) among them.
+        let mut old_hsdirs = (0..4096_u32)
+            .map(|i| {
+                let mut rsa_id = [0xff; 20];
+                rsa_id[..4].copy_from_slice(&i.to_be_bytes());
+                let id = RelayIds::builder()
+                    .rsa_identity(RsaIdentity::from_bytes(&rsa_id).unwrap())
+                    .build()
+                    .unwrap();
+                (id, DescriptorStatus::Clean)
+            })
+            .collect::<Vec<_>>();
+        for (n, (id, _)) in hs_dirs.iter().rev().enumerate() {
+            old_hsdirs.insert(n * 97, (id.clone(), old_status(id)));
+        }
+
+        let (new_hs_dirs, _) = TimePeriodContext::compute_hsdirs(
+            period,
+            blind_id,
+            &netdir,
+            old_hsdirs.iter(),
+            None,
+            &[],
+        )
+        .unwrap();
+
+        assert_eq!(
+            new_hs_dirs.iter().map(|(id, _)| id).collect::<Vec<_>>(),
+            hs_dirs.iter().map(|(id, _)| id).collect::<Vec<_>>(),
+        );
+        for (id, status) in &new_hs_dirs {
+            assert_eq!(*status, old_status(id));
+        }
+        assert!(new_hs_dirs
+            .iter()
+            .any(|(_, status)| *status == DescriptorStatus::Clean));
+    }
+
+    #[test]
+    fn select_hsdirs_skips_hsdirs_without_ids() {
+        let netdir = Arc::new(testnet::construct_netdir().unwrap_if_sufficient().unwrap());
+        let period = netdir.hs_time_period();
+        let blind_id = HsBlindId::from([7; 32]);
+
+        let (hs_dirs, _) =
+            TimePeriodContext::compute_hsdirs(period, blind_id, &netdir, iter::empty(), None, &[])
+                .unwrap();
+        assert!(!hs_dirs.is_empty());
+
+        // An HsDir with no identities, mixed in with the real ones.
+        let mut ring = hs_dirs.iter().map(|(id, _)| id.clone()).collect::<Vec<_>>();
+        ring.insert(ring.len() / 2, RelayIds::empty());
+
+        let (selected, _) =
+            TimePeriodContext::select_hsdirs(ring.into_iter(), iter::empty(), iter::empty(), None);
+        assert_eq!(selected, hs_dirs);
+        assert!(!selected.iter().any(|(id, _)| *id == RelayIds::empty()));
+    }
+
+    #[test]
+    fn extra_hsdirs_do_not_count_towards_quorum() {
+        let netdir = Arc::new(testnet::construct_netdir().unwrap_if_sufficient().unwrap());
+        let period = netdir.hs_time_period();
+        let blind_id = HsBlindId::from([7; 32]);
+
+        let (ring, _) =
+            TimePeriodContext::compute_hsdirs(period, blind_id, &netdir, iter::empty(), None, &[])
+                .unwrap();
+        let ring = ring.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+        // Enough relays that aren't in the ring to make up a quorum on their own.
+        let extra_hsdirs = netdir
+            .relays()
+            .filter(|relay| !ring.iter().any(|id| relay.has_all_relay_ids_from(id)))
+            .map(|relay| RelayId::from(*relay.rsa_identity().unwrap()))
+            .take(ring.len())
+            .collect::<Vec<_>>();
+        assert_eq!(extra_hsdirs.len(), ring.len());
+
+        let mut ctx = TimePeriodContext::new(
+            period,
+            blind_id,
+            &netdir,
+            iter::empty(),
+            None,
+            &extra_hsdirs,
+        )
+        .unwrap();
+        assert_eq!(ctx.hs_dirs.len(), ring.len() * 2);
+        assert_eq!(ctx.extra_hs_dirs.len(), ring.len());
+
+        // Our descriptor is only up-to-date on the extra HsDirs, which clients don't use.
+        for (id, status) in &mut ctx.hs_dirs {
+            if !ring.contains(id) {
+                *status = DescriptorStatus::Clean;
+            }
+        }
+        assert_eq!(ctx.n_clean_hs_dirs(), 0);
+        assert!(!ctx.reached_quorum());
+
+        // Once it is up-to-date on the ring, we have a quorum.
+        ctx.hs_dirs
+            .iter_mut()
+            .for_each(|(_, status)| *status = DescriptorStatus::Clean);
+        assert_eq!(ctx.n_clean_hs_dirs(), ring.len());
+        assert!(ctx.reached_quorum());
+    }
+
+    #[test]
+    fn hsdir_upload_order() {
+        use tor_basic_utils::test_rng::Config;
+
+        let netdir = Arc::new(testnet::construct_netdir().unwrap_if_sufficient().unwrap());
+        let period = netdir.hs_time_period();
+        let blind_id = HsBlindId::from([7; 32]);
+        let ring =
+            TimePeriodContext::compute_hsdirs(period, blind_id, &netdir, iter::empty(), None, &[])
+                .unwrap()
+                .0
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>();
+        assert!(ring.len() > 2);
+
+        let ordered = |order, seed| {
+            let mut hs_dirs = ring.clone();
+            let mut rng = Config::Seeded(seed).into_rng();
+            order_hsdirs_for_upload(&mut hs_dirs, order, &mut rng);
+            hs_dirs
+        };
+
+        // By default, we upload in ring order.
+        assert_eq!(ordered(DescriptorUploadOrder::default(), [1; 32]), ring);
+        assert_eq!(ordered(DescriptorUploadOrder::RingOrder, [1; 32]), ring);
+
+        // The random order is a shuffle of the ring, determined by the rng.
+        let shuffled = ordered(DescriptorUploadOrder::Random, [1; 32]);
+        assert_ne!(shuffled, ring);
+        assert_eq!(ordered(DescriptorUploadOrder::Random, [1; 32]), shuffled);
+        assert_ne!(ordered(DescriptorUploadOrder::Random, [2; 32]), shuffled);
+
+        let sorted = |mut ids: Vec<RelayIds>| {
+            ids.sort_by_key(|id| id.rsa_identity().copied());
+            ids
+        };
+        assert_eq!(sorted(shuffled), sorted(ring.clone()));
+    }
+
+    #[test]
+    fn retry_budget() {
+        const INTERVAL: Duration = Duration::from_secs(2);
+        const MAX_WAIT: Duration = Duration::from_secs(5);
+
+        let now = Instant::now();
+        let mut budget = RetryBudget::new(now, 3, INTERVAL);
+
+        // The first `burst` retries don't have to wait.
+        for _ in 0..3 {
+            assert_eq!(budget.reserve(now, MAX_WAIT), Some(Duration::ZERO));
+        }
+        // After that, each retry waits for one more interval.
+        assert_eq!(budget.reserve(now, MAX_WAIT), Some(INTERVAL));
+        assert_eq!(budget.reserve(now, MAX_WAIT), Some(INTERVAL * 2));
+        // A retry that would have to wait too long is not reserved...
+        assert_eq!(budget.reserve(now, MAX_WAIT), None);
+        // ...so it doesn't push back the retries that come after it.
+        assert_eq!(budget.reserve(now + INTERVAL, MAX_WAIT), Some(INTERVAL * 2));
+
+        // Once enough time has passed, the bucket is full again.
+        let later = now + INTERVAL * 10;
+        for _ in 0..3 {
+            assert_eq!(budget.reserve(later, MAX_WAIT), Some(Duration::ZERO));
+        }
+        assert_eq!(budget.reserve(later, MAX_WAIT), Some(INTERVAL));
+    }
+}
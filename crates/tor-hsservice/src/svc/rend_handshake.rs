@@ -293,6 +293,10 @@ impl IntroRequest {
             RetryError::in_attempt_to("Establish a circuit to a rendezvous point");
 
         // Open circuit to rendezvous point.
+        //
+        // TODO HSS: Services running with Anonymity::DangerouslyNonAnonymous
+        // should use a one-hop circuit here, as they do for their HsDirs and
+        // introduction points.
         for _attempt in 1..=max_n_attempts.into() {
             match hs_pool
                 .get_or_launch_specific(&netdir, HsCircKind::SvcRend, rend_point.clone())
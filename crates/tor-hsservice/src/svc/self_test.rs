@@ -0,0 +1,254 @@
+//! End-to-end self-test of an onion service
+//!
+//! We check that the service is reachable by connecting to it ourselves,
+//! the way any other client would:
+//! fetching its descriptor from the HsDirs,
+//! and completing a rendezvous via one of its introduction points.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::StreamExt as _;
+use tor_circmgr::hspool::HsCircPool;
+use tor_circmgr::isolation::StreamIsolation;
+use tor_circmgr::{CircuitTiming, CircuitTimingBuilder};
+use tor_error::into_internal;
+use tor_hsclient::{HsClientConnector, HsClientConnectorConfig, HsClientSecretKeys};
+use tor_hscrypto::pk::HsId;
+use tor_rtcompat::{Runtime, SleepProvider};
+
+use crate::status::DescriptorUploadTimes;
+use crate::svc::netdir::NetDirProviderRx;
+use crate::SelfTestError;
+
+/// Something that can self-test an onion service
+///
+/// This is a trait, rather than [`SelfTester`] itself,
+/// so that `OnionService` needn't be generic over the runtime.
+#[async_trait]
+pub(crate) trait SelfTest: Send + Sync {
+    /// Connect to the service, whose identity is `hsid`, as a client
+    ///
+    /// Returns how long it took to complete the rendezvous.
+    async fn self_test(&self, hsid: HsId) -> Result<Duration, SelfTestError>;
+}
+
+/// Something that can connect to an onion service, as a client
+///
+/// This is a trait so that we can mock the network in tests.
+#[async_trait]
+pub(crate) trait Connect: Send + Sync + 'static {
+    /// Connect to the service `hsid`, returning once a rendezvous has completed
+    async fn connect(&self, hsid: HsId) -> Result<(), SelfTestError>;
+}
+
+/// A self-tester for one onion service
+pub(crate) struct SelfTester<R, C> {
+    /// The runtime, which we use for timing the test
+    runtime: R,
+    /// The times of our successful descriptor uploads, maintained by the publisher
+    upload_times: DescriptorUploadTimes,
+    /// How we connect to the service
+    connector: C,
+}
+
+impl<R, C> SelfTester<R, C> {
+    /// Create a new `SelfTester`
+    pub(crate) fn new(runtime: R, upload_times: DescriptorUploadTimes, connector: C) -> Self {
+        Self {
+            runtime,
+            upload_times,
+            connector,
+        }
+    }
+}
+
+#[async_trait]
+impl<R: SleepProvider, C: Connect> SelfTest for SelfTester<R, C> {
+    async fn self_test(&self, hsid: HsId) -> Result<Duration, SelfTestError> {
+        // If we haven't published a descriptor, nobody (including us) can find the service:
+        // don't bother trying.
+        let published = !self.upload_times.lock().expect("poisoned lock").is_empty();
+        if !published {
+            return Err(SelfTestError::NotPublished);
+        }
+
+        let start = self.runtime.now();
+        self.connector.connect(hsid).await?;
+        Ok(self.runtime.now().saturating_duration_since(start))
+    }
+}
+
+/// A [`Connect`] that really connects over the Tor network, using an [`HsClientConnector`]
+pub(crate) struct RealConnector<R: Runtime> {
+    /// The runtime
+    runtime: R,
+    /// The circuit pool, which we share with the rest of the service
+    circ_pool: Arc<HsCircPool<R>>,
+    /// A source of network directories
+    dir_provider_rx: NetDirProviderRx,
+}
+
+impl<R: Runtime> RealConnector<R> {
+    /// Create a new `RealConnector`
+    pub(crate) fn new(
+        runtime: R,
+        circ_pool: Arc<HsCircPool<R>>,
+        dir_provider_rx: NetDirProviderRx,
+    ) -> Self {
+        Self {
+            runtime,
+            circ_pool,
+            dir_provider_rx,
+        }
+    }
+}
+
+#[async_trait]
+impl<R: Runtime> Connect for RealConnector<R> {
+    async fn connect(&self, hsid: HsId) -> Result<(), SelfTestError> {
+        let netdir = self
+            .dir_provider_rx
+            .borrow()
+            .timely_netdir()
+            .map_err(SelfTestError::NetDir)?;
+
+        // We use a fresh connector each time, so that we don't reuse any cached
+        // descriptor or circuit: we want to know whether a new client could reach us.
+        // (It has no housekeeping to do, so we give it an empty prompt stream,
+        // which lets its housekeeping task exit straight away.)
+        let connector = HsClientConnector::new(
+            self.runtime.clone(),
+            Arc::clone(&self.circ_pool),
+            &ConnectorConfig::new()?,
+            futures::stream::empty().boxed(),
+        )
+        .map_err(SelfTestError::ClientStartup)?;
+
+        let _circ = connector
+            .get_or_launch_circuit(
+                &netdir,
+                hsid,
+                HsClientSecretKeys::none(),
+                StreamIsolation::no_isolation(),
+            )
+            .await
+            .map_err(SelfTestError::Connect)?;
+
+        Ok(())
+    }
+}
+
+/// Configuration for the [`HsClientConnector`] we use for self-tests
+struct ConnectorConfig {
+    /// Circuit timing parameters
+    circuit_timing: CircuitTiming,
+}
+
+impl ConnectorConfig {
+    /// Create a `ConnectorConfig` with the default parameters
+    fn new() -> Result<Self, SelfTestError> {
+        let circuit_timing = CircuitTimingBuilder::default()
+            .build()
+            .map_err(into_internal!("default CircuitTiming is invalid"))?;
+        Ok(ConnectorConfig { circuit_timing })
+    }
+}
+
+impl AsRef<CircuitTiming> for ConnectorConfig {
+    fn as_ref(&self) -> &CircuitTiming {
+        &self.circuit_timing
+    }
+}
+
+impl HsClientConnectorConfig for ConnectorConfig {}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::SystemTime;
+
+    use tor_hscrypto::time::TimePeriod;
+    use tor_rtmock::MockRuntime;
+
+    use crate::status::DescriptorUploadTime;
+
+    /// How long our mock rendezvous takes
+    const RENDEZVOUS_TIME: Duration = Duration::from_secs(3);
+
+    /// A [`Connect`] that completes a rendezvous after [`RENDEZVOUS_TIME`]
+    struct MockConnector {
+        /// The runtime
+        runtime: MockRuntime,
+        /// The number of times we were asked to connect
+        n_connects: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Connect for MockConnector {
+        async fn connect(&self, _hsid: HsId) -> Result<(), SelfTestError> {
+            self.n_connects.fetch_add(1, Ordering::SeqCst);
+            self.runtime.sleep(RENDEZVOUS_TIME).await;
+            Ok(())
+        }
+    }
+
+    /// Make a `SelfTester` for a service that has published descriptors at `upload_times`
+    fn mk_tester(
+        runtime: &MockRuntime,
+        upload_times: Vec<DescriptorUploadTime>,
+    ) -> (SelfTester<MockRuntime, MockConnector>, Arc<AtomicUsize>) {
+        let n_connects = Arc::new(AtomicUsize::new(0));
+        let connector = MockConnector {
+            runtime: runtime.clone(),
+            n_connects: Arc::clone(&n_connects),
+        };
+        let upload_times = Arc::new(Mutex::new(upload_times));
+        let tester = SelfTester::new(runtime.clone(), upload_times, connector);
+        (tester, n_connects)
+    }
+
+    #[test]
+    fn healthy_service_passes() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let uploaded =
+                DescriptorUploadTime::new(TimePeriod::from_parts(1, 2, 3), SystemTime::UNIX_EPOCH);
+            let (tester, n_connects) = mk_tester(&runtime, vec![uploaded]);
+
+            let (res, _) = futures::join!(
+                tester.self_test(HsId::from([42; 32])),
+                runtime.advance_by(RENDEZVOUS_TIME),
+            );
+            assert_eq!(res.unwrap(), RENDEZVOUS_TIME);
+            assert_eq!(n_connects.load(Ordering::SeqCst), 1);
+        });
+    }
+
+    #[test]
+    fn unpublished_service_fails() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let (tester, n_connects) = mk_tester(&runtime, vec![]);
+
+            let res = tester.self_test(HsId::from([42; 32])).await;
+            assert!(matches!(res, Err(SelfTestError::NotPublished)));
+            // We didn't even try to connect.
+            assert_eq!(n_connects.load(Ordering::SeqCst), 0);
+        });
+    }
+}
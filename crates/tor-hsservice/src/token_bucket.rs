@@ -0,0 +1,180 @@
+//! A token bucket, for rate-limiting things we do ourselves.
+//!
+//! Configured with a [`TokenBucketConfig`].
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use educe::Educe;
+use tor_rtcompat::SleepProvider;
+
+use crate::config::TokenBucketConfig;
+
+/// A token bucket
+///
+/// The bucket holds at most `burst` tokens, and is refilled at `rate` tokens per second.
+/// Each permitted action takes one token.
+#[derive(Debug, Clone)]
+pub(crate) struct TokenBucket {
+    /// Tokens added to the bucket per second
+    rate: u32,
+    /// Capacity of the bucket
+    burst: u32,
+    /// Tokens currently in the bucket
+    tokens: u32,
+    /// When the bucket was last refilled
+    ///
+    /// Only advanced by whole tokens' worth of time,
+    /// so that we don't lose fractional tokens.
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Create a new, full, bucket
+    pub(crate) fn new(config: &TokenBucketConfig, now: Instant) -> Self {
+        TokenBucket {
+            rate: config.rate(),
+            burst: config.burst(),
+            tokens: config.burst(),
+            last_refill: now,
+        }
+    }
+
+    /// Try to take a token from the bucket, returning `true` if the action is permitted
+    pub(crate) fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens == 0 {
+            return false;
+        }
+        self.tokens -= 1;
+        true
+    }
+
+    /// Add the tokens that have accrued since `last_refill`
+    fn refill(&mut self, now: Instant) {
+        if self.tokens >= self.burst || self.rate == 0 {
+            // Time spent full (or with no refill at all) doesn't earn any tokens.
+            self.last_refill = now;
+            return;
+        }
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let rate = u128::from(self.rate);
+        let earned = elapsed.as_nanos() * rate / 1_000_000_000;
+        if earned == 0 {
+            return;
+        }
+        let space = self.burst - self.tokens;
+        if earned >= u128::from(space) {
+            self.tokens = self.burst;
+            self.last_refill = now;
+        } else {
+            // `earned < space`, so this cast is lossless.
+            let earned = earned as u32;
+            self.tokens += earned;
+            // Round up, so that we never credit the same time twice.
+            let used = (u128::from(earned) * 1_000_000_000 + rate - 1) / rate;
+            let used = Duration::from_nanos(used.try_into().unwrap_or(u64::MAX));
+            self.last_refill = std::cmp::min(self.last_refill + used, now);
+        }
+    }
+}
+
+/// A [`TokenBucket`] shared between several users, using the runtime's clock
+#[derive(Clone, Educe)]
+#[educe(Debug)]
+pub(crate) struct SharedTokenBucket {
+    /// The bucket
+    bucket: Arc<Mutex<TokenBucket>>,
+    /// Source of the current time
+    #[educe(Debug(ignore))]
+    now: Arc<dyn Fn() -> Instant + Send + Sync>,
+}
+
+impl SharedTokenBucket {
+    /// Create a new, full, shared bucket
+    pub(crate) fn new<R: SleepProvider>(runtime: &R, config: &TokenBucketConfig) -> Self {
+        let runtime = runtime.clone();
+        let bucket = TokenBucket::new(config, runtime.now());
+        SharedTokenBucket {
+            bucket: Arc::new(Mutex::new(bucket)),
+            now: Arc::new(move || runtime.now()),
+        }
+    }
+
+    /// Try to take a token from the bucket, returning `true` if the action is permitted
+    pub(crate) fn try_take(&self) -> bool {
+        let now = (self.now)();
+        self.bucket.lock().expect("poisoned lock").try_take(now)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use tor_rtmock::MockRuntime;
+
+    /// Count how many of `n` actions at `now` the bucket permits
+    fn n_permitted(bucket: &mut TokenBucket, now: Instant, n: usize) -> usize {
+        (0..n).filter(|_| bucket.try_take(now)).count()
+    }
+
+    #[test]
+    fn burst_then_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(&TokenBucketConfig::new(10, 5), start);
+
+        // A burst larger than `burst` is cut off at `burst`.
+        assert_eq!(n_permitted(&mut bucket, start, 20), 5);
+
+        // After 250ms, we have earned 2.5 tokens; we get to use 2 of them.
+        let t = start + Duration::from_millis(250);
+        assert_eq!(n_permitted(&mut bucket, t, 20), 2);
+
+        // The remaining half token is not lost.
+        let t = t + Duration::from_millis(50);
+        assert_eq!(n_permitted(&mut bucket, t, 20), 1);
+
+        // However long we wait, we never get more than `burst`.
+        let t = t + Duration::from_secs(60);
+        assert_eq!(n_permitted(&mut bucket, t, 20), 5);
+    }
+
+    #[test]
+    fn zero_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(&TokenBucketConfig::new(0, 3), start);
+        assert_eq!(n_permitted(&mut bucket, start, 10), 3);
+        let t = start + Duration::from_secs(3600);
+        assert_eq!(n_permitted(&mut bucket, t, 10), 0);
+    }
+
+    #[test]
+    fn shared() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let bucket = SharedTokenBucket::new(&runtime, &TokenBucketConfig::new(2, 2));
+            let other = bucket.clone();
+
+            assert!(bucket.try_take());
+            assert!(other.try_take());
+            // Both handles draw from the same bucket.
+            assert!(!bucket.try_take());
+            assert!(!other.try_take());
+
+            runtime.advance_by(Duration::from_millis(500)).await;
+            assert!(other.try_take());
+            assert!(!bucket.try_take());
+        });
+    }
+}
@@ -124,6 +124,13 @@ impl KeyMgr {
         Ok(None)
     }
 
+    /// A dummy `get_checked` implementation that always behaves like the requested key is not found.
+    ///
+    /// This function always returns `Ok(None)`.
+    pub fn get_checked<K, P>(&self, _: &dyn Any, _: &P) -> Result<Option<K>> {
+        Ok(None)
+    }
+
     /// A dummy `insert` implementation that always fails.
     ///
     /// This function always returns an error.
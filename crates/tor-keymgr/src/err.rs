@@ -31,6 +31,13 @@ pub enum Error {
 pub trait KeystoreError:
     HasKind + StdError + DynClone + fmt::Debug + fmt::Display + Send + Sync + 'static
 {
+    /// Whether this error was caused by a stored key whose algorithm
+    /// does not match the algorithm expected for its key type.
+    ///
+    /// Used by `KeyMgr::self_check` to tell mismatched keys apart from unreadable ones.
+    fn is_key_type_mismatch(&self) -> bool {
+        false
+    }
 }
 
 impl HasKind for Error {
@@ -82,6 +89,12 @@ pub enum KeystoreCorruptionError {
     /// A keystore contains a key that has an invalid [`KeyPath`](crate::KeyPath).
     #[error("{0}")]
     KeyPath(#[from] KeyPathError),
+
+    /// A keystore contains a keypair that does not match the public key we expected.
+    ///
+    /// See [`KeyMgr::get_checked`](crate::KeyMgr::get_checked).
+    #[error("Keypair does not match the expected public key")]
+    PublicKeyMismatch,
 }
 
 #[cfg(test)]
@@ -3,8 +3,8 @@
 // TODO #902: OpenSSH keys can have passphrases. While the current implementation isn't able to
 // handle such keys, we will eventually need to support them (this will be a breaking API change).
 
-use ssh_key::private::KeypairData;
-use ssh_key::public::KeyData;
+use ssh_key::private::{KeypairData, PrivateKey};
+use ssh_key::public::{KeyData, PublicKey};
 use ssh_key::Algorithm;
 
 use crate::keystore::arti::err::ArtiNativeKeystoreError;
@@ -98,79 +98,54 @@ impl From<Algorithm> for SshKeyAlgorithm {
     }
 }
 
-/// Parse an OpenSSH key, returning its underlying [`KeyData`], if it's a public key, or
-/// [`KeypairData`], if it's a private one.
-macro_rules! parse_openssh {
-    (PRIVATE $key:expr, $key_type:expr) => {{
-        parse_openssh!(
-            $key,
-            $key_type,
-            ssh_key::private::PrivateKey::from_openssh,
-            convert_ed25519_kp,
-            convert_expanded_ed25519_kp,
-            convert_x25519_kp,
-            KeypairData
-        )
-    }};
-
-    (PUBLIC $key:expr, $key_type:expr) => {{
-        parse_openssh!(
-            $key,
-            $key_type,
-            ssh_key::public::PublicKey::from_openssh,
-            convert_ed25519_pk,
-            convert_expanded_ed25519_pk,
-            convert_x25519_pk,
-            KeyData
-        )
-    }};
-
-    ($key:expr, $key_type:expr, $parse_fn:path, $ed25519_fn:path, $expanded_ed25519_fn:path, $x25519_fn:path, $key_data_ty:tt) => {{
-        let key = $parse_fn(&*$key.inner).map_err(|e| {
-            ArtiNativeKeystoreError::SshKeyParse {
-                // TODO: rust thinks this clone is necessary because key.path is also used below (but
-                // if we get to this point, we're going to return an error and never reach the other
-                // error handling branches where we use key.path).
-                path: $key.path.clone(),
-                key_type: $key_type.clone().clone(),
-                err: e.into(),
-            }
-        })?;
+/// Whether a key is stored as an OpenSSH private key, or as an OpenSSH public key.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum SshKeyKind {
+    /// An OpenSSH private key (i.e. a keypair).
+    Private,
+    /// An OpenSSH public key.
+    Public,
+}
 
-        let wanted_key_algo = $key_type.ssh_algorithm()?;
+impl SshKeyAlgorithm {
+    /// Convert the [`KeypairData`] of an OpenSSH private key that uses this algorithm to the
+    /// keypair type we use internally.
+    ///
+    /// Returns `None` if `key` is not a keypair of this algorithm.
+    fn convert_keypair(&self, key: &KeypairData) -> Option<Result<ErasedKey>> {
+        let key: Result<ErasedKey> = match (self, key) {
+            (SshKeyAlgorithm::Ed25519, KeypairData::Ed25519(key)) => {
+                convert_ed25519_kp(key).map(|k| Box::new(k) as ErasedKey)
+            }
+            (SshKeyAlgorithm::X25519, KeypairData::Other(key)) => {
+                convert_x25519_kp(key).map(|k| Box::new(k) as ErasedKey)
+            }
+            (SshKeyAlgorithm::Ed25519Expanded, KeypairData::Other(key)) => {
+                convert_expanded_ed25519_kp(key).map(|k| Box::new(k) as ErasedKey)
+            }
+            _ => return None,
+        };
 
-        if SshKeyAlgorithm::from(key.algorithm()) != wanted_key_algo {
-            return Err(ArtiNativeKeystoreError::UnexpectedSshKeyType {
-                path: $key.path,
-                wanted_key_algo,
-                found_key_algo: key.algorithm().into(),
-            }.into());
-        }
+        Some(key)
+    }
 
-        // Build the expected key type (i.e. convert ssh_key key types to the key types
-        // we're using internally).
-        match key.key_data() {
-            $key_data_ty::Ed25519(key) => Ok($ed25519_fn(key).map(Box::new)?),
-            $key_data_ty::Other(other) => {
-                match SshKeyAlgorithm::from(key.algorithm()) {
-                    SshKeyAlgorithm::X25519 => Ok($x25519_fn(other).map(Box::new)?),
-                    SshKeyAlgorithm::Ed25519Expanded => Ok($expanded_ed25519_fn(other).map(Box::new)?),
-                    _ => {
-                        Err(ArtiNativeKeystoreError::UnexpectedSshKeyType {
-                            path: $key.path,
-                            wanted_key_algo,
-                            found_key_algo: key.algorithm().into(),
-                        }.into())
-                    }
-                }
+    /// Convert the [`KeyData`] of an OpenSSH public key that uses this algorithm to the
+    /// public key type we use internally.
+    ///
+    /// Returns `None` if `key` is not a public key of this algorithm.
+    fn convert_public(&self, key: &KeyData) -> Option<Result<ErasedKey>> {
+        let key: Result<ErasedKey> = match (self, key) {
+            (SshKeyAlgorithm::Ed25519, KeyData::Ed25519(key)) => {
+                convert_ed25519_pk(key).map(|k| Box::new(k) as ErasedKey)
             }
-            _ => Err(ArtiNativeKeystoreError::UnexpectedSshKeyType {
-                path: $key.path,
-                wanted_key_algo,
-                found_key_algo: key.algorithm().into(),
-            }.into())
-        }
-    }};
+            (SshKeyAlgorithm::X25519, KeyData::Other(key)) => {
+                convert_x25519_pk(key).map(|k| Box::new(k) as ErasedKey)
+            }
+            _ => return None,
+        };
+
+        Some(key)
+    }
 }
 
 /// Try to convert an [`Ed25519Keypair`](ssh_key::private::Ed25519Keypair) to an [`ed25519::Keypair`].
@@ -231,20 +206,6 @@ fn convert_ed25519_pk(key: &ssh_key::public::Ed25519PublicKey) -> Result<ed25519
     })?)
 }
 
-/// Try to convert an [`OpaquePublicKey`](ssh_key::public::OpaquePublicKey) to an [`ed25519::PublicKey`].
-///
-/// This function always returns an error because the custom `ed25519-expanded@spec.torproject.org`
-/// SSH algorithm should not be used for ed25519 public keys (only for expanded ed25519 key
-/// _pairs_). This function is needed for the [`parse_openssh!`] macro.
-fn convert_expanded_ed25519_pk(
-    _key: &ssh_key::public::OpaquePublicKey,
-) -> Result<ed25519::PublicKey> {
-    Err(ArtiNativeKeystoreError::InvalidSshKeyData(
-        "invalid ed25519 public key (ed25519 public keys should be stored as ssh-ed25519)".into(),
-    )
-    .into())
-}
-
 /// Try to convert an [`OpaquePublicKey`](ssh_key::public::OpaquePublicKey) to a [`curve25519::PublicKey`].
 fn convert_x25519_pk(key: &ssh_key::public::OpaquePublicKey) -> Result<curve25519::PublicKey> {
     let public: [u8; 32] = key.as_ref().try_into().map_err(|_| {
@@ -255,12 +216,20 @@ fn convert_x25519_pk(key: &ssh_key::public::OpaquePublicKey) -> Result<curve2551
 }
 
 impl KeyType {
-    /// Get the algorithm of this key type.
-    pub(crate) fn ssh_algorithm(&self) -> Result<SshKeyAlgorithm> {
+    /// Get the algorithm of this key type, and whether it is stored as a private or public
+    /// OpenSSH key.
+    ///
+    /// To support a new key type, add it here, and, if it uses a new algorithm, teach
+    /// [`SshKeyAlgorithm`] how to convert it.
+    fn ssh_format(&self) -> Result<(SshKeyAlgorithm, SshKeyKind)> {
+        use SshKeyKind::*;
+
         match self {
-            KeyType::Ed25519Keypair | KeyType::Ed25519PublicKey => Ok(SshKeyAlgorithm::Ed25519),
-            KeyType::X25519StaticKeypair | KeyType::X25519PublicKey => Ok(SshKeyAlgorithm::X25519),
-            KeyType::Ed25519ExpandedKeypair => Ok(SshKeyAlgorithm::Ed25519Expanded),
+            KeyType::Ed25519Keypair => Ok((SshKeyAlgorithm::Ed25519, Private)),
+            KeyType::Ed25519PublicKey => Ok((SshKeyAlgorithm::Ed25519, Public)),
+            KeyType::X25519StaticKeypair => Ok((SshKeyAlgorithm::X25519, Private)),
+            KeyType::X25519PublicKey => Ok((SshKeyAlgorithm::X25519, Public)),
+            KeyType::Ed25519ExpandedKeypair => Ok((SshKeyAlgorithm::Ed25519Expanded, Private)),
             KeyType::Unknown { arti_extension } => Err(ArtiNativeKeystoreError::UnknownKeyType(
                 UnknownKeyTypeError {
                     arti_extension: arti_extension.clone(),
@@ -276,21 +245,35 @@ impl KeyType {
     /// The caller is expected to downcast the value returned to a concrete type.
     pub(crate) fn parse_ssh_format_erased(&self, key: UnparsedOpenSshKey) -> Result<ErasedKey> {
         // TODO HSS: perhaps this needs to be a method on EncodableKey instead?
-
-        match &self {
-            KeyType::Ed25519Keypair
-            | KeyType::X25519StaticKeypair
-            | KeyType::Ed25519ExpandedKeypair => {
-                parse_openssh!(PRIVATE key, self)
+        let (wanted_key_algo, kind) = self.ssh_format()?;
+
+        let parse_err = |e: ssh_key::Error| ArtiNativeKeystoreError::SshKeyParse {
+            path: key.path.clone(),
+            key_type: self.clone(),
+            err: e.into(),
+        };
+
+        let (found_key_algo, converted) = match kind {
+            SshKeyKind::Private => {
+                let parsed = PrivateKey::from_openssh(&*key.inner).map_err(parse_err)?;
+                let converted = wanted_key_algo.convert_keypair(parsed.key_data());
+                (parsed.algorithm(), converted)
             }
-            KeyType::Ed25519PublicKey | KeyType::X25519PublicKey => {
-                parse_openssh!(PUBLIC key, self)
+            SshKeyKind::Public => {
+                let parsed = PublicKey::from_openssh(&key.inner).map_err(parse_err)?;
+                let converted = wanted_key_algo.convert_public(parsed.key_data());
+                (parsed.algorithm(), converted)
+            }
+        };
+
+        let found_key_algo = SshKeyAlgorithm::from(found_key_algo);
+        match converted {
+            Some(converted) if found_key_algo == wanted_key_algo => converted,
+            _ => Err(ArtiNativeKeystoreError::UnexpectedSshKeyType {
+                path: key.path,
+                wanted_key_algo,
+                found_key_algo,
             }
-            KeyType::Unknown { arti_extension } => Err(ArtiNativeKeystoreError::UnknownKeyType(
-                UnknownKeyTypeError {
-                    arti_extension: arti_extension.clone(),
-                },
-            )
             .into()),
         }
     }
@@ -456,4 +439,37 @@ mod tests {
             err = "Unexpected OpenSSH key type: wanted X25519, found armadillo@torproject.org"
         );
     }
+
+    #[test]
+    fn mismatched_x25519_ed25519_keys() {
+        test_parse_ssh_format_erased!(
+            Ed25519Keypair,
+            OPENSSH_X25519,
+            err = "Unexpected OpenSSH key type: wanted Ed25519, found X25519"
+        );
+
+        test_parse_ssh_format_erased!(
+            Ed25519PublicKey,
+            OPENSSH_X25519_PUB,
+            err = "Unexpected OpenSSH key type: wanted Ed25519, found X25519"
+        );
+
+        test_parse_ssh_format_erased!(
+            X25519StaticKeypair,
+            OPENSSH_ED25519,
+            err = "Unexpected OpenSSH key type: wanted X25519, found Ed25519"
+        );
+
+        test_parse_ssh_format_erased!(
+            X25519PublicKey,
+            OPENSSH_ED25519_PUB,
+            err = "Unexpected OpenSSH key type: wanted X25519, found Ed25519"
+        );
+
+        test_parse_ssh_format_erased!(
+            Ed25519ExpandedKeypair,
+            OPENSSH_X25519,
+            err = "Unexpected OpenSSH key type: wanted Ed25519Expanded, found X25519"
+        );
+    }
 }
@@ -11,8 +11,9 @@ use ssh_key::public::{Ed25519PublicKey, KeyData, OpaquePublicKey};
 use ssh_key::{Algorithm, AlgorithmName};
 use tor_error::internal;
 use tor_hscrypto::pk::{
-    HsBlindIdKey, HsBlindIdKeypair, HsClientDescEncKeypair, HsDescSigningKeypair, HsIdKey,
-    HsIdKeypair, HsIntroPtSessionIdKeypair, HsSvcNtorKeypair,
+    HsBlindIdKey, HsBlindIdKeypair, HsClientDescEncKey, HsClientDescEncKeypair,
+    HsDescSigningKeypair, HsIdKey, HsIdKeypair, HsIntroPtSessionIdKey, HsIntroPtSessionIdKeypair,
+    HsSvcNtorKey, HsSvcNtorKeypair,
 };
 use tor_llcrypto::pk::{curve25519, ed25519};
 
@@ -291,6 +292,50 @@ pub trait ToEncodableKey {
     fn from_encodable_key(key: Self::Key) -> Self;
 }
 
+/// A keypair that can be checked against a known public key.
+///
+/// Used by [`KeyMgr::get_checked`](crate::KeyMgr::get_checked).
+pub trait HasPublicKey {
+    /// The type of the public key.
+    type PublicKey;
+
+    /// Return `true` if `public` is the public part of this keypair.
+    fn has_public_key(&self, public: &Self::PublicKey) -> bool;
+}
+
+impl HasPublicKey for HsIdKeypair {
+    type PublicKey = HsIdKey;
+
+    fn has_public_key(&self, public: &HsIdKey) -> bool {
+        *HsIdKey::from(self) == **public
+    }
+}
+
+impl HasPublicKey for HsClientDescEncKeypair {
+    type PublicKey = HsClientDescEncKey;
+
+    fn has_public_key(&self, public: &HsClientDescEncKey) -> bool {
+        **self.public() == **public
+    }
+}
+
+impl HasPublicKey for HsIntroPtSessionIdKeypair {
+    type PublicKey = HsIntroPtSessionIdKey;
+
+    fn has_public_key(&self, public: &HsIntroPtSessionIdKey) -> bool {
+        let keypair: &ed25519::Keypair = self.as_ref();
+        keypair.verifying_key() == **public
+    }
+}
+
+impl HasPublicKey for HsSvcNtorKeypair {
+    type PublicKey = HsSvcNtorKey;
+
+    fn has_public_key(&self, public: &HsSvcNtorKey) -> bool {
+        **self.public() == **public
+    }
+}
+
 impl ToEncodableKey for HsClientDescEncKeypair {
     type Key = curve25519::StaticKeypair;
 
@@ -303,6 +348,18 @@ impl ToEncodableKey for HsClientDescEncKeypair {
     }
 }
 
+impl ToEncodableKey for HsClientDescEncKey {
+    type Key = curve25519::PublicKey;
+
+    fn to_encodable_key(self) -> Self::Key {
+        self.into()
+    }
+
+    fn from_encodable_key(key: Self::Key) -> Self {
+        HsClientDescEncKey::from(key)
+    }
+}
+
 impl ToEncodableKey for HsBlindIdKeypair {
     type Key = ed25519::ExpandedKeypair;
 
@@ -302,14 +302,16 @@ mod tests {
     #![allow(clippy::needless_pass_by_value)]
     //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
     use super::*;
-    use crate::{ArtiPath, CTorPath, KeyPath};
+    use crate::{ArtiPath, CTorPath, KeyPath, ToEncodableKey};
     use std::fs;
     use tempfile::{tempdir, TempDir};
-    use tor_llcrypto::pk::ed25519;
+    use tor_hscrypto::pk::HsClientDescEncKey;
+    use tor_llcrypto::pk::{curve25519, ed25519};
 
     // TODO HS TEST: this is included twice in the binary (refactor the test utils so that we only
     // include it once)
     const OPENSSH_ED25519: &str = include_str!("../../testdata/ed25519_openssh.private");
+    const OPENSSH_X25519: &str = include_str!("../../testdata/x25519_openssh.private");
 
     const TEST_SPECIFIER_PATH: &str = "parent1/parent2/parent3/test-specifier";
 
@@ -609,4 +611,51 @@ mod tests {
             key_store.list().unwrap()
         );
     }
+
+    #[test]
+    fn client_desc_enc_key() {
+        let (key_store, _keystore_dir) = init_keystore(false);
+
+        let key_spec = TestSpecifier::default();
+        let key_type = curve25519::PublicKey::key_type();
+        let raw = [7_u8; 32];
+        let key = HsClientDescEncKey::from(curve25519::PublicKey::from(raw));
+
+        assert_found!(key_store, &key_spec, &key_type, false);
+        key_store
+            .insert(&key.clone().to_encodable_key(), &key_spec, &key_type)
+            .unwrap();
+
+        assert!(key_path(&key_store, &key_type)
+            .to_string_lossy()
+            .ends_with("test-specifier.x25519_public"));
+
+        let erased_key = key_store.get(&key_spec, &key_type).unwrap().unwrap();
+        let Ok(found) = erased_key.downcast::<curve25519::PublicKey>() else {
+            panic!("failed to downcast key to curve25519::PublicKey")
+        };
+        let found = HsClientDescEncKey::from_encodable_key(*found);
+
+        assert_eq!(found, key);
+        assert_eq!(found.to_bytes(), raw);
+    }
+
+    #[test]
+    fn get_wrong_key_type() {
+        let (key_store, _keystore_dir) = init_keystore(true);
+
+        // Overwrite the ed25519 keypair with an x25519 one
+        let ed_key_type = KeyType::Ed25519Keypair;
+        fs::write(key_path(&key_store, &ed_key_type), OPENSSH_X25519).unwrap();
+
+        let err = key_store
+            .get(&TestSpecifier::default(), &ed_key_type)
+            .map(|_| "<type erased key>")
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Unexpected OpenSSH key type: wanted Ed25519, found X25519"
+        );
+    }
 }
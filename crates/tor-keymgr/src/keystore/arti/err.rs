@@ -117,7 +117,11 @@ pub(crate) enum MalformedPathError {
     InvalidArtiPath(ArtiPathSyntaxError),
 }
 
-impl KeystoreError for ArtiNativeKeystoreError {}
+impl KeystoreError for ArtiNativeKeystoreError {
+    fn is_key_type_mismatch(&self) -> bool {
+        matches!(self, ArtiNativeKeystoreError::UnexpectedSshKeyType { .. })
+    }
+}
 
 impl HasKind for ArtiNativeKeystoreError {
     fn kind(&self) -> ErrorKind {
@@ -56,6 +56,8 @@ mod key_type;
 mod keystore;
 #[cfg(feature = "keymgr")]
 mod mgr;
+#[cfg(feature = "keymgr")]
+mod self_check;
 
 #[cfg(not(feature = "keymgr"))]
 mod dummy;
@@ -73,8 +75,12 @@ pub use key_specifier::{
 pub use {
     key_type::{KeyType, UnknownKeyTypeError},
     keystore::arti::ArtiNativeKeystore,
-    keystore::{EncodableKey, ErasedKey, Keygen, KeygenRng, Keystore, SshKeyData, ToEncodableKey},
+    keystore::{
+        EncodableKey, ErasedKey, HasPublicKey, Keygen, KeygenRng, Keystore, SshKeyData,
+        ToEncodableKey,
+    },
     mgr::{KeyMgr, KeyMgrBuilder},
+    self_check::{KeyCheck, KeyStatus, SelfCheckReport},
     ssh_key,
 };
 
@@ -3,9 +3,9 @@
 //! See the [`KeyMgr`] docs for more details.
 
 use crate::{
-    BoxedKeystore, EncodableKey, KeyInfoExtractor, KeyPath, KeyPathError, KeyPathInfo,
-    KeyPathPattern, KeySpecifier, KeyType, Keygen, KeygenRng, KeystoreId, KeystoreSelector, Result,
-    ToEncodableKey,
+    BoxedKeystore, EncodableKey, Error, HasPublicKey, KeyCheck, KeyInfoExtractor, KeyPath,
+    KeyPathError, KeyPathInfo, KeyPathPattern, KeySpecifier, KeyStatus, KeyType, Keygen, KeygenRng,
+    KeystoreCorruptionError, KeystoreId, KeystoreSelector, Result, SelfCheckReport, ToEncodableKey,
 };
 
 use itertools::Itertools;
@@ -117,6 +117,32 @@ impl KeyMgr {
         self.get_from_store(key_spec, &K::Key::key_type(), self.all_stores())
     }
 
+    /// Read a keypair from one of the key stores, and check that it matches `expected_public`.
+    ///
+    /// This is like [`KeyMgr::get`], except that it checks that the keypair it finds is the
+    /// counterpart of a public key we already know (for example, one we have advertised).
+    ///
+    /// Returns `Ok(None)` if none of the key stores have the requested key.
+    ///
+    /// Returns a [`KeystoreCorruptionError::PublicKeyMismatch`] error
+    /// if the key exists, but it does not match `expected_public`.
+    pub fn get_checked<K>(
+        &self,
+        key_spec: &dyn KeySpecifier,
+        expected_public: &K::PublicKey,
+    ) -> Result<Option<K>>
+    where
+        K: ToEncodableKey + HasPublicKey,
+    {
+        let key: Option<K> = self.get(key_spec)?;
+        match key {
+            Some(key) if !key.has_public_key(expected_public) => {
+                Err(KeystoreCorruptionError::PublicKeyMismatch.into())
+            }
+            key => Ok(key),
+        }
+    }
+
     /// Read a key from one of the key stores, and try to deserialize it as `K::Key`.
     ///
     /// The key returned is retrieved from the first key store that contains an entry for the given
@@ -416,6 +442,37 @@ impl KeyMgr {
             .collect::<Result<Vec<_>>>()
     }
 
+    /// Check the integrity of every key in every keystore.
+    ///
+    /// Each key listed by each keystore is read back and parsed. The returned
+    /// [`SelfCheckReport`] says which keys are fine, which disappeared after being listed,
+    /// which could not be read or parsed, and which contain a key of the wrong algorithm
+    /// for their [`KeyType`].
+    ///
+    /// This function does not modify any of the keystores.
+    ///
+    /// Returns an error only if one of the keystores could not be listed at all.
+    pub fn self_check(&self) -> Result<SelfCheckReport> {
+        let mut keys = vec![];
+
+        for store in self.all_stores() {
+            for (path, key_type) in store.list()? {
+                let status = match store.get(&path, &key_type) {
+                    Ok(Some(_)) => KeyStatus::Ok,
+                    Ok(None) => KeyStatus::Missing,
+                    Err(Error::Keystore(e)) if e.is_key_type_mismatch() => {
+                        KeyStatus::AlgorithmMismatch(Error::Keystore(e))
+                    }
+                    Err(e) => KeyStatus::Unreadable(e),
+                };
+
+                keys.push(KeyCheck::new(store.id().clone(), path, key_type, status));
+            }
+        }
+
+        Ok(SelfCheckReport::new(keys))
+    }
+
     /// Describe the specified key.
     ///
     /// Returns [`KeyPathError::Unrecognized`] if none of the registered
@@ -522,6 +579,7 @@ mod tests {
     #![allow(clippy::needless_pass_by_value)]
     //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
     use super::*;
+    use crate::test_utils::HookedKeystore;
     use crate::{ArtiPath, ArtiPathUnavailableError, ErasedKey, KeyPath, KeyType, SshKeyData};
     use std::collections::HashMap;
     use std::result::Result as StdResult;
@@ -529,6 +587,9 @@ mod tests {
     use std::sync::RwLock;
     use tor_basic_utils::test_rng::testing_rng;
 
+    const OPENSSH_ED25519: &str = include_str!("../testdata/ed25519_openssh.private");
+    const OPENSSH_X25519: &str = include_str!("../testdata/x25519_openssh.private");
+
     /// The type of "key" stored in the test key stores.
     type TestKey = String;
 
@@ -573,6 +634,15 @@ mod tests {
         }
     }
 
+    impl HasPublicKey for TestKey {
+        type PublicKey = TestPublicKey;
+
+        fn has_public_key(&self, public: &TestPublicKey) -> bool {
+            // Our fake keys are their own public keys
+            self == public
+        }
+    }
+
     macro_rules! impl_keystore {
         ($name:tt, $id:expr) => {
             struct $name {
@@ -916,6 +986,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn get_checked() {
+        let mut builder = KeyMgrBuilder::default().default_store(Box::<Keystore1>::default());
+
+        builder.secondary_stores().extend([Keystore2::new_boxed()]);
+
+        let mgr = builder.build().unwrap();
+
+        mgr.insert(
+            "coot".to_string(),
+            &TestKeySpecifier1,
+            KeystoreSelector::Id(&KeystoreId::from_str("keystore2").unwrap()),
+        )
+        .unwrap();
+
+        // The stored key matches
+        assert_eq!(
+            mgr.get_checked::<TestKey>(&TestKeySpecifier1, &"keystore2_coot".to_string())
+                .unwrap(),
+            Some("keystore2_coot".to_string())
+        );
+
+        // The key doesn't exist, so there is nothing to check
+        assert!(mgr
+            .get_checked::<TestKey>(&TestKeySpecifier2, &"keystore2_coot".to_string())
+            .unwrap()
+            .is_none());
+
+        // The stored key has been swapped for a different one
+        mgr.insert(
+            "gull".to_string(),
+            &TestKeySpecifier1,
+            KeystoreSelector::Id(&KeystoreId::from_str("keystore2").unwrap()),
+        )
+        .unwrap();
+        let err = mgr
+            .get_checked::<TestKey>(&TestKeySpecifier1, &"keystore2_coot".to_string())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            crate::Error::Corruption(KeystoreCorruptionError::PublicKeyMismatch)
+        ));
+        assert_eq!(
+            tor_error::HasKind::kind(&err),
+            tor_error::ErrorKind::KeystoreCorrupted
+        );
+    }
+
     #[test]
     fn get_or_generate() {
         let mut builder = KeyMgrBuilder::default().default_store(Box::<Keystore1>::default());
@@ -981,4 +1099,78 @@ mod tests {
             "keystore1_rock_dove".to_string()
         );
     }
+
+    #[test]
+    fn self_check() {
+        let keystore_dir = tempfile::tempdir().unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&keystore_dir, std::fs::Permissions::from_mode(0o700))
+                .unwrap();
+        }
+
+        let write_key = |name: &str, contents: &str| {
+            std::fs::write(keystore_dir.path().join(name), contents).unwrap();
+        };
+        write_key("valid.ed25519_private", OPENSSH_ED25519);
+        write_key(
+            "corrupted.ed25519_private",
+            &OPENSSH_ED25519[..OPENSSH_ED25519.len() / 2],
+        );
+        write_key("mismatched.ed25519_private", OPENSSH_X25519);
+        write_key("unknown.rsa_private", OPENSSH_ED25519);
+
+        let store = crate::ArtiNativeKeystore::from_path_and_mistrust(
+            &keystore_dir,
+            &fs_mistrust::Mistrust::default(),
+        )
+        .unwrap();
+        let mgr = KeyMgrBuilder::default()
+            .default_store(Box::new(HookedKeystore::new(store).on_list(|keys| {
+                // A key that is listed, but isn't actually there.
+                keys.push((
+                    ArtiPath::new("phantom".into()).unwrap().into(),
+                    KeyType::Ed25519Keypair,
+                ));
+            })))
+            .build()
+            .unwrap();
+
+        let report = mgr.self_check().unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.keys().len(), 5);
+        assert_eq!(report.problems().count(), 4);
+
+        let status_of = |name: &str| {
+            let path = KeyPath::Arti(ArtiPath::new(name.into()).unwrap());
+            report
+                .keys()
+                .iter()
+                .find(|key| key.path() == &path)
+                .unwrap()
+                .status()
+                .clone()
+        };
+
+        assert!(matches!(status_of("valid"), KeyStatus::Ok));
+        assert!(matches!(status_of("phantom"), KeyStatus::Missing));
+        assert!(matches!(status_of("corrupted"), KeyStatus::Unreadable(_)));
+        assert!(matches!(status_of("unknown"), KeyStatus::Unreadable(_)));
+        assert!(matches!(
+            status_of("mismatched"),
+            KeyStatus::AlgorithmMismatch(_)
+        ));
+
+        // The check didn't touch any of the keys
+        assert!(keystore_dir
+            .path()
+            .join("corrupted.ed25519_private")
+            .exists());
+        assert!(keystore_dir
+            .path()
+            .join("mismatched.ed25519_private")
+            .exists());
+    }
 }
@@ -0,0 +1,104 @@
+//! The report returned by [`KeyMgr::self_check`](crate::KeyMgr::self_check).
+
+use crate::{Error, KeyPath, KeyType, KeystoreId};
+
+/// The outcome of checking a single stored key.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum KeyStatus {
+    /// The key was read and parsed successfully.
+    Ok,
+    /// The key was listed by its keystore, but could not be found when we tried to read it.
+    Missing,
+    /// The key could not be read or parsed.
+    Unreadable(Error),
+    /// The key was parsed, but its algorithm does not match the one expected
+    /// for its [`KeyType`].
+    AlgorithmMismatch(Error),
+}
+
+/// The result of checking a single stored key.
+#[derive(Debug, Clone)]
+pub struct KeyCheck {
+    /// The keystore the key is stored in.
+    keystore: KeystoreId,
+    /// The path of the key.
+    path: KeyPath,
+    /// The type of the key, as recorded by its keystore.
+    key_type: KeyType,
+    /// The outcome of the check.
+    status: KeyStatus,
+}
+
+impl KeyCheck {
+    /// Create a new [`KeyCheck`].
+    pub(crate) fn new(
+        keystore: KeystoreId,
+        path: KeyPath,
+        key_type: KeyType,
+        status: KeyStatus,
+    ) -> Self {
+        Self {
+            keystore,
+            path,
+            key_type,
+            status,
+        }
+    }
+
+    /// The keystore the key is stored in.
+    pub fn keystore(&self) -> &KeystoreId {
+        &self.keystore
+    }
+
+    /// The path of the key.
+    pub fn path(&self) -> &KeyPath {
+        &self.path
+    }
+
+    /// The type of the key, as recorded by its keystore.
+    pub fn key_type(&self) -> &KeyType {
+        &self.key_type
+    }
+
+    /// The outcome of the check.
+    pub fn status(&self) -> &KeyStatus {
+        &self.status
+    }
+
+    /// Whether the key was read and parsed successfully.
+    pub fn is_ok(&self) -> bool {
+        matches!(self.status, KeyStatus::Ok)
+    }
+}
+
+/// A report describing the state of every key in a [`KeyMgr`](crate::KeyMgr)'s keystores.
+///
+/// See [`KeyMgr::self_check`](crate::KeyMgr::self_check).
+#[derive(Debug, Clone, Default)]
+pub struct SelfCheckReport {
+    /// The result of checking each key, in the order the keystores listed them.
+    keys: Vec<KeyCheck>,
+}
+
+impl SelfCheckReport {
+    /// Create a new [`SelfCheckReport`] from the results of checking each key.
+    pub(crate) fn new(keys: Vec<KeyCheck>) -> Self {
+        Self { keys }
+    }
+
+    /// The result of checking each key.
+    pub fn keys(&self) -> &[KeyCheck] {
+        &self.keys
+    }
+
+    /// The keys that could not be read successfully.
+    pub fn problems(&self) -> impl Iterator<Item = &KeyCheck> {
+        self.keys.iter().filter(|key| !key.is_ok())
+    }
+
+    /// Whether every key was read and parsed successfully.
+    pub fn is_ok(&self) -> bool {
+        self.problems().next().is_none()
+    }
+}
@@ -17,6 +17,9 @@ use std::fmt::Debug;
 
 use crate::{ArtiPath, KeyPath, KeySpecifier};
 
+#[cfg(feature = "keymgr")]
+use crate::{EncodableKey, ErasedKey, KeyType, Keystore, KeystoreId, Result};
+
 /// Check that `spec` produces the [`ArtiPath`] from `path`, and that `path` parses to `spec`
 ///
 /// # Panics
@@ -32,3 +35,110 @@ where
     assert_eq!(spec.arti_path().unwrap(), apath);
     assert_eq!(&S::try_from(&KeyPath::Arti(apath)).unwrap(), spec, "{path}");
 }
+
+/// A hook run by [`HookedKeystore::get`] before it consults the inner keystore.
+///
+/// If the hook returns an error, `get` returns it instead.
+#[cfg(feature = "keymgr")]
+pub type GetHook = Box<dyn Fn(&dyn KeySpecifier, &KeyType) -> Result<()> + Send + Sync>;
+
+/// A hook run by [`HookedKeystore::list`] on the entries listed by the inner keystore.
+#[cfg(feature = "keymgr")]
+pub type ListHook = Box<dyn Fn(&mut Vec<(KeyPath, KeyType)>) + Send + Sync>;
+
+/// A [`Keystore`] that forwards every call to an inner keystore,
+/// except where a test has overridden its behaviour.
+///
+/// By default, this behaves exactly like the inner keystore.
+/// Use [`with_id`](Self::with_id), [`on_get`](Self::on_get) and [`on_list`](Self::on_list)
+/// to change that.
+#[cfg(feature = "keymgr")]
+pub struct HookedKeystore {
+    /// The underlying keystore.
+    inner: Box<dyn Keystore>,
+    /// The ID to report instead of the inner keystore's, if any.
+    id: Option<KeystoreId>,
+    /// The hook to run on each call to `get`, if any.
+    get_hook: Option<GetHook>,
+    /// The hook to run on each call to `list`, if any.
+    list_hook: Option<ListHook>,
+}
+
+#[cfg(feature = "keymgr")]
+impl HookedKeystore {
+    /// Wrap `inner`, without overriding any of its behaviour.
+    pub fn new(inner: impl Keystore) -> Self {
+        Self {
+            inner: Box::new(inner),
+            id: None,
+            get_hook: None,
+            list_hook: None,
+        }
+    }
+
+    /// Report `id` as the ID of this keystore.
+    ///
+    /// All `ArtiNativeKeystore`s have the same ID, so this is needed to
+    /// build a `KeyMgr` with more than one of them.
+    pub fn with_id(mut self, id: KeystoreId) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Run `hook` on each call to `get`, before consulting the inner keystore.
+    pub fn on_get(
+        mut self,
+        hook: impl Fn(&dyn KeySpecifier, &KeyType) -> Result<()> + Send + Sync + 'static,
+    ) -> Self {
+        self.get_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Run `hook` on the entries listed by the inner keystore, on each call to `list`.
+    pub fn on_list(
+        mut self,
+        hook: impl Fn(&mut Vec<(KeyPath, KeyType)>) + Send + Sync + 'static,
+    ) -> Self {
+        self.list_hook = Some(Box::new(hook));
+        self
+    }
+}
+
+#[cfg(feature = "keymgr")]
+impl Keystore for HookedKeystore {
+    fn id(&self) -> &KeystoreId {
+        self.id.as_ref().unwrap_or_else(|| self.inner.id())
+    }
+
+    fn contains(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<bool> {
+        self.inner.contains(key_spec, key_type)
+    }
+
+    fn get(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<Option<ErasedKey>> {
+        if let Some(hook) = &self.get_hook {
+            hook(key_spec, key_type)?;
+        }
+        self.inner.get(key_spec, key_type)
+    }
+
+    fn insert(
+        &self,
+        key: &dyn EncodableKey,
+        key_spec: &dyn KeySpecifier,
+        key_type: &KeyType,
+    ) -> Result<()> {
+        self.inner.insert(key, key_spec, key_type)
+    }
+
+    fn remove(&self, key_spec: &dyn KeySpecifier, key_type: &KeyType) -> Result<Option<()>> {
+        self.inner.remove(key_spec, key_type)
+    }
+
+    fn list(&self) -> Result<Vec<(KeyPath, KeyType)>> {
+        let mut keys = self.inner.list()?;
+        if let Some(hook) = &self.list_hook {
+            hook(&mut keys);
+        }
+        Ok(keys)
+    }
+}
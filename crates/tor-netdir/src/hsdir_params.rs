@@ -498,4 +498,40 @@ mod test {
         );
         assert_eq!(secondary[1].shared_rand.as_ref(), &SRV2);
     }
+
+    #[test]
+    #[cfg(feature = "hs-service")]
+    fn ring_params_overlapping_periods() {
+        // For each consensus, enumerating the time periods within one period length
+        // of valid-after finds exactly the time periods we have ring parameters for.
+        let simple = example_consensus_builder().testing_consensus().unwrap();
+        let tricky = example_consensus_builder()
+            .shared_rand_prev(7, SRV1.into(), Some(t("1985-10-25T00:00:00Z")))
+            .shared_rand_cur(7, SRV2.into(), Some(t("1985-10-25T05:00:00Z")))
+            .param("hsdir_interval", 120) // 2 hours
+            .testing_consensus()
+            .unwrap();
+
+        for (consensus, length, start) in [
+            // There is no SRV for the previous time period, so it has no ring.
+            (simple, d("1 day"), t("1985-10-25T07:00:00Z")),
+            (tricky, d("2 hours"), t("1985-10-25T05:00:00Z")),
+        ] {
+            let netparams = NetParameters::from_map(consensus.params());
+            let HsDirs { current, secondary } =
+                HsDirParams::compute(&consensus, &netparams).unwrap();
+            let mut periods = std::iter::once(&current)
+                .chain(secondary.iter())
+                .map(|params| params.time_period)
+                .collect::<Vec<_>>();
+            periods.sort_by_key(|period| period.interval_num());
+
+            let valid_after = consensus.lifetime().valid_after();
+            let overlapping =
+                TimePeriod::overlapping(length, d("12 hours"), start..valid_after + length)
+                    .unwrap()
+                    .collect::<Vec<_>>();
+            assert_eq!(periods, overlapping);
+        }
+    }
 }
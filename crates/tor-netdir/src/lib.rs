@@ -2741,4 +2741,48 @@ mod test {
         // If we use relays [A, B, C] for replica 1, and hs_index(2) = E, then replica 2 _must_ get
         // relays [E, F, D]. We should have a test that checks this.
     }
+
+    #[test]
+    #[cfg(feature = "hs-service")]
+    fn hs_all_time_periods_overlapping() {
+        // Our test network has no shared random values, so the only relevant time period
+        // is the one that contains the start of the consensus' validity period.
+        let one_hour = Duration::from_secs(60 * 60);
+        let one_day = one_hour * 24;
+        // 2023-06-01T12:00:00Z: with the default parameters, a time period begins here.
+        let boundary = std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1_685_620_800);
+
+        for (valid_after, hsdir_interval, length) in [
+            (boundary - Duration::from_secs(1), 1440, one_day),
+            (boundary, 1440, one_day),
+            (boundary + one_hour * 23, 1440, one_day),
+            (boundary + one_hour * 3, 120, one_hour * 2),
+        ] {
+            // A one-hour voting period, so that the time period offset is 12 hours.
+            let lifetime = netstatus::Lifetime::new(
+                valid_after,
+                valid_after + one_hour,
+                valid_after + one_hour * 3,
+            )
+            .unwrap();
+            let netdir = construct_custom_netdir_with_params(
+                simple_net_func,
+                [("hsdir_interval", hsdir_interval)],
+                Some(lifetime),
+            )
+            .unwrap()
+            .unwrap_if_sufficient()
+            .unwrap();
+
+            let overlapping = TimePeriod::overlapping(
+                length,
+                one_hour * 12,
+                valid_after..valid_after + Duration::from_secs(1),
+            )
+            .unwrap()
+            .collect::<Vec<_>>();
+            assert_eq!(netdir.hs_all_time_periods(), overlapping);
+            assert_eq!(overlapping, vec![netdir.hs_time_period()]);
+        }
+    }
 }
@@ -323,6 +323,13 @@ impl HsDesc {
         &self.intro_points
     }
 
+    /// The revision counter of this descriptor.
+    ///
+    /// Higher values supersede lower ones, within a time period.
+    pub fn revision_counter(&self) -> RevisionCounter {
+        self.idx_info.revision
+    }
+
     /// Return true if this onion service claims to be a non-anonymous "single
     /// onion service".
     ///
@@ -20,7 +20,12 @@ use once_cell::sync::Lazy;
 // of Arti can use this trait to add new methods to the RPC engine. Should we
 // care?
 #[typetag::deserialize(tag = "method", content = "params")]
-pub trait DynMethod: std::fmt::Debug + Send + Downcast {}
+pub trait DynMethod: std::fmt::Debug + Send + Downcast {
+    /// Return the name of this method, as used in requests.
+    ///
+    /// This is implemented for you by [`decl_method!`](crate::decl_method).
+    fn method_name(&self) -> &'static str;
+}
 downcast_rs::impl_downcast!(DynMethod);
 
 /// A typed method, used to ensure that all implementations of a method have the
@@ -95,7 +100,11 @@ macro_rules! decl_method {
         $(
             $crate::impl_const_type_id!{$id}
             #[typetag::deserialize(name = $name)]
-            impl $crate::DynMethod for $id {}
+            impl $crate::DynMethod for $id {
+                fn method_name(&self) -> &'static str {
+                    $name
+                }
+            }
             $crate::inventory::submit!{
                 $crate::MethodInfo_ { method_name : $name }
             }
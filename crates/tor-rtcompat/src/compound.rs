@@ -133,10 +133,28 @@ where
         self.inner.tcp.connect(addr).await
     }
 
+    #[inline]
+    async fn connect_from(
+        &self,
+        local: Option<SocketAddr>,
+        remote: &SocketAddr,
+    ) -> IoResult<Self::TcpStream> {
+        self.inner.tcp.connect_from(local, remote).await
+    }
+
     #[inline]
     async fn listen(&self, addr: &SocketAddr) -> IoResult<Self::TcpListener> {
         self.inner.tcp.listen(addr).await
     }
+
+    #[inline]
+    async fn listen_with_options(
+        &self,
+        addr: &SocketAddr,
+        options: &TcpListenOptions,
+    ) -> IoResult<Self::TcpListener> {
+        self.inner.tcp.listen_with_options(addr, options).await
+    }
 }
 
 impl<SpawnR, SleepR, TcpR, TlsR, UdpR, S> TlsProvider<S>
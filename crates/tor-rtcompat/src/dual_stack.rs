@@ -0,0 +1,79 @@
+//! Definitions for [`listen_dual_stack`] and related types.
+
+use crate::traits::{TcpListener, TcpProvider};
+use futures::stream::{self, SelectAll};
+use std::io::{ErrorKind, Result as IoResult};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Listeners for the same port, on IPv4 and IPv6
+///
+/// Returned by [`listen_dual_stack`].
+#[derive(Debug)]
+pub struct DualStackListener<L> {
+    /// The listeners
+    ///
+    /// Either one listener for each address family,
+    /// or a single listener that accepts connections from both (or only one of them).
+    listeners: Vec<L>,
+}
+
+/// The incoming TCP streams from all the listeners of a [`DualStackListener`], merged
+pub type DualStackIncoming<L> = SelectAll<<L as TcpListener>::Incoming>;
+
+impl<L: TcpListener> DualStackListener<L> {
+    /// Return the local addresses that we are bound to.
+    pub fn local_addrs(&self) -> IoResult<Vec<SocketAddr>> {
+        self.listeners.iter().map(TcpListener::local_addr).collect()
+    }
+
+    /// Wrap these listeners into a single [`Stream`](futures::stream::Stream)
+    /// that yields TCP streams and addresses, from any of them.
+    pub fn incoming(self) -> DualStackIncoming<L> {
+        stream::select_all(self.listeners.into_iter().map(TcpListener::incoming))
+    }
+}
+
+/// Listen on `port`, on all local IPv4 and IPv6 addresses.
+///
+/// We listen on `[::]:port`, and on `0.0.0.0:port`.
+/// On some platforms, a listener on `[::]` accepts IPv4 connections too
+/// (as IPv4-mapped IPv6 addresses), so that the second listener can't be bound.
+/// In that case, we just use the first.
+/// If this host doesn't support IPv6 at all, we just listen on IPv4.
+///
+/// If `port` is 0, the OS chooses a port, which is the same for both address families.
+pub async fn listen_dual_stack<R: TcpProvider>(
+    runtime: &R,
+    port: u16,
+) -> IoResult<DualStackListener<R::TcpListener>> {
+    let v6_addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
+    let v6 = match runtime.listen(&v6_addr).await {
+        Ok(lis) => Some(lis),
+        Err(e) if is_ipv6_unavailable(&e) => None,
+        Err(e) => return Err(e),
+    };
+
+    let port = match &v6 {
+        Some(lis) => lis.local_addr()?.port(),
+        None => port,
+    };
+    let v4_addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+    let v4 = match runtime.listen(&v4_addr).await {
+        Ok(lis) => Some(lis),
+        // The IPv6 listener is already accepting IPv4 connections.
+        Err(e) if e.kind() == ErrorKind::AddrInUse && v6.is_some() => None,
+        Err(e) => return Err(e),
+    };
+
+    Ok(DualStackListener {
+        listeners: v6.into_iter().chain(v4).collect(),
+    })
+}
+
+/// Return true if `e`, from trying to listen on IPv6, means that this host doesn't do IPv6
+fn is_ipv6_unavailable(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::AddrNotAvailable | ErrorKind::Unsupported
+    )
+}
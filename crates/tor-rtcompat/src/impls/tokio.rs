@@ -9,7 +9,8 @@ pub(crate) mod net {
     use async_trait::async_trait;
 
     pub(crate) use tokio_crate::net::{
-        TcpListener as TokioTcpListener, TcpStream as TokioTcpStream, UdpSocket as TokioUdpSocket,
+        TcpListener as TokioTcpListener, TcpSocket as TokioTcpSocket, TcpStream as TokioTcpStream,
+        UdpSocket as TokioUdpSocket,
     };
 
     use futures::io::{AsyncRead, AsyncWrite};
@@ -154,10 +155,65 @@ impl crate::traits::TcpProvider for TokioRuntimeHandle {
         let s = net::TokioTcpStream::connect(addr).await?;
         Ok(s.into())
     }
+    async fn connect_from(
+        &self,
+        local: Option<std::net::SocketAddr>,
+        remote: &std::net::SocketAddr,
+    ) -> IoResult<Self::TcpStream> {
+        let Some(local) = local else {
+            return self.connect(remote).await;
+        };
+        let socket = if remote.is_ipv4() {
+            net::TokioTcpSocket::new_v4()?
+        } else {
+            net::TokioTcpSocket::new_v6()?
+        };
+        socket.bind(local)?;
+        let s = socket.connect(*remote).await?;
+        Ok(s.into())
+    }
     async fn listen(&self, addr: &std::net::SocketAddr) -> IoResult<Self::TcpListener> {
         let lis = net::TokioTcpListener::bind(*addr).await?;
         Ok(net::TcpListener { lis })
     }
+    async fn listen_with_options(
+        &self,
+        addr: &std::net::SocketAddr,
+        options: &TcpListenOptions,
+    ) -> IoResult<Self::TcpListener> {
+        let socket = if addr.is_ipv4() {
+            net::TokioTcpSocket::new_v4()?
+        } else {
+            net::TokioTcpSocket::new_v6()?
+        };
+        // We only set the options that were asked for, leaving the others at
+        // the platform's defaults.
+        if options.reuse_address {
+            socket.set_reuseaddr(true)?;
+        }
+        if options.reuse_port {
+            set_reuseport(&socket)?;
+        }
+        socket.bind(*addr)?;
+        // This is the backlog that `TcpListener::bind` uses.
+        let lis = socket.listen(1024)?;
+        Ok(net::TcpListener { lis })
+    }
+}
+
+/// Set `SO_REUSEPORT` on `socket`
+#[cfg(all(unix, not(target_os = "solaris"), not(target_os = "illumos")))]
+fn set_reuseport(socket: &net::TokioTcpSocket) -> IoResult<()> {
+    socket.set_reuseport(true)
+}
+
+/// Set `SO_REUSEPORT` on `socket`: not supported on this platform
+#[cfg(not(all(unix, not(target_os = "solaris"), not(target_os = "illumos"))))]
+fn set_reuseport(_socket: &net::TokioTcpSocket) -> IoResult<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
 }
 
 #[async_trait]
@@ -48,19 +48,23 @@ pub(crate) mod impls;
 pub mod task;
 
 mod compound;
+mod dual_stack;
 mod opaque;
 pub mod scheduler;
+pub mod timeout_track;
 mod timer;
 mod traits;
 
 #[cfg(any(feature = "async-std", feature = "tokio"))]
 use std::io;
 pub use traits::{
-    BlockOn, CertifiedConn, Runtime, SleepProvider, TcpListener, TcpProvider, TlsProvider,
-    UdpProvider, UdpSocket,
+    BlockOn, CertifiedConn, Runtime, SleepProvider, TcpListenOptions, TcpListener, TcpProvider,
+    TlsProvider, UdpProvider, UdpSocket,
 };
 
-pub use timer::{SleepProviderExt, Timeout, TimeoutError};
+pub use timer::{Deadline, SleepProviderExt, Timeout, TimeoutError};
+
+pub use dual_stack::{listen_dual_stack, DualStackIncoming, DualStackListener};
 
 /// Traits used to describe TLS connections and objects that can
 /// create them.
@@ -342,7 +346,7 @@ mod test {
     use futures::stream::StreamExt;
     use native_tls_crate as native_tls;
     use std::io::Result as IoResult;
-    use std::net::{Ipv4Addr, SocketAddrV4};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4};
     use std::time::{Duration, Instant};
 
     // Test "sleep" with a tiny delay, and make sure that at least that
@@ -639,6 +643,60 @@ mod test {
         }
     }
 
+    // Listen on both IPv4 and IPv6, and connect to ourself over each.
+    //
+    // NOTE: requires Ipv4 and Ipv6 localhost.
+    fn dual_stack_listener<R: Runtime>(runtime: &R) -> IoResult<()> {
+        let rt1 = runtime.clone();
+
+        let listener = runtime.block_on(crate::listen_dual_stack(&rt1, 0))?;
+        let port = listener.local_addrs()?[0].port();
+        let mut stream = listener.incoming();
+
+        runtime.block_on(async {
+            let task1 = async {
+                let mut peers = vec![];
+                for _ in 0..2 {
+                    let (mut con, addr) = stream.next().await.unwrap()?;
+                    let mut buf = [0_u8; 2];
+                    con.read_exact(&mut buf[..]).await?;
+                    peers.push((addr.ip(), buf));
+                }
+                IoResult::Ok(peers)
+            };
+            let task2 = async {
+                let v4 = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+                let v6 = SocketAddr::from((Ipv6Addr::LOCALHOST, port));
+                for (addr, msg) in [(v4, b"v4"), (v6, b"v6")] {
+                    let mut con = rt1.connect(&addr).await?;
+                    con.write_all(msg).await?;
+                    con.flush().await?;
+                }
+                IoResult::Ok(())
+            };
+
+            let (peers, send_r) = futures::join!(task1, task2);
+            send_r?;
+
+            for (ip, msg) in peers? {
+                match &msg {
+                    // Our IPv6 listener may have accepted the IPv4 connection.
+                    b"v4" => {
+                        let ip = match ip {
+                            IpAddr::V6(ip) => IpAddr::from(ip.to_ipv4_mapped().unwrap()),
+                            ip @ IpAddr::V4(_) => ip,
+                        };
+                        assert_eq!(ip, IpAddr::from(Ipv4Addr::LOCALHOST));
+                    }
+                    b"v6" => assert_eq!(ip, IpAddr::from(Ipv6Addr::LOCALHOST)),
+                    _ => panic!("unexpected message {msg:?}"),
+                }
+            }
+
+            Ok(())
+        })
+    }
+
     runtime_tests! {
         small_delay,
         small_timeout_ok,
@@ -647,9 +705,75 @@ mod test {
         self_connect_tcp,
         self_connect_udp,
         listener_stream,
+        dual_stack_listener,
     }
 
     tls_runtime_tests! {
         simple_tls,
     }
+
+    // Try binding two listeners to the same address, which `SO_REUSEPORT` permits.
+    //
+    // NOTE: requires Ipv4 localhost.
+    #[cfg(all(
+        feature = "tokio",
+        unix,
+        not(target_os = "solaris"),
+        not(target_os = "illumos")
+    ))]
+    #[test]
+    fn listen_with_reuse() -> IoResult<()> {
+        let runtime = crate::tokio::PreferredRuntime::create()?;
+        let localhost = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+        let options = TcpListenOptions {
+            reuse_address: true,
+            reuse_port: true,
+            ..Default::default()
+        };
+
+        runtime.block_on(async {
+            let listener1 = runtime
+                .listen_with_options(&localhost.into(), &options)
+                .await?;
+            let addr = listener1.local_addr()?;
+            let listener2 = runtime.listen_with_options(&addr, &options).await?;
+            assert_eq!(listener2.local_addr()?, addr);
+
+            // Without the options, the address is in use.
+            let err = runtime
+                .listen(&addr)
+                .await
+                .err()
+                .expect("listened on an address in use");
+            assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+            Ok(())
+        })
+    }
+
+    // Connect to ourself from a specific local address.
+    //
+    // NOTE: requires Ipv4 localhost.
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn connect_from_local_addr() -> IoResult<()> {
+        let runtime = crate::tokio::PreferredRuntime::create()?;
+        let localhost = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+
+        // Find a free local port to connect from.
+        let local = std::net::TcpListener::bind(localhost)?.local_addr()?;
+
+        runtime.block_on(async {
+            let listener = runtime.listen(&localhost.into()).await?;
+            let remote = listener.local_addr()?;
+
+            let (accepted, connected) = futures::join!(
+                listener.accept(),
+                runtime.connect_from(Some(local), &remote)
+            );
+            let _con = connected?;
+            let (_con, peer) = accepted?;
+            assert_eq!(peer, local);
+            Ok(())
+        })
+    }
 }
@@ -41,9 +41,25 @@ macro_rules! implement_opaque_runtime {
             self.$member.connect(addr).await
         }
         #[inline]
+        async fn connect_from(
+            &self,
+            local: Option<std::net::SocketAddr>,
+            remote: &std::net::SocketAddr,
+        ) -> std::io::Result<Self::TcpStream> {
+            self.$member.connect_from(local, remote).await
+        }
+        #[inline]
         async fn listen(&self, addr: &std::net::SocketAddr) -> std::io::Result<Self::TcpListener> {
             self.$member.listen(addr).await
         }
+        #[inline]
+        async fn listen_with_options(
+            &self,
+            addr: &std::net::SocketAddr,
+            options: &$crate::traits::TcpListenOptions,
+        ) -> std::io::Result<Self::TcpListener> {
+            self.$member.listen_with_options(addr, options).await
+        }
     }
 
     impl<S> $crate::traits::TlsProvider<S> for $t
@@ -81,68 +81,42 @@
 //! # Example
 //!
 //! ```
-//! use std::sync::{Arc, Mutex};
 //! use std::time::Duration;
-//! use futures::task::SpawnExt as _;
-//! use tor_rtcompat::{BlockOn as _, SleepProvider as _};
+//! use tor_rtcompat::SleepProvider;
+//! use tor_rtcompat::timeout_track::TrackingInstantNow;
 //!
-//! # use tor_hsservice::timeout_track_for_doctests_unstable_no_semver_guarantees as timeout_track;
-//! # #[cfg(all)] // works like #[cfg(FALSE)].  Instead, we have this workaround ^.
-//! use crate::timeout_track;
-//! use timeout_track::TrackingInstantNow;
+//! // Example program which models cooking a stir-fry
+//! async fn cook(runtime: &impl SleepProvider, mut perform_action: impl FnMut(&str)) {
+//!     perform_action("add ingredients");
+//!     let started = runtime.now();
+//!     let mut last_stirred = started;
+//!     loop {
+//!         let now_track = TrackingInstantNow::now(runtime);
 //!
-//! // Test harness
-//! let runtime = tor_rtmock::MockRuntime::new();
-//! let actions = Arc::new(Mutex::new("".to_string())); // initial letters of performed actions
-//! let perform_action = {
-//!     let actions = actions.clone();
-//!     move |s: &str| actions.lock().unwrap().extend(s.chars().take(1))
-//! };
-//!
-//! runtime.spawn({
-//!     let runtime = runtime.clone();
-//!
-//!     // Example program which models cooking a stir-fry
-//!     async move {
-//!         perform_action("add ingredients");
-//!         let started = runtime.now();
-//!         let mut last_stirred = started;
-//!         loop {
-//!             let now_track = TrackingInstantNow::now(&runtime);
-//!
-//!             const STIR_EVERY: Duration = Duration::from_secs(25);
-//!             // In production, we might avoid panics:  .. >= last_stirred.checked_add(..)
-//!             if now_track >= last_stirred + STIR_EVERY {
-//!                 perform_action("stir");
-//!                 last_stirred = now_track.get_now_untracked();
-//!                 continue;
-//!             }
-//!
-//!             const COOK_FOR: Duration = Duration::from_secs(3 * 60);
-//!             if now_track >= started + COOK_FOR {
-//!                 break;
-//!             }
+//!         const STIR_EVERY: Duration = Duration::from_secs(25);
+//!         // In production, we might avoid panics:  .. >= last_stirred.checked_add(..)
+//!         if now_track >= last_stirred + STIR_EVERY {
+//!             perform_action("stir");
+//!             last_stirred = now_track.get_now_untracked();
+//!             continue;
+//!         }
 //!
-//!             now_track.wait_for_earliest(&runtime).await;
+//!         const COOK_FOR: Duration = Duration::from_secs(3 * 60);
+//!         if now_track >= started + COOK_FOR {
+//!             break;
 //!         }
-//!         perform_action("dish up");
-//!     }
-//! }).unwrap();
 //!
-//! // Do a test run
-//! runtime.block_on(async {
-//!     runtime.advance_by(Duration::from_secs(1 * 60)).await;
-//!     assert_eq!(*actions.lock().unwrap(), "ass");
-//!     runtime.advance_by(Duration::from_secs(2 * 60)).await;
-//!     assert_eq!(*actions.lock().unwrap(), "asssssssd");
-//! });
+//!         now_track.wait_for_earliest(runtime).await;
+//!     }
+//!     perform_action("dish up");
+//! }
 //! ```
+//!
+//! (A run of this example, with a mock runtime, can be found in
+//! the tests for `tor-rtmock`.)
 
-// TODO - eventually we hope this will become pub, in another crate
-
-// Rustdoc complains that we link to these private docs from these docs which are
-// themselves only formatted with --document-private-items.
-// TODO - Remove when this is actually public
+// Rustdoc complains that we link to the private `cmp` methods from public docs.
+// The links are still useful with --document-private-items.
 #![allow(rustdoc::private_intra_doc_links)]
 
 use std::cell::Cell;
@@ -153,7 +127,7 @@ use derive_adhoc::{define_derive_adhoc, Adhoc};
 use futures::{future, select_biased, FutureExt as _};
 use itertools::chain;
 
-use tor_rtcompat::{SleepProvider, SleepProviderExt as _};
+use crate::{SleepProvider, SleepProviderExt as _};
 
 //========== derive-adhoc macros, which must come first ==========
 
@@ -629,12 +603,6 @@ mod test {
     #![allow(clippy::needless_pass_by_value)] // TODO hoist into standard lint block
 
     use super::*;
-    use futures::poll;
-    use std::future::Future;
-    use std::task::Poll;
-    use tor_async_utils::oneshot;
-    use tor_rtcompat::BlockOn;
-    use tor_rtmock::MockRuntime;
 
     fn parse_rfc3339(s: &str) -> SystemTime {
         humantime::parse_rfc3339(s).unwrap()
@@ -783,73 +751,4 @@ mod test {
             // No need to test edge cases, as our Update impls are just delegations
         }
     }
-
-    fn test_sleeper<WF>(
-        expected_wait: Option<Duration>,
-        wait_for_timeout: impl FnOnce(MockRuntime) -> WF + Send + 'static,
-    ) where
-        WF: Future<Output = ()> + Send + 'static,
-    {
-        let runtime = MockRuntime::new();
-        runtime.clone().block_on(async move {
-            // prevent underflow of Instant in case we started very recently
-            // (just jump the clock)
-            runtime.advance_by(secs(1000000)).await;
-            // set SystemTime to a known value
-            runtime.jump_wallclock(earliest_systemtime());
-
-            let (tx, rx) = oneshot::channel();
-
-            runtime.mock_task().spawn_identified("timeout task", {
-                let runtime = runtime.clone();
-                async move {
-                    wait_for_timeout(runtime.clone()).await;
-                    tx.send(()).unwrap();
-                }
-            });
-
-            runtime.mock_task().progress_until_stalled().await;
-
-            if expected_wait == Some(Duration::ZERO) {
-                assert_eq!(poll!(rx), Poll::Ready(Ok(())));
-            } else {
-                let actual_wait = runtime.time_until_next_timeout();
-                assert_eq!(actual_wait, expected_wait);
-            }
-        });
-    }
-
-    fn test_sleeper_combined(
-        expected_wait: Option<Duration>,
-        update_tt: impl FnOnce(&MockRuntime, &TrackingNow) + Send + 'static,
-    ) {
-        test_sleeper(expected_wait, |rt| async move {
-            let tt = TrackingNow::now(&rt);
-            update_tt(&rt, &tt);
-            tt.wait_for_earliest(&rt).await;
-        });
-    }
-
-    #[test]
-    fn sleeps() {
-        let s = earliest_systemtime();
-        let d = secs(42);
-
-        test_sleeper_combined(None, |_rt, _tt| {});
-        test_sleeper_combined(None, move |_rt, tt| {
-            assert!(*tt > (s - d));
-        });
-        test_sleeper_combined(Some(d), move |_rt, tt| {
-            assert!(*tt < (s + d));
-        });
-
-        test_sleeper_combined(None, move |rt, tt| {
-            let i = rt.now();
-            assert!(*tt > (i - d));
-        });
-        test_sleeper_combined(Some(d), move |rt, tt| {
-            let i = rt.now();
-            assert!(*tt < (i + d));
-        });
-    }
 }
@@ -1,4 +1,4 @@
-//! Definitions for [`SleepProviderExt`] and related types.
+//! Definitions for [`SleepProviderExt`], [`Deadline`], and related types.
 
 use crate::traits::SleepProvider;
 use futures::{Future, FutureExt};
@@ -6,7 +6,7 @@ use pin_project::pin_project;
 use std::{
     pin::Pin,
     task::{Context, Poll},
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
 /// An error value given when a function times out.
@@ -168,6 +168,96 @@ where
     }
 }
 
+/// A resettable timer that fires once a given [`Instant`] is reached.
+///
+/// A `Deadline` is a [`Future`] which becomes ready when the
+/// [`SleepProvider`]'s view of the current time reaches the deadline.
+/// Unlike a plain [`SleepProvider::sleep`] future, it can be moved to
+/// a different time with [`reset`](Deadline::reset), and it can be
+/// awaited by reference (as `&mut deadline`).
+///
+/// Awaiting a `Deadline` is cancellation-safe:
+/// all of its state lives in the `Deadline` itself,
+/// so it can be polled in one arm of a `select_biased!`
+/// on each iteration of a loop,
+/// and dropping the reference future loses nothing.
+///
+/// Once it has fired, a `Deadline` is unset, and
+/// will not become ready again until it is reset.
+/// An unset `Deadline` never becomes ready.
+pub struct Deadline<SP: SleepProvider> {
+    /// The provider that we use to make new SleepFutures.
+    provider: SP,
+    /// The instant at which we should fire, if any.
+    when: Option<Instant>,
+    /// The future representing our current delay, if we have made one yet.
+    ///
+    /// This is discarded whenever `when` changes.
+    sleep_future: Option<Pin<Box<SP::SleepFuture>>>,
+}
+
+// We never pin-project to `provider`, and `sleep_future` is already boxed.
+impl<SP: SleepProvider> Unpin for Deadline<SP> {}
+
+impl<SP: SleepProvider> Deadline<SP> {
+    /// Create a new `Deadline`, which is not set.
+    pub fn new(provider: SP) -> Self {
+        Deadline {
+            provider,
+            when: None,
+            sleep_future: None,
+        }
+    }
+
+    /// Make this `Deadline` fire at `when` (replacing any previous deadline).
+    ///
+    /// If `when` is already in the past, the `Deadline` will be ready
+    /// as soon as it is next polled.
+    pub fn reset(&mut self, when: Instant) {
+        if self.when != Some(when) {
+            self.when = Some(when);
+            self.sleep_future = None;
+        }
+    }
+
+    /// Unset this `Deadline`, so that it will not fire until it is reset.
+    pub fn clear(&mut self) {
+        self.when = None;
+        self.sleep_future = None;
+    }
+
+    /// Return the instant at which this `Deadline` will fire, if it is set.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.when
+    }
+}
+
+impl<SP: SleepProvider> Future for Deadline<SP> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        let Some(when) = this.when else {
+            // Nothing to wait for; we'll be polled again after a reset.
+            return Poll::Pending;
+        };
+
+        let now = this.provider.now();
+        if now < when {
+            let provider = &this.provider;
+            let sleep_future = this
+                .sleep_future
+                .get_or_insert_with(|| Box::pin(provider.sleep(when - now)));
+            if sleep_future.poll_unpin(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+
+        this.clear();
+        Poll::Ready(())
+    }
+}
+
 /// We never sleep more than this much, in case our system clock jumps.
 ///
 /// Note that there's a tradeoff here: Making this duration
@@ -156,8 +156,70 @@ pub trait TcpProvider: Clone + Send + Sync + 'static {
     /// unnecessary DNS lookups.
     async fn connect(&self, addr: &SocketAddr) -> IoResult<Self::TcpStream>;
 
+    /// Launch a TCP connection to `remote`, from the local address `local`.
+    ///
+    /// This is useful on multi-homed hosts, to choose the interface we connect from.
+    /// If `local` is `None`, this is the same as [`connect`](TcpProvider::connect).
+    ///
+    /// Not all runtimes support choosing the local address:
+    /// the default implementation fails with [`std::io::ErrorKind::Unsupported`]
+    /// if `local` is specified.
+    async fn connect_from(
+        &self,
+        local: Option<SocketAddr>,
+        remote: &SocketAddr,
+    ) -> IoResult<Self::TcpStream> {
+        if local.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "choosing the local address is not supported by this runtime",
+            ));
+        }
+        self.connect(remote).await
+    }
+
     /// Open a TCP listener on a given socket address.
     async fn listen(&self, addr: &SocketAddr) -> IoResult<Self::TcpListener>;
+
+    /// Open a TCP listener on a given socket address, with the specified socket options.
+    ///
+    /// Not all runtimes support all options.  If `options` asks for something this runtime
+    /// (or this platform) doesn't support, this fails with [`std::io::ErrorKind::Unsupported`].
+    ///
+    /// The default implementation supports only the default options,
+    /// for which it is equivalent to [`listen`](TcpProvider::listen).
+    async fn listen_with_options(
+        &self,
+        addr: &SocketAddr,
+        options: &TcpListenOptions,
+    ) -> IoResult<Self::TcpListener> {
+        if *options != TcpListenOptions::default() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "socket options not supported by this runtime",
+            ));
+        }
+        self.listen(addr).await
+    }
+}
+
+/// Socket options for [`TcpProvider::listen_with_options`]
+///
+/// The default is to set none of these options.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[non_exhaustive]
+pub struct TcpListenOptions {
+    /// Set `SO_REUSEADDR` before binding.
+    ///
+    /// This lets us bind to an address that has connections lingering in `TIME_WAIT`,
+    /// for example when restarting quickly.
+    pub reuse_address: bool,
+
+    /// Set `SO_REUSEPORT` before binding.
+    ///
+    /// This lets several sockets, that all set it, be bound to the same address at once.
+    /// It is only available on some Unix platforms.
+    pub reuse_port: bool,
 }
 
 /// Trait for a local socket that accepts incoming TCP streams.
@@ -9,11 +9,12 @@ use super::io::{stream_pair, LocalStream};
 use super::MockNetRuntime;
 use core::fmt;
 use tor_rtcompat::tls::TlsConnector;
-use tor_rtcompat::{CertifiedConn, Runtime, TcpListener, TcpProvider, TlsProvider};
+use tor_rtcompat::{CertifiedConn, Runtime, SleepProvider, TcpListener, TcpProvider, TlsProvider};
 use tor_rtcompat::{UdpProvider, UdpSocket};
 
 use async_trait::async_trait;
 use futures::channel::mpsc;
+use futures::future::BoxFuture;
 use futures::io::{AsyncRead, AsyncWrite};
 use futures::lock::Mutex as AsyncMutex;
 use futures::sink::SinkExt;
@@ -27,6 +28,7 @@ use std::pin::Pin;
 use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use std::time::Duration;
 use thiserror::Error;
 use void::Void;
 
@@ -86,6 +88,7 @@ enum AddrBehavior {
 ///
 /// We don't handle localhost specially, and we don't simulate providers
 /// that can connect to some addresses but not all.
+/// (But see below for simulating failures.)
 ///
 /// We don't do the right thing (block) if there is a listener that
 /// never calls accept.
@@ -105,6 +108,15 @@ enum AddrBehavior {
 ///
 /// We pretend to provide TLS, but there's no actual encryption or
 /// authentication.
+///
+/// # Fault injection
+///
+/// To exercise timeout and retry paths,
+/// outgoing connections can be made slow, or made to fail:
+/// see [`set_connect_delay`](MockNetProvider::set_connect_delay),
+/// [`set_connect_failure_rate`](MockNetProvider::set_connect_failure_rate),
+/// and [`add_connect_failures`](MockNetProvider::add_connect_failures).
+/// These settings are shared by all clones of a `MockNetProvider`.
 #[derive(Clone)]
 pub struct MockNetProvider {
     /// Actual implementation of this host's view of the network.
@@ -134,6 +146,53 @@ struct MockNetProviderInner {
     ///
     /// See discussion of limitations on `listen()` implementation.
     next_port: AtomicU16,
+    /// Simulated faults to apply to outgoing connections.
+    faults: Mutex<ConnectFaults>,
+}
+
+/// A function that sleeps for a given duration, using some `SleepProvider`
+type SleepFn = Arc<dyn Fn(Duration) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Simulated faults that a [`MockNetProvider`] applies to its outgoing connections
+#[derive(Default)]
+struct ConnectFaults {
+    /// How long to wait before each connection attempt, and how to wait
+    delay: Option<(Duration, SleepFn)>,
+    /// The fraction of connection attempts that should fail
+    failure_rate: f64,
+    /// Accumulated fractional failures, not yet turned into an actual failure
+    ///
+    /// We don't have any randomness, so we fail an attempt
+    /// whenever this reaches 1.
+    failure_debt: f64,
+    /// Remaining failures for attempts to particular addresses
+    ///
+    /// Each entry is the error to return, and how many more attempts should fail.
+    addr_failures: HashMap<SocketAddr, (ErrorKind, usize)>,
+}
+
+impl ConnectFaults {
+    /// Decide whether an attempt to connect to `addr` should fail
+    ///
+    /// Updates the counters, since the attempt is now being made.
+    fn check_attempt(&mut self, addr: &SocketAddr) -> IoResult<()> {
+        if let Some((kind, remaining)) = self.addr_failures.get_mut(addr) {
+            let kind = *kind;
+            *remaining -= 1;
+            if *remaining == 0 {
+                self.addr_failures.remove(addr);
+            }
+            return Err(fault(kind));
+        }
+
+        self.failure_debt += self.failure_rate;
+        if self.failure_debt >= 1.0 {
+            self.failure_debt -= 1.0;
+            return Err(fault(ErrorKind::ConnectionRefused));
+        }
+
+        Ok(())
+    }
 }
 
 /// A [`TcpListener`] implementation returned by a [`MockNetProvider`].
@@ -270,6 +329,7 @@ impl ProviderBuilder {
             addrs: self.addrs.clone(),
             net: Arc::clone(&self.net),
             next_port: AtomicU16::new(1),
+            faults: Default::default(),
         };
         MockNetProvider {
             inner: Arc::new(inner),
@@ -419,6 +479,54 @@ impl MockNetProvider {
         Ok(SocketAddr::new(ipaddr, port))
     }
 
+    /// Make every outgoing connection attempt wait for `delay` before proceeding
+    ///
+    /// The delay is measured using `sleep_provider`,
+    /// which should usually be the mock time provider of the runtime in use.
+    ///
+    /// Replaces any previously configured delay.
+    pub fn set_connect_delay<SP: SleepProvider>(&self, sleep_provider: SP, delay: Duration) {
+        let sleep: SleepFn = Arc::new(move |d| sleep_provider.sleep(d).boxed());
+        self.lock_faults().delay = Some((delay, sleep));
+    }
+
+    /// Make a fraction `rate` of outgoing connection attempts fail
+    ///
+    /// Failing attempts fail with [`ErrorKind::ConnectionRefused`].
+    ///
+    /// There's no randomness here:
+    /// failures are spread evenly over the attempts,
+    /// so with a `rate` of `0.5`, every second attempt fails.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rate` is not in the range `0.0..=1.0`.
+    pub fn set_connect_failure_rate(&self, rate: f64) {
+        assert!((0.0..=1.0).contains(&rate), "bad failure rate {rate}");
+        let mut faults = self.lock_faults();
+        faults.failure_rate = rate;
+        faults.failure_debt = 0.0;
+    }
+
+    /// Make the next `count` connection attempts to `addr` fail with `kind`
+    ///
+    /// Replaces any failures previously configured for `addr`.
+    /// These failures take priority over the
+    /// [failure rate](MockNetProvider::set_connect_failure_rate).
+    pub fn add_connect_failures(&self, addr: SocketAddr, kind: ErrorKind, count: usize) {
+        let mut faults = self.lock_faults();
+        if count == 0 {
+            faults.addr_failures.remove(&addr);
+        } else {
+            faults.addr_failures.insert(addr, (kind, count));
+        }
+    }
+
+    /// Lock and return our simulated fault settings
+    fn lock_faults(&self) -> std::sync::MutexGuard<'_, ConnectFaults> {
+        self.inner.faults.lock().expect("Poisoned lock for faults")
+    }
+
     /// Create a mock TLS listener with provided certificate.
     ///
     /// Note that no encryption or authentication is actually
@@ -439,6 +547,16 @@ impl TcpProvider for MockNetProvider {
     type TcpListener = MockNetListener;
 
     async fn connect(&self, addr: &SocketAddr) -> IoResult<LocalStream> {
+        let delay = self
+            .lock_faults()
+            .delay
+            .as_ref()
+            .map(|(delay, sleep)| sleep(*delay));
+        if let Some(delay) = delay {
+            delay.await;
+        }
+        self.lock_faults().check_attempt(addr)?;
+
         let my_addr = self.get_origin_addr_for(addr)?;
         let (mut mine, theirs) = stream_pair();
 
@@ -555,6 +673,9 @@ pub enum MockNetError {
     /// General-purpose error.  The real information is in `ErrorKind`.
     #[error("Invalid operation on mock network")]
     BadOp,
+    /// A failure that we were configured to simulate.
+    #[error("Simulated failure on mock network")]
+    InjectedFault,
 }
 
 /// Wrap `k` in a new [`std::io::Error`].
@@ -562,6 +683,11 @@ fn err(k: ErrorKind) -> IoError {
     IoError::new(k, MockNetError::BadOp)
 }
 
+/// Wrap `k` in a new [`std::io::Error`], for a simulated failure.
+fn fault(k: ErrorKind) -> IoError {
+    IoError::new(k, MockNetError::InjectedFault)
+}
+
 #[cfg(test)]
 mod test {
     // @@ begin test lint list maintained by maint/add_warning @@
@@ -739,4 +865,95 @@ mod test {
             IoResult::Ok(())
         });
     }
+
+    /// Connect to `addr`, retrying on failure up to `max_attempts` times
+    ///
+    /// Returns the errors from the failed attempts, and the final outcome.
+    async fn connect_with_retries(
+        client: &MockNetProvider,
+        addr: &SocketAddr,
+        max_attempts: usize,
+    ) -> (Vec<ErrorKind>, IoResult<LocalStream>) {
+        let mut failures = vec![];
+        loop {
+            match client.connect(addr).await {
+                Err(e) if failures.len() + 1 < max_attempts => failures.push(e.kind()),
+                outcome => return (failures, outcome),
+            }
+        }
+    }
+
+    #[test]
+    fn connect_failures_retried() {
+        test_with_all_runtimes!(|_rt| async {
+            let (client1, client2) = client_pair();
+            let lis = client2.listen(&"0.0.0.0:99".parse().unwrap()).await?;
+            let address = lis.local_addr()?;
+
+            client1.add_connect_failures(address, ErrorKind::ConnectionReset, 2);
+
+            // Only connections to `address` are affected.
+            let elsewhere = "192.0.2.200:99".parse().unwrap();
+            let e = client1
+                .connect(&elsewhere)
+                .await
+                .err()
+                .expect("connected to an address nobody listens on");
+            assert_eq!(e.kind(), ErrorKind::ConnectionRefused);
+
+            let (failures, outcome) = connect_with_retries(&client1, &address, 5).await;
+            assert_eq!(failures, [ErrorKind::ConnectionReset; 2]);
+            let _conn = outcome?;
+            let (_conn, a) = lis.accept().await?;
+            assert_eq!(a.ip(), "192.0.2.55".parse::<IpAddr>().unwrap());
+
+            // Not enough retries.
+            client1.add_connect_failures(address, ErrorKind::ConnectionReset, 2);
+            let (failures, outcome) = connect_with_retries(&client1, &address, 2).await;
+            assert_eq!(failures, [ErrorKind::ConnectionReset]);
+            let e = outcome.err().expect("connected despite too few retries");
+            assert_eq!(e.kind(), ErrorKind::ConnectionReset);
+            IoResult::Ok(())
+        });
+    }
+
+    #[test]
+    fn connect_failure_rate() {
+        test_with_all_runtimes!(|_rt| async {
+            let (client1, client2) = client_pair();
+            let lis = client2.listen(&"0.0.0.0:99".parse().unwrap()).await?;
+            let address = lis.local_addr()?;
+
+            client1.set_connect_failure_rate(0.5);
+            let mut outcomes = vec![];
+            for _ in 0..4 {
+                outcomes.push(client1.connect(&address).await.is_ok());
+            }
+            assert_eq!(outcomes, [true, false, true, false]);
+
+            client1.set_connect_failure_rate(0.0);
+            let _conn = client1.connect(&address).await?;
+            IoResult::Ok(())
+        });
+    }
+
+    #[test]
+    fn connect_delay() {
+        crate::MockRuntime::test_with_various(|rt| async move {
+            let (client1, client2) = client_pair();
+            let lis = client2
+                .listen(&"0.0.0.0:99".parse().unwrap())
+                .await
+                .unwrap();
+            let address = lis.local_addr().unwrap();
+
+            let delay = Duration::from_secs(30);
+            client1.set_connect_delay(rt.mock_sleep().clone(), delay);
+
+            let start = rt.now();
+            let (conn, ()) = futures::join!(client1.connect(&address), rt.advance_until_stalled());
+            let _conn = conn.unwrap();
+            assert_eq!(rt.now() - start, delay);
+        });
+    }
 }
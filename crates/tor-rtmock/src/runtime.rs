@@ -217,6 +217,31 @@ impl MockRuntime {
         self.task.progress_until_stalled().await;
     }
 
+    /// Run tasks until stalled, and then check that no other tasks remain
+    ///
+    /// Calls [`progress_until_stalled()`](MockRuntime::progress_until_stalled),
+    /// and then checks that [`MockExecutor::n_tasks()`] counts only the calling task.
+    ///
+    /// Useful at the end of a test case, to check that tasks that ought to have exited
+    /// (for example, because their shutdown signal was dropped)
+    /// have indeed done so.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any other tasks remain, saying how many;
+    /// before panicking, prints a [debug dump](MockExecutor::debug_dump) of the tasks.
+    ///
+    /// And, see [`progress_until_stalled`](MockRuntime::progress_until_stalled)
+    pub async fn assert_no_extra_tasks(&self) {
+        self.progress_until_stalled().await;
+        // The calling task is always counted.
+        let n_extra = self.task.n_tasks().saturating_sub(1);
+        if n_extra != 0 {
+            self.task.debug_dump();
+            panic!("{n_extra} task(s) leaked: still present after progress_until_stalled");
+        }
+    }
+
     /// Run tasks and advance time up to at most `limit`
     ///
     /// Will return when all other tasks are either:
@@ -498,6 +523,35 @@ mod test {
         });
     }
 
+    //---------- test assert_no_extra_tasks ----------
+
+    #[traced_test]
+    #[test]
+    fn no_extra_tasks() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let tt = TestTasks::spawn(&runtime);
+
+            // Dropping the sender makes the "rx" task exit.
+            drop(tt.tx);
+            runtime.advance_until_stalled().await;
+            runtime.assert_no_extra_tasks().await;
+        });
+    }
+
+    #[traced_test]
+    #[test]
+    #[should_panic(expected = "1 task(s) leaked")]
+    fn extra_tasks() {
+        MockRuntime::test_with_various(|runtime| async move {
+            let tt = TestTasks::spawn(&runtime);
+
+            // The "rx" task is still waiting for us.
+            runtime.advance_until_stalled().await;
+            runtime.assert_no_extra_tasks().await;
+            drop(tt);
+        });
+    }
+
     //---------- test advance_until ----------
 
     impl TestTasks {
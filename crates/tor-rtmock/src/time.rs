@@ -595,6 +595,46 @@ mod test {
         assert_eq!(sp.wallclock(), w1 + interval * 3);
     }
 
+    #[test]
+    fn deadline() {
+        use futures::FutureExt as _;
+        use tor_rtcompat::Deadline;
+
+        let sp = MockSleepProvider::new(SystemTime::now());
+        let one_hour = Duration::new(3600, 0);
+        let start = sp.now();
+
+        let mut deadline = Deadline::new(sp.clone());
+        // An unset deadline never fires.
+        assert!((&mut deadline).now_or_never().is_none());
+
+        deadline.reset(start + one_hour);
+        assert_eq!(deadline.deadline(), Some(start + one_hour));
+        assert!((&mut deadline).now_or_never().is_none());
+
+        // Reset before firing: the original deadline is forgotten.
+        sp.advance_noyield(one_hour / 2);
+        assert!((&mut deadline).now_or_never().is_none());
+        deadline.reset(start + one_hour * 2);
+        sp.advance_noyield(one_hour);
+        assert!((&mut deadline).now_or_never().is_none());
+
+        // Fire, after which the deadline is unset.
+        sp.advance_noyield(one_hour);
+        assert!((&mut deadline).now_or_never().is_some());
+        assert_eq!(deadline.deadline(), None);
+        assert!((&mut deadline).now_or_never().is_none());
+
+        // A deadline in the past fires immediately.
+        deadline.reset(start);
+        assert!((&mut deadline).now_or_never().is_some());
+
+        // A cleared deadline doesn't fire.
+        deadline.reset(start);
+        deadline.clear();
+        assert!((&mut deadline).now_or_never().is_none());
+    }
+
     #[test]
     fn time_moves_on() {
         test_with_all_runtimes!(|_| async {
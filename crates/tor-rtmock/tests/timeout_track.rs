@@ -0,0 +1,139 @@
+//! Example: tests for the timeout tracking in tor-rtcompat, using the mock runtime.
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use std::time::{Duration, SystemTime};
+
+use futures::poll;
+use futures::task::SpawnExt as _;
+
+use tor_async_utils::oneshot;
+use tor_rtcompat::timeout_track::{TrackingInstantNow, TrackingNow};
+use tor_rtcompat::{BlockOn as _, SleepProvider as _};
+use tor_rtmock::MockRuntime;
+
+fn secs(s: u64) -> Duration {
+    Duration::from_secs(s)
+}
+
+fn earliest_systemtime() -> SystemTime {
+    humantime::parse_rfc3339("1993-11-01T00:00:00Z").unwrap()
+}
+
+/// Runs the example from the `timeout_track` module-level docs
+#[test]
+fn stir_fry() {
+    let runtime = MockRuntime::new();
+    let actions = Arc::new(Mutex::new("".to_string())); // initial letters of performed actions
+    let perform_action = {
+        let actions = actions.clone();
+        move |s: &str| actions.lock().unwrap().extend(s.chars().take(1))
+    };
+
+    runtime
+        .spawn({
+            let runtime = runtime.clone();
+
+            async move {
+                perform_action("add ingredients");
+                let started = runtime.now();
+                let mut last_stirred = started;
+                loop {
+                    let now_track = TrackingInstantNow::now(&runtime);
+
+                    const STIR_EVERY: Duration = Duration::from_secs(25);
+                    if now_track >= last_stirred + STIR_EVERY {
+                        perform_action("stir");
+                        last_stirred = now_track.get_now_untracked();
+                        continue;
+                    }
+
+                    const COOK_FOR: Duration = Duration::from_secs(3 * 60);
+                    if now_track >= started + COOK_FOR {
+                        break;
+                    }
+
+                    now_track.wait_for_earliest(&runtime).await;
+                }
+                perform_action("dish up");
+            }
+        })
+        .unwrap();
+
+    runtime.block_on(async {
+        runtime.advance_by(secs(60)).await;
+        assert_eq!(*actions.lock().unwrap(), "ass");
+        runtime.advance_by(secs(2 * 60)).await;
+        assert_eq!(*actions.lock().unwrap(), "asssssssd");
+    });
+}
+
+fn test_sleeper<WF>(
+    expected_wait: Option<Duration>,
+    wait_for_timeout: impl FnOnce(MockRuntime) -> WF + Send + 'static,
+) where
+    WF: Future<Output = ()> + Send + 'static,
+{
+    let runtime = MockRuntime::new();
+    runtime.clone().block_on(async move {
+        // prevent underflow of Instant in case we started very recently
+        // (just jump the clock)
+        runtime.advance_by(secs(1000000)).await;
+        // set SystemTime to a known value
+        runtime.jump_wallclock(earliest_systemtime());
+
+        let (tx, rx) = oneshot::channel();
+
+        runtime.mock_task().spawn_identified("timeout task", {
+            let runtime = runtime.clone();
+            async move {
+                wait_for_timeout(runtime.clone()).await;
+                tx.send(()).unwrap();
+            }
+        });
+
+        runtime.mock_task().progress_until_stalled().await;
+
+        if expected_wait == Some(Duration::ZERO) {
+            assert_eq!(poll!(rx), Poll::Ready(Ok(())));
+        } else {
+            let actual_wait = runtime.time_until_next_timeout();
+            assert_eq!(actual_wait, expected_wait);
+        }
+    });
+}
+
+fn test_sleeper_combined(
+    expected_wait: Option<Duration>,
+    update_tt: impl FnOnce(&MockRuntime, &TrackingNow) + Send + 'static,
+) {
+    test_sleeper(expected_wait, |rt| async move {
+        let tt = TrackingNow::now(&rt);
+        update_tt(&rt, &tt);
+        tt.wait_for_earliest(&rt).await;
+    });
+}
+
+#[test]
+fn sleeps() {
+    let s = earliest_systemtime();
+    let d = secs(42);
+
+    test_sleeper_combined(None, |_rt, _tt| {});
+    test_sleeper_combined(None, move |_rt, tt| {
+        assert!(*tt > (s - d));
+    });
+    test_sleeper_combined(Some(d), move |_rt, tt| {
+        assert!(*tt < (s + d));
+    });
+
+    test_sleeper_combined(None, move |rt, tt| {
+        let i = rt.now();
+        assert!(*tt > (i - d));
+    });
+    test_sleeper_combined(Some(d), move |rt, tt| {
+        let i = rt.now();
+        assert!(*tt < (i + d));
+    });
+}
@@ -32,11 +32,37 @@ pub enum Error {
     #[error("SOCKS feature ({0}) not implemented")]
     NotImplemented(Cow<'static, str>),
 
+    /// The SOCKS5 client offered no authentication method that we support.
+    ///
+    /// (For example, it offered only GSSAPI.)
+    #[error("SOCKS client offered no supported authentication method")]
+    NoSupportedAuthMethod,
+
+    /// A message in the SOCKS handshake was longer than we are willing to buffer.
+    ///
+    /// (See `SocksProxyHandshake::set_max_message_len`.)
+    #[error("SOCKS message too long (limit is {limit} bytes)")]
+    MessageTooLong {
+        /// The most bytes we would buffer for a single message.
+        limit: usize,
+    },
+
+    /// The other party closed the connection in the middle of the handshake.
+    ///
+    /// Returned by `handshake_at_eof`, when the input so far is only part of a message.
+    #[error("SOCKS connection closed during handshake")]
+    UnexpectedEof,
+
     /// Tried to progress the SOCKS handshake when it was already
     /// finished.  This is a programming error.
     #[error("SOCKS handshake was finished; no need to call this again")]
     AlreadyFinished(tor_error::Bug),
 
+    /// A connection that should have started with a PROXY protocol header
+    /// didn't start with a valid one.
+    #[error("Invalid PROXY protocol header: {0}")]
+    BadProxyHeader(&'static str),
+
     /// The SOCKS proxy refused our authentication.
     #[error("SOCKS Authentication failed")]
     AuthRejected,
@@ -59,9 +85,13 @@ impl HasKind for Error {
                 // see it.
                 EK::Internal
             }
-            E::Syntax | E::Decode(_) | E::BadProtocol(_) => EK::LocalProtocolViolation,
-            E::NotImplemented(_) => EK::NotImplemented,
-            E::AuthRejected => EK::LocalProtocolViolation,
+            E::Syntax | E::Decode(_) | E::BadProtocol(_) | E::BadProxyHeader(_) => {
+                EK::LocalProtocolViolation
+            }
+            E::NotImplemented(_) | E::NoSupportedAuthMethod => EK::NotImplemented,
+            E::AuthRejected | E::UnexpectedEof | E::MessageTooLong { .. } => {
+                EK::LocalProtocolViolation
+            }
             E::AlreadyFinished(e) => e.kind(),
             E::Bug(e) => e.kind(),
         }
@@ -15,6 +15,9 @@ use tor_bytes::{EncodeResult, Error as BytesError, Readable, Reader, Writeable,
 const USERNAME_PASSWORD: u8 = 0x02;
 /// Constant for "no authentication".
 const NO_AUTHENTICATION: u8 = 0x00;
+/// Constant for "no acceptable methods": sent when none of the client's
+/// offered authentication methods are ones we support.
+const NO_ACCEPTABLE_METHODS: u8 = 0xFF;
 
 /// An action to take in response to a SOCKS handshake message.
 #[derive(Clone, Debug)]
@@ -22,6 +25,10 @@ const NO_AUTHENTICATION: u8 = 0x00;
 pub struct Action {
     /// If nonzero, this many bytes should be drained from the
     /// client's inputs.
+    ///
+    /// Any input past this point was not consumed by the handshake: once the
+    /// handshake is finished, it belongs to the stream that follows, and must
+    /// not be discarded.  See [`Action::leftover`].
     pub drain: usize,
     /// If nonempty, this reply should be sent to the other party.
     pub reply: Vec<u8>,
@@ -29,6 +36,16 @@ pub struct Action {
     pub finished: bool,
 }
 
+impl Action {
+    /// Return the part of `input` that this action did not consume.
+    ///
+    /// `input` must be the same input that was passed to the `handshake`
+    /// call that returned this action.
+    pub fn leftover<'a>(&self, input: &'a [u8]) -> &'a [u8] {
+        &input[self.drain..]
+    }
+}
+
 impl Readable for SocksAddr {
     fn take_from(r: &mut Reader<'_>) -> BytesResult<SocksAddr> {
         let atype = r.take_u8()?;
@@ -61,20 +78,18 @@ impl Readable for SocksAddr {
 
 impl Writeable for SocksAddr {
     fn write_onto<W: Writer + ?Sized>(&self, w: &mut W) -> EncodeResult<()> {
+        w.write_u8(self.addr_type().atyp());
         match self {
             SocksAddr::Ip(IpAddr::V4(ip)) => {
-                w.write_u8(1);
                 w.write(ip)?;
             }
             SocksAddr::Ip(IpAddr::V6(ip)) => {
-                w.write_u8(4);
                 w.write(ip)?;
             }
             SocksAddr::Hostname(h) => {
                 let h = h.as_ref();
                 assert!(h.len() < 256);
                 let hlen = h.len() as u8;
-                w.write_u8(3);
                 w.write_u8(hlen);
                 w.write(h.as_bytes())?;
             }
@@ -180,7 +195,7 @@ mod test_roundtrip {
                 SocksCmd::CONNECT,
                 SocksAddr::Ip("192.0.2.33".parse().unwrap()),
                 22,
-                SocksAuth::Socks4(b"swordfish".to_vec()),
+                SocksAuth::Socks4(b"swordfish".to_vec().into()),
             )
             .unwrap(),
             SocksStatus::GENERAL_FAILURE,
@@ -207,10 +222,240 @@ mod test_roundtrip {
                 SocksCmd::CONNECT,
                 SocksAddr::Ip("2001:db8::32".parse().unwrap()),
                 443,
-                SocksAuth::Username(b"belbo".to_vec(), b"non".to_vec()),
+                SocksAuth::Username(b"belbo".to_vec().into(), b"non".to_vec().into()),
             )
             .unwrap(),
             SocksStatus::GENERAL_FAILURE,
         );
     }
 }
+
+#[cfg(all(feature = "client-handshake", feature = "proxy-handshake"))]
+#[cfg(test)]
+mod test_vectors {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+
+    //! Check both sides of the handshake against recorded transcripts.
+
+    use super::client::SocksClientHandshake;
+    use super::proxy::SocksProxyHandshake;
+    use super::Action;
+    use crate::{SocksAddr, SocksAuth, SocksCmd, SocksRequest, SocksStatus, SocksVersion, TResult};
+    use hex_literal::hex;
+
+    /// Data that follows a handshake message on the same connection.
+    ///
+    /// The handshake must leave it alone, so that it can be handed to
+    /// whatever comes next.
+    const TRAILER: &[u8] = b"GET / HTTP/1.0\r\n\r\n";
+
+    /// A recorded SOCKS handshake.
+    struct Transcript {
+        /// A name for this transcript, for failure messages.
+        name: &'static str,
+        /// The request that the client makes.
+        request: SocksRequest,
+        /// The status that the proxy replies with.
+        status: SocksStatus,
+        /// Each message sent by the client, along with the proxy's answer.
+        ///
+        /// The answer to the last message is the proxy's final reply.
+        exchanges: Vec<(Vec<u8>, Vec<u8>)>,
+    }
+
+    /// Return our corpus of transcripts.
+    fn transcripts() -> Vec<Transcript> {
+        vec![
+            Transcript {
+                name: "socks4",
+                request: SocksRequest::new(
+                    SocksVersion::V4,
+                    SocksCmd::CONNECT,
+                    SocksAddr::Ip("203.0.113.7".parse().unwrap()),
+                    80,
+                    SocksAuth::NoAuth,
+                )
+                .unwrap(),
+                status: SocksStatus::SUCCEEDED,
+                exchanges: vec![(
+                    hex!("04 01 0050 CB007107 00").to_vec(),
+                    hex!("00 5A 0000 00000000").to_vec(),
+                )],
+            },
+            Transcript {
+                name: "socks4a",
+                request: SocksRequest::new(
+                    SocksVersion::V4,
+                    SocksCmd::CONNECT,
+                    SocksAddr::Hostname("www.example.com".to_string().try_into().unwrap()),
+                    443,
+                    SocksAuth::Socks4(b"swordfish".to_vec().into()),
+                )
+                .unwrap(),
+                status: SocksStatus::GENERAL_FAILURE,
+                exchanges: vec![(
+                    hex!(
+                        "04 01 01BB 00000001 73776f72646669736800
+                         7777772e6578616d706c652e636f6d00"
+                    )
+                    .to_vec(),
+                    hex!("00 5B 0000 00000000").to_vec(),
+                )],
+            },
+            Transcript {
+                name: "socks5 no-auth",
+                request: SocksRequest::new(
+                    SocksVersion::V5,
+                    SocksCmd::CONNECT,
+                    SocksAddr::Hostname("www.torproject.org".to_string().try_into().unwrap()),
+                    443,
+                    SocksAuth::NoAuth,
+                )
+                .unwrap(),
+                status: SocksStatus::SUCCEEDED,
+                exchanges: vec![
+                    (hex!("05 01 00").to_vec(), hex!("05 00").to_vec()),
+                    (
+                        hex!("05 01 00 03 12 7777772e746f7270726f6a6563742e6f7267 01BB").to_vec(),
+                        hex!("05 00 00 01 00000000 0000").to_vec(),
+                    ),
+                ],
+            },
+            Transcript {
+                name: "socks5 username/password",
+                request: SocksRequest::new(
+                    SocksVersion::V5,
+                    SocksCmd::CONNECT,
+                    SocksAddr::Ip("2001:db8::32".parse().unwrap()),
+                    443,
+                    SocksAuth::Username(b"belbo".to_vec().into(), b"non".to_vec().into()),
+                )
+                .unwrap(),
+                status: SocksStatus::HOST_UNREACHABLE,
+                exchanges: vec![
+                    (hex!("05 02 02 00").to_vec(), hex!("05 02").to_vec()),
+                    (
+                        hex!("01 05 62656c626f 03 6e6f6e").to_vec(),
+                        hex!("01 00").to_vec(),
+                    ),
+                    (
+                        hex!("05 01 00 04 20010db8000000000000000000000032 01BB").to_vec(),
+                        hex!("05 04 00 01 00000000 0000").to_vec(),
+                    ),
+                ],
+            },
+        ]
+    }
+
+    /// Pass `msg`, followed by [`TRAILER`], to `handshake`, `chunk` bytes at
+    /// a time, as if it were arriving over the network.
+    ///
+    /// Check that every attempt before `msg` is complete gives `Truncated`,
+    /// and that the handshake consumes exactly `msg`.
+    fn feed(
+        name: &str,
+        msg: &[u8],
+        chunk: usize,
+        mut handshake: impl FnMut(&[u8]) -> TResult<Action>,
+    ) -> Action {
+        let input = [msg, TRAILER].concat();
+        let mut n_read: usize = 0;
+        loop {
+            n_read = std::cmp::min(n_read.saturating_add(chunk), input.len());
+            match handshake(&input[..n_read]) {
+                Err(_) => {
+                    assert!(n_read < msg.len(), "{name}: truncated on complete message");
+                }
+                Ok(action) => {
+                    let action = action.unwrap();
+                    assert!(n_read >= msg.len(), "{name}: accepted partial message");
+                    assert_eq!(action.drain, msg.len(), "{name}");
+                    assert_eq!(
+                        &input[action.drain..n_read],
+                        &TRAILER[..n_read - msg.len()],
+                        "{name}"
+                    );
+                    return action;
+                }
+            }
+        }
+    }
+
+    /// Run the proxy side of `t`, reading `chunk` bytes at a time.
+    fn check_proxy(t: &Transcript, chunk: usize) {
+        let mut hs = SocksProxyHandshake::new();
+        let ((last_msg, final_reply), exchanges) = t.exchanges.split_last().unwrap();
+        for (client_msg, proxy_msg) in exchanges {
+            let action = feed(t.name, client_msg, chunk, |input| hs.handshake(input));
+            assert!(!action.finished, "{}", t.name);
+            assert_eq!(&action.reply, proxy_msg, "{}", t.name);
+        }
+        let action = feed(t.name, last_msg, chunk, |input| hs.handshake(input));
+        assert!(action.finished, "{}", t.name);
+        assert!(action.reply.is_empty(), "{}", t.name);
+
+        let request = hs.into_request().unwrap();
+        assert_eq!(request, t.request, "{}", t.name);
+        assert_eq!(
+            &request.reply(t.status, None).unwrap(),
+            final_reply,
+            "{}",
+            t.name
+        );
+    }
+
+    /// Run the client side of `t`, reading `chunk` bytes at a time.
+    fn check_client(t: &Transcript, chunk: usize) {
+        let mut hs = SocksClientHandshake::new(t.request.clone());
+        // The client speaks first, without waiting for any input.
+        let action = hs.handshake(&[]).unwrap().unwrap();
+        assert_eq!(action.drain, 0, "{}", t.name);
+        assert_eq!(action.reply, t.exchanges[0].0, "{}", t.name);
+
+        for (idx, (_, proxy_msg)) in t.exchanges.iter().enumerate() {
+            let action = feed(t.name, proxy_msg, chunk, |input| hs.handshake(input));
+            match t.exchanges.get(idx + 1) {
+                Some((client_msg, _)) => {
+                    assert!(!action.finished, "{}", t.name);
+                    assert_eq!(&action.reply, client_msg, "{}", t.name);
+                }
+                None => {
+                    assert!(action.finished, "{}", t.name);
+                    assert!(action.reply.is_empty(), "{}", t.name);
+                }
+            }
+        }
+
+        let reply = hs.into_reply().unwrap();
+        assert_eq!(reply.status(), t.status, "{}", t.name);
+    }
+
+    #[test]
+    fn proxy_transcripts() {
+        for t in transcripts() {
+            for chunk in [usize::MAX, 1, 3] {
+                check_proxy(&t, chunk);
+            }
+        }
+    }
+
+    #[test]
+    fn client_transcripts() {
+        for t in transcripts() {
+            for chunk in [usize::MAX, 1, 3] {
+                check_client(&t, chunk);
+            }
+        }
+    }
+}
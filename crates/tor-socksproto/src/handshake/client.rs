@@ -70,6 +70,10 @@ impl SocksClientHandshake {
     ///
     /// Other errors (besides `Truncated`) indicate a failure.
     ///
+    /// If the proxy has closed the connection, no more input will arrive:
+    /// use [`handshake_at_eof`](Self::handshake_at_eof) instead, so as not to
+    /// wait for it forever.
+    ///
     /// On success, return an Action describing what to tell the proxy,
     /// and how much of its input to consume.
     pub fn handshake(&mut self, input: &[u8]) -> TResult<Action> {
@@ -100,6 +104,22 @@ impl SocksClientHandshake {
         }
     }
 
+    /// Try to advance this handshake, given all the input that the proxy will
+    /// ever send us, in `input`.
+    ///
+    /// Use this instead of [`handshake`](Self::handshake) once the proxy has
+    /// closed the connection.  Where `handshake` would give [`Truncated`],
+    /// this fails the handshake with [`Error::UnexpectedEof`].
+    pub fn handshake_at_eof(&mut self, input: &[u8]) -> Result<Action> {
+        match self.handshake(input) {
+            Ok(rv) => rv,
+            Err(Truncated { .. }) => {
+                self.state = State::Failed;
+                Err(Error::UnexpectedEof)
+            }
+        }
+    }
+
     /// Send the client side of the socks 4 handshake.
     fn send_v4(&mut self) -> Result<Action> {
         let mut msg = Vec::new();
@@ -355,7 +375,7 @@ mod test {
             SocksCmd::CONNECT,
             SocksAddr::Hostname("www.torproject.org".to_string().try_into().unwrap()),
             443,
-            SocksAuth::Socks4(b"hello".to_vec()),
+            SocksAuth::Socks4(b"hello".to_vec().into()),
         )
         .unwrap();
         let mut hs = SocksClientHandshake::new(r);
@@ -421,6 +441,64 @@ mod test {
         assert_eq!(reply.addr().to_string(), "192.0.2.21");
     }
 
+    #[test]
+    fn socks5_resolve_ptr() {
+        let r = SocksRequest::new(
+            SocksVersion::V5,
+            SocksCmd::RESOLVE_PTR,
+            SocksAddr::Ip("192.0.2.21".parse().unwrap()),
+            0,
+            SocksAuth::NoAuth,
+        )
+        .unwrap();
+
+        let mut hs = SocksClientHandshake::new(r);
+        let action = hs.handshake(&[]).unwrap().unwrap();
+        assert_eq!(action.reply, hex!("05 01 00"));
+
+        // The client asks for the address to be looked up...
+        let action = hs.handshake(&hex!("0500")).unwrap().unwrap();
+        assert_eq!(action.reply, hex!("05 F1 00 01 C0000215 0000"));
+        assert_eq!(action.finished, false);
+
+        // ...and the proxy replies with the hostname.
+        let action = hs
+            .handshake(&hex!("05 00 00 03 0b 6578616d706c652e636f6d 0000"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(action.drain, 18);
+        assert_eq!(action.finished, true);
+
+        let reply = hs.into_reply().unwrap();
+        assert_eq!(reply.status(), SocksStatus::SUCCEEDED);
+        assert_eq!(reply.addr().to_string(), "example.com");
+    }
+
+    #[test]
+    fn socks5_eof() {
+        let r = SocksRequest::new(
+            SocksVersion::V5,
+            SocksCmd::CONNECT,
+            SocksAddr::Hostname("www.torproject.org".to_string().try_into().unwrap()),
+            443,
+            SocksAuth::NoAuth,
+        )
+        .unwrap();
+
+        let mut hs = SocksClientHandshake::new(r);
+        hs.handshake(&[]).unwrap().unwrap();
+        hs.handshake(&hex!("0500")).unwrap().unwrap();
+
+        // The proxy hangs up halfway through its reply.
+        let partial = hex!("05 00 00 01 C000");
+        assert!(hs.handshake(&partial).is_err());
+        assert!(matches!(
+            hs.handshake_at_eof(&partial),
+            Err(Error::UnexpectedEof)
+        ));
+        assert!(hs.into_reply().is_none());
+    }
+
     #[test]
     fn socks5_with_auth_ok() {
         let r = SocksRequest::new(
@@ -428,7 +506,7 @@ mod test {
             SocksCmd::CONNECT,
             SocksAddr::Hostname("www.torproject.org".to_string().try_into().unwrap()),
             443,
-            SocksAuth::Username(b"hello".to_vec(), b"world".to_vec()),
+            SocksAuth::Username(b"hello".to_vec().into(), b"world".to_vec().into()),
         )
         .unwrap();
 
@@ -1,14 +1,16 @@
 //! Types to implement the SOCKS handshake.
 
 use super::Action;
-use crate::msg::{SocksAddr, SocksAuth, SocksCmd, SocksRequest, SocksStatus, SocksVersion};
+use crate::msg::{
+    SocksAddr, SocksAuth, SocksCmd, SocksReply, SocksRequest, SocksStatus, SocksVersion,
+};
 use crate::{Error, Result, TResult, Truncated};
 
 use tor_bytes::{EncodeResult, Error as BytesError};
 use tor_bytes::{Reader, Writer};
 use tor_error::internal;
 
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 /// The Proxy (responder) side of an ongoing SOCKS handshake.
 ///
@@ -25,6 +27,11 @@ pub struct SocksProxyHandshake {
     socks5_auth: Option<SocksAuth>,
     /// Completed SOCKS handshake.
     handshake: Option<SocksRequest>,
+    /// The reason this handshake failed, if it failed after we sent a reply
+    /// telling the client so.
+    failure: Option<Error>,
+    /// The longest (partial) message we are willing to buffer.
+    max_message_len: usize,
 }
 
 /// Possible state for a Socks connection.
@@ -48,16 +55,53 @@ enum State {
     Failed,
 }
 
+/// Detect which version of SOCKS a client is speaking, given the first
+/// bytes it has sent.
+///
+/// If `input` is empty, gives a [`Truncated`]: the caller should wait for
+/// more input and try again.  If the first byte does not name a SOCKS version
+/// we support, gives [`Error::BadProtocol`].
+///
+/// [`SocksProxyHandshake::handshake`] uses this to pick which handshake to
+/// perform; it is exposed so that callers can do the same detection
+/// themselves, without consuming any input.
+pub fn detect_socks_version(input: &[u8]) -> TResult<SocksVersion> {
+    let Some(&version) = input.first() else {
+        return Err(Truncated::new());
+    };
+    Ok(version.try_into())
+}
+
 impl SocksProxyHandshake {
+    /// The default value for [`set_max_message_len`](Self::set_max_message_len).
+    ///
+    /// This is enough for the longest SOCKS5 message (a username/password
+    /// authentication message, at 513 bytes), with room to spare for SOCKS4
+    /// user IDs.
+    pub const DEFAULT_MAX_MESSAGE_LEN: usize = 1024;
+
     /// Construct a new SocksProxyHandshake in its initial state
     pub fn new() -> Self {
         SocksProxyHandshake {
             state: State::Initial,
             socks5_auth: None,
             handshake: None,
+            failure: None,
+            max_message_len: Self::DEFAULT_MAX_MESSAGE_LEN,
         }
     }
 
+    /// Set the longest incomplete message that we will accept from the client.
+    ///
+    /// If the input to [`handshake`](Self::handshake) is at least this long,
+    /// but still doesn't contain a complete message, the handshake fails with
+    /// [`Error::MessageTooLong`], rather than asking for more input.
+    /// This protects us from clients that promise (or, for SOCKS4, send)
+    /// more data than any reasonable handshake needs.
+    pub fn set_max_message_len(&mut self, max_message_len: usize) {
+        self.max_message_len = max_message_len;
+    }
+
     /// Try to advance a SocksProxyHandshake, given some client input in
     /// `input`.
     ///
@@ -68,6 +112,10 @@ impl SocksProxyHandshake {
     ///
     /// Other errors (besides `Truncated`) indicate a failure.
     ///
+    /// If the client has closed the connection, no more input will arrive:
+    /// use [`handshake_at_eof`](Self::handshake_at_eof) instead, so as not to
+    /// wait for it forever.
+    ///
     /// On success, return an Action describing what to tell the client,
     /// and how much of its input to consume.
     pub fn handshake(&mut self, input: &[u8]) -> TResult<Action> {
@@ -75,9 +123,11 @@ impl SocksProxyHandshake {
             return Err(Truncated::new());
         }
         let rv = match (self.state, input[0]) {
-            (State::Initial, 4) => self.s4(input),
-            (State::Initial, 5) => self.s5_initial(input),
-            (State::Initial, v) => Err(Error::BadProtocol(v)),
+            (State::Initial, _) => match detect_socks_version(input)? {
+                Ok(SocksVersion::V4) => self.s4(input),
+                Ok(SocksVersion::V5) => self.s5_initial(input),
+                Err(e) => Err(e),
+            },
             (State::Socks5Username, 1) => self.s5_uname(input),
             (State::Socks5Wait, 5) => self.s5(input),
             (State::Done, _) => Err(Error::AlreadyFinished(internal!(
@@ -88,6 +138,16 @@ impl SocksProxyHandshake {
             ))),
             (_, _) => Err(Error::Syntax),
         };
+        let rv = match rv {
+            Err(Error::Decode(tor_bytes::Error::Truncated))
+                if input.len() >= self.max_message_len =>
+            {
+                Err(Error::MessageTooLong {
+                    limit: self.max_message_len,
+                })
+            }
+            rv => rv,
+        };
         match rv {
             Err(Error::Decode(tor_bytes::Error::Truncated)) => Err(Truncated::new()),
             Err(e) => {
@@ -98,6 +158,22 @@ impl SocksProxyHandshake {
         }
     }
 
+    /// Try to advance this handshake, given all the input that the client will
+    /// ever send us, in `input`.
+    ///
+    /// Use this instead of [`handshake`](Self::handshake) once the client has
+    /// closed the connection.  Where `handshake` would give [`Truncated`],
+    /// this fails the handshake with [`Error::UnexpectedEof`].
+    pub fn handshake_at_eof(&mut self, input: &[u8]) -> Result<Action> {
+        match self.handshake(input) {
+            Ok(rv) => rv,
+            Err(Truncated { .. }) => {
+                self.state = State::Failed;
+                Err(Error::UnexpectedEof)
+            }
+        }
+    }
+
     /// Complete a socks4 or socks4a handshake.
     fn s4(&mut self, input: &[u8]) -> Result<Action> {
         let mut r = Reader::from_slice(input);
@@ -113,7 +189,7 @@ impl SocksProxyHandshake {
         let auth = if username.is_empty() {
             SocksAuth::NoAuth
         } else {
-            SocksAuth::Socks4(username)
+            SocksAuth::Socks4(username.into())
         };
 
         let addr = if ip != 0 && (ip >> 8) == 0 {
@@ -145,7 +221,7 @@ impl SocksProxyHandshake {
 
     /// Socks5: initial handshake to negotiate authentication method.
     fn s5_initial(&mut self, input: &[u8]) -> Result<Action> {
-        use super::{NO_AUTHENTICATION, USERNAME_PASSWORD};
+        use super::{NO_ACCEPTABLE_METHODS, NO_AUTHENTICATION, USERNAME_PASSWORD};
         let mut r = Reader::from_slice(input);
         let version: SocksVersion = r.take_u8()?.try_into()?;
         if version != SocksVersion::V5 {
@@ -162,8 +238,15 @@ impl SocksProxyHandshake {
             self.socks5_auth = Some(SocksAuth::NoAuth);
             (State::Socks5Wait, [5, NO_AUTHENTICATION])
         } else {
-            // In theory we should reply with "NO ACCEPTABLE METHODS".
-            return Err(Error::NotImplemented("authentication methods".into()));
+            // None of the offered methods (GSSAPI, say) are ones we support:
+            // tell the client so, and give up.
+            self.state = State::Failed;
+            self.failure = Some(Error::NoSupportedAuthMethod);
+            return Ok(Action {
+                drain: r.consumed(),
+                reply: vec![5, NO_ACCEPTABLE_METHODS],
+                finished: true,
+            });
         };
 
         self.state = next;
@@ -190,7 +273,10 @@ impl SocksProxyHandshake {
         let plen = r.take_u8()?;
         let passwd = r.take(plen as usize)?;
 
-        self.socks5_auth = Some(SocksAuth::Username(username.into(), passwd.into()));
+        self.socks5_auth = Some(SocksAuth::Username(
+            username.to_vec().into(),
+            passwd.to_vec().into(),
+        ));
         self.state = State::Socks5Wait;
         Ok(Action {
             drain: r.consumed(),
@@ -236,6 +322,15 @@ impl SocksProxyHandshake {
         self.state == State::Done
     }
 
+    /// Return the reason this handshake failed, if it finished unsuccessfully
+    /// with an [`Action`] that told the client why.
+    ///
+    /// (Failures that don't need a reply are reported directly by
+    /// [`handshake`](SocksProxyHandshake::handshake).)
+    pub fn failure(&self) -> Option<&Error> {
+        self.failure.as_ref()
+    }
+
     /// Consume this handshake's state; if it finished successfully,
     /// return a SocksRequest.
     pub fn into_request(self) -> Option<SocksRequest> {
@@ -253,21 +348,92 @@ impl SocksRequest {
     /// Format a reply to this request, indicating success or failure.
     ///
     /// Note that an address should be provided only when the request
-    /// was for a RESOLVE.
+    /// was for a RESOLVE (in which case it is the resolved IP address)
+    /// or a RESOLVE_PTR (in which case it is the resolved hostname).
     pub fn reply(&self, status: SocksStatus, addr: Option<&SocksAddr>) -> EncodeResult<Vec<u8>> {
-        match self.version() {
-            SocksVersion::V4 => self.s4(status, addr),
-            SocksVersion::V5 => self.s5(status, addr),
+        let reply = match addr {
+            Some(a) => SocksReply::new(status, a.clone(), self.port()),
+            // TODO: sometimes I think we want to answer with ::, not 0.0.0.0
+            None => SocksReply::new(status, SocksAddr::Ip(Ipv4Addr::UNSPECIFIED.into()), 0),
+        };
+        reply.encode(self.version())
+    }
+}
+
+impl SocksReply {
+    /// Construct a reply to `request`, reporting that it failed with `status`.
+    ///
+    /// The reply's bound address is left unspecified: it is `[::]:0` if the
+    /// client asked for an IPv6 address, and `0.0.0.0:0` otherwise.
+    ///
+    /// Use this when the request fails before we have anything better to
+    /// report, so that the client gets a well-formed reply rather than a
+    /// closed connection.
+    pub fn failure(request: &SocksRequest, status: SocksStatus) -> Self {
+        let addr: IpAddr = match request.addr() {
+            SocksAddr::Ip(IpAddr::V6(_)) => Ipv6Addr::UNSPECIFIED.into(),
+            SocksAddr::Ip(IpAddr::V4(_)) | SocksAddr::Hostname(_) => Ipv4Addr::UNSPECIFIED.into(),
+        };
+        SocksReply::new(status, SocksAddr::Ip(addr), 0)
+    }
+
+    /// Construct a [`GENERAL_FAILURE`](SocksStatus::GENERAL_FAILURE) reply
+    /// to `request`.
+    pub fn general_failure(request: &SocksRequest) -> Self {
+        Self::failure(request, SocksStatus::GENERAL_FAILURE)
+    }
+
+    /// Construct a [`NOT_ALLOWED`](SocksStatus::NOT_ALLOWED) reply to
+    /// `request`.
+    pub fn not_allowed(request: &SocksRequest) -> Self {
+        Self::failure(request, SocksStatus::NOT_ALLOWED)
+    }
+
+    /// Construct a [`NETWORK_UNREACHABLE`](SocksStatus::NETWORK_UNREACHABLE)
+    /// reply to `request`.
+    pub fn network_unreachable(request: &SocksRequest) -> Self {
+        Self::failure(request, SocksStatus::NETWORK_UNREACHABLE)
+    }
+
+    /// Construct a [`HOST_UNREACHABLE`](SocksStatus::HOST_UNREACHABLE) reply
+    /// to `request`.
+    pub fn host_unreachable(request: &SocksRequest) -> Self {
+        Self::failure(request, SocksStatus::HOST_UNREACHABLE)
+    }
+
+    /// Construct a [`CONNECTION_REFUSED`](SocksStatus::CONNECTION_REFUSED)
+    /// reply to `request`.
+    pub fn connection_refused(request: &SocksRequest) -> Self {
+        Self::failure(request, SocksStatus::CONNECTION_REFUSED)
+    }
+
+    /// Construct a [`TTL_EXPIRED`](SocksStatus::TTL_EXPIRED) reply to
+    /// `request`.
+    pub fn ttl_expired(request: &SocksRequest) -> Self {
+        Self::failure(request, SocksStatus::TTL_EXPIRED)
+    }
+
+    /// Construct a [`COMMAND_NOT_SUPPORTED`](SocksStatus::COMMAND_NOT_SUPPORTED)
+    /// reply to `request`.
+    pub fn command_not_supported(request: &SocksRequest) -> Self {
+        Self::failure(request, SocksStatus::COMMAND_NOT_SUPPORTED)
+    }
+
+    /// Encode this reply as a message in the given SOCKS `version`.
+    pub fn encode(&self, version: SocksVersion) -> EncodeResult<Vec<u8>> {
+        match version {
+            SocksVersion::V4 => self.s4(),
+            SocksVersion::V5 => self.s5(),
         }
     }
 
     /// Format a SOCKS4 reply.
-    fn s4(&self, status: SocksStatus, addr: Option<&SocksAddr>) -> EncodeResult<Vec<u8>> {
+    fn s4(&self) -> EncodeResult<Vec<u8>> {
         let mut w = Vec::new();
         w.write_u8(0);
-        w.write_u8(status.into_socks4_status());
-        match addr {
-            Some(SocksAddr::Ip(IpAddr::V4(ip))) => {
+        w.write_u8(self.status().into_socks4_status());
+        match self.addr() {
+            SocksAddr::Ip(IpAddr::V4(ip)) => {
                 w.write_u16(self.port());
                 w.write(ip)?;
             }
@@ -280,19 +446,13 @@ impl SocksRequest {
     }
 
     /// Format a SOCKS5 reply.
-    fn s5(&self, status: SocksStatus, addr: Option<&SocksAddr>) -> EncodeResult<Vec<u8>> {
+    fn s5(&self) -> EncodeResult<Vec<u8>> {
         let mut w = Vec::new();
         w.write_u8(5);
-        w.write_u8(status.into());
+        w.write_u8(self.status().into());
         w.write_u8(0); // reserved.
-        if let Some(a) = addr {
-            w.write(a)?;
-            w.write_u16(self.port());
-        } else {
-            // TODO: sometimes I think we want to answer with ::, not 0.0.0.0
-            w.write(&SocksAddr::Ip(std::net::Ipv4Addr::UNSPECIFIED.into()))?;
-            w.write_u16(0);
-        }
+        w.write(self.addr())?;
+        w.write_u16(self.port());
         Ok(w)
     }
 }
@@ -312,8 +472,224 @@ mod test {
     #![allow(clippy::needless_pass_by_value)]
     //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
     use super::*;
+    use crate::msg::SocksAddrType;
     use hex_literal::hex;
 
+    #[test]
+    fn detect_version() {
+        assert_eq!(
+            detect_socks_version(&hex!("04 01 0050")).unwrap().unwrap(),
+            SocksVersion::V4
+        );
+        assert_eq!(
+            detect_socks_version(&hex!("05")).unwrap().unwrap(),
+            SocksVersion::V5
+        );
+        assert!(matches!(
+            detect_socks_version(b"GET / HTTP/1.1"),
+            Ok(Err(Error::BadProtocol(b'G')))
+        ));
+    }
+
+    #[test]
+    fn detect_version_truncated() {
+        assert!(detect_socks_version(&[]).is_err());
+
+        // A single byte is enough to pick a version, but not to finish the
+        // handshake: the caller must keep the input and retry.
+        let mut h = SocksProxyHandshake::new();
+        assert!(h.handshake(&[]).is_err());
+        assert!(h.handshake(&hex!("05")).is_err());
+        assert!(h.handshake(&hex!("04 01 0050")).is_err());
+        assert_eq!(h.state, State::Initial);
+        let a = h.handshake(&hex!("05 01 00")).unwrap().unwrap();
+        assert_eq!(a.reply, &[5, 0]);
+        assert_eq!(h.state, State::Socks5Wait);
+    }
+
+    #[test]
+    fn message_too_long() {
+        // A username that claims to be 255 bytes long, but with a limit of 64 bytes.
+        let mut h = SocksProxyHandshake::new();
+        h.set_max_message_len(64);
+        h.handshake(&hex!("05 01 02")).unwrap().unwrap();
+        let mut input = hex!("01 ff").to_vec();
+        input.resize(63, b'x');
+        assert!(h.handshake(&input).is_err());
+        input.push(b'x');
+        assert!(matches!(
+            h.handshake(&input),
+            Ok(Err(Error::MessageTooLong { limit: 64 }))
+        ));
+        assert_eq!(h.state, State::Failed);
+
+        // A SOCKS4 user ID that never ends, with the default limit.
+        let mut h = SocksProxyHandshake::new();
+        let mut input = hex!("04 01 0050 CB007107").to_vec();
+        input.resize(SocksProxyHandshake::DEFAULT_MAX_MESSAGE_LEN - 1, b'x');
+        assert!(h.handshake(&input).is_err());
+        input.push(b'x');
+        assert!(matches!(
+            h.handshake(&input),
+            Ok(Err(Error::MessageTooLong { .. }))
+        ));
+
+        // The limit only applies to incomplete messages.
+        let mut h = SocksProxyHandshake::new();
+        h.set_max_message_len(3);
+        let a = h
+            .handshake(&hex!("04 01 0050 CB007107 00"))
+            .unwrap()
+            .unwrap();
+        assert!(a.finished);
+    }
+
+    #[test]
+    fn eof_during_handshake() {
+        // The client hangs up halfway through its SOCKS5 request.
+        let mut h = SocksProxyHandshake::new();
+        h.handshake(&hex!("05 01 00")).unwrap().unwrap();
+        let partial = hex!("05 01 00 01 7f00");
+        assert!(h.handshake(&partial).is_err());
+        assert!(matches!(
+            h.handshake_at_eof(&partial),
+            Err(Error::UnexpectedEof)
+        ));
+        assert_eq!(h.state, State::Failed);
+        assert!(matches!(
+            h.handshake(&partial),
+            Ok(Err(Error::AlreadyFinished(_)))
+        ));
+
+        // Hanging up before sending anything at all is also unexpected.
+        let mut h = SocksProxyHandshake::new();
+        assert!(matches!(h.handshake_at_eof(&[]), Err(Error::UnexpectedEof)));
+
+        // But if the input is complete, the handshake proceeds as usual.
+        let mut h = SocksProxyHandshake::new();
+        let a = h.handshake_at_eof(&hex!("04 01 0050 CB007107 00")).unwrap();
+        assert!(a.finished);
+        assert_eq!(h.into_request().unwrap().port(), 80);
+    }
+
+    #[test]
+    fn failure_replies() {
+        let req = |addr: &str| {
+            let addr = match addr.parse() {
+                Ok(ip) => SocksAddr::Ip(ip),
+                Err(_) => SocksAddr::Hostname(addr.to_string().try_into().unwrap()),
+            };
+            SocksRequest::new(
+                SocksVersion::V5,
+                SocksCmd::CONNECT,
+                addr,
+                443,
+                SocksAuth::NoAuth,
+            )
+            .unwrap()
+        };
+        let v4 = req("203.0.113.7");
+        let v6 = req("2001:db8::7");
+        let name = req("www.example.com");
+
+        let encode = |r: SocksReply| r.encode(SocksVersion::V5).unwrap();
+        /// A function that makes a reply to a request.
+        type Construct = fn(&SocksRequest) -> SocksReply;
+        let cases: [(Construct, u8); 7] = [
+            (SocksReply::general_failure, 0x01),
+            (SocksReply::not_allowed, 0x02),
+            (SocksReply::network_unreachable, 0x03),
+            (SocksReply::host_unreachable, 0x04),
+            (SocksReply::connection_refused, 0x05),
+            (SocksReply::ttl_expired, 0x06),
+            (SocksReply::command_not_supported, 0x07),
+        ];
+        for (construct, status) in cases {
+            let mut expect_v4 = hex!("05 00 00 01 00000000 0000").to_vec();
+            expect_v4[1] = status;
+            let mut expect_v6 = hex!("05 00 00 04 00000000000000000000000000000000 0000").to_vec();
+            expect_v6[1] = status;
+
+            assert_eq!(encode(construct(&v4)), expect_v4);
+            assert_eq!(encode(construct(&name)), expect_v4);
+            assert_eq!(encode(construct(&v6)), expect_v6);
+            assert_eq!(construct(&v6).status(), SocksStatus::from(status));
+            assert_eq!(construct(&v6).port(), 0);
+        }
+
+        // SOCKS4 has no way to report a status other than "rejected or failed".
+        assert_eq!(
+            SocksReply::host_unreachable(&v6)
+                .encode(SocksVersion::V4)
+                .unwrap(),
+            hex!("00 5B 0000 00000000")
+        );
+    }
+
+    #[test]
+    fn reply_addr_types() {
+        let encode = |addr: SocksAddr| {
+            SocksReply::new(SocksStatus::SUCCEEDED, addr, 0x1f90)
+                .encode(SocksVersion::V5)
+                .unwrap()
+        };
+        let v4 = SocksAddr::Ip("203.0.113.7".parse().unwrap());
+        let v6 = SocksAddr::Ip("2001:db8::7".parse().unwrap());
+        let name = SocksAddr::Hostname("www.example.com".to_string().try_into().unwrap());
+
+        assert_eq!(v4.addr_type(), SocksAddrType::Ipv4);
+        assert_eq!(v6.addr_type(), SocksAddrType::Ipv6);
+        assert_eq!(name.addr_type(), SocksAddrType::Hostname);
+
+        assert_eq!(encode(v4), hex!("05 00 00 01 CB007107 1f90"));
+        assert_eq!(
+            encode(v6),
+            hex!("05 00 00 04 20010db8000000000000000000000007 1f90")
+        );
+        assert_eq!(
+            encode(name),
+            hex!("05 00 00 03 0f 7777772e6578616d706c652e636f6d 1f90")
+        );
+    }
+
+    #[test]
+    fn reply_force_addr_type() {
+        let reply = |addr: &str| {
+            let addr = match addr.parse() {
+                Ok(ip) => SocksAddr::Ip(ip),
+                Err(_) => SocksAddr::Hostname(addr.to_string().try_into().unwrap()),
+            };
+            SocksReply::new(SocksStatus::SUCCEEDED, addr, 80)
+        };
+        let force = |addr: &str, addr_type| {
+            let r = reply(addr).force_addr_type(addr_type);
+            assert_eq!(r.addr().addr_type(), addr_type);
+            assert_eq!(r.port(), 80);
+            r.addr().to_string()
+        };
+        use SocksAddrType as AT;
+
+        // Forcing IPv4 never reveals anything but an IPv4 address we already had.
+        assert_eq!(force("www.example.com", AT::Ipv4), "0.0.0.0");
+        assert_eq!(force("2001:db8::7", AT::Ipv4), "0.0.0.0");
+        assert_eq!(force("203.0.113.7", AT::Ipv4), "203.0.113.7");
+        assert_eq!(
+            reply("www.example.com")
+                .force_addr_type(AT::Ipv4)
+                .encode(SocksVersion::V5)
+                .unwrap(),
+            hex!("05 00 00 01 00000000 0050")
+        );
+
+        assert_eq!(force("www.example.com", AT::Ipv6), "::");
+        assert_eq!(force("203.0.113.7", AT::Ipv6), "::");
+        assert_eq!(force("2001:db8::7", AT::Ipv6), "2001:db8::7");
+
+        assert_eq!(force("www.example.com", AT::Hostname), "www.example.com");
+        assert_eq!(force("203.0.113.7", AT::Hostname), "203.0.113.7");
+        assert_eq!(force("2001:db8::7", AT::Hostname), "2001:db8::7");
+    }
+
     #[test]
     fn socks4_good() {
         let mut h = SocksProxyHandshake::default();
@@ -357,7 +733,7 @@ mod test {
         let req = h.into_request().unwrap();
         assert_eq!(req.port(), 443);
         assert_eq!(req.addr().to_string(), "www.example.com");
-        assert_eq!(req.auth(), &SocksAuth::Socks4(b"swordfish".to_vec()));
+        assert_eq!(req.auth(), &SocksAuth::Socks4(b"swordfish".to_vec().into()));
         assert_eq!(req.command(), SocksCmd::CONNECT);
 
         assert_eq!(
@@ -366,6 +742,26 @@ mod test {
         );
     }
 
+    #[test]
+    fn leftover_payload() {
+        let payload = b"GET / HTTP/1.0\r\n\r\n";
+
+        let mut h = SocksProxyHandshake::new();
+        let input = [&hex!("04 01 0050 CB007107 00")[..], payload].concat();
+        let a = h.handshake(&input).unwrap().unwrap();
+        assert!(a.finished);
+        assert_eq!(a.drain, 9);
+        assert_eq!(a.leftover(&input), payload);
+
+        let mut h = SocksProxyHandshake::new();
+        let a = h.handshake(&hex!("05 01 00")).unwrap().unwrap();
+        assert!(a.leftover(&hex!("05 01 00")).is_empty());
+        let input = [&hex!("05 01 00 01 7f000007 1f90")[..], payload].concat();
+        let a = h.handshake(&input).unwrap().unwrap();
+        assert!(a.finished);
+        assert_eq!(a.leftover(&input), payload);
+    }
+
     #[test]
     fn socks5_init_noauth() {
         let mut h = SocksProxyHandshake::new();
@@ -389,8 +785,23 @@ mod test {
     #[test]
     fn socks5_init_nothing_works() {
         let mut h = SocksProxyHandshake::new();
-        let a = h.handshake(&hex!("05 02 9988")[..]);
-        assert!(matches!(a, Ok(Err(Error::NotImplemented(_)))));
+        let a = h.handshake(&hex!("05 02 9988")[..]).unwrap().unwrap();
+        assert!(a.finished);
+        assert_eq!(a.reply, &[5, 0xFF]);
+        assert!(matches!(h.failure(), Some(Error::NoSupportedAuthMethod)));
+    }
+
+    #[test]
+    fn socks5_init_gssapi_only() {
+        let mut h = SocksProxyHandshake::new();
+        let a = h.handshake(&hex!("05 01 01 99")[..]).unwrap().unwrap();
+        assert!(a.finished);
+        assert_eq!(a.drain, 3);
+        assert_eq!(a.reply, &[5, 0xFF]);
+        assert!(!h.finished());
+        assert_eq!(h.state, State::Failed);
+        assert!(matches!(h.failure(), Some(Error::NoSupportedAuthMethod)));
+        assert!(h.into_request().is_none());
     }
 
     #[test]
@@ -407,7 +818,7 @@ mod test {
         assert_eq!(
             h.socks5_auth.unwrap(),
             // _Horse Feathers_, 1932
-            SocksAuth::Username(b"Wagstaff".to_vec(), b"$wordfi5h".to_vec())
+            SocksAuth::Username(b"Wagstaff".to_vec().into(), b"$wordfi5h".to_vec().into())
         );
     }
 
@@ -443,6 +854,92 @@ mod test {
         );
     }
 
+    #[test]
+    fn socks5_resolve() {
+        // RESOLVE: the client sends a hostname, and we reply with an address.
+        let mut h = SocksProxyHandshake::new();
+        let _a = h.handshake(&hex!("05 01 00")).unwrap().unwrap();
+        let a = h
+            .handshake(&hex!("05 F0 00 03 0b 6578616d706c652e636f6d 0000"))
+            .unwrap()
+            .unwrap();
+        assert!(a.finished);
+        assert!(a.reply.is_empty());
+
+        let req = h.into_request().unwrap();
+        assert_eq!(req.command(), SocksCmd::RESOLVE);
+        assert_eq!(req.addr().to_string(), "example.com");
+        assert_eq!(req.port(), 0);
+        assert_eq!(
+            req.reply(
+                SocksStatus::SUCCEEDED,
+                Some(&SocksAddr::Ip("192.0.2.21".parse().unwrap()))
+            )
+            .unwrap(),
+            hex!("05 00 00 01 C0000215 0000")
+        );
+        assert_eq!(
+            req.reply(
+                SocksStatus::SUCCEEDED,
+                Some(&SocksAddr::Ip("2001:db8::7".parse().unwrap()))
+            )
+            .unwrap(),
+            hex!("05 00 00 04 20010db8000000000000000000000007 0000")
+        );
+
+        // SOCKS4a can carry a RESOLVE too, but only an IPv4 answer.
+        let mut h = SocksProxyHandshake::new();
+        let a = h
+            .handshake(&hex!("04 F0 0000 00000001 00 6578616d706c652e636f6d00"))
+            .unwrap()
+            .unwrap();
+        assert!(a.finished);
+        let req = h.into_request().unwrap();
+        assert_eq!(req.command(), SocksCmd::RESOLVE);
+        assert_eq!(req.addr().to_string(), "example.com");
+        assert_eq!(
+            req.reply(
+                SocksStatus::SUCCEEDED,
+                Some(&SocksAddr::Ip("192.0.2.21".parse().unwrap()))
+            )
+            .unwrap(),
+            hex!("00 5A 0000 C0000215")
+        );
+    }
+
+    #[test]
+    fn socks5_resolve_ptr() {
+        // RESOLVE_PTR: the client sends an address, and we reply with a hostname.
+        let mut h = SocksProxyHandshake::new();
+        let _a = h.handshake(&hex!("05 01 00")).unwrap().unwrap();
+        let a = h
+            .handshake(&hex!("05 F1 00 01 C0000215 0000"))
+            .unwrap()
+            .unwrap();
+        assert!(a.finished);
+        assert!(a.reply.is_empty());
+
+        let req = h.into_request().unwrap();
+        assert_eq!(req.command(), SocksCmd::RESOLVE_PTR);
+        assert_eq!(req.addr().to_string(), "192.0.2.21");
+        assert_eq!(req.port(), 0);
+        assert_eq!(
+            req.reply(
+                SocksStatus::SUCCEEDED,
+                Some(&SocksAddr::Hostname(
+                    "example.com".to_string().try_into().unwrap()
+                ))
+            )
+            .unwrap(),
+            hex!("05 00 00 03 0b 6578616d706c652e636f6d 0000")
+        );
+        // If the lookup fails, there's no hostname to send.
+        assert_eq!(
+            req.reply(SocksStatus::HOST_UNREACHABLE, None).unwrap(),
+            hex!("05 04 00 01 00000000 0000")
+        );
+    }
+
     #[test]
     fn socks5_request_ok_ipv6() {
         let mut h = SocksProxyHandshake::new();
@@ -43,13 +43,19 @@
 mod err;
 mod handshake;
 mod msg;
+#[cfg(feature = "proxy-handshake")]
+mod proxy_protocol;
 
 pub use err::Error;
 pub use handshake::Action;
 
 #[cfg(feature = "proxy-handshake")]
 #[cfg_attr(docsrs, doc(cfg(feature = "proxy-handshake")))]
-pub use handshake::proxy::SocksProxyHandshake;
+pub use handshake::proxy::{detect_socks_version, SocksProxyHandshake};
+
+#[cfg(feature = "proxy-handshake")]
+#[cfg_attr(docsrs, doc(cfg(feature = "proxy-handshake")))]
+pub use proxy_protocol::{looks_like_proxy_protocol, ProxyProtocolHeader};
 
 #[cfg(feature = "client-handshake")]
 #[cfg_attr(docsrs, doc(cfg(feature = "client-handshake")))]
@@ -61,7 +67,8 @@ pub use handshake::client::SocksClientHandshake;
 pub use SocksProxyHandshake as SocksHandshake;
 
 pub use msg::{
-    SocksAddr, SocksAuth, SocksCmd, SocksReply, SocksRequest, SocksStatus, SocksVersion,
+    SocksAddr, SocksAddrType, SocksAuth, SocksCmd, SocksReply, SocksRequest, SocksStatus,
+    SocksVersion,
 };
 pub use tor_error::Truncated;
 
@@ -73,4 +80,9 @@ pub type Result<T> = std::result::Result<T, Error>;
 ///
 /// This is a separate type from Result because a truncated message is not a
 /// true error: it just means that you need to read more bytes and try again.
+///
+/// If there are no more bytes to read, because the other party has closed the
+/// connection, don't wait for them: use `handshake_at_eof` (on
+/// `SocksProxyHandshake` or `SocksClientHandshake`) instead, which reports a
+/// truncated message as [`Error::UnexpectedEof`].
 pub type TResult<T> = std::result::Result<Result<T>, Truncated>;
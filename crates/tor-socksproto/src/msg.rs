@@ -4,12 +4,14 @@ use crate::{Error, Result};
 
 use caret::caret_int;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::net::IpAddr;
 
 #[cfg(feature = "arbitrary")]
 use std::net::Ipv6Addr;
 
 use tor_error::bad_api_usage;
+use zeroize::Zeroizing;
 
 #[cfg(feature = "arbitrary")]
 use arbitrary::{Arbitrary, Result as ArbitraryResult, Unstructured};
@@ -101,6 +103,42 @@ impl<'a> Arbitrary<'a> for SocksAddr {
     }
 }
 
+/// The type of an address in a SOCKS5 message.
+///
+/// This is the `ATYP` field of SOCKS5 requests and replies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SocksAddrType {
+    /// An IPv4 address (`ATYP` 0x01).
+    Ipv4,
+    /// A hostname (`ATYP` 0x03).
+    Hostname,
+    /// An IPv6 address (`ATYP` 0x04).
+    Ipv6,
+}
+
+impl SocksAddrType {
+    /// Return the SOCKS5 `ATYP` code for this type of address.
+    pub fn atyp(self) -> u8 {
+        match self {
+            SocksAddrType::Ipv4 => 1,
+            SocksAddrType::Hostname => 3,
+            SocksAddrType::Ipv6 => 4,
+        }
+    }
+}
+
+impl SocksAddr {
+    /// Return the type of this address.
+    pub fn addr_type(&self) -> SocksAddrType {
+        match self {
+            SocksAddr::Hostname(_) => SocksAddrType::Hostname,
+            SocksAddr::Ip(IpAddr::V4(_)) => SocksAddrType::Ipv4,
+            SocksAddr::Ip(IpAddr::V6(_)) => SocksAddrType::Ipv6,
+        }
+    }
+}
+
 /// A hostname for use with SOCKS.  It is limited in length.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct SocksHostname(String);
@@ -118,16 +156,57 @@ impl<'a> Arbitrary<'a> for SocksHostname {
 }
 
 /// Provided authentication from a SOCKS handshake
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
-#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+///
+/// The credentials are wiped from memory when this object is dropped,
+/// and are not shown in its `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum SocksAuth {
     /// No authentication was provided
     NoAuth,
     /// Socks4 authentication (a string) was provided.
-    Socks4(Vec<u8>),
+    Socks4(Zeroizing<Vec<u8>>),
     /// Socks5 username/password authentication was provided.
-    Username(Vec<u8>, Vec<u8>),
+    Username(Zeroizing<Vec<u8>>, Zeroizing<Vec<u8>>),
+}
+
+impl fmt::Debug for SocksAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SocksAuth::NoAuth => write!(f, "NoAuth"),
+            SocksAuth::Socks4(_) => write!(f, "Socks4(..)"),
+            SocksAuth::Username(_, _) => write!(f, "Username(..)"),
+        }
+    }
+}
+
+impl Hash for SocksAuth {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            SocksAuth::NoAuth => {}
+            SocksAuth::Socks4(data) => data.as_slice().hash(state),
+            SocksAuth::Username(user, pass) => {
+                user.as_slice().hash(state);
+                pass.as_slice().hash(state);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for SocksAuth {
+    fn arbitrary(u: &mut Unstructured<'a>) -> ArbitraryResult<Self> {
+        let b = u8::arbitrary(u)?;
+        Ok(match b % 3 {
+            0 => SocksAuth::NoAuth,
+            1 => SocksAuth::Socks4(Vec::<u8>::arbitrary(u)?.into()),
+            _ => SocksAuth::Username(
+                Vec::<u8>::arbitrary(u)?.into(),
+                Vec::<u8>::arbitrary(u)?.into(),
+            ),
+        })
+    }
 }
 
 caret_int! {
@@ -380,9 +459,12 @@ pub struct SocksReply {
 }
 
 impl SocksReply {
-    /// Create a new SocksReply.
-    #[cfg(feature = "client-handshake")]
-    pub(crate) fn new(status: SocksStatus, addr: SocksAddr, port: u16) -> Self {
+    /// Create a new SocksReply, with the given `status`, and bound address `addr` and `port`.
+    ///
+    /// The address may be of any type; see [`SocksReply::force_addr_type`]
+    /// for replying to clients that don't expect some of them.
+    #[cfg(any(feature = "client-handshake", feature = "proxy-handshake"))]
+    pub fn new(status: SocksStatus, addr: SocksAddr, port: u16) -> Self {
         Self { status, addr, port }
     }
 
@@ -409,6 +491,37 @@ impl SocksReply {
     pub fn port(&self) -> u16 {
         self.port
     }
+
+    /// Make sure that the address in this reply has type `addr_type`.
+    ///
+    /// An address that already has that type is kept.  Otherwise, an IP address
+    /// is replaced with the unspecified address of the requested family
+    /// (`0.0.0.0` or `[::]`), and a hostname forced to be an IP address is
+    /// replaced the same way.  An IP address forced to be a hostname is
+    /// written out as text.
+    ///
+    /// This is useful for clients that can't handle some types of address in
+    /// a reply (many don't expect a hostname), and for making sure that
+    /// a reply reveals nothing about how we resolved a hostname: forcing
+    /// [`SocksAddrType::Ipv4`] on a reply to a request for a hostname
+    /// always gives `0.0.0.0`.
+    pub fn force_addr_type(mut self, addr_type: SocksAddrType) -> Self {
+        if self.addr.addr_type() == addr_type {
+            return self;
+        }
+        self.addr = match addr_type {
+            SocksAddrType::Ipv4 => SocksAddr::Ip(std::net::Ipv4Addr::UNSPECIFIED.into()),
+            SocksAddrType::Ipv6 => SocksAddr::Ip(std::net::Ipv6Addr::UNSPECIFIED.into()),
+            // We already know that this is an IP address.
+            SocksAddrType::Hostname => SocksAddr::Hostname(
+                self.addr
+                    .to_string()
+                    .try_into()
+                    .expect("IP address too long for a SOCKS hostname?!"),
+            ),
+        };
+        self
+    }
 }
 
 #[cfg(test)]
@@ -427,6 +540,20 @@ mod test {
     //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
     use super::*;
 
+    #[test]
+    fn auth_debug_redacted() {
+        let a = SocksAuth::Username(b"hunter".to_vec().into(), b"swordfish".to_vec().into());
+        let s = format!("{:?}", a);
+        assert_eq!(s, "Username(..)");
+
+        let a = SocksAuth::Socks4(b"swordfish".to_vec().into());
+        let s = format!("{:?}", a);
+        assert!(!s.contains("swordfish"));
+        assert!(!s.contains(&format!("{:?}", b"swordfish")));
+
+        assert_eq!(format!("{:?}", SocksAuth::NoAuth), "NoAuth");
+    }
+
     #[test]
     fn display_sa() {
         let a = SocksAddr::Ip(IpAddr::V4("127.0.0.1".parse().unwrap()));
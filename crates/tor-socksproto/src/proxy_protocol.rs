@@ -0,0 +1,382 @@
+//! Parse the HAProxy PROXY protocol header.
+//!
+//! When a SOCKS port is placed behind a load balancer or other proxy,
+//! the address that the connection comes from is the proxy's, not the client's.
+//! Proxies that speak the [PROXY protocol] send a header before any other data,
+//! telling us the real client address.
+//!
+//! We support both the human-readable version 1 header,
+//! and the binary version 2 header.
+//!
+//! [PROXY protocol]: https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+
+use crate::{Error, Result, TResult, Truncated};
+
+use tor_bytes::Reader;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// The start of every version 1 (human-readable) header.
+const V1_PREFIX: &[u8] = b"PROXY ";
+
+/// The longest possible version 1 header, including the trailing CRLF.
+const V1_MAX_LEN: usize = 107;
+
+/// The signature at the start of every version 2 (binary) header.
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Version 2 command: the connection was made by the proxy itself (eg, a health check).
+const V2_CMD_LOCAL: u8 = 0x0;
+/// Version 2 command: the connection was relayed on behalf of a client.
+const V2_CMD_PROXY: u8 = 0x1;
+/// Version 2 address family and protocol: TCP over IPv4.
+const V2_FAM_TCP4: u8 = 0x11;
+/// Version 2 address family and protocol: TCP over IPv6.
+const V2_FAM_TCP6: u8 = 0x21;
+
+/// A PROXY protocol header, received before the SOCKS handshake.
+///
+/// Use [`ProxyProtocolHeader::parse`] to parse one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProxyProtocolHeader {
+    /// The addresses of the client and of the proxy's listener, if the proxy told us.
+    addrs: Option<(SocketAddr, SocketAddr)>,
+    /// How many bytes of input the header occupied.
+    drain: usize,
+}
+
+/// Return true if `input` starts with a PROXY protocol header signature
+/// (of either version).
+///
+/// This is useful for recognising a proxy that sends us a header
+/// when we weren't expecting one.
+pub fn looks_like_proxy_protocol(input: &[u8]) -> bool {
+    input.starts_with(V1_PREFIX) || input.starts_with(V2_SIGNATURE)
+}
+
+/// Return an error describing a malformed PROXY protocol header.
+fn bad(problem: &'static str) -> Error {
+    Error::BadProxyHeader(problem)
+}
+
+impl ProxyProtocolHeader {
+    /// Try to parse a PROXY protocol header from the start of `input`.
+    ///
+    /// If there isn't enough input, gives a [`Truncated`].
+    /// In this case, the caller must retain the input,
+    /// and pass it (with more data appended) to a later call.
+    ///
+    /// If `input` doesn't start with a valid header, gives [`Error::BadProxyHeader`]:
+    /// a connection on which we expect a header must not be accepted without one.
+    ///
+    /// On success, the header occupied the first [`drain`](ProxyProtocolHeader::drain)
+    /// bytes of `input`; the rest belongs to the protocol that follows.
+    pub fn parse(input: &[u8]) -> TResult<Self> {
+        let rv = if input.starts_with(V2_SIGNATURE) {
+            Self::parse_v2(input)
+        } else if input.starts_with(V1_PREFIX) {
+            Self::parse_v1(input)
+        } else if V2_SIGNATURE.starts_with(input) || V1_PREFIX.starts_with(input) {
+            return Err(Truncated::new());
+        } else {
+            Err(bad("missing header"))
+        };
+        match rv {
+            Err(Error::Decode(tor_bytes::Error::Truncated)) => Err(Truncated::new()),
+            other => Ok(other),
+        }
+    }
+
+    /// Parse a version 1 (human-readable) header.
+    ///
+    /// `input` must start with [`V1_PREFIX`].
+    fn parse_v1(input: &[u8]) -> Result<Self> {
+        let searchable = &input[..input.len().min(V1_MAX_LEN)];
+        let Some(end) = searchable.windows(2).position(|w| w == b"\r\n") else {
+            if input.len() >= V1_MAX_LEN {
+                return Err(bad("version 1 header too long"));
+            }
+            return Err(Error::Decode(tor_bytes::Error::Truncated));
+        };
+
+        let line = std::str::from_utf8(&input[V1_PREFIX.len()..end])
+            .map_err(|_| bad("version 1 header is not ASCII"))?;
+        let mut fields = line.split(' ');
+
+        let addrs = match fields.next() {
+            // The proxy doesn't know (or won't tell us); the rest of the line is to be ignored.
+            Some("UNKNOWN") => None,
+            Some(proto @ ("TCP4" | "TCP6")) => {
+                let fields: Vec<&str> = fields.collect();
+                let &[src, dst, src_port, dst_port] = fields.as_slice() else {
+                    return Err(bad("wrong number of fields in version 1 header"));
+                };
+                let parse_ip = |s: &str| -> Result<IpAddr> {
+                    let ip: IpAddr = s.parse().map_err(|_| bad("invalid address"))?;
+                    if ip.is_ipv4() != (proto == "TCP4") {
+                        return Err(bad("address does not match protocol"));
+                    }
+                    Ok(ip)
+                };
+                let parse_port =
+                    |s: &str| -> Result<u16> { s.parse().map_err(|_| bad("invalid port")) };
+                Some((
+                    SocketAddr::new(parse_ip(src)?, parse_port(src_port)?),
+                    SocketAddr::new(parse_ip(dst)?, parse_port(dst_port)?),
+                ))
+            }
+            _ => return Err(bad("unrecognized protocol in version 1 header")),
+        };
+
+        Ok(ProxyProtocolHeader {
+            addrs,
+            drain: end + 2,
+        })
+    }
+
+    /// Parse a version 2 (binary) header.
+    ///
+    /// `input` must start with [`V2_SIGNATURE`].
+    fn parse_v2(input: &[u8]) -> Result<Self> {
+        let mut r = Reader::from_slice(input);
+        r.advance(V2_SIGNATURE.len())?;
+        let ver_cmd = r.take_u8()?;
+        let family = r.take_u8()?;
+        let len = r.take_u16()?;
+        let body = r.take(len.into())?;
+
+        if ver_cmd >> 4 != 2 {
+            return Err(bad("unsupported version 2 header version"));
+        }
+
+        // Once we have the whole body, running out of it is an error, not truncation.
+        let short_body = |_| bad("version 2 address block too short");
+        let mut b = Reader::from_slice(body);
+        let addrs = match (ver_cmd & 0xf, family) {
+            (V2_CMD_LOCAL, _) => None,
+            (V2_CMD_PROXY, V2_FAM_TCP4) => {
+                let src: Ipv4Addr = b.extract().map_err(short_body)?;
+                let dst: Ipv4Addr = b.extract().map_err(short_body)?;
+                let src_port = b.take_u16().map_err(short_body)?;
+                let dst_port = b.take_u16().map_err(short_body)?;
+                Some((
+                    SocketAddr::new(src.into(), src_port),
+                    SocketAddr::new(dst.into(), dst_port),
+                ))
+            }
+            (V2_CMD_PROXY, V2_FAM_TCP6) => {
+                let src: Ipv6Addr = b.extract().map_err(short_body)?;
+                let dst: Ipv6Addr = b.extract().map_err(short_body)?;
+                let src_port = b.take_u16().map_err(short_body)?;
+                let dst_port = b.take_u16().map_err(short_body)?;
+                Some((
+                    SocketAddr::new(src.into(), src_port),
+                    SocketAddr::new(dst.into(), dst_port),
+                ))
+            }
+            // Unspecified, datagram, or AF_UNIX: nothing we can use as a client address.
+            (V2_CMD_PROXY, _) => None,
+            (_, _) => return Err(bad("unrecognized version 2 command")),
+        };
+        // Any remaining bytes in the body are TLVs, which we ignore.
+
+        Ok(ProxyProtocolHeader {
+            addrs,
+            drain: r.consumed(),
+        })
+    }
+
+    /// Return the address of the client, as reported by the proxy.
+    ///
+    /// Returns `None` if the proxy didn't report an address that we can use
+    /// (for example, if it made the connection on its own behalf).
+    pub fn source(&self) -> Option<SocketAddr> {
+        self.addrs.map(|(src, _)| src)
+    }
+
+    /// Return the address on which the proxy received the client's connection.
+    ///
+    /// Returns `None` if the proxy didn't report an address that we can use.
+    pub fn destination(&self) -> Option<SocketAddr> {
+        self.addrs.map(|(_, dst)| dst)
+    }
+
+    /// Return the number of bytes of input that this header occupied.
+    ///
+    /// The caller should drain these before starting the SOCKS handshake.
+    pub fn drain(&self) -> usize {
+        self.drain
+    }
+}
+
+#[cfg(test)]
+mod test {
+    // @@ begin test lint list maintained by maint/add_warning @@
+    #![allow(clippy::bool_assert_comparison)]
+    #![allow(clippy::clone_on_copy)]
+    #![allow(clippy::dbg_macro)]
+    #![allow(clippy::print_stderr)]
+    #![allow(clippy::print_stdout)]
+    #![allow(clippy::single_char_pattern)]
+    #![allow(clippy::unwrap_used)]
+    #![allow(clippy::unchecked_duration_subtraction)]
+    #![allow(clippy::useless_vec)]
+    #![allow(clippy::needless_pass_by_value)]
+    //! <!-- @@ end test lint list maintained by maint/add_warning @@ -->
+    use super::*;
+    use crate::{SocksCmd, SocksProxyHandshake};
+    use hex_literal::hex;
+
+    /// Parse a PROXY header from `input`, then check that a SOCKS5 CONNECT
+    /// to 127.0.0.7:8080 follows it.
+    fn parse_then_socks5(input: &[u8]) -> ProxyProtocolHeader {
+        let header = ProxyProtocolHeader::parse(input).unwrap().unwrap();
+        let mut input = &input[header.drain()..];
+
+        let mut h = SocksProxyHandshake::new();
+        let a = h.handshake(input).unwrap().unwrap();
+        assert_eq!(a.drain, 3);
+        assert_eq!(a.reply, &[5, 0]);
+        input = a.leftover(input);
+        let a = h.handshake(input).unwrap().unwrap();
+        assert!(a.finished);
+        assert!(a.leftover(input).is_empty());
+
+        let req = h.into_request().unwrap();
+        assert_eq!(req.command(), SocksCmd::CONNECT);
+        assert_eq!(req.addr().to_string(), "127.0.0.7");
+        assert_eq!(req.port(), 8080);
+
+        header
+    }
+
+    /// A SOCKS5 handshake with no authentication, asking to connect to 127.0.0.7:8080.
+    const SOCKS5: &[u8] = &hex!("05 01 00  05 01 00 01 7f000007 1f90");
+
+    #[test]
+    fn v1() {
+        let input = [
+            &b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 9150\r\n"[..],
+            SOCKS5,
+        ]
+        .concat();
+        assert!(looks_like_proxy_protocol(&input));
+        let header = parse_then_socks5(&input);
+        assert_eq!(header.source(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination(),
+            Some("198.51.100.2:9150".parse().unwrap())
+        );
+
+        let input = [
+            &b"PROXY TCP6 2001:db8::1 2001:db8::2 443 9150\r\n"[..],
+            SOCKS5,
+        ]
+        .concat();
+        let header = parse_then_socks5(&input);
+        assert_eq!(header.source(), Some("[2001:db8::1]:443".parse().unwrap()));
+
+        let input = [&b"PROXY UNKNOWN whatever\r\n"[..], SOCKS5].concat();
+        let header = parse_then_socks5(&input);
+        assert_eq!(header.source(), None);
+        assert_eq!(header.drain(), 24);
+    }
+
+    #[test]
+    fn v2() {
+        let input = [
+            &V2_SIGNATURE[..],
+            &hex!("21 11 000C  c0000201 c6336402 dc04 23be"),
+            SOCKS5,
+        ]
+        .concat();
+        assert!(looks_like_proxy_protocol(&input));
+        let header = parse_then_socks5(&input);
+        assert_eq!(header.source(), Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination(),
+            Some("198.51.100.2:9150".parse().unwrap())
+        );
+        assert_eq!(header.drain(), 28);
+
+        // IPv6, with a trailing TLV that we ignore.
+        let input = [
+            &V2_SIGNATURE[..],
+            &hex!(
+                "21 21 0028
+                 20010db8000000000000000000000001
+                 20010db8000000000000000000000002
+                 01bb 23be
+                 04 0001 00"
+            ),
+            SOCKS5,
+        ]
+        .concat();
+        let header = parse_then_socks5(&input);
+        assert_eq!(header.source(), Some("[2001:db8::1]:443".parse().unwrap()));
+
+        // LOCAL: no addresses, even if some are present.
+        let input = [
+            &V2_SIGNATURE[..],
+            &hex!("20 11 000C  c0000201 c6336402 dc04 23be"),
+            SOCKS5,
+        ]
+        .concat();
+        let header = parse_then_socks5(&input);
+        assert_eq!(header.source(), None);
+    }
+
+    #[test]
+    fn truncated() {
+        let v1 = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 9150\r\n";
+        let v2 = [
+            &V2_SIGNATURE[..],
+            &hex!("21 11 000C  c0000201 c6336402 dc04 23be"),
+        ]
+        .concat();
+        for full in [&v1[..], &v2[..]] {
+            for n in 0..full.len() {
+                assert!(ProxyProtocolHeader::parse(&full[..n]).is_err(), "{n}");
+            }
+            assert!(ProxyProtocolHeader::parse(full).is_ok());
+        }
+    }
+
+    #[test]
+    fn bad_headers() {
+        let bad_inputs: &[&[u8]] = &[
+            // No header at all: a plain SOCKS handshake.
+            SOCKS5,
+            b"GET / HTTP/1.0\r\n\r\n",
+            b"PROXY TCP5 192.0.2.1 198.51.100.2 56324 9150\r\n",
+            b"PROXY TCP4 2001:db8::1 2001:db8::2 443 9150\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.2 56324\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 99999\r\n",
+            &[&b"PROXY UNKNOWN "[..], &[b'x'; 100]].concat(),
+            // Version 3.
+            &[
+                &V2_SIGNATURE[..],
+                &hex!("31 11 000C c0000201 c6336402 dc04 23be"),
+            ]
+            .concat(),
+            // Unknown command.
+            &[
+                &V2_SIGNATURE[..],
+                &hex!("22 11 000C c0000201 c6336402 dc04 23be"),
+            ]
+            .concat(),
+            // Address block too short for its family.
+            &[&V2_SIGNATURE[..], &hex!("21 11 0008 c0000201 c6336402")].concat(),
+        ];
+        for input in bad_inputs {
+            assert!(
+                matches!(
+                    ProxyProtocolHeader::parse(input),
+                    Ok(Err(Error::BadProxyHeader(_)))
+                ),
+                "{input:?}"
+            );
+        }
+        assert!(!looks_like_proxy_protocol(SOCKS5));
+    }
+}
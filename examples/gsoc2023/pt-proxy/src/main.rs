@@ -274,10 +274,10 @@ async fn main() -> Result<()> {
             match settings {
                 Protocol::Socks(_, auth) => match auth {
                     SocksAuth::Username(raw_username, raw_password) => {
-                        let username = String::from_utf8(raw_username)?;
+                        let username = String::from_utf8(raw_username.to_vec())?;
                         let password = match raw_password.is_empty() {
                             true => String::from("\0"),
-                            false => String::from_utf8(raw_password)?,
+                            false => String::from_utf8(raw_password.to_vec())?,
                         };
                         let creds = ForwardingCreds {
                             username,